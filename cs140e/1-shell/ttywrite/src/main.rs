@@ -0,0 +1,186 @@
+extern crate serial;
+extern crate structopt;
+extern crate xmodem;
+#[macro_use] extern crate structopt_derive;
+
+mod parsers;
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use serial::core::{BaudRate, CharSize, FlowControl, SerialDevice, SerialPortSettings, StopBits};
+use structopt::StructOpt;
+use xmodem::{Progress, Xmodem};
+
+use parsers::{parse_width, parse_stop_bits, parse_flow_control, parse_baud_rate};
+
+/// The bytes of file data in an XMODEM packet.
+const PACKET_SIZE: usize = 128;
+
+/// XMODEM's negative acknowledgement, which asks for a packet again.
+const NAK: u8 = 0x15;
+
+/// The width of the progress bar, in characters.
+const BAR_WIDTH: usize = 40;
+
+#[derive(StructOpt, Debug)]
+#[structopt(about = "Write to TTY using the XMODEM protocol by default.")]
+struct Opt {
+    #[structopt(short = "i", help = "Input file (defaults to stdin if not set)",
+                parse(from_os_str))]
+    input: Option<PathBuf>,
+
+    #[structopt(short = "b", long = "baud", parse(try_from_str = "parse_baud_rate"),
+                help = "Set baud rate", default_value = "115200")]
+    baud_rate: BaudRate,
+
+    #[structopt(short = "t", long = "timeout", parse(try_from_str),
+                help = "Set timeout in seconds", default_value = "10")]
+    timeout: u64,
+
+    #[structopt(short = "w", long = "width", parse(try_from_str = "parse_width"),
+                help = "Set data character width in bits", default_value = "8")]
+    char_width: CharSize,
+
+    #[structopt(help = "Path to TTY device", parse(from_os_str))]
+    tty_path: PathBuf,
+
+    #[structopt(short = "f", long = "flow-control", parse(try_from_str = "parse_flow_control"),
+                help = "Enable flow control ('hardware' or 'software')", default_value = "none")]
+    flow_control: FlowControl,
+
+    #[structopt(short = "s", long = "stop-bits", parse(try_from_str = "parse_stop_bits"),
+                help = "Set number of stop bits", default_value = "1")]
+    stop_bits: StopBits,
+
+    #[structopt(short = "r", long = "raw", help = "Disable XMODEM")]
+    raw: bool,
+
+    #[structopt(short = "q", long = "quiet", help = "Don't show progress")]
+    quiet: bool,
+}
+
+/// The state of the transfer, for the progress callback, which can't
+/// capture any: the packets in the file, those acknowledged so far, and
+/// those the receiver asked for again.
+static TOTAL_PACKETS: AtomicUsize = AtomicUsize::new(0);
+static SENT_PACKETS: AtomicUsize = AtomicUsize::new(0);
+static RETRIES: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether the receiver has asked for the first packet, after which every
+/// NAK it sends is a retry.
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+/// A serial port that counts the NAKs read from it once the transfer has
+/// started.
+struct RetryCounter<T>(T);
+
+impl<T: Read> Read for RetryCounter<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.0.read(buf)?;
+        if STARTED.load(Ordering::Relaxed) {
+            let naks = buf[..n].iter().filter(|&&byte| byte == NAK).count();
+            RETRIES.fetch_add(naks, Ordering::Relaxed);
+        }
+        Ok(n)
+    }
+}
+
+impl<T: Write> Write for RetryCounter<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Draws the progress bar on standard error.
+fn draw_progress() {
+    let total = TOTAL_PACKETS.load(Ordering::Relaxed).max(1);
+    let sent = SENT_PACKETS.load(Ordering::Relaxed).min(total);
+    let filled = sent * BAR_WIDTH / total;
+    let bar: String = (0..BAR_WIDTH).map(|i| if i < filled { '=' } else { ' ' }).collect();
+    eprint!("\r[{}] {:3}% {}/{} packets, {} retries",
+            bar, sent * 100 / total, sent, total, RETRIES.load(Ordering::Relaxed));
+}
+
+fn progress_fn(progress: Progress) {
+    match progress {
+        Progress::Waiting => eprintln!("waiting for receiver..."),
+        Progress::Started => {
+            STARTED.store(true, Ordering::Relaxed);
+            draw_progress();
+        }
+        Progress::Packet(_) => {
+            SENT_PACKETS.fetch_add(1, Ordering::Relaxed);
+            draw_progress();
+        }
+    }
+}
+
+fn quiet_progress_fn(progress: Progress) {
+    if let Progress::Started = progress {
+        STARTED.store(true, Ordering::Relaxed);
+    }
+}
+
+fn run(opt: Opt) -> io::Result<()> {
+    let mut serial = serial::open(&opt.tty_path)?;
+    let mut settings = serial.read_settings()?;
+    settings.set_baud_rate(opt.baud_rate)?;
+    settings.set_char_size(opt.char_width);
+    settings.set_stop_bits(opt.stop_bits);
+    settings.set_flow_control(opt.flow_control);
+    serial.write_settings(&settings)?;
+    serial.set_timeout(Duration::from_secs(opt.timeout))?;
+
+    // The input is read whole first, so that its size, and with it the
+    // number of packets, is known even when it is standard input.
+    let mut data = Vec::new();
+    match opt.input {
+        Some(ref path) => File::open(path)?.read_to_end(&mut data)?,
+        None => io::stdin().read_to_end(&mut data)?,
+    };
+
+    if opt.raw {
+        serial.write_all(&data)?;
+        eprintln!("wrote {} bytes", data.len());
+        return Ok(());
+    }
+
+    TOTAL_PACKETS.store((data.len() + PACKET_SIZE - 1) / PACKET_SIZE, Ordering::Relaxed);
+    let progress: fn(Progress) = if opt.quiet { quiet_progress_fn } else { progress_fn };
+    let result = Xmodem::transmit_with_progress(&data[..], RetryCounter(serial), progress);
+    if !opt.quiet {
+        eprintln!("");
+    }
+
+    let retries = RETRIES.load(Ordering::Relaxed);
+    match result {
+        Ok(n) => {
+            eprintln!("wrote {} bytes in {} packets with {} retries",
+                      n, SENT_PACKETS.load(Ordering::Relaxed), retries);
+            Ok(())
+        }
+        Err(e) => {
+            eprintln!("transfer failed after {} packets and {} retries",
+                      SENT_PACKETS.load(Ordering::Relaxed), retries);
+            Err(e)
+        }
+    }
+}
+
+fn main() {
+    let opt = Opt::from_args();
+    let tty_path = opt.tty_path.clone();
+    if let Err(e) = run(opt) {
+        eprintln!("ttywrite: {}: {}", tty_path.display(), e);
+        process::exit(1);
+    }
+}
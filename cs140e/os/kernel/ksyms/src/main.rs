@@ -0,0 +1,186 @@
+//! Builds the kernel's symbol table from the output of `nm -n` on a linked
+//! kernel, read from standard input, and writes it to standard output. See
+//! `src/ksyms.rs` in the kernel for how it is used.
+//!
+//! The table holds every function: its address, and its name, demangled
+//! and without the hash Rust appends. All values are little-endian:
+//!
+//!   * a header: the magic number `KSYM`, the number of symbols, the
+//!     address of the first as a `u64`, and the offsets in the table of the
+//!     block markers and of the names;
+//!   * each symbol's address, as a `u32` offset from the first's, in order;
+//!   * for each block of `BLOCK` symbols, the offset of its first name from
+//!     the start of the names;
+//!   * the names, front-coded: each is the number of bytes it shares with
+//!     the one before, which is 0 at the start of a block, then the number
+//!     of bytes that follow, and those bytes. Names are cut at `MAX_NAME`.
+
+use std::io::{self, BufRead, Write};
+
+const MAGIC: u32 = 0x4D59_534B;
+const HEADER_LEN: usize = 24;
+const BLOCK: usize = 16;
+const MAX_NAME: usize = 255;
+
+fn main() {
+    let stdin = io::stdin();
+    let mut symbols: Vec<(u64, String)> = Vec::new();
+    for line in stdin.lock().lines() {
+        let line = line.expect("ksyms: can't read nm output");
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        // Undefined symbols have no address. Of the rest, only those in the
+        // text section are functions; `$x` and `.L` ones are local labels.
+        if fields.len() != 3 {
+            continue;
+        }
+        let (addr, kind, name) = (fields[0], fields[1], fields[2]);
+        if !["t", "T", "w", "W"].contains(&kind) || name.starts_with('$')
+            || name.starts_with(".L")
+        {
+            continue;
+        }
+        if let Ok(addr) = u64::from_str_radix(addr, 16) {
+            symbols.push((addr, truncate(demangle(name))));
+        }
+    }
+
+    symbols.sort_by_key(|&(addr, _)| addr);
+    symbols.dedup_by_key(|&mut (addr, _)| addr);
+    let table = table(&symbols);
+    io::stdout().write_all(&table).expect("ksyms: can't write the table");
+}
+
+/// Returns the table for `symbols`, which are sorted by address.
+fn table(symbols: &[(u64, String)]) -> Vec<u8> {
+    let base = symbols.first().map_or(0, |&(addr, _)| addr);
+    let mut markers = Vec::new();
+    let mut names = Vec::new();
+    let mut previous = "";
+    for (i, (_, name)) in symbols.iter().enumerate() {
+        let shared = if i % BLOCK == 0 {
+            markers.push(names.len() as u32);
+            0
+        } else {
+            previous.bytes().zip(name.bytes()).take_while(|&(a, b)| a == b).count()
+        };
+        let rest = &name.as_bytes()[shared..];
+        names.push(shared as u8);
+        names.push(rest.len() as u8);
+        names.extend_from_slice(rest);
+        previous = name;
+    }
+
+    let markers_at = HEADER_LEN + 4 * symbols.len();
+    let names_at = markers_at + 4 * markers.len();
+    let mut table = Vec::with_capacity(names_at + names.len());
+    put_u32(&mut table, MAGIC);
+    put_u32(&mut table, symbols.len() as u32);
+    put_u32(&mut table, base as u32);
+    put_u32(&mut table, (base >> 32) as u32);
+    put_u32(&mut table, markers_at as u32);
+    put_u32(&mut table, names_at as u32);
+    for &(addr, _) in symbols {
+        let offset = addr - base;
+        assert!(offset <= u32::MAX as u64, "ksyms: kernel text too large");
+        put_u32(&mut table, offset as u32);
+    }
+    for &marker in markers.iter() {
+        put_u32(&mut table, marker);
+    }
+    table.extend_from_slice(&names);
+    table
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    for i in 0..4 {
+        buf.push((value >> (8 * i)) as u8);
+    }
+}
+
+/// Cuts `name` to at most `MAX_NAME` bytes, on a character boundary.
+fn truncate(mut name: String) -> String {
+    let mut len = name.len().min(MAX_NAME);
+    while !name.is_char_boundary(len) {
+        len -= 1;
+    }
+    name.truncate(len);
+    name
+}
+
+/// Demangles `name` if it is a Rust symbol, dropping the hash: for
+/// instance, `_ZN6kernel9scheduler8schedule17h0123456789abcdefE` becomes
+/// `kernel::scheduler::schedule`. Other names are returned as they are.
+fn demangle(name: &str) -> String {
+    if !name.starts_with("_ZN") || !name.ends_with('E') {
+        return name.to_string();
+    }
+
+    let mut parts = Vec::new();
+    let mut rest = &name[3..name.len() - 1];
+    while !rest.is_empty() {
+        let digits = rest.bytes().take_while(|b| b.is_ascii_digit()).count();
+        let len = match rest[..digits].parse::<usize>() {
+            Ok(len) if digits + len <= rest.len() => len,
+            _ => return name.to_string(),
+        };
+        parts.push(&rest[digits..digits + len]);
+        rest = &rest[digits + len..];
+    }
+    if parts.is_empty() {
+        return name.to_string();
+    }
+
+    let is_hash = |part: &str| {
+        part.len() == 17 && part.starts_with('h')
+            && part[1..].bytes().all(|b| b.is_ascii_hexdigit())
+    };
+    if parts.last().is_some_and(|part| is_hash(part)) {
+        parts.pop();
+    }
+    parts.iter().map(|part| unescape(part)).collect::<Vec<_>>().join("::")
+}
+
+/// Undoes the escapes in a component of a mangled Rust name.
+fn unescape(part: &str) -> String {
+    let mut rest = if part.starts_with("_$") { &part[1..] } else { part };
+    let mut out = String::new();
+    while let Some(c) = rest.chars().next() {
+        if rest.starts_with("..") {
+            out.push_str("::");
+            rest = &rest[2..];
+            continue;
+        }
+
+        if c == '$' {
+            if let Some(end) = rest[1..].find('$') {
+                let code = &rest[1..end + 1];
+                let replacement = match code {
+                    "SP" => Some('@'),
+                    "BP" => Some('*'),
+                    "RF" => Some('&'),
+                    "LT" => Some('<'),
+                    "GT" => Some('>'),
+                    "LP" => Some('('),
+                    "RP" => Some(')'),
+                    "C" => Some(','),
+                    _ if code.starts_with('u') => {
+                        u32::from_str_radix(&code[1..], 16).ok().and_then(::std::char::from_u32)
+                    }
+                    _ => None,
+                };
+                if let Some(replacement) = replacement {
+                    out.push(replacement);
+                    rest = &rest[end + 2..];
+                    continue;
+                }
+            }
+        }
+
+        out.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,168 @@
+mod demangle {
+    use demangle;
+
+    const HASH: &str = "17h0123456789abcdefE";
+
+    /// Mangles `parts` as rustc's legacy scheme does, with a hash.
+    fn mangle(parts: &[&str]) -> String {
+        let mut name = String::from("_ZN");
+        for part in parts {
+            let mut escaped = String::new();
+            let mut rest = *part;
+            while let Some(c) = rest.chars().next() {
+                if rest.starts_with("::") {
+                    escaped.push_str("..");
+                    rest = &rest[2..];
+                    continue;
+                }
+                match c {
+                    '@' => escaped.push_str("$SP$"),
+                    '*' => escaped.push_str("$BP$"),
+                    '&' => escaped.push_str("$RF$"),
+                    '<' => escaped.push_str("$LT$"),
+                    '>' => escaped.push_str("$GT$"),
+                    '(' => escaped.push_str("$LP$"),
+                    ')' => escaped.push_str("$RP$"),
+                    ',' => escaped.push_str("$C$"),
+                    c if c.is_ascii_alphanumeric() || c == '_' || c == '.' => escaped.push(c),
+                    c => escaped.push_str(&format!("$u{:x}$", c as u32)),
+                }
+                rest = &rest[c.len_utf8()..];
+            }
+            if escaped.starts_with('$') {
+                escaped.insert(0, '_');
+            }
+            name.push_str(&format!("{}{}", escaped.len(), escaped));
+        }
+        name + HASH
+    }
+
+    fn round_trip(parts: &[&str]) {
+        let mangled = mangle(parts);
+        assert_eq!(demangle(&mangled), parts.join("::"), "{}", mangled);
+    }
+
+    #[test]
+    fn round_trips() {
+        round_trip(&["kernel", "scheduler", "schedule"]);
+        round_trip(&["kmain"]);
+        round_trip(&["<kernel::fs::Fs as core::ops::Drop>", "drop"]);
+        round_trip(&["core", "ptr", "drop_in_place<alloc::vec::Vec<(u64, &str)>>"]);
+        round_trip(&["kernel", "process", "{{closure}}"]);
+        round_trip(&["<*const T as core::fmt::Pointer>", "fmt"]);
+        round_trip(&["kernel", "net", "tcp", "Connection", "arrive"]);
+        round_trip(&["<&'a mut I as core::iter::Iterator>", "next"]);
+        round_trip(&["kernel", "console", "h\u{e9}llo", "@", "\u{1F980}"]);
+    }
+
+    #[test]
+    fn hashes() {
+        assert_eq!(demangle("_ZN6kernel9scheduler8schedule17h0123456789abcdefE"),
+                   "kernel::scheduler::schedule");
+        // Without a hash, or with a last component only like one.
+        assert_eq!(demangle("_ZN6kernel9scheduler8scheduleE"), "kernel::scheduler::schedule");
+        assert_eq!(demangle("_ZN6kernel17h0123456789abcdegE"), "kernel::h0123456789abcdeg");
+        assert_eq!(demangle("_ZN6kernel16h0123456789abcdeE"), "kernel::h0123456789abcde");
+    }
+
+    #[test]
+    fn other_names() {
+        for name in &["kmain", "memcpy", "_start", "_ZN", "_ZNE", "_ZN6kernel", "__rust_alloc",
+                      "_ZN6kernel9schedulerE.llvm.123"] {
+            assert_eq!(demangle(name), *name);
+        }
+    }
+
+    #[test]
+    fn malformed_names() {
+        // Lengths past the end, missing, or too large to parse.
+        for name in &["_ZN6kernel9schedE", "_ZNkernelE", "_ZN6kernel4E",
+                      "_ZN99999999999999999999999kernelE"] {
+            assert_eq!(demangle(name), *name);
+        }
+
+        // Escapes that aren't: unknown codes, unterminated ones, and code
+        // points that don't exist.
+        assert_eq!(demangle("_ZN5$XX$aE"), "$XX$a");
+        assert_eq!(demangle("_ZN4$LTaE"), "$LTa");
+        assert_eq!(demangle("_ZN9$ud800$abE"), "$ud800$ab");
+        assert_eq!(demangle("_ZN6$uzz$aE"), "$uzz$a");
+        assert_eq!(demangle("_ZN2$$E"), "$$");
+    }
+}
+
+mod table {
+    use {table, truncate, BLOCK, HEADER_LEN, MAGIC, MAX_NAME};
+
+    fn u32_at(table: &[u8], offset: usize) -> u32 {
+        table[offset..offset + 4].iter().rev().fold(0, |word, &byte| word << 8 | byte as u32)
+    }
+
+    /// Reads back every symbol in `table`.
+    fn decode(table: &[u8]) -> Vec<(u64, String)> {
+        assert_eq!(u32_at(table, 0), MAGIC);
+        let count = u32_at(table, 4) as usize;
+        let base = u32_at(table, 8) as u64 | (u32_at(table, 12) as u64) << 32;
+        let markers_at = u32_at(table, 16) as usize;
+        let names_at = u32_at(table, 20) as usize;
+        assert_eq!(markers_at, HEADER_LEN + 4 * count);
+        assert_eq!(names_at, markers_at + 4 * count.div_ceil(BLOCK));
+
+        let mut symbols = Vec::new();
+        let mut name: Vec<u8> = Vec::new();
+        let mut at = names_at;
+        for i in 0..count {
+            if i % BLOCK == 0 {
+                assert_eq!(names_at + u32_at(table, markers_at + 4 * (i / BLOCK)) as usize, at);
+                assert_eq!(table[at], 0);
+            }
+            let (shared, len) = (table[at] as usize, table[at + 1] as usize);
+            name.truncate(shared);
+            name.extend_from_slice(&table[at + 2..at + 2 + len]);
+            at += 2 + len;
+
+            let addr = base + u32_at(table, HEADER_LEN + 4 * i) as u64;
+            symbols.push((addr, String::from_utf8(name.clone()).unwrap()));
+        }
+        assert_eq!(at, table.len());
+        symbols
+    }
+
+    #[test]
+    fn round_trips() {
+        let symbols: Vec<(u64, String)> = (0..40u64)
+            .map(|i| (0xffff_0000_0008_0000 + 0x40 * i, format!("kernel::f{:02}::g", i)))
+            .collect();
+        assert_eq!(decode(&table(&symbols)), symbols);
+
+        // Names sharing nothing, and a name that is a prefix of the next.
+        let symbols = vec![(0x80000, "b".to_string()), (0x80010, "a".to_string()),
+                           (0x80020, "ab".to_string()), (0x80030, "ab".to_string()),
+                           (0x80040, String::new())];
+        assert_eq!(decode(&table(&symbols)), symbols);
+    }
+
+    #[test]
+    fn empty() {
+        let table = table(&[]);
+        assert_eq!(table.len(), HEADER_LEN);
+        assert!(decode(&table).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "kernel text too large")]
+    fn too_large() {
+        table(&[(0, "a".to_string()), (1 << 32, "b".to_string())]);
+    }
+
+    #[test]
+    fn truncates() {
+        assert_eq!(truncate("short".to_string()), "short");
+        assert_eq!(truncate("x".repeat(300)).len(), MAX_NAME);
+
+        // A character straddling the cut is dropped whole.
+        let name = "x".repeat(MAX_NAME - 1) + "\u{e9}";
+        assert_eq!(truncate(name), "x".repeat(MAX_NAME - 1));
+        assert_eq!(decode(&table(&[(0, truncate("y".repeat(400)))]))[0].1.len(), MAX_NAME);
+    }
+}
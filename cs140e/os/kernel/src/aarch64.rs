@@ -0,0 +1,466 @@
+/// Returns the ID of the core executing this code (`MPIDR_EL1.Aff0`).
+#[cfg(not(test))]
+#[inline(always)]
+pub fn affinity() -> usize {
+    let x: usize;
+    unsafe {
+        asm!("mrs $0, mpidr_el1" : "=r"(x) : : : "volatile");
+    }
+    x & 0b11
+}
+
+/// Returns `true` if the MMU and data cache are enabled (`SCTLR_EL1.M` and
+/// `SCTLR_EL1.C`). Exclusive loads and stores, and therefore atomic
+/// read-modify-write operations, only work once both are on.
+#[cfg(not(test))]
+#[inline(always)]
+pub fn caches_enabled() -> bool {
+    let sctlr: u64;
+    unsafe {
+        asm!("mrs $0, sctlr_el1" : "=r"(sctlr) : : : "volatile");
+    }
+    sctlr & 0b101 == 0b101
+}
+
+/// Masks IRQs and FIQs on this core and returns the previous `DAIF` value,
+/// to be passed to `irq_restore()`.
+#[cfg(not(test))]
+#[inline(always)]
+pub fn irq_save() -> u64 {
+    let daif: u64;
+    unsafe {
+        asm!("mrs $0, DAIF
+              msr DAIFSet, #0b0011" : "=r"(daif) : : "memory" : "volatile");
+    }
+    daif
+}
+
+/// Restores the `DAIF` value `daif` returned by a previous `irq_save()`.
+#[cfg(not(test))]
+#[inline(always)]
+pub fn irq_restore(daif: u64) {
+    unsafe {
+        asm!("msr DAIF, $0" : : "r"(daif) : "memory" : "volatile");
+    }
+}
+
+/// Unmasks IRQs on this core.
+#[cfg(not(test))]
+#[inline(always)]
+pub fn irq_enable() {
+    unsafe {
+        asm!("msr DAIFClr, #0b0010" : : : "memory" : "volatile");
+    }
+}
+
+/// Returns `true` if IRQs are masked on this core (`DAIF.I`).
+#[cfg(not(test))]
+#[inline(always)]
+pub fn irqs_masked() -> bool {
+    let daif: u64;
+    unsafe {
+        asm!("mrs $0, DAIF" : "=r"(daif) : : : "volatile");
+    }
+    daif & (1 << 7) != 0
+}
+
+/// Waits for an interrupt. Returns once an interrupt is pending, whether or
+/// not IRQs are masked.
+#[cfg(not(test))]
+#[inline(always)]
+pub fn wfi() {
+    unsafe {
+        asm!("wfi" : : : "memory" : "volatile");
+    }
+}
+
+/// Wakes every core waiting in `wfe`.
+#[cfg(not(test))]
+#[inline(always)]
+pub fn sev() {
+    unsafe {
+        asm!("dsb sy
+              sev" : : : "memory" : "volatile");
+    }
+}
+
+/// Returns the frequency of the generic timers in Hz (`CNTFRQ_EL0`).
+#[cfg(not(test))]
+#[inline(always)]
+pub fn timer_frequency() -> u64 {
+    let x: u64;
+    unsafe {
+        asm!("mrs $0, cntfrq_el0" : "=r"(x) : : : "volatile");
+    }
+    x
+}
+
+/// Returns the generic timers' count (`CNTPCT_EL0`), which advances
+/// `timer_frequency()` times a second on every core alike.
+#[cfg(not(test))]
+#[inline(always)]
+pub fn timer_count() -> u64 {
+    let x: u64;
+    unsafe {
+        asm!("isb
+              mrs $0, cntpct_el0" : "=r"(x) : : "memory" : "volatile");
+    }
+    x
+}
+
+/// Enables this core's physical timer to fire `ticks` generic timer ticks
+/// from now, clearing any pending interrupt from it.
+#[cfg(not(test))]
+#[inline(always)]
+pub fn physical_timer_in(ticks: u32) {
+    unsafe {
+        asm!("msr cntp_tval_el0, $0
+              msr cntp_ctl_el0, $1
+              isb" : : "r"(ticks as u64), "r"(1u64) : "memory" : "volatile");
+    }
+}
+
+/// Returns the current frame pointer (`x29`). The kernel is built with frame
+/// pointers, so `[fp]` holds the caller's frame pointer and `[fp + 8]` holds
+/// the return address into the caller.
+#[cfg(not(test))]
+#[inline(always)]
+pub fn fp() -> usize {
+    let x: usize;
+    unsafe {
+        asm!("mov $0, x29" : "=r"(x) : : : "volatile");
+    }
+    x
+}
+
+/// Enables the MMU and the data and instruction caches with the given
+/// `MAIR_EL1`, `TCR_EL1`, and translation table base addresses.
+///
+/// The code calling this must be identity-mapped by `ttbr0`, since the
+/// instruction after the switch is fetched through the new tables.
+#[cfg(not(test))]
+#[inline(always)]
+pub unsafe fn enable_mmu(mair: u64, tcr: u64, ttbr0: u64, ttbr1: u64) {
+    asm!("msr mair_el1, $0
+          msr tcr_el1, $1
+          msr ttbr0_el1, $2
+          msr ttbr1_el1, $3
+          isb
+          tlbi vmalle1
+          dsb ish
+          isb
+          mrs x9, sctlr_el1
+          orr x9, x9, #(1 << 0)
+          orr x9, x9, #(1 << 2)
+          orr x9, x9, #(1 << 12)
+          msr sctlr_el1, x9
+          isb"
+         : : "r"(mair), "r"(tcr), "r"(ttbr0), "r"(ttbr1) : "x9", "memory" : "volatile");
+}
+
+/// Switches `TTBR1_EL1` to `ttbr1` and discards every cached translation.
+#[cfg(not(test))]
+#[inline(always)]
+pub unsafe fn set_ttbr1(ttbr1: u64) {
+    asm!("msr ttbr1_el1, $0
+          isb
+          tlbi vmalle1
+          dsb ish
+          isb"
+         : : "r"(ttbr1) : "memory" : "volatile");
+}
+
+/// Discards every cached translation on this core.
+#[cfg(not(test))]
+#[inline(always)]
+pub fn flush_tlb() {
+    unsafe {
+        asm!("dsb ishst
+              tlbi vmalle1
+              dsb ish
+              isb" : : : "memory" : "volatile");
+    }
+}
+
+/// Discards every cached translation of the kernel's and user addresses on
+/// every core in the inner shareable domain.
+#[cfg(not(test))]
+#[inline(always)]
+pub fn flush_tlb_all_cores() {
+    unsafe {
+        asm!("dsb ishst
+              tlbi vmalle1is
+              dsb ish
+              isb" : : : "memory" : "volatile");
+    }
+}
+
+/// Waits for earlier memory accesses, including writes to translation
+/// tables, to complete for every core in the inner shareable domain.
+#[cfg(not(test))]
+#[inline(always)]
+pub fn dsb() {
+    unsafe { asm!("dsb ish" : : : "memory" : "volatile"); }
+}
+
+/// Makes instructions written to `start..start + len` visible to instruction
+/// fetch: cleans the data cache lines holding them to the point of
+/// unification, then invalidates the instruction cache.
+#[cfg(not(test))]
+pub fn sync_icache(start: usize, len: usize) {
+    const LINE: usize = 64;
+    let mut line = start & !(LINE - 1);
+    while line < start + len {
+        unsafe { asm!("dc cvau, $0" : : "r"(line) : "memory" : "volatile"); }
+        line += LINE;
+    }
+
+    unsafe {
+        asm!("dsb ish
+              ic iallu
+              dsb ish
+              isb" : : : "memory" : "volatile");
+    }
+}
+
+/// Cleans and invalidates the data cache lines holding `start..start + len`
+/// to the point of coherency, so that a core running with its caches off
+/// sees what was written.
+#[cfg(not(test))]
+pub fn clean_dcache(start: usize, len: usize) {
+    const LINE: usize = 64;
+    let mut line = start & !(LINE - 1);
+    while line < start + len {
+        unsafe { asm!("dc civac, $0" : : "r"(line) : "memory" : "volatile"); }
+        line += LINE;
+    }
+
+    unsafe { asm!("dsb sy" : : : "memory" : "volatile"); }
+}
+
+/// Returns `true` if EL0 could read `addr`, or write it if `write` is set,
+/// according to the current translation tables (`AT S1E0R`/`AT S1E0W`).
+#[cfg(not(test))]
+#[inline(always)]
+pub fn user_can_access(addr: usize, write: bool) -> bool {
+    let par: u64;
+    unsafe {
+        if write {
+            asm!("at s1e0w, $1
+                  isb
+                  mrs $0, par_el1" : "=r"(par) : "r"(addr) : "memory" : "volatile");
+        } else {
+            asm!("at s1e0r, $1
+                  isb
+                  mrs $0, par_el1" : "=r"(par) : "r"(addr) : "memory" : "volatile");
+        }
+    }
+    par & 1 == 0
+}
+
+/// Returns `true` if EL1 could read `addr` according to the current
+/// translation tables (`AT S1E1R`), so that reading it won't fault.
+#[cfg(not(test))]
+#[inline(always)]
+pub fn kernel_can_read(addr: usize) -> bool {
+    let par: u64;
+    unsafe {
+        asm!("at s1e1r, $1
+              isb
+              mrs $0, par_el1" : "=r"(par) : "r"(addr) : "memory" : "volatile");
+    }
+    par & 1 == 0
+}
+
+/// Returns `true` if EL1 could write `addr` according to the current
+/// translation tables (`AT S1E1W`), so that writing it won't fault.
+#[cfg(not(test))]
+#[inline(always)]
+pub fn kernel_can_write(addr: usize) -> bool {
+    let par: u64;
+    unsafe {
+        asm!("at s1e1w, $1
+              isb
+              mrs $0, par_el1" : "=r"(par) : "r"(addr) : "memory" : "volatile");
+    }
+    par & 1 == 0
+}
+
+/// Enables or disables software stepping on this core (`MDSCR_EL1.SS`),
+/// with debug exceptions from EL1 enabled (`MDSCR_EL1.KDE`) and the OS lock
+/// cleared. A context returned to with `SPSR.SS` set and `SPSR.D` clear
+/// then runs one instruction and takes a software step exception.
+#[cfg(not(test))]
+#[inline(always)]
+pub fn set_single_step(enabled: bool) {
+    const SS: u64 = 1;
+    const KDE: u64 = 1 << 13;
+    unsafe {
+        let mut mdscr: u64;
+        asm!("mrs $0, mdscr_el1" : "=r"(mdscr) : : : "volatile");
+        mdscr = if enabled { mdscr | SS | KDE } else { mdscr & !SS };
+        asm!("msr oslar_el1, xzr
+              msr mdscr_el1, $0
+              isb" : : "r"(mdscr) : "memory" : "volatile");
+    }
+}
+
+/// Enables breakpoint and watchpoint debug exceptions on this core
+/// (`MDSCR_EL1.MDE`), taken from EL1 too (`MDSCR_EL1.KDE`), and clears the
+/// OS lock, which would hold them off.
+#[cfg(not(test))]
+#[inline(always)]
+pub fn enable_debug_exceptions() {
+    const KDE: u64 = 1 << 13;
+    const MDE: u64 = 1 << 15;
+    unsafe {
+        let mut mdscr: u64;
+        asm!("mrs $0, mdscr_el1" : "=r"(mdscr) : : : "volatile");
+        mdscr |= KDE | MDE;
+        asm!("msr oslar_el1, xzr
+              msr mdscr_el1, $0
+              isb" : : "r"(mdscr) : "memory" : "volatile");
+    }
+}
+
+/// Sets hardware breakpoint `n` of this core to `value` (`DBGBVR<n>_EL1`)
+/// and `control` (`DBGBCR<n>_EL1`). The Cortex-A53 has six: `n` is 0-5.
+#[cfg(not(test))]
+pub fn set_hw_breakpoint(n: usize, value: u64, control: u32) {
+    let control = control as u64;
+    unsafe {
+        match n {
+            0 => asm!("msr dbgbvr0_el1, $0
+                       msr dbgbcr0_el1, $1" : : "r"(value), "r"(control) : : "volatile"),
+            1 => asm!("msr dbgbvr1_el1, $0
+                       msr dbgbcr1_el1, $1" : : "r"(value), "r"(control) : : "volatile"),
+            2 => asm!("msr dbgbvr2_el1, $0
+                       msr dbgbcr2_el1, $1" : : "r"(value), "r"(control) : : "volatile"),
+            3 => asm!("msr dbgbvr3_el1, $0
+                       msr dbgbcr3_el1, $1" : : "r"(value), "r"(control) : : "volatile"),
+            4 => asm!("msr dbgbvr4_el1, $0
+                       msr dbgbcr4_el1, $1" : : "r"(value), "r"(control) : : "volatile"),
+            5 => asm!("msr dbgbvr5_el1, $0
+                       msr dbgbcr5_el1, $1" : : "r"(value), "r"(control) : : "volatile"),
+            _ => panic!("no hardware breakpoint {}", n),
+        }
+        asm!("isb" : : : "memory" : "volatile");
+    }
+}
+
+/// Sets hardware watchpoint `n` of this core to `value` (`DBGWVR<n>_EL1`)
+/// and `control` (`DBGWCR<n>_EL1`). The Cortex-A53 has four: `n` is 0-3.
+#[cfg(not(test))]
+pub fn set_hw_watchpoint(n: usize, value: u64, control: u32) {
+    let control = control as u64;
+    unsafe {
+        match n {
+            0 => asm!("msr dbgwvr0_el1, $0
+                       msr dbgwcr0_el1, $1" : : "r"(value), "r"(control) : : "volatile"),
+            1 => asm!("msr dbgwvr1_el1, $0
+                       msr dbgwcr1_el1, $1" : : "r"(value), "r"(control) : : "volatile"),
+            2 => asm!("msr dbgwvr2_el1, $0
+                       msr dbgwcr2_el1, $1" : : "r"(value), "r"(control) : : "volatile"),
+            3 => asm!("msr dbgwvr3_el1, $0
+                       msr dbgwcr3_el1, $1" : : "r"(value), "r"(control) : : "volatile"),
+            _ => panic!("no hardware watchpoint {}", n),
+        }
+        asm!("isb" : : : "memory" : "volatile");
+    }
+}
+
+/// The cycle counter's bit in `PMCNTENSET_EL0`, `PMINTENSET_EL1`, and the
+/// registers that clear them, and in `PMOVSCLR_EL0`.
+#[cfg(not(test))]
+const PMU_CYCLES: u64 = 1 << 31;
+
+/// Starts this core's PMU cycle counter (`PMCR_EL0.E`, `PMCNTENSET_EL0.C`),
+/// counting at EL0 and EL1 and overflowing out of its low 32 bits
+/// (`PMCR_EL0.LC` clear), with its overflow interrupt enabled if
+/// `interrupt` is set.
+#[cfg(not(test))]
+pub fn start_cycle_counter(interrupt: bool) {
+    const PMCR_E: u64 = 1;
+    const PMCR_LC: u64 = 1 << 6;
+    unsafe {
+        let mut pmcr: u64;
+        asm!("mrs $0, pmcr_el0" : "=r"(pmcr) : : : "volatile");
+        pmcr = (pmcr | PMCR_E) & !PMCR_LC;
+        asm!("msr pmccfiltr_el0, xzr
+              msr pmcr_el0, $0
+              msr pmovsclr_el0, $1
+              msr pmcntenset_el0, $1" : : "r"(pmcr), "r"(PMU_CYCLES) : "memory" : "volatile");
+        if interrupt {
+            asm!("msr pmintenset_el1, $0" : : "r"(PMU_CYCLES) : "memory" : "volatile");
+        } else {
+            asm!("msr pmintenclr_el1, $0" : : "r"(PMU_CYCLES) : "memory" : "volatile");
+        }
+        asm!("isb" : : : "memory" : "volatile");
+    }
+}
+
+/// Stops this core's cycle counter and disables its overflow interrupt.
+#[cfg(not(test))]
+pub fn stop_cycle_counter() {
+    unsafe {
+        asm!("msr pmintenclr_el1, $0
+              msr pmcntenclr_el0, $0
+              msr pmovsclr_el0, $0
+              isb" : : "r"(PMU_CYCLES) : "memory" : "volatile");
+    }
+}
+
+/// Returns this core's cycle counter (`PMCCNTR_EL0`).
+#[cfg(not(test))]
+#[inline(always)]
+pub fn cycle_counter() -> u64 {
+    let cycles: u64;
+    unsafe {
+        asm!("mrs $0, pmccntr_el0" : "=r"(cycles) : : : "volatile");
+    }
+    cycles
+}
+
+/// Sets this core's cycle counter to `cycles` and clears its overflow flag,
+/// deasserting its interrupt.
+#[cfg(not(test))]
+#[inline(always)]
+pub fn set_cycle_counter(cycles: u64) {
+    unsafe {
+        asm!("msr pmovsclr_el0, $1
+              msr pmccntr_el0, $0
+              isb" : : "r"(cycles), "r"(PMU_CYCLES) : "memory" : "volatile");
+    }
+}
+
+// Host stubs for tests: a single core with the MMU off.
+#[cfg(test)] pub fn affinity() -> usize { 0 }
+#[cfg(test)] pub fn fp() -> usize { 0 }
+#[cfg(test)] pub fn caches_enabled() -> bool { false }
+#[cfg(test)] pub fn irq_save() -> u64 { 0 }
+#[cfg(test)] pub fn irq_restore(_daif: u64) { }
+#[cfg(test)] pub fn irq_enable() { }
+#[cfg(test)] pub fn irqs_masked() -> bool { false }
+#[cfg(test)] pub fn wfi() { }
+#[cfg(test)] pub fn sev() { }
+#[cfg(test)] pub fn timer_frequency() -> u64 { 0 }
+#[cfg(test)] pub fn timer_count() -> u64 { 0 }
+#[cfg(test)] pub fn physical_timer_in(_ticks: u32) { }
+#[cfg(test)] pub fn clean_dcache(_start: usize, _len: usize) { }
+#[cfg(test)] pub unsafe fn enable_mmu(_mair: u64, _tcr: u64, _ttbr0: u64, _ttbr1: u64) { }
+#[cfg(test)] pub unsafe fn set_ttbr1(_ttbr1: u64) { }
+#[cfg(test)] pub fn flush_tlb() { }
+#[cfg(test)] pub fn flush_tlb_all_cores() { }
+#[cfg(test)] pub fn dsb() { }
+#[cfg(test)] pub fn sync_icache(_start: usize, _len: usize) { }
+#[cfg(test)] pub fn user_can_access(_addr: usize, _write: bool) -> bool { false }
+#[cfg(test)] pub fn kernel_can_read(_addr: usize) -> bool { false }
+#[cfg(test)] pub fn kernel_can_write(_addr: usize) -> bool { false }
+#[cfg(test)] pub fn set_single_step(_enabled: bool) { }
+#[cfg(test)] pub fn enable_debug_exceptions() { }
+#[cfg(test)] pub fn set_hw_breakpoint(_n: usize, _value: u64, _control: u32) { }
+#[cfg(test)] pub fn set_hw_watchpoint(_n: usize, _value: u64, _control: u32) { }
+#[cfg(test)] pub fn start_cycle_counter(_interrupt: bool) { }
+#[cfg(test)] pub fn stop_cycle_counter() { }
+#[cfg(test)] pub fn cycle_counter() -> u64 { 0 }
+#[cfg(test)] pub fn set_cycle_counter(_cycles: u64) { }
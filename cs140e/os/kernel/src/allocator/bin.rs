@@ -1,36 +1,235 @@
 use std::fmt;
 use std::cmp::{min, max};
-use alloc::heap::{AllocErr, Layout};
+use core::alloc::Layout;
 
 use allocator::util::*;
-use allocator::linked_list::LinkedList;
+use allocator::AllocErr;
+use allocator::bitmap::Bitmap;
+use allocator::free_list::{FreeList, MIN_BLOCK_SIZE};
+use allocator::page::{PageAllocator, PAGE_SIZE};
+use allocator::AllocStats;
 
-/// A simple allocator that allocates based on size classes.
-#[derive(Debug)]
+#[cfg(feature = "heap-debug")]
+use allocator::debug;
+
+/// The number of size classes below a page. Bin `i` holds blocks of
+/// `block_size(i)` bytes; anything larger is served by the page allocator.
+pub const BIN_COUNT: usize = 8;
+
+/// The maximum number of discontiguous regions a single allocator manages.
+pub const MAX_REGIONS: usize = 8;
+
+/// log2 of `MIN_BLOCK_SIZE`: blocks in bin `i` are `1 << (i + MIN_SHIFT)`
+/// bytes.
+const MIN_SHIFT: usize = 4;
+
+/// Returns the size, in bytes, of blocks in bin `index`.
+pub fn block_size(index: usize) -> usize {
+    1 << (index + MIN_SHIFT)
+}
+
+/// How an allocation is served.
+#[derive(Debug, Copy, Clone)]
+enum Class {
+    /// From the bin with the given index.
+    Bin(usize),
+    /// Directly by the page allocator, as the given number of pages.
+    Pages(usize),
+}
+
+impl Class {
+    /// Returns the number of bytes of memory backing an allocation of this
+    /// class.
+    fn size(self) -> usize {
+        match self {
+            Class::Bin(index) => block_size(index),
+            Class::Pages(count) => count * PAGE_SIZE,
+        }
+    }
+}
+
+/// A buddy allocator that allocates based on power-of-two size classes.
+///
+/// Requests of a page or more are served by a `PageAllocator` in whole pages.
+/// Smaller requests are served from bins, which refill a page at a time from
+/// the page allocator and return pages to it once fully coalesced.
 pub struct Allocator {
-    bins: [LinkedList; 32],
+    bins: [FreeList; BIN_COUNT],
+    bitmap: Bitmap,
+    pages: PageAllocator,
+    regions: [(usize, usize); MAX_REGIONS],
+    region_count: usize,
+    stats: AllocStats,
 }
 
 impl Allocator {
     /// Creates a new bin allocator that will allocate memory from the region
     /// starting at address `start` and ending at address `end`.
     pub fn new(start: usize, end: usize) -> Allocator {
-        let mut bins = [LinkedList::new(); 32];
-        let mut start = start;
-
-        while start < end {
-            let sz = min(1 << start.trailing_zeros(),
-                        (end - start).next_power_of_two() << 1);
-            if sz >= 1 << 3 {
-                unsafe {
-                    bins[sz.trailing_zeros() as usize - 3]
-                        .push(start as *mut usize);
-                }
+        Allocator::with_regions(&[(start, end)])
+    }
+
+    /// Creates a new bin allocator that will allocate memory from each of the
+    /// `(start, end)` ranges in `regions`. Empty ranges are ignored.
+    ///
+    /// The free-block bitmaps are allocated from the heap itself, after the
+    /// regions have been carved into blocks.
+    ///
+    /// # Panics
+    ///
+    /// Panics if more than `MAX_REGIONS` non-adjacent ranges are given, if
+    /// any two ranges overlap, or if the heap cannot hold the bitmaps.
+    pub fn with_regions(regions: &[(usize, usize)]) -> Allocator {
+        let mut allocator = Allocator {
+            bins: [FreeList::new(); BIN_COUNT],
+            bitmap: Bitmap::empty(),
+            pages: PageAllocator::new(),
+            regions: [(0, 0); MAX_REGIONS],
+            region_count: 0,
+            stats: AllocStats::default(),
+        };
+
+        for &(start, end) in regions {
+            allocator.add_region(start, end);
+        }
+
+        if allocator.region_count > 0 {
+            allocator.build_bitmaps();
+        }
+
+        allocator
+    }
+
+    /// Records the memory from `start` to `end` as belonging to this
+    /// allocator and carves it into free blocks. A region adjacent to an
+    /// existing one is merged with it, so blocks coalesced across the boundary
+    /// remain within a single region.
+    fn add_region(&mut self, start: usize, end: usize) {
+        if start >= end {
+            return;
+        }
+
+        let mut merged = false;
+        for region in self.regions[..self.region_count].iter_mut() {
+            if start < region.1 && region.0 < end {
+                panic!("heap region {:#x}..{:#x} overlaps {:#x}..{:#x}",
+                       start, end, region.0, region.1);
+            } else if region.1 == start {
+                region.1 = end;
+                merged = true;
+            } else if region.0 == end {
+                region.0 = start;
+                merged = true;
+            }
+        }
+
+        if !merged {
+            if self.region_count == MAX_REGIONS {
+                panic!("too many heap regions (max {})", MAX_REGIONS);
+            }
+            self.regions[self.region_count] = (start, end);
+            self.region_count += 1;
+        }
+
+        self.carve(start, end);
+    }
+
+    /// Pushes the memory from `start` to `end` onto the free lists: whole
+    /// pages go to the page allocator, and the slivers before and after them
+    /// to the bins.
+    fn carve(&mut self, start: usize, end: usize) {
+        let (first, last) = (align_up(start, PAGE_SIZE), align_down(end, PAGE_SIZE));
+        if first < last {
+            self.carve_bins(start, first);
+            self.pages.add_range(first, last);
+            self.carve_bins(last, end);
+        } else {
+            self.carve_bins(start, end);
+        }
+    }
+
+    /// Pushes the memory from `start` to `end`, less than a page, onto the
+    /// bins' free lists.
+    fn carve_bins(&mut self, start: usize, end: usize) {
+        let mut current = start;
+
+        // Carve the region into the largest blocks that are both aligned to
+        // their own size and fit before `end`. Allocation relies on this
+        // invariant to satisfy alignment by size class alone.
+        while current < end {
+            let remaining = 1 << (63 - (end - current).leading_zeros());
+            let sz = min(min(1 << current.trailing_zeros(), remaining),
+                         block_size(BIN_COUNT - 1));
+            if sz >= MIN_BLOCK_SIZE {
+                #[cfg(feature = "heap-debug")]
+                debug::poison(current, sz);
+
+                self.push_free(sz.trailing_zeros() as usize - MIN_SHIFT, current);
             }
-            start += sz;
+            current += sz;
+        }
+    }
+
+    /// Allocates the page allocator's and the bins' free-block bitmaps from
+    /// the heap and marks every block currently on a free list.
+    fn build_bitmaps(&mut self) {
+        let start = self.regions().iter().map(|r| r.0).min().unwrap();
+        let end = self.regions().iter().map(|r| r.1).max().unwrap();
+
+        if self.pages.total_pages() > 0 {
+            let mut bitmap = PageAllocator::bitmap_for(align_down(start, PAGE_SIZE),
+                                                       align_up(end, PAGE_SIZE));
+            unsafe { bitmap.set_storage(self.alloc_bitmap(bitmap.storage_size())); }
+            self.pages.set_bitmap(bitmap);
         }
 
-        Allocator { bins: bins }
+        let mut bitmap = Bitmap::new(align_down(start, MIN_BLOCK_SIZE), end, MIN_SHIFT, BIN_COUNT);
+        unsafe { bitmap.set_storage(self.alloc_bitmap(bitmap.storage_size())); }
+        self.bitmap = bitmap;
+
+        for index in 0..BIN_COUNT {
+            let mut next = self.bins[index].peek();
+            while let Some(addr) = next {
+                self.bitmap.set(index, addr, true);
+                next = unsafe { FreeList::next_of(addr) };
+            }
+        }
+    }
+
+    /// Allocates `size` bytes of storage for a bitmap.
+    fn alloc_bitmap(&mut self, size: usize) -> *mut u64 {
+        let layout = Layout::from_size_align(max(size, 8), 8).unwrap();
+        self.alloc_inner(layout).expect("heap too small for its free-block bitmaps") as *mut u64
+    }
+
+    /// Returns the regions managed by this allocator.
+    pub fn regions(&self) -> &[(usize, usize)] {
+        &self.regions[..self.region_count]
+    }
+
+    /// Pushes the block at `addr` onto bin `index`'s free list.
+    fn push_free(&mut self, index: usize, addr: usize) {
+        unsafe { self.bins[index].push(addr); }
+        self.bitmap.set(index, addr, true);
+    }
+
+    /// Pops a block from bin `index`'s free list, if it is not empty.
+    fn pop_free(&mut self, index: usize) -> Option<usize> {
+        let addr = self.bins[index].pop()?;
+        self.bitmap.set(index, addr, false);
+        Some(addr)
+    }
+
+    /// Removes the block at `addr` from bin `index`'s free list if it is on
+    /// it. Returns `true` if the block was removed.
+    fn take_free(&mut self, index: usize, addr: usize) -> bool {
+        if !self.bitmap.test(index, addr) {
+            return false;
+        }
+
+        unsafe { self.bins[index].remove(addr); }
+        self.bitmap.set(index, addr, false);
+        true
     }
 
     /// Allocates memory. Returns a pointer meeting the size and alignment
@@ -51,39 +250,125 @@ impl Allocator {
     /// # Errors
     ///
     /// Returning `Err` indicates that either memory is exhausted
-    /// (`AllocError::Exhausted`) or `layout` does not meet this allocator's
-    /// size or alignment constraints (`AllocError::Unsupported`).
+    /// (`AllocErr::Exhausted`) or `layout` does not meet this allocator's
+    /// size or alignment constraints (`AllocErr::Unsupported`).
     pub fn alloc(&mut self, layout: Layout) -> Result<*mut u8, AllocErr> {
+        self.stats.alloc_calls += 1;
+        let result = self.alloc_inner(layout.clone());
+        match result {
+            Ok(_) => {
+                self.stats.bytes_in_use += layout.size();
+                self.stats.peak_bytes = max(self.stats.peak_bytes, self.stats.bytes_in_use);
+            }
+            Err(_) => self.stats.failed_allocs += 1,
+        }
+
+        result
+    }
+
+    /// Allocates a block for `layout`.
+    #[cfg(not(feature = "heap-debug"))]
+    fn alloc_inner(&mut self, layout: Layout) -> Result<*mut u8, AllocErr> {
+        self.alloc_block(layout)
+    }
+
+    /// Allocates a block for `layout`, surrounded by red zones when
+    /// heap-debug is enabled.
+    #[cfg(feature = "heap-debug")]
+    fn alloc_inner(&mut self, layout: Layout) -> Result<*mut u8, AllocErr> {
+        let padded = match debug::padded(&layout) {
+            Some(padded) => padded,
+            None => return Err(AllocErr::Exhausted { request: layout }),
+        };
+
+        match self.alloc_block(padded.clone()) {
+            Ok(block) => {
+                let size = Self::block_size_for(&padded).unwrap();
+                Ok(debug::arm(block as usize, size, &layout) as *mut u8)
+            }
+            Err(AllocErr::Exhausted { .. }) => Err(AllocErr::Exhausted { request: layout }),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Takes a block for `layout` from the bins or the page allocator.
+    fn alloc_block(&mut self, layout: Layout) -> Result<*mut u8, AllocErr> {
 		if !layout.align().is_power_of_two() {
 			return Err(AllocErr::Unsupported {details: "Requested layout is not a power of two"} );
 		} else if layout.align() <= 0 {
 			return Err(AllocErr::Unsupported {details: "Requested layout is too small"} );
 		}
 
-        let sz_bits = layout.size().next_power_of_two().trailing_zeros();
-		Self::_alloc(self, sz_bits as usize, layout.align(), layout)
-    }
-	
-    fn _alloc(&mut self, sz: usize, align: usize, layout: Layout) -> Result<*mut u8, AllocErr> {
-        let bin_index = sz.saturating_sub(3);
-        if bin_index >= 32 {
-            return Err(AllocErr::Exhausted{
-                request: layout
-            })
-        }
-        for node in self.bins[bin_index].iter_mut() {
-            let addr = node.value() as usize;
-            if (addr / align) * align == addr {
-                return Ok(node.pop() as *mut u8);
+        let block = match Self::class_for(&layout) {
+            Some(Class::Bin(index)) => self.alloc_from_bins(index),
+            Some(Class::Pages(count)) => self.pages.alloc(count, max(layout.align(), PAGE_SIZE)),
+            None => None,
+        };
+
+        match block {
+            Some(block) => Ok(block as *mut u8),
+            None => Err(AllocErr::Exhausted { request: layout }),
+        }
+    }
+
+    /// Takes a block from bin `bin_index`, splitting larger blocks as needed.
+    /// If every bin that could serve the request is empty, a page is taken
+    /// from the page allocator and split instead.
+    fn alloc_from_bins(&mut self, bin_index: usize) -> Option<usize> {
+        // Find the smallest non-empty bin that can satisfy the request.
+        let mut index = bin_index;
+        while index < BIN_COUNT && self.bins[index].is_empty() {
+            index += 1;
+        }
+
+        // Every free block is aligned to its own size, so splitting a block
+        // in half yields two blocks that are each aligned to the new size. The
+        // lower half is kept; the upper half is returned to the smaller bin.
+        //
+        // With heap-debug, the block is checked before it is unlinked: a write
+        // after free may have clobbered its links too.
+        let block = match index < BIN_COUNT {
+            true => {
+                #[cfg(feature = "heap-debug")]
+                debug::check_poison(self.bins[index].peek().unwrap(), block_size(index));
+
+                self.pop_free(index).unwrap()
             }
+            false => self.pages.alloc(1, PAGE_SIZE)?,
+        };
+
+        while index > bin_index {
+            index -= 1;
+            self.push_free(index, block + block_size(index));
         }
-        let new_node = Self::_alloc(self, sz + 1, align, layout)?; 
-        unsafe {
-            self.bins[bin_index]
-                .push(new_node.add(1 << sz) as *mut usize);
+
+        Some(block)
+    }
+
+    /// Returns how `layout` is served, or `None` if it is too large to
+    /// serve at all.
+    ///
+    /// The bin is chosen from `max(size, align)`: since every block is aligned
+    /// to its own size, a block at least as large as `align` is always
+    /// suitably aligned. Requests that would need a block of a page or more
+    /// are served by the page allocator, rounded up to whole pages rather
+    /// than to a power of two. This must agree between `alloc` and `dealloc`.
+    fn class_for(layout: &Layout) -> Option<Class> {
+        let size = max(max(layout.size(), layout.align()), MIN_BLOCK_SIZE);
+        let size = size.checked_next_power_of_two()?;
+        if size < PAGE_SIZE {
+            return Some(Class::Bin(size.trailing_zeros() as usize - MIN_SHIFT));
         }
-        Ok(new_node)
-	}
+
+        let count = layout.size().checked_add(PAGE_SIZE - 1)? / PAGE_SIZE;
+        Some(Class::Pages(max(count, 1)))
+    }
+
+    /// Returns the number of bytes of memory that serve `layout`, or `None` if
+    /// the layout is too large to serve at all.
+    pub fn block_size_for(layout: &Layout) -> Option<usize> {
+        Self::class_for(layout).map(Class::size)
+    }
 
     /// Deallocates the memory referenced by `ptr`.
     ///
@@ -99,42 +384,239 @@ impl Allocator {
     /// Parameters not meeting these conditions may result in undefined
     /// behavior.
     pub fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
-        let sz_bits = layout.size().next_power_of_two().trailing_zeros();
+        self.stats.dealloc_calls += 1;
+        self.stats.bytes_in_use = self.stats.bytes_in_use.saturating_sub(layout.size());
+
+        #[cfg(feature = "heap-debug")]
+        let (ptr, layout) = match debug::padded(&layout) {
+            Some(padded) => (self.disarm(ptr as usize, &layout, &padded) as *mut u8, padded),
+            None => return,
+        };
 
-        Self::_dealloc(self, ptr, sz_bits as usize)
+        self.free_block(ptr as usize, layout);
     }
 
-    fn _dealloc(&mut self, ptr: *mut u8, sz: usize) {
-        let my_addr = ptr as usize;
-        let mut buddy : Option<usize> = None;
-        let buddy_addr = my_addr ^ (1 << sz);
-        // For sz < 3, use bin[0]
-        let bin_index = sz.saturating_sub(3);
-        if bin_index >= 32 {
-            return;
+    /// Returns the memory at `block` for `layout` to the bins or the page
+    /// allocator.
+    fn free_block(&mut self, block: usize, layout: Layout) {
+        match Self::class_for(&layout) {
+            Some(Class::Bin(index)) => self.free_to_bins(block, index),
+            Some(Class::Pages(count)) => self.pages.free(block, count),
+            None => (),
         }
+    }
+
+    /// Returns the block at `block` to bin `index`, coalescing it with its
+    /// buddies. A block that coalesces into a whole page is returned to the
+    /// page allocator.
+    fn free_to_bins(&mut self, block: usize, index: usize) {
+        let mut index = index;
+
+        #[cfg(feature = "heap-debug")]
+        debug::poison(block, block_size(index));
 
-        for node in self.bins[bin_index].iter_mut() {
-            let node_addr = node.value() as usize;
-            if node_addr == buddy_addr {
-                node.pop();
-                buddy = Some(node_addr);
+        // Coalesce with the buddy block for as long as the buddy is free.
+        let mut block = block;
+        while index < BIN_COUNT {
+            let buddy = block ^ block_size(index);
+            if !self.take_free(index, buddy) {
                 break;
             }
+
+            // The upper half's list links are now in the middle of a free
+            // block.
+            #[cfg(feature = "heap-debug")]
+            debug::poison(max(block, buddy), MIN_BLOCK_SIZE);
+
+            block = min(block, buddy);
+            index += 1;
+        }
+
+        match index < BIN_COUNT {
+            true => self.push_free(index, block),
+            false => self.pages.free(block, 1),
+        }
+    }
+}
+
+/// An overwritten red zone, as found by `check_heap()`.
+#[cfg(feature = "heap-debug")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Corruption {
+    /// The first overwritten byte.
+    pub addr: usize,
+    /// The start of the block containing `addr`.
+    pub block: usize,
+    /// The block's size class, or `None` if its header was overwritten.
+    pub block_size: Option<usize>,
+}
+
+#[cfg(feature = "heap-debug")]
+impl Allocator {
+    /// Checks the red zones around the allocation at `ptr` for `layout`,
+    /// padded to `padded`, and returns the address of its block.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the block is already free or a red zone was overwritten.
+    fn disarm(&self, ptr: usize, layout: &Layout, padded: &Layout) -> usize {
+        let block = ptr - debug::pad(layout);
+        let class = Self::class_for(padded).unwrap();
+        self.check_double_free(block, class);
+
+        if let Err(addr) = debug::check(block, class.size(), layout) {
+            panic!("heap-debug: red zone overwritten at {:#x}: allocation {:#x} \
+                    (size {}) in block {:#x} (size class {})",
+                   addr, ptr, layout.size(), block, class.size());
         }
 
-        match buddy {
-            Some(node_addr) => {
-                let new_addr = min(my_addr, node_addr);
-                Self::_dealloc(self, new_addr as *mut u8, sz + 1);
+        block
+    }
+
+    /// Checks the red zones of every allocated block in the heap.
+    ///
+    /// Walks each region block by block: free blocks are found in the bins'
+    /// and the page allocator's bitmaps, and every other block must begin
+    /// with an intact allocation header.
+    /// Returns the number of allocations checked or the first corruption.
+    pub fn check_heap(&self) -> Result<usize, Corruption> {
+        let mut checked = 0;
+        for &(start, end) in self.regions() {
+            let mut addr = align_up(start, MIN_BLOCK_SIZE);
+            while addr + MIN_BLOCK_SIZE <= end {
+                let free = (0..BIN_COUNT).rev()
+                    .find(|&i| addr % block_size(i) == 0 && self.bitmap.test(i, addr))
+                    .map(block_size)
+                    .or_else(|| self.pages.free_block_at(addr));
+                if let Some(size) = free {
+                    addr += size;
+                    continue;
+                }
+
+                let size = debug::header(addr)
+                    .and_then(|layout| debug::padded(&layout))
+                    .and_then(|padded| Self::block_size_for(&padded))
+                    .filter(|&size| addr + size <= end);
+                let (layout, size) = match size {
+                    Some(size) => (debug::header(addr).unwrap(), size),
+                    None => return Err(Corruption { addr, block: addr, block_size: None }),
+                };
+
+                if let Err(bad) = debug::check(addr, size, &layout) {
+                    return Err(Corruption { addr: bad, block: addr, block_size: Some(size) });
+                }
+
+                checked += 1;
+                addr += size;
             }
-            None => {
-                unsafe {
-                    self.bins[sz - 3].push(ptr as *mut usize);
+        }
+
+        Ok(checked)
+    }
+
+    /// Panics if the block at `addr` of `class` is already free: either on
+    /// the free list of its own bin, contained in a larger free block it was
+    /// coalesced into, or part of a free block of pages.
+    fn check_double_free(&self, addr: usize, class: Class) {
+        let mut free = self.pages.free_block_containing(addr);
+        if let Class::Bin(index) = class {
+            for i in index..BIN_COUNT {
+                let containing = align_down(addr, block_size(i));
+                if self.bitmap.test(i, containing) {
+                    free = Some((containing, block_size(i)));
                 }
             }
         }
-	}
+
+        if let Some((block, size)) = free {
+            panic!("heap-debug: double free of {:#x} (size {}); already free in \
+                    block {:#x} (size {})", addr, class.size(), block, size);
+        }
+    }
+}
+
+/// The result of walking a single bin's free list.
+struct Occupancy {
+    /// The number of well-formed blocks found before the walk ended.
+    blocks: usize,
+    /// The address of the first node that failed validation, if any.
+    corrupt: Option<usize>,
+}
+
+impl Allocator {
+    /// Returns a snapshot of this allocator's statistics.
+    ///
+    /// The counters are maintained on every call to `alloc` and `dealloc`;
+    /// `per_bin_frees` is computed here by walking each bin's free list.
+    pub fn stats(&self) -> AllocStats {
+        let mut stats = self.stats;
+        for index in 0..BIN_COUNT {
+            stats.per_bin_frees[index] = self.occupancy(index).blocks;
+        }
+        stats.free_pages = self.pages.free_pages();
+        stats.total_pages = self.pages.total_pages();
+        stats
+    }
+
+    /// Walks the free list of bin `index` without trusting its contents.
+    ///
+    /// Every node is checked to lie within the heap, to be aligned to the
+    /// bin's block size, and to be marked free in the bitmap before it is
+    /// dereferenced, and the walk is bounded by the number of blocks that
+    /// could possibly fit in the heap, so a corrupt or cyclic list terminates
+    /// instead of faulting or spinning.
+    fn occupancy(&self, index: usize) -> Occupancy {
+        let size = block_size(index);
+        let total: usize = self.regions().iter().map(|&(s, e)| e - s).sum();
+        let limit = total / size;
+        let tracked = self.bitmap.has_storage();
+
+        let mut blocks = 0;
+        let mut next = self.bins[index].peek();
+        while let Some(addr) = next {
+            let valid = addr % size == 0
+                && (!tracked || self.bitmap.test(index, addr))
+                && self.regions().iter()
+                    .any(|&(s, e)| addr >= s && addr.saturating_add(size) <= e);
+            if !valid || blocks >= limit {
+                return Occupancy { blocks, corrupt: Some(addr) };
+            }
+
+            blocks += 1;
+            next = unsafe { FreeList::next_of(addr) };
+        }
+
+        Occupancy { blocks, corrupt: None }
+    }
+}
+
+impl fmt::Debug for Allocator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Allocator {{ heap:")?;
+        for &(start, end) in self.regions() {
+            write!(f, " {:#x}..{:#x}", start, end)?;
+        }
+        writeln!(f)?;
+
+        let mut total = 0;
+        for index in 0..BIN_COUNT {
+            if self.bins[index].is_empty() {
+                continue;
+            }
+
+            let size = block_size(index);
+            let occupancy = self.occupancy(index);
+            total += occupancy.blocks * size;
+            write!(f, "    bin {:2} ({:>10} B): {:6} free, {:>10} bytes",
+                   index, size, occupancy.blocks, occupancy.blocks * size)?;
+            match occupancy.corrupt {
+                Some(addr) => writeln!(f, " (corrupt node at {:#x})", addr)?,
+                None => writeln!(f)?,
+            }
+        }
+
+        writeln!(f, "    {:?}", self.pages)?;
+        total += self.pages.free_pages() * PAGE_SIZE;
+        write!(f, "    total free: {} bytes }}", total)
+    }
 }
-//
-// FIXME: Implement `Debug` for `Allocator`.
@@ -0,0 +1,115 @@
+use std::ptr;
+
+/// The maximum number of levels (block sizes) a `Bitmap` can track.
+pub const MAX_LEVELS: usize = 32;
+
+/// One bit per potential block at each level of a buddy allocator, set while
+/// that block is on the level's free list.
+///
+/// Blocks at level `i` are `1 << (i + shift)` bytes. With every block aligned
+/// to its own size, the block at `addr` in level `i` has bit
+/// `(addr - base) >> (i + shift)`. This makes "is my buddy free?" a single bit
+/// test instead of a walk of the buddy's free list.
+pub struct Bitmap {
+    base: usize,
+    shift: usize,
+    words: *mut u64,
+    /// Offset, in bits, of each level's bits within `words`.
+    offsets: [usize; MAX_LEVELS],
+    /// Number of bits for each level.
+    lens: [usize; MAX_LEVELS],
+    /// Total number of bits.
+    bits: usize,
+}
+
+unsafe impl Send for Bitmap {}
+
+impl Bitmap {
+    /// Returns a bitmap that tracks nothing. Every query returns `false`.
+    pub const fn empty() -> Bitmap {
+        Bitmap {
+            base: 0,
+            shift: 0,
+            words: ptr::null_mut(),
+            offsets: [0; MAX_LEVELS],
+            lens: [0; MAX_LEVELS],
+            bits: 0,
+        }
+    }
+
+    /// Returns a bitmap covering `base..end` for `levels` levels, the smallest
+    /// of which holds blocks of `1 << shift` bytes. The bitmap has no storage
+    /// until `set_storage()` is called.
+    ///
+    /// Levels whose blocks are larger than the span get no bits at all.
+    pub fn new(base: usize, end: usize, shift: usize, levels: usize) -> Bitmap {
+        let mut bitmap = Bitmap { base, shift, ..Bitmap::empty() };
+        for level in 0..levels {
+            if 1 << (level + shift) > end - base {
+                break;
+            }
+
+            bitmap.offsets[level] = bitmap.bits;
+            bitmap.lens[level] = ((end - base) >> (level + shift)) + 1;
+            bitmap.bits += bitmap.lens[level];
+        }
+
+        bitmap
+    }
+
+    /// Returns the number of bytes of storage this bitmap needs.
+    pub fn storage_size(&self) -> usize {
+        (self.bits + 63) / 64 * 8
+    }
+
+    /// Sets this bitmap's storage to the `storage_size()` bytes at `words`
+    /// and clears every bit.
+    ///
+    /// # Safety
+    ///
+    /// `words` must be 8-byte aligned, writeable, and valid for as long as
+    /// the bitmap is used.
+    pub unsafe fn set_storage(&mut self, words: *mut u64) {
+        ptr::write_bytes(words as *mut u8, 0, self.storage_size());
+        self.words = words;
+    }
+
+    /// Returns `true` if this bitmap has storage.
+    pub fn has_storage(&self) -> bool {
+        !self.words.is_null()
+    }
+
+    /// Returns the (word, mask) position of the block at `addr` in `level`,
+    /// or `None` if the address lies outside the tracked span.
+    fn position(&self, level: usize, addr: usize) -> Option<(usize, u64)> {
+        if self.words.is_null() {
+            return None;
+        }
+
+        let bit = addr.checked_sub(self.base)? >> (level + self.shift);
+        if bit >= self.lens[level] {
+            return None;
+        }
+
+        let bit = self.offsets[level] + bit;
+        Some((bit / 64, 1 << (bit % 64)))
+    }
+
+    /// Returns `true` if the block at `addr` in `level` is marked free.
+    pub fn test(&self, level: usize, addr: usize) -> bool {
+        match self.position(level, addr) {
+            Some((word, mask)) => unsafe { *self.words.add(word) & mask != 0 },
+            None => false,
+        }
+    }
+
+    /// Marks the block at `addr` in `level` as free or not.
+    pub fn set(&mut self, level: usize, addr: usize, free: bool) {
+        if let Some((word, mask)) = self.position(level, addr) {
+            unsafe {
+                let word = self.words.add(word);
+                *word = if free { *word | mask } else { *word & !mask };
+            }
+        }
+    }
+}
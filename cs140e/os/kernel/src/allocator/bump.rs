@@ -1,19 +1,60 @@
-use alloc::heap::{AllocErr, Layout};
+use core::alloc::Layout;
 
 use allocator::util::*;
+use allocator::AllocErr;
 
-/// A "bump" allocator: allocates memory by bumping a pointer; never frees.
+/// A "bump" allocator: allocates memory by bumping a pointer.
+///
+/// Individual allocations are never freed. Instead, a `checkpoint()` can be
+/// taken and everything allocated after it released at once with `reset_to()`.
 #[derive(Debug)]
 pub struct Allocator {
+    start: usize,
     current: usize,
     end: usize,
 }
 
+/// A saved position of a bump `Allocator`, as returned by `checkpoint()`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Checkpoint(usize);
+
 impl Allocator {
     /// Creates a new bump allocator that will allocate memory from the region
     /// starting at address `start` and ending at address `end`.
     pub fn new(start: usize, end: usize) -> Allocator {
-        Allocator {current: start, end: end}
+        Allocator {start: start, current: start, end: end}
+    }
+
+    /// Returns the number of bytes allocated so far, including alignment
+    /// padding.
+    pub fn used(&self) -> usize {
+        self.current - self.start
+    }
+
+    /// Returns a checkpoint at the current allocation position.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint(self.current)
+    }
+
+    /// Releases every allocation made since `checkpoint` was taken.
+    ///
+    /// # Safety
+    ///
+    /// The _caller_ must ensure that nothing allocated after `checkpoint` is
+    /// used again. Memory handed out before the checkpoint is unaffected.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `checkpoint` was not taken from this allocator or lies
+    /// beyond its current position, as happens when it was taken after an
+    /// earlier checkpoint that has since been reset to.
+    pub fn reset_to(&mut self, checkpoint: Checkpoint) {
+        if checkpoint.0 < self.start || checkpoint.0 > self.current {
+            panic!("stale bump checkpoint {:#x} (allocator at {:#x}..{:#x})",
+                   checkpoint.0, self.start, self.current);
+        }
+
+        self.current = checkpoint.0;
     }
 
     /// Allocates memory. Returns a pointer meeting the size and alignment
@@ -34,8 +75,8 @@ impl Allocator {
     /// # Errors
     ///
     /// Returning `Err` indicates that either memory is exhausted
-    /// (`AllocError::Exhausted`) or `layout` does not meet this allocator's
-    /// size or alignment constraints (`AllocError::Unsupported`).
+    /// (`AllocErr::Exhausted`) or `layout` does not meet this allocator's
+    /// size or alignment constraints (`AllocErr::Unsupported`).
     pub fn alloc(&mut self, layout: Layout) -> Result<*mut u8, AllocErr> {
         let new_start = align_up(self.current, layout.align()).saturating_add(layout.size());
         if new_start > self.end {
@@ -0,0 +1,106 @@
+use std::cmp::max;
+use std::mem::size_of;
+use core::alloc::Layout;
+
+use allocator::free_list::MIN_BLOCK_SIZE;
+
+/// The minimum number of guard bytes on either side of an allocation.
+pub const RED_ZONE: usize = 16;
+
+/// The byte pattern written into red zones.
+pub const CANARY: u8 = 0xCB;
+
+/// Marks the start of an allocated block.
+const MAGIC: u32 = 0x5AFE_B10C;
+
+/// Written at the start of every allocated block, so `check_heap()` can
+/// find the allocation within it.
+#[repr(C)]
+struct Header {
+    size: usize,
+    pad: u32,
+    magic: u32,
+}
+
+/// Returns the number of bytes between the start of a block and the
+/// allocation for `layout` within it.
+pub fn pad(layout: &Layout) -> usize {
+    max(RED_ZONE, layout.align())
+}
+
+/// Returns the layout of the block that holds `layout` and its red zones.
+pub fn padded(layout: &Layout) -> Option<Layout> {
+    let pad = pad(layout);
+    let size = pad.checked_add(layout.size())?.checked_add(RED_ZONE)?;
+    Layout::from_size_align(size, pad).ok()
+}
+
+/// Writes the header and red zones for `layout` into the block at `block`
+/// of `size` bytes. Returns the address of the allocation.
+pub fn arm(block: usize, size: usize, layout: &Layout) -> usize {
+    let ptr = block + pad(layout);
+    let end = ptr + layout.size();
+    unsafe {
+        ::std::ptr::write_bytes(block as *mut u8, CANARY, ptr - block);
+        ::std::ptr::write_bytes(end as *mut u8, CANARY, block + size - end);
+        *(block as *mut Header) = Header {
+            size: layout.size(),
+            pad: pad(layout) as u32,
+            magic: MAGIC,
+        };
+    }
+    ptr
+}
+
+/// Returns the layout recorded in the header at `block`, or `None` if the
+/// header is not intact.
+pub fn header(block: usize) -> Option<Layout> {
+    let header = unsafe { &*(block as *const Header) };
+    let pad = header.pad as usize;
+    if header.magic != MAGIC || !pad.is_power_of_two() || pad < RED_ZONE {
+        return None;
+    }
+
+    Layout::from_size_align(header.size, pad).ok()
+}
+
+/// Checks the header and red zones of the block at `block` of `size`
+/// bytes holding `layout`. Returns the first bad address on failure.
+pub fn check(block: usize, size: usize, layout: &Layout) -> Result<(), usize> {
+    match header(block) {
+        Some(h) if h.size() == layout.size() && h.align() == pad(layout) => (),
+        _ => return Err(block),
+    }
+
+    let ptr = block + pad(layout);
+    let end = ptr + layout.size();
+    let zones = (block + size_of::<Header>()..ptr).chain(end..block + size);
+    for addr in zones {
+        if unsafe { *(addr as *const u8) } != CANARY {
+            return Err(addr);
+        }
+    }
+
+    Ok(())
+}
+
+/// The byte pattern written over free memory.
+pub const POISON: u8 = 0xA5;
+
+/// Fills `size` bytes at `addr` with `POISON`.
+pub fn poison(addr: usize, size: usize) {
+    unsafe { ::std::ptr::write_bytes(addr as *mut u8, POISON, size); }
+}
+
+/// Panics if any byte of the block at `addr` of `size` bytes, other than
+/// its free-list links, is not `POISON`.
+pub fn check_poison(addr: usize, size: usize) {
+    for offset in MIN_BLOCK_SIZE..size {
+        let byte = unsafe { *((addr + offset) as *const u8) };
+        if byte != POISON {
+            panic!("heap-debug: use after free: {:#x} in free block {:#x} \
+                    (size {}) was overwritten with {:#04x}",
+                   addr + offset, addr, size, byte);
+        }
+    }
+}
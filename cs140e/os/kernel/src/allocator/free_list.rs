@@ -0,0 +1,104 @@
+use std::ptr;
+
+/// The link stored in the first two words of every free block.
+#[repr(C)]
+struct Link {
+    next: *mut Link,
+    prev: *mut Link,
+}
+
+/// The minimum size of a block that can be placed on a `FreeList`.
+pub const MIN_BLOCK_SIZE: usize = 2 * ::std::mem::size_of::<usize>();
+
+/// An _intrusive_, doubly-linked list of free blocks.
+///
+/// Unlike `LinkedList`, any block on the list can be removed in constant time
+/// given only its address, which is what buddy coalescing needs. The price is
+/// a second word of link in every free block: blocks must be at least
+/// `MIN_BLOCK_SIZE` bytes.
+#[derive(Copy, Clone)]
+pub struct FreeList {
+    head: *mut Link,
+}
+
+unsafe impl Send for FreeList {}
+
+impl FreeList {
+    /// Returns a new, empty free list.
+    pub const fn new() -> FreeList {
+        FreeList { head: ptr::null_mut() }
+    }
+
+    /// Returns `true` if the list is empty and `false` otherwise.
+    pub fn is_empty(&self) -> bool {
+        self.head.is_null()
+    }
+
+    /// Returns the address of the first block in the list without removing
+    /// it, if any.
+    pub fn peek(&self) -> Option<usize> {
+        match self.is_empty() {
+            true => None,
+            false => Some(self.head as usize),
+        }
+    }
+
+    /// Pushes the block at `addr` to the front of the list.
+    ///
+    /// # Safety
+    ///
+    /// The caller must ensure that `addr` refers to unique, writeable memory
+    /// at least `MIN_BLOCK_SIZE` bytes in size that is valid as long as the
+    /// block resides in `self`.
+    pub unsafe fn push(&mut self, addr: usize) {
+        let link = addr as *mut Link;
+        (*link).next = self.head;
+        (*link).prev = ptr::null_mut();
+        if !self.head.is_null() {
+            (*self.head).prev = link;
+        }
+        self.head = link;
+    }
+
+    /// Removes and returns the address of the first block in the list, if
+    /// any.
+    pub fn pop(&mut self) -> Option<usize> {
+        let addr = self.peek()?;
+        unsafe { self.remove(addr); }
+        Some(addr)
+    }
+
+    /// Removes the block at `addr` from the list.
+    ///
+    /// # Safety
+    ///
+    /// The block at `addr` must currently be on this list.
+    pub unsafe fn remove(&mut self, addr: usize) {
+        let link = addr as *mut Link;
+        let (next, prev) = ((*link).next, (*link).prev);
+        if !next.is_null() {
+            (*next).prev = prev;
+        }
+
+        match prev.is_null() {
+            true => self.head = next,
+            false => (*prev).next = next,
+        }
+    }
+
+    /// Returns the address of the block following the block at `addr`, if
+    /// any. Used to walk a list whose contents may not be trustworthy: the
+    /// caller validates each address before asking for its successor.
+    ///
+    /// # Safety
+    ///
+    /// `addr` must refer to readable memory at least `MIN_BLOCK_SIZE` bytes
+    /// in size.
+    pub unsafe fn next_of(addr: usize) -> Option<usize> {
+        let next = (*(addr as *const Link)).next;
+        match next.is_null() {
+            true => None,
+            false => Some(next as usize),
+        }
+    }
+}
@@ -1,5 +1,26 @@
 mod linked_list;
-mod util;
+mod free_list;
+mod bitmap;
+pub mod util;
+pub mod bump;
+mod track;
+mod oom;
+pub mod slab;
+mod page;
+
+/// Heap poisoning and red zones, enabled by the `heap-debug` feature.
+///
+/// Every free block is filled with `POISON` except for its first two words,
+/// which hold the free-list links. When a block is handed out, it is checked
+/// to still be fully poisoned: any other byte means something wrote to the
+/// block while it was free.
+///
+/// Every allocation is placed in a larger block: a `Header` and `CANARY`
+/// bytes precede it, and `CANARY` bytes fill the rest of the block after it.
+/// The canaries are checked when the allocation is freed and by
+/// `check_heap()`, catching overruns before they reach a neighbouring block.
+#[cfg(feature = "heap-debug")]
+mod debug;
 
 #[path = "bin.rs"]
 mod imp;
@@ -7,13 +28,60 @@ mod imp;
 #[cfg(test)]
 mod tests;
 
-use mutex::Mutex;
-use alloc::heap::{Alloc, AllocErr, Layout};
-use std::cmp::max;
+use aarch64;
+use boot::BootInfo;
+use mutex::IrqMutex;
+use trace::{trace_event, Event};
+use core::alloc::{GlobalAlloc, Layout};
+use std::{fmt, ptr};
+
+pub use self::slab::{Counted, SlabArc, SlabBox, SlabCache, SlabStats};
+pub use self::track::{LiveAlloc, MAX_TRACKED};
+pub use self::oom::{OomAction, OomHandler, MAX_OOM_RETRIES};
+pub use self::imp::block_size;
+pub use self::page::PAGE_SIZE;
+#[cfg(feature = "heap-debug")]
+pub use self::imp::Corruption;
 
-/// Thread-safe (locking) wrapper around a particular memory allocator.
-#[derive(Debug)]
-pub struct Allocator(Mutex<Option<imp::Allocator>>);
+use self::track::Tracker;
+use self::oom::OomState;
+
+/// The reason an allocation request could not be satisfied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AllocErr {
+    /// There is not enough free memory to satisfy `request`.
+    Exhausted { request: Layout },
+    /// The request does not meet the allocator's constraints.
+    Unsupported { details: &'static str },
+    /// The allocator is locked, possibly by the caller itself.
+    Busy,
+}
+
+/// A snapshot of allocator statistics, as returned by `Allocator::stats()`.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct AllocStats {
+    /// Bytes currently allocated, as requested by callers.
+    pub bytes_in_use: usize,
+    /// The largest value `bytes_in_use` has reached.
+    pub peak_bytes: usize,
+    /// Number of calls to `alloc`, successful or not.
+    pub alloc_calls: usize,
+    /// Number of calls to `dealloc`.
+    pub dealloc_calls: usize,
+    /// Number of calls to `alloc` that returned an error.
+    pub failed_allocs: usize,
+    /// Number of free blocks in each bin. Bin `i` holds blocks of
+    /// `block_size(i)` bytes.
+    pub per_bin_frees: [usize; imp::BIN_COUNT],
+    /// Number of free pages held by the page allocator.
+    pub free_pages: usize,
+    /// Number of pages managed by the page allocator.
+    pub total_pages: usize,
+}
+
+/// Thread-safe (locking) wrapper around a particular memory allocator. Its
+/// locks mask IRQs, so that interrupt handlers can allocate.
+pub struct Allocator(IrqMutex<Option<imp::Allocator>>, IrqMutex<Tracker>, IrqMutex<OomState>);
 
 impl Allocator {
     /// Returns an uninitialized `Allocator`.
@@ -21,25 +89,134 @@ impl Allocator {
     /// The allocator must be initialized by calling `initialize()` before the
     /// first memory allocation. Failure to do will result in panics.
     pub const fn uninitialized() -> Self {
-        Allocator(Mutex::new(None))
+        Allocator(IrqMutex::new(None), IrqMutex::new(Tracker::new()), IrqMutex::new(OomState::new()))
+    }
+
+    /// Initializes the memory allocator to manage the heap regions described
+    /// by `info`.
+    pub fn initialize(&self, info: &BootInfo) {
+        *self.0.lock() = Some(imp::Allocator::with_regions(&info.heap_regions()));
+    }
+
+    /// Returns a snapshot of the allocator's statistics.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the allocator has not been initialized.
+    pub fn stats(&self) -> AllocStats {
+        self.with_lock(|a| a.stats())
+    }
+
+    /// Checks the red zones around every live allocation. Returns the number
+    /// of allocations checked or the first corruption found.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the allocator has not been initialized.
+    #[cfg(feature = "heap-debug")]
+    pub fn check_heap(&self) -> Result<usize, Corruption> {
+        self.with_lock(|a| a.check_heap())
+    }
+
+    /// Attempts to allocate memory for `layout` without blocking.
+    ///
+    /// Unlike `GlobalAlloc::alloc`, this method never spins on the allocator
+    /// lock: if the lock is held, by another core or by a re-entrant caller on
+    /// this core (an interrupt handler, for instance), `AllocErr::Busy` is
+    /// returned immediately.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the allocator has not been initialized.
+    pub fn try_alloc(&self, layout: Layout) -> Result<*mut u8, AllocErr> {
+        match self.0.try_lock() {
+            Some(mut inner) => inner.as_mut().expect("allocator uninitialized").alloc(layout),
+            None => Err(AllocErr::Busy),
+        }
+    }
+
+    /// Registers `handler` to be called when the global allocator runs out of
+    /// memory, or removes the handler if `None`. Returns the previous handler.
+    ///
+    /// The handler is called before `GlobalAlloc::alloc` returns an exhausted
+    /// allocation and may ask for the allocation to be retried, up to
+    /// `MAX_OOM_RETRIES` times. `try_alloc` never calls the handler.
+    pub fn set_oom_handler(&self, handler: Option<OomHandler>) -> Option<OomHandler> {
+        self.with_oom(|o| o.set_handler(handler))
+    }
+
+    /// Enables or disables recording of live allocations. Only allocations
+    /// made while tracking is enabled are reported by `dump_live()`.
+    pub fn set_tracking(&self, enabled: bool) {
+        self.with_tracker(|t| t.set_enabled(enabled));
+    }
+
+    /// Returns `true` if live allocations are being recorded.
+    pub fn tracking(&self) -> bool {
+        self.with_tracker(|t| t.enabled())
+    }
+
+    /// Runs `f`, tagging every allocation it makes with `tag` in the live
+    /// allocation table.
+    pub fn with_tag<R, F: FnOnce() -> R>(&self, tag: &'static str, f: F) -> R {
+        let previous = self.with_tracker(|t| t.set_tag(Some(tag)));
+        let result = f();
+        self.with_tracker(|t| t.set_tag(previous));
+        result
+    }
+
+    /// Prints every live allocation recorded since tracking was enabled,
+    /// along with totals per tag.
+    pub fn dump_live(&self) {
+        self.with_tracker(|t| t.dump());
+    }
+
+    /// Runs `f` with the tracker locked and IRQs masked on this core.
+    fn with_tracker<R, F: FnOnce(&mut Tracker) -> R>(&self, f: F) -> R {
+        f(&mut self.1.lock())
+    }
+
+    /// Runs `f` with the out-of-memory state locked and IRQs masked on this
+    /// core.
+    fn with_oom<R, F: FnOnce(&mut OomState) -> R>(&self, f: F) -> R {
+        f(&mut self.2.lock())
+    }
+
+    /// Calls the out-of-memory handler, if one is registered and not already
+    /// running, for an exhausted allocation of `layout`. Returns `true` if the
+    /// allocation should be retried.
+    fn out_of_memory(&self, layout: &Layout) -> bool {
+        let handler = match self.with_oom(|o| o.enter()) {
+            Some(handler) => handler,
+            None => return false,
+        };
+
+        let action = handler(layout);
+        self.with_oom(|o| o.exit());
+        action == OomAction::Retry
     }
 
-    /// Initializes the memory allocator.
+    /// Runs `f` with the allocator locked and IRQs masked on this core, so an
+    /// interrupt handler can never observe the free lists mid-update.
     ///
     /// # Panics
     ///
-    /// Panics if the system's memory map could not be retrieved.
-    pub fn initialize(&self) {
-        let (start, end) = memory_map().expect("failed to find memory map");
-        *self.0.lock() = Some(imp::Allocator::new(start, end));
+    /// Panics if the allocator has not been initialized or if the lock is
+    /// already held by this core, which would otherwise deadlock.
+    fn with_lock<R, F: FnOnce(&mut imp::Allocator) -> R>(&self, f: F) -> R {
+        if self.0.is_held_by_current_core() {
+            panic!("re-entrant allocator call on core {}", aarch64::affinity());
+        }
+
+        f(self.0.lock().as_mut().expect("allocator uninitialized"))
     }
 }
 
-unsafe impl<'a> Alloc for &'a Allocator {
+unsafe impl GlobalAlloc for Allocator {
     /// Allocates memory. Returns a pointer meeting the size and alignment
     /// properties of `layout.size()` and `layout.align()`.
     ///
-    /// If this method returns an `Ok(addr)`, `addr` will be non-null address
+    /// If this method returns a non-null `addr`, `addr` will be an address
     /// pointing to a block of storage suitable for holding an instance of
     /// `layout`. In particular, the block will be at least `layout.size()`
     /// bytes large and will be aligned to `layout.align()`. The returned block
@@ -53,11 +230,25 @@ unsafe impl<'a> Alloc for &'a Allocator {
     ///
     /// # Errors
     ///
-    /// Returning `Err` indicates that either memory is exhausted
-    /// (`AllocError::Exhausted`) or `layout` does not meet this allocator's
-    /// size or alignment constraints (`AllocError::Unsupported`).
-    unsafe fn alloc(&mut self, layout: Layout) -> Result<*mut u8, AllocErr> {
-        self.0.lock().as_mut().expect("allocator uninitialized").alloc(layout)
+    /// Returns a null pointer if memory is exhausted or `layout` does not meet
+    /// this allocator's size or alignment constraints. If memory is exhausted,
+    /// the out-of-memory handler is consulted first.
+    #[inline(never)]
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut retries = 0;
+        loop {
+            match self.with_lock(|a| a.alloc(layout.clone())) {
+                Ok(ptr) => {
+                    let caller = caller();
+                    self.with_tracker(|t| t.record(ptr as usize, &layout, caller));
+                    trace_event!(Event::Alloc, layout.size());
+                    return ptr;
+                }
+                Err(AllocErr::Exhausted { .. })
+                    if retries < MAX_OOM_RETRIES && self.out_of_memory(&layout) => retries += 1,
+                Err(_) => return ptr::null_mut(),
+            }
+        }
     }
 
     /// Deallocates the memory referenced by `ptr`.
@@ -73,35 +264,48 @@ unsafe impl<'a> Alloc for &'a Allocator {
     ///
     /// Parameters not meeting these conditions may result in undefined
     /// behavior.
-    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
-        self.0.lock().as_mut().expect("allocator uninitialized").dealloc(ptr, layout);
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.with_lock(|a| a.dealloc(ptr, layout));
+        self.with_tracker(|t| t.forget(ptr as usize));
+        trace_event!(Event::Free, layout.size());
     }
 }
 
-extern "C" {
-    static _end: u8;
+impl fmt::Debug for Allocator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Allocator").field(&self.0).finish()
+    }
 }
 
-/// Returns the (start address, end address) of the available memory on this
-/// system if it can be determined. If it cannot, `None` is returned.
-///
-/// This function is expected to return `Some` under all normal cirumstances.
-fn memory_map() -> Option<(usize, usize)> {
-    let binary_end = unsafe { (&_end as *const u8) as usize };
-
-    use pi;
-    for tag in pi::atags::Atags::get() {
-        match tag.mem() {
-            Some(mem) => {
-                let mut head = mem.start as usize;
-                let end = (mem.start + mem.size) as usize;
-                if binary_end < end {
-                    head = max(head, binary_end);
-                }
-                return Some((head, end));
-            }
-            _ => return None,
+/// Returns the return address of the function that called into the global
+/// allocator, on a best-effort basis, by walking frame pointers past
+/// `GlobalAlloc::alloc` and the `alloc` crate's shim. Returns 0 if the frame
+/// chain looks broken.
+#[inline(always)]
+fn caller() -> usize {
+    let mut fp = aarch64::fp();
+    for _ in 0..2 {
+        if fp == 0 || fp % 16 != 0 {
+            return 0;
         }
+        fp = unsafe { *(fp as *const usize) };
+    }
+
+    match fp != 0 && fp % 16 == 0 {
+        true => unsafe { *((fp + 8) as *const usize) },
+        false => 0,
     }
-    None
+}
+
+/// Called by `alloc` when an allocation fails. Prints the allocator's state
+/// and then panics.
+#[cfg(not(test))]
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+    use console::{kprintln, log_error};
+    use ALLOCATOR;
+
+    log_error!("out of memory: failed to allocate {:?}", layout);
+    kprintln!("{:?}", ALLOCATOR);
+    panic!("out of memory")
 }
@@ -0,0 +1,57 @@
+use core::alloc::Layout;
+
+/// The maximum number of times a single allocation is retried at the request
+/// of an out-of-memory handler.
+pub const MAX_OOM_RETRIES: usize = 3;
+
+/// What the allocator should do once an out-of-memory handler returns.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OomAction {
+    /// The handler freed memory: try the allocation again.
+    Retry,
+    /// Give up: the allocation fails as it would have without a handler.
+    Fail,
+}
+
+/// A function called when an allocation for the given layout cannot be
+/// satisfied, before the failure is returned to the caller.
+///
+/// The handler runs without the allocator lock held, so it may inspect the
+/// allocator, free memory (by dropping caches, say), or panic with a report of
+/// its own. An allocation made by the handler that itself runs out of memory
+/// fails without calling the handler again.
+pub type OomHandler = fn(&Layout) -> OomAction;
+
+/// The registered out-of-memory handler and whether it is running.
+pub struct OomState {
+    handler: Option<OomHandler>,
+    running: bool,
+}
+
+impl OomState {
+    /// Returns a new state with no handler registered.
+    pub const fn new() -> OomState {
+        OomState { handler: None, running: false }
+    }
+
+    /// Registers `handler` and returns the previous one.
+    pub fn set_handler(&mut self, handler: Option<OomHandler>) -> Option<OomHandler> {
+        ::std::mem::replace(&mut self.handler, handler)
+    }
+
+    /// Marks the handler as running and returns it. Returns `None` if no
+    /// handler is registered or if it is already running.
+    pub fn enter(&mut self) -> Option<OomHandler> {
+        if self.running {
+            return None;
+        }
+
+        self.running = self.handler.is_some();
+        self.handler
+    }
+
+    /// Marks the handler as no longer running.
+    pub fn exit(&mut self) {
+        self.running = false;
+    }
+}
@@ -0,0 +1,205 @@
+use std::fmt;
+use std::cmp::{min, max};
+
+use allocator::bitmap::Bitmap;
+use allocator::free_list::FreeList;
+
+#[cfg(feature = "heap-debug")]
+use allocator::{debug, free_list::MIN_BLOCK_SIZE};
+
+/// The size of a page frame.
+pub const PAGE_SIZE: usize = 1 << PAGE_SHIFT;
+
+/// log2 of `PAGE_SIZE`.
+const PAGE_SHIFT: usize = 12;
+
+/// The number of orders. Order `i` holds blocks of `1 << i` pages.
+pub const ORDER_COUNT: usize = 24;
+
+/// A buddy allocator of page frames.
+///
+/// Free memory is kept in blocks of `1 << order` pages, each aligned to its
+/// own size, with a bitmap for constant-time buddy lookup. Unlike the bin
+/// allocator, requests are not rounded up to a power of two: the pages past
+/// the end of a request are returned to the free lists as soon as the block
+/// serving it is split off.
+pub struct PageAllocator {
+    orders: [FreeList; ORDER_COUNT],
+    bitmap: Bitmap,
+    free_pages: usize,
+    total_pages: usize,
+}
+
+impl PageAllocator {
+    /// Returns a page allocator that manages no memory.
+    pub const fn new() -> PageAllocator {
+        PageAllocator {
+            orders: [FreeList::new(); ORDER_COUNT],
+            bitmap: Bitmap::empty(),
+            free_pages: 0,
+            total_pages: 0,
+        }
+    }
+
+    /// Adds the pages from `start` to `end`, both page-aligned, to the free
+    /// lists.
+    pub fn add_range(&mut self, start: usize, end: usize) {
+        self.total_pages += (end - start) / PAGE_SIZE;
+        self.release(start, end);
+    }
+
+    /// Returns a bitmap covering `base..end`, which must contain every range
+    /// added to this allocator, without storage. Once storage is attached,
+    /// it is handed back with `set_bitmap()`.
+    pub fn bitmap_for(base: usize, end: usize) -> Bitmap {
+        Bitmap::new(base, end, PAGE_SHIFT, ORDER_COUNT)
+    }
+
+    /// Starts using `bitmap`, which has storage, for buddy lookups and marks
+    /// every block currently on a free list.
+    pub fn set_bitmap(&mut self, bitmap: Bitmap) {
+        self.bitmap = bitmap;
+        for order in 0..ORDER_COUNT {
+            let mut next = self.orders[order].peek();
+            while let Some(addr) = next {
+                self.bitmap.set(order, addr, true);
+                next = unsafe { FreeList::next_of(addr) };
+            }
+        }
+    }
+
+    /// Returns the number of free pages.
+    pub fn free_pages(&self) -> usize {
+        self.free_pages
+    }
+
+    /// Returns the number of pages managed by this allocator.
+    pub fn total_pages(&self) -> usize {
+        self.total_pages
+    }
+
+    /// Allocates `count` contiguous pages aligned to `align` bytes, which
+    /// must be a power of two. Returns the address of the first page.
+    pub fn alloc(&mut self, count: usize, align: usize) -> Option<usize> {
+        let pages = max(count.checked_next_power_of_two()?, align / PAGE_SIZE);
+        let want = pages.trailing_zeros() as usize;
+
+        let mut order = want;
+        while order < ORDER_COUNT && self.orders[order].is_empty() {
+            order += 1;
+        }
+
+        if order >= ORDER_COUNT {
+            return None;
+        }
+
+        // See `bin::Allocator::alloc_block`: the block is checked before its
+        // links are trusted.
+        #[cfg(feature = "heap-debug")]
+        debug::check_poison(self.orders[order].peek().unwrap(), PAGE_SIZE << order);
+
+        let block = self.pop_free(order).unwrap();
+        while order > want {
+            order -= 1;
+            self.push_free(order, block + (PAGE_SIZE << order));
+        }
+
+        self.free_pages -= 1 << want;
+        self.release(block + count * PAGE_SIZE, block + (PAGE_SIZE << want));
+        Some(block)
+    }
+
+    /// Frees the `count` pages starting at `addr`, as returned by `alloc`.
+    pub fn free(&mut self, addr: usize, count: usize) {
+        self.release(addr, addr + count * PAGE_SIZE);
+    }
+
+    /// Returns the size, in bytes, of the free block starting at `addr`, if
+    /// there is one.
+    pub fn free_block_at(&self, addr: usize) -> Option<usize> {
+        (0..ORDER_COUNT).rev()
+            .find(|&order| addr % (PAGE_SIZE << order) == 0 && self.bitmap.test(order, addr))
+            .map(|order| PAGE_SIZE << order)
+    }
+
+    /// Returns the `(start, size)` of the free block containing `addr`, if
+    /// there is one.
+    pub fn free_block_containing(&self, addr: usize) -> Option<(usize, usize)> {
+        (0..ORDER_COUNT)
+            .map(|order| (addr & !((PAGE_SIZE << order) - 1), order))
+            .find(|&(block, order)| self.bitmap.test(order, block))
+            .map(|(block, order)| (block, PAGE_SIZE << order))
+    }
+
+    /// Returns the pages from `start` to `end` to the free lists as the
+    /// largest aligned blocks that fit, coalescing each with its buddies.
+    fn release(&mut self, start: usize, end: usize) {
+        let mut current = start;
+        while current < end {
+            let remaining = 1 << (63 - (end - current).leading_zeros());
+            let size = min(min(1 << current.trailing_zeros(), remaining),
+                           PAGE_SIZE << (ORDER_COUNT - 1));
+            self.free_block(current, size.trailing_zeros() as usize - PAGE_SHIFT);
+            self.free_pages += size / PAGE_SIZE;
+            current += size;
+        }
+    }
+
+    /// Returns the block at `block` of `order` to the free lists, coalescing
+    /// it with its buddy for as long as the buddy is free.
+    fn free_block(&mut self, mut block: usize, mut order: usize) {
+        #[cfg(feature = "heap-debug")]
+        debug::poison(block, PAGE_SIZE << order);
+
+        while order + 1 < ORDER_COUNT {
+            let buddy = block ^ (PAGE_SIZE << order);
+            if !self.bitmap.test(order, buddy) {
+                break;
+            }
+
+            unsafe { self.orders[order].remove(buddy); }
+            self.bitmap.set(order, buddy, false);
+
+            #[cfg(feature = "heap-debug")]
+            debug::poison(max(block, buddy), MIN_BLOCK_SIZE);
+
+            block = min(block, buddy);
+            order += 1;
+        }
+
+        self.push_free(order, block);
+    }
+
+    /// Pushes the block at `addr` onto the free list of `order`.
+    fn push_free(&mut self, order: usize, addr: usize) {
+        unsafe { self.orders[order].push(addr); }
+        self.bitmap.set(order, addr, true);
+    }
+
+    /// Pops a block from the free list of `order`, if it is not empty.
+    fn pop_free(&mut self, order: usize) -> Option<usize> {
+        let addr = self.orders[order].pop()?;
+        self.bitmap.set(order, addr, false);
+        Some(addr)
+    }
+}
+
+impl fmt::Debug for PageAllocator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "pages: {} free of {}", self.free_pages, self.total_pages)?;
+        for order in 0..ORDER_COUNT {
+            let mut count = 0;
+            let mut next = self.orders[order].peek();
+            while let Some(addr) = next {
+                count += 1;
+                next = unsafe { FreeList::next_of(addr) };
+            }
+
+            if count > 0 {
+                write!(f, "\n    order {:2} ({:>10} B): {:6} free", order, PAGE_SIZE << order, count)?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,356 @@
+use std::{fmt, mem, ptr};
+use std::cmp::max;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{self, AtomicUsize, Ordering};
+use core::alloc::Layout;
+use alloc::alloc::alloc;
+
+use allocator::AllocErr;
+use allocator::linked_list::LinkedList;
+use mutex::IrqMutex;
+
+/// The minimum size of a slab, in bytes.
+const MIN_SLAB_SIZE: usize = 4096;
+
+/// The minimum number of objects that fit in a single slab.
+const MIN_OBJECTS_PER_SLAB: usize = 8;
+
+/// Statistics for a single `SlabCache`.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SlabStats {
+    /// The name of the cache.
+    pub name: &'static str,
+    /// The size of each object slot, in bytes.
+    pub object_size: usize,
+    /// The size of each slab, in bytes.
+    pub slab_size: usize,
+    /// The number of slabs obtained from the heap.
+    pub slabs: usize,
+    /// The number of objects currently allocated.
+    pub objects_in_use: usize,
+    /// The largest value `objects_in_use` has reached.
+    pub peak_objects: usize,
+    /// The number of free object slots across all slabs.
+    pub free_objects: usize,
+    /// Number of successful calls to `alloc`.
+    pub alloc_calls: usize,
+    /// Number of calls to `free`.
+    pub free_calls: usize,
+}
+
+/// A cache of fixed-size slots for objects of type `T`.
+///
+/// Slabs are obtained from the global allocator in chunks large enough to
+/// hold several objects and are carved into slots kept on an intrusive free
+/// list. Allocating and freeing an object is a list push or pop, and objects
+/// of the same type pack tightly instead of each rounding up to a power-of-two
+/// bin. Slabs are never returned to the heap.
+///
+/// A `SlabCache` does no locking of its own. To share one, put it in a
+/// `static IrqMutex` and hold its objects in `SlabBox`es or `SlabArc`s,
+/// which take the lock only to move objects in and out:
+///
+/// ```rust,ignore
+/// static PROCESSES: IrqMutex<SlabCache<Process>> = IrqMutex::new(SlabCache::new("process"));
+///
+/// let process = SlabBox::new(&PROCESSES, Process::boot())?;
+/// ```
+pub struct SlabCache<T> {
+    name: &'static str,
+    free: LinkedList,
+    stats: SlabStats,
+    _marker: PhantomData<T>,
+}
+
+unsafe impl<T: Send> Send for SlabCache<T> {}
+
+impl<T> SlabCache<T> {
+    /// Returns a new, empty cache named `name`. No memory is allocated until
+    /// the first call to `alloc`.
+    pub const fn new(name: &'static str) -> SlabCache<T> {
+        SlabCache {
+            name: name,
+            free: LinkedList::new(),
+            stats: SlabStats {
+                name: name,
+                object_size: 0,
+                slab_size: 0,
+                slabs: 0,
+                objects_in_use: 0,
+                peak_objects: 0,
+                free_objects: 0,
+                alloc_calls: 0,
+                free_calls: 0,
+            },
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns the name of this cache.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The layout of a single slot: large enough for a `T` or a free-list
+    /// link, and aligned for both.
+    fn slot_layout() -> Layout {
+        let align = max(mem::align_of::<T>(), mem::align_of::<usize>());
+        let size = max(mem::size_of::<T>(), mem::size_of::<usize>());
+        Layout::from_size_align((size + align - 1) & !(align - 1), align).unwrap()
+    }
+
+    /// The layout of a slab.
+    fn slab_layout() -> Layout {
+        let slot = Self::slot_layout();
+        let size = max(MIN_SLAB_SIZE, slot.size() * MIN_OBJECTS_PER_SLAB);
+        Layout::from_size_align(size.next_power_of_two(), slot.align()).unwrap()
+    }
+
+    /// Obtains a new slab from the heap and returns its address.
+    fn new_slab() -> Result<usize, AllocErr> {
+        let slab = Self::slab_layout();
+        match unsafe { alloc(slab.clone()) } as usize {
+            0 => Err(AllocErr::Exhausted { request: slab }),
+            base => Ok(base),
+        }
+    }
+
+    /// Pushes the slots of the slab at `base`, from `new_slab()`, onto the
+    /// free list.
+    unsafe fn add_slab(&mut self, base: usize) {
+        let (slot, slab) = (Self::slot_layout(), Self::slab_layout());
+        let count = slab.size() / slot.size();
+        for i in (0..count).rev() {
+            self.free.push((base + i * slot.size()) as *mut usize);
+        }
+
+        self.stats.object_size = slot.size();
+        self.stats.slabs += 1;
+        self.stats.free_objects += count;
+    }
+
+    /// Obtains a new slab from the heap and pushes its slots onto the free
+    /// list.
+    fn grow(&mut self) -> Result<(), AllocErr> {
+        let base = Self::new_slab()?;
+        unsafe { self.add_slab(base); }
+        Ok(())
+    }
+
+    /// Moves `value` into a free slot and returns a pointer to it, or
+    /// returns `value` if no slot is free.
+    fn try_alloc(&mut self, value: T) -> Result<*mut T, T> {
+        let slot = match self.free.pop() {
+            Some(slot) => slot as *mut T,
+            None => return Err(value),
+        };
+        unsafe { ptr::write(slot, value); }
+
+        self.stats.alloc_calls += 1;
+        self.stats.free_objects -= 1;
+        self.stats.objects_in_use += 1;
+        self.stats.peak_objects = max(self.stats.peak_objects, self.stats.objects_in_use);
+        Ok(slot)
+    }
+
+    /// Moves `value` into a free slot and returns a pointer to it, growing
+    /// the cache by one slab if no slot is free.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AllocErr::Exhausted` if a new slab is needed but the heap
+    /// could not provide one.
+    pub fn alloc(&mut self, value: T) -> Result<*mut T, AllocErr> {
+        if self.free.is_empty() {
+            self.grow()?;
+        }
+
+        match self.try_alloc(value) {
+            Ok(slot) => Ok(slot),
+            Err(_) => unreachable!("slab cache grew without a free slot"),
+        }
+    }
+
+    /// Moves the object at `ptr` out and returns its slot to the cache.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `alloc` on this same cache and must
+    /// not have been freed since.
+    pub unsafe fn take(&mut self, ptr: *mut T) -> T {
+        let value = ptr::read(ptr);
+        self.free.push(ptr as *mut usize);
+
+        self.stats.free_calls += 1;
+        self.stats.free_objects += 1;
+        self.stats.objects_in_use -= 1;
+        value
+    }
+
+    /// Drops the object at `ptr` and returns its slot to the cache.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `alloc` on this same cache and must
+    /// not have been freed since.
+    pub unsafe fn free(&mut self, ptr: *mut T) {
+        drop(self.take(ptr));
+    }
+
+    /// Returns a snapshot of this cache's statistics.
+    pub fn stats(&self) -> SlabStats {
+        let mut stats = self.stats;
+        stats.object_size = Self::slot_layout().size();
+        stats.slab_size = Self::slab_layout().size();
+        stats
+    }
+}
+
+/// Moves `value` into a slot of the shared `cache`.
+///
+/// A new slab is allocated with the cache unlocked, so that if the heap runs
+/// out, nothing freed to make room finds the cache locked.
+fn alloc_shared<T>(cache: &IrqMutex<SlabCache<T>>, value: T) -> Result<*mut T, AllocErr> {
+    let value = match cache.lock().try_alloc(value) {
+        Ok(slot) => return Ok(slot),
+        Err(value) => value,
+    };
+
+    let base = SlabCache::<T>::new_slab()?;
+    let mut cache = cache.lock();
+    unsafe { cache.add_slab(base); }
+    match cache.try_alloc(value) {
+        Ok(slot) => Ok(slot),
+        Err(_) => unreachable!("slab cache grew without a free slot"),
+    }
+}
+
+/// A `T` in a slot of a shared slab cache, like a `Box`, returned to the
+/// cache when dropped.
+pub struct SlabBox<T: 'static> {
+    ptr: *mut T,
+    cache: &'static IrqMutex<SlabCache<T>>,
+}
+
+unsafe impl<T: Send> Send for SlabBox<T> {}
+unsafe impl<T: Sync> Sync for SlabBox<T> {}
+
+impl<T> SlabBox<T> {
+    /// Moves `value` into a slot of `cache`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AllocErr::Exhausted`, dropping `value`, if the cache needs
+    /// a new slab and the heap can't provide one.
+    pub fn new(cache: &'static IrqMutex<SlabCache<T>>, value: T) -> Result<SlabBox<T>, AllocErr> {
+        Ok(SlabBox { ptr: alloc_shared(cache, value)?, cache })
+    }
+}
+
+impl<T> Deref for SlabBox<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.ptr }
+    }
+}
+
+impl<T> DerefMut for SlabBox<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.ptr }
+    }
+}
+
+impl<T> Drop for SlabBox<T> {
+    fn drop(&mut self) {
+        // The value is dropped once the cache is unlocked.
+        let value = unsafe { self.cache.lock().take(self.ptr) };
+        drop(value);
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for SlabBox<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+/// A value and the number of `SlabArc`s sharing it, as kept in their cache.
+pub struct Counted<T> {
+    count: AtomicUsize,
+    value: T,
+}
+
+/// A `T` in a slot of a shared slab cache, like an `Arc`: clones share it,
+/// and the last to be dropped returns it to the cache.
+pub struct SlabArc<T: 'static> {
+    ptr: *mut Counted<T>,
+    cache: &'static IrqMutex<SlabCache<Counted<T>>>,
+}
+
+unsafe impl<T: Send + Sync> Send for SlabArc<T> {}
+unsafe impl<T: Send + Sync> Sync for SlabArc<T> {}
+
+impl<T> SlabArc<T> {
+    /// Moves `value` into a slot of `cache`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AllocErr::Exhausted`, dropping `value`, if the cache needs
+    /// a new slab and the heap can't provide one.
+    pub fn new(cache: &'static IrqMutex<SlabCache<Counted<T>>>, value: T)
+        -> Result<SlabArc<T>, AllocErr>
+    {
+        let counted = Counted { count: AtomicUsize::new(1), value };
+        Ok(SlabArc { ptr: alloc_shared(cache, counted)?, cache })
+    }
+
+    fn counted(&self) -> &Counted<T> {
+        unsafe { &*self.ptr }
+    }
+}
+
+impl<T> Clone for SlabArc<T> {
+    fn clone(&self) -> SlabArc<T> {
+        self.counted().count.fetch_add(1, Ordering::Relaxed);
+        SlabArc { ptr: self.ptr, cache: self.cache }
+    }
+}
+
+impl<T> Deref for SlabArc<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.counted().value
+    }
+}
+
+impl<T> Drop for SlabArc<T> {
+    fn drop(&mut self) {
+        if self.counted().count.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+
+        // Every other reference's uses happen before the value is dropped,
+        // once the cache is unlocked.
+        atomic::fence(Ordering::Acquire);
+        let counted = unsafe { self.cache.lock().take(self.ptr) };
+        drop(counted);
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for SlabArc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        (**self).fmt(f)
+    }
+}
+
+impl<T> fmt::Debug for SlabCache<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SlabCache")
+            .field("name", &self.name)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
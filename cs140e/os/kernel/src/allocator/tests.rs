@@ -59,15 +59,15 @@ mod allocator {
     #[allow(dead_code)] mod bump;
     #[allow(dead_code)] mod bin;
 
-    use alloc::allocator::{AllocErr, Layout};
-    use alloc::raw_vec::RawVec;
+    use core::alloc::Layout;
+    use allocator::AllocErr;
 
     macro test_allocators {
         (@$kind:ident, $name:ident, $mem:expr, |$info:pat| $block:expr) => {
             #[test]
             fn $name() {
-                let mem: RawVec<u8> = RawVec::with_capacity($mem);
-                let start = mem.ptr() as usize;
+                let mut mem: Vec<u8> = Vec::with_capacity($mem);
+                let start = mem.as_mut_ptr() as usize;
                 let end = start + $mem;
 
                 let allocator = $kind::Allocator::new(start, end);
@@ -183,42 +183,32 @@ mod allocator {
         }
     });
 
-    test_allocators!(@bin, bin_dealloc_1, 65536, |(_, _, mut a)| {
-        let layouts = [
-            layout!(16, 16),
-            layout!(16, 256),
-            layout!(32, 4),
-            layout!(32, 1024),
-            layout!(4, 1024),
-            layout!(4, 32),
-        ];
+    test_allocators!(@bump, bump_reset_to_checkpoint, 4096, |(start, _, mut a)| {
+        a.alloc(layout!(100, 8)).unwrap();
+        let checkpoint = a.checkpoint();
+        let used = a.used();
 
-        // tests for resonable internal fragmentation, reuse of aligned blocks,
-        // and proper alignment after binning
-        for (i, layout) in layouts.iter().enumerate() {
-            let mut ptrs = vec![];
-            for _ in 0..(25 + i * 2) {
-                let ptr = a.alloc(layout.clone()).expect("allocation");
-                assert!(ptr as usize % layout.align() == 0,
-                    "{:x} is not aligned to {}", ptr as usize, layout.align());
-                scribble(ptr, layout.size());
-                ptrs.push((ptr, layout.clone()));
-            }
+        let first = a.alloc(layout!(512, 64)).unwrap();
+        a.alloc(layout!(1024, 16)).unwrap();
+        assert!(a.used() > used);
 
-            for (ptr, layout) in ptrs {
-                a.dealloc(ptr, layout);
-            }
-        }
+        // everything after the checkpoint is released and handed out again
+        a.reset_to(checkpoint);
+        assert_eq!(a.used(), used);
+        assert_eq!(a.alloc(layout!(512, 64)).unwrap(), first);
+        assert!(first as usize >= start + 100);
+    });
 
-        for _ in 0..500 {
-            for layout in &layouts {
-                let ptr = a.alloc(layout.clone()).expect("allocation");
-                scribble(ptr, layout.size());
-                assert!(ptr as usize % layout.align() == 0,
-                    "{:x} is not aligned to {}", ptr as usize, layout.align());
-                a.dealloc(ptr, layout.clone());
-            }
-        }
+    test_allocators!(@bump, bump_stale_checkpoint, 4096, |(_, _, mut a)| {
+        let outer = a.checkpoint();
+        a.alloc(layout!(64, 8)).unwrap();
+        let inner = a.checkpoint();
+        a.reset_to(outer);
+
+        let result = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| {
+            a.reset_to(inner);
+        }));
+        assert!(result.is_err(), "stale checkpoint was accepted");
     });
 
     test_allocators!(@bin, bin_dealloc_2, 8192, |(_, _, mut a)| {
@@ -243,6 +233,215 @@ mod allocator {
             }
         }
     });
+
+    test_allocators!(@bin, bin_large_exact_pages, 1 << 17, |(_, _, mut a)| {
+        // a 5-page request takes 5 pages, not 8: the rest of the block is
+        // returned to the page allocator
+        let layout = layout!(5 * 4096 - 100, 8);
+        let free = a.stats().free_pages;
+
+        let mut ptrs = vec![];
+        for i in 0..3 {
+            let ptr = a.alloc(layout.clone()).expect("allocation");
+            assert_eq!(a.stats().free_pages, free - 5 * (i + 1));
+            scribble(ptr, layout.size());
+            ptrs.push(ptr);
+        }
+
+        for ptr in ptrs {
+            a.dealloc(ptr, layout.clone());
+        }
+        assert_eq!(a.stats().free_pages, free);
+    });
+
+    /// Tests whose heaps are sized for their blocks alone. Red zones more
+    /// than double these blocks, so under heap-debug the heaps run out and
+    /// the tests are left out.
+    macro sized_without_red_zones($($item:item)*) {
+        $(#[cfg(not(feature = "heap-debug"))] $item)*
+    }
+
+    sized_without_red_zones! {
+        test_allocators!(@bin, bin_dealloc_1, 65536, |(_, _, mut a)| {
+            let layouts = [
+                layout!(16, 16),
+                layout!(16, 256),
+                layout!(32, 4),
+                layout!(32, 1024),
+                layout!(4, 1024),
+                layout!(4, 32),
+            ];
+
+            // tests for resonable internal fragmentation, reuse of aligned blocks,
+            // and proper alignment after binning
+            for (i, layout) in layouts.iter().enumerate() {
+                let mut ptrs = vec![];
+                for _ in 0..(25 + i * 2) {
+                    let ptr = a.alloc(layout.clone()).expect("allocation");
+                    assert!(ptr as usize % layout.align() == 0,
+                        "{:x} is not aligned to {}", ptr as usize, layout.align());
+                    scribble(ptr, layout.size());
+                    ptrs.push((ptr, layout.clone()));
+                }
+
+                for (ptr, layout) in ptrs {
+                    a.dealloc(ptr, layout);
+                }
+            }
+
+            for _ in 0..500 {
+                for layout in &layouts {
+                    let ptr = a.alloc(layout.clone()).expect("allocation");
+                    scribble(ptr, layout.size());
+                    assert!(ptr as usize % layout.align() == 0,
+                        "{:x} is not aligned to {}", ptr as usize, layout.align());
+                    a.dealloc(ptr, layout.clone());
+                }
+            }
+        });
+
+        test_allocators!(@bin, bin_align_exceeds_size, 1 << 20, |(_, _, mut a)| {
+            // small, page-aligned allocations, as needed for page tables
+            let layouts = [
+                layout!(8, 4096),
+                layout!(64, 4096),
+                layout!(4096, 4096),
+                layout!(16, 1 << 16),
+            ];
+
+            for _ in 0..10 {
+                let mut ptrs = vec![];
+                for layout in layouts.iter().cycle().take(40) {
+                    let ptr = a.alloc(layout.clone()).expect("allocation");
+                    assert!(ptr as usize % layout.align() == 0,
+                        "{:x} is not aligned to {}", ptr as usize, layout.align());
+                    scribble(ptr, layout.size());
+                    ptrs.push((ptr, layout.clone()));
+                }
+
+                for (ptr, layout) in ptrs {
+                    a.dealloc(ptr, layout);
+                }
+            }
+        });
+
+        test_allocators!(@bin, bin_coalesce_after_aligned, 1 << 16, |(start, end, mut a)| {
+            // freeing everything must coalesce back into blocks that can satisfy
+            // an allocation of the largest power of two within the region
+            let largest = 1 << (63 - (end - start).leading_zeros());
+            let big = layout!(largest / 2, largest / 2);
+
+            let mut ptrs = vec![];
+            for _ in 0..8 {
+                ptrs.push(a.alloc(layout!(8, 4096)).expect("allocation"));
+            }
+
+            for ptr in ptrs {
+                a.dealloc(ptr, layout!(8, 4096));
+            }
+
+            let ptr = a.alloc(big.clone()).expect("coalesced allocation");
+            assert!(ptr as usize % big.align() == 0);
+        });
+
+        #[test]
+        fn bin_multiple_regions() {
+            let mut mem: Vec<u8> = Vec::with_capacity(3 * 4096);
+            let base = mem.as_mut_ptr() as usize;
+            let low = (base, base + 4096);
+            let high = (base + 2 * 4096, base + 3 * 4096);
+
+            // leave a hole between the two regions that must never be handed out
+            let mut a = bin::Allocator::with_regions(&[low, high]);
+            let mut pointers: Vec<usize> = vec![];
+            while let Ok(ptr) = a.alloc(layout!(64, 8)) {
+                scribble(ptr, 64);
+                pointers.push(ptr as usize);
+            }
+
+            assert!(pointers.len() > 64, "only {} allocations", pointers.len());
+            for &ptr in &pointers {
+                let in_low = ptr >= low.0 && ptr + 64 <= low.1;
+                let in_high = ptr >= high.0 && ptr + 64 <= high.1;
+                assert!(in_low || in_high, "{:x} is outside both regions", ptr);
+            }
+        }
+    }
+
+    #[bench]
+    fn bin_churn(b: &mut ::test::Bencher) {
+        // many small blocks live at once, freed in an interleaved order: every
+        // free has to find its buddy among thousands of free blocks
+        let mut mem: Vec<u8> = Vec::with_capacity(1 << 22);
+        let start = mem.as_mut_ptr() as usize;
+        let mut a = bin::Allocator::new(start, start + (1 << 22));
+
+        let mut ptrs = Vec::with_capacity(16384);
+        b.iter(|| {
+            for _ in 0..16384 {
+                ptrs.push(a.alloc(layout!(16, 16)).expect("allocation"));
+            }
+
+            for i in (0..ptrs.len()).step_by(2).chain((1..ptrs.len()).step_by(2)) {
+                a.dealloc(ptrs[i], layout!(16, 16));
+            }
+            ptrs.clear();
+        });
+    }
+
+    #[cfg(feature = "heap-debug")]
+    test_allocators!(@bin, bin_debug_double_free, 4096, |(_, _, mut a)| {
+        let result = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| {
+            let ptr = a.alloc(layout!(64, 8)).unwrap();
+            a.dealloc(ptr, layout!(64, 8));
+            a.dealloc(ptr, layout!(64, 8));
+        }));
+        assert!(result.is_err(), "double free was not detected");
+    });
+
+    #[cfg(feature = "heap-debug")]
+    test_allocators!(@bin, bin_debug_use_after_free, 4096, |(_, _, mut a)| {
+        let result = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| {
+            let ptr = a.alloc(layout!(64, 8)).unwrap();
+            a.dealloc(ptr, layout!(64, 8));
+            scribble(ptr, 64);
+            a.alloc(layout!(64, 8)).unwrap();
+        }));
+        assert!(result.is_err(), "use after free was not detected");
+    });
+
+    #[cfg(feature = "heap-debug")]
+    test_allocators!(@bin, bin_debug_overrun, 4096, |(_, _, mut a)| {
+        let result = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| {
+            let ptr = a.alloc(layout!(24, 8)).unwrap();
+            scribble(ptr, 25);
+            a.dealloc(ptr, layout!(24, 8));
+        }));
+        assert!(result.is_err(), "overrun was not detected");
+    });
+
+    #[cfg(feature = "heap-debug")]
+    test_allocators!(@bin, bin_debug_check_heap, 1 << 16, |(_, _, mut a)| {
+        let layouts = [layout!(24, 8), layout!(100, 64), layout!(3000, 4096)];
+        let mut ptrs = vec![];
+        for layout in layouts.iter().cycle().take(12) {
+            let ptr = a.alloc(layout.clone()).unwrap();
+            scribble(ptr, layout.size());
+            ptrs.push(ptr);
+        }
+
+        // every allocation, plus the allocator's own two bitmaps
+        assert_eq!(a.check_heap(), Ok(14));
+
+        let (ptr, size) = (ptrs[4], layouts[1].size());
+        unsafe { *ptr.offset(-1) = 0; }
+        let corruption = a.check_heap().unwrap_err();
+        assert_eq!(corruption.addr, ptr as usize - 1);
+
+        unsafe { *ptr.offset(-1) = 0xCB; }
+        unsafe { *ptr.add(size) = 0; }
+        assert_eq!(a.check_heap().unwrap_err().addr, ptr as usize + size);
+    });
 }
 
 mod linked_list {
@@ -337,3 +536,102 @@ mod linked_list {
         assert_eq!(iter.next(), None);
     }
 }
+
+mod slab {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use allocator::slab::{Counted, SlabArc, SlabBox, SlabCache};
+    use mutex::IrqMutex;
+
+    #[derive(Debug, PartialEq)]
+    struct Entry {
+        id: u64,
+        data: [u8; 40],
+    }
+
+    #[test]
+    fn reuses_freed_slots() {
+        let mut cache = SlabCache::new("entry");
+        let first = cache.alloc(Entry { id: 1, data: [1; 40] }).unwrap();
+        unsafe { cache.free(first); }
+
+        let second = cache.alloc(Entry { id: 2, data: [2; 40] }).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(unsafe { &*second }.id, 2);
+        assert_eq!(cache.stats().slabs, 1);
+    }
+
+    #[test]
+    fn grows_and_tracks_stats() {
+        let mut cache = SlabCache::new("entry");
+        let mut ptrs = vec![];
+        for i in 0..500 {
+            let ptr = cache.alloc(Entry { id: i, data: [i as u8; 40] }).unwrap();
+            assert!(ptr as usize % ::std::mem::align_of::<Entry>() == 0);
+            ptrs.push(ptr);
+        }
+
+        for (i, &ptr) in ptrs.iter().enumerate() {
+            assert_eq!(unsafe { &*ptr }.id, i as u64);
+        }
+
+        let stats = cache.stats();
+        assert!(stats.slabs > 1);
+        assert_eq!(stats.objects_in_use, 500);
+        assert_eq!(stats.peak_objects, 500);
+
+        for ptr in ptrs {
+            unsafe { cache.free(ptr); }
+        }
+
+        let stats = cache.stats();
+        assert_eq!(stats.objects_in_use, 0);
+        assert_eq!(stats.free_calls, 500);
+        assert_eq!(stats.free_objects, stats.slabs * (4096 / stats.object_size));
+    }
+
+    #[test]
+    fn boxes_return_their_slots() {
+        static BOXES: IrqMutex<SlabCache<Entry>> = IrqMutex::new(SlabCache::new("box"));
+
+        let first = SlabBox::new(&BOXES, Entry { id: 1, data: [1; 40] }).unwrap();
+        let addr = &*first as *const Entry;
+        assert_eq!(BOXES.lock().stats().objects_in_use, 1);
+        drop(first);
+        assert_eq!(BOXES.lock().stats().objects_in_use, 0);
+
+        let mut second = SlabBox::new(&BOXES, Entry { id: 2, data: [2; 40] }).unwrap();
+        second.id = 3;
+        assert_eq!(&*second as *const Entry, addr);
+        assert_eq!(*second, Entry { id: 3, data: [2; 40] });
+        assert_eq!(BOXES.lock().stats().slabs, 1);
+    }
+
+    /// Counts its drops.
+    struct Dropped(&'static AtomicUsize);
+
+    impl Drop for Dropped {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn shared_values_drop_with_the_last_reference() {
+        static SHARED: IrqMutex<SlabCache<Counted<Dropped>>> =
+            IrqMutex::new(SlabCache::new("shared"));
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+        let first = SlabArc::new(&SHARED, Dropped(&DROPS)).unwrap();
+        let second = first.clone();
+        assert_eq!(SHARED.lock().stats().objects_in_use, 1);
+
+        drop(first);
+        assert_eq!(DROPS.load(Ordering::SeqCst), 0);
+        assert_eq!(SHARED.lock().stats().objects_in_use, 1);
+
+        drop(second);
+        assert_eq!(DROPS.load(Ordering::SeqCst), 1);
+        assert_eq!(SHARED.lock().stats().objects_in_use, 0);
+    }
+}
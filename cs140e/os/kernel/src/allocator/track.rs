@@ -0,0 +1,121 @@
+use core::alloc::Layout;
+
+use console::kprintln;
+use allocator::imp;
+
+/// The maximum number of live allocations that can be tracked at once.
+pub const MAX_TRACKED: usize = 1024;
+
+/// A single live allocation recorded by the `Tracker`.
+#[derive(Debug, Copy, Clone)]
+pub struct LiveAlloc {
+    /// The address returned to the caller.
+    pub addr: usize,
+    /// The requested size, in bytes.
+    pub size: usize,
+    /// The size of the bin the allocation was served from, in bytes.
+    pub bin: usize,
+    /// The tag in effect when the allocation was made, if any.
+    pub tag: Option<&'static str>,
+    /// The return address of the allocating function, if it could be found.
+    pub caller: usize,
+}
+
+/// A fixed-size side table of live allocations.
+///
+/// The table never allocates, so it can be updated from within the allocator.
+/// Allocations made while the table is full are counted in `dropped` and are
+/// not reported by `dump()`.
+pub struct Tracker {
+    enabled: bool,
+    tag: Option<&'static str>,
+    entries: [Option<LiveAlloc>; MAX_TRACKED],
+    dropped: usize,
+}
+
+impl Tracker {
+    /// Returns a new, disabled tracker.
+    pub const fn new() -> Tracker {
+        Tracker { enabled: false, tag: None, entries: [None; MAX_TRACKED], dropped: 0 }
+    }
+
+    /// Returns `true` if allocations are currently being recorded.
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Enables or disables tracking. Disabling clears the table.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if !enabled {
+            self.entries = [None; MAX_TRACKED];
+            self.dropped = 0;
+        }
+        self.enabled = enabled;
+    }
+
+    /// Sets the tag attached to subsequent allocations and returns the
+    /// previous one.
+    pub fn set_tag(&mut self, tag: Option<&'static str>) -> Option<&'static str> {
+        ::std::mem::replace(&mut self.tag, tag)
+    }
+
+    /// Records a new live allocation at `addr` for `layout`.
+    pub fn record(&mut self, addr: usize, layout: &Layout, caller: usize) {
+        if !self.enabled {
+            return;
+        }
+
+        let bin = imp::Allocator::block_size_for(layout).unwrap_or(0);
+        let entry = LiveAlloc { addr, size: layout.size(), bin, tag: self.tag, caller };
+        match self.entries.iter_mut().find(|e| e.is_none()) {
+            Some(slot) => *slot = Some(entry),
+            None => self.dropped += 1,
+        }
+    }
+
+    /// Forgets the live allocation at `addr`, if it was recorded.
+    pub fn forget(&mut self, addr: usize) {
+        if !self.enabled {
+            return;
+        }
+
+        for slot in self.entries.iter_mut() {
+            if slot.map(|e| e.addr) == Some(addr) {
+                *slot = None;
+                return;
+            }
+        }
+    }
+
+    /// Prints every recorded live allocation followed by per-tag totals.
+    pub fn dump(&self) {
+        if !self.enabled {
+            kprintln!("allocation tracking is disabled");
+            return;
+        }
+
+        let live = || self.entries.iter().filter_map(|e| *e);
+        for e in live() {
+            kprintln!("{:#012x} {:>8} B (bin {:>8}) {:<12} caller {:#x}",
+                      e.addr, e.size, e.bin, e.tag.unwrap_or("-"), e.caller);
+        }
+
+        // Summarize by tag. Tags are few, so a quadratic pass is fine and
+        // avoids allocating from inside the allocator's diagnostics.
+        kprintln!("--");
+        for (i, e) in live().enumerate() {
+            if live().take(i).any(|prev| prev.tag == e.tag) {
+                continue;
+            }
+
+            let (count, bytes) = live().filter(|o| o.tag == e.tag)
+                .fold((0, 0), |(c, b), o| (c + 1, b + o.size));
+            kprintln!("{:<12} {:>6} allocations, {:>10} bytes",
+                      e.tag.unwrap_or("(untagged)"), count, bytes);
+        }
+
+        if self.dropped > 0 {
+            kprintln!("{} allocations not tracked: table full", self.dropped);
+        }
+    }
+}
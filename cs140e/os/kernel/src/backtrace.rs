@@ -0,0 +1,99 @@
+//! Stack backtraces, by walking the chain of frame records.
+//!
+//! The kernel is built with frame pointers: every function's prologue pushes
+//! a frame record, the caller's frame pointer and the return address, and
+//! points `x29` at it. Following the chain from a frame pointer yields the
+//! return address into each caller in turn. Frame pointers are checked
+//! before being followed, so a corrupt chain ends the walk instead of
+//! faulting. `print()` names the function each address is in from the
+//! kernel's symbol table, `ksyms`.
+
+use std::fmt;
+
+use aarch64;
+use console::ekprintln;
+use ksyms;
+use vm::USER_BASE;
+
+/// The most return addresses a backtrace yields.
+pub const MAX_FRAMES: usize = 32;
+
+/// An iterator over the return addresses in a chain of frame records,
+/// innermost first.
+#[derive(Debug, Copy, Clone)]
+pub struct Backtrace {
+    fp: usize,
+    depth: usize,
+}
+
+/// Returns a backtrace of the caller.
+#[inline(always)]
+pub fn here() -> Backtrace {
+    from_fp(aarch64::fp())
+}
+
+/// Returns a backtrace starting at the frame record `fp` points to.
+pub fn from_fp(fp: usize) -> Backtrace {
+    Backtrace { fp, depth: 0 }
+}
+
+/// Returns `true` if `fp` can be a frame record in the kernel: non-null,
+/// aligned, below the user half, and mapped.
+fn valid(fp: usize) -> bool {
+    fp != 0 && fp % 16 == 0 && fp < USER_BASE
+        && aarch64::kernel_can_read(fp) && aarch64::kernel_can_read(fp + 8)
+}
+
+impl Iterator for Backtrace {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.depth >= MAX_FRAMES || !valid(self.fp) {
+            return None;
+        }
+
+        let (next, ret) = unsafe {
+            let record = self.fp as *const usize;
+            (*record, *record.add(1))
+        };
+
+        // Stacks grow down, so callers' records are at higher addresses. A
+        // chain that doesn't climb is corrupt or loops.
+        self.fp = if next > self.fp { next } else { 0 };
+        self.depth += 1;
+        match ret {
+            0 => None,
+            ret => Some(ret),
+        }
+    }
+}
+
+/// Prints the return addresses `trace` yields, one to a line, with the
+/// function each is in when the symbol table has it.
+pub fn print<I: Iterator<Item = usize>>(trace: I) {
+    for (i, addr) in trace.enumerate() {
+        ekprintln!("{}", Frame(i, addr));
+    }
+}
+
+/// Writes the return addresses `trace` yields to `w` as `print()` prints
+/// them.
+pub fn write<W: fmt::Write, I: Iterator<Item = usize>>(w: &mut W, trace: I) -> fmt::Result {
+    for (i, addr) in trace.enumerate() {
+        writeln!(w, "{}", Frame(i, addr))?;
+    }
+    Ok(())
+}
+
+/// The `i`th return address of a backtrace, shown with its function.
+struct Frame(usize, usize);
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Frame(i, addr) = *self;
+        match ksyms::lookup(addr) {
+            Some(symbol) => write!(f, "  #{:<2} {:#018x} {}", i, addr, symbol),
+            None => write!(f, "  #{:<2} {:#018x}", i, addr),
+        }
+    }
+}
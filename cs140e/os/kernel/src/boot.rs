@@ -0,0 +1,227 @@
+use std::cmp::max;
+
+use pi;
+use pi::atags::Atags;
+use pi::fdt::Fdt;
+use pi::common::IO_BASE;
+use pi::interrupt::INT_BASE;
+use pi::uart::MU_REG_BASE;
+use console::log_warn;
+use allocator::util::align_up;
+
+/// The size of a page, used to align the start of the heap.
+const PAGE_SIZE: usize = 4096;
+
+/// The number of bytes reserved for the boot stack, which grows down from
+/// `_start`.
+const BOOT_STACK_SIZE: usize = 128 * 1024;
+
+/// The first address that may be used for the heap below the kernel image.
+/// Everything below it holds the ATAGS and firmware spin tables.
+const LOW_MEMORY_START: usize = PAGE_SIZE;
+
+/// The amount of memory assumed if neither the ATAGS nor the firmware report
+/// a memory size: the smallest Pi 3 configuration's ARM split.
+const DEFAULT_MEM_SIZE: usize = 256 * 1024 * 1024;
+
+extern "C" {
+    static _start: u8;
+    static _end: u8;
+}
+
+/// Where the memory size in `BootInfo` was obtained from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MemSource {
+    /// The `MEM` ATAG passed by the firmware.
+    Atags,
+    /// The device tree's `/memory` node.
+    DeviceTree,
+    /// The firmware's "get ARM memory" mailbox property.
+    Mailbox,
+    /// Nothing reported a size; `DEFAULT_MEM_SIZE` was assumed.
+    Default,
+}
+
+/// Information about the machine gathered once, early in boot.
+#[derive(Debug, Copy, Clone)]
+pub struct BootInfo {
+    /// The physical address of the first byte of ARM memory.
+    pub mem_start: usize,
+    /// The number of bytes of ARM memory.
+    pub mem_size: usize,
+    /// Where `mem_start` and `mem_size` came from.
+    pub mem_source: MemSource,
+    /// The kernel command line, if the firmware passed one.
+    pub cmdline: Option<&'static str>,
+    /// The `(start, end)` of the device tree, if the firmware passed one.
+    pub dtb: Option<(usize, usize)>,
+    /// The physical addresses of the peripherals, the mini UART's registers,
+    /// and the interrupt controller's registers, if the device tree gives
+    /// them.
+    pub io_base: Option<usize>,
+    pub uart_base: Option<usize>,
+    pub intc_base: Option<usize>,
+}
+
+impl BootInfo {
+    /// Gathers boot information from the device tree at `dtb`, which the
+    /// firmware passes in `x0`, or from the ATAGS if there is none. Falls
+    /// back to the firmware mailbox for the memory size if neither gives it.
+    pub fn detect(dtb: usize) -> BootInfo {
+        let mut info = BootInfo {
+            mem_start: 0,
+            mem_size: DEFAULT_MEM_SIZE,
+            mem_source: MemSource::Default,
+            cmdline: None,
+            dtb: None,
+            io_base: None,
+            uart_base: None,
+            intc_base: None,
+        };
+
+        // The firmware loads the device tree where the ATAGS would be, so
+        // only look for ATAGS without one.
+        match unsafe { Fdt::from_addr(dtb) } {
+            Some(fdt) => info.read_device_tree(&fdt, dtb),
+            None => {
+                for tag in Atags::get() {
+                    if let Some(mem) = tag.mem() {
+                        info.mem_start = mem.start as usize;
+                        info.mem_size = mem.size as usize;
+                        info.mem_source = MemSource::Atags;
+                    } else if let Some(cmd) = tag.cmd() {
+                        info.cmdline = Some(cmd);
+                    }
+                }
+            }
+        }
+
+        if info.mem_source == MemSource::Default {
+            if let Some((base, size)) = pi::mailbox::arm_memory() {
+                info.mem_start = base as usize;
+                info.mem_size = size as usize;
+                info.mem_source = MemSource::Mailbox;
+            }
+        }
+
+        info
+    }
+
+    /// Fills in what the device tree `fdt`, at `addr`, describes: memory,
+    /// the command line in `/chosen`, and where the peripherals are.
+    fn read_device_tree(&mut self, fdt: &Fdt<'static>, addr: usize) {
+        self.dtb = Some((addr, addr + fdt.size()));
+
+        let root_cells = match fdt.root() {
+            Some(root) => root.cells(),
+            None => return,
+        };
+        if let Some((start, size)) = fdt.find("/memory").and_then(|mem| mem.reg(root_cells).next()) {
+            self.mem_start = start as usize;
+            self.mem_size = size as usize;
+            self.mem_source = MemSource::DeviceTree;
+        }
+
+        self.cmdline = fdt.find("/chosen").and_then(|chosen| chosen.property_str("bootargs"));
+
+        if let Some(soc) = fdt.find("/soc") {
+            let ranges = || soc.ranges(soc.cells(), root_cells.0);
+            self.io_base = ranges().next().map(|(_, parent, _)| parent as usize);
+
+            // Devices under `/soc` give VideoCore bus addresses, which its
+            // `ranges` map to physical ones.
+            let device = |compatible: &str| {
+                let (bus, _) = fdt.find_compatible(compatible)?.reg(soc.cells()).next()?;
+                ranges().find(|&(child, _, size)| child <= bus && bus - child < size)
+                    .map(|(child, parent, _)| (parent + (bus - child)) as usize)
+            };
+            self.uart_base = device("brcm,bcm2835-aux-uart");
+            self.intc_base = device("brcm,bcm2836-armctrl-ic");
+        }
+    }
+
+    /// Warns about each peripheral the device tree places somewhere other
+    /// than where its driver expects it.
+    pub fn check_peripherals(&self) {
+        let peripherals = [
+            ("peripherals", self.io_base, IO_BASE),
+            ("mini UART", self.uart_base, MU_REG_BASE),
+            ("interrupt controller", self.intc_base, INT_BASE),
+        ];
+
+        for &(name, found, expected) in peripherals.iter() {
+            match found {
+                Some(found) if found != expected => {
+                    log_warn!("device tree puts the {} at {:#x}, not {:#x}", name, found, expected)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Returns the value of the `name=value` parameter on the kernel command
+    /// line, if it is there.
+    pub fn param(&self, name: &str) -> Option<&'static str> {
+        self.cmdline?.split_whitespace().filter_map(|param| {
+            let mut parts = param.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) if key == name => Some(value),
+                _ => None,
+            }
+        }).next()
+    }
+
+    /// Returns `true` if `name` is on the kernel command line on its own, as
+    /// a flag.
+    pub fn has_flag(&self, name: &str) -> bool {
+        self.cmdline.map_or(false, |cmdline| cmdline.split_whitespace().any(|p| p == name))
+    }
+
+    /// Returns the address one past the last byte of ARM memory.
+    pub fn mem_end(&self) -> usize {
+        self.mem_start + self.mem_size
+    }
+
+    /// Returns the `(start, end)` of the kernel image, as laid out by the
+    /// linker script.
+    pub fn kernel_image() -> (usize, usize) {
+        unsafe { (&_start as *const u8 as usize, &_end as *const u8 as usize) }
+    }
+
+    /// Returns the `(start, end)` ranges of memory available for the heap.
+    /// Either range may be empty.
+    ///
+    /// The first range lies above the kernel image: it begins at the first
+    /// page boundary after the image and extends to the end of ARM memory.
+    /// The second lies below the image, between the ATAGS and the bottom of
+    /// the `BOOT_STACK_SIZE` bytes reserved for the boot stack. Neither
+    /// includes the device tree.
+    pub fn heap_regions(&self) -> [(usize, usize); 2] {
+        let (image_start, image_end) = Self::kernel_image();
+
+        let high_start = align_up(max(self.mem_start, image_end), PAGE_SIZE);
+        let high = (high_start, max(high_start, self.mem_end()));
+
+        let low_start = max(self.mem_start, LOW_MEMORY_START);
+        let low_end = image_start.saturating_sub(BOOT_STACK_SIZE);
+        let low = (low_start, max(low_start, low_end));
+
+        [self.exclude_dtb(high), self.exclude_dtb(low)]
+    }
+
+    /// Returns the part of `range` on one side of the device tree: above it
+    /// if it starts in the range's first page, below it otherwise.
+    fn exclude_dtb(&self, (start, end): (usize, usize)) -> (usize, usize) {
+        match self.dtb {
+            Some((dtb_start, dtb_end)) if dtb_start < end && start < dtb_end => {
+                if dtb_start < start + PAGE_SIZE {
+                    let above = align_up(dtb_end, PAGE_SIZE);
+                    (above, max(above, end))
+                } else {
+                    (start, dtb_start)
+                }
+            }
+            _ => (start, end),
+        }
+    }
+}
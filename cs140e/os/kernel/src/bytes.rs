@@ -0,0 +1,72 @@
+//! Reading and writing integers in byte buffers, for on-disk and on-wire
+//! formats: `be` for big-endian ones, such as network headers, and `le` for
+//! little-endian ones, such as file systems.
+//!
+//! Every function takes the offset of the integer in the buffer, and panics
+//! if it doesn't fit there, as indexing would.
+
+/// Big-endian (network byte order) integers.
+pub mod be {
+    pub fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+        (bytes[offset] as u16) << 8 | bytes[offset + 1] as u16
+    }
+
+    pub fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        (u16_at(bytes, offset) as u32) << 16 | u16_at(bytes, offset + 2) as u32
+    }
+
+    pub fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+        (u32_at(bytes, offset) as u64) << 32 | u32_at(bytes, offset + 4) as u64
+    }
+
+    pub fn put_u16(buf: &mut [u8], offset: usize, value: u16) {
+        buf[offset] = (value >> 8) as u8;
+        buf[offset + 1] = value as u8;
+    }
+
+    pub fn put_u32(buf: &mut [u8], offset: usize, value: u32) {
+        put_u16(buf, offset, (value >> 16) as u16);
+        put_u16(buf, offset + 2, value as u16);
+    }
+
+    pub fn put_u64(buf: &mut [u8], offset: usize, value: u64) {
+        put_u32(buf, offset, (value >> 32) as u32);
+        put_u32(buf, offset + 4, value as u32);
+    }
+
+    /// Appends `value` to `buf`.
+    pub fn push_u16(buf: &mut Vec<u8>, value: u16) {
+        buf.push((value >> 8) as u8);
+        buf.push(value as u8);
+    }
+}
+
+/// Little-endian integers.
+pub mod le {
+    pub fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+        bytes[offset] as u16 | (bytes[offset + 1] as u16) << 8
+    }
+
+    pub fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u16_at(bytes, offset) as u32 | (u16_at(bytes, offset + 2) as u32) << 16
+    }
+
+    pub fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+        u32_at(bytes, offset) as u64 | (u32_at(bytes, offset + 4) as u64) << 32
+    }
+
+    pub fn put_u16(buf: &mut [u8], offset: usize, value: u16) {
+        buf[offset] = value as u8;
+        buf[offset + 1] = (value >> 8) as u8;
+    }
+
+    pub fn put_u32(buf: &mut [u8], offset: usize, value: u32) {
+        put_u16(buf, offset, value as u16);
+        put_u16(buf, offset + 2, (value >> 16) as u16);
+    }
+
+    pub fn put_u64(buf: &mut [u8], offset: usize, value: u64) {
+        put_u32(buf, offset, value as u32);
+        put_u32(buf, offset + 4, (value >> 32) as u32);
+    }
+}
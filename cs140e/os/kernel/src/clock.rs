@@ -0,0 +1,184 @@
+//! Wall-clock time.
+//!
+//! The Pi has no battery-backed real-time clock, so the kernel only knows the
+//! date once something tells it: the shell's `date -s`, or the SNTP client
+//! in `net::sntp`. Until then wall-clock time counts from the UNIX epoch at
+//! boot, as if the kernel had booted at midnight on January 1, 1970.
+//!
+//! The clock is kept as the wall-clock time at which the system timer read
+//! zero, so converting a monotonic timestamp, such as one taken for a log
+//! line, is a single addition, and setting the clock never makes monotonic
+//! time jump.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use pi::timer;
+
+/// The identifier of wall-clock time, for `gettime`.
+pub const CLOCK_REALTIME: u64 = 0;
+
+/// The identifier of the time since boot, for `gettime`.
+pub const CLOCK_MONOTONIC: u64 = 1;
+
+/// The wall-clock time when the system timer read zero, in microseconds
+/// since the UNIX epoch.
+static BOOT_TIME: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether the clock has been set since boot.
+static SET: AtomicBool = AtomicBool::new(false);
+
+/// Returns the microseconds since boot.
+pub fn monotonic_us() -> u64 {
+    timer::current_time()
+}
+
+/// Returns the wall-clock time, in microseconds since the UNIX epoch.
+pub fn now_us() -> u64 {
+    to_wall_us(monotonic_us())
+}
+
+/// Returns the wall-clock time at `monotonic` microseconds since boot, in
+/// microseconds since the UNIX epoch.
+pub fn to_wall_us(monotonic: u64) -> u64 {
+    BOOT_TIME.load(Ordering::Relaxed) as u64 + monotonic
+}
+
+/// Sets the wall-clock time to `epoch_us` microseconds since the UNIX
+/// epoch. Returns `false`, leaving the clock as it was, if that is before
+/// boot.
+pub fn set_us(epoch_us: u64) -> bool {
+    match epoch_us.checked_sub(monotonic_us()) {
+        Some(boot) => {
+            BOOT_TIME.store(boot as usize, Ordering::Relaxed);
+            SET.store(true, Ordering::Relaxed);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Returns `true` if the clock has been set since boot, so that wall-clock
+/// time is the real date.
+pub fn is_set() -> bool {
+    SET.load(Ordering::Relaxed)
+}
+
+/// A UTC calendar date and time, to the second.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u32,
+    /// 1 through 12.
+    pub month: u32,
+    /// 1 through 31.
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+/// The days from the UNIX epoch to March 1 of year 0 of the proleptic
+/// Gregorian calendar, negated. Years are counted from March so that the
+/// leap day falls at the end.
+const EPOCH_SHIFT: i64 = 719_468;
+
+/// The days in a 400-year Gregorian cycle.
+const DAYS_PER_ERA: i64 = 146_097;
+
+impl DateTime {
+    /// Returns the date and time `secs` seconds after the UNIX epoch.
+    pub fn from_epoch(secs: u64) -> DateTime {
+        let (days, rem) = ((secs / 86_400) as i64, secs % 86_400);
+
+        let z = days + EPOCH_SHIFT;
+        let era = z / DAYS_PER_ERA;
+        let doe = z - era * DAYS_PER_ERA;
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        DateTime {
+            year: year as u32,
+            month: month as u32,
+            day: day as u32,
+            hour: (rem / 3600) as u32,
+            minute: (rem / 60 % 60) as u32,
+            second: (rem % 60) as u32,
+        }
+    }
+
+    /// Returns the seconds since the UNIX epoch, or `None` if this is
+    /// before it or isn't a valid date.
+    pub fn to_epoch(&self) -> Option<u64> {
+        if self.year < 1970 || self.month < 1 || self.month > 12 || self.day < 1
+            || self.day > days_in_month(self.year, self.month)
+            || self.hour > 23 || self.minute > 59 || self.second > 59
+        {
+            return None;
+        }
+
+        let y = self.year as i64 - if self.month <= 2 { 1 } else { 0 };
+        let m = self.month as i64;
+        let era = y / 400;
+        let yoe = y - era * 400;
+        let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + self.day as i64 - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * DAYS_PER_ERA + doe - EPOCH_SHIFT;
+
+        Some(days as u64 * 86_400
+             + self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64)
+    }
+}
+
+/// Returns the number of days in `month` of `year`.
+fn days_in_month(year: u32, month: u32) -> u32 {
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl fmt::Display for DateTime {
+    /// Formats as `YYYY-MM-DD HH:MM:SS`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+               self.year, self.month, self.day, self.hour, self.minute, self.second)
+    }
+}
+
+impl FromStr for DateTime {
+    type Err = ();
+
+    /// Parses `YYYY-MM-DD HH:MM:SS`, with a `T` or a space between the date
+    /// and the time, or `YYYY-MM-DD` alone for midnight.
+    fn from_str(s: &str) -> Result<DateTime, ()> {
+        let (date, time) = match s.find(|c: char| c == ' ' || c == 'T') {
+            Some(i) => (&s[..i], &s[i + 1..]),
+            None => (s, "00:00:00"),
+        };
+
+        let mut fields = [0u32; 6];
+        let parts = date.split('-').chain(time.split(':'));
+        let mut count = 0;
+        for (field, part) in fields.iter_mut().zip(parts) {
+            *field = part.parse().map_err(|_| ())?;
+            count += 1;
+        }
+        if count != 6 || date.split('-').count() != 3 || time.split(':').count() != 3 {
+            return Err(());
+        }
+
+        let datetime = DateTime {
+            year: fields[0], month: fields[1], day: fields[2],
+            hour: fields[3], minute: fields[4], second: fields[5],
+        };
+        datetime.to_epoch().map(|_| datetime).ok_or(())
+    }
+}
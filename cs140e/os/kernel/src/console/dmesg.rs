@@ -0,0 +1,69 @@
+/// The number of bytes of console output kept for `dmesg`.
+pub const DMESG_SIZE: usize = 16 * 1024;
+
+/// A ring buffer holding the most recent `DMESG_SIZE` bytes of console
+/// output. Once full, each write overwrites the oldest bytes.
+pub struct Ring {
+    buf: [u8; DMESG_SIZE],
+    /// The index the next byte is written to.
+    head: usize,
+    /// The number of valid bytes, at most `DMESG_SIZE`.
+    len: usize,
+}
+
+impl Ring {
+    /// Returns an empty ring.
+    pub const fn new() -> Ring {
+        Ring { buf: [0; DMESG_SIZE], head: 0, len: 0 }
+    }
+
+    /// Appends `bytes`, discarding the oldest bytes as needed.
+    pub fn write(&mut self, bytes: &[u8]) {
+        // Only the last `DMESG_SIZE` bytes can survive.
+        let skip = bytes.len().saturating_sub(DMESG_SIZE);
+        for &byte in &bytes[skip..] {
+            self.buf[self.head] = byte;
+            self.head = (self.head + 1) % DMESG_SIZE;
+        }
+
+        self.len = ::std::cmp::min(self.len + bytes.len(), DMESG_SIZE);
+    }
+
+    /// Returns the buffered bytes, oldest first, as two slices: the bytes
+    /// before the point the ring wraps and the bytes after it.
+    pub fn as_slices(&self) -> (&[u8], &[u8]) {
+        if self.len < DMESG_SIZE {
+            (&self.buf[..self.head], &[])
+        } else {
+            (&self.buf[self.head..], &self.buf[..self.head])
+        }
+    }
+
+    /// Returns a copy of the buffered bytes, oldest first.
+    pub fn to_vec(&self) -> Vec<u8> {
+        let (first, second) = self.as_slices();
+        let mut bytes = Vec::with_capacity(first.len() + second.len());
+        bytes.extend_from_slice(first);
+        bytes.extend_from_slice(second);
+        bytes
+    }
+
+    /// Copies the newest bytes, oldest first, into `out`, as many as fit.
+    /// Returns the number copied.
+    pub fn copy_tail(&self, out: &mut [u8]) -> usize {
+        let (first, second) = self.as_slices();
+        let skip = (first.len() + second.len()).saturating_sub(out.len());
+        let mut n = 0;
+        for (slot, &byte) in out.iter_mut().zip(first.iter().chain(second).skip(skip)) {
+            *slot = byte;
+            n += 1;
+        }
+        n
+    }
+
+    /// Discards every buffered byte.
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+}
@@ -0,0 +1,289 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use pi::interrupt::Interrupt;
+
+use super::CONSOLE;
+use gdbstub;
+use init::{kernel_init, Init, Stage};
+use irq;
+use kthread;
+use mutex::IrqMutex;
+use process::{Id, Signal};
+use scheduler;
+use sync::WaitQueue;
+use traps::TrapFrame;
+
+/// The number of received bytes buffered for `UartInput`.
+const RX_BUFFER_SIZE: usize = 256;
+
+/// Bytes received by the UART interrupt handler, in a ring.
+struct RxBuffer {
+    buf: [u8; RX_BUFFER_SIZE],
+    /// The index of the oldest byte.
+    head: usize,
+    len: usize,
+}
+
+impl RxBuffer {
+    /// Appends `byte`, dropping it if the buffer is full.
+    fn push(&mut self, byte: u8) {
+        if self.len < RX_BUFFER_SIZE {
+            self.buf[(self.head + self.len) % RX_BUFFER_SIZE] = byte;
+            self.len += 1;
+        }
+    }
+
+    /// Removes and returns the oldest byte.
+    fn pop(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let byte = self.buf[self.head];
+        self.head = (self.head + 1) % RX_BUFFER_SIZE;
+        self.len -= 1;
+        Some(byte)
+    }
+}
+
+static RX: IrqMutex<RxBuffer> = IrqMutex::new(RxBuffer { buf: [0; RX_BUFFER_SIZE], head: 0, len: 0 });
+
+/// Readers waiting for `RX` to be non-empty.
+static RX_READY: WaitQueue = WaitQueue::new();
+
+/// Whether `enable_rx_interrupt()` has been called.
+static RX_IRQ: AtomicBool = AtomicBool::new(false);
+
+/// The process Ctrl-C sends `SIGINT` to, or 0, the boot process, which
+/// doesn't take signals, for none.
+static FOREGROUND: AtomicUsize = AtomicUsize::new(0);
+
+/// The byte Ctrl-C sends.
+pub(super) const CTRL_C: u8 = 0x03;
+
+/// Makes `id` the console's foreground process, which Ctrl-C interrupts
+/// instead of being read as input, or clears it. Only takes effect once
+/// `enable_rx_interrupt()` has been called. If the running process has a
+/// session, sets the session's foreground process instead.
+pub fn set_foreground(id: Option<Id>) {
+    match scheduler::session() {
+        Some(session) => session.set_foreground(id),
+        None => set_uart_foreground(id),
+    }
+}
+
+/// Makes `id` the UART's foreground process, or clears it, whatever the
+/// running process's session.
+pub(super) fn set_uart_foreground(id: Option<Id>) {
+    FOREGROUND.store(id.unwrap_or(0) as usize, Ordering::Relaxed);
+}
+
+kernel_init!(RX_INIT, Stage::Drivers, "console input", Init::Plain(enable_rx_interrupt));
+
+/// Switches `UartInput` from polling the UART to sleeping until the UART's
+/// receive interrupt delivers input.
+pub fn enable_rx_interrupt() {
+    irq::register(Interrupt::Aux, handle_rx).expect("AUX IRQ already registered");
+    CONSOLE.lock().enable_rx_interrupt();
+    RX_IRQ.store(true, Ordering::Relaxed);
+}
+
+/// The AUX IRQ handler: drains the UART's receive FIFO, which deasserts the
+/// interrupt, and wakes any reader. Ctrl-C interrupts the foreground
+/// process, if there is one. While GDB is attached, the input is its: Ctrl-C
+/// stops the kernel in `tf`, and everything else is dropped.
+fn handle_rx(tf: &mut TrapFrame) {
+    let foreground = FOREGROUND.load(Ordering::Relaxed) as Id;
+    let gdb = gdbstub::is_attached();
+    let mut interrupt = false;
+    {
+        let mut console = CONSOLE.lock();
+        let mut rx = RX.lock();
+        while let Some(byte) = console.try_read_byte() {
+            if byte == CTRL_C && (gdb || foreground != 0) {
+                interrupt = true;
+            } else if !gdb {
+                rx.push(byte);
+            }
+        }
+    }
+
+    if interrupt && gdb {
+        gdbstub::interrupt(tf);
+    } else if interrupt {
+        scheduler::signal(foreground, Signal::Int);
+    }
+    RX_READY.wake_all();
+}
+
+/// A source of console input: the mini UART, a session such as a telnet
+/// connection, or in the future a USB keyboard.
+pub trait ConsoleInput: Send {
+    /// Reads a byte, blocking until one is available.
+    fn read_byte(&mut self) -> u8;
+
+    /// Returns whether the input has ended for good, as when a terminal
+    /// disconnects.
+    fn hung_up(&self) -> bool {
+        false
+    }
+}
+
+/// Console input read from the mini UART.
+pub struct UartInput;
+
+impl ConsoleInput for UartInput {
+    /// Flushes console output, then sleeps until the receive interrupt
+    /// delivers a byte. Until `enable_rx_interrupt()` is called, polls the
+    /// UART instead, yielding between polls; the console lock masks IRQs,
+    /// so waiting while holding it would stop the scheduler.
+    fn read_byte(&mut self) -> u8 {
+        CONSOLE.lock().flush();
+        if RX_IRQ.load(Ordering::Relaxed) {
+            let mut byte = None;
+            RX_READY.wait_until(|| {
+                byte = RX.lock().pop();
+                byte.is_some()
+            });
+            return byte.unwrap();
+        }
+
+        loop {
+            if let Some(byte) = CONSOLE.lock().try_read_byte() {
+                return byte;
+            }
+
+            kthread::yield_now();
+        }
+    }
+}
+
+impl UartInput {
+    /// Returns a byte of input if one has arrived, without waiting.
+    pub fn try_read_byte(&mut self) -> Option<u8> {
+        if RX_IRQ.load(Ordering::Relaxed) {
+            RX.lock().pop()
+        } else {
+            CONSOLE.lock().try_read_byte()
+        }
+    }
+}
+
+/// How a `LineDiscipline` hands input to its reader.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mode {
+    /// Bytes are passed through as they arrive, without echo.
+    Raw,
+    /// Bytes are echoed and collected into lines, with backspace editing.
+    Cooked,
+}
+
+/// Error type for `LineDiscipline::read_line` failures.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LineError {
+    /// The line was longer than the limit; the excess bytes were discarded.
+    TooLong,
+}
+
+/// A line discipline over a `ConsoleInput`, turning a stream of bytes into
+/// lines in cooked mode or passing it through untouched in raw mode.
+pub struct LineDiscipline<I: ConsoleInput> {
+    input: I,
+    mode: Mode,
+}
+
+impl<I: ConsoleInput> LineDiscipline<I> {
+    /// Returns a line discipline over `input` in cooked mode.
+    pub fn new(input: I) -> LineDiscipline<I> {
+        LineDiscipline { input, mode: Mode::Cooked }
+    }
+
+    /// Returns the current mode.
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Switches to `mode`.
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.mode = mode;
+    }
+
+    /// Returns whether the input has ended for good. See
+    /// `ConsoleInput::hung_up()`.
+    pub fn hung_up(&self) -> bool {
+        self.input.hung_up()
+    }
+
+    /// Reads a single byte, blocking until one is available. The byte is
+    /// never echoed, whatever the mode.
+    pub fn read_byte(&mut self) -> u8 {
+        self.input.read_byte()
+    }
+
+    /// Reads input into `line`, which is cleared first.
+    ///
+    /// In raw mode, this reads exactly one byte. In cooked mode, this reads
+    /// until `\r` or `\n`, echoing printable characters, handling backspace
+    /// and delete, and ringing the bell for anything else. The terminator is
+    /// echoed as a newline but not stored. Once `line` holds `max` bytes, the
+    /// rest of the line is discarded and `LineError::TooLong` is returned
+    /// when it ends. Backspace can't undo that: after a character has been
+    /// discarded, it only rings the bell.
+    pub fn read_line(&mut self, line: &mut Vec<u8>, max: usize) -> Result<(), LineError> {
+        line.clear();
+        if self.mode == Mode::Raw {
+            line.push(self.input.read_byte());
+            return Ok(());
+        }
+
+        let mut overflowed = false;
+        loop {
+            let byte = self.input.read_byte();
+            match byte {
+                b'\r' | b'\n' => {
+                    echo(b"\r\n");
+                    return if overflowed { Err(LineError::TooLong) } else { Ok(()) };
+                }
+                8 | 127 => {
+                    // Once a character has been discarded, the line is
+                    // rejected whatever is erased, so nothing is.
+                    if !overflowed && line.pop().is_some() {
+                        echo(&[8, b' ', 8]);
+                    } else {
+                        bell();
+                    }
+                }
+                32...126 if line.len() >= max => {
+                    // Swallow the rest of the line; it's rejected on enter.
+                    if !overflowed {
+                        bell();
+                    }
+                    overflowed = true;
+                }
+                32...126 => {
+                    line.push(byte);
+                    echo(&[byte]);
+                }
+                _ => bell(),
+            }
+        }
+    }
+}
+
+/// Writes `bytes` to the console, or the running process's session, as
+/// they are.
+fn echo(bytes: &[u8]) {
+    if let Some(session) = scheduler::session() {
+        return session.write_bytes(bytes);
+    }
+
+    let mut console = CONSOLE.lock();
+    for &byte in bytes {
+        console.write_byte(byte);
+    }
+}
+
+/// Rings the terminal bell.
+fn bell() {
+    echo(&[7]);
+}
@@ -0,0 +1,228 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use pi::timer;
+
+use aarch64;
+use clock::{self, DateTime};
+use console::style::Color;
+use mutex::Mutex;
+
+/// The severity of a log message. A message is printed if its level is at or
+/// below the level in effect for the module it comes from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    /// Returns the level with the numeric value `value`, if any.
+    fn from_usize(value: usize) -> Option<Level> {
+        match value {
+            1 => Some(Level::Error),
+            2 => Some(Level::Warn),
+            3 => Some(Level::Info),
+            4 => Some(Level::Debug),
+            5 => Some(Level::Trace),
+            _ => None,
+        }
+    }
+
+    /// Returns the lowercase name of this level.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+
+    /// Returns the color this level is printed in.
+    pub fn color(&self) -> Color {
+        match *self {
+            Level::Error => Color::Red,
+            Level::Warn => Color::Yellow,
+            Level::Info => Color::Green,
+            Level::Debug => Color::Cyan,
+            Level::Trace => Color::Magenta,
+        }
+    }
+}
+
+impl FromStr for Level {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Level, ()> {
+        match s {
+            "error" => Ok(Level::Error),
+            "warn" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" => Ok(Level::Debug),
+            "trace" => Ok(Level::Trace),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.name())
+    }
+}
+
+/// The level in effect for modules without an override.
+static LEVEL: AtomicUsize = AtomicUsize::new(Level::Info as usize);
+
+/// Per-module overrides of the global level, as `(module path prefix,
+/// level)` pairs. `None` until the first override is set, so logging never
+/// allocates unless overrides are in use.
+static OVERRIDES: Mutex<Option<Vec<(String, Level)>>> = Mutex::new(None);
+
+/// Whether log lines are prefixed with the microseconds since boot.
+static TIMESTAMPS: AtomicBool = AtomicBool::new(false);
+
+/// Whether log lines are prefixed with the wall-clock date and time.
+static DATES: AtomicBool = AtomicBool::new(false);
+
+/// Whether log lines are prefixed with the ID of the core that logged them.
+static CORE_IDS: AtomicBool = AtomicBool::new(false);
+
+/// Returns the global log level.
+pub fn level() -> Level {
+    Level::from_usize(LEVEL.load(Ordering::Relaxed)).unwrap_or(Level::Info)
+}
+
+/// Sets the global log level.
+pub fn set_level(level: Level) {
+    LEVEL.store(level as usize, Ordering::Relaxed);
+}
+
+/// Sets the level for every module whose path starts with `module`,
+/// overriding the global level, or removes the override if `level` is `None`.
+/// When several overrides match a module, the longest prefix wins.
+pub fn set_module_level(module: &str, level: Option<Level>) {
+    let mut overrides = OVERRIDES.lock();
+    let list = overrides.get_or_insert_with(Vec::new);
+    list.retain(|&(ref m, _)| m != module);
+    if let Some(level) = level {
+        list.push((module.to_string(), level));
+    }
+}
+
+/// Calls `f` with each per-module override.
+pub fn for_each_override<F: FnMut(&str, Level)>(mut f: F) {
+    if let Some(ref list) = *OVERRIDES.lock() {
+        for &(ref module, level) in list.iter() {
+            f(module, level);
+        }
+    }
+}
+
+/// Returns `true` if log lines are prefixed with a timestamp.
+pub fn timestamps() -> bool {
+    TIMESTAMPS.load(Ordering::Relaxed)
+}
+
+/// Enables or disables the timestamp prefix on log lines.
+pub fn set_timestamps(enabled: bool) {
+    TIMESTAMPS.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns `true` if log lines are prefixed with the date and time.
+pub fn dates() -> bool {
+    DATES.load(Ordering::Relaxed)
+}
+
+/// Enables or disables the date and time prefix on log lines.
+pub fn set_dates(enabled: bool) {
+    DATES.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns `true` if log lines are prefixed with a core ID.
+pub fn core_ids() -> bool {
+    CORE_IDS.load(Ordering::Relaxed)
+}
+
+/// Enables or disables the core ID prefix on log lines.
+pub fn set_core_ids(enabled: bool) {
+    CORE_IDS.store(enabled, Ordering::Relaxed);
+}
+
+/// The optional date, timestamp, and core ID printed at the start of a log
+/// line.
+struct Prefix;
+
+impl fmt::Display for Prefix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let us = timer::current_time();
+        if dates() {
+            let wall = clock::to_wall_us(us);
+            write!(f, "[{}] ", DateTime::from_epoch(wall / 1_000_000))?;
+        }
+
+        if timestamps() {
+            write!(f, "[{:5}.{:06}] ", us / 1_000_000, us % 1_000_000)?;
+        }
+
+        if core_ids() {
+            write!(f, "<{}> ", aarch64::affinity())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns `true` if a message at `level` from `module` should be printed.
+pub fn enabled(level: Level, module: &str) -> bool {
+    let mut limit = self::level();
+    if let Some(ref list) = *OVERRIDES.lock() {
+        let matching = list.iter()
+            .filter(|&&(ref prefix, _)| module.starts_with(prefix.as_str()))
+            .max_by_key(|&&(ref prefix, _)| prefix.len());
+        if let Some(&(_, l)) = matching {
+            limit = l;
+        }
+    }
+
+    level <= limit
+}
+
+/// Internal function called by the `log_*!` macros.
+#[doc(hidden)]
+pub fn _log(level: Level, module: &str, args: fmt::Arguments) {
+    if enabled(level, module) {
+        super::_print(format_args!("{}[{:>5}] {}: {}\n", Prefix, level.color().paint(level), module, args));
+    }
+}
+
+/// Logs a message at `Level::Error`.
+pub macro log_error($($arg:tt)*) {
+    _log(Level::Error, module_path!(), format_args!($($arg)*))
+}
+
+/// Logs a message at `Level::Warn`.
+pub macro log_warn($($arg:tt)*) {
+    _log(Level::Warn, module_path!(), format_args!($($arg)*))
+}
+
+/// Logs a message at `Level::Info`.
+pub macro log_info($($arg:tt)*) {
+    _log(Level::Info, module_path!(), format_args!($($arg)*))
+}
+
+/// Logs a message at `Level::Debug`.
+pub macro log_debug($($arg:tt)*) {
+    _log(Level::Debug, module_path!(), format_args!($($arg)*))
+}
+
+/// Logs a message at `Level::Trace`.
+pub macro log_trace($($arg:tt)*) {
+    _log(Level::Trace, module_path!(), format_args!($($arg)*))
+}
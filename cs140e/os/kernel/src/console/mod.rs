@@ -0,0 +1,367 @@
+pub mod log;
+mod sink;
+mod dmesg;
+mod input;
+mod session;
+pub mod style;
+
+use std::io;
+use std::fmt;
+
+use pi::uart::MiniUart;
+
+use gdbstub;
+use mutex::IrqMutex;
+use scheduler;
+
+pub use self::log::{Level, log_error, log_warn, log_info, log_debug, log_trace};
+pub use self::sink::{Sink, UART_SINK, MAX_SINKS};
+pub use self::dmesg::DMESG_SIZE;
+pub use self::style::{Color, cwrite, cwriteln};
+pub use self::input::{ConsoleInput, UartInput, LineDiscipline, LineError, Mode, enable_rx_interrupt, set_foreground};
+pub use self::session::{Session, SessionInput};
+
+/// The number of bytes of output buffered before it is written to the sinks.
+pub const OUTPUT_BUFFER_SIZE: usize = 256;
+
+use self::sink::Entry;
+use self::dmesg::Ring;
+
+/// A global singleton allowing read/write access to the console.
+///
+/// Input is always read from the mini UART. Output fans out to the mini UART
+/// and to every other registered `Sink`, each of which can be disabled.
+///
+/// Output is buffered and written to the sinks in bulk when a newline is
+/// written, when the buffer fills, before input is read, and on `flush()`.
+/// Everything flushed is also kept in a ring buffer for `dmesg`, whether or
+/// not any sink is enabled.
+pub struct Console {
+    inner: Option<MiniUart>,
+    uart_enabled: bool,
+    sinks: [Option<Entry>; MAX_SINKS],
+    out: [u8; OUTPUT_BUFFER_SIZE],
+    out_len: usize,
+    dmesg: Ring,
+}
+
+impl Console {
+    /// Creates a new instance of `Console`.
+    const fn new() -> Console {
+        Console {
+            inner: None,
+            uart_enabled: true,
+            sinks: [None, None, None, None, None, None, None, None],
+            out: [0; OUTPUT_BUFFER_SIZE],
+            out_len: 0,
+            dmesg: Ring::new(),
+        }
+    }
+
+    /// Initializes the console if it's not already initialized.
+    #[inline]
+    fn initialize(&mut self) {
+        self.inner = Some(MiniUart::new());
+    }
+
+    /// Returns a mutable borrow to the inner `MiniUart`, initializing it as
+    /// needed.
+    fn inner(&mut self) -> &mut MiniUart {
+        if self.inner.is_none() {
+            self.initialize();
+        }
+        self.inner.as_mut().unwrap()
+    }
+
+    /// Reads a byte from the UART device, blocking until a byte is available.
+    /// Buffered output is flushed first.
+    pub fn read_byte(&mut self) -> u8 {
+        self.flush();
+        self.inner().read_byte()
+    }
+
+    /// Reads a byte from the UART device if one is ready, without blocking.
+    pub fn try_read_byte(&mut self) -> Option<u8> {
+        if self.inner().has_byte() {
+            Some(self.inner().read_byte())
+        } else {
+            None
+        }
+    }
+
+    /// Enables the UART's receive interrupt. See `enable_rx_interrupt()`.
+    pub fn enable_rx_interrupt(&mut self) {
+        self.inner().enable_rx_interrupt();
+    }
+
+    /// Writes the byte `byte` to every enabled sink.
+    pub fn write_byte(&mut self, byte: u8) {
+        self.emit(&[byte]);
+    }
+
+    /// Registers `sink` and enables it. If the sink table is full or a sink
+    /// with the same name is already registered, `sink` is handed back.
+    pub fn add_sink(&mut self, sink: Box<Sink>) -> Result<(), Box<Sink>> {
+        let name = sink.name();
+        if name == UART_SINK || self.entry(name).is_some() {
+            return Err(sink);
+        }
+
+        match self.sinks.iter_mut().find(|e| e.is_none()) {
+            Some(slot) => {
+                *slot = Some(Entry { sink, enabled: true });
+                Ok(())
+            }
+            None => Err(sink),
+        }
+    }
+
+    /// Unregisters the sink named `name` and returns it, if it exists. The
+    /// mini UART cannot be removed, only disabled.
+    pub fn remove_sink(&mut self, name: &str) -> Option<Box<Sink>> {
+        self.sinks.iter_mut()
+            .find(|e| e.as_ref().map(|e| e.sink.name()) == Some(name))
+            .and_then(|slot| slot.take())
+            .map(|entry| entry.sink)
+    }
+
+    /// Enables or disables output to the sink named `name`. Returns `false`
+    /// if no such sink exists.
+    pub fn set_sink_enabled(&mut self, name: &str, enabled: bool) -> bool {
+        if name == UART_SINK {
+            self.uart_enabled = enabled;
+            return true;
+        }
+
+        match self.entry(name) {
+            Some(entry) => {
+                entry.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Calls `f` with the name of each sink and whether it is enabled.
+    pub fn for_each_sink<F: FnMut(&str, bool)>(&self, mut f: F) {
+        f(UART_SINK, self.uart_enabled);
+        for entry in self.sinks.iter().filter_map(|e| e.as_ref()) {
+            f(entry.sink.name(), entry.enabled);
+        }
+    }
+
+    /// Returns the registered sink named `name`, if any.
+    fn entry(&mut self, name: &str) -> Option<&mut Entry> {
+        self.sinks.iter_mut()
+            .filter_map(|e| e.as_mut())
+            .find(|e| e.sink.name() == name)
+    }
+
+    /// Appends `bytes` to the output buffer, flushing it when it fills or
+    /// once `bytes` has been buffered if it contains a newline.
+    fn emit(&mut self, bytes: &[u8]) {
+        let mut rest = bytes;
+        while !rest.is_empty() {
+            let n = ::std::cmp::min(rest.len(), OUTPUT_BUFFER_SIZE - self.out_len);
+            self.out[self.out_len..self.out_len + n].copy_from_slice(&rest[..n]);
+            self.out_len += n;
+            rest = &rest[n..];
+
+            if self.out_len == OUTPUT_BUFFER_SIZE {
+                self.flush();
+            }
+        }
+
+        if bytes.contains(&b'\n') {
+            self.flush();
+        }
+    }
+
+    /// Writes all buffered output to every enabled sink and to the `dmesg`
+    /// ring.
+    pub fn flush(&mut self) {
+        let len = ::std::mem::replace(&mut self.out_len, 0);
+        if len == 0 {
+            return;
+        }
+
+        self.dmesg.write(&self.out[..len]);
+        send(&mut self.inner, self.uart_enabled, &mut self.sinks, &self.out[..len]);
+    }
+
+    /// Writes the contents of the `dmesg` ring to every enabled sink without
+    /// adding them to the ring again.
+    pub fn replay_dmesg(&mut self) {
+        self.flush();
+        let log = self.dmesg.to_vec();
+        send(&mut self.inner, self.uart_enabled, &mut self.sinks, &log);
+    }
+
+    /// Returns the contents of the `dmesg` ring, once buffered output is in
+    /// it.
+    pub fn dmesg(&mut self) -> Vec<u8> {
+        self.flush();
+        self.dmesg.to_vec()
+    }
+
+    /// Discards the contents of the `dmesg` ring.
+    pub fn clear_dmesg(&mut self) {
+        self.dmesg.clear();
+    }
+}
+
+/// Writes `bytes` to the mini UART, if `uart_enabled`, and to every enabled
+/// sink in `sinks`. This takes the console's fields rather than the console so
+/// that `bytes` can borrow from it. While GDB is attached, the mini UART is
+/// its, and the bytes are wrapped for it to print.
+fn send(uart: &mut Option<MiniUart>, uart_enabled: bool, sinks: &mut [Option<Entry>], bytes: &[u8]) {
+    if uart_enabled {
+        let uart = uart.get_or_insert_with(MiniUart::new);
+        if gdbstub::is_attached() {
+            gdbstub::write_console(uart, bytes);
+        } else {
+            uart.write_bytes(bytes);
+        }
+    }
+
+    for entry in sinks.iter_mut().filter_map(|e| e.as_mut()) {
+        if entry.enabled {
+            entry.sink.write_bytes(bytes);
+        }
+    }
+}
+
+impl io::Read for Console {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner().read(buf)
+    }
+}
+
+impl io::Write for Console {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.emit(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Console::flush(self);
+        Ok(())
+    }
+}
+
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        // Write a \r before each \n, as the UART expects.
+        let mut lines = s.split('\n');
+        if let Some(first) = lines.next() {
+            self.emit(first.as_bytes());
+        }
+
+        for line in lines {
+            self.emit(b"\r\n");
+            self.emit(line.as_bytes());
+        }
+
+        Ok(())
+    }
+}
+
+/// Global `Console` singleton. Its lock masks IRQs, so that interrupt
+/// handlers can log.
+pub static CONSOLE: IrqMutex<Console> = IrqMutex::new(Console::new());
+
+/// Writes all buffered console output to the sinks.
+pub fn flush() {
+    #[cfg(not(test))]
+    CONSOLE.lock().flush();
+}
+
+/// Copies the newest bytes of the `dmesg` ring into `buf`, as many as fit,
+/// and returns the number copied. Returns 0 rather than waiting if the
+/// console is locked. For crash records.
+pub fn dmesg_tail(buf: &mut [u8]) -> usize {
+    match CONSOLE.try_lock() {
+        Some(console) => console.dmesg.copy_tail(buf),
+        None => 0,
+    }
+}
+
+/// Releases the console lock even if it is held, discarding any output the
+/// holder had buffered but not flushed.
+///
+/// This is unsafe for the same reasons as `IrqMutex::force_unlock()`. It is meant
+/// for a panic on a core that may have been holding the console.
+pub unsafe fn force_unlock() {
+    CONSOLE.force_unlock();
+    (*CONSOLE.lock()).out_len = 0;
+}
+
+/// Internal function called by the `ekprint[ln]!` macros.
+///
+/// If the console is free, `args` is written and flushed through it as usual.
+/// If it is locked, whether by a core that will never release it or by the
+/// caller itself, `args` is written straight to a freshly initialized mini
+/// UART instead, bypassing the buffer and every other sink.
+#[doc(hidden)]
+pub fn _emergency_print(args: fmt::Arguments) {
+    #[cfg(not(test))]
+    {
+        use std::fmt::Write;
+        match CONSOLE.try_lock() {
+            Some(mut console) => {
+                let _ = console.write_fmt(args);
+                console.flush();
+            }
+            None => {
+                let _ = MiniUart::new().write_fmt(args);
+            }
+        }
+    }
+
+    #[cfg(test)]
+    { print!("{}", args); }
+}
+
+/// Internal function called by the `kprint[ln]!` macros. Output goes to the
+/// running process's session, if it has one.
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    #[cfg(not(test))]
+    {
+        use std::fmt::Write;
+        if let Some(session) = scheduler::session() {
+            return session.print(args);
+        }
+
+        let mut console = CONSOLE.lock();
+        console.write_fmt(args).unwrap();
+    }
+
+    #[cfg(test)]
+    { print!("{}", args); }
+}
+
+/// Like `println!`, but for kernel-space.
+pub macro kprintln {
+    () => (kprint!("\n")),
+    ($fmt:expr) => (kprint!(concat!($fmt, "\n"))),
+    ($fmt:expr, $($arg:tt)*) => (kprint!(concat!($fmt, "\n"), $($arg)*))
+}
+
+/// Like `print!`, but for kernel-space.
+pub macro kprint($($arg:tt)*) {
+    _print(format_args!($($arg)*))
+}
+
+/// Like `kprintln!`, but never waits for the console lock. For panics and
+/// exception handlers, where the lock may never be released.
+pub macro ekprintln {
+    () => (ekprint!("\n")),
+    ($fmt:expr) => (ekprint!(concat!($fmt, "\n"))),
+    ($fmt:expr, $($arg:tt)*) => (ekprint!(concat!($fmt, "\n"), $($arg)*))
+}
+
+/// Like `kprint!`, but never waits for the console lock.
+pub macro ekprint($($arg:tt)*) {
+    _emergency_print(format_args!($($arg)*))
+}
@@ -0,0 +1,271 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use aarch64;
+use mutex::IrqMutex;
+use preempt;
+use process::{Id, Signal};
+use scheduler;
+use sync::WaitQueue;
+
+use super::input::{self, ConsoleInput, UartInput, CTRL_C};
+
+/// The number of bytes of input a session buffers; more are dropped until
+/// some are read.
+const INPUT_LIMIT: usize = 1024;
+
+/// The number of bytes of output a session buffers before writers wait for
+/// the terminal to take some, or, if they can't wait, drop the rest.
+const OUTPUT_LIMIT: usize = 16 * 1024;
+
+/// A console other than the UART, such as a telnet connection: a terminal
+/// with its own input, output, and foreground process.
+///
+/// A process's session is inherited by the processes it starts. Console
+/// output from a process with a session, through `kprint!`, the `write`
+/// system call, or `/dev/console`, goes to the session instead of the
+/// sinks, and its console input comes from the session. Output from
+/// interrupt handlers, or while the scheduler is locked, always goes to the
+/// sinks. See `scheduler::session()`.
+///
+/// Whatever serves the terminal moves bytes between it and the session with
+/// `push_input()` and `take_output()`, and calls `hang_up()` when it goes
+/// away.
+///
+/// A session can also just capture output, for a command whose output is
+/// redirected; see `redirect()`.
+pub struct Session {
+    name: String,
+    input: Input,
+    output: IrqMutex<VecDeque<u8>>,
+    /// The bytes of output buffered before writers wait.
+    output_limit: usize,
+    /// The process Ctrl-C interrupts, or 0 for none.
+    foreground: AtomicUsize,
+    hung_up: AtomicBool,
+    /// Readers waiting for input or a hangup.
+    readable: WaitQueue,
+    /// Writers waiting for room in `output` or a hangup.
+    writable: WaitQueue,
+}
+
+/// Where a session's input comes from.
+enum Input {
+    /// The session's own buffer, filled by `push_input()`.
+    Own(IrqMutex<VecDeque<u8>>),
+    /// The terminal of another session, or the UART if `None`.
+    Terminal(Option<Arc<Session>>),
+}
+
+impl Session {
+    /// Returns a new session named `name`, for log messages.
+    pub fn new(name: &str) -> Arc<Session> {
+        Session::with_input(name, Input::Own(IrqMutex::new(VecDeque::new())), OUTPUT_LIMIT)
+    }
+
+    /// Returns a session named `name` that captures the output of a command
+    /// run in it, without limit, until it is taken. Input, Ctrl-C, and
+    /// hangups are those of `terminal`, the session the command would have
+    /// run in, or of the UART if it is `None`.
+    pub fn redirect(name: &str, terminal: Option<Arc<Session>>) -> Arc<Session> {
+        Session::with_input(name, Input::Terminal(terminal), usize::max_value())
+    }
+
+    fn with_input(name: &str, input: Input, output_limit: usize) -> Arc<Session> {
+        Arc::new(Session {
+            name: name.to_string(),
+            input,
+            output: IrqMutex::new(VecDeque::new()),
+            output_limit,
+            foreground: AtomicUsize::new(0),
+            hung_up: AtomicBool::new(false),
+            readable: WaitQueue::new(),
+            writable: WaitQueue::new(),
+        })
+    }
+
+    /// Returns the session's name.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Adds `bytes`, typed at the terminal, to the session's input. Ctrl-C
+    /// interrupts the foreground process instead, if there is one. Does
+    /// nothing to a session whose input is another terminal's.
+    pub fn push_input(&self, bytes: &[u8]) {
+        let input = match self.input {
+            Input::Own(ref input) => input,
+            Input::Terminal(_) => return,
+        };
+
+        let foreground = self.foreground.load(Ordering::Relaxed) as Id;
+        let mut interrupt = false;
+        {
+            let mut input = input.lock();
+            for &byte in bytes {
+                if byte == CTRL_C && foreground != 0 {
+                    interrupt = true;
+                } else if input.len() < INPUT_LIMIT {
+                    input.push_back(byte);
+                }
+            }
+        }
+
+        if interrupt {
+            scheduler::signal(foreground, Signal::Int);
+        }
+        self.readable.wake_all();
+    }
+
+    /// Removes and returns up to `max` bytes of output for the terminal.
+    pub fn take_output(&self, max: usize) -> Vec<u8> {
+        let taken: Vec<u8> = {
+            let mut output = self.output.lock();
+            let len = max.min(output.len());
+            output.drain(..len).collect()
+        };
+
+        if !taken.is_empty() {
+            self.writable.wake_all();
+        }
+        taken
+    }
+
+    /// Ends the session: the terminal has gone away, or the shell on it
+    /// has exited. Readers get no more input, output is discarded, and the
+    /// foreground process, if any, is asked to terminate.
+    pub fn hang_up(&self) {
+        if self.hung_up.swap(true, Ordering::Relaxed) {
+            return;
+        }
+
+        let foreground = self.foreground.load(Ordering::Relaxed) as Id;
+        if foreground != 0 {
+            scheduler::signal(foreground, Signal::Term);
+        }
+        self.readable.wake_all();
+        self.writable.wake_all();
+    }
+
+    /// Returns whether the session, or the terminal its input comes from,
+    /// has ended.
+    pub fn is_hung_up(&self) -> bool {
+        self.hung_up.load(Ordering::Relaxed) || match self.input {
+            Input::Terminal(Some(ref terminal)) => terminal.is_hung_up(),
+            _ => false,
+        }
+    }
+
+    /// Makes `id` the process Ctrl-C interrupts, or clears it.
+    pub fn set_foreground(&self, id: Option<Id>) {
+        match self.input {
+            Input::Own(_) => self.foreground.store(id.unwrap_or(0) as usize, Ordering::Relaxed),
+            Input::Terminal(Some(ref terminal)) => terminal.set_foreground(id),
+            Input::Terminal(None) => input::set_uart_foreground(id),
+        }
+    }
+
+    /// Writes `bytes` as they are to the terminal. Waits for room if the
+    /// output buffer is full, unless the caller can't sleep, in which case
+    /// whatever doesn't fit is dropped.
+    pub fn write_bytes(&self, bytes: &[u8]) {
+        let can_sleep = !preempt::in_atomic() && !aarch64::irqs_masked();
+        let mut rest = bytes;
+        while !rest.is_empty() && !self.is_hung_up() {
+            let written = {
+                let mut output = self.output.lock();
+                let len = rest.len().min(self.output_limit - output.len());
+                output.extend(rest[..len].iter().cloned());
+                len
+            };
+
+            rest = &rest[written..];
+            if !rest.is_empty() {
+                if !can_sleep {
+                    return;
+                }
+                self.writable.wait_until(|| {
+                    self.is_hung_up() || self.output.lock().len() < self.output_limit
+                });
+            }
+        }
+    }
+
+    /// Writes `args` to the terminal, emitting a `\r` before every `\n` as
+    /// the console does.
+    pub fn print(&self, args: fmt::Arguments) {
+        use std::fmt::Write;
+        let _ = Writer(self).write_fmt(args);
+    }
+
+    /// Waits for a byte of input and returns it, or returns `None` once the
+    /// session has ended.
+    pub fn read_byte(&self) -> Option<u8> {
+        let input = match self.input {
+            Input::Own(ref input) => input,
+            Input::Terminal(Some(ref terminal)) => return terminal.read_byte(),
+            Input::Terminal(None) => return Some(UartInput.read_byte()),
+        };
+
+        let mut byte = None;
+        self.readable.wait_until(|| {
+            byte = input.lock().pop_front();
+            byte.is_some() || self.is_hung_up()
+        });
+        byte
+    }
+
+    /// Returns a byte of input if one has arrived, without waiting.
+    pub fn try_read_byte(&self) -> Option<u8> {
+        match self.input {
+            Input::Own(ref input) => input.lock().pop_front(),
+            Input::Terminal(Some(ref terminal)) => terminal.try_read_byte(),
+            Input::Terminal(None) => UartInput.try_read_byte(),
+        }
+    }
+}
+
+impl fmt::Debug for Session {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Session")
+            .field("name", &self.name)
+            .field("hung_up", &self.is_hung_up())
+            .finish()
+    }
+}
+
+/// Formats output for a session.
+struct Writer<'a>(&'a Session);
+
+impl<'a> fmt::Write for Writer<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut lines = s.split('\n');
+        if let Some(first) = lines.next() {
+            self.0.write_bytes(first.as_bytes());
+        }
+
+        for line in lines {
+            self.0.write_bytes(b"\r\n");
+            self.0.write_bytes(line.as_bytes());
+        }
+
+        Ok(())
+    }
+}
+
+/// Console input read from a session.
+pub struct SessionInput(pub Arc<Session>);
+
+impl ConsoleInput for SessionInput {
+    /// Waits for a byte of input. Once the session has ended, returns `\r`
+    /// at once, ending any line being read.
+    fn read_byte(&mut self) -> u8 {
+        self.0.read_byte().unwrap_or(b'\r')
+    }
+
+    fn hung_up(&self) -> bool {
+        self.0.is_hung_up()
+    }
+}
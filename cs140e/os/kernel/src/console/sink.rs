@@ -0,0 +1,29 @@
+/// A destination for console output: a UART, a framebuffer, a buffer in
+/// memory, and so on.
+///
+/// Sinks receive exactly the bytes written to the console, with every `\n`
+/// written through `fmt::Write` already preceded by a `\r`.
+pub trait Sink: Send {
+    /// Returns a short name identifying this sink, used to enable, disable,
+    /// and remove it. Names must be unique among registered sinks.
+    fn name(&self) -> &'static str;
+
+    /// Writes `bytes` to this sink.
+    ///
+    /// This is called with the console locked, so it must not print to the
+    /// console itself.
+    fn write_bytes(&mut self, bytes: &[u8]);
+}
+
+/// The name of the mini UART sink, which is always registered.
+pub const UART_SINK: &str = "uart";
+
+/// The maximum number of sinks that can be registered, not counting the mini
+/// UART.
+pub const MAX_SINKS: usize = 8;
+
+/// A registered sink and whether output is currently sent to it.
+pub struct Entry {
+    pub sink: Box<Sink>,
+    pub enabled: bool,
+}
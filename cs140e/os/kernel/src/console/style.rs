@@ -0,0 +1,87 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use super::_print;
+
+/// Whether styled output includes ANSI escape codes.
+static COLORS: AtomicBool = AtomicBool::new(true);
+
+/// Returns `true` if styled output includes ANSI escape codes.
+pub fn colors_enabled() -> bool {
+    COLORS.load(Ordering::Relaxed)
+}
+
+/// Enables or disables ANSI escape codes in styled output. When disabled,
+/// styled values are printed as plain text.
+pub fn set_colors_enabled(enabled: bool) {
+    COLORS.store(enabled, Ordering::Relaxed);
+}
+
+/// The escape code that resets all styles.
+pub const RESET: &str = "\x1b[0m";
+
+/// The escape code that starts bold text.
+pub const BOLD: &str = "\x1b[1m";
+
+/// A foreground color, or bold text in the terminal's default color.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Color {
+    Black,
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    White,
+    Bold,
+}
+
+impl Color {
+    /// Returns the escape code that selects this color.
+    pub fn code(&self) -> &'static str {
+        match *self {
+            Color::Black => "\x1b[30m",
+            Color::Red => "\x1b[31m",
+            Color::Green => "\x1b[32m",
+            Color::Yellow => "\x1b[33m",
+            Color::Blue => "\x1b[34m",
+            Color::Magenta => "\x1b[35m",
+            Color::Cyan => "\x1b[36m",
+            Color::White => "\x1b[37m",
+            Color::Bold => BOLD,
+        }
+    }
+
+    /// Wraps `value` so that it is displayed in this color.
+    pub fn paint<T: fmt::Display>(self, value: T) -> Styled<T> {
+        Styled(self, value)
+    }
+}
+
+/// A value displayed in a color. Width, fill, and alignment apply to the
+/// value itself, not to the escape codes around it.
+pub struct Styled<T>(Color, T);
+
+impl<T: fmt::Display> fmt::Display for Styled<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if !colors_enabled() {
+            return self.1.fmt(f);
+        }
+
+        f.write_str(self.0.code())?;
+        self.1.fmt(f)?;
+        f.write_str(RESET)
+    }
+}
+
+/// Like `kprint!`, but in the `Color` named by the first argument.
+pub macro cwrite($color:ident, $($arg:tt)*) {
+    _print(format_args!("{}", Color::$color.paint(format_args!($($arg)*))))
+}
+
+/// Like `kprintln!`, but in the `Color` named by the first argument. The
+/// newline follows the reset, so the color never leaks onto the next line.
+pub macro cwriteln($color:ident, $($arg:tt)*) {
+    _print(format_args!("{}\n", Color::$color.paint(format_args!($($arg)*))))
+}
@@ -0,0 +1,276 @@
+//! Crash records: what a panic reported, saved to the SD card so that it can
+//! be read after a reboot, for boards no one is watching the console of.
+//!
+//! On panic, `save()` writes the panic's message and location, the registers
+//! of the exception being handled, backtraces, and the end of the `dmesg`
+//! ring to the `SECTORS` sectors of the card from `FIRST_SECTOR` on. That is
+//! the gap partitioning tools leave between the MBR and the first partition,
+//! and records are only enabled at boot if the partition table leaves it.
+//!
+//! A record is a header sector followed by its sections, back to back. The
+//! header holds a magic number, when and on which core the panic happened,
+//! the sections' lengths, and a checksum of them. It is written last, so a
+//! write the panic doesn't finish leaves a record that fails its checksum,
+//! not one that reads as another's.
+//!
+//! Saving a record takes no locks it would wait for and doesn't allocate: it
+//! is formatted in a static buffer and written with `sd::try_write_raw()`.
+
+use std::{fmt, io};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use pi::emmc::BLOCK_SIZE;
+use pi::timer;
+
+use aarch64;
+use backtrace;
+use bytes::le;
+use console::{self, log_info, log_warn};
+use fs::block::BlockDevice;
+use fs::mbr;
+use fs::sd::{self, Sd};
+use init::{kernel_init, Init, Stage};
+use traps;
+
+/// The first sector of the SD card a record is written to.
+pub const FIRST_SECTOR: u64 = 1;
+
+/// The sectors a record may take.
+pub const SECTORS: usize = 48;
+
+/// The bytes a record may take, header included.
+const RECORD_SIZE: usize = SECTORS * BLOCK_SIZE;
+
+/// The bytes at the start of a record's header.
+const MAGIC: [u8; 8] = *b"KCRASH01";
+
+/// The offsets in the header of when the panic happened, in microseconds
+/// since boot, of the core it happened on, of the sections' checksum, and of
+/// their lengths.
+const UPTIME_AT: usize = 8;
+const CORE_AT: usize = 16;
+const CHECKSUM_AT: usize = 20;
+const LENGTHS_AT: usize = 24;
+
+/// The sections of a record, in order, and the most bytes each may take.
+/// The `dmesg` section takes what is left.
+const SECTIONS: usize = 4;
+const MESSAGE_MAX: usize = 1024;
+const REGISTERS_MAX: usize = 2048;
+const BACKTRACE_MAX: usize = 4096;
+
+/// Whether the partition table leaves the record's sectors free.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Whether a record is being saved, so that a panic while saving one
+/// doesn't try again.
+static SAVING: AtomicBool = AtomicBool::new(false);
+
+/// The record being saved. Only the panic that sets `SAVING` uses it.
+static mut BUFFER: [u8; RECORD_SIZE] = [0; RECORD_SIZE];
+
+/// An error saving a record.
+#[derive(Debug)]
+pub enum Error {
+    /// Records are disabled: the partition table doesn't leave their
+    /// sectors free, or the SD card hasn't been initialized.
+    Disabled,
+    /// A record is already being saved.
+    Saving,
+    /// Writing the record to the SD card failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Disabled => f.write_str("crash records are disabled"),
+            Error::Saving => f.write_str("already saving a crash record"),
+            Error::Io(ref e) => write!(f, "SD card: {}", e),
+        }
+    }
+}
+
+/// A record, as read back.
+pub struct Record {
+    /// When the panic happened, in microseconds since boot.
+    pub uptime_us: u64,
+    /// The core it happened on.
+    pub core: usize,
+    /// The panic's message and location.
+    pub message: String,
+    /// The registers of the exception being handled, if any.
+    pub registers: String,
+    /// Backtraces of the exception's context, if it was kernel code, and of
+    /// the panic.
+    pub backtrace: String,
+    /// The end of the console output.
+    pub dmesg: Vec<u8>,
+}
+
+/// Formats into a buffer, dropping what doesn't fit.
+struct Cursor<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> fmt::Write for Cursor<'a> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let n = s.len().min(self.buf.len() - self.len);
+        self.buf[self.len..self.len + n].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+        Ok(())
+    }
+}
+
+/// Formats a section at `buf[*at..]` with `f`, at most `max` bytes of it,
+/// and moves `at` past it. Returns its length.
+fn section<F: FnOnce(&mut Cursor)>(buf: &mut [u8], at: &mut usize, max: usize, f: F) -> u32 {
+    let end = (*at + max).min(buf.len());
+    let mut cursor = Cursor { buf: &mut buf[*at..end], len: 0 };
+    f(&mut cursor);
+    *at += cursor.len;
+    cursor.len as u32
+}
+
+/// Saves a record of a panic with message `message` at `file`, `line`, and
+/// `col`, replacing the last. Called by the panic handler.
+pub fn save(message: fmt::Arguments, file: &str, line: u32, col: u32) -> Result<(), Error> {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return Err(Error::Disabled);
+    }
+    if SAVING.swap(true, Ordering::Relaxed) {
+        return Err(Error::Saving);
+    }
+
+    let buf = unsafe { &mut BUFFER };
+    for byte in buf.iter_mut() {
+        *byte = 0;
+    }
+
+    let mut lengths = [0; SECTIONS];
+    let mut at = BLOCK_SIZE;
+    lengths[0] = section(buf, &mut at, MESSAGE_MAX, |w| {
+        let _ = write!(w, "{}\nat {}:{}:{}\n", message, file, line, col);
+    });
+    lengths[1] = section(buf, &mut at, REGISTERS_MAX, |w| {
+        let _ = match traps::current_frame() {
+            Some(tf) => write!(w, "{}", tf.registers()),
+            None => writeln!(w, "not handling an exception"),
+        };
+    });
+    lengths[2] = section(buf, &mut at, BACKTRACE_MAX, |w| {
+        if let Some(tf) = traps::current_frame() {
+            if tf.el() == 1 {
+                let _ = writeln!(w, "interrupted kernel backtrace:");
+                let from_fp = backtrace::from_fp(tf.x[29] as usize);
+                let _ = backtrace::write(w, Some(tf.elr as usize).into_iter().chain(from_fp));
+            }
+        }
+        let _ = writeln!(w, "backtrace:");
+        let _ = backtrace::write(w, backtrace::here());
+    });
+    let dmesg = console::dmesg_tail(&mut buf[at..]);
+    lengths[3] = dmesg as u32;
+    at += dmesg;
+
+    buf[..MAGIC.len()].copy_from_slice(&MAGIC);
+    le::put_u64(buf, UPTIME_AT, timer::current_time());
+    le::put_u32(buf, CORE_AT, aarch64::affinity() as u32);
+    let sum = checksum(&buf[BLOCK_SIZE..at]);
+    le::put_u32(buf, CHECKSUM_AT, sum);
+    for (i, &length) in lengths.iter().enumerate() {
+        le::put_u32(buf, LENGTHS_AT + i * 4, length);
+    }
+
+    // The header goes last.
+    let sectors = (at + BLOCK_SIZE - 1) / BLOCK_SIZE;
+    for i in (1..sectors).chain(0..1) {
+        let sector = unsafe { &*(buf[i * BLOCK_SIZE..].as_ptr() as *const [u8; BLOCK_SIZE]) };
+        sd::try_write_raw(FIRST_SECTOR + i as u64, sector).map_err(Error::Io)?;
+    }
+    Ok(())
+}
+
+/// Returns the last record saved, or `None` if there isn't one. Fails with
+/// `InvalidData` if the record is corrupt.
+pub fn read() -> io::Result<Option<Record>> {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return Ok(None);
+    }
+
+    let mut buf = vec![0; RECORD_SIZE];
+    Sd.read_blocks(FIRST_SECTOR, &mut buf)?;
+    if buf[..MAGIC.len()] != MAGIC {
+        return Ok(None);
+    }
+
+    let corrupt = || io::Error::new(io::ErrorKind::InvalidData, "crash record is corrupt");
+    let mut lengths = [0; SECTIONS];
+    for (i, length) in lengths.iter_mut().enumerate() {
+        *length = le::u32_at(&buf, LENGTHS_AT + i * 4) as usize;
+    }
+    let end = lengths.iter().fold(BLOCK_SIZE, |end, &length| end.saturating_add(length));
+    if end > RECORD_SIZE || checksum(&buf[BLOCK_SIZE..end]) != le::u32_at(&buf, CHECKSUM_AT) {
+        return Err(corrupt());
+    }
+
+    let mut sections = Vec::with_capacity(SECTIONS);
+    let mut at = BLOCK_SIZE;
+    for &length in lengths.iter() {
+        sections.push(buf[at..at + length].to_vec());
+        at += length;
+    }
+    let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).into_owned();
+    Ok(Some(Record {
+        uptime_us: le::u64_at(&buf, UPTIME_AT),
+        core: le::u32_at(&buf, CORE_AT) as usize,
+        message: text(&sections[0]),
+        registers: text(&sections[1]),
+        backtrace: text(&sections[2]),
+        dmesg: sections.pop().unwrap(),
+    }))
+}
+
+/// Discards the last record saved.
+pub fn clear() -> io::Result<()> {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    sd::write_raw(FIRST_SECTOR, &[0; BLOCK_SIZE])
+}
+
+/// Returns the 32-bit FNV-1a hash of `bytes`.
+fn checksum(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c_9dc5, |hash, &byte| (hash ^ byte as u32).wrapping_mul(0x0100_0193))
+}
+
+/// Enables records if the partition table leaves their sectors free, and
+/// says so if there is one from a previous boot.
+fn init() {
+    let end = FIRST_SECTOR + SECTORS as u64;
+    match mbr::read(&mut Sd) {
+        Ok(partitions) => if partitions.iter().any(|p| p.is_used() && p.start < end) {
+            log_warn!("crash records disabled: a partition overlaps sectors {}..{}",
+                      FIRST_SECTOR, end);
+            return;
+        },
+        Err(e) => {
+            log_warn!("crash records disabled: {}", e);
+            return;
+        }
+    }
+
+    ENABLED.store(true, Ordering::Relaxed);
+    match read() {
+        Ok(Some(record)) => {
+            log_info!("crash record from a previous boot: {}; see `crashlog`",
+                      record.message.lines().next().unwrap_or(""));
+        }
+        Ok(None) => {}
+        Err(e) => log_warn!("crash record: {}", e),
+    }
+}
+
+kernel_init!(CRASHLOG_INIT, Stage::Drivers, "crash records", Init::Plain(init));
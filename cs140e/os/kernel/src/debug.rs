@@ -0,0 +1,127 @@
+//! Reading arbitrary kernel memory without risking a panic, for inspecting
+//! memory and device registers from the shell.
+//!
+//! Every load goes through one of the probes in `ext/probe.S`, which are in
+//! the exception table: if a load faults, the exception handler resumes at
+//! the probe's fixup instead of panicking, and the read fails. A value
+//! whose size is 1, 2, 4, or 8 and whose address is aligned for it is read
+//! in a single load of that width, as device registers must be.
+
+use std::mem;
+
+use console::kprintln;
+
+#[cfg(not(test))]
+extern "C" {
+    fn probe_read_8(addr: usize, out: *mut u8) -> u64;
+    fn probe_read_16(addr: usize, out: *mut u16) -> u64;
+    fn probe_read_32(addr: usize, out: *mut u32) -> u64;
+    fn probe_read_64(addr: usize, out: *mut u64) -> u64;
+}
+
+/// The bytes `dump_region()` shows on a line.
+const BYTES_PER_LINE: usize = 16;
+
+/// Reads the byte at `addr`, or returns `None` if reading it faults.
+#[cfg(not(test))]
+fn read_u8(addr: usize) -> Option<u8> {
+    let mut value = 0;
+    match unsafe { probe_read_8(addr, &mut value) } {
+        0 => Some(value),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+fn read_u8(_addr: usize) -> Option<u8> {
+    None
+}
+
+/// Reads `mem::size_of::<T>()` bytes at `addr`, aligned for a load of that
+/// width, into `out`.
+#[cfg(not(test))]
+fn read_sized<T>(addr: usize, out: *mut T) -> bool {
+    let status = unsafe {
+        match mem::size_of::<T>() {
+            1 => probe_read_8(addr, out as *mut u8),
+            2 => probe_read_16(addr, out as *mut u16),
+            4 => probe_read_32(addr, out as *mut u32),
+            _ => probe_read_64(addr, out as *mut u64),
+        }
+    };
+    status == 0
+}
+
+#[cfg(test)]
+fn read_sized<T>(_addr: usize, _out: *mut T) -> bool {
+    false
+}
+
+/// Reads a `T` from `addr`, or returns `None` if any of it can't be read.
+///
+/// `T` should be a type any bit pattern is valid for, such as an integer:
+/// the memory at `addr` can hold anything.
+pub fn try_read<T: Copy>(addr: usize) -> Option<T> {
+    let size = mem::size_of::<T>();
+    let mut value: T = unsafe { mem::zeroed() };
+    let single = (size == 1 || size == 2 || size == 4 || size == 8) && addr % size == 0;
+    if single {
+        if !read_sized(addr, &mut value) {
+            return None;
+        }
+    } else {
+        let bytes = &mut value as *mut T as *mut u8;
+        for i in 0..size {
+            unsafe { *bytes.add(i) = read_u8(addr.checked_add(i)?)?; }
+        }
+    }
+    Some(value)
+}
+
+/// Reads `buf.len()` bytes at `addr` into `buf`, a byte at a time. Returns
+/// the address of the first byte that can't be read if one can't.
+pub fn read_bytes(addr: usize, buf: &mut [u8]) -> Result<(), usize> {
+    for (i, byte) in buf.iter_mut().enumerate() {
+        let at = addr.wrapping_add(i);
+        *byte = read_u8(at).ok_or(at)?;
+    }
+    Ok(())
+}
+
+/// Prints the `len` bytes at `addr`, as `xxd` does: the address, the bytes
+/// in hex, and the bytes as ASCII, `BYTES_PER_LINE` to a line. Stops at the
+/// first byte that can't be read, returning its address.
+pub fn dump_region(addr: usize, len: usize) -> Result<(), usize> {
+    let mut line = [0u8; BYTES_PER_LINE];
+    let mut done = 0;
+    while done < len {
+        let start = addr.wrapping_add(done);
+        let count = (len - done).min(BYTES_PER_LINE);
+        let result = read_bytes(start, &mut line[..count]);
+        let valid = match result {
+            Ok(()) => count,
+            Err(at) => at.wrapping_sub(start),
+        };
+        if valid > 0 {
+            print_line(start, &line[..valid]);
+        }
+        result?;
+        done += count;
+    }
+    Ok(())
+}
+
+fn print_line(addr: usize, bytes: &[u8]) {
+    let mut hex = String::with_capacity(BYTES_PER_LINE * 3);
+    let mut ascii = String::with_capacity(BYTES_PER_LINE);
+    for (i, &byte) in bytes.iter().enumerate() {
+        if i > 0 && i % 2 == 0 {
+            hex.push(' ');
+        }
+        hex.push_str(&format!("{:02x}", byte));
+        ascii.push(if byte >= 0x20 && byte < 0x7F { byte as char } else { '.' });
+    }
+    // Two hex digits for each byte, and a space between each pair of them.
+    let width = BYTES_PER_LINE * 2 + BYTES_PER_LINE / 2 - 1;
+    kprintln!("{:016x}: {:<width$}  {}", addr, hex, ascii, width = width);
+}
@@ -0,0 +1,44 @@
+//! Block devices: storage read and written in fixed-size blocks addressed by
+//! logical block address (LBA).
+
+use std::io;
+
+/// The block size of SD cards, and the default.
+pub const DEFAULT_BLOCK_SIZE: usize = 512;
+
+/// A device read and written in blocks of `block_size()` bytes.
+///
+/// Transfers cover any number of consecutive blocks, so that a driver can
+/// move them in one command rather than one command per block.
+pub trait BlockDevice: Send {
+    /// Returns the size of a block in bytes.
+    fn block_size(&self) -> usize {
+        DEFAULT_BLOCK_SIZE
+    }
+
+    /// Returns whether the device can't be written. Devices that implement
+    /// `write_blocks()` must override this too.
+    fn read_only(&self) -> bool {
+        true
+    }
+
+    /// Reads the blocks from `lba` on into `buf`, whose length must be a
+    /// multiple of the block size.
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> io::Result<()>;
+
+    /// Writes `buf`, whose length must be a multiple of the block size, to
+    /// the blocks from `lba` on. Fails with `PermissionDenied` unless the
+    /// device implements it.
+    fn write_blocks(&mut self, _lba: u64, _buf: &[u8]) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::PermissionDenied, "device is read only"))
+    }
+}
+
+/// Returns the number of blocks of `block_size` bytes in a buffer of `len`
+/// bytes, or an `InvalidInput` error if it isn't a whole number of them.
+pub fn block_count(len: usize, block_size: usize) -> io::Result<usize> {
+    if len % block_size != 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer not a multiple of the block size"));
+    }
+    Ok(len / block_size)
+}
@@ -0,0 +1,232 @@
+//! A write-back cache of device blocks, between the file systems and the
+//! block devices they are on.
+//!
+//! Blocks read are kept in memory until the cache is full, when the least
+//! recently used one is evicted, so that the FAT and directory blocks a
+//! file system keeps going back to are read from the device once. Blocks
+//! written are only marked dirty; they reach the device when they are
+//! evicted, when the cache is synced, or when the flusher thread started
+//! here syncs every mounted file system every `FLUSH_INTERVAL_MS`.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::Arc;
+
+use fat32::traits;
+
+use console::log_warn;
+use fs::block::{self, BlockDevice};
+use init::{kernel_init, Init, Stage};
+use kthread;
+use sync;
+use tick;
+use FILE_SYSTEM;
+
+/// The number of blocks cached per device unless the `bcache=` boot
+/// parameter says otherwise: 128 KiB of 512-byte blocks.
+pub const DEFAULT_CAPACITY: usize = 256;
+
+/// How often the flusher writes dirty blocks back.
+pub const FLUSH_INTERVAL_MS: u64 = 5000;
+
+/// A cached block.
+struct Block {
+    data: Box<[u8]>,
+    /// Whether `data` has been written since it was read or written back.
+    dirty: bool,
+    /// When the block was last used, by `Inner::clock`.
+    used: u64,
+}
+
+struct Inner {
+    device: Box<BlockDevice>,
+    block_size: usize,
+    capacity: usize,
+    blocks: BTreeMap<u64, Block>,
+    /// Counts uses, to order blocks by when they were last used.
+    clock: u64,
+}
+
+/// A handle to a block device's cache. Clones share the cache, so a file
+/// system can own one while the kernel keeps another to sync it.
+#[derive(Clone)]
+pub struct Cache(Arc<sync::Mutex<Inner>>);
+
+impl Cache {
+    /// Caches up to `capacity` blocks, at least one, of `device`.
+    pub fn new<D: BlockDevice + 'static>(device: D, capacity: usize) -> Cache {
+        Cache(Arc::new(sync::Mutex::new(Inner {
+            block_size: device.block_size(),
+            device: Box::new(device),
+            capacity: ::std::cmp::max(capacity, 1),
+            blocks: BTreeMap::new(),
+            clock: 0,
+        })))
+    }
+
+    /// Writes every dirty block back to the device, consecutive blocks in
+    /// one transfer. Blocks that fail stay dirty.
+    pub fn sync(&self) -> io::Result<()> {
+        self.0.lock().sync()
+    }
+}
+
+impl Inner {
+    /// Returns the cached block `lba`, reading it from the device first if
+    /// it isn't cached.
+    fn get(&mut self, lba: u64) -> io::Result<&mut Block> {
+        self.clock += 1;
+        if !self.blocks.contains_key(&lba) {
+            self.make_room()?;
+            let mut data = vec![0; self.block_size].into_boxed_slice();
+            self.device.read_blocks(lba, &mut data)?;
+            self.blocks.insert(lba, Block { data, dirty: false, used: 0 });
+        }
+
+        let block = self.blocks.get_mut(&lba).unwrap();
+        block.used = self.clock;
+        Ok(block)
+    }
+
+    /// Evicts the least recently used block if the cache is full, writing
+    /// it back first if it is dirty.
+    fn make_room(&mut self) -> io::Result<()> {
+        if self.blocks.len() < self.capacity {
+            return Ok(());
+        }
+
+        let lba = *self.blocks.iter().min_by_key(|&(_, block)| block.used).unwrap().0;
+        let block = self.blocks.remove(&lba).unwrap();
+        if block.dirty {
+            if let Err(e) = self.device.write_blocks(lba, &block.data) {
+                self.blocks.insert(lba, block);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, lba: u64, buf: &mut [u8]) -> io::Result<()> {
+        block::block_count(buf.len(), self.block_size)?;
+        let block_size = self.block_size;
+        for (lba, chunk) in (lba..).zip(buf.chunks_mut(block_size)) {
+            chunk.copy_from_slice(&self.get(lba)?.data);
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, lba: u64, buf: &[u8]) -> io::Result<()> {
+        block::block_count(buf.len(), self.block_size)?;
+        if self.device.read_only() {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "device is read only"));
+        }
+
+        let block_size = self.block_size;
+        for (lba, chunk) in (lba..).zip(buf.chunks(block_size)) {
+            // Whole blocks are written, so a block that isn't cached needn't
+            // be read first.
+            self.clock += 1;
+            if !self.blocks.contains_key(&lba) {
+                self.make_room()?;
+                let data = vec![0; block_size].into_boxed_slice();
+                self.blocks.insert(lba, Block { data, dirty: false, used: 0 });
+            }
+
+            let block = self.blocks.get_mut(&lba).unwrap();
+            block.data.copy_from_slice(chunk);
+            block.dirty = true;
+            block.used = self.clock;
+        }
+        Ok(())
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        let dirty: Vec<u64> = self.blocks.iter()
+            .filter(|&(_, block)| block.dirty)
+            .map(|(&lba, _)| lba)
+            .collect();
+
+        let mut result = Ok(());
+        let mut i = 0;
+        while i < dirty.len() {
+            let mut run = 1;
+            while i + run < dirty.len() && dirty[i + run] == dirty[i] + run as u64 {
+                run += 1;
+            }
+
+            let mut buf = Vec::with_capacity(run * self.block_size);
+            for lba in &dirty[i..i + run] {
+                buf.extend_from_slice(&self.blocks[lba].data);
+            }
+
+            match self.device.write_blocks(dirty[i], &buf) {
+                Ok(()) => {
+                    for lba in &dirty[i..i + run] {
+                        self.blocks.get_mut(lba).unwrap().dirty = false;
+                    }
+                }
+                Err(e) => result = Err(e),
+            }
+            i += run;
+        }
+        result
+    }
+}
+
+impl BlockDevice for Cache {
+    fn block_size(&self) -> usize {
+        self.0.lock().block_size
+    }
+
+    fn read_only(&self) -> bool {
+        self.0.lock().device.read_only()
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.0.lock().read(lba, buf)
+    }
+
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> io::Result<()> {
+        self.0.lock().write(lba, buf)
+    }
+}
+
+impl traits::BlockDevice for Cache {
+    fn sector_size(&self) -> u64 {
+        BlockDevice::block_size(self) as u64
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let size = BlockDevice::block_size(self);
+        if buf.len() < size {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer too small"));
+        }
+
+        self.read_blocks(n, &mut buf[..size]).map(|_| size)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        let size = BlockDevice::block_size(self);
+        if buf.len() < size {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer too small"));
+        }
+
+        self.write_blocks(n, &buf[..size]).map(|_| size)
+    }
+}
+
+kernel_init!(FLUSHER_INIT, Stage::Scheduler, "block cache flusher", Init::Plain(init));
+
+/// Starts the flusher thread.
+fn init() {
+    let handle = kthread::spawn("bflush", || loop {
+        tick::sleep_ms(FLUSH_INTERVAL_MS);
+        if let Err(e) = FILE_SYSTEM.sync() {
+            log_warn!("bflush: write-back failed: {}", e);
+        }
+    });
+
+    if handle.is_none() {
+        log_warn!("no memory for the block cache flusher; blocks are written back on sync");
+    }
+}
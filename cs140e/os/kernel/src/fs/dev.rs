@@ -0,0 +1,276 @@
+//! The device file system, mounted at `/dev`: one file for each character
+//! device, so that devices can be opened, read, and written like any other
+//! file.
+//!
+//! * `console`: reads input from the console and writes to it.
+//! * `null`: reads nothing and discards what is written.
+//! * `zero`: reads endless zeros and discards what is written.
+//! * `random`: reads endless bytes from the hardware generator.
+
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use console::{ConsoleInput, Session, UartInput, CONSOLE};
+use fs::vfs::{self, Kind, Metadata};
+use random;
+use scheduler;
+
+/// A device file: its name and how to open it.
+#[derive(Copy, Clone)]
+struct Device {
+    name: &'static str,
+    open: fn() -> Box<vfs::File>,
+}
+
+/// Every device file, in the order they are listed.
+const DEVICES: [Device; 4] = [
+    Device { name: "console", open: open_console },
+    Device { name: "null", open: open_null },
+    Device { name: "zero", open: open_zero },
+    Device { name: "random", open: open_random },
+];
+
+/// Opens `/dev/console`: the running process's session, if it has one, or
+/// the UART.
+pub fn open_console() -> Box<vfs::File> {
+    Box::new(Console(scheduler::session()))
+}
+
+fn open_null() -> Box<vfs::File> {
+    Box::new(Null)
+}
+
+fn open_zero() -> Box<vfs::File> {
+    Box::new(Zero)
+}
+
+fn open_random() -> Box<vfs::File> {
+    Box::new(Random)
+}
+
+/// The device file system.
+pub struct DevFs;
+
+impl vfs::FileSystem for DevFs {
+    fn open(&self, path: &Path) -> io::Result<Box<vfs::Entry>> {
+        let name = path.strip_prefix("/").ok().and_then(|name| name.to_str());
+        match name {
+            Some("") => Ok(Box::new(Root)),
+            Some(name) => DEVICES.iter().find(|device| device.name == name)
+                .map(|&device| Box::new(device) as Box<vfs::Entry>)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such device")),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "no such device")),
+        }
+    }
+}
+
+/// The directory of every device.
+struct Root;
+
+impl vfs::Dir for Root {
+    fn entries(&self) -> io::Result<Vec<Box<vfs::Entry>>> {
+        Ok(DEVICES.iter().map(|&device| Box::new(device) as Box<vfs::Entry>).collect())
+    }
+}
+
+impl vfs::Entry for Root {
+    fn name(&self) -> &str {
+        ""
+    }
+
+    fn metadata(&self) -> Metadata {
+        Metadata::new(Kind::Dir, 0, true)
+    }
+
+    fn into_file(self: Box<Self>) -> Option<Box<vfs::File>> {
+        None
+    }
+
+    fn into_dir(self: Box<Self>) -> Option<Box<vfs::Dir>> {
+        Some(self as Box<vfs::Dir>)
+    }
+}
+
+impl vfs::Entry for Device {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn metadata(&self) -> Metadata {
+        Metadata::new(Kind::File, 0, false)
+    }
+
+    fn into_file(self: Box<Self>) -> Option<Box<vfs::File>> {
+        Some((self.open)())
+    }
+
+    fn into_dir(self: Box<Self>) -> Option<Box<vfs::Dir>> {
+        None
+    }
+}
+
+/// Implements `Seek`, which does nothing on a device, and `vfs::File` for
+/// each device whose reads never wait.
+macro devices($($device:ident),*) {
+    $(
+        impl io::Seek for $device {
+            fn seek(&mut self, _pos: io::SeekFrom) -> io::Result<u64> {
+                Ok(0)
+            }
+        }
+
+        impl vfs::File for $device {
+            fn size(&self) -> u64 {
+                0
+            }
+        }
+    )*
+}
+
+devices!(Null, Zero, Random);
+
+/// `/dev/console`, on the session of the process that opened it, if it had
+/// one.
+struct Console(Option<Arc<Session>>);
+
+impl Read for Console {
+    /// Waits for a byte of input, then reads whatever else has arrived.
+    /// Reads nothing once the session has ended.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        buf[0] = match self.0 {
+            Some(ref session) => match session.read_byte() {
+                Some(byte) => byte,
+                None => return Ok(0),
+            },
+            None => UartInput.read_byte(),
+        };
+        let mut n = 1;
+        while n < buf.len() {
+            let byte = match self.0 {
+                Some(ref session) => session.try_read_byte(),
+                None => UartInput.try_read_byte(),
+            };
+            match byte {
+                Some(byte) => buf[n] = byte,
+                None => break,
+            }
+            n += 1;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for Console {
+    /// Writes `buf`, emitting a `\r` before every `\n` as the console does.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(ref session) = self.0 {
+            for (i, line) in buf.split(|&byte| byte == b'\n').enumerate() {
+                if i > 0 {
+                    session.write_bytes(b"\r\n");
+                }
+                session.write_bytes(line);
+            }
+            return Ok(buf.len());
+        }
+
+        let mut console = CONSOLE.lock();
+        for &byte in buf {
+            if byte == b'\n' {
+                console.write_byte(b'\r');
+            }
+            console.write_byte(byte);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.0.is_none() {
+            CONSOLE.lock().flush();
+        }
+        Ok(())
+    }
+}
+
+impl io::Seek for Console {
+    fn seek(&mut self, _pos: io::SeekFrom) -> io::Result<u64> {
+        Ok(0)
+    }
+}
+
+impl vfs::File for Console {
+    fn size(&self) -> u64 {
+        0
+    }
+
+    /// Reads wait for input.
+    fn may_block(&self) -> bool {
+        true
+    }
+}
+
+/// `/dev/null`.
+struct Null;
+
+impl Read for Null {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Write for Null {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// `/dev/zero`.
+struct Zero;
+
+impl Read for Zero {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        for byte in buf.iter_mut() {
+            *byte = 0;
+        }
+        Ok(buf.len())
+    }
+}
+
+impl Write for Zero {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// `/dev/random`. Writing to it fails.
+struct Random;
+
+impl Read for Random {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        random::fill(buf)
+            .map(|_| buf.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "random number generator failed"))
+    }
+}
+
+impl Write for Random {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::PermissionDenied, "device is read only"))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
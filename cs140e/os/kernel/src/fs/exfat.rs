@@ -0,0 +1,468 @@
+//! A read-only exFAT file system, the format SD cards larger than 32 GB
+//! ship with.
+//!
+//! exFAT keeps its clusters in a heap like FAT32 does, but a file whose
+//! clusters are consecutive can skip the FAT entirely, and a directory
+//! entry is a set of 32-byte entries: a file entry with the attributes and
+//! timestamps, a stream extension with the size and first cluster, and
+//! file name entries holding the name in UTF-16.
+//!
+//! Names are compared case-insensitively with Rust's Unicode case mapping
+//! rather than the volume's up-case table, which only differs for the rare
+//! names whose case mapping changed after the volume was formatted.
+//! Timestamps are taken to be UTC, ignoring their offsets.
+
+use std::cmp::min;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path};
+use std::sync::Arc;
+
+use bytes::le::{u16_at, u32_at, u64_at};
+use clock::DateTime;
+use fs::block::BlockDevice;
+use fs::vfs::{self, Kind, Metadata};
+use sync;
+
+/// The file system name at offset 3 of the boot sector.
+const SIGNATURE: &[u8] = b"EXFAT   ";
+
+/// The size of a directory entry.
+const ENTRY_SIZE: usize = 32;
+
+/// Directory entry types, with the in-use bit set.
+const TYPE_END: u8 = 0x00;
+const TYPE_FILE: u8 = 0x85;
+const TYPE_STREAM: u8 = 0xC0;
+const TYPE_NAME: u8 = 0xC1;
+
+/// File attributes.
+const ATTR_READ_ONLY: u16 = 0x01;
+const ATTR_HIDDEN: u16 = 0x02;
+const ATTR_SYSTEM: u16 = 0x04;
+const ATTR_DIRECTORY: u16 = 0x10;
+
+/// The stream extension flag of a file whose clusters are consecutive and
+/// have no FAT chain.
+const FLAG_NO_FAT_CHAIN: u8 = 0x02;
+
+/// The UTF-16 units in a file name entry.
+const NAME_UNITS: usize = 15;
+
+/// The first cluster of the heap.
+const FIRST_CLUSTER: u32 = 2;
+
+/// A FAT entry at or above this ends a chain or marks a bad cluster.
+const BAD_CLUSTER: u32 = 0xFFFF_FFF7;
+
+/// A mounted exFAT volume.
+struct Volume {
+    device: sync::Mutex<Box<BlockDevice>>,
+    sector_size: u64,
+    cluster_size: u64,
+    /// The sector of the FAT.
+    fat_offset: u64,
+    /// The sector of the first cluster.
+    heap_offset: u64,
+    cluster_count: u32,
+    root_cluster: u32,
+}
+
+/// The clusters of a file or directory.
+#[derive(Copy, Clone)]
+struct Chain {
+    first: u32,
+    /// Whether the clusters are consecutive, without a FAT chain.
+    contiguous: bool,
+}
+
+impl Volume {
+    /// Reads the boot sector of `device` and returns the volume on it.
+    fn new(mut device: Box<BlockDevice>) -> io::Result<Volume> {
+        let mut boot = vec![0; device.block_size()];
+        device.read_blocks(0, &mut boot)?;
+        if boot.len() < 512 || &boot[3..11] != SIGNATURE || boot[510..512] != [0x55, 0xAA] {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not an exFAT file system"));
+        }
+
+        // Sectors are 512 to 4096 bytes, and clusters at most 32 MB.
+        let sector_shift = boot[108] as u32;
+        let cluster_shift = sector_shift + boot[109] as u32;
+        if sector_shift < 9 || sector_shift > 12 || cluster_shift > 25
+            || 1 << sector_shift != device.block_size()
+        {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "unsupported exFAT geometry"));
+        }
+
+        Ok(Volume {
+            device: sync::Mutex::new(device),
+            sector_size: 1 << sector_shift,
+            cluster_size: 1 << cluster_shift,
+            fat_offset: u32_at(&boot, 80) as u64,
+            heap_offset: u32_at(&boot, 88) as u64,
+            cluster_count: u32_at(&boot, 92),
+            root_cluster: u32_at(&boot, 96),
+        })
+    }
+
+    /// Returns whether `cluster` is in the heap.
+    fn is_valid(&self, cluster: u32) -> bool {
+        cluster >= FIRST_CLUSTER && cluster - FIRST_CLUSTER < self.cluster_count
+    }
+
+    /// Reads `buf.len()` bytes from `offset` within `cluster`, which must
+    /// stay within it.
+    fn read_cluster(&self, cluster: u32, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        if !self.is_valid(cluster) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "cluster out of range"));
+        }
+
+        let heap = self.heap_offset * self.sector_size;
+        self.read_bytes(heap + (cluster - FIRST_CLUSTER) as u64 * self.cluster_size + offset, buf)
+    }
+
+    /// Reads `buf.len()` bytes from byte `offset` of the device, a sector
+    /// at a time.
+    fn read_bytes(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let mut device = self.device.lock();
+        let mut sector = vec![0; self.sector_size as usize];
+        let mut done = 0;
+        while done < buf.len() {
+            let at = offset + done as u64;
+            let start = (at % self.sector_size) as usize;
+            let n = min(buf.len() - done, sector.len() - start);
+            device.read_blocks(at / self.sector_size, &mut sector)?;
+            buf[done..done + n].copy_from_slice(&sector[start..start + n]);
+            done += n;
+        }
+        Ok(())
+    }
+
+    /// Returns the FAT entry of `cluster`: the next cluster in its chain,
+    /// or `None` at the end.
+    fn next(&self, cluster: u32) -> io::Result<Option<u32>> {
+        let mut entry = [0; 4];
+        self.read_bytes(self.fat_offset * self.sector_size + cluster as u64 * 4, &mut entry)?;
+        match u32_at(&entry, 0) {
+            next if next >= BAD_CLUSTER => Ok(None),
+            next if self.is_valid(next) => Ok(Some(next)),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "corrupt FAT chain")),
+        }
+    }
+
+    /// Returns cluster `index` of `chain`, walking it from `from`, an
+    /// earlier (index, cluster) of it, if given.
+    fn cluster_at(&self, chain: Chain, index: u64, from: Option<(u64, u32)>) -> io::Result<u32> {
+        if chain.contiguous {
+            return Ok(chain.first.wrapping_add(index as u32));
+        }
+
+        let (mut i, mut cluster) = match from {
+            Some((i, cluster)) if i <= index => (i, cluster),
+            _ => (0, chain.first),
+        };
+        while i < index {
+            cluster = self.next(cluster)?
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "FAT chain too short"))?;
+            i += 1;
+        }
+        Ok(cluster)
+    }
+
+    /// Reads the whole of a directory: `size` bytes of `chain`, or up to the
+    /// end of the chain for the root directory, whose size is unknown.
+    fn read_dir(&self, chain: Chain, size: Option<u64>) -> io::Result<Vec<u8>> {
+        let clusters = size.map(|size| {
+            size / self.cluster_size + (size % self.cluster_size != 0) as u64
+        });
+        let mut data = Vec::new();
+        let mut cluster = Some(chain.first);
+        let mut index = 0;
+        while let Some(current) = cluster {
+            if clusters.map_or(false, |n| index >= n) {
+                break;
+            }
+            // A chain can't be longer than the heap, so a longer one loops.
+            if index >= self.cluster_count as u64 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "FAT chain loops"));
+            }
+
+            let start = data.len();
+            data.resize(start + self.cluster_size as usize, 0);
+            self.read_cluster(current, 0, &mut data[start..])?;
+            index += 1;
+            cluster = match chain.contiguous {
+                true => Some(current.wrapping_add(1)),
+                false => self.next(current)?,
+            };
+        }
+        Ok(data)
+    }
+
+    /// Returns the entries of a directory.
+    fn entries(&self, chain: Chain, size: Option<u64>) -> io::Result<Vec<Node>> {
+        let data = self.read_dir(chain, size)?;
+        let mut nodes = Vec::new();
+        let mut i = 0;
+        while i + ENTRY_SIZE <= data.len() {
+            match data[i] {
+                TYPE_END => break,
+                TYPE_FILE => {
+                    let count = data[i + 1] as usize;
+                    let end = i + (count + 1) * ENTRY_SIZE;
+                    if end > data.len() {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated entry set"));
+                    }
+                    if let Some(node) = Node::parse(&data[i..end]) {
+                        nodes.push(node);
+                    }
+                    i = end;
+                }
+                // Volume labels, bitmaps, deleted entries, and the like.
+                _ => i += ENTRY_SIZE,
+            }
+        }
+        Ok(nodes)
+    }
+}
+
+/// A file or directory, from its directory entry set.
+#[derive(Clone)]
+struct Node {
+    name: String,
+    attributes: u16,
+    created: Option<DateTime>,
+    modified: Option<DateTime>,
+    chain: Chain,
+    /// The size in bytes, or `None` for the root directory.
+    size: Option<u64>,
+    /// The bytes written; those past it read as zero.
+    valid: u64,
+}
+
+impl Node {
+    /// Parses an entry set: a file entry followed by its secondary entries.
+    /// Returns `None` if it isn't a well-formed file or directory.
+    fn parse(set: &[u8]) -> Option<Node> {
+        let stream = set.get(ENTRY_SIZE..2 * ENTRY_SIZE)?;
+        if stream[0] != TYPE_STREAM {
+            return None;
+        }
+
+        let name_len = stream[3] as usize;
+        let mut units = Vec::with_capacity(name_len);
+        for entry in set[2 * ENTRY_SIZE..].chunks(ENTRY_SIZE) {
+            if entry[0] != TYPE_NAME {
+                break;
+            }
+            for j in 0..NAME_UNITS {
+                units.push(u16_at(entry, 2 + 2 * j));
+            }
+        }
+        if units.len() < name_len {
+            return None;
+        }
+
+        let file = &set[..ENTRY_SIZE];
+        Some(Node {
+            name: String::from_utf16_lossy(&units[..name_len]),
+            attributes: u16_at(file, 4),
+            created: date_time(u32_at(file, 8)),
+            modified: date_time(u32_at(file, 12)),
+            chain: Chain {
+                first: u32_at(stream, 20),
+                contiguous: stream[1] & FLAG_NO_FAT_CHAIN != 0,
+            },
+            size: Some(u64_at(stream, 24)),
+            valid: u64_at(stream, 8),
+        })
+    }
+
+    fn is_dir(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            kind: if self.is_dir() { Kind::Dir } else { Kind::File },
+            size: if self.is_dir() { 0 } else { self.size.unwrap_or(0) },
+            read_only: true,
+            hidden: self.attributes & ATTR_HIDDEN != 0,
+            system: self.attributes & ATTR_SYSTEM != 0,
+            created: self.created,
+            modified: self.modified,
+        }
+    }
+}
+
+/// Returns the date and time of an exFAT timestamp, or `None` if it was
+/// never set.
+fn date_time(timestamp: u32) -> Option<DateTime> {
+    let (date, time) = (timestamp >> 16, timestamp & 0xFFFF);
+    if date == 0 {
+        return None;
+    }
+
+    Some(DateTime {
+        year: 1980 + (date >> 9),
+        month: date >> 5 & 0xF,
+        day: date & 0x1F,
+        hour: time >> 11,
+        minute: time >> 5 & 0x3F,
+        second: (time & 0x1F) * 2,
+    })
+}
+
+/// A mounted exFAT file system. Every change to it fails with
+/// `PermissionDenied`.
+pub struct ExFat(Arc<Volume>);
+
+impl ExFat {
+    /// Mounts the exFAT file system on `device`. Fails with `InvalidData` if
+    /// there isn't one.
+    pub fn mount<D: BlockDevice + 'static>(device: D) -> io::Result<ExFat> {
+        Ok(ExFat(Arc::new(Volume::new(Box::new(device))?)))
+    }
+}
+
+impl vfs::FileSystem for ExFat {
+    fn open(&self, path: &Path) -> io::Result<Box<vfs::Entry>> {
+        let mut node = Node {
+            name: String::new(),
+            attributes: ATTR_DIRECTORY | ATTR_READ_ONLY,
+            created: None,
+            modified: None,
+            chain: Chain { first: self.0.root_cluster, contiguous: false },
+            size: None,
+            valid: 0,
+        };
+
+        for component in path.components() {
+            let name = match component {
+                Component::Normal(name) => name.to_str().unwrap_or(""),
+                _ => continue,
+            };
+            if !node.is_dir() {
+                return Err(vfs::not_a_directory());
+            }
+
+            let name = name.to_lowercase();
+            node = self.0.entries(node.chain, node.size)?.into_iter()
+                .find(|child| child.name.to_lowercase() == name)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such file or directory"))?;
+        }
+
+        Ok(Box::new(Entry { volume: self.0.clone(), node }))
+    }
+}
+
+/// A node found by name.
+struct Entry {
+    volume: Arc<Volume>,
+    node: Node,
+}
+
+impl vfs::Entry for Entry {
+    fn name(&self) -> &str {
+        &self.node.name
+    }
+
+    fn metadata(&self) -> Metadata {
+        self.node.metadata()
+    }
+
+    fn into_file(self: Box<Self>) -> Option<Box<vfs::File>> {
+        if self.node.is_dir() {
+            return None;
+        }
+
+        let Entry { volume, node } = *self;
+        Some(Box::new(File { volume, node, pos: 0, cursor: None }))
+    }
+
+    fn into_dir(self: Box<Self>) -> Option<Box<vfs::Dir>> {
+        match self.node.is_dir() {
+            true => Some(Box::new(Dir(*self))),
+            false => None,
+        }
+    }
+}
+
+/// An open directory.
+struct Dir(Entry);
+
+impl vfs::Dir for Dir {
+    fn entries(&self) -> io::Result<Vec<Box<vfs::Entry>>> {
+        let nodes = self.0.volume.entries(self.0.node.chain, self.0.node.size)?;
+        Ok(nodes.into_iter().map(|node| {
+            Box::new(Entry { volume: self.0.volume.clone(), node }) as Box<vfs::Entry>
+        }).collect())
+    }
+}
+
+/// An open file.
+struct File {
+    volume: Arc<Volume>,
+    node: Node,
+    pos: u64,
+    /// The last cluster read and its index in the chain, so that reading on
+    /// from it needn't walk the FAT from the start.
+    cursor: Option<(u64, u32)>,
+}
+
+impl Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let pos = self.pos;
+        let n = vfs::File::read_at(self, pos, buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Write for File {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(vfs::read_only())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for File {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = vfs::seek_position(pos, self.pos, self.node.size.unwrap_or(0))?;
+        Ok(self.pos)
+    }
+}
+
+impl vfs::File for File {
+    fn size(&self) -> u64 {
+        self.node.size.unwrap_or(0)
+    }
+
+    /// Reads from `offset` up to the end of its cluster. The chain is walked
+    /// on from the cluster last read when `offset` is at or after it.
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let size = self.node.size.unwrap_or(0);
+        if offset >= size || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let cluster_size = self.volume.cluster_size;
+        let n = min(min(buf.len() as u64, cluster_size - offset % cluster_size), size - offset);
+        if offset >= self.node.valid {
+            // Allocated but never written.
+            for byte in buf[..n as usize].iter_mut() {
+                *byte = 0;
+            }
+            return Ok(n as usize);
+        }
+
+        let index = offset / cluster_size;
+        let cluster = self.volume.cluster_at(self.node.chain, index, self.cursor)?;
+        self.cursor = Some((index, cluster));
+        let n = min(n, self.node.valid - offset) as usize;
+        self.volume.read_cluster(cluster, offset % cluster_size, &mut buf[..n])?;
+        Ok(n)
+    }
+}
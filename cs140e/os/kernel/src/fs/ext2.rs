@@ -0,0 +1,430 @@
+//! A read-only ext2 file system, for sharing files with Linux.
+//!
+//! The volume is divided into block groups, each with a table of inodes; a
+//! group descriptor table after the superblock says where each group's
+//! table is. An inode holds a file's mode, size, and timestamps, and the
+//! numbers of its first 12 blocks, then of a block of block numbers, a
+//! block of those, and a block of those in turn. A directory is a file of
+//! variable-length entries naming inodes.
+//!
+//! ext3 volumes mount too, as long as their journal needs no recovery, but
+//! ext4 features that change the layout, such as extents, are refused.
+//! Only regular files and directories are shown; symbolic links and
+//! special files are skipped. ext2 records no creation time.
+
+use std::cmp::min;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path};
+use std::sync::Arc;
+
+use bytes::le::{u16_at, u32_at};
+use clock::DateTime;
+use fs::block::BlockDevice;
+use fs::vfs::{self, Kind, Metadata};
+use sync;
+
+/// The byte offset of the superblock.
+const SUPERBLOCK_OFFSET: u64 = 1024;
+
+/// The size of the superblock.
+const SUPERBLOCK_SIZE: usize = 1024;
+
+/// The superblock's magic number.
+const MAGIC: u16 = 0xEF53;
+
+/// The inode of the root directory.
+const ROOT_INODE: u32 = 2;
+
+/// The inode size of revision 0 volumes.
+const GOOD_OLD_INODE_SIZE: u64 = 128;
+
+/// The size of a group descriptor.
+const GROUP_DESCRIPTOR_SIZE: u64 = 32;
+
+/// The incompatible features this driver can read: directory entries with
+/// a file type, and flexible block groups.
+const SUPPORTED_INCOMPAT: u32 = 0x0002 | 0x0200;
+
+/// The read-only compatible feature of files larger than 4 GB.
+const RO_COMPAT_LARGE_FILE: u32 = 0x0002;
+
+/// The block numbers in an inode: 12 direct, then single, double, and
+/// triple indirect.
+const DIRECT_BLOCKS: u64 = 12;
+const INDIRECT: usize = 12;
+const DOUBLE_INDIRECT: usize = 13;
+const TRIPLE_INDIRECT: usize = 14;
+
+/// The file type bits of an inode's mode.
+const MODE_TYPE: u16 = 0xF000;
+const MODE_DIR: u16 = 0x4000;
+const MODE_FILE: u16 = 0x8000;
+
+/// The write permission bits of an inode's mode.
+const MODE_WRITE: u16 = 0o222;
+
+/// A mounted ext2 volume.
+struct Volume {
+    device: sync::Mutex<Box<BlockDevice>>,
+    block_size: u64,
+    inodes_per_group: u32,
+    inode_size: u64,
+    inodes_count: u32,
+    large_files: bool,
+    /// The first block of each group's inode table.
+    inode_tables: Vec<u32>,
+}
+
+/// What an inode says about a file.
+#[derive(Copy, Clone)]
+struct Inode {
+    mode: u16,
+    size: u64,
+    mtime: u32,
+    blocks: [u32; 15],
+}
+
+impl Volume {
+    /// Reads the superblock and group descriptors of `device` and returns
+    /// the volume on it.
+    fn new(device: Box<BlockDevice>) -> io::Result<Volume> {
+        let sector_size = device.block_size() as u64;
+        let mut volume = Volume {
+            device: sync::Mutex::new(device),
+            block_size: sector_size,
+            inodes_per_group: 0,
+            inode_size: GOOD_OLD_INODE_SIZE,
+            inodes_count: 0,
+            large_files: false,
+            inode_tables: Vec::new(),
+        };
+
+        let mut sb = [0; SUPERBLOCK_SIZE];
+        volume.read_bytes(SUPERBLOCK_OFFSET, &mut sb)?;
+        if u16_at(&sb, 56) != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not an ext2 file system"));
+        }
+
+        let log_block_size = u32_at(&sb, 24);
+        let incompat = u32_at(&sb, 96);
+        if log_block_size > 6 || 1024 << log_block_size < sector_size {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "unsupported ext2 block size"));
+        }
+        if u32_at(&sb, 76) >= 1 && incompat & !SUPPORTED_INCOMPAT != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "unsupported ext2 features"));
+        }
+
+        volume.block_size = 1024 << log_block_size;
+        volume.inodes_count = u32_at(&sb, 0);
+        volume.inodes_per_group = u32_at(&sb, 40);
+        if u32_at(&sb, 76) >= 1 {
+            volume.inode_size = u16_at(&sb, 88) as u64;
+            volume.large_files = u32_at(&sb, 100) & RO_COMPAT_LARGE_FILE != 0;
+        }
+
+        let blocks_per_group = u32_at(&sb, 32) as u64;
+        let first_data_block = u32_at(&sb, 20) as u64;
+        let blocks = (u32_at(&sb, 4) as u64).saturating_sub(first_data_block);
+        if blocks_per_group == 0 || volume.inodes_per_group == 0 || volume.inode_size < 128 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "corrupt ext2 superblock"));
+        }
+
+        // Read the descriptors a block at a time, so that a corrupt group
+        // count runs into the end of the device rather than out of memory.
+        let groups = (blocks + blocks_per_group - 1) / blocks_per_group;
+        let table = (first_data_block + 1) * volume.block_size;
+        let mut descriptors = vec![0; volume.block_size as usize];
+        while (volume.inode_tables.len() as u64) < groups {
+            let read = volume.inode_tables.len() as u64;
+            let n = min(groups - read, volume.block_size / GROUP_DESCRIPTOR_SIZE);
+            let chunk = &mut descriptors[..(n * GROUP_DESCRIPTOR_SIZE) as usize];
+            volume.read_bytes(table + read * GROUP_DESCRIPTOR_SIZE, chunk)?;
+            volume.inode_tables.extend(chunk.chunks(GROUP_DESCRIPTOR_SIZE as usize)
+                .map(|descriptor| u32_at(descriptor, 8)));
+        }
+        Ok(volume)
+    }
+
+    /// Reads `buf.len()` bytes from byte `offset` of the device, a sector
+    /// at a time.
+    fn read_bytes(&self, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        let mut device = self.device.lock();
+        let sector_size = device.block_size() as u64;
+        let mut sector = vec![0; sector_size as usize];
+        let mut done = 0;
+        while done < buf.len() {
+            let at = offset + done as u64;
+            let start = (at % sector_size) as usize;
+            let n = min(buf.len() - done, sector.len() - start);
+            device.read_blocks(at / sector_size, &mut sector)?;
+            buf[done..done + n].copy_from_slice(&sector[start..start + n]);
+            done += n;
+        }
+        Ok(())
+    }
+
+    /// Reads inode `ino`.
+    fn inode(&self, ino: u32) -> io::Result<Inode> {
+        if ino == 0 || ino > self.inodes_count {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "inode out of range"));
+        }
+
+        let (group, index) = ((ino - 1) / self.inodes_per_group, (ino - 1) % self.inodes_per_group);
+        let table = *self.inode_tables.get(group as usize)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "inode out of range"))?;
+        let mut raw = [0; GOOD_OLD_INODE_SIZE as usize];
+        self.read_bytes(table as u64 * self.block_size + index as u64 * self.inode_size, &mut raw)?;
+
+        let mode = u16_at(&raw, 0);
+        let mut size = u32_at(&raw, 4) as u64;
+        if self.large_files && mode & MODE_TYPE == MODE_FILE {
+            size |= (u32_at(&raw, 108) as u64) << 32;
+        }
+        let mut blocks = [0; 15];
+        for (i, block) in blocks.iter_mut().enumerate() {
+            *block = u32_at(&raw, 40 + 4 * i);
+        }
+        Ok(Inode { mode, size, mtime: u32_at(&raw, 16), blocks })
+    }
+
+    /// Returns the block holding block `index` of the file of `inode`, or 0
+    /// for a hole.
+    fn block_at(&self, inode: &Inode, index: u64) -> io::Result<u32> {
+        let per_block = self.block_size / 4;
+        if index < DIRECT_BLOCKS {
+            return Ok(inode.blocks[index as usize]);
+        }
+
+        // Find which tree the block is in and its path down the tree.
+        let mut index = index - DIRECT_BLOCKS;
+        let mut span = per_block;
+        let mut depth = 1;
+        let roots = [INDIRECT, DOUBLE_INDIRECT, TRIPLE_INDIRECT];
+        while index >= span {
+            index -= span;
+            span *= per_block;
+            depth += 1;
+            if depth > roots.len() {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "offset past the largest file"));
+            }
+        }
+
+        let mut block = inode.blocks[roots[depth - 1]];
+        while span > 1 && block != 0 {
+            span /= per_block;
+            let mut entry = [0; 4];
+            self.read_bytes(block as u64 * self.block_size + index / span * 4, &mut entry)?;
+            block = u32_at(&entry, 0);
+            index %= span;
+        }
+        Ok(block)
+    }
+
+    /// Reads `buf.len()` bytes from `offset` in the file of `inode`, within
+    /// one block.
+    fn read_file(&self, inode: &Inode, offset: u64, buf: &mut [u8]) -> io::Result<()> {
+        match self.block_at(inode, offset / self.block_size)? {
+            0 => {
+                for byte in buf.iter_mut() {
+                    *byte = 0;
+                }
+                Ok(())
+            }
+            block => self.read_bytes(block as u64 * self.block_size + offset % self.block_size, buf),
+        }
+    }
+
+    /// Returns the regular files and directories in the directory `inode`.
+    /// Entries never cross a block, so the directory is read and parsed a
+    /// block at a time.
+    fn entries(&self, inode: &Inode) -> io::Result<Vec<Node>> {
+        let mut nodes = Vec::new();
+        let mut block = vec![0; self.block_size as usize];
+        let mut offset = 0;
+        while offset < inode.size {
+            let n = min(inode.size - offset, self.block_size) as usize;
+            self.read_file(inode, offset, &mut block[..n])?;
+            self.parse_entries(&block[..n], &mut nodes)?;
+            offset += n as u64;
+        }
+        Ok(nodes)
+    }
+
+    /// Adds the regular files and directories among the entries in `data`,
+    /// a block of a directory, to `nodes`.
+    fn parse_entries(&self, data: &[u8], nodes: &mut Vec<Node>) -> io::Result<()> {
+        let mut i = 0;
+        while i + 8 <= data.len() {
+            let ino = u32_at(&data, i);
+            let rec_len = u16_at(&data, i + 4) as usize;
+            let name_len = data[i + 6] as usize;
+            if rec_len < 8 || i + rec_len > data.len() || 8 + name_len > rec_len {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "corrupt directory entry"));
+            }
+
+            let name = String::from_utf8_lossy(&data[i + 8..i + 8 + name_len]).into_owned();
+            if ino != 0 && name != "." && name != ".." {
+                let inode = self.inode(ino)?;
+                if inode.mode & MODE_TYPE == MODE_DIR || inode.mode & MODE_TYPE == MODE_FILE {
+                    nodes.push(Node { name, inode });
+                }
+            }
+            i += rec_len;
+        }
+        Ok(())
+    }
+}
+
+/// A regular file or directory, with its name.
+#[derive(Clone)]
+struct Node {
+    name: String,
+    inode: Inode,
+}
+
+impl Node {
+    fn is_dir(&self) -> bool {
+        self.inode.mode & MODE_TYPE == MODE_DIR
+    }
+
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            kind: if self.is_dir() { Kind::Dir } else { Kind::File },
+            size: if self.is_dir() { 0 } else { self.inode.size },
+            read_only: self.inode.mode & MODE_WRITE == 0,
+            hidden: self.name.starts_with('.'),
+            system: false,
+            created: None,
+            modified: Some(DateTime::from_epoch(self.inode.mtime as u64)),
+        }
+    }
+}
+
+/// A mounted ext2 file system. Every change to it fails with
+/// `PermissionDenied`.
+pub struct Ext2(Arc<Volume>);
+
+impl Ext2 {
+    /// Mounts the ext2 file system on `device`. Fails with `InvalidData` if
+    /// there isn't one or it uses features this driver can't read.
+    pub fn mount<D: BlockDevice + 'static>(device: D) -> io::Result<Ext2> {
+        Ok(Ext2(Arc::new(Volume::new(Box::new(device))?)))
+    }
+}
+
+impl vfs::FileSystem for Ext2 {
+    fn open(&self, path: &Path) -> io::Result<Box<vfs::Entry>> {
+        let mut node = Node { name: String::new(), inode: self.0.inode(ROOT_INODE)? };
+        for component in path.components() {
+            let name = match component {
+                Component::Normal(name) => name.to_str().unwrap_or(""),
+                _ => continue,
+            };
+            if !node.is_dir() {
+                return Err(vfs::not_a_directory());
+            }
+
+            node = self.0.entries(&node.inode)?.into_iter()
+                .find(|child| child.name == name)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such file or directory"))?;
+        }
+
+        Ok(Box::new(Entry { volume: self.0.clone(), node }))
+    }
+}
+
+/// A node found by name.
+struct Entry {
+    volume: Arc<Volume>,
+    node: Node,
+}
+
+impl vfs::Entry for Entry {
+    fn name(&self) -> &str {
+        &self.node.name
+    }
+
+    fn metadata(&self) -> Metadata {
+        self.node.metadata()
+    }
+
+    fn into_file(self: Box<Self>) -> Option<Box<vfs::File>> {
+        if self.node.is_dir() {
+            return None;
+        }
+
+        let Entry { volume, node } = *self;
+        Some(Box::new(File { volume, inode: node.inode, pos: 0 }))
+    }
+
+    fn into_dir(self: Box<Self>) -> Option<Box<vfs::Dir>> {
+        match self.node.is_dir() {
+            true => Some(Box::new(Dir(*self))),
+            false => None,
+        }
+    }
+}
+
+/// An open directory.
+struct Dir(Entry);
+
+impl vfs::Dir for Dir {
+    fn entries(&self) -> io::Result<Vec<Box<vfs::Entry>>> {
+        let nodes = self.0.volume.entries(&self.0.node.inode)?;
+        Ok(nodes.into_iter().map(|node| {
+            Box::new(Entry { volume: self.0.volume.clone(), node }) as Box<vfs::Entry>
+        }).collect())
+    }
+}
+
+/// An open file.
+struct File {
+    volume: Arc<Volume>,
+    inode: Inode,
+    pos: u64,
+}
+
+impl Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let pos = self.pos;
+        let n = vfs::File::read_at(self, pos, buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Write for File {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(vfs::read_only())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for File {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = vfs::seek_position(pos, self.pos, self.inode.size)?;
+        Ok(self.pos)
+    }
+}
+
+impl vfs::File for File {
+    fn size(&self) -> u64 {
+        self.inode.size
+    }
+
+    /// Reads from `offset` up to the end of its block.
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if offset >= self.inode.size || buf.is_empty() {
+            return Ok(0);
+        }
+
+        let block_size = self.volume.block_size;
+        let n = min(min(buf.len() as u64, block_size - offset % block_size),
+                    self.inode.size - offset) as usize;
+        self.volume.read_file(&self.inode, offset, &mut buf[..n])?;
+        Ok(n)
+    }
+}
@@ -0,0 +1,194 @@
+//! The FAT32 file system from the `fat32` crate, as a `vfs::FileSystem`.
+
+use std::io;
+use std::path::Path;
+
+use fat32::traits;
+use fat32::vfat::{self, Shared, VFat};
+
+use bytes::le;
+use clock::DateTime;
+use fs::block::BlockDevice;
+use fs::cache::Cache;
+use fs::mbr::{self, PartitionEntry};
+use fs::vfs::{self, Kind, Metadata};
+use mutex::Mutex;
+
+/// A mounted FAT32 file system.
+pub struct Fat32 {
+    vfat: Mutex<Shared<VFat>>,
+    /// The cache the file system reads `device` through.
+    cache: Cache,
+}
+
+impl Fat32 {
+    /// Mounts the FAT32 file system on the first FAT32 partition of the
+    /// device `cache` reads through.
+    pub fn mount(cache: Cache) -> io::Result<Fat32> {
+        let vfat = VFat::from(cache.clone()).map_err(not_fat32)?;
+        Ok(Fat32 { vfat: Mutex::new(vfat), cache })
+    }
+
+    /// Mounts the FAT32 file system on partition `number`, 1 through
+    /// `mbr::PARTITIONS`, of the device `cache` reads through. Fails with
+    /// `NotFound` if there is no such partition, and with `InvalidData` if
+    /// it isn't a FAT32 partition.
+    pub fn mount_partition(cache: Cache, number: usize) -> io::Result<Fat32> {
+        let entry = match number {
+            1...mbr::PARTITIONS => mbr::read(&mut cache.clone())?[number - 1],
+            _ => return Err(io::Error::new(io::ErrorKind::NotFound, "no such partition")),
+        };
+        if !entry.is_used() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no such partition"));
+        }
+        if !entry.is_fat32() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a FAT32 partition"));
+        }
+
+        let device = OnePartition { cache: cache.clone(), entry };
+        let vfat = VFat::from(device).map_err(not_fat32)?;
+        Ok(Fat32 { vfat: Mutex::new(vfat), cache })
+    }
+
+    /// Returns a handle to the file system, which is unlocked while it is
+    /// used so that other processes can use it too.
+    fn vfat(&self) -> Shared<VFat> {
+        self.vfat.lock().clone()
+    }
+}
+
+/// Returns the error for a partition the `fat32` crate couldn't mount.
+fn not_fat32<E: ::std::fmt::Debug>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("not a FAT32 file system: {:?}", error))
+}
+
+/// A device that shows one partition of another: its partition table lists
+/// only that partition, first, so that the `fat32` crate, which mounts the
+/// first FAT32 partition it finds, mounts that one.
+struct OnePartition {
+    cache: Cache,
+    entry: PartitionEntry,
+}
+
+impl traits::BlockDevice for OnePartition {
+    fn sector_size(&self) -> u64 {
+        traits::BlockDevice::sector_size(&self.cache)
+    }
+
+    fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
+        if n != 0 {
+            return traits::BlockDevice::read_sector(&mut self.cache, n, buf);
+        }
+
+        let size = self.cache.block_size();
+        if buf.len() < size || size < 512 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "buffer too small"));
+        }
+        for byte in buf[..size].iter_mut() {
+            *byte = 0;
+        }
+        {
+            let entry = &mut buf[446..462];
+            entry[4] = mbr::TYPE_FAT32_LBA;
+            le::put_u32(entry, 8, self.entry.start as u32);
+            le::put_u32(entry, 12, self.entry.sectors as u32);
+        }
+        buf[510..512].copy_from_slice(&[0x55, 0xAA]);
+        Ok(size)
+    }
+
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        if n == 0 {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "partition table is read only"));
+        }
+        traits::BlockDevice::write_sector(&mut self.cache, n, buf)
+    }
+}
+
+/// Returns the date and time of a directory entry's timestamp, or `None`
+/// if it was never set: FAT stores 0 for the month and day then.
+fn date_time<T: traits::Timestamp>(timestamp: T) -> Option<DateTime> {
+    if timestamp.month() == 0 || timestamp.day() == 0 {
+        return None;
+    }
+
+    Some(DateTime {
+        year: timestamp.year() as u32,
+        month: timestamp.month() as u32,
+        day: timestamp.day() as u32,
+        hour: timestamp.hour() as u32,
+        minute: timestamp.minute() as u32,
+        second: timestamp.second() as u32,
+    })
+}
+
+impl vfs::File for vfat::File {
+    fn size(&self) -> u64 {
+        traits::File::size(self)
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        traits::File::sync(self)
+    }
+}
+
+impl vfs::Dir for vfat::Dir {
+    fn entries(&self) -> io::Result<Vec<Box<vfs::Entry>>> {
+        let entries = traits::Dir::entries(self)?;
+        Ok(entries.map(|entry| Box::new(entry) as Box<vfs::Entry>).collect())
+    }
+}
+
+impl vfs::Entry for vfat::Entry {
+    fn name(&self) -> &str {
+        traits::Entry::name(self)
+    }
+
+    fn metadata(&self) -> Metadata {
+        let file = traits::Entry::as_file(self);
+        let metadata = traits::Entry::metadata(self);
+        Metadata {
+            kind: if file.is_some() { Kind::File } else { Kind::Dir },
+            size: file.map_or(0, traits::File::size),
+            read_only: traits::Metadata::read_only(metadata),
+            hidden: traits::Metadata::hidden(metadata),
+            system: traits::Metadata::system(metadata),
+            created: date_time(traits::Metadata::created(metadata)),
+            modified: date_time(traits::Metadata::modified(metadata)),
+        }
+    }
+
+    fn into_file(self: Box<Self>) -> Option<Box<vfs::File>> {
+        traits::Entry::into_file(*self).map(|file| Box::new(file) as Box<vfs::File>)
+    }
+
+    fn into_dir(self: Box<Self>) -> Option<Box<vfs::Dir>> {
+        traits::Entry::into_dir(*self).map(|dir| Box::new(dir) as Box<vfs::Dir>)
+    }
+}
+
+impl vfs::FileSystem for Fat32 {
+    fn open(&self, path: &Path) -> io::Result<Box<vfs::Entry>> {
+        Ok(Box::new(traits::FileSystem::open(&self.vfat(), path)?))
+    }
+
+    fn create_file(&self, path: &Path) -> io::Result<Box<vfs::File>> {
+        Ok(Box::new(traits::FileSystem::create_file(&self.vfat(), path)?))
+    }
+
+    fn create_dir(&self, path: &Path, parents: bool) -> io::Result<Box<vfs::Dir>> {
+        Ok(Box::new(traits::FileSystem::create_dir(&self.vfat(), path, parents)?))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        traits::FileSystem::rename(&self.vfat(), from, to)
+    }
+
+    fn remove(&self, path: &Path, children: bool) -> io::Result<()> {
+        traits::FileSystem::remove(&self.vfat(), path, children)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.cache.sync()
+    }
+}
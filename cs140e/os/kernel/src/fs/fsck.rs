@@ -0,0 +1,672 @@
+//! Checking FAT32 file systems for damage, and repairing it.
+//!
+//! The checker walks the directory tree from the root, following the cluster
+//! chain of every file and directory through the FAT and claiming the
+//! clusters on it. A chain that leaves the volume or reaches a free or bad
+//! cluster is broken; one that reaches a cluster already claimed, by
+//! another chain or its own, is cross-linked. Clusters the FAT marks in use
+//! that no chain claims are lost. Directory entries are checked for names
+//! FAT can't hold, sizes that don't fit their chains, and `.` and `..`
+//! entries that point elsewhere; the copies of the FAT against each other;
+//! and the FSInfo free count against the FAT.
+//!
+//! Repairs are those `fsck.fat` makes: broken and cross-linked chains are
+//! cut short before the bad link, sizes and chains are trimmed to fit each
+//! other, directories that have no clusters left are removed, lost clusters
+//! are freed, and the free count is recounted. Bad names and missing `.`
+//! and `..` entries are only reported. Paths in the report are made of
+//! short names.
+
+use std::cmp::min;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io;
+
+use bytes::le::{put_u16, put_u32, u16_at, u32_at};
+use fs::block::BlockDevice;
+use fs::mbr::Partition;
+
+/// The sector size FAT32 is checked with.
+const SECTOR_SIZE: usize = 512;
+
+/// The size of a directory entry.
+const ENTRY_SIZE: usize = 32;
+
+/// The FAT entries in a sector.
+const ENTRIES_PER_SECTOR: usize = SECTOR_SIZE / 4;
+
+/// The sectors read per request when loading the FAT.
+const READ_CHUNK: u64 = 64;
+
+/// The bits of a FAT entry that hold a cluster number; the top four are
+/// reserved.
+const ENTRY_MASK: u32 = 0x0FFF_FFFF;
+
+/// The FAT entry of a bad cluster.
+const BAD_CLUSTER: u32 = 0x0FFF_FFF7;
+
+/// FAT entries from this one on end a chain.
+const END_OF_CHAIN_MIN: u32 = 0x0FFF_FFF8;
+
+/// The FAT entry written to end a chain.
+const END_OF_CHAIN: u32 = 0x0FFF_FFFF;
+
+/// The FSInfo free count that means the count is unknown.
+const FREE_UNKNOWN: u32 = 0xFFFF_FFFF;
+
+/// Directory entry attributes.
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_LONG_NAME: u8 = 0x0F;
+
+/// The first name byte of a deleted entry, and of the end of a directory.
+const DELETED: u8 = 0xE5;
+const END_OF_DIR: u8 = 0x00;
+
+/// A problem found on the volume.
+#[derive(Debug)]
+pub enum Problem {
+    /// The copy of the FAT numbered `copy`, from 1, differs from the first.
+    FatMismatch { copy: u64 },
+    /// The chain of `path` reaches `cluster`, which is out of range, free,
+    /// or bad.
+    BrokenChain { path: String, cluster: u32 },
+    /// The chain of `path` reaches `cluster`, which the chain of `other` has
+    /// already claimed.
+    CrossLinked { path: String, other: String, cluster: u32 },
+    /// The size of `path` doesn't fit its chain of `clusters` clusters.
+    SizeMismatch { path: String, size: u32, clusters: u64 },
+    /// The directory at `path` has a size; directories record none.
+    DirectorySize { path: String, size: u32 },
+    /// `path` has a name that isn't a valid short name.
+    BadName { path: String },
+    /// The directory at `path` lacks its `name` entry, `.` or `..`.
+    MissingDotEntry { path: String, name: &'static str },
+    /// The `name` entry, `.` or `..`, of the directory at `path` points to
+    /// the wrong cluster.
+    BadDotEntry { path: String, name: &'static str },
+    /// `count` clusters are marked in use but belong to no chain.
+    LostClusters { count: u64 },
+    /// FSInfo records `recorded` free clusters, but there are `actual`.
+    FreeCount { recorded: u32, actual: u64 },
+}
+
+impl Problem {
+    /// Returns whether a repair fixes the problem.
+    pub fn repairable(&self) -> bool {
+        match *self {
+            Problem::BadName { .. } | Problem::MissingDotEntry { .. } => false,
+            _ => true,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Problem::FatMismatch { copy } => write!(f, "FAT copy {} differs from the first", copy),
+            Problem::BrokenChain { ref path, cluster } => {
+                write!(f, "{}: chain broken at cluster {}", path, cluster)
+            }
+            Problem::CrossLinked { ref path, ref other, cluster } => {
+                write!(f, "{}: cross-linked with {} at cluster {}", path, other, cluster)
+            }
+            Problem::SizeMismatch { ref path, size, clusters } => {
+                write!(f, "{}: size {} doesn't fit its {} clusters", path, size, clusters)
+            }
+            Problem::DirectorySize { ref path, size } => {
+                write!(f, "{}: directory has size {}, should be 0", path, size)
+            }
+            Problem::BadName { ref path } => write!(f, "{}: invalid name", path),
+            Problem::MissingDotEntry { ref path, name } => {
+                write!(f, "{}: no '{}' entry", path, name)
+            }
+            Problem::BadDotEntry { ref path, name } => {
+                write!(f, "{}: '{}' entry points to the wrong cluster", path, name)
+            }
+            Problem::LostClusters { count } => write!(f, "{} lost clusters", count),
+            Problem::FreeCount { recorded, actual } => {
+                write!(f, "free count is {}, should be {}", recorded, actual)
+            }
+        }
+    }
+}
+
+/// The outcome of checking a volume.
+#[derive(Debug)]
+pub struct Report {
+    /// The problems found, in the order they were.
+    pub problems: Vec<Problem>,
+    /// Whether the repairable problems were repaired.
+    pub repaired: bool,
+    /// The files and directories reached from the root.
+    pub files: u64,
+    pub dirs: u64,
+    /// The clusters in use, and on the volume.
+    pub used: u64,
+    pub clusters: u64,
+}
+
+/// How a chain ended.
+enum End {
+    /// At an end-of-chain entry.
+    Ok,
+    /// At `cluster`, which is out of range, free, or bad.
+    Broken(u32),
+    /// At `cluster`, claimed by the chain with ID `owner`.
+    CrossLinked(u32, usize),
+}
+
+/// A directory waiting to be checked.
+struct Pending {
+    path: String,
+    /// The first cluster of the directory, and of its parent, or 0 if the
+    /// parent is the root.
+    first: u32,
+    parent: u32,
+    clusters: Vec<u32>,
+}
+
+/// A volume being checked.
+struct Checker<'a, D: BlockDevice + 'a> {
+    partition: &'a mut Partition<D>,
+    repair: bool,
+    sectors_per_cluster: u64,
+    fats: u64,
+    fat_start: u64,
+    fat_sectors: u64,
+    data_start: u64,
+    clusters: u64,
+    root_cluster: u32,
+    fsinfo_sector: u64,
+    /// The first copy of the FAT, as far as the clusters go, and which of
+    /// its sectors have been changed.
+    fat: Vec<u32>,
+    dirty: BTreeSet<u64>,
+    /// The ID of the chain that claims each cluster, or 0, and the path of
+    /// the chain with each ID, from 1.
+    owners: Vec<usize>,
+    paths: Vec<String>,
+    report: Report,
+}
+
+/// Checks the FAT32 file system on `partition`, repairing what it can if
+/// `repair` is set. Fails with `InvalidData` if the partition doesn't hold a
+/// FAT32 file system or its root directory is unreadable, and with
+/// `PermissionDenied` if `repair` is set and the device is read only.
+pub fn fat32<D: BlockDevice>(partition: &mut Partition<D>, repair: bool) -> io::Result<Report> {
+    if partition.block_size() != SECTOR_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "sectors must be 512 bytes"));
+    }
+    if repair && partition.read_only() {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "device is read only"));
+    }
+
+    let mut checker = Checker::new(partition, repair)?;
+    checker.check_fats()?;
+    checker.check_tree()?;
+    checker.check_lost();
+    checker.check_free_count()?;
+    checker.write_fat()?;
+    Ok(checker.finish())
+}
+
+impl<'a, D: BlockDevice> Checker<'a, D> {
+    /// Reads the boot sector and the first FAT of `partition`.
+    fn new(partition: &'a mut Partition<D>, repair: bool) -> io::Result<Checker<'a, D>> {
+        let mut boot = [0; SECTOR_SIZE];
+        partition.read_blocks(0, &mut boot)?;
+
+        let not_fat32 = || io::Error::new(io::ErrorKind::InvalidData, "not a FAT32 file system");
+        let sectors_per_cluster = boot[13] as u64;
+        let reserved = u16_at(&boot, 14) as u64;
+        let fats = boot[16] as u64;
+        let sectors = match u16_at(&boot, 19) {
+            0 => u32_at(&boot, 32) as u64,
+            sectors => sectors as u64,
+        };
+        let fat_sectors = u32_at(&boot, 36) as u64;
+        if boot[510..512] != [0x55, 0xAA] || u16_at(&boot, 11) as usize != SECTOR_SIZE
+            || !sectors_per_cluster.is_power_of_two() || reserved == 0 || fats == 0
+            || u16_at(&boot, 17) != 0 || u16_at(&boot, 22) != 0 || fat_sectors == 0
+        {
+            return Err(not_fat32());
+        }
+
+        let data_start = reserved + fats * fat_sectors;
+        let clusters = sectors.saturating_sub(data_start) / sectors_per_cluster;
+        if clusters == 0 || fat_sectors * (ENTRIES_PER_SECTOR as u64) < clusters + 2 {
+            return Err(not_fat32());
+        }
+
+        let mut checker = Checker {
+            partition,
+            repair,
+            sectors_per_cluster,
+            fats,
+            fat_start: reserved,
+            fat_sectors,
+            data_start,
+            clusters,
+            root_cluster: u32_at(&boot, 44),
+            fsinfo_sector: u16_at(&boot, 48) as u64,
+            fat: Vec::new(),
+            dirty: BTreeSet::new(),
+            owners: vec![0; clusters as usize + 2],
+            paths: Vec::new(),
+            report: Report {
+                problems: Vec::new(),
+                repaired: repair,
+                files: 0,
+                dirs: 0,
+                used: 0,
+                clusters,
+            },
+        };
+        checker.fat = checker.read_fat(0)?;
+        Ok(checker)
+    }
+
+    /// Returns the sectors of a FAT that map the clusters.
+    fn fat_used_sectors(&self) -> u64 {
+        (self.clusters + 2 + ENTRIES_PER_SECTOR as u64 - 1) / ENTRIES_PER_SECTOR as u64
+    }
+
+    /// Reads the copy of the FAT numbered `copy`, from 0, as far as the
+    /// clusters go.
+    fn read_fat(&mut self, copy: u64) -> io::Result<Vec<u32>> {
+        let start = self.fat_start + copy * self.fat_sectors;
+        let end = self.fat_used_sectors();
+        let mut fat = Vec::with_capacity(end as usize * ENTRIES_PER_SECTOR);
+        let mut buf = vec![0; READ_CHUNK as usize * SECTOR_SIZE];
+        let mut sector = 0;
+        while sector < end {
+            let count = min(end - sector, READ_CHUNK);
+            let buf = &mut buf[..count as usize * SECTOR_SIZE];
+            self.partition.read_blocks(start + sector, buf)?;
+            fat.extend(buf.chunks(4).map(|entry| u32_at(entry, 0)));
+            sector += count;
+        }
+        Ok(fat)
+    }
+
+    /// Writes the changed sectors of the FAT to every copy of it.
+    fn write_fat(&mut self) -> io::Result<()> {
+        if !self.repair {
+            return Ok(());
+        }
+
+        let dirty: Vec<u64> = self.dirty.iter().cloned().collect();
+        let mut buf = [0; SECTOR_SIZE];
+        for sector in dirty {
+            let entries = &self.fat[sector as usize * ENTRIES_PER_SECTOR..][..ENTRIES_PER_SECTOR];
+            for (i, &entry) in entries.iter().enumerate() {
+                put_u32(&mut buf, 4 * i, entry);
+            }
+            for copy in 0..self.fats {
+                let lba = self.fat_start + copy * self.fat_sectors + sector;
+                self.partition.write_blocks(lba, &buf)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the FAT entry of `cluster`, without its reserved bits.
+    fn next(&self, cluster: u32) -> u32 {
+        self.fat[cluster as usize] & ENTRY_MASK
+    }
+
+    /// Sets the FAT entry of `cluster` to `value`, if repairing.
+    fn set_next(&mut self, cluster: u32, value: u32) {
+        if self.repair {
+            let entry = &mut self.fat[cluster as usize];
+            *entry = (*entry & !ENTRY_MASK) | value;
+            self.dirty.insert(cluster as u64 / ENTRIES_PER_SECTOR as u64);
+        }
+    }
+
+    /// Returns whether `cluster` is on the volume.
+    fn in_range(&self, cluster: u32) -> bool {
+        cluster >= 2 && (cluster as u64) < self.clusters + 2
+    }
+
+    /// Returns the size of a cluster in bytes.
+    fn cluster_size(&self) -> u64 {
+        self.sectors_per_cluster * SECTOR_SIZE as u64
+    }
+
+    /// Compares the other copies of the FAT against the first. If repairing,
+    /// a copy that differs is overwritten with the first.
+    fn check_fats(&mut self) -> io::Result<()> {
+        for copy in 1..self.fats {
+            if self.read_fat(copy)? != self.fat {
+                self.report.problems.push(Problem::FatMismatch { copy: copy + 1 });
+                if self.repair {
+                    let sectors = self.fat_used_sectors();
+                    self.dirty.extend(0..sectors);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Follows the chain from `start`, claiming its clusters for `path`.
+    /// Returns the clusters claimed and how the chain ended.
+    fn walk(&mut self, start: u32, path: &str) -> (Vec<u32>, End) {
+        self.paths.push(path.to_string());
+        let id = self.paths.len();
+
+        let mut clusters = Vec::new();
+        let mut cluster = start;
+        loop {
+            if !self.in_range(cluster) {
+                return (clusters, End::Broken(cluster));
+            }
+            match self.owners[cluster as usize] {
+                0 => {}
+                owner => return (clusters, End::CrossLinked(cluster, owner)),
+            }
+            let next = self.next(cluster);
+            if next == 0 || next == BAD_CLUSTER {
+                return (clusters, End::Broken(cluster));
+            }
+
+            self.owners[cluster as usize] = id;
+            clusters.push(cluster);
+            if next >= END_OF_CHAIN_MIN {
+                return (clusters, End::Ok);
+            }
+            cluster = next;
+        }
+    }
+
+    /// Follows the chain from `start` for `path`, reporting how it is
+    /// broken, if it is, and cutting it short before the break if
+    /// repairing. Returns the clusters left on it.
+    fn chain(&mut self, start: u32, path: &str) -> Vec<u32> {
+        let (clusters, end) = self.walk(start, path);
+        let problem = match end {
+            End::Ok => return clusters,
+            End::Broken(cluster) => Problem::BrokenChain { path: path.to_string(), cluster },
+            End::CrossLinked(cluster, owner) => Problem::CrossLinked {
+                path: path.to_string(),
+                other: self.paths[owner - 1].clone(),
+                cluster,
+            },
+        };
+
+        self.report.problems.push(problem);
+        if let Some(&last) = clusters.last() {
+            self.set_next(last, END_OF_CHAIN);
+        }
+        clusters
+    }
+
+    /// Frees `clusters`, if repairing.
+    fn free(&mut self, clusters: &[u32]) {
+        if self.repair {
+            for &cluster in clusters {
+                self.set_next(cluster, 0);
+                self.owners[cluster as usize] = 0;
+            }
+        }
+    }
+
+    /// Checks every directory and file reached from the root.
+    fn check_tree(&mut self) -> io::Result<()> {
+        let root = self.root_cluster;
+        let clusters = self.chain(root, "/");
+        if clusters.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "root directory is unreadable"));
+        }
+
+        let mut pending = vec![Pending { path: String::new(), first: root, parent: 0, clusters }];
+        while let Some(dir) = pending.pop() {
+            self.report.dirs += 1;
+            self.check_dir(dir, &mut pending)?;
+        }
+        Ok(())
+    }
+
+    /// Checks the entries of `dir`, adding its subdirectories to `pending`,
+    /// and writes them back if any were repaired.
+    fn check_dir(&mut self, dir: Pending, pending: &mut Vec<Pending>) -> io::Result<()> {
+        let cluster_size = self.cluster_size() as usize;
+        let mut data = vec![0; dir.clusters.len() * cluster_size];
+        for (i, &cluster) in dir.clusters.iter().enumerate() {
+            let lba = self.cluster_sector(cluster);
+            self.partition.read_blocks(lba, &mut data[i * cluster_size..(i + 1) * cluster_size])?;
+        }
+
+        let is_root = dir.first == self.root_cluster;
+        let mut changed = false;
+        for (i, entry) in data.chunks_mut(ENTRY_SIZE).enumerate() {
+            match entry[0] {
+                END_OF_DIR => break,
+                DELETED => continue,
+                _ => {}
+            }
+            let attr = entry[11];
+            if attr & ATTR_LONG_NAME == ATTR_LONG_NAME || attr & ATTR_VOLUME_ID != 0 {
+                continue;
+            }
+
+            let name = short_name(&entry[..11]);
+            let first = (u16_at(entry, 20) as u32) << 16 | u16_at(entry, 26) as u32;
+            let dot = match (is_root, i) {
+                (false, 0) => Some((".", dir.first)),
+                (false, 1) => Some(("..", dir.parent)),
+                _ => None,
+            };
+            if let Some((dot, cluster)) = dot {
+                // Some systems point `..` at the root's cluster rather than 0.
+                let root = self.root_cluster;
+                let points_to_root = dot == ".." && cluster == 0 && first == root;
+                if name != dot {
+                    let path = dir_path(&dir.path);
+                    self.report.problems.push(Problem::MissingDotEntry { path, name: dot });
+                } else {
+                    if first != cluster && !points_to_root {
+                        let path = dir_path(&dir.path);
+                        self.report.problems.push(Problem::BadDotEntry { path, name: dot });
+                        changed |= self.set_first(entry, cluster);
+                    }
+                    continue;
+                }
+            }
+            if name == "." || name == ".." {
+                continue;
+            }
+
+            let path = format!("{}/{}", dir.path, name);
+            if !valid_name(&entry[..11]) {
+                self.report.problems.push(Problem::BadName { path: path.clone() });
+            }
+            if attr & ATTR_DIRECTORY != 0 {
+                let parent = if is_root { 0 } else { dir.first };
+                changed |= self.check_subdir(entry, path, first, parent, pending);
+            } else {
+                changed |= self.check_file(entry, path, first);
+            }
+        }
+
+        if changed && self.repair {
+            for (i, &cluster) in dir.clusters.iter().enumerate() {
+                let lba = self.cluster_sector(cluster);
+                self.partition.write_blocks(lba, &data[i * cluster_size..(i + 1) * cluster_size])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the entry of the directory at `path`, starting at cluster
+    /// `first`, in the directory starting at cluster `parent`, or 0 for the
+    /// root, and adds it to `pending`. Returns whether the entry was changed.
+    fn check_subdir(&mut self, entry: &mut [u8], path: String, first: u32, parent: u32,
+                    pending: &mut Vec<Pending>) -> bool {
+        let mut changed = false;
+        let size = u32_at(entry, 28);
+        if size != 0 {
+            self.report.problems.push(Problem::DirectorySize { path: path.clone(), size });
+            changed |= self.set_size(entry, 0);
+        }
+
+        let clusters = self.chain(first, &path);
+        if clusters.is_empty() {
+            // Nothing of the directory is left to check.
+            if self.repair {
+                entry[0] = DELETED;
+                changed = true;
+            }
+            return changed;
+        }
+
+        pending.push(Pending { path, first, parent, clusters });
+        changed
+    }
+
+    /// Checks the entry of the file at `path`, starting at cluster `first`.
+    /// Returns whether the entry was changed.
+    fn check_file(&mut self, entry: &mut [u8], path: String, first: u32) -> bool {
+        self.report.files += 1;
+        let size = u32_at(entry, 28);
+        let clusters = match first {
+            0 => Vec::new(),
+            first => self.chain(first, &path),
+        };
+
+        let cluster_size = self.cluster_size();
+        let needed = (size as u64 + cluster_size - 1) / cluster_size;
+        let have = clusters.len() as u64;
+        if needed == have {
+            return false;
+        }
+
+        self.report.problems.push(Problem::SizeMismatch { path, size, clusters: have });
+        if needed > have {
+            // The data past the chain is gone.
+            let size = (have * cluster_size) as u32;
+            let changed = self.set_size(entry, size);
+            return changed | (have == 0 && self.set_first(entry, 0));
+        }
+
+        self.free(&clusters[needed as usize..]);
+        match needed {
+            0 => self.set_first(entry, 0),
+            needed => {
+                self.set_next(clusters[needed as usize - 1], END_OF_CHAIN);
+                false
+            }
+        }
+    }
+
+    /// Sets the first cluster of `entry`, if repairing. Returns whether it
+    /// was changed.
+    fn set_first(&mut self, entry: &mut [u8], cluster: u32) -> bool {
+        if self.repair {
+            put_u16(entry, 20, (cluster >> 16) as u16);
+            put_u16(entry, 26, cluster as u16);
+        }
+        self.repair
+    }
+
+    /// Sets the size in `entry`, if repairing. Returns whether it was
+    /// changed.
+    fn set_size(&mut self, entry: &mut [u8], size: u32) -> bool {
+        if self.repair {
+            put_u32(entry, 28, size);
+        }
+        self.repair
+    }
+
+    /// Returns the first sector of `cluster`.
+    fn cluster_sector(&self, cluster: u32) -> u64 {
+        self.data_start + (cluster as u64 - 2) * self.sectors_per_cluster
+    }
+
+    /// Reports the clusters marked in use that no chain claimed, and frees
+    /// them if repairing.
+    fn check_lost(&mut self) {
+        let lost: Vec<u32> = (2..self.clusters as u32 + 2)
+            .filter(|&cluster| {
+                let next = self.next(cluster);
+                next != 0 && next != BAD_CLUSTER && self.owners[cluster as usize] == 0
+            })
+            .collect();
+
+        if !lost.is_empty() {
+            self.report.problems.push(Problem::LostClusters { count: lost.len() as u64 });
+            self.free(&lost);
+        }
+    }
+
+    /// Returns whether `cluster` is free once lost clusters are freed.
+    fn is_free(&self, cluster: u32) -> bool {
+        self.owners[cluster as usize] == 0 && self.next(cluster) != BAD_CLUSTER
+    }
+
+    /// Compares the FSInfo free count, if it is known, against the FAT, and
+    /// corrects it if repairing.
+    fn check_free_count(&mut self) -> io::Result<()> {
+        let actual = (2..self.clusters as u32 + 2).filter(|&c| self.is_free(c)).count() as u64;
+        let mut fsinfo = [0; SECTOR_SIZE];
+        self.partition.read_blocks(self.fsinfo_sector, &mut fsinfo)?;
+        if u32_at(&fsinfo, 0) != 0x4161_5252 || u32_at(&fsinfo, 484) != 0x6141_7272 {
+            return Ok(());
+        }
+
+        let recorded = u32_at(&fsinfo, 488);
+        if recorded != FREE_UNKNOWN && recorded as u64 != actual {
+            self.report.problems.push(Problem::FreeCount { recorded, actual });
+            if self.repair {
+                put_u32(&mut fsinfo, 488, actual as u32);
+                self.partition.write_blocks(self.fsinfo_sector, &fsinfo)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the report, counting the clusters in use.
+    fn finish(mut self) -> Report {
+        self.report.used = (2..self.clusters as u32 + 2)
+            .filter(|&cluster| self.owners[cluster as usize] != 0)
+            .count() as u64;
+        self.report
+    }
+}
+
+/// Returns the path of a directory in the report: `/` for the root.
+fn dir_path(path: &str) -> String {
+    if path.is_empty() { "/".to_string() } else { path.to_string() }
+}
+
+/// Returns the 8.3 name in the 11 bytes of `raw` as `NAME.EXT`.
+fn short_name(raw: &[u8]) -> String {
+    let mut first = raw[0];
+    if first == 0x05 {
+        // A name starting with 0xE5 is stored as 0x05.
+        first = DELETED;
+    }
+
+    let text = |bytes: &[u8]| -> String {
+        bytes.iter().map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '?' })
+            .collect::<String>().trim_right().to_string()
+    };
+    let mut base = vec![first];
+    base.extend_from_slice(&raw[1..8]);
+    let (base, ext) = (text(&base), text(&raw[8..11]));
+    if ext.is_empty() { base } else { format!("{}.{}", base, ext) }
+}
+
+/// Returns whether the 11 bytes of `raw` are a valid 8.3 name: upper case,
+/// without control characters or those FAT reserves, and not starting with
+/// a space.
+fn valid_name(raw: &[u8]) -> bool {
+    raw[0] != b' ' && raw.iter().enumerate().all(|(i, &b)| match b {
+        0x05 if i == 0 => true,
+        0x00...0x1F | b'a'...b'z' => false,
+        b'"' | b'*' | b'+' | b',' | b'.' | b'/' | b':' | b';' | b'<' | b'=' | b'>' | b'?'
+            | b'[' | b'\\' | b']' | b'|' => false,
+        _ => true,
+    })
+}
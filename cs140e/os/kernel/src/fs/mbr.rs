@@ -0,0 +1,134 @@
+//! Master boot record (MBR) partition tables, and partitions as block
+//! devices of their own.
+
+use std::io;
+
+use bytes::le::u32_at;
+use fs::block::{self, BlockDevice};
+
+/// The number of entries in the partition table.
+pub const PARTITIONS: usize = 4;
+
+/// The partition type of FAT32 with CHS addressing.
+pub const TYPE_FAT32_CHS: u8 = 0x0B;
+
+/// The partition type of FAT32 with LBA addressing.
+pub const TYPE_FAT32_LBA: u8 = 0x0C;
+
+/// The partition type shared by exFAT and NTFS.
+pub const TYPE_EXFAT: u8 = 0x07;
+
+/// The partition type of Linux file systems, such as ext2.
+pub const TYPE_LINUX: u8 = 0x83;
+
+/// The offset of the partition table in the MBR.
+const TABLE_OFFSET: usize = 446;
+
+/// The size of an entry in the partition table.
+const ENTRY_SIZE: usize = 16;
+
+/// An entry in the partition table.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct PartitionEntry {
+    /// The partition type, or 0 if the entry is unused.
+    pub kind: u8,
+    /// The first sector.
+    pub start: u64,
+    /// The number of sectors.
+    pub sectors: u64,
+}
+
+impl PartitionEntry {
+    /// Returns whether the entry describes a partition.
+    pub fn is_used(&self) -> bool {
+        self.kind != 0 && self.sectors != 0
+    }
+
+    /// Returns whether the partition type is FAT32.
+    pub fn is_fat32(&self) -> bool {
+        self.kind == TYPE_FAT32_CHS || self.kind == TYPE_FAT32_LBA
+    }
+}
+
+/// Reads the partition table from the MBR in the first sector of `device`.
+/// Fails with `InvalidData` if the sector isn't an MBR.
+pub fn read<D: BlockDevice>(device: &mut D) -> io::Result<[PartitionEntry; PARTITIONS]> {
+    let mut sector = vec![0; device.block_size()];
+    device.read_blocks(0, &mut sector)?;
+    if sector.len() < 512 || sector[510..512] != [0x55, 0xAA] {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "no master boot record"));
+    }
+
+    let mut entries = [PartitionEntry::default(); PARTITIONS];
+    for (i, entry) in entries.iter_mut().enumerate() {
+        let raw = &sector[TABLE_OFFSET + i * ENTRY_SIZE..TABLE_OFFSET + (i + 1) * ENTRY_SIZE];
+        *entry = PartitionEntry {
+            kind: raw[4],
+            start: u32_at(raw, 8) as u64,
+            sectors: u32_at(raw, 12) as u64,
+        };
+    }
+    Ok(entries)
+}
+
+/// A partition of a device, addressed from its first sector.
+pub struct Partition<D: BlockDevice> {
+    device: D,
+    start: u64,
+    sectors: u64,
+}
+
+impl<D: BlockDevice> Partition<D> {
+    /// Opens partition `number`, 1 through `PARTITIONS`, of `device`. Fails
+    /// with `NotFound` if the table has no such partition.
+    pub fn open(mut device: D, number: usize) -> io::Result<Partition<D>> {
+        let entry = match number {
+            1...PARTITIONS => read(&mut device)?[number - 1],
+            _ => return Err(io::Error::new(io::ErrorKind::NotFound, "no such partition")),
+        };
+
+        if !entry.is_used() {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no such partition"));
+        }
+        Ok(Partition { device, start: entry.start, sectors: entry.sectors })
+    }
+
+    /// Returns the sector of the device the partition starts at.
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    /// Returns the number of sectors in the partition.
+    pub fn sectors(&self) -> u64 {
+        self.sectors
+    }
+
+    /// Returns the device sector of the `count` sectors from `lba`, or an
+    /// `InvalidInput` error if they don't all fit in the partition.
+    fn offset(&self, lba: u64, count: usize) -> io::Result<u64> {
+        match lba.checked_add(count as u64) {
+            Some(end) if end <= self.sectors => Ok(self.start + lba),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "past the end of the partition")),
+        }
+    }
+}
+
+impl<D: BlockDevice> BlockDevice for Partition<D> {
+    fn block_size(&self) -> usize {
+        self.device.block_size()
+    }
+
+    fn read_only(&self) -> bool {
+        self.device.read_only()
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> io::Result<()> {
+        let lba = self.offset(lba, block::block_count(buf.len(), self.block_size())?)?;
+        self.device.read_blocks(lba, buf)
+    }
+
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> io::Result<()> {
+        let lba = self.offset(lba, block::block_count(buf.len(), self.block_size())?)?;
+        self.device.write_blocks(lba, buf)
+    }
+}
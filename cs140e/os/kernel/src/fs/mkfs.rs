@@ -0,0 +1,212 @@
+//! Formatting: writing a fresh, empty file system onto a partition.
+//!
+//! FAT32 is laid out as Microsoft's specification and `mkfs.fat` do: the
+//! reserved sectors holding the boot sector, FSInfo, and their backups; two
+//! copies of the FAT; then the clusters, the first of them the root
+//! directory.
+
+use std::cmp::min;
+use std::io;
+
+use bytes::le::{put_u16, put_u32};
+use clock;
+use fs::block::BlockDevice;
+use fs::mbr::Partition;
+
+/// The sector size FAT32 is formatted for.
+const SECTOR_SIZE: usize = 512;
+
+/// The sectors before the first FAT.
+const RESERVED_SECTORS: u64 = 32;
+
+/// The copies of the FAT.
+const FATS: u64 = 2;
+
+/// The sector of the FSInfo structure.
+const FSINFO_SECTOR: u64 = 1;
+
+/// The sector of the backup boot sector, followed by the backup FSInfo.
+const BACKUP_BOOT_SECTOR: u64 = 6;
+
+/// The cluster the root directory starts at.
+const ROOT_CLUSTER: u32 = 2;
+
+/// The fewest clusters a FAT32 file system can have; any fewer and it would
+/// be taken for FAT16.
+const MIN_CLUSTERS: u64 = 65_525;
+
+/// The most clusters a FAT32 file system can have.
+const MAX_CLUSTERS: u64 = 0x0FFF_FFF5;
+
+/// The media descriptor of a fixed disk.
+const MEDIA: u8 = 0xF8;
+
+/// The FAT entry that ends a cluster chain.
+const END_OF_CHAIN: u32 = 0x0FFF_FFFF;
+
+/// The directory entry attribute of the volume label.
+const ATTR_VOLUME_ID: u8 = 0x08;
+
+/// The sectors zeroed per write.
+const ZERO_CHUNK: u64 = 64;
+
+/// The longest volume label.
+pub const MAX_LABEL: usize = 11;
+
+/// The label in the boot sector of a volume formatted without one.
+pub const NO_LABEL: &str = "NO NAME";
+
+/// Returns the sectors per cluster for a partition of `sectors` sectors,
+/// from the table in Microsoft's FAT specification.
+fn sectors_per_cluster(sectors: u64) -> u64 {
+    match sectors {
+        0...532_480 => 1,
+        532_481...16_777_216 => 8,
+        16_777_217...33_554_432 => 16,
+        33_554_433...67_108_864 => 32,
+        _ => 64,
+    }
+}
+
+/// The shape of a FAT32 file system.
+struct Layout {
+    sectors: u64,
+    sectors_per_cluster: u64,
+    /// The sectors in one FAT.
+    fat_sectors: u64,
+    clusters: u64,
+}
+
+impl Layout {
+    /// Returns the layout of a file system filling `sectors` sectors, or an
+    /// `InvalidInput` error if FAT32 can't.
+    fn new(sectors: u64) -> io::Result<Layout> {
+        let sectors_per_cluster = sectors_per_cluster(sectors);
+
+        // Each FAT sector maps 128 clusters; the specification's estimate
+        // errs on the side of a FAT a little larger than it needs to be.
+        let data = sectors.saturating_sub(RESERVED_SECTORS);
+        let per_fat_sector = (256 * sectors_per_cluster + FATS) / 2;
+        let fat_sectors = (data + per_fat_sector - 1) / per_fat_sector;
+
+        let clusters = data.saturating_sub(FATS * fat_sectors) / sectors_per_cluster;
+        if clusters < MIN_CLUSTERS {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "partition too small for FAT32"));
+        }
+        if clusters > MAX_CLUSTERS || sectors > u32::max_value() as u64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "partition too large for FAT32"));
+        }
+
+        Ok(Layout { sectors, sectors_per_cluster, fat_sectors, clusters })
+    }
+
+    /// Returns the sector of the first cluster, the root directory.
+    fn data_start(&self) -> u64 {
+        RESERVED_SECTORS + FATS * self.fat_sectors
+    }
+}
+
+/// Formats `partition` as an empty FAT32 file system labeled `label`, in
+/// upper case, or unlabeled if `label` is empty. Fails with `InvalidInput`
+/// if the label is longer than `MAX_LABEL` bytes or FAT32 can't fill the
+/// partition, and with `PermissionDenied` if the device is read only.
+///
+/// The boot sector is written last, so a format that fails partway doesn't
+/// leave something that looks like a file system.
+pub fn fat32<D: BlockDevice>(partition: &mut Partition<D>, label: &str) -> io::Result<()> {
+    if partition.block_size() != SECTOR_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "sectors must be 512 bytes"));
+    }
+    if label.len() > MAX_LABEL || !label.is_ascii() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "label longer than 11 ASCII characters"));
+    }
+    if partition.read_only() {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, "device is read only"));
+    }
+
+    let layout = Layout::new(partition.sectors())?;
+    let label = if label.is_empty() { NO_LABEL.to_string() } else { label.to_ascii_uppercase() };
+    let mut name = [b' '; MAX_LABEL];
+    name[..label.len()].copy_from_slice(label.as_bytes());
+
+    // Everything before the root directory starts out zero, and the root
+    // directory is empty.
+    let root_end = layout.data_start() + layout.sectors_per_cluster;
+    zero(partition, 0, root_end)?;
+
+    let mut fat = [0; SECTOR_SIZE];
+    put_u32(&mut fat, 0, 0x0FFF_FF00 | MEDIA as u32);
+    put_u32(&mut fat, 4, END_OF_CHAIN);
+    put_u32(&mut fat, 4 * ROOT_CLUSTER as usize, END_OF_CHAIN);
+    for i in 0..FATS {
+        partition.write_blocks(RESERVED_SECTORS + i * layout.fat_sectors, &fat)?;
+    }
+
+    // An unlabeled volume has its label only in the boot sector.
+    if label != NO_LABEL {
+        let mut root = [0; SECTOR_SIZE];
+        root[..MAX_LABEL].copy_from_slice(&name);
+        root[11] = ATTR_VOLUME_ID;
+        partition.write_blocks(layout.data_start(), &root)?;
+    }
+
+    let fsinfo = fsinfo(&layout);
+    partition.write_blocks(FSINFO_SECTOR, &fsinfo)?;
+    partition.write_blocks(BACKUP_BOOT_SECTOR + FSINFO_SECTOR, &fsinfo)?;
+
+    let boot = boot_sector(&layout, partition.start(), &name);
+    partition.write_blocks(BACKUP_BOOT_SECTOR, &boot)?;
+    partition.write_blocks(0, &boot)
+}
+
+/// Zeroes the sectors from `start` to `end`.
+fn zero<D: BlockDevice>(partition: &mut Partition<D>, start: u64, end: u64) -> io::Result<()> {
+    let zeros = vec![0; ZERO_CHUNK as usize * SECTOR_SIZE];
+    let mut lba = start;
+    while lba < end {
+        let count = min(end - lba, ZERO_CHUNK);
+        partition.write_blocks(lba, &zeros[..count as usize * SECTOR_SIZE])?;
+        lba += count;
+    }
+    Ok(())
+}
+
+/// Returns the boot sector, with the BIOS parameter block, of a file system
+/// laid out as `layout` on a partition starting at sector `hidden`.
+fn boot_sector(layout: &Layout, hidden: u64, label: &[u8; MAX_LABEL]) -> [u8; SECTOR_SIZE] {
+    let mut boot = [0; SECTOR_SIZE];
+    boot[..3].copy_from_slice(&[0xEB, 0x58, 0x90]);
+    boot[3..11].copy_from_slice(b"MSWIN4.1");
+    put_u16(&mut boot, 11, SECTOR_SIZE as u16);
+    boot[13] = layout.sectors_per_cluster as u8;
+    put_u16(&mut boot, 14, RESERVED_SECTORS as u16);
+    boot[16] = FATS as u8;
+    boot[21] = MEDIA;
+    put_u16(&mut boot, 24, 63);
+    put_u16(&mut boot, 26, 255);
+    put_u32(&mut boot, 28, hidden as u32);
+    put_u32(&mut boot, 32, layout.sectors as u32);
+    put_u32(&mut boot, 36, layout.fat_sectors as u32);
+    put_u32(&mut boot, 44, ROOT_CLUSTER);
+    put_u16(&mut boot, 48, FSINFO_SECTOR as u16);
+    put_u16(&mut boot, 50, BACKUP_BOOT_SECTOR as u16);
+    boot[64] = 0x80;
+    boot[66] = 0x29;
+    put_u32(&mut boot, 67, clock::now_us() as u32);
+    boot[71..82].copy_from_slice(label);
+    boot[82..90].copy_from_slice(b"FAT32   ");
+    boot[510..512].copy_from_slice(&[0x55, 0xAA]);
+    boot
+}
+
+/// Returns the FSInfo sector of a fresh file system laid out as `layout`:
+/// every cluster but the root directory's is free.
+fn fsinfo(layout: &Layout) -> [u8; SECTOR_SIZE] {
+    let mut fsinfo = [0; SECTOR_SIZE];
+    put_u32(&mut fsinfo, 0, 0x4161_5252);
+    put_u32(&mut fsinfo, 484, 0x6141_7272);
+    put_u32(&mut fsinfo, 488, (layout.clusters - 1) as u32);
+    put_u32(&mut fsinfo, 492, ROOT_CLUSTER + 1);
+    put_u32(&mut fsinfo, 508, 0xAA55_0000);
+    fsinfo
+}
@@ -1,33 +1,337 @@
+pub mod block;
+pub mod cache;
+pub mod dev;
+pub mod exfat;
+pub mod ext2;
+pub mod fsck;
+pub mod mbr;
+pub mod mkfs;
+pub mod proc;
+pub mod tmp;
+pub mod worker;
 pub mod sd;
+pub mod vfs;
+mod fat;
 
-use std::io;
-use std::path::Path;
+#[cfg(test)]
+mod tests;
 
-use fat32::vfat::{self, Shared, VFat};
-pub use fat32::traits;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
+use console::{log_info, log_warn};
 use mutex::Mutex;
+use scheduler;
+use self::cache::Cache;
+use self::dev::DevFs;
+use self::exfat::ExFat;
+use self::ext2::Ext2;
+use self::mbr::{Partition, PartitionEntry};
+use self::proc::ProcFs;
+use self::tmp::TmpFs;
 use self::sd::Sd;
+use self::vfs::{Dir, Entry, File, Kind, Vfs};
 
-pub struct FileSystem(Mutex<Option<Shared<VFat>>>);
+pub use self::fat::Fat32;
+
+/// The kernel's view of every mounted file system, as one tree of paths.
+pub struct FileSystem {
+    vfs: Mutex<Option<Vfs>>,
+    /// The SD card, through its cache.
+    disk: Mutex<Option<Cache>>,
+}
 
 impl FileSystem {
     /// Returns an uninitialized `FileSystem`.
     ///
-    /// The file system must be initialized by calling `initialize()` before the
-    /// first memory allocation. Failure to do will result in panics.
+    /// The file system must be initialized by calling `initialize()` before
+    /// it is used. Failure to do will result in panics.
     pub const fn uninitialized() -> Self {
-        FileSystem(Mutex::new(None))
+        FileSystem { vfs: Mutex::new(None), disk: Mutex::new(None) }
     }
 
-    /// Initializes the file system.
+    /// Initializes the file system, mounting the FAT32 file system on the
+    /// first FAT32 partition of the SD card at `/`, the device file system at
+    /// `/dev`, the process file system at `/proc`, an empty temporary file
+    /// system at `/tmp`, and any exFAT or ext2 partition `N` of the SD card
+    /// at `/mnt/sdN`. The SD card is read through a cache of `cache_blocks`
+    /// blocks. Mounts of partition `N` are listed as being on `sdN`.
     ///
     /// # Panics
     ///
     /// Panics if the underlying disk or file sytem failed to initialize.
-    pub fn initialize(&self) {
-        unimplemented!("FileSystem::initialize()")
+    pub fn initialize(&self, cache_blocks: usize) {
+        let sd = Sd::new().expect("failed to initialize the SD card");
+        let disk = Cache::new(sd, cache_blocks);
+        let fat = Fat32::mount(disk.clone()).expect("failed to mount the FAT32 file system");
+        let root = mbr::read(&mut disk.clone()).ok()
+            .and_then(|partitions| partitions.iter().position(|entry| entry.is_fat32()))
+            .map_or("sd".to_string(), |i| format!("sd{}", i + 1));
+        let mut vfs = Vfs::new();
+        vfs.mount(Path::new("/"), "fat32", &root, Arc::new(fat)).unwrap();
+        vfs.mount(Path::new("/dev"), "devfs", "devfs", Arc::new(DevFs)).unwrap();
+        vfs.mount(Path::new("/proc"), "procfs", "procfs", Arc::new(ProcFs)).unwrap();
+        vfs.mount(Path::new("/tmp"), "tmpfs", "tmpfs", Arc::new(TmpFs::new())).unwrap();
+        mount_partitions(&mut vfs, &disk);
+        *self.vfs.lock() = Some(vfs);
+        *self.disk.lock() = Some(disk);
+    }
+
+    /// Runs `f` with the mount table locked.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file system has not been initialized.
+    fn with_vfs<R, F: FnOnce(&mut Vfs) -> R>(&self, f: F) -> R {
+        f(self.vfs.lock().as_mut().expect("file system not initialized"))
+    }
+
+    /// Returns the SD card, through the cache the file systems on it use.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the file system has not been initialized.
+    fn disk(&self) -> Cache {
+        self.disk.lock().clone().expect("file system not initialized")
+    }
+
+    /// Returns the SD card's partition table.
+    pub fn partitions(&self) -> io::Result<[PartitionEntry; mbr::PARTITIONS]> {
+        mbr::read(&mut self.disk())
+    }
+
+    /// Formats partition `number` of the SD card, 1 through
+    /// `mbr::PARTITIONS`, as an empty FAT32 file system labeled `label`, and
+    /// syncs it to the card. Fails with `Other` if it is mounted. See
+    /// `mkfs::fat32()`.
+    pub fn format_fat32(&self, number: usize, label: &str) -> io::Result<()> {
+        if self.is_mounted(number) {
+            return Err(io::Error::new(io::ErrorKind::Other, "partition is mounted"));
+        }
+
+        let disk = self.disk();
+        mkfs::fat32(&mut Partition::open(disk.clone(), number)?, label)?;
+        disk.sync()
+    }
+
+    /// Checks the FAT32 file system on partition `number` of the SD card,
+    /// 1 through `mbr::PARTITIONS`. If `repair` is set, repairs it and syncs
+    /// it to the card, failing with `Other` if it is mounted; checking a
+    /// mounted partition is safe, since its writes go through the same
+    /// cache. See `fsck::fat32()`.
+    pub fn check_fat32(&self, number: usize, repair: bool) -> io::Result<fsck::Report> {
+        if repair && self.is_mounted(number) {
+            return Err(io::Error::new(io::ErrorKind::Other, "partition is mounted"));
+        }
+
+        let disk = self.disk();
+        let report = fsck::fat32(&mut Partition::open(disk.clone(), number)?, repair)?;
+        if repair {
+            disk.sync()?;
+        }
+        Ok(report)
+    }
+
+    /// Returns whether partition `number` of the SD card is mounted.
+    fn is_mounted(&self, number: usize) -> bool {
+        let source = format!("sd{}", number);
+        self.with_vfs(|v| v.mounts().iter().any(|m| m.source == source))
+    }
+
+    /// Mounts `fs`, a file system of type `kind` on `source`, at `point`.
+    /// See `Vfs::mount()`.
+    pub fn mount<P: AsRef<Path>>(&self, point: P, kind: &'static str, source: &str,
+                                 fs: Arc<vfs::FileSystem>) -> io::Result<()>
+    {
+        self.with_vfs(|v| v.mount(point.as_ref(), kind, source, fs))
+    }
+
+    /// Mounts the file system on partition `number` of the SD card, 1
+    /// through `mbr::PARTITIONS`, at `point`, and returns its type: FAT32,
+    /// exFAT, or ext2, by the partition type. Fails with `AlreadyExists` if
+    /// the partition is mounted, and with `InvalidData` if it holds none of
+    /// them.
+    pub fn mount_partition<P: AsRef<Path>>(&self, number: usize, point: P)
+        -> io::Result<&'static str>
+    {
+        if self.is_mounted(number) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "partition already mounted"));
+        }
+
+        let (kind, fs) = open_partition(&self.disk(), number)?;
+        self.mount(point, kind, &format!("sd{}", number), fs)?;
+        Ok(kind)
+    }
+
+    /// Unmounts the file system mounted at `point` and writes the changes
+    /// it buffered to its device. Fails with `vfs::busy()` if a process's
+    /// current directory is on it; see `Vfs::unmount()` for the other
+    /// failures. The file system stays unmounted if writing fails, but its
+    /// blocks stay dirty in the cache, for the flusher to retry.
+    pub fn unmount<P: AsRef<Path>>(&self, point: P) -> io::Result<()> {
+        let point = vfs::canonicalize(Path::new("/"), point.as_ref());
+        let mut busy = false;
+        scheduler::for_each(|process| busy |= process.cwd.starts_with(&point));
+        if busy {
+            return Err(vfs::busy());
+        }
+
+        // The file system is synced and dropped here, outside the lock.
+        let mount = self.with_vfs(|v| v.unmount(&point))?;
+        mount.fs.sync()
+    }
+
+    /// Returns the file system serving the absolute `path`. It can't be
+    /// unmounted while the handle is held.
+    pub fn file_system<P: AsRef<Path>>(&self, path: P) -> io::Result<Arc<vfs::FileSystem>> {
+        self.resolve(path.as_ref()).map(|(fs, _)| fs)
     }
+
+    /// Calls `f` with the mount point, type, and source of every mount.
+    pub fn for_each_mount<F: FnMut(&Path, &str, &str)>(&self, mut f: F) {
+        let mounts: Vec<_> = self.with_vfs(|v| {
+            v.mounts().iter().map(|m| (m.point.clone(), m.kind, m.source.clone())).collect()
+        });
+
+        for &(ref point, kind, ref source) in mounts.iter() {
+            f(point, kind, source);
+        }
+    }
+
+    /// Writes the changes buffered in memory by every mounted file system to
+    /// its device. Returns the first error, after trying them all.
+    pub fn sync(&self) -> io::Result<()> {
+        let mounts: Vec<_> = self.with_vfs(|v| v.mounts().iter().map(|m| m.fs.clone()).collect());
+
+        let mut result = Ok(());
+        for fs in mounts {
+            if let Err(e) = fs.sync() {
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
+        result
+    }
+
+    /// Returns the file system serving `path` and the path within it. The
+    /// mount table is unlocked before the file system is used.
+    fn resolve(&self, path: &Path) -> io::Result<(Arc<vfs::FileSystem>, PathBuf)> {
+        self.with_vfs(|v| v.resolve(path))
+    }
+
+    /// Returns the entry at the absolute `path`. Fails with
+    /// `vfs::not_a_directory()` if a file stands where a directory in the
+    /// path should, and with `NotFound` if anything else is missing.
+    pub fn open<P: AsRef<Path>>(&self, path: P) -> io::Result<Box<Entry>> {
+        let (fs, path) = self.resolve(path.as_ref())?;
+        fs.open(&path).map_err(|e| lookup_error(&*fs, &path, e))
+    }
+
+    /// Opens the file at the absolute `path`.
+    pub fn open_file<P: AsRef<Path>>(&self, path: P) -> io::Result<Box<File>> {
+        self.open(path)?.into_file()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "is a directory"))
+    }
+
+    /// Opens the directory at the absolute `path`.
+    pub fn open_dir<P: AsRef<Path>>(&self, path: P) -> io::Result<Box<Dir>> {
+        self.open(path)?.into_dir()
+            .ok_or_else(vfs::not_a_directory)
+    }
+
+    /// Creates an empty file at the absolute `path`.
+    pub fn create_file<P: AsRef<Path>>(&self, path: P) -> io::Result<Box<File>> {
+        let (fs, path) = self.resolve(path.as_ref())?;
+        fs.create_file(&path)
+    }
+
+    /// Creates a directory at the absolute `path`, and any missing parents
+    /// if `parents` is set.
+    pub fn create_dir<P: AsRef<Path>>(&self, path: P, parents: bool) -> io::Result<Box<Dir>> {
+        let (fs, path) = self.resolve(path.as_ref())?;
+        fs.create_dir(&path, parents)
+    }
+
+    /// Moves the entry at `from` to `to`, which must be on the same file
+    /// system.
+    pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(&self, from: P, to: Q) -> io::Result<()> {
+        let (from_fs, from) = self.resolve(from.as_ref())?;
+        let (to_fs, to) = self.resolve(to.as_ref())?;
+        if !Arc::ptr_eq(&from_fs, &to_fs) {
+            return Err(io::Error::new(io::ErrorKind::Other, "cross-device rename"));
+        }
+        from_fs.rename(&from, &to)
+    }
+
+    /// Removes the entry at the absolute `path`, with its contents if
+    /// `children` is set.
+    pub fn remove<P: AsRef<Path>>(&self, path: P, children: bool) -> io::Result<()> {
+        let (fs, path) = self.resolve(path.as_ref())?;
+        fs.remove(&path, children)
+    }
+}
+
+/// Mounts each exFAT or ext2 partition `N` of `disk` at `/mnt/sdN`.
+/// Partitions of those types holding other file systems, such as NTFS, are
+/// skipped.
+fn mount_partitions(vfs: &mut Vfs, disk: &Cache) {
+    let partitions = match mbr::read(&mut disk.clone()) {
+        Ok(partitions) => partitions,
+        Err(_) => return,
+    };
+
+    for (i, entry) in partitions.iter().enumerate() {
+        let kind = match entry.kind {
+            mbr::TYPE_EXFAT => "exfat",
+            mbr::TYPE_LINUX => "ext2",
+            _ => continue,
+        };
+
+        let (source, point) = (format!("sd{}", i + 1), format!("/mnt/sd{}", i + 1));
+        let result = open_partition(disk, i + 1)
+            .and_then(|(kind, fs)| vfs.mount(Path::new(&point), kind, &source, fs));
+        match result {
+            Ok(()) => log_info!("fs: mounted {} partition {} at {}", kind, i + 1, point),
+            Err(ref e) if e.kind() == io::ErrorKind::InvalidData => {}
+            Err(e) => log_warn!("fs: can't mount {} partition {}: {}", kind, i + 1, e),
+        }
+    }
+}
+
+/// Mounts the file system on partition `number` of `disk`, choosing FAT32,
+/// exFAT, or ext2 by the partition type, and returns its type and it. Fails
+/// with `InvalidData` if the partition type is none of theirs, or the
+/// partition doesn't hold the file system its type says.
+fn open_partition(disk: &Cache, number: usize) -> io::Result<(&'static str, Arc<vfs::FileSystem>)> {
+    let partition = Partition::open(disk.clone(), number)?;
+    let entry = mbr::read(&mut disk.clone())?[number - 1];
+    let mounted: (&'static str, Arc<vfs::FileSystem>) = match entry.kind {
+        _ if entry.is_fat32() => ("fat32", Arc::new(Fat32::mount_partition(disk.clone(), number)?)),
+        mbr::TYPE_EXFAT => ("exfat", Arc::new(ExFat::mount(partition)?)),
+        mbr::TYPE_LINUX => ("ext2", Arc::new(Ext2::mount(partition)?)),
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "unknown partition type")),
+    };
+    Ok(mounted)
 }
 
-// FIXME: Implement `fat32::traits::FileSystem` for a useful type.
+/// Returns the error for failing to open `path` on `fs` with `error`: if it
+/// is `NotFound`, and the nearest ancestor of `path` that exists is a file,
+/// `vfs::not_a_directory()` instead. File systems report either error for a
+/// file in the middle of a path, so this makes them agree.
+fn lookup_error(fs: &vfs::FileSystem, path: &Path, error: io::Error) -> io::Error {
+    if error.kind() != io::ErrorKind::NotFound {
+        return error;
+    }
+
+    let mut ancestor = path.parent();
+    while let Some(dir) = ancestor {
+        match fs.open(dir) {
+            Ok(ref entry) if entry.metadata().kind == Kind::File => return vfs::not_a_directory(),
+            Ok(_) => return error,
+            Err(_) => ancestor = dir.parent(),
+        }
+    }
+    error
+}
@@ -0,0 +1,262 @@
+//! The process file system, mounted at `/proc`: kernel statistics as text
+//! files, generated when they are opened.
+//!
+//! * `meminfo`: the allocator's statistics.
+//! * `slabinfo`: the statistics of each slab cache.
+//! * `uptime`: the seconds since boot.
+//! * `interrupts`: the count of each interrupt.
+//! * `ID/status`: the state and accounting of process `ID`.
+//!
+//! Every file is read only and reports a size of 0, since its contents
+//! aren't known until it is opened.
+
+use std::cmp::min;
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path};
+
+use allocator::{self, SlabStats};
+use clock;
+use fs::vfs::{self, Kind, Metadata};
+use irq;
+use process::{files, Id};
+use scheduler;
+use ALLOCATOR;
+
+/// A file of statistics at the top of `/proc`: its name and what writes
+/// its contents.
+struct Stat {
+    name: &'static str,
+    generate: fn() -> String,
+}
+
+/// Every file at the top of `/proc`, in the order they are listed.
+static STATS: [Stat; 4] = [
+    Stat { name: "meminfo", generate: meminfo },
+    Stat { name: "slabinfo", generate: slabinfo },
+    Stat { name: "uptime", generate: uptime },
+    Stat { name: "interrupts", generate: interrupts },
+];
+
+/// A file or directory in `/proc`.
+#[derive(Copy, Clone)]
+enum Node {
+    Root,
+    Stat(&'static Stat),
+    /// The directory of a process.
+    Process(Id),
+    /// A process's `status` file.
+    Status(Id),
+}
+
+/// The process file system.
+pub struct ProcFs;
+
+impl vfs::FileSystem for ProcFs {
+    fn open(&self, path: &Path) -> io::Result<Box<vfs::Entry>> {
+        let mut names = path.components().filter_map(|component| match component {
+            Component::Normal(name) => name.to_str(),
+            _ => None,
+        });
+
+        let node = match (names.next(), names.next(), names.next()) {
+            (None, _, _) => Some(Node::Root),
+            (Some(name), None, _) => STATS.iter().find(|stat| stat.name == name)
+                .map(Node::Stat)
+                .or_else(|| process(name).map(Node::Process)),
+            (Some(name), Some("status"), None) => process(name).map(Node::Status),
+            _ => None,
+        };
+
+        node.map(Node::entry)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no such file"))
+    }
+}
+
+/// Returns the ID `name` if it names a process that exists.
+fn process(name: &str) -> Option<Id> {
+    name.parse().ok().and_then(|id| scheduler::info(id).map(|_| id))
+}
+
+impl Node {
+    /// Returns the node as an entry, with its name.
+    fn entry(self) -> Box<vfs::Entry> {
+        let name = match self {
+            Node::Root => String::new(),
+            Node::Stat(stat) => stat.name.to_string(),
+            Node::Process(id) => id.to_string(),
+            Node::Status(_) => "status".to_string(),
+        };
+        Box::new(NodeEntry { name, node: self })
+    }
+}
+
+impl vfs::Dir for Node {
+    fn entries(&self) -> io::Result<Vec<Box<vfs::Entry>>> {
+        let mut nodes = Vec::new();
+        match *self {
+            Node::Root => {
+                nodes.extend(STATS.iter().map(Node::Stat));
+                scheduler::for_each(|p| nodes.push(Node::Process(p.id)));
+            }
+            Node::Process(id) => nodes.push(Node::Status(id)),
+            Node::Stat(_) | Node::Status(_) => {}
+        }
+        Ok(nodes.into_iter().map(Node::entry).collect())
+    }
+}
+
+/// A node found by name.
+struct NodeEntry {
+    name: String,
+    node: Node,
+}
+
+impl vfs::Entry for NodeEntry {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn metadata(&self) -> Metadata {
+        let kind = match self.node {
+            Node::Root | Node::Process(_) => Kind::Dir,
+            Node::Stat(_) | Node::Status(_) => Kind::File,
+        };
+        Metadata::new(kind, 0, true)
+    }
+
+    fn into_file(self: Box<Self>) -> Option<Box<vfs::File>> {
+        let text = match self.node {
+            Node::Root | Node::Process(_) => return None,
+            Node::Stat(stat) => (stat.generate)(),
+            Node::Status(id) => status(id),
+        };
+        Some(Box::new(Text { bytes: text.into_bytes(), pos: 0 }))
+    }
+
+    fn into_dir(self: Box<Self>) -> Option<Box<vfs::Dir>> {
+        match self.node {
+            Node::Root | Node::Process(_) => Some(Box::new(self.node)),
+            Node::Stat(_) | Node::Status(_) => None,
+        }
+    }
+}
+
+/// A generated file: its text and the offset of the next read.
+struct Text {
+    bytes: Vec<u8>,
+    pos: u64,
+}
+
+impl Read for Text {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let pos = self.pos;
+        let n = vfs::File::read_at(self, pos, buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Write for Text {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(vfs::read_only())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for Text {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = vfs::seek_position(pos, self.pos, self.bytes.len() as u64)?;
+        Ok(self.pos)
+    }
+}
+
+impl vfs::File for Text {
+    fn size(&self) -> u64 {
+        self.bytes.len() as u64
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let start = min(offset, self.bytes.len() as u64) as usize;
+        let n = min(buf.len(), self.bytes.len() - start);
+        buf[..n].copy_from_slice(&self.bytes[start..start + n]);
+        Ok(n)
+    }
+}
+
+/// Returns the contents of `meminfo`.
+fn meminfo() -> String {
+    let stats = ALLOCATOR.stats();
+    let mut text = String::new();
+    let page_kib = allocator::PAGE_SIZE / 1024;
+    let _ = writeln!(text, "PagesTotal:   {:>10} kB", stats.total_pages * page_kib);
+    let _ = writeln!(text, "PagesFree:    {:>10} kB", stats.free_pages * page_kib);
+    let _ = writeln!(text, "HeapInUse:    {:>10} B", stats.bytes_in_use);
+    let _ = writeln!(text, "HeapPeak:     {:>10} B", stats.peak_bytes);
+    let _ = writeln!(text, "Allocs:       {:>10}", stats.alloc_calls);
+    let _ = writeln!(text, "FailedAllocs: {:>10}", stats.failed_allocs);
+    let _ = writeln!(text, "Deallocs:     {:>10}", stats.dealloc_calls);
+    let slab: usize = slab_caches().iter().map(|cache| cache.slabs * cache.slab_size).sum();
+    let _ = writeln!(text, "Slab:         {:>10} B", slab);
+    text
+}
+
+/// Returns the statistics of every slab cache.
+fn slab_caches() -> [SlabStats; 2] {
+    [scheduler::slab_stats(), files::slab_stats()]
+}
+
+/// Returns the contents of `slabinfo`: a header, then a line for each slab
+/// cache.
+fn slabinfo() -> String {
+    let mut text = String::new();
+    let _ = writeln!(text, "{:<10} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8}",
+                     "name", "in_use", "peak", "free", "objsize", "slabs", "slabsize");
+    for cache in slab_caches().iter() {
+        let _ = writeln!(text, "{:<10} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8}",
+                         cache.name, cache.objects_in_use, cache.peak_objects,
+                         cache.free_objects, cache.object_size, cache.slabs, cache.slab_size);
+    }
+    text
+}
+
+/// Returns the contents of `uptime`: seconds since boot, to hundredths.
+fn uptime() -> String {
+    let us = clock::monotonic_us();
+    format!("{}.{:02}\n", us / 1_000_000, us % 1_000_000 / 10_000)
+}
+
+/// Returns the contents of `interrupts`: a line for each interrupt with a
+/// count, then one for spurious interrupts.
+fn interrupts() -> String {
+    let mut text = String::new();
+    irq::for_each_count(|int, count, handled| {
+        let _ = writeln!(text, "{:<8} {:>10}{}", format!("{:?}", int), count,
+                         if handled { "" } else { "  (no handler)" });
+    });
+    let _ = writeln!(text, "{:<8} {:>10}", "spurious", irq::spurious());
+    text
+}
+
+/// Returns the contents of process `id`'s `status`, or nothing if it has
+/// exited.
+fn status(id: Id) -> String {
+    let p = match scheduler::info(id) {
+        Some(p) => p,
+        None => return String::new(),
+    };
+
+    let mut text = String::new();
+    let _ = writeln!(text, "Name:       {}", p.name);
+    let _ = writeln!(text, "Pid:        {}", p.id);
+    let _ = writeln!(text, "State:      {:?}", p.state);
+    let _ = writeln!(text, "Nice:       {}", p.nice);
+    let _ = writeln!(text, "UserTime:   {} us", p.user_time);
+    let _ = writeln!(text, "KernelTime: {} us", p.kernel_time);
+    let _ = writeln!(text, "Switches:   {}", p.switches);
+    let _ = writeln!(text, "Memory:     {} kB", p.memory / 1024);
+    text
+}
@@ -1,5 +1,11 @@
 use std::io;
-use fat32::traits::BlockDevice;
+use fat32::traits;
+
+use pi::emmc;
+use pi::timer::spin_sleep_us;
+
+use fs::block::{self, BlockDevice};
+use sync;
 
 extern "C" {
     /// A global representing the last SD controller error that occured.
@@ -25,12 +31,31 @@ extern "C" {
     fn sd_readsector(n: i32, buffer: *mut u8) -> i32;
 }
 
-// FIXME: Define a `#[no_mangle]` `wait_micros` function for use by `libsd`.
-// The `wait_micros` C signature is: `void wait_micros(unsigned int);`
+/// Held for each `libsd` call, which can spin for milliseconds and uses
+/// global state.
+static TRANSACTION: sync::Mutex<()> = sync::Mutex::new(());
+
+/// Returns the contention counters for SD card transactions.
+pub fn stats() -> sync::Stats {
+    TRANSACTION.stats()
+}
+
+/// Sleeps for `us` microseconds. Called by `libsd`, whose C signature for it
+/// is `void wait_micros(unsigned int);`.
+#[no_mangle]
+pub extern "C" fn wait_micros(us: u32) {
+    spin_sleep_us(us as u64);
+}
 
+/// Error type for SD controller failures.
 #[derive(Debug)]
 pub enum Error {
-    // FIXME: Fill me in.
+    /// The controller timed out.
+    Timeout,
+    /// Sending a command to the controller failed.
+    SendCommand,
+    /// Any other error, with `libsd`'s error code.
+    Unknown(i64),
 }
 
 /// A handle to an SD card controller.
@@ -40,28 +65,124 @@ pub struct Sd;
 impl Sd {
     /// Initializes the SD card controller and returns a handle to it.
     pub fn new() -> Result<Sd, Error> {
-        unimplemented!("Sd::new()")
+        let _transaction = TRANSACTION.lock();
+        match unsafe { sd_init() } {
+            0 => Ok(Sd),
+            -1 => Err(Error::Timeout),
+            -2 => Err(Error::SendCommand),
+            code => Err(Error::Unknown(code as i64)),
+        }
     }
 }
 
+/// Writes `buf` to sector `n` of the SD card directly, bypassing the block
+/// cache, which won't see the write. This is for records the kernel keeps
+/// outside any partition, such as crash records; file systems write through
+/// the cache and `write_blocks()`.
+///
+/// An error of kind `InvalidInput` is returned if `n` is past `2^32 - 1`,
+/// `TimedOut` if the card times out, and `Other` for any other error. None
+/// allocate, so this can be called while panicking.
+pub fn write_raw(n: u64, buf: &[u8; emmc::BLOCK_SIZE]) -> io::Result<()> {
+    let _transaction = TRANSACTION.lock();
+    write_raw_locked(n, buf)
+}
+
+/// Like `write_raw()`, but fails with `WouldBlock` instead of waiting if
+/// another SD card transaction is in progress. For panics.
+pub fn try_write_raw(n: u64, buf: &[u8; emmc::BLOCK_SIZE]) -> io::Result<()> {
+    let _transaction = TRANSACTION.try_lock()
+        .ok_or_else(|| io::Error::from(io::ErrorKind::WouldBlock))?;
+    write_raw_locked(n, buf)
+}
+
+fn write_raw_locked(n: u64, buf: &[u8; emmc::BLOCK_SIZE]) -> io::Result<()> {
+    if n > u32::max_value() as u64 {
+        return Err(io::Error::from(io::ErrorKind::InvalidInput));
+    }
+
+    emmc::write_block(n as u32, buf).map_err(|e| match e {
+        emmc::Error::Timeout => io::Error::from(io::ErrorKind::TimedOut),
+        emmc::Error::Controller(_) => io::Error::from(io::ErrorKind::Other),
+    })
+}
+
 impl BlockDevice for Sd {
-    /// Reads sector `n` from the SD card into `buf`. On success, the number of
-    /// bytes read is returned.
+    /// Reads the sectors from `lba` on into `buf`, under one transaction so
+    /// that other processes can't interleave their own.
     ///
     /// # Errors
     ///
-    /// An I/O error of kind `InvalidInput` is returned if `buf.len() < 512` or
-    /// `n > 2^31 - 1` (the maximum value for an `i32`).
+    /// An I/O error of kind `InvalidInput` is returned if `buf.len()` isn't
+    /// a multiple of 512 or a sector is past `2^31 - 1` (the maximum value
+    /// for an `i32`).
     ///
     /// An error of kind `TimedOut` is returned if a timeout occurs while
     /// reading from the SD card.
     ///
     /// An error of kind `Other` is returned for all other errors.
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> io::Result<()> {
+        let count = block::block_count(buf.len(), self.block_size())?;
+        match lba.checked_add(count as u64) {
+            Some(end) if end <= i32::max_value() as u64 + 1 => {}
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid sector")),
+        }
+
+        // `libsd` only reads a sector at a time.
+        let _transaction = TRANSACTION.lock();
+        for (n, sector) in (lba..).zip(buf.chunks_mut(self.block_size())) {
+            if unsafe { sd_readsector(n as i32, sector.as_mut_ptr()) } == 0 {
+                return match unsafe { sd_err } {
+                    -1 => Err(io::Error::new(io::ErrorKind::TimedOut, "SD card timed out")),
+                    _ => Err(io::Error::new(io::ErrorKind::Other, "SD card read failed")),
+                };
+            }
+        }
+        Ok(())
+    }
+
+    /// The card is written with `pi::emmc`, since `libsd` only reads. That
+    /// needs a high capacity card, as `pi::emmc::write_block()` says.
+    fn read_only(&self) -> bool {
+        false
+    }
+
+    /// Writes `buf` to the sectors from `lba` on, under one transaction.
+    ///
+    /// # Errors
+    ///
+    /// An I/O error of kind `InvalidInput` is returned if `buf.len()` isn't
+    /// a multiple of 512 or a sector is past `2^32 - 1`. Otherwise, the
+    /// errors are those of `write_raw()`.
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> io::Result<()> {
+        let count = block::block_count(buf.len(), self.block_size())?;
+        match lba.checked_add(count as u64) {
+            Some(end) if end <= u32::max_value() as u64 + 1 => {}
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid sector")),
+        }
+
+        // `pi::emmc` only writes a sector at a time, too.
+        let _transaction = TRANSACTION.lock();
+        for (n, sector) in (lba..).zip(buf.chunks(emmc::BLOCK_SIZE)) {
+            let sector = unsafe { &*(sector.as_ptr() as *const [u8; emmc::BLOCK_SIZE]) };
+            write_raw_locked(n, sector)?;
+        }
+        Ok(())
+    }
+}
+
+impl traits::BlockDevice for Sd {
+    /// Reads sector `n` from the SD card into `buf`. On success, the number of
+    /// bytes read is returned. See `read_blocks()`.
     fn read_sector(&mut self, n: u64, buf: &mut [u8]) -> io::Result<usize> {
-        unimplemented!("Sd::read_sector()")
+        if buf.len() < 512 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid sector or buffer"));
+        }
+
+        self.read_blocks(n, &mut buf[..512]).map(|_| 512)
     }
 
-    fn write_sector(&mut self, _n: u64, _buf: &[u8]) -> io::Result<usize> {
-        unimplemented!("SD card and file system are read only")
+    fn write_sector(&mut self, n: u64, buf: &[u8]) -> io::Result<usize> {
+        self.write_blocks(n, buf).map(|_| buf.len())
     }
 }
@@ -0,0 +1,1064 @@
+use std::io;
+use std::sync::{Arc, Mutex};
+
+use bytes::le;
+use fs::block::{self, BlockDevice, DEFAULT_BLOCK_SIZE};
+
+/// A block device kept in memory, which logs the transfers made.
+struct RamDisk {
+    data: Vec<u8>,
+    read_only: bool,
+    /// The first block and block count of every read and write.
+    reads: Vec<(u64, usize)>,
+    writes: Vec<(u64, usize)>,
+}
+
+impl RamDisk {
+    fn new(blocks: usize) -> RamDisk {
+        RamDisk {
+            data: vec![0; blocks * DEFAULT_BLOCK_SIZE],
+            read_only: false,
+            reads: Vec::new(),
+            writes: Vec::new(),
+        }
+    }
+
+    /// Returns block `lba`.
+    fn block(&self, lba: u64) -> &[u8] {
+        let start = lba as usize * DEFAULT_BLOCK_SIZE;
+        &self.data[start..start + DEFAULT_BLOCK_SIZE]
+    }
+
+    /// Returns the byte range of the `len` bytes from `lba`, or an error if
+    /// they run past the end of the disk.
+    fn range(&self, lba: u64, len: usize) -> io::Result<(usize, usize)> {
+        block::block_count(len, DEFAULT_BLOCK_SIZE)?;
+        let start = lba as usize * DEFAULT_BLOCK_SIZE;
+        match start.checked_add(len) {
+            Some(end) if end <= self.data.len() => Ok((start, end)),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, "past the end of the disk")),
+        }
+    }
+
+    /// Writes an MBR whose first partition is `sectors` sectors from sector
+    /// 1 on.
+    fn partition(&mut self, kind: u8, sectors: u32) {
+        let entry = &mut self.data[446..462];
+        entry[4] = kind;
+        le::put_u32(entry, 8, 1);
+        le::put_u32(entry, 12, sectors);
+        self.data[510..512].copy_from_slice(&[0x55, 0xAA]);
+    }
+}
+
+impl BlockDevice for RamDisk {
+    fn read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> io::Result<()> {
+        let (start, end) = self.range(lba, buf.len())?;
+        buf.copy_from_slice(&self.data[start..end]);
+        self.reads.push((lba, buf.len() / DEFAULT_BLOCK_SIZE));
+        Ok(())
+    }
+
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> io::Result<()> {
+        if self.read_only {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "device is read only"));
+        }
+        let (start, end) = self.range(lba, buf.len())?;
+        self.data[start..end].copy_from_slice(buf);
+        self.writes.push((lba, buf.len() / DEFAULT_BLOCK_SIZE));
+        Ok(())
+    }
+}
+
+/// A `RamDisk` that a test can still look at once it has given it away.
+#[derive(Clone)]
+struct SharedDisk(Arc<Mutex<RamDisk>>);
+
+impl SharedDisk {
+    fn new(blocks: usize) -> SharedDisk {
+        SharedDisk(Arc::new(Mutex::new(RamDisk::new(blocks))))
+    }
+
+    fn with<T, F: FnOnce(&mut RamDisk) -> T>(&self, f: F) -> T {
+        f(&mut self.0.lock().unwrap())
+    }
+}
+
+impl BlockDevice for SharedDisk {
+    fn read_only(&self) -> bool {
+        self.with(|disk| disk.read_only)
+    }
+
+    fn read_blocks(&mut self, lba: u64, buf: &mut [u8]) -> io::Result<()> {
+        self.with(|disk| disk.read_blocks(lba, buf))
+    }
+
+    fn write_blocks(&mut self, lba: u64, buf: &[u8]) -> io::Result<()> {
+        self.with(|disk| disk.write_blocks(lba, buf))
+    }
+}
+
+mod cache {
+    use std::io;
+
+    use fs::block::{BlockDevice, DEFAULT_BLOCK_SIZE};
+    use fs::cache::Cache;
+    use super::SharedDisk;
+
+    /// Returns a block filled with `byte`.
+    fn filled(byte: u8) -> Vec<u8> {
+        vec![byte; DEFAULT_BLOCK_SIZE]
+    }
+
+    fn read(cache: &mut Cache, lba: u64) -> Vec<u8> {
+        let mut buf = filled(0);
+        cache.read_blocks(lba, &mut buf).expect("cache read");
+        buf
+    }
+
+    #[test]
+    fn reads_once() {
+        let disk = SharedDisk::new(8);
+        disk.with(|disk| disk.write_blocks(3, &filled(0x33)).unwrap());
+        let mut cache = Cache::new(disk.clone(), 4);
+
+        assert_eq!(read(&mut cache, 3), filled(0x33));
+        assert_eq!(read(&mut cache, 3), filled(0x33));
+        assert_eq!(disk.with(|disk| disk.reads.clone()), [(3, 1)]);
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let disk = SharedDisk::new(8);
+        let mut cache = Cache::new(disk.clone(), 2);
+
+        read(&mut cache, 0);
+        read(&mut cache, 1);
+        // Using block 0 again leaves block 1 the least recently used, so
+        // reading block 2 evicts it.
+        read(&mut cache, 0);
+        read(&mut cache, 2);
+        read(&mut cache, 0);
+        read(&mut cache, 1);
+        assert_eq!(disk.with(|disk| disk.reads.clone()), [(0, 1), (1, 1), (2, 1), (1, 1)]);
+    }
+
+    #[test]
+    fn writes_wait_for_sync() {
+        let disk = SharedDisk::new(8);
+        let mut cache = Cache::new(disk.clone(), 4);
+
+        cache.write_blocks(5, &filled(0x55)).expect("cache write");
+        assert_eq!(read(&mut cache, 5), filled(0x55));
+        assert_eq!(disk.with(|disk| disk.block(5).to_vec()), filled(0));
+        assert!(disk.with(|disk| disk.reads.is_empty() && disk.writes.is_empty()));
+
+        cache.sync().expect("sync");
+        assert_eq!(disk.with(|disk| disk.block(5).to_vec()), filled(0x55));
+        cache.sync().expect("second sync");
+        assert_eq!(disk.with(|disk| disk.writes.clone()), [(5, 1)]);
+    }
+
+    #[test]
+    fn evicting_a_dirty_block_writes_it_back() {
+        let disk = SharedDisk::new(8);
+        let mut cache = Cache::new(disk.clone(), 1);
+
+        cache.write_blocks(0, &filled(0xAA)).expect("cache write");
+        read(&mut cache, 1);
+        assert_eq!(disk.with(|disk| disk.writes.clone()), [(0, 1)]);
+        assert_eq!(disk.with(|disk| disk.block(0).to_vec()), filled(0xAA));
+
+        // A clean block is dropped without being written.
+        read(&mut cache, 2);
+        assert_eq!(disk.with(|disk| disk.writes.len()), 1);
+        assert_eq!(read(&mut cache, 0), filled(0xAA));
+    }
+
+    #[test]
+    fn sync_writes_runs_of_blocks_together() {
+        let disk = SharedDisk::new(16);
+        let mut cache = Cache::new(disk.clone(), 8);
+
+        let mut run = filled(1);
+        run.extend(filled(2));
+        run.extend(filled(3));
+        cache.write_blocks(1, &run).expect("cache write");
+        cache.write_blocks(7, &filled(7)).expect("cache write");
+        cache.sync().expect("sync");
+        assert_eq!(disk.with(|disk| disk.writes.clone()), [(1, 3), (7, 1)]);
+        assert_eq!(disk.with(|disk| disk.block(3).to_vec()), filled(3));
+    }
+
+    #[test]
+    fn failed_write_back_stays_dirty() {
+        let disk = SharedDisk::new(8);
+        let mut cache = Cache::new(disk.clone(), 4);
+
+        cache.write_blocks(2, &filled(0x22)).expect("cache write");
+        disk.with(|disk| disk.read_only = true);
+        assert!(cache.sync().is_err());
+
+        disk.with(|disk| disk.read_only = false);
+        cache.sync().expect("sync");
+        assert_eq!(disk.with(|disk| disk.block(2).to_vec()), filled(0x22));
+    }
+
+    #[test]
+    fn read_only_device_refuses_writes() {
+        let disk = SharedDisk::new(8);
+        disk.with(|disk| disk.read_only = true);
+        let mut cache = Cache::new(disk, 4);
+
+        let error = cache.write_blocks(0, &filled(1)).expect_err("write to a read-only disk");
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+    }
+}
+
+mod fsck {
+    use std::io;
+
+    use bytes::le;
+    use fs::fsck::{self, Report};
+    use fs::mbr::{Partition, TYPE_FAT32_LBA};
+    use super::RamDisk;
+
+    /// The layout of the test volume, in sectors of the partition: the boot
+    /// sector, FSInfo, two one-sector FATs, then one sector per cluster.
+    const FSINFO: usize = 1;
+    const FATS: [usize; 2] = [2, 3];
+    const DATA: usize = 4;
+    const CLUSTERS: usize = 64;
+    const SECTORS: usize = DATA + CLUSTERS;
+
+    const ROOT: u32 = 2;
+    const END: u32 = 0x0FFF_FFFF;
+    const ATTR_DIRECTORY: u8 = 0x10;
+
+    /// A small FAT32 volume on partition 1 of a `RamDisk`, built up
+    /// directly rather than through `mkfs`, so that it can be damaged.
+    struct Volume(RamDisk);
+
+    impl Volume {
+        /// Returns a volume with an empty root directory in cluster 2 and
+        /// the right free count.
+        fn new() -> Volume {
+            let mut disk = RamDisk::new(1 + SECTORS);
+            disk.partition(TYPE_FAT32_LBA, SECTORS as u32);
+            let mut volume = Volume(disk);
+
+            {
+                let boot = volume.sector(0);
+                le::put_u16(boot, 11, 512);
+                boot[13] = 1;
+                le::put_u16(boot, 14, FATS[0] as u16);
+                boot[16] = FATS.len() as u8;
+                le::put_u32(boot, 32, SECTORS as u32);
+                le::put_u32(boot, 36, 1);
+                le::put_u32(boot, 44, ROOT);
+                le::put_u16(boot, 48, FSINFO as u16);
+                boot[510..512].copy_from_slice(&[0x55, 0xAA]);
+            }
+            {
+                let fsinfo = volume.sector(FSINFO);
+                le::put_u32(fsinfo, 0, 0x4161_5252);
+                le::put_u32(fsinfo, 484, 0x6141_7272);
+            }
+            volume.set_fat(0, 0x0FFF_FFF8);
+            volume.set_fat(1, END);
+            volume.chain(&[ROOT]);
+            volume.set_free_count(CLUSTERS as u32 - 1);
+            volume
+        }
+
+        /// Returns partition sector `n`.
+        fn sector(&mut self, n: usize) -> &mut [u8] {
+            let start = (1 + n) * 512;
+            &mut self.0.data[start..start + 512]
+        }
+
+        /// Sets the FAT entry of `cluster` in every copy of the FAT.
+        fn set_fat(&mut self, cluster: u32, value: u32) {
+            for &fat in FATS.iter() {
+                le::put_u32(self.sector(fat), cluster as usize * 4, value);
+            }
+        }
+
+        /// Links `clusters` into a chain, ending it after the last.
+        fn chain(&mut self, clusters: &[u32]) {
+            for pair in clusters.windows(2) {
+                self.set_fat(pair[0], pair[1]);
+            }
+            self.set_fat(clusters[clusters.len() - 1], END);
+        }
+
+        fn set_free_count(&mut self, count: u32) {
+            le::put_u32(self.sector(FSINFO), 488, count);
+        }
+
+        /// Writes entry `index` of the directory in cluster `dir`.
+        fn entry(&mut self, dir: u32, index: usize, name: &[u8; 11], attr: u8, first: u32,
+                 size: u32) {
+            let sector = self.sector(DATA + dir as usize - 2);
+            let entry = &mut sector[index * 32..(index + 1) * 32];
+            entry[..11].copy_from_slice(name);
+            entry[11] = attr;
+            le::put_u16(entry, 20, (first >> 16) as u16);
+            le::put_u16(entry, 26, first as u16);
+            le::put_u32(entry, 28, size);
+        }
+
+        /// Adds `A.TXT` in clusters 3 and 4, and `SUB`, an empty directory
+        /// in cluster 5, to the root, and sets the free count to match.
+        fn populate(&mut self) {
+            self.entry(ROOT, 0, b"A       TXT", 0, 3, 1000);
+            self.chain(&[3, 4]);
+            self.entry(ROOT, 1, b"SUB        ", ATTR_DIRECTORY, 5, 0);
+            self.chain(&[5]);
+            self.entry(5, 0, b".          ", ATTR_DIRECTORY, 5, 0);
+            self.entry(5, 1, b"..         ", ATTR_DIRECTORY, 0, 0);
+            self.set_free_count(CLUSTERS as u32 - 4);
+        }
+
+        fn open(self) -> Partition<RamDisk> {
+            Partition::open(self.0, 1).expect("partition 1")
+        }
+    }
+
+    fn problems(report: &Report) -> Vec<String> {
+        report.problems.iter().map(|problem| problem.to_string()).collect()
+    }
+
+    /// Repairs `partition`, checks that the repair found `expected`, then
+    /// checks that the volume is clean.
+    fn repair_and_recheck(partition: &mut Partition<RamDisk>, expected: &[&str]) {
+        let report = fsck::fat32(partition, true).expect("repair");
+        assert!(report.repaired);
+        assert_eq!(problems(&report), expected);
+
+        let report = fsck::fat32(partition, false).expect("check after repair");
+        assert_eq!(problems(&report), Vec::<String>::new());
+    }
+
+    #[test]
+    fn clean_volume() {
+        let mut volume = Volume::new();
+        volume.populate();
+        let mut partition = volume.open();
+
+        let report = fsck::fat32(&mut partition, false).expect("check");
+        assert_eq!(problems(&report), Vec::<String>::new());
+        assert!(!report.repaired);
+        assert_eq!((report.files, report.dirs), (1, 2));
+        assert_eq!((report.used, report.clusters), (4, CLUSTERS as u64));
+    }
+
+    #[test]
+    fn cross_links_and_lost_clusters() {
+        let mut volume = Volume::new();
+        volume.populate();
+        // B.TXT runs from its own cluster into the last one of A.TXT.
+        volume.entry(ROOT, 2, b"B       TXT", 0, 6, 512);
+        volume.set_fat(6, 4);
+        // Clusters 10 and 11 are in use by nothing.
+        volume.chain(&[10, 11]);
+        volume.set_free_count(CLUSTERS as u32 - 5);
+        let mut partition = volume.open();
+
+        let expected = ["/B.TXT: cross-linked with /A.TXT at cluster 4", "2 lost clusters"];
+        let report = fsck::fat32(&mut partition, false).expect("check");
+        assert_eq!(problems(&report), expected);
+        assert_eq!((report.files, report.dirs, report.used), (2, 2, 5));
+
+        repair_and_recheck(&mut partition, &expected);
+    }
+
+    #[test]
+    fn broken_chain() {
+        let mut volume = Volume::new();
+        volume.populate();
+        // C.TXT needs two clusters, but the second is free.
+        volume.entry(ROOT, 2, b"C       TXT", 0, 7, 1024);
+        volume.set_fat(7, 8);
+        volume.set_free_count(CLUSTERS as u32 - 5);
+        let mut partition = volume.open();
+
+        repair_and_recheck(&mut partition, &[
+            "/C.TXT: chain broken at cluster 8",
+            "/C.TXT: size 1024 doesn't fit its 1 clusters",
+        ]);
+    }
+
+    #[test]
+    fn size_longer_than_chain() {
+        let mut volume = Volume::new();
+        volume.populate();
+        volume.entry(ROOT, 0, b"A       TXT", 0, 3, 100);
+        let mut partition = volume.open();
+
+        // Freeing the cluster past the end changes the free count too.
+        repair_and_recheck(&mut partition, &[
+            "/A.TXT: size 100 doesn't fit its 2 clusters",
+            "free count is 60, should be 61",
+        ]);
+    }
+
+    #[test]
+    fn fat_copies_differ() {
+        let mut volume = Volume::new();
+        le::put_u32(volume.sector(FATS[1]), 20 * 4, END);
+        let mut partition = volume.open();
+
+        repair_and_recheck(&mut partition, &["FAT copy 2 differs from the first"]);
+    }
+
+    #[test]
+    fn wrong_free_count() {
+        let mut volume = Volume::new();
+        volume.populate();
+        volume.set_free_count(5);
+        let mut partition = volume.open();
+
+        repair_and_recheck(&mut partition, &["free count is 5, should be 60"]);
+    }
+
+    #[test]
+    fn bad_dot_entry() {
+        let mut volume = Volume::new();
+        volume.populate();
+        volume.entry(5, 1, b"..         ", ATTR_DIRECTORY, 9, 0);
+        let mut partition = volume.open();
+
+        repair_and_recheck(&mut partition, &["/SUB: '..' entry points to the wrong cluster"]);
+    }
+
+    #[test]
+    fn bad_names_are_only_reported() {
+        let mut volume = Volume::new();
+        volume.populate();
+        volume.entry(ROOT, 0, b"a       TXT", 0, 3, 1000);
+        let mut partition = volume.open();
+
+        let report = fsck::fat32(&mut partition, true).expect("repair");
+        assert_eq!(problems(&report), ["/a.TXT: invalid name"]);
+        assert!(!report.problems[0].repairable());
+    }
+
+    #[test]
+    fn repair_needs_a_writable_device() {
+        let mut volume = Volume::new();
+        volume.0.read_only = true;
+        let mut partition = volume.open();
+
+        let error = fsck::fat32(&mut partition, true).expect_err("repair of a read-only disk");
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        assert!(fsck::fat32(&mut partition, false).is_ok());
+    }
+
+    #[test]
+    fn not_fat32() {
+        let mut volume = Volume::new();
+        // Three sectors per cluster, which isn't a power of two.
+        volume.sector(0)[13] = 3;
+        let mut partition = volume.open();
+
+        let error = fsck::fat32(&mut partition, false).expect_err("check of a non-FAT32 volume");
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
+
+mod exfat {
+    use std::io::{self, Read};
+    use std::path::Path;
+
+    use bytes::le;
+    use clock::DateTime;
+    use fs::exfat::ExFat;
+    use fs::vfs::{FileSystem, Kind};
+    use super::RamDisk;
+
+    /// The layout of the test volume, in 512-byte sectors: the boot sector,
+    /// a one-sector FAT, then one sector per cluster.
+    const FAT: usize = 1;
+    const HEAP: usize = 2;
+    const CLUSTERS: usize = 16;
+
+    const ROOT: u32 = 2;
+    const END: u32 = 0xFFFF_FFFF;
+    const ATTR_DIRECTORY: u16 = 0x10;
+
+    /// 2018-03-14 12:30:20.
+    const TIMESTAMP: u32 = (38 << 9 | 3 << 5 | 14) << 16 | 12 << 11 | 30 << 5 | 10;
+
+    /// A small exFAT volume on a `RamDisk`, built up directly so that it can
+    /// be damaged.
+    struct Volume(RamDisk);
+
+    impl Volume {
+        /// Returns a volume with an empty one-cluster root directory.
+        fn new() -> Volume {
+            let mut volume = Volume(RamDisk::new(HEAP + CLUSTERS));
+            {
+                let boot = volume.sector(0);
+                boot[3..11].copy_from_slice(b"EXFAT   ");
+                le::put_u32(boot, 80, FAT as u32);
+                le::put_u32(boot, 88, HEAP as u32);
+                le::put_u32(boot, 92, CLUSTERS as u32);
+                le::put_u32(boot, 96, ROOT);
+                boot[108] = 9;
+                boot[109] = 0;
+                boot[510..512].copy_from_slice(&[0x55, 0xAA]);
+            }
+            volume.chain(&[ROOT]);
+            volume
+        }
+
+        fn sector(&mut self, n: usize) -> &mut [u8] {
+            &mut self.0.data[n * 512..(n + 1) * 512]
+        }
+
+        fn boot(&mut self) -> &mut [u8] {
+            self.sector(0)
+        }
+
+        /// Links `clusters` into a chain, ending it after the last.
+        fn chain(&mut self, clusters: &[u32]) {
+            for pair in clusters.windows(2) {
+                self.set_fat(pair[0], pair[1]);
+            }
+            self.set_fat(clusters[clusters.len() - 1], END);
+        }
+
+        fn set_fat(&mut self, cluster: u32, value: u32) {
+            le::put_u32(self.sector(FAT), cluster as usize * 4, value);
+        }
+
+        fn fill(&mut self, cluster: u32, byte: u8) {
+            for b in self.sector(HEAP + cluster as usize - 2).iter_mut() {
+                *b = byte;
+            }
+        }
+
+        /// Returns entry `index` of the directory in cluster `dir`.
+        fn entry(&mut self, dir: u32, index: usize) -> &mut [u8] {
+            let sector = self.sector(HEAP + dir as usize - 2);
+            &mut sector[index * 32..(index + 1) * 32]
+        }
+
+        /// Writes the entry set of a `size`-byte file or directory from
+        /// cluster `first` at entry `index` of the directory in cluster
+        /// `dir`, with every byte written. Returns the index after it.
+        fn add(&mut self, dir: u32, index: usize, name: &str, attributes: u16, first: u32,
+               size: u64, contiguous: bool) -> usize {
+            let units: Vec<u16> = name.encode_utf16().collect();
+            let names = (units.len() + 14) / 15;
+            {
+                let file = self.entry(dir, index);
+                file[0] = 0x85;
+                file[1] = 1 + names as u8;
+                le::put_u16(file, 4, attributes);
+                le::put_u32(file, 8, TIMESTAMP);
+                le::put_u32(file, 12, TIMESTAMP);
+            }
+            {
+                let stream = self.entry(dir, index + 1);
+                stream[0] = 0xC0;
+                stream[1] = if contiguous { 0x03 } else { 0x01 };
+                stream[3] = units.len() as u8;
+                le::put_u64(stream, 8, size);
+                le::put_u32(stream, 20, first);
+                le::put_u64(stream, 24, size);
+            }
+            for (i, chunk) in units.chunks(15).enumerate() {
+                let entry = self.entry(dir, index + 2 + i);
+                entry[0] = 0xC1;
+                for (j, &unit) in chunk.iter().enumerate() {
+                    le::put_u16(entry, 2 + 2 * j, unit);
+                }
+            }
+            index + 2 + names
+        }
+
+        fn mount(self) -> ExFat {
+            ExFat::mount(self.0).ok().expect("mount")
+        }
+    }
+
+    fn read(fs: &ExFat, path: &str) -> io::Result<Vec<u8>> {
+        let mut file = fs.open(Path::new(path))?.into_file().expect("a file");
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn names(fs: &ExFat, path: &str) -> io::Result<Vec<String>> {
+        let dir = fs.open(Path::new(path))?.into_dir().expect("a directory");
+        Ok(dir.entries()?.iter().map(|entry| entry.name().to_string()).collect())
+    }
+
+    fn error(fs: &ExFat, path: &str) -> io::Error {
+        fs.open(Path::new(path)).err().expect("open should fail")
+    }
+
+    /// Returns the message of the `InvalidData` error `result` should be.
+    fn invalid<T>(result: io::Result<T>) -> String {
+        let e = result.err().expect("should fail");
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        e.to_string()
+    }
+
+    /// Returns a volume with `Hello.txt`, 700 bytes in clusters 3 and 4
+    /// without a FAT chain, and `Sub`, a directory in cluster 5 holding a
+    /// 600-byte file chained through clusters 6 and 9.
+    fn populated() -> Volume {
+        let mut volume = Volume::new();
+        // A volume label, and a deleted file entry and stream extension.
+        volume.entry(ROOT, 0)[0] = 0x83;
+        volume.entry(ROOT, 1)[0] = 0x05;
+        volume.entry(ROOT, 2)[0] = 0x40;
+
+        let next = volume.add(ROOT, 3, "Hello.txt", 0, 3, 700, true);
+        volume.fill(3, b'a');
+        volume.fill(4, b'b');
+        volume.add(ROOT, next, "Sub", ATTR_DIRECTORY, 5, 512, false);
+        volume.chain(&[5]);
+
+        volume.add(5, 0, "A long file name.txt", 0, 6, 600, false);
+        volume.chain(&[6, 9]);
+        volume.fill(6, b'c');
+        volume.fill(9, b'd');
+        volume
+    }
+
+    #[test]
+    fn reads_files_and_directories() {
+        let fs = populated().mount();
+
+        assert_eq!(names(&fs, "/").unwrap(), ["Hello.txt", "Sub"]);
+        assert_eq!(names(&fs, "/sub").unwrap(), ["A long file name.txt"]);
+
+        let hello = read(&fs, "/HELLO.TXT").unwrap();
+        assert_eq!(hello.len(), 700);
+        assert!(hello[..512].iter().all(|&b| b == b'a'));
+        assert!(hello[512..].iter().all(|&b| b == b'b'));
+
+        let chained = read(&fs, "/Sub/a long FILE name.txt").unwrap();
+        assert_eq!(chained.len(), 600);
+        assert!(chained[..512].iter().all(|&b| b == b'c'));
+        assert!(chained[512..].iter().all(|&b| b == b'd'));
+    }
+
+    #[test]
+    fn metadata() {
+        let fs = populated().mount();
+
+        let hello = fs.open(Path::new("/Hello.txt")).unwrap().metadata();
+        assert_eq!((hello.kind, hello.size, hello.read_only), (Kind::File, 700, true));
+        let time = DateTime { year: 2018, month: 3, day: 14, hour: 12, minute: 30, second: 20 };
+        assert_eq!((hello.created, hello.modified), (Some(time), Some(time)));
+
+        let sub = fs.open(Path::new("/Sub")).unwrap().metadata();
+        assert_eq!((sub.kind, sub.size), (Kind::Dir, 0));
+    }
+
+    #[test]
+    fn lookups_that_fail() {
+        let fs = populated().mount();
+
+        assert_eq!(error(&fs, "/missing").kind(), io::ErrorKind::NotFound);
+        assert_eq!(error(&fs, "/Sub/missing").kind(), io::ErrorKind::NotFound);
+        assert_eq!(error(&fs, "/Hello.txt/a").to_string(), "not a directory");
+        assert_eq!(fs.create_file(Path::new("/new")).err().expect("read only").kind(),
+                   io::ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn unwritten_bytes_read_as_zero() {
+        let mut volume = populated();
+        // Only the first 100 bytes of Hello.txt were written.
+        le::put_u64(volume.entry(ROOT, 4), 8, 100);
+        let fs = volume.mount();
+
+        let hello = read(&fs, "/Hello.txt").unwrap();
+        assert_eq!(hello.len(), 700);
+        assert!(hello[..100].iter().all(|&b| b == b'a'));
+        assert!(hello[100..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn not_exfat() {
+        let mut volume = Volume::new();
+        volume.boot()[3..11].copy_from_slice(b"NTFS    ");
+        assert_eq!(invalid(ExFat::mount(volume.0)), "not an exFAT file system");
+
+        let mut volume = Volume::new();
+        volume.boot()[510] = 0;
+        assert_eq!(invalid(ExFat::mount(volume.0)), "not an exFAT file system");
+    }
+
+    #[test]
+    fn unsupported_geometry() {
+        // Sectors of 256 bytes, sectors of 4096 bytes on a 512-byte device,
+        // and 64 MB clusters.
+        for &(sector_shift, cluster_shift) in [(8, 0), (12, 0), (9, 17)].iter() {
+            let mut volume = Volume::new();
+            volume.boot()[108] = sector_shift;
+            volume.boot()[109] = cluster_shift;
+            assert_eq!(invalid(ExFat::mount(volume.0)), "unsupported exFAT geometry");
+        }
+    }
+
+    #[test]
+    fn corrupt_chains() {
+        let mut volume = populated();
+        // The second cluster of the chained file is reserved cluster 1.
+        volume.set_fat(6, 1);
+        assert_eq!(invalid(read(&volume.mount(), "/Sub/a long file name.txt")),
+                   "corrupt FAT chain");
+
+        let mut volume = populated();
+        volume.set_fat(6, END);
+        assert_eq!(invalid(read(&volume.mount(), "/Sub/a long file name.txt")),
+                   "FAT chain too short");
+
+        let mut volume = populated();
+        le::put_u32(volume.entry(ROOT, 4), 20, CLUSTERS as u32 + 2);
+        assert_eq!(invalid(read(&volume.mount(), "/Hello.txt")), "cluster out of range");
+
+        let mut volume = populated();
+        volume.set_fat(ROOT, ROOT);
+        assert_eq!(invalid(names(&volume.mount(), "/")), "FAT chain loops");
+    }
+
+    #[test]
+    fn oversized_directory() {
+        // The chain ends before the size does.
+        let mut volume = populated();
+        le::put_u64(volume.entry(ROOT, 7), 24, u64::max_value());
+        assert_eq!(names(&volume.mount(), "/Sub").unwrap(), ["A long file name.txt"]);
+
+        // Without a chain, the directory runs off the end of the heap.
+        let mut volume = populated();
+        le::put_u64(volume.entry(ROOT, 7), 24, u64::max_value());
+        volume.entry(ROOT, 7)[1] |= 0x02;
+        assert_eq!(invalid(names(&volume.mount(), "/Sub")), "cluster out of range");
+    }
+
+    #[test]
+    fn malformed_entry_sets_are_skipped() {
+        let mut volume = Volume::new();
+        let mut next = volume.add(ROOT, 0, "Good", 0, 3, 0, true);
+
+        // A name longer than its name entries hold.
+        let long = next;
+        next = volume.add(ROOT, next, "Long", 0, 3, 0, true);
+        volume.entry(ROOT, long + 1)[3] = 40;
+
+        // A file entry without a stream extension.
+        let streamless = next;
+        next = volume.add(ROOT, next, "Streamless", 0, 3, 0, true);
+        volume.entry(ROOT, streamless + 1)[0] = 0xC1;
+
+        // A file entry claiming no secondary entries.
+        let lonely = next;
+        volume.add(ROOT, next, "Lonely", 0, 3, 0, true);
+        volume.entry(ROOT, lonely)[1] = 0;
+
+        assert_eq!(names(&volume.mount(), "/").unwrap(), ["Good"]);
+    }
+
+    #[test]
+    fn truncated_entry_set() {
+        let mut volume = Volume::new();
+        let next = volume.add(ROOT, 0, "Good", 0, 3, 0, true);
+        // Deleted entries up to the last, which claims two more after it.
+        for index in next..15 {
+            volume.entry(ROOT, index)[0] = 0x05;
+        }
+        volume.entry(ROOT, 15)[0] = 0x85;
+        volume.entry(ROOT, 15)[1] = 2;
+        assert_eq!(invalid(names(&volume.mount(), "/")), "truncated entry set");
+    }
+}
+
+mod ext2 {
+    use std::io::{self, Read};
+    use std::path::Path;
+
+    use bytes::le;
+    use clock::DateTime;
+    use fs::ext2::Ext2;
+    use fs::vfs::FileSystem;
+    use super::RamDisk;
+
+    /// The layout of the test volume, in 1 KB blocks: the boot block, the
+    /// superblock, the group descriptors, a two-block inode table of 16
+    /// inodes, then data.
+    const BLOCK_SIZE: usize = 1024;
+    const DESCRIPTORS: usize = 2;
+    const INODE_TABLE: usize = 3;
+    const BLOCKS: usize = 32;
+    const INODES: u32 = 16;
+
+    const ROOT: u32 = 2;
+    const MODE_DIR: u16 = 0o040000;
+    const MODE_FILE: u16 = 0o100000;
+    const MODE_LINK: u16 = 0o120000;
+    const MTIME: u32 = 1_500_000_000;
+
+    /// A small ext2 volume on a `RamDisk`, built up directly so that it can
+    /// be damaged.
+    struct Volume(RamDisk);
+
+    impl Volume {
+        /// Returns a revision 0 volume whose root directory is empty.
+        fn new() -> Volume {
+            let mut volume = Volume(RamDisk::new(BLOCKS * BLOCK_SIZE / 512));
+            {
+                let sb = volume.superblock();
+                le::put_u32(sb, 0, INODES);
+                le::put_u32(sb, 4, BLOCKS as u32);
+                le::put_u32(sb, 20, 1);
+                le::put_u32(sb, 32, 8192);
+                le::put_u32(sb, 40, INODES);
+                le::put_u16(sb, 56, 0xEF53);
+            }
+            le::put_u32(volume.block(DESCRIPTORS), 8, INODE_TABLE as u32);
+            volume.inode(ROOT, MODE_DIR | 0o755, BLOCK_SIZE as u32, &[5]);
+            volume.dir(5, &[(ROOT, "."), (ROOT, "..")]);
+            volume
+        }
+
+        fn block(&mut self, n: usize) -> &mut [u8] {
+            &mut self.0.data[n * BLOCK_SIZE..(n + 1) * BLOCK_SIZE]
+        }
+
+        fn superblock(&mut self) -> &mut [u8] {
+            self.block(1)
+        }
+
+        fn fill(&mut self, block: usize, byte: u8) {
+            for b in self.block(block).iter_mut() {
+                *b = byte;
+            }
+        }
+
+        /// Returns the raw inode `ino`.
+        fn raw_inode(&mut self, ino: u32) -> &mut [u8] {
+            let start = INODE_TABLE * BLOCK_SIZE + (ino as usize - 1) * 128;
+            &mut self.0.data[start..start + 128]
+        }
+
+        /// Writes inode `ino`, with `blocks` as its first block numbers.
+        fn inode(&mut self, ino: u32, mode: u16, size: u32, blocks: &[u32]) {
+            let raw = self.raw_inode(ino);
+            le::put_u16(raw, 0, mode);
+            le::put_u32(raw, 4, size);
+            le::put_u32(raw, 16, MTIME);
+            for (i, &block) in blocks.iter().enumerate() {
+                le::put_u32(raw, 40 + 4 * i, block);
+            }
+        }
+
+        /// Writes `entries` into directory block `block`, the last entry
+        /// taking up the rest of it.
+        fn dir(&mut self, block: usize, entries: &[(u32, &str)]) {
+            let data = self.block(block);
+            let mut at = 0;
+            for (i, &(ino, name)) in entries.iter().enumerate() {
+                let rec_len = match i == entries.len() - 1 {
+                    true => BLOCK_SIZE - at,
+                    false => (8 + name.len() + 3) & !3,
+                };
+                le::put_u32(data, at, ino);
+                le::put_u16(data, at + 4, rec_len as u16);
+                data[at + 6] = name.len() as u8;
+                data[at + 8..at + 8 + name.len()].copy_from_slice(name.as_bytes());
+                at += rec_len;
+            }
+        }
+
+        fn mount(self) -> Ext2 {
+            Ext2::mount(self.0).ok().expect("mount")
+        }
+    }
+
+    fn read(fs: &Ext2, path: &str) -> io::Result<Vec<u8>> {
+        let mut file = fs.open(Path::new(path))?.into_file().expect("a file");
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn names(fs: &Ext2, path: &str) -> io::Result<Vec<String>> {
+        let dir = fs.open(Path::new(path))?.into_dir().expect("a directory");
+        Ok(dir.entries()?.iter().map(|entry| entry.name().to_string()).collect())
+    }
+
+    /// Returns the message of the `InvalidData` error `result` should be.
+    fn invalid<T>(result: io::Result<T>) -> String {
+        let e = result.err().expect("should fail");
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        e.to_string()
+    }
+
+    /// Returns a volume with `hello.txt`, 1500 bytes in blocks 6 and 7, a
+    /// symbolic link, a deleted entry, and `sub`, a read-only directory in
+    /// block 8 holding `big`: a sparse file with one block through its
+    /// single indirect block and its last through its double indirect one.
+    fn populated() -> Volume {
+        let mut volume = Volume::new();
+        volume.dir(5, &[(ROOT, "."), (ROOT, ".."), (12, "hello.txt"), (13, "sub"),
+                        (14, "link"), (0, "deleted")]);
+
+        volume.inode(12, MODE_FILE | 0o644, 1500, &[6, 7]);
+        volume.fill(6, b'a');
+        volume.fill(7, b'b');
+        volume.inode(14, MODE_LINK | 0o777, 9, &[]);
+
+        volume.inode(13, MODE_DIR | 0o555, BLOCK_SIZE as u32, &[8]);
+        volume.dir(8, &[(13, "."), (ROOT, ".."), (15, "big")]);
+
+        let mut blocks = [0; 14];
+        blocks[12] = 10;
+        blocks[13] = 16;
+        volume.inode(15, MODE_FILE | 0o644, 269 * BLOCK_SIZE as u32, &blocks);
+        le::put_u32(volume.block(10), 0, 11);
+        volume.fill(11, b'i');
+        le::put_u32(volume.block(16), 0, 17);
+        le::put_u32(volume.block(17), 0, 18);
+        volume.fill(18, b'd');
+        volume
+    }
+
+    #[test]
+    fn reads_files_and_directories() {
+        let fs = populated().mount();
+
+        assert_eq!(names(&fs, "/").unwrap(), ["hello.txt", "sub"]);
+        assert_eq!(names(&fs, "/sub").unwrap(), ["big"]);
+
+        let hello = read(&fs, "/hello.txt").unwrap();
+        assert_eq!(hello.len(), 1500);
+        assert!(hello[..BLOCK_SIZE].iter().all(|&b| b == b'a'));
+        assert!(hello[BLOCK_SIZE..].iter().all(|&b| b == b'b'));
+
+        assert_eq!(fs.open(Path::new("/HELLO.TXT")).err().expect("case sensitive").kind(),
+                   io::ErrorKind::NotFound);
+        assert_eq!(fs.open(Path::new("/link")).err().expect("skipped").kind(),
+                   io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn metadata() {
+        let fs = populated().mount();
+
+        let hello = fs.open(Path::new("/hello.txt")).unwrap().metadata();
+        assert_eq!((hello.size, hello.read_only, hello.hidden), (1500, false, false));
+        assert_eq!(hello.modified, Some(DateTime::from_epoch(MTIME as u64)));
+        assert_eq!(hello.created, None);
+
+        let sub = fs.open(Path::new("/sub")).unwrap().metadata();
+        assert_eq!((sub.size, sub.read_only), (0, true));
+    }
+
+    #[test]
+    fn indirect_blocks_and_holes() {
+        let fs = populated().mount();
+
+        let big = read(&fs, "/sub/big").unwrap();
+        assert_eq!(big.len(), 269 * BLOCK_SIZE);
+        let blocks: Vec<&[u8]> = big.chunks(BLOCK_SIZE).collect();
+        assert!(blocks[..12].iter().all(|block| block.iter().all(|&b| b == 0)));
+        assert!(blocks[12].iter().all(|&b| b == b'i'));
+        assert!(blocks[13..268].iter().all(|block| block.iter().all(|&b| b == 0)));
+        assert!(blocks[268].iter().all(|&b| b == b'd'));
+    }
+
+    #[test]
+    fn not_ext2() {
+        let mut volume = Volume::new();
+        le::put_u16(volume.superblock(), 56, 0xEF52);
+        assert_eq!(invalid(Ext2::mount(volume.0)), "not an ext2 file system");
+    }
+
+    #[test]
+    fn unsupported_volumes() {
+        let mut volume = Volume::new();
+        le::put_u32(volume.superblock(), 24, 7);
+        assert_eq!(invalid(Ext2::mount(volume.0)), "unsupported ext2 block size");
+
+        // A revision 1 volume using extents.
+        let mut volume = Volume::new();
+        le::put_u32(volume.superblock(), 76, 1);
+        le::put_u16(volume.superblock(), 88, 128);
+        le::put_u32(volume.superblock(), 96, 0x0040);
+        assert_eq!(invalid(Ext2::mount(volume.0)), "unsupported ext2 features");
+    }
+
+    #[test]
+    fn corrupt_superblock() {
+        for &(offset, value) in [(32, 0), (40, 0)].iter() {
+            let mut volume = Volume::new();
+            le::put_u32(volume.superblock(), offset, value);
+            assert_eq!(invalid(Ext2::mount(volume.0)), "corrupt ext2 superblock");
+        }
+
+        // A revision 1 volume with inodes too small to hold their fields.
+        let mut volume = Volume::new();
+        le::put_u32(volume.superblock(), 76, 1);
+        le::put_u16(volume.superblock(), 88, 64);
+        assert_eq!(invalid(Ext2::mount(volume.0)), "corrupt ext2 superblock");
+    }
+
+    #[test]
+    fn too_many_groups() {
+        // Four billion groups: the descriptors run off the end of the disk
+        // long before they could run out of memory.
+        let mut volume = Volume::new();
+        le::put_u32(volume.superblock(), 4, u32::max_value());
+        le::put_u32(volume.superblock(), 32, 1);
+        assert!(Ext2::mount(volume.0).is_err());
+    }
+
+    #[test]
+    fn corrupt_directory_entries() {
+        // A zero record length, one past the end of the block, and a name
+        // longer than its record.
+        for &(offset, value) in [(4, 0), (4, 2048), (6, 13)].iter() {
+            let mut volume = populated();
+            let entry = 12;
+            match offset {
+                4 => le::put_u16(volume.block(5), entry + offset, value),
+                _ => volume.block(5)[entry + offset] = value as u8,
+            }
+            assert_eq!(invalid(names(&volume.mount(), "/")), "corrupt directory entry");
+        }
+    }
+
+    #[test]
+    fn inodes_out_of_range() {
+        let mut volume = populated();
+        volume.dir(8, &[(13, "."), (ROOT, ".."), (INODES + 1, "big")]);
+        assert_eq!(invalid(names(&volume.mount(), "/sub")), "inode out of range");
+
+        // More inodes than the one group has.
+        let mut volume = populated();
+        le::put_u32(volume.superblock(), 0, 2 * INODES);
+        volume.dir(8, &[(13, "."), (ROOT, ".."), (INODES + 1, "big")]);
+        assert_eq!(invalid(names(&volume.mount(), "/sub")), "inode out of range");
+    }
+
+    #[test]
+    fn sparse_directory() {
+        // A 4 GB root directory whose second block is a hole: reading it
+        // stops there, without first allocating 4 GB.
+        let mut volume = populated();
+        le::put_u32(volume.raw_inode(ROOT), 4, u32::max_value());
+        assert_eq!(invalid(names(&volume.mount(), "/")), "corrupt directory entry");
+    }
+}
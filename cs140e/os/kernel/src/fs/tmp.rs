@@ -0,0 +1,282 @@
+//! The temporary file system, mounted at `/tmp`: files and directories kept
+//! in the kernel heap, and lost when the machine restarts.
+
+use std::cmp::min;
+use std::collections::BTreeMap;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path};
+use std::sync::Arc;
+
+use fs::vfs::{self, Kind, Metadata};
+use mutex::Mutex;
+
+/// A directory's entries, by name.
+type Entries = BTreeMap<String, Node>;
+
+/// A file or directory. Clones share it.
+#[derive(Clone)]
+enum Node {
+    File(Arc<Mutex<Vec<u8>>>),
+    Dir(Arc<Mutex<Entries>>),
+}
+
+impl Node {
+    fn new_dir() -> Node {
+        Node::Dir(Arc::new(Mutex::new(BTreeMap::new())))
+    }
+
+    fn metadata(&self) -> Metadata {
+        match *self {
+            Node::File(ref data) => Metadata::new(Kind::File, data.lock().len() as u64, false),
+            Node::Dir(_) => Metadata::new(Kind::Dir, 0, false),
+        }
+    }
+}
+
+/// A temporary file system.
+pub struct TmpFs {
+    root: Node,
+}
+
+impl TmpFs {
+    /// Returns an empty file system.
+    pub fn new() -> TmpFs {
+        TmpFs { root: Node::new_dir() }
+    }
+
+    /// Returns the node at `path`.
+    fn lookup(&self, path: &Path) -> io::Result<Node> {
+        let mut node = self.root.clone();
+        for name in names(path) {
+            let child = match node {
+                Node::Dir(ref entries) => entries.lock().get(name).cloned(),
+                Node::File(_) => return Err(vfs::not_a_directory()),
+            };
+            node = child.ok_or_else(not_found)?;
+        }
+        Ok(node)
+    }
+
+    /// Returns the entries of the directory holding `path`, and the name of
+    /// `path` in it.
+    fn parent<'a>(&self, path: &'a Path) -> io::Result<(Arc<Mutex<Entries>>, &'a str)> {
+        let name = path.file_name().and_then(|name| name.to_str())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no file name"))?;
+        match self.lookup(path.parent().unwrap_or(Path::new("/")))? {
+            Node::Dir(entries) => Ok((entries, name)),
+            Node::File(_) => Err(vfs::not_a_directory()),
+        }
+    }
+}
+
+impl vfs::FileSystem for TmpFs {
+    fn open(&self, path: &Path) -> io::Result<Box<vfs::Entry>> {
+        let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+        let node = self.lookup(path)?;
+        Ok(Box::new(Entry { name: name.to_string(), node }))
+    }
+
+    fn create_file(&self, path: &Path) -> io::Result<Box<vfs::File>> {
+        let (parent, name) = self.parent(path)?;
+        let mut entries = parent.lock();
+        if entries.contains_key(name) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "file exists"));
+        }
+
+        let data = Arc::new(Mutex::new(Vec::new()));
+        entries.insert(name.to_string(), Node::File(data.clone()));
+        Ok(Box::new(File { data, pos: 0 }))
+    }
+
+    fn create_dir(&self, path: &Path, parents: bool) -> io::Result<Box<vfs::Dir>> {
+        let names = names(path);
+        let mut node = self.root.clone();
+        let mut created = false;
+        for (i, &name) in names.iter().enumerate() {
+            let entries = match node {
+                Node::Dir(entries) => entries,
+                Node::File(_) => return Err(vfs::not_a_directory()),
+            };
+
+            let mut entries = entries.lock();
+            node = match entries.get(name).cloned() {
+                Some(child) => child,
+                None if parents || i + 1 == names.len() => {
+                    created = true;
+                    let child = Node::new_dir();
+                    entries.insert(name.to_string(), child.clone());
+                    child
+                }
+                None => return Err(not_found()),
+            };
+        }
+
+        match (node, created || parents) {
+            (Node::Dir(entries), true) => Ok(Box::new(Dir(entries))),
+            _ => Err(io::Error::new(io::ErrorKind::AlreadyExists, "file exists")),
+        }
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        if to.starts_with(from) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "can't move a directory into itself"));
+        }
+
+        let (from_parent, from_name) = self.parent(from)?;
+        let (to_parent, to_name) = self.parent(to)?;
+        if Arc::ptr_eq(&from_parent, &to_parent) {
+            let mut entries = from_parent.lock();
+            if entries.contains_key(to_name) {
+                return Err(io::Error::new(io::ErrorKind::AlreadyExists, "file exists"));
+            }
+            let node = entries.remove(from_name).ok_or_else(not_found)?;
+            entries.insert(to_name.to_string(), node);
+            return Ok(());
+        }
+
+        // Directories are locked one at a time, so no two processes can
+        // deadlock renaming in opposite directions.
+        if to_parent.lock().contains_key(to_name) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "file exists"));
+        }
+        let node = from_parent.lock().remove(from_name).ok_or_else(not_found)?;
+        let mut to_entries = to_parent.lock();
+        if to_entries.contains_key(to_name) {
+            // Created meanwhile: put the entry back.
+            drop(to_entries);
+            from_parent.lock().insert(from_name.to_string(), node);
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "file exists"));
+        }
+        to_entries.insert(to_name.to_string(), node);
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path, children: bool) -> io::Result<()> {
+        let (parent, name) = self.parent(path)?;
+        let mut entries = parent.lock();
+        let empty = match entries.get(name) {
+            Some(&Node::Dir(ref dir)) => dir.lock().is_empty(),
+            Some(&Node::File(_)) => true,
+            None => return Err(not_found()),
+        };
+
+        if !empty && !children {
+            return Err(io::Error::new(io::ErrorKind::Other, "directory not empty"));
+        }
+        entries.remove(name);
+        Ok(())
+    }
+}
+
+/// Returns the names in `path`, which is canonical. See
+/// `vfs::canonicalize()`.
+fn names(path: &Path) -> Vec<&str> {
+    path.components().filter_map(|component| match component {
+        Component::Normal(name) => name.to_str(),
+        _ => None,
+    }).collect()
+}
+
+fn not_found() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "no such file or directory")
+}
+
+/// A node found by name.
+struct Entry {
+    name: String,
+    node: Node,
+}
+
+impl vfs::Entry for Entry {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn metadata(&self) -> Metadata {
+        self.node.metadata()
+    }
+
+    fn into_file(self: Box<Self>) -> Option<Box<vfs::File>> {
+        match self.node {
+            Node::File(data) => Some(Box::new(File { data, pos: 0 })),
+            Node::Dir(_) => None,
+        }
+    }
+
+    fn into_dir(self: Box<Self>) -> Option<Box<vfs::Dir>> {
+        match self.node {
+            Node::Dir(entries) => Some(Box::new(Dir(entries))),
+            Node::File(_) => None,
+        }
+    }
+}
+
+/// An open directory.
+struct Dir(Arc<Mutex<Entries>>);
+
+impl vfs::Dir for Dir {
+    fn entries(&self) -> io::Result<Vec<Box<vfs::Entry>>> {
+        Ok(self.0.lock().iter().map(|(name, node)| {
+            Box::new(Entry { name: name.clone(), node: node.clone() }) as Box<vfs::Entry>
+        }).collect())
+    }
+}
+
+/// An open file: its contents and the offset of the next read or write.
+struct File {
+    data: Arc<Mutex<Vec<u8>>>,
+    pos: u64,
+}
+
+impl Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let pos = self.pos;
+        let n = vfs::File::read_at(self, pos, buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Write for File {
+    /// Writes `buf` at the offset, first filling any gap past the end of
+    /// the file with zeros.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut data = self.data.lock();
+        let start = self.pos as usize;
+        let end = start.checked_add(buf.len())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "file too large"))?;
+        if end > data.len() {
+            data.resize(end, 0);
+        }
+
+        data[start..end].copy_from_slice(buf);
+        self.pos = end as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for File {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let size = self.data.lock().len() as u64;
+        self.pos = vfs::seek_position(pos, self.pos, size)?;
+        Ok(self.pos)
+    }
+}
+
+impl vfs::File for File {
+    fn size(&self) -> u64 {
+        self.data.lock().len() as u64
+    }
+
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.data.lock();
+        let start = min(offset, data.len() as u64) as usize;
+        let n = min(buf.len(), data.len() - start);
+        buf[..n].copy_from_slice(&data[start..start + n]);
+        Ok(n)
+    }
+}
@@ -0,0 +1,302 @@
+//! The virtual file system: the interface every file system implements, and
+//! the mount table that joins them into one tree of paths.
+//!
+//! A file system is mounted at an absolute path, its mount point, and a
+//! path is served by the file system mounted at its longest matching prefix,
+//! which sees the rest of the path as absolute within itself. The traits
+//! here are object safe, unlike the `fat32` crate's, so that file systems of
+//! different types can share the table.
+//!
+//! Every path given to the kernel passes through `canonicalize()` first, so
+//! file systems only ever see absolute paths free of `.`, `..`, and
+//! repeated slashes.
+
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+use clock::DateTime;
+
+/// What an entry is.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Kind {
+    File,
+    Dir,
+}
+
+/// What is known about an entry without opening it.
+#[derive(Debug, Copy, Clone)]
+pub struct Metadata {
+    pub kind: Kind,
+    /// The size in bytes. Always 0 for a directory.
+    pub size: u64,
+    pub read_only: bool,
+    /// Hidden from listings by convention, though `ls` shows it.
+    pub hidden: bool,
+    /// Belongs to the operating system, by convention.
+    pub system: bool,
+    /// When the entry was created, if the file system records it.
+    pub created: Option<DateTime>,
+    /// When the entry was last written, if the file system records it.
+    pub modified: Option<DateTime>,
+}
+
+impl Metadata {
+    /// Returns the metadata of an entry with no attributes or timestamps.
+    pub fn new(kind: Kind, size: u64, read_only: bool) -> Metadata {
+        Metadata { kind, size, read_only, hidden: false, system: false, created: None, modified: None }
+    }
+}
+
+/// An open file.
+///
+/// Reads return at most the rest of the device block or cluster the offset
+/// is in, so that a file can be streamed a chunk at a time. File systems
+/// that find a file's blocks by walking a chain keep their place in it, so
+/// that reading a file from start to end, by `read()` or `read_at()`, takes
+/// time linear in its size.
+pub trait File: io::Read + io::Write + io::Seek + Send {
+    /// Returns the size of the file in bytes.
+    fn size(&self) -> u64;
+
+    /// Reads from `offset` into `buf`, like `read()`, without moving the
+    /// file's offset. Returns 0 at or past the end of the file.
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let pos = self.seek(io::SeekFrom::Current(0))?;
+        self.seek(io::SeekFrom::Start(offset))?;
+        let result = self.read(buf);
+        self.seek(io::SeekFrom::Start(pos))?;
+        result
+    }
+
+    /// Writes any changes buffered in memory to the device.
+    fn sync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Returns whether a read may wait indefinitely, for input that may
+    /// never come, as one of the console does.
+    fn may_block(&self) -> bool {
+        false
+    }
+}
+
+/// An open directory.
+pub trait Dir: Send {
+    /// Returns the directory's entries, without `.` and `..`.
+    fn entries(&self) -> io::Result<Vec<Box<Entry>>>;
+}
+
+/// A file or directory, found by name but not yet opened as either.
+pub trait Entry: Send {
+    /// Returns the entry's name, without its parents.
+    fn name(&self) -> &str;
+
+    /// Returns the entry's metadata.
+    fn metadata(&self) -> Metadata;
+
+    /// Opens the entry as a file, or returns `None` if it is a directory.
+    fn into_file(self: Box<Self>) -> Option<Box<File>>;
+
+    /// Opens the entry as a directory, or returns `None` if it is a file.
+    fn into_dir(self: Box<Self>) -> Option<Box<Dir>>;
+}
+
+/// A file system that can be mounted. Paths passed to it are absolute
+/// within it. The operations that change the file system fail with
+/// `PermissionDenied` unless it implements them.
+pub trait FileSystem: Send + Sync {
+    /// Returns the entry at `path`.
+    fn open(&self, path: &Path) -> io::Result<Box<Entry>>;
+
+    /// Creates an empty file at `path`, whose parent must exist.
+    fn create_file(&self, _path: &Path) -> io::Result<Box<File>> {
+        Err(read_only())
+    }
+
+    /// Creates a directory at `path`, along with any missing parents if
+    /// `parents` is set.
+    fn create_dir(&self, _path: &Path, _parents: bool) -> io::Result<Box<Dir>> {
+        Err(read_only())
+    }
+
+    /// Moves the entry at `from` to `to`.
+    fn rename(&self, _from: &Path, _to: &Path) -> io::Result<()> {
+        Err(read_only())
+    }
+
+    /// Removes the entry at `path`. A directory must be empty unless
+    /// `children` is set, in which case its contents are removed too.
+    fn remove(&self, _path: &Path, _children: bool) -> io::Result<()> {
+        Err(read_only())
+    }
+
+    /// Writes any changes buffered in memory to the device.
+    fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Returns the error for changing a file system that can't be changed.
+pub fn read_only() -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, "read-only file system")
+}
+
+/// Returns the error for unmounting a file system that is in use.
+pub fn busy() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "file system busy")
+}
+
+/// The error for using a file as a directory, whether by opening it as one
+/// or by naming it in the middle of a path. Recognized by
+/// `is_not_a_directory()`, since `io::ErrorKind` has no kind for it.
+#[derive(Debug)]
+pub struct NotADirectory;
+
+impl fmt::Display for NotADirectory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("not a directory")
+    }
+}
+
+impl error::Error for NotADirectory {
+    fn description(&self) -> &str {
+        "not a directory"
+    }
+}
+
+/// Returns the error for using a file as a directory.
+pub fn not_a_directory() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, NotADirectory)
+}
+
+/// Returns `true` if `error` came from `not_a_directory()`.
+pub fn is_not_a_directory(error: &io::Error) -> bool {
+    error.get_ref().map_or(false, |e| e.is::<NotADirectory>())
+}
+
+/// Returns the offset `pos` seeks to in a file of `size` bytes whose offset
+/// is `current`, or an `InvalidInput` error if it is before the start.
+pub fn seek_position(pos: io::SeekFrom, current: u64, size: u64) -> io::Result<u64> {
+    let (base, offset) = match pos {
+        io::SeekFrom::Start(offset) => return Ok(offset),
+        io::SeekFrom::End(offset) => (size, offset),
+        io::SeekFrom::Current(offset) => (current, offset),
+    };
+
+    let pos = match offset >= 0 {
+        true => base.checked_add(offset as u64),
+        false => base.checked_sub(offset.wrapping_neg() as u64),
+    };
+    pos.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek before start"))
+}
+
+/// A file system mounted at a path.
+pub struct Mount {
+    /// The absolute path the file system is mounted at.
+    pub point: PathBuf,
+    /// The type of file system, such as `fat32`, for listing mounts.
+    pub kind: &'static str,
+    /// What the file system is on, such as `sd2`, for listing mounts.
+    pub source: String,
+    pub fs: Arc<FileSystem>,
+}
+
+/// The mount table.
+pub struct Vfs {
+    mounts: Vec<Mount>,
+}
+
+impl Vfs {
+    /// Returns a table with nothing mounted.
+    pub fn new() -> Vfs {
+        Vfs { mounts: Vec::new() }
+    }
+
+    /// Mounts `fs`, a file system of type `kind` on `source`, at the
+    /// absolute path `point`. Fails with `AlreadyExists` if something is
+    /// mounted there.
+    pub fn mount(&mut self, point: &Path, kind: &'static str, source: &str, fs: Arc<FileSystem>)
+        -> io::Result<()>
+    {
+        if !point.has_root() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "mount point not absolute"));
+        }
+
+        let point = canonicalize(Path::new("/"), point);
+        if self.mounts.iter().any(|m| m.point == point) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "already mounted"));
+        }
+
+        self.mounts.push(Mount { point, kind, source: source.to_string(), fs });
+        Ok(())
+    }
+
+    /// Removes the file system mounted at `point` and returns it. Fails with
+    /// `NotFound` if nothing is mounted there, and with `Other` if other file
+    /// systems are mounted below it or anything but the table holds it, such
+    /// as an open file.
+    pub fn unmount(&mut self, point: &Path) -> io::Result<Mount> {
+        let point = canonicalize(Path::new("/"), point);
+        let index = self.mounts.iter().position(|m| m.point == point)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "not mounted"))?;
+
+        if self.mounts.iter().any(|m| m.point != point && m.point.starts_with(&point)) {
+            return Err(io::Error::new(io::ErrorKind::Other, "file systems mounted below"));
+        }
+        if Arc::strong_count(&self.mounts[index].fs) > 1 {
+            return Err(busy());
+        }
+
+        Ok(self.mounts.remove(index))
+    }
+
+    /// Returns every mount, in the order they were made.
+    pub fn mounts(&self) -> &[Mount] {
+        &self.mounts
+    }
+
+    /// Returns the file system serving the absolute `path` and the path
+    /// within it. Fails with `NotFound` if nothing is mounted above `path`.
+    pub fn resolve(&self, path: &Path) -> io::Result<(Arc<FileSystem>, PathBuf)> {
+        if !path.has_root() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "path not absolute"));
+        }
+
+        let path = canonicalize(Path::new("/"), path);
+        let mount = self.mounts.iter()
+            .filter(|m| path.starts_with(&m.point))
+            .max_by_key(|m| m.point.components().count())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no file system mounted"))?;
+
+        let rest = path.strip_prefix(&mount.point).unwrap();
+        Ok((mount.fs.clone(), Path::new("/").join(rest)))
+    }
+}
+
+/// Returns the absolute, canonical form of `path`: relative to the absolute
+/// directory `cwd` unless it is absolute itself, with repeated slashes and
+/// `.` components removed and `..` components applied. `..` at the root
+/// stays at the root.
+///
+/// `..` is applied to the path as written, without looking at the file
+/// systems, which is sound since there are no symbolic links. So a `..`
+/// after a file name escapes it rather than failing; `FileSystem::open()`
+/// reports a file in the middle of a path instead.
+pub fn canonicalize(cwd: &Path, path: &Path) -> PathBuf {
+    let mut canonical = match path.has_root() {
+        true => PathBuf::from("/"),
+        false => canonicalize(Path::new("/"), cwd),
+    };
+
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => canonical.push(name),
+            Component::ParentDir => { canonical.pop(); }
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => {}
+        }
+    }
+    canonical
+}
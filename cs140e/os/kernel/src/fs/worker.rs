@@ -0,0 +1,247 @@
+//! File I/O for system calls, done by worker threads.
+//!
+//! System call handlers run in exception context, so they can't sleep, but
+//! file systems sleep on their locks and devices. So a handler hands its
+//! work to one of `WORKERS` kernel threads as an `Op` with `call()`, which
+//! parks the calling process; the handler then arranges for the `svc` to
+//! run again, as `wait` does, and when it does, once the worker has woken
+//! the process, `call()` returns the result.
+//!
+//! A worker is busy for as long as its operation takes, so a read of the
+//! console ties one up until input arrives, which may be never. Reads that
+//! may wait like that go to a pool of their own, `WAITING_WORKERS` strong,
+//! so that however many processes wait on input, the `WORKERS` that serve
+//! file systems stay free.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, SeekFrom};
+use std::mem;
+use std::path::PathBuf;
+
+use allocator::SlabArc;
+use console::log_warn;
+use fs::vfs::Metadata;
+use init::{kernel_init, Init, Stage};
+use kthread;
+use mutex::IrqMutex;
+use process::Id;
+use process::files::OpenFile;
+use scheduler;
+use sync::WaitQueue;
+use FILE_SYSTEM;
+
+/// The number of worker threads for operations that finish.
+pub const WORKERS: usize = 4;
+
+/// The number of worker threads for reads that may wait indefinitely; see
+/// `Op::may_block()`.
+pub const WAITING_WORKERS: usize = 4;
+
+/// An operation for a worker.
+pub enum Op {
+    /// `OpenFile::open()`, replying `File`.
+    Open(PathBuf, u64),
+    /// `OpenFile::read()`, replying `Data`.
+    Read(SlabArc<OpenFile>, usize),
+    /// `OpenFile::write()`, replying `Count`.
+    Write(SlabArc<OpenFile>, Vec<u8>),
+    /// `OpenFile::seek()`, replying `Count`.
+    Seek(SlabArc<OpenFile>, SeekFrom),
+    /// The metadata of the entry at a path, replying `Metadata`.
+    Stat(PathBuf),
+    /// `OpenFile::metadata()`, replying `Metadata`.
+    Fstat(SlabArc<OpenFile>),
+}
+
+/// The result of an `Op`.
+pub enum Reply {
+    File(OpenFile),
+    Data(Vec<u8>),
+    Count(u64),
+    Metadata(Metadata),
+}
+
+impl Op {
+    /// Returns whether the operation may wait indefinitely, as a read of
+    /// the console does.
+    fn may_block(&self) -> bool {
+        match *self {
+            Op::Read(ref file, _) => file.may_block(),
+            _ => false,
+        }
+    }
+
+    /// Does the operation, sleeping as it needs to.
+    fn run(self) -> io::Result<Reply> {
+        match self {
+            Op::Open(path, flags) => OpenFile::open(&path, flags).map(Reply::File),
+            Op::Read(file, len) => file.read(len).map(Reply::Data),
+            Op::Write(file, buf) => file.write(&buf).map(|n| Reply::Count(n as u64)),
+            Op::Seek(file, pos) => file.seek(pos).map(Reply::Count),
+            Op::Stat(path) => FILE_SYSTEM.open(&path).map(|entry| Reply::Metadata(entry.metadata())),
+            Op::Fstat(file) => Ok(Reply::Metadata(file.metadata())),
+        }
+    }
+}
+
+/// Where a process's operation is.
+enum State {
+    Queued(Op),
+    Running,
+    Done(io::Result<Reply>),
+}
+
+/// A process's operation, for system call `num`.
+struct Request {
+    num: u16,
+    state: State,
+}
+
+/// The operations of every process with one, and the order they were
+/// queued in, those that may wait indefinitely apart. A process has at most
+/// one, since it is parked until it is done.
+struct Requests {
+    by_process: BTreeMap<Id, Request>,
+    queue: VecDeque<Id>,
+    waiting: VecDeque<Id>,
+}
+
+impl Requests {
+    /// Takes the next queued operation to run, marking it running: one that
+    /// may wait indefinitely if `waiting` is set, and one that doesn't if
+    /// not.
+    fn next(&mut self, waiting: bool) -> Option<(Id, Op)> {
+        let queue = if waiting { &mut self.waiting } else { &mut self.queue };
+        while let Some(id) = queue.pop_front() {
+            if let Some(request) = self.by_process.get_mut(&id) {
+                if let State::Queued(op) = mem::replace(&mut request.state, State::Running) {
+                    return Some((id, op));
+                }
+            }
+        }
+        None
+    }
+}
+
+static REQUESTS: IrqMutex<Option<Requests>> = IrqMutex::new(None);
+
+/// Workers sleep here until an operation is queued, those for operations
+/// that may wait indefinitely on `QUEUED_WAITING`.
+static QUEUED: WaitQueue = WaitQueue::new();
+static QUEUED_WAITING: WaitQueue = WaitQueue::new();
+
+/// Runs `f` with the requests locked.
+fn with_requests<R, F: FnOnce(&mut Requests) -> R>(f: F) -> R {
+    let mut requests = REQUESTS.lock();
+    f(requests.get_or_insert_with(|| Requests {
+        by_process: BTreeMap::new(),
+        queue: VecDeque::new(),
+        waiting: VecDeque::new(),
+    }))
+}
+
+/// For system call `num`: returns the result of the running process's
+/// operation if it is done, or else queues the one `op()` returns, parks
+/// the process, and returns `None`. The caller must then arrange for the
+/// `svc` to run again, with the same arguments, once the process is woken.
+///
+/// A finished operation for a different system call, which a signal handler
+/// run in between must have made, is dropped and its call made again.
+pub fn call<F: FnOnce() -> Op>(num: u16, op: F) -> Option<io::Result<Reply>> {
+    let id = scheduler::current_id();
+    let mut waiting = false;
+    let (result, stale) = with_requests(|requests| {
+        let done = match requests.by_process.get(&id) {
+            Some(&Request { state: State::Done(_), .. }) => true,
+            _ => false,
+        };
+
+        let mut stale = None;
+        if done {
+            let request = requests.by_process.remove(&id).unwrap();
+            match (request.num == num, request.state) {
+                (true, State::Done(result)) => return (Some(result), None),
+                (_, state) => stale = Some(state),
+            }
+        }
+
+        // If the process was woken before its operation was done, it is
+        // still queued or running, and is parked again.
+        if !requests.by_process.contains_key(&id) {
+            let op = op();
+            waiting = op.may_block();
+            requests.by_process.insert(id, Request { num, state: State::Queued(op) });
+            if waiting {
+                requests.waiting.push_back(id);
+            } else {
+                requests.queue.push_back(id);
+            }
+        }
+        scheduler::block_current();
+        (None, stale)
+    });
+
+    drop(stale);
+    if result.is_none() {
+        let queued = if waiting { &QUEUED_WAITING } else { &QUEUED };
+        queued.wake_one();
+        scheduler::request_resched();
+    }
+    result
+}
+
+/// Forgets the operation of the process `id`, which has exited. One already
+/// running finishes, but its result is dropped.
+pub fn release(id: Id) {
+    let request = with_requests(|requests| requests.by_process.remove(&id));
+    // Any file it holds is closed here, outside the lock.
+    drop(request);
+}
+
+/// Returns `true` if this core has the requests locked, in which case
+/// `release()` would deadlock.
+pub fn is_locked_here() -> bool {
+    REQUESTS.is_held_by_current_core()
+}
+
+kernel_init!(WORKERS_INIT, Stage::Scheduler, "file I/O workers", Init::Plain(init));
+
+/// Starts the workers.
+fn init() {
+    for i in 0..WORKERS + WAITING_WORKERS {
+        let waiting = i >= WORKERS;
+        let name = if waiting { "fsio-wait" } else { "fsio" };
+        if kthread::spawn(name, move || work(waiting)).is_none() {
+            log_warn!("no memory for a file I/O worker");
+        }
+    }
+}
+
+/// A worker: runs queued operations, those that may wait indefinitely if
+/// `waiting` is set and the others if not, and wakes the processes they are
+/// for.
+fn work(waiting: bool) {
+    let queued = if waiting { &QUEUED_WAITING } else { &QUEUED };
+    loop {
+        let mut next = None;
+        queued.wait_until(|| {
+            next = with_requests(|requests| requests.next(waiting));
+            next.is_some()
+        });
+
+        let (id, op) = next.unwrap();
+        let result = op.run();
+        let (woken, dropped) = with_requests(|requests| match requests.by_process.get_mut(&id) {
+            Some(request) => {
+                request.state = State::Done(result);
+                (true, None)
+            }
+            None => (false, Some(result)),
+        });
+
+        drop(dropped);
+        if woken {
+            scheduler::wake(id);
+        }
+    }
+}
@@ -0,0 +1,806 @@
+//! A stub for the GDB remote serial protocol, so that the kernel can be
+//! debugged with breakpoints rather than print statements.
+//!
+//! The stub talks to GDB over the console's serial line, the mini UART. It
+//! is attached by the shell's `gdb` command, or by `gdb=on` on the kernel
+//! command line, which stop the kernel where it is to wait for GDB. Close
+//! the terminal program, then connect:
+//!
+//!     aarch64-none-elf-gdb build/kernel.elf -ex 'target remote /dev/ttyUSB0'
+//!
+//! While the stub is attached the serial line is GDB's: console output is
+//! sent as `O` packets, which GDB prints, and console input is ignored but
+//! for Ctrl-C, which GDB sends to stop the kernel. The stub detaches when
+//! GDB does.
+//!
+//! The kernel stops on a `brk` in kernel code, including the software
+//! breakpoints GDB sets, on a hit of a hardware breakpoint or watchpoint,
+//! which GDB's `hbreak`, `watch`, `rwatch`, and `awatch` set through
+//! `hwdebug`, after a single step, on Ctrl-C, and on an exception it can't
+//! handle, before it panics. GDB sees the context the exception
+//! interrupted, and may change its registers and any memory the translation
+//! tables map. Only the core that stopped waits for GDB, with IRQs masked;
+//! the others run on, and wait their turn if they stop too.
+
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use pi::interrupt::Interrupt;
+use pi::uart::MiniUart;
+
+use aarch64;
+use allocator::PAGE_SIZE;
+use boot::BootInfo;
+use console::{self, log_info, log_warn};
+use hwdebug::{self, Access};
+use init::{kernel_init, Init, Stage};
+use irq;
+use mutex::IrqMutex;
+use traps::{Syndrome, TrapFrame};
+
+/// The immediate of the `brk` instructions the stub plants as breakpoints
+/// and `attach()` executes.
+pub const BREAKPOINT_IMM: u16 = 0xDB;
+
+/// `brk #BREAKPOINT_IMM`, and the mask and value that match any `brk`.
+const BREAKPOINT: u32 = 0xD420_0000 | (BREAKPOINT_IMM as u32) << 5;
+const BRK_MASK: u32 = 0xFFE0_001F;
+const BRK: u32 = 0xD420_0000;
+
+/// The most software breakpoints set at once.
+const MAX_BREAKPOINTS: usize = 32;
+
+/// The longest packet exchanged, which the stub tells GDB.
+const PACKET_MAX: usize = 4096;
+
+/// The most bytes of console output sent in one `O` packet.
+const OUTPUT_CHUNK: usize = 256;
+
+/// Stop reasons, as GDB numbers the signals.
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGBUS: u8 = 10;
+const SIGSEGV: u8 = 11;
+
+/// Bits of `SPSR`: software step, the debug and IRQ masks, and the
+/// condition flags.
+const SPSR_SS: u64 = 1 << 21;
+const SPSR_D: u64 = 1 << 9;
+const SPSR_I: u64 = 1 << 7;
+const SPSR_FLAGS: u64 = 0xF << 28;
+
+/// GDB's numbers for the AArch64 registers after `x0` through `x30`.
+const REG_SP: usize = 31;
+const REG_PC: usize = 32;
+const REG_CPSR: usize = 33;
+const REG_V0: usize = 34;
+const REG_FPSR: usize = 66;
+const REG_FPCR: usize = 67;
+
+/// Whether GDB is attached.
+static ATTACHED: AtomicBool = AtomicBool::new(false);
+
+/// Whether a core is stopped in the stub, talking to GDB.
+static STOPPED: AtomicBool = AtomicBool::new(false);
+
+/// A software breakpoint: where it is, and the instruction it replaces
+/// while planted.
+#[derive(Debug, Copy, Clone)]
+struct Breakpoint {
+    addr: usize,
+    original: u32,
+}
+
+/// Why a single step is under way.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Stepping {
+    No,
+    /// GDB asked for it; the step stops.
+    Client,
+    /// Continuing from a breakpoint, which can't be planted until its
+    /// instruction has run.
+    OverBreakpoint,
+}
+
+/// How GDB resumed the kernel.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Resume {
+    Continue,
+    Step,
+    Detach,
+}
+
+/// What the stub keeps between stops.
+struct State {
+    breakpoints: [Option<Breakpoint>; MAX_BREAKPOINTS],
+    /// Whether the breakpoints' `brk`s are in memory.
+    planted: bool,
+    stepping: Stepping,
+    /// The stepped context's `SPSR.D` and `SPSR.I`, which stepping changes.
+    step_masks: u64,
+    /// Whether GDB resumed the kernel and is waiting for it to stop.
+    resumed: bool,
+    /// Why the kernel last stopped.
+    signal: u8,
+    /// The access and the watched address, if a watchpoint stopped it.
+    watch: Option<(Access, usize)>,
+}
+
+/// The stub: its state, and the packet being handled and its reply.
+struct Stub {
+    state: State,
+    packet: [u8; PACKET_MAX],
+    reply: Reply,
+}
+
+/// Locked for as long as a core is stopped, so the others wait their turn.
+static STUB: IrqMutex<Stub> = IrqMutex::new(Stub {
+    state: State {
+        breakpoints: [None; MAX_BREAKPOINTS],
+        planted: false,
+        stepping: Stepping::No,
+        step_masks: 0,
+        resumed: false,
+        signal: SIGTRAP,
+        watch: None,
+    },
+    packet: [0; PACKET_MAX],
+    reply: Reply { buf: [0; PACKET_MAX], len: 0 },
+});
+
+/// Returns whether GDB is attached.
+pub fn is_attached() -> bool {
+    ATTACHED.load(Ordering::Relaxed)
+}
+
+kernel_init!(GDBSTUB_INIT, Stage::Drivers, "gdb stub", Init::Boot(init));
+
+/// Attaches the stub at boot if the command line has `gdb=on`.
+fn init(info: &BootInfo) {
+    match info.param("gdb") {
+        Some("on") => attach(),
+        Some(value) => log_warn!("gdb: invalid setting {:?}; expected on", value),
+        None => {}
+    }
+}
+
+/// Attaches the stub and stops the kernel until GDB connects and resumes
+/// it. Does nothing if it is already attached.
+pub fn attach() {
+    if is_attached() {
+        return;
+    }
+
+    log_info!("gdb: waiting for gdb on the serial line");
+    console::flush();
+    ATTACHED.store(true, Ordering::Relaxed);
+
+    // `brk #BREAKPOINT_IMM`.
+    #[cfg(not(test))]
+    unsafe { asm!("brk #0xdb" : : : : "volatile"); }
+}
+
+/// Handles a `brk` exception: if GDB is attached and the `brk` is in kernel
+/// code, stops until GDB resumes. Returns `false`, leaving `tf` as it was,
+/// otherwise.
+pub fn handle_brk(tf: &mut TrapFrame) -> bool {
+    if !is_attached() || tf.el() != 1 {
+        return false;
+    }
+
+    stop(tf, SIGTRAP, None, true);
+    true
+}
+
+/// Stops until GDB resumes, if it is attached, on a hit of a hardware
+/// breakpoint or, with `watch`, the access and the watched address, of a
+/// watchpoint. Returns `false` if GDB isn't attached.
+pub fn hardware_stop(tf: &mut TrapFrame, watch: Option<(Access, usize)>) -> bool {
+    if !is_attached() {
+        return false;
+    }
+
+    stop(tf, SIGTRAP, watch, false);
+    true
+}
+
+/// Handles a software step exception: stops until GDB resumes if GDB asked
+/// for the step, and carries on if the stub stepped over a breakpoint.
+/// Returns `false` if the stub wasn't stepping.
+pub fn handle_step(tf: &mut TrapFrame) -> bool {
+    let stepping = STUB.lock().state.stepping;
+    match stepping {
+        Stepping::No => false,
+        Stepping::Client => {
+            stop(tf, SIGTRAP, None, false);
+            true
+        }
+        Stepping::OverBreakpoint => {
+            let mut stub = STUB.lock();
+            stub.state.end_step(tf);
+            stub.state.plant();
+            true
+        }
+    }
+}
+
+/// Stops until GDB resumes, if it is attached, because it sent Ctrl-C
+/// while the console was interrupted in `tf`.
+pub fn interrupt(tf: &mut TrapFrame) {
+    if is_attached() {
+        stop(tf, SIGINT, None, false);
+    }
+}
+
+/// Stops until GDB resumes, if it is attached, on an exception the kernel
+/// can't handle, so that GDB can look around before the panic.
+pub fn fault(tf: &mut TrapFrame) {
+    if !is_attached() {
+        return;
+    }
+
+    let signal = match Syndrome::from(tf.esr) {
+        Syndrome::DataAbort { .. } | Syndrome::InstructionAbort { .. } => SIGSEGV,
+        Syndrome::PcAlignmentFault | Syndrome::SpAlignmentFault => SIGBUS,
+        _ => SIGILL,
+    };
+    stop(tf, signal, None, false);
+}
+
+/// Writes `bytes` of console output to `uart` as `O` packets, which GDB
+/// prints, without waiting for GDB to acknowledge them. Output while a core
+/// is stopped would be taken for replies, so it is dropped.
+pub fn write_console(uart: &mut MiniUart, bytes: &[u8]) {
+    if STOPPED.load(Ordering::Relaxed) {
+        return;
+    }
+
+    for chunk in bytes.chunks(OUTPUT_CHUNK) {
+        let mut sum = b'O';
+        uart.write_bytes(b"$O");
+        for &byte in chunk {
+            for &digit in [hex_digit(byte >> 4), hex_digit(byte & 0xF)].iter() {
+                uart.write_byte(digit);
+                sum = sum.wrapping_add(digit);
+            }
+        }
+        uart.write_bytes(&[b'#', hex_digit(sum >> 4), hex_digit(sum & 0xF)]);
+    }
+}
+
+/// Stops in the context `tf` for `signal`, and `watch` if a watchpoint
+/// trapped, serving GDB's requests until it resumes or detaches. `at_brk`
+/// is set if `tf` stopped at a `brk`; one that isn't a planted breakpoint
+/// is stepped past.
+fn stop(tf: &mut TrapFrame, signal: u8, watch: Option<(Access, usize)>, at_brk: bool) {
+    let mut guard = STUB.lock();
+    let stub = &mut *guard;
+    STOPPED.store(true, Ordering::Relaxed);
+    let rx_enabled = irq::is_enabled(Interrupt::Aux);
+    irq::disable(Interrupt::Aux);
+
+    stub.state.lift();
+    stub.state.end_step(tf);
+    if at_brk && read_u32(tf.elr as usize).map_or(false, |insn| insn & BRK_MASK == BRK) {
+        tf.elr += 4;
+    }
+
+    let mut uart = MiniUart::new();
+    stub.state.signal = signal;
+    stub.state.watch = watch;
+    if stub.state.resumed {
+        stub.reply.clear();
+        stub.state.reply_stop(&mut stub.reply);
+        send(&mut uart, stub.reply.as_bytes());
+    }
+
+    let resume = stub.serve(&mut uart, tf);
+    match resume {
+        Resume::Continue if stub.state.breakpoint_at(tf.elr as usize) => {
+            stub.state.start_step(tf, Stepping::OverBreakpoint);
+        }
+        Resume::Continue => stub.state.plant(),
+        Resume::Step => stub.state.start_step(tf, Stepping::Client),
+        Resume::Detach => {
+            stub.state.breakpoints = [None; MAX_BREAKPOINTS];
+            ATTACHED.store(false, Ordering::Relaxed);
+        }
+    }
+    stub.state.resumed = resume != Resume::Detach;
+
+    if rx_enabled {
+        irq::enable(Interrupt::Aux);
+    }
+    STOPPED.store(false, Ordering::Relaxed);
+}
+
+impl Stub {
+    /// Serves GDB's requests until one resumes the kernel.
+    fn serve(&mut self, uart: &mut MiniUart, tf: &mut TrapFrame) -> Resume {
+        loop {
+            let len = receive(uart, &mut self.packet);
+            self.reply.clear();
+            let resume = handle(&mut self.state, &self.packet[..len], &mut self.reply, tf);
+            match resume {
+                Some(Resume::Continue) | Some(Resume::Step) => {}
+                None | Some(Resume::Detach) => send(uart, self.reply.as_bytes()),
+            }
+            if let Some(resume) = resume {
+                return resume;
+            }
+        }
+    }
+}
+
+/// Handles `packet`, writing the reply to `reply`, which is left empty for
+/// requests the stub doesn't support. Returns how to resume, if the packet
+/// resumes the kernel.
+fn handle(state: &mut State, packet: &[u8], reply: &mut Reply, tf: &mut TrapFrame)
+    -> Option<Resume>
+{
+    let (&command, args) = packet.split_first()?;
+    match command {
+        b'?' => state.reply_stop(reply),
+        b'g' => {
+            for n in 0..REG_FPCR + 1 {
+                write_register(tf, n, reply);
+            }
+        }
+        b'G' => {
+            let mut hex = args;
+            for n in 0..REG_FPCR + 1 {
+                let len = register_size(n) * 2;
+                if hex.len() < len {
+                    break;
+                }
+                set_register(tf, n, &hex[..len]);
+                hex = &hex[len..];
+            }
+            reply.push(b"OK");
+        }
+        b'p' => match parse_hex(args) {
+            Some(n) if n as usize <= REG_FPCR => write_register(tf, n as usize, reply),
+            _ => reply.push(b"E01"),
+        },
+        b'P' => {
+            let mut parts = args.splitn(2, |&b| b == b'=');
+            let n = parts.next().and_then(parse_hex).map(|n| n as usize);
+            match (n, parts.next()) {
+                (Some(n), Some(hex)) if n <= REG_FPCR && set_register(tf, n, hex) => {
+                    reply.push(b"OK")
+                }
+                _ => reply.push(b"E01"),
+            }
+        }
+        b'm' => match parse_range(args) {
+            Some((addr, len)) => read_memory(addr, len, reply),
+            None => reply.push(b"E01"),
+        },
+        b'M' => {
+            let mut parts = args.splitn(2, |&b| b == b':');
+            match (parts.next().and_then(parse_range), parts.next()) {
+                (Some((addr, len)), Some(hex)) if hex.len() == len * 2 => {
+                    if write_memory(addr, hex) {
+                        reply.push(b"OK");
+                    } else {
+                        reply.push(b"E14");
+                    }
+                }
+                _ => reply.push(b"E01"),
+            }
+        }
+        b'c' | b's' => {
+            if let Some(addr) = parse_hex(args) {
+                tf.elr = addr;
+            }
+            return Some(if command == b'c' { Resume::Continue } else { Resume::Step });
+        }
+        b'D' => {
+            reply.push(b"OK");
+            return Some(Resume::Detach);
+        }
+        b'k' => return Some(Resume::Detach),
+        b'Z' | b'z' if args.starts_with(b"0,") => {
+            let addr = parse_range(&args[2..]).map(|(addr, _)| addr);
+            let done = match (command, addr) {
+                (b'Z', Some(addr)) => state.add_breakpoint(addr),
+                (_, Some(addr)) => {
+                    state.remove_breakpoint(addr);
+                    true
+                }
+                (_, None) => false,
+            };
+            if done {
+                reply.push(b"OK");
+            } else {
+                reply.push(b"E01");
+            }
+        }
+        // Hardware breakpoints (1), and write (2), read (3), and access (4)
+        // watchpoints.
+        b'Z' | b'z' if args.len() > 2 && b"1234".contains(&args[0]) && args[1] == b',' => {
+            let done = match (command, args[0], parse_range(&args[2..])) {
+                (_, _, None) => false,
+                (b'Z', b'1', Some((addr, _))) => hwdebug::set_breakpoint(addr).is_ok(),
+                (b'Z', kind, Some((addr, len))) => {
+                    hwdebug::set_watchpoint(addr, len, watch_access(kind)).is_ok()
+                }
+                (_, b'1', Some((addr, _))) => {
+                    hwdebug::clear_breakpoint(addr);
+                    true
+                }
+                (_, _, Some((addr, _))) => {
+                    hwdebug::clear_watchpoint(addr);
+                    true
+                }
+            };
+            if done {
+                reply.push(b"OK");
+            } else {
+                reply.push(b"E01");
+            }
+        }
+        b'q' if args.starts_with(b"Supported") => {
+            reply.push(b"PacketSize=");
+            reply.hex_be(PACKET_MAX as u64);
+        }
+        b'q' if args.starts_with(b"Attached") => reply.push(b"1"),
+        b'H' => reply.push(b"OK"),
+        _ => {}
+    }
+    None
+}
+
+impl State {
+    /// Writes the stop reply for the last stop to `reply`, naming the
+    /// watched address if a watchpoint trapped.
+    fn reply_stop(&self, reply: &mut Reply) {
+        match self.watch {
+            Some((access, addr)) => {
+                let kind: &[u8] = match access {
+                    Access::Write => b"watch:",
+                    Access::Read => b"rwatch:",
+                    Access::ReadWrite => b"awatch:",
+                };
+                reply.push(b"T");
+                reply.hex(self.signal);
+                reply.push(kind);
+                reply.hex_be(addr as u64);
+                reply.push(b";");
+            }
+            None => {
+                reply.push(b"S");
+                reply.hex(self.signal);
+            }
+        }
+    }
+
+    fn breakpoint_at(&self, addr: usize) -> bool {
+        self.breakpoints.iter().any(|bp| bp.map(|bp| bp.addr) == Some(addr))
+    }
+
+    /// Adds a breakpoint at `addr`, which is planted when the kernel is
+    /// resumed. Fails if `addr` can't hold one or there are too many.
+    fn add_breakpoint(&mut self, addr: usize) -> bool {
+        if addr % 4 != 0 || !aarch64::kernel_can_write(addr) {
+            return false;
+        }
+        if self.breakpoint_at(addr) {
+            return true;
+        }
+
+        match self.breakpoints.iter_mut().find(|bp| bp.is_none()) {
+            Some(slot) => {
+                *slot = Some(Breakpoint { addr, original: 0 });
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn remove_breakpoint(&mut self, addr: usize) {
+        for slot in self.breakpoints.iter_mut() {
+            if slot.map(|bp| bp.addr) == Some(addr) {
+                *slot = None;
+            }
+        }
+    }
+
+    /// Writes a `brk` over the instruction at each breakpoint.
+    fn plant(&mut self) {
+        if self.planted {
+            return;
+        }
+
+        for bp in self.breakpoints.iter_mut().filter_map(|bp| bp.as_mut()) {
+            if let Some(original) = read_u32(bp.addr) {
+                bp.original = original;
+                unsafe { ptr::write_volatile(bp.addr as *mut u32, BREAKPOINT) };
+                aarch64::sync_icache(bp.addr, 4);
+            }
+        }
+        self.planted = true;
+    }
+
+    /// Puts back the instructions the breakpoints replaced.
+    fn lift(&mut self) {
+        if !self.planted {
+            return;
+        }
+
+        for bp in self.breakpoints.iter().filter_map(|bp| bp.as_ref()) {
+            if read_u32(bp.addr) == Some(BREAKPOINT) {
+                unsafe { ptr::write_volatile(bp.addr as *mut u32, bp.original) };
+                aarch64::sync_icache(bp.addr, 4);
+            }
+        }
+        self.planted = false;
+    }
+
+    /// Arranges for `tf` to run one instruction, with IRQs masked, and
+    /// then take a software step exception.
+    fn start_step(&mut self, tf: &mut TrapFrame, why: Stepping) {
+        self.stepping = why;
+        self.step_masks = tf.spsr & (SPSR_D | SPSR_I);
+        tf.spsr = (tf.spsr | SPSR_SS | SPSR_I) & !SPSR_D;
+        aarch64::set_single_step(true);
+    }
+
+    /// Ends the step under way, if any, restoring `tf`'s masks.
+    fn end_step(&mut self, tf: &mut TrapFrame) {
+        if self.stepping == Stepping::No {
+            return;
+        }
+
+        aarch64::set_single_step(false);
+        tf.spsr = tf.spsr & !(SPSR_SS | SPSR_D | SPSR_I) | self.step_masks;
+        self.stepping = Stepping::No;
+    }
+}
+
+/// Returns the access a watchpoint of `kind`, in a `Z` packet, traps on.
+fn watch_access(kind: u8) -> Access {
+    match kind {
+        b'2' => Access::Write,
+        b'3' => Access::Read,
+        _ => Access::ReadWrite,
+    }
+}
+
+/// Returns the size in bytes of register `n`.
+fn register_size(n: usize) -> usize {
+    match n {
+        REG_CPSR | REG_FPSR | REG_FPCR => 4,
+        n if n >= REG_V0 => 16,
+        _ => 8,
+    }
+}
+
+/// Returns the stack pointer of the context `tf`. A trap from EL1 pushed
+/// `tf` onto the stack it interrupted.
+fn stack_pointer(tf: &TrapFrame) -> u64 {
+    match tf.el() {
+        0 => tf.sp_el0,
+        _ => tf as *const TrapFrame as u64 + mem::size_of::<TrapFrame>() as u64,
+    }
+}
+
+/// Writes register `n` of `tf` to `reply`, or `x`s if it isn't saved.
+fn write_register(tf: &TrapFrame, n: usize, reply: &mut Reply) {
+    let value = match n {
+        0...30 => tf.x[n] as u128,
+        REG_SP => stack_pointer(tf) as u128,
+        REG_PC => tf.elr as u128,
+        REG_CPSR => tf.spsr as u32 as u128,
+        REG_FPSR | REG_FPCR => {
+            reply.push(b"xxxxxxxx");
+            return;
+        }
+        _ => tf.q[n - REG_V0],
+    };
+    reply.hex_le(value, register_size(n));
+}
+
+/// Sets register `n` of `tf` from `hex`, GDB's encoding of its bytes.
+/// Returns `false` if `hex` is malformed or the register can't be set: the
+/// kernel stack pointer, which `tf` is on, and the FP status registers,
+/// which it doesn't hold. Only the condition flags of `CPSR` can be set.
+fn set_register(tf: &mut TrapFrame, n: usize, hex: &[u8]) -> bool {
+    let size = register_size(n);
+    let mut value: u128 = 0;
+    if hex.len() != size * 2 {
+        return false;
+    }
+    for i in (0..size).rev() {
+        match parse_byte(&hex[i * 2..i * 2 + 2]) {
+            Some(byte) => value = value << 8 | byte as u128,
+            None => return false,
+        }
+    }
+
+    match n {
+        0...30 => tf.x[n] = value as u64,
+        REG_SP if tf.el() == 0 => tf.sp_el0 = value as u64,
+        REG_PC => tf.elr = value as u64,
+        REG_CPSR => tf.spsr = tf.spsr & !SPSR_FLAGS | value as u64 & SPSR_FLAGS,
+        REG_SP | REG_FPSR | REG_FPCR => return false,
+        _ => tf.q[n - REG_V0] = value,
+    }
+    true
+}
+
+/// Writes the `len` bytes at `addr` to `reply`, as many as fit, stopping at
+/// the first unmapped page. Replies with an error if none can be read.
+fn read_memory(addr: usize, len: usize, reply: &mut Reply) {
+    let len = len.min(PACKET_MAX / 2);
+    for a in addr..addr.saturating_add(len) {
+        if (a == addr || a % PAGE_SIZE == 0) && !aarch64::kernel_can_read(a) {
+            break;
+        }
+        reply.hex(unsafe { ptr::read_volatile(a as *const u8) });
+    }
+
+    if reply.len == 0 && len > 0 {
+        reply.push(b"E14");
+    }
+}
+
+/// Writes the bytes `hex` encodes to `addr`, making any instructions among
+/// them visible to instruction fetch. Returns `false`, having written
+/// nothing, if any of them can't be written.
+fn write_memory(addr: usize, hex: &[u8]) -> bool {
+    let len = hex.len() / 2;
+    let writable = (addr..addr.saturating_add(len))
+        .filter(|&a| a == addr || a % PAGE_SIZE == 0)
+        .all(|a| aarch64::kernel_can_write(a));
+    if !writable || hex.chunks(2).any(|pair| parse_byte(pair).is_none()) {
+        return false;
+    }
+
+    for (i, pair) in hex.chunks(2).enumerate() {
+        unsafe { ptr::write_volatile((addr + i) as *mut u8, parse_byte(pair).unwrap()) };
+    }
+    aarch64::sync_icache(addr, len);
+    true
+}
+
+/// Returns the instruction at `addr`, if it can be read.
+fn read_u32(addr: usize) -> Option<u32> {
+    if addr % 4 == 0 && aarch64::kernel_can_read(addr) {
+        Some(unsafe { ptr::read_volatile(addr as *const u32) })
+    } else {
+        None
+    }
+}
+
+/// Waits for a packet from GDB, acknowledges it, and returns the length of
+/// its contents, which are copied to `buf` and cut short if they don't fit.
+/// Packets with a bad checksum are asked for again.
+fn receive(uart: &mut MiniUart, buf: &mut [u8]) -> usize {
+    loop {
+        while uart.read_byte() != b'$' {}
+
+        let mut len = 0;
+        let mut sum: u8 = 0;
+        loop {
+            let byte = uart.read_byte();
+            if byte == b'#' {
+                break;
+            }
+            sum = sum.wrapping_add(byte);
+            if len < buf.len() {
+                buf[len] = byte;
+                len += 1;
+            }
+        }
+
+        let checksum = [uart.read_byte(), uart.read_byte()];
+        if parse_byte(&checksum) == Some(sum) {
+            uart.write_byte(b'+');
+            return len;
+        }
+        uart.write_byte(b'-');
+    }
+}
+
+/// Sends `data` as a packet, again until GDB acknowledges it.
+fn send(uart: &mut MiniUart, data: &[u8]) {
+    let sum = data.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+    loop {
+        uart.write_byte(b'$');
+        uart.write_bytes(data);
+        uart.write_bytes(&[b'#', hex_digit(sum >> 4), hex_digit(sum & 0xF)]);
+
+        loop {
+            match uart.read_byte() {
+                b'+' => return,
+                b'-' => break,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// A reply being built, cut short if it grows past `PACKET_MAX`.
+struct Reply {
+    buf: [u8; PACKET_MAX],
+    len: usize,
+}
+
+impl Reply {
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            if self.len < PACKET_MAX {
+                self.buf[self.len] = byte;
+                self.len += 1;
+            }
+        }
+    }
+
+    /// Appends `byte` as two hex digits.
+    fn hex(&mut self, byte: u8) {
+        self.push(&[hex_digit(byte >> 4), hex_digit(byte & 0xF)]);
+    }
+
+    /// Appends the `size` low bytes of `value`, least significant first, as
+    /// registers are sent.
+    fn hex_le(&mut self, value: u128, size: usize) {
+        for i in 0..size {
+            self.hex((value >> (8 * i)) as u8);
+        }
+    }
+
+    /// Appends `value` as a hex number, without leading zeros.
+    fn hex_be(&mut self, value: u64) {
+        let digits = (64 - value.leading_zeros() as usize + 3) / 4;
+        for i in (0..digits.max(1)).rev() {
+            self.push(&[hex_digit((value >> (4 * i)) as u8 & 0xF)]);
+        }
+    }
+}
+
+fn hex_digit(nibble: u8) -> u8 {
+    b"0123456789abcdef"[nibble as usize]
+}
+
+/// Parses the two hex digits in `pair`.
+fn parse_byte(pair: &[u8]) -> Option<u8> {
+    match pair {
+        [high, low] => {
+            let high = (*high as char).to_digit(16)?;
+            let low = (*low as char).to_digit(16)?;
+            Some((high << 4 | low) as u8)
+        }
+        _ => None,
+    }
+}
+
+/// Parses `hex`, a hex number.
+fn parse_hex(hex: &[u8]) -> Option<u64> {
+    if hex.is_empty() || hex.len() > 16 {
+        return None;
+    }
+    let mut value = 0;
+    for &b in hex {
+        value = value << 4 | (b as char).to_digit(16)? as u64;
+    }
+    Some(value)
+}
+
+/// Parses `ADDR,LEN`, in hex, as GDB sends memory ranges and breakpoints.
+fn parse_range(args: &[u8]) -> Option<(usize, usize)> {
+    let mut parts = args.splitn(2, |&b| b == b',');
+    let addr = parse_hex(parts.next()?)?;
+    let len = parse_hex(parts.next()?)?;
+    Some((addr as usize, len as usize))
+}
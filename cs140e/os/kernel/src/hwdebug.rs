@@ -0,0 +1,420 @@
+//! Hardware breakpoints and watchpoints, from the cores' debug registers.
+//!
+//! A breakpoint traps when a core is about to execute the instruction at
+//! its address, and a watchpoint when one is about to load or store any of
+//! the bytes it watches, without changing memory as a `brk` does: they work
+//! in code that can't be written, and catch whatever corrupts a variable
+//! the moment it does. Both apply to EL1 only, on every core; the
+//! Cortex-A53 has `MAX_BREAKPOINTS` and `MAX_WATCHPOINTS` of them.
+//!
+//! Points are kept in one table, which each core loads into its registers
+//! when it changes: the core changing it at once, the others at their next
+//! tick. A hit is reported with the registers of the context that hit it,
+//! or, if GDB is attached, stops the kernel for GDB, which can also set
+//! points itself. The instruction that hit is then stepped with the points
+//! disabled on that core, so that it can run, and the points are restored.
+
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use aarch64;
+use backtrace;
+use console::ekprintln;
+use gdbstub;
+use ksyms;
+use mutex::IrqMutex;
+use smp::MAX_CORES;
+use traps::TrapFrame;
+
+/// The number of breakpoints and watchpoints.
+pub const MAX_BREAKPOINTS: usize = 6;
+pub const MAX_WATCHPOINTS: usize = 4;
+
+/// Bits of `DBGBCR<n>_EL1` and `DBGWCR<n>_EL1`: enabled, for EL1 only, and,
+/// for a breakpoint, matching the whole instruction.
+const CR_ENABLE: u32 = 1;
+const CR_EL1: u32 = 0b01 << 1;
+const BCR_BAS_ALL: u32 = 0b1111 << 5;
+
+/// The shift of the load/store control and byte address select fields of
+/// `DBGWCR<n>_EL1`.
+const WCR_LSC_SHIFT: u32 = 3;
+const WCR_BAS_SHIFT: u32 = 5;
+
+/// `ESR.ISS.WnR` for a watchpoint: set if a store hit it.
+const ESR_WNR: u64 = 1 << 6;
+
+/// Bits of `SPSR`: software step, and the debug and IRQ masks.
+const SPSR_SS: u64 = 1 << 21;
+const SPSR_D: u64 = 1 << 9;
+const SPSR_I: u64 = 1 << 7;
+
+/// The accesses a watchpoint traps on.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Access {
+    /// Returns the value of `DBGWCR<n>_EL1.LSC` for this access.
+    fn lsc(self) -> u32 {
+        match self {
+            Access::Read => 0b01,
+            Access::Write => 0b10,
+            Access::ReadWrite => 0b11,
+        }
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Access::Read => "read",
+            Access::Write => "write",
+            Access::ReadWrite => "read/write",
+        })
+    }
+}
+
+/// An error setting a point.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// Every breakpoint or watchpoint is in use.
+    Full,
+    /// The address isn't aligned, or the watched bytes aren't 1, 2, 4, or 8
+    /// within an aligned doubleword.
+    Unaligned,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Error::Full => "no free debug registers",
+            Error::Unaligned => "address or length not aligned",
+        })
+    }
+}
+
+/// A breakpoint, and how many times it has been hit.
+#[derive(Debug, Copy, Clone)]
+pub struct Breakpoint {
+    pub addr: usize,
+    pub hits: usize,
+}
+
+/// A watchpoint: the `len` bytes at `addr`, and how many times they have
+/// been accessed.
+#[derive(Debug, Copy, Clone)]
+pub struct Watchpoint {
+    pub addr: usize,
+    pub len: usize,
+    pub access: Access,
+    pub hits: usize,
+}
+
+impl Watchpoint {
+    /// Returns whether an access that reported `addr` can be one this
+    /// watchpoint trapped: the address reported is that of the access,
+    /// which may be wider than the bytes watched, so any in the same
+    /// doubleword matches.
+    fn matches(&self, addr: usize) -> bool {
+        addr & !7 == self.addr & !7
+    }
+
+    /// Returns the value of `DBGWCR<n>_EL1` for this watchpoint, which
+    /// selects bytes of the doubleword that holds them.
+    fn control(&self) -> u32 {
+        let bytes = ((1u32 << self.len) - 1) << (self.addr % 8);
+        CR_ENABLE | CR_EL1 | self.access.lsc() << WCR_LSC_SHIFT | bytes << WCR_BAS_SHIFT
+    }
+}
+
+struct Points {
+    breakpoints: [Option<Breakpoint>; MAX_BREAKPOINTS],
+    watchpoints: [Option<Watchpoint>; MAX_WATCHPOINTS],
+}
+
+static POINTS: IrqMutex<Points> = IrqMutex::new(Points {
+    breakpoints: [None; MAX_BREAKPOINTS],
+    watchpoints: [None; MAX_WATCHPOINTS],
+});
+
+/// The version of the table, bumped on every change, and the version each
+/// core's registers hold.
+static VERSION: AtomicUsize = AtomicUsize::new(0);
+static LOADED: [AtomicUsize; MAX_CORES] = [
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+];
+
+/// Whether each core is stepping the instruction that hit a point: not, in
+/// a step of its own, or in one GDB started.
+const NOT_STEPPING: usize = 0;
+const STEPPING: usize = 1;
+const STEPPING_FOR_GDB: usize = 2;
+static STEP: [AtomicUsize; MAX_CORES] = [
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+];
+
+/// The stepped context's `SPSR.D` and `SPSR.I` on each core, which stepping
+/// changes.
+static STEP_MASKS: [AtomicUsize; MAX_CORES] = [
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+];
+
+/// Returns the breakpoints that are set.
+pub fn breakpoints() -> [Option<Breakpoint>; MAX_BREAKPOINTS] {
+    POINTS.lock().breakpoints
+}
+
+/// Returns the watchpoints that are set.
+pub fn watchpoints() -> [Option<Watchpoint>; MAX_WATCHPOINTS] {
+    POINTS.lock().watchpoints
+}
+
+/// Sets a breakpoint at the instruction at `addr`. Setting one that is
+/// already set succeeds.
+pub fn set_breakpoint(addr: usize) -> Result<(), Error> {
+    if addr % 4 != 0 {
+        return Err(Error::Unaligned);
+    }
+
+    {
+        let mut points = POINTS.lock();
+        if points.breakpoints.iter().any(|bp| bp.map(|bp| bp.addr) == Some(addr)) {
+            return Ok(());
+        }
+        let index = points.breakpoints.iter().position(|bp| bp.is_none()).ok_or(Error::Full)?;
+        points.breakpoints[index] = Some(Breakpoint { addr, hits: 0 });
+    }
+    changed();
+    Ok(())
+}
+
+/// Clears the breakpoint at `addr`, returning whether there was one.
+pub fn clear_breakpoint(addr: usize) -> bool {
+    let cleared = {
+        let mut points = POINTS.lock();
+        let slot = points.breakpoints.iter_mut().find(|bp| bp.map(|bp| bp.addr) == Some(addr));
+        match slot {
+            Some(slot) => slot.take().is_some(),
+            None => false,
+        }
+    };
+    if cleared {
+        changed();
+    }
+    cleared
+}
+
+/// Sets a watchpoint on the `len` bytes at `addr`, which must be 1, 2, 4,
+/// or 8 bytes within an aligned doubleword. Setting one that is already set
+/// changes the accesses it traps on.
+pub fn set_watchpoint(addr: usize, len: usize, access: Access) -> Result<(), Error> {
+    if ![1, 2, 4, 8].contains(&len) || addr % 8 + len > 8 {
+        return Err(Error::Unaligned);
+    }
+
+    {
+        let mut points = POINTS.lock();
+        let existing = points.watchpoints.iter()
+            .position(|wp| wp.map(|wp| (wp.addr, wp.len)) == Some((addr, len)));
+        let index = match existing {
+            Some(index) => index,
+            None => points.watchpoints.iter().position(|wp| wp.is_none()).ok_or(Error::Full)?,
+        };
+        points.watchpoints[index] = Some(Watchpoint { addr, len, access, hits: 0 });
+    }
+    changed();
+    Ok(())
+}
+
+/// Clears the watchpoint at `addr`, returning whether there was one.
+pub fn clear_watchpoint(addr: usize) -> bool {
+    let cleared = {
+        let mut points = POINTS.lock();
+        let slot = points.watchpoints.iter_mut().find(|wp| wp.map(|wp| wp.addr) == Some(addr));
+        match slot {
+            Some(slot) => slot.take().is_some(),
+            None => false,
+        }
+    };
+    if cleared {
+        changed();
+    }
+    cleared
+}
+
+/// Notes that the table changed, and loads it into this core's registers.
+fn changed() {
+    VERSION.fetch_add(1, Ordering::Relaxed);
+    sync();
+}
+
+/// Loads the table into this core's registers if it changed since they
+/// were last loaded. Called by each core's tick.
+pub fn sync() {
+    let core = aarch64::affinity();
+    let version = VERSION.load(Ordering::Relaxed);
+    if LOADED[core].load(Ordering::Relaxed) != version
+        && STEP[core].load(Ordering::Relaxed) == NOT_STEPPING
+    {
+        load();
+        LOADED[core].store(version, Ordering::Relaxed);
+    }
+}
+
+/// Writes the table to this core's registers.
+fn load() {
+    let points = POINTS.lock();
+    aarch64::enable_debug_exceptions();
+    for (n, bp) in points.breakpoints.iter().enumerate() {
+        match *bp {
+            Some(bp) => aarch64::set_hw_breakpoint(n, bp.addr as u64, CR_ENABLE | CR_EL1
+                                                   | BCR_BAS_ALL),
+            None => aarch64::set_hw_breakpoint(n, 0, 0),
+        }
+    }
+    for (n, wp) in points.watchpoints.iter().enumerate() {
+        match *wp {
+            Some(wp) => aarch64::set_hw_watchpoint(n, (wp.addr & !7) as u64, wp.control()),
+            None => aarch64::set_hw_watchpoint(n, 0, 0),
+        }
+    }
+}
+
+/// Disables every point in this core's registers.
+fn unload() {
+    for n in 0..MAX_BREAKPOINTS {
+        aarch64::set_hw_breakpoint(n, 0, 0);
+    }
+    for n in 0..MAX_WATCHPOINTS {
+        aarch64::set_hw_watchpoint(n, 0, 0);
+    }
+}
+
+/// Handles a breakpoint exception: reports the hit, or stops for GDB, then
+/// steps the instruction.
+pub fn handle_breakpoint(tf: &mut TrapFrame) {
+    let addr = tf.elr as usize;
+    let index = {
+        let mut points = POINTS.lock();
+        let bp = points.breakpoints.iter_mut().enumerate()
+            .filter_map(|(index, bp)| bp.as_mut().map(|bp| (index, bp)))
+            .find(|&(_, ref bp)| bp.addr == addr);
+        match bp {
+            Some((index, bp)) => {
+                bp.hits += 1;
+                Some(index)
+            }
+            None => None,
+        }
+    };
+
+    if !gdbstub::hardware_stop(tf, None) {
+        match index {
+            Some(index) => ekprintln!("breakpoint {} hit at {}", index, Location(addr)),
+            None => ekprintln!("breakpoint hit at {}", Location(addr)),
+        }
+        report(tf);
+    }
+    step_over(tf);
+}
+
+/// Handles a watchpoint exception: reports the access, or stops for GDB,
+/// then steps the instruction that made it.
+pub fn handle_watchpoint(tf: &mut TrapFrame) {
+    let addr = tf.far as usize;
+    let write = tf.esr & ESR_WNR != 0;
+    let hit = {
+        let mut points = POINTS.lock();
+        let wp = points.watchpoints.iter_mut().enumerate()
+            .filter_map(|(index, wp)| wp.as_mut().map(|wp| (index, wp)))
+            .find(|&(_, ref wp)| wp.matches(addr));
+        match wp {
+            Some((index, wp)) => {
+                wp.hits += 1;
+                Some((index, *wp))
+            }
+            None => None,
+        }
+    };
+
+    let access = if write { Access::Write } else { Access::Read };
+    let watched = hit.map_or(addr, |(_, wp)| wp.addr);
+    if !gdbstub::hardware_stop(tf, Some((access, watched))) {
+        let kind = if write { "write to" } else { "read of" };
+        match hit {
+            Some((index, wp)) => ekprintln!("watchpoint {} ({} bytes at {:#x}): {} {:#x} at {}",
+                                            index, wp.len, wp.addr, kind, addr,
+                                            Location(tf.elr as usize)),
+            None => ekprintln!("watchpoint: {} {:#x} at {}", kind, addr,
+                               Location(tf.elr as usize)),
+        }
+        report(tf);
+    }
+    step_over(tf);
+}
+
+/// An address, shown with the function it is in if that is known. Hits are
+/// reported without allocating, in case the heap's lock is held.
+struct Location(usize);
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match ksyms::lookup(self.0) {
+            Some(symbol) => write!(f, "{:#x} ({})", self.0, symbol),
+            None => write!(f, "{:#x}", self.0),
+        }
+    }
+}
+
+/// Prints the registers of `tf` and a backtrace of it.
+fn report(tf: &TrapFrame) {
+    tf.dump();
+    ekprintln!("backtrace:");
+    backtrace::print(Some(tf.elr as usize).into_iter()
+        .chain(backtrace::from_fp(tf.x[29] as usize)));
+}
+
+/// Arranges for `tf` to run the instruction that hit a point with the
+/// points disabled on this core, then take a software step exception. If
+/// GDB is already stepping `tf`, its step is shared.
+fn step_over(tf: &mut TrapFrame) {
+    let core = aarch64::affinity();
+    unload();
+    if tf.spsr & SPSR_SS != 0 {
+        STEP[core].store(STEPPING_FOR_GDB, Ordering::Relaxed);
+        return;
+    }
+
+    STEP_MASKS[core].store((tf.spsr & (SPSR_D | SPSR_I)) as usize, Ordering::Relaxed);
+    tf.spsr = (tf.spsr | SPSR_SS | SPSR_I) & !SPSR_D;
+    aarch64::set_single_step(true);
+    STEP[core].store(STEPPING, Ordering::Relaxed);
+}
+
+/// Handles a software step exception: if this core was stepping over a
+/// point, restores the points. Returns `true` if the step was this module's
+/// alone, and `false` if it wasn't stepping or GDB shares the step, which
+/// GDB's stub must then handle.
+pub fn handle_step(tf: &mut TrapFrame) -> bool {
+    let core = aarch64::affinity();
+    let step = STEP[core].swap(NOT_STEPPING, Ordering::Relaxed);
+    if step == NOT_STEPPING {
+        return false;
+    }
+
+    let version = VERSION.load(Ordering::Relaxed);
+    load();
+    LOADED[core].store(version, Ordering::Relaxed);
+    if step == STEPPING_FOR_GDB {
+        return false;
+    }
+
+    aarch64::set_single_step(false);
+    let masks = STEP_MASKS[core].load(Ordering::Relaxed) as u64;
+    tf.spsr = tf.spsr & !(SPSR_SS | SPSR_D | SPSR_I) | masks;
+    true
+}
@@ -0,0 +1,99 @@
+//! Boot-time initialization of the kernel's subsystems.
+//!
+//! Each subsystem registers its initialization function, and the stage it
+//! belongs to, with `kernel_init!` next to the function itself. The entries
+//! are collected in the `.kernel_init` linker section, and `run()` calls
+//! them a stage at a time, in the order of `Stage`, logging how long each
+//! stage took. A subsystem may rely on everything in earlier stages, but
+//! not on the order of entries within its own stage.
+
+use std::{mem, slice};
+
+use pi::timer;
+
+use boot::BootInfo;
+use console::{log_debug, log_info};
+
+/// The stages of boot, in the order they run.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    /// Translation tables and the MMU, which atomic operations need.
+    Mmu,
+    /// The heap.
+    Allocator,
+    /// The SD card and the file system on it.
+    Fs,
+    /// The timer tick and other interrupt-driven drivers. IRQs are
+    /// unmasked from here on.
+    Drivers,
+    /// Kernel processes, such as `init`.
+    Scheduler,
+    /// The secondary cores.
+    Secondaries,
+}
+
+/// Every stage, in order.
+const STAGES: [Stage; 6] = [
+    Stage::Mmu, Stage::Allocator, Stage::Fs, Stage::Drivers, Stage::Scheduler, Stage::Secondaries,
+];
+
+/// An initialization function.
+#[derive(Copy, Clone)]
+pub enum Init {
+    Plain(fn()),
+    /// A function that needs what boot found out about the machine.
+    Boot(fn(&BootInfo)),
+}
+
+/// A registered initialization function. See `kernel_init!`.
+pub struct Entry {
+    pub name: &'static str,
+    pub stage: Stage,
+    pub init: Init,
+}
+
+extern "C" {
+    static __kernel_init_start: Entry;
+    static __kernel_init_end: Entry;
+}
+
+/// Registers `$init`, an `init::Init`, to run in `$stage` under the name
+/// `$name`. `$entry` names the static holding the entry, which must be
+/// unique within the module.
+pub macro kernel_init($entry:ident, $stage:expr, $name:expr, $init:expr) {
+    #[link_section = ".kernel_init"]
+    #[used]
+    static $entry: $crate::init::Entry = $crate::init::Entry {
+        name: $name,
+        stage: $stage,
+        init: $init,
+    };
+}
+
+/// Returns every registered entry, in no particular order.
+#[cfg(not(test))]
+fn entries() -> &'static [Entry] {
+    unsafe {
+        let start = &__kernel_init_start as *const Entry;
+        let end = &__kernel_init_end as *const Entry;
+        let len = (end as usize - start as usize) / mem::size_of::<Entry>();
+        slice::from_raw_parts(start, len)
+    }
+}
+
+/// Runs every registered initialization function, a stage at a time.
+#[cfg(not(test))]
+pub fn run(info: &BootInfo) {
+    for &stage in STAGES.iter() {
+        let stage_start = timer::current_time();
+        for entry in entries().iter().filter(|entry| entry.stage == stage) {
+            let start = timer::current_time();
+            match entry.init {
+                Init::Plain(init) => init(),
+                Init::Boot(init) => init(info),
+            }
+            log_debug!("{} ({:?}): {} us", entry.name, stage, timer::current_time() - start);
+        }
+        log_info!("{:?} stage: {} us", stage, timer::current_time() - stage_start);
+    }
+}
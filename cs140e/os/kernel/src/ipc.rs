@@ -0,0 +1,291 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+
+use ktest::kernel_test;
+use mutex::IrqMutex;
+use process::Id;
+use scheduler;
+use sync::WaitQueue;
+
+/// A port identifier. IDs are never reused.
+pub type PortId = u64;
+
+/// The longest message a port carries, in bytes.
+pub const MAX_MESSAGE: usize = 4096;
+
+/// The most messages a port can be created to hold.
+pub const MAX_CAPACITY: usize = 64;
+
+/// The ways a port operation can fail.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// No port has the given ID, or it has been closed.
+    NoPort,
+    /// Only the process that created a port may close it.
+    NotOwner,
+    /// The capacity is zero or more than `MAX_CAPACITY`.
+    BadCapacity,
+    /// The message is longer than `MAX_MESSAGE`, or than the buffer it is
+    /// received into.
+    TooLong,
+    /// The port holds its capacity of messages.
+    Full,
+    /// The port holds no messages.
+    Empty,
+    /// The process owns as many ports as its limit allows.
+    TooMany,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Error::NoPort => "no such port",
+            Error::NotOwner => "port belongs to another process",
+            Error::BadCapacity => "invalid port capacity",
+            Error::TooLong => "message too long",
+            Error::Full => "port is full",
+            Error::Empty => "port is empty",
+            Error::TooMany => "too many ports",
+        })
+    }
+}
+
+/// A bounded queue of messages.
+struct Port {
+    owner: Id,
+    capacity: usize,
+    messages: VecDeque<Vec<u8>>,
+}
+
+/// Every open port.
+struct Ports {
+    ports: BTreeMap<PortId, Port>,
+    last_id: PortId,
+}
+
+static PORTS: IrqMutex<Option<Ports>> = IrqMutex::new(None);
+
+/// Senders waiting for a port to have room. Woken whenever a message is
+/// received from any port or a port is closed, and recheck their own.
+static SENDABLE: WaitQueue = WaitQueue::new();
+
+/// Receivers waiting for a port to have a message. Woken whenever a message
+/// is sent to any port or a port is closed, and recheck their own.
+static RECEIVABLE: WaitQueue = WaitQueue::new();
+
+/// Calls `f` with the port table, creating it first if needed.
+fn with_ports<T, F: FnOnce(&mut Ports) -> T>(f: F) -> T {
+    let mut ports = PORTS.lock();
+    f(ports.get_or_insert_with(|| Ports { ports: BTreeMap::new(), last_id: 0 }))
+}
+
+/// Creates a port, owned by the process `owner`, that holds up to
+/// `capacity` messages, and returns its ID. Fails with `TooMany` if `owner`
+/// already owns `limit` ports.
+///
+/// Ports are named only by ID: any process that knows it, for instance by
+/// being forked after the port was created, can send and receive.
+pub fn create(owner: Id, capacity: usize, limit: u64) -> Result<PortId, Error> {
+    if capacity == 0 || capacity > MAX_CAPACITY {
+        return Err(Error::BadCapacity);
+    }
+
+    with_ports(|p| {
+        if p.ports.values().filter(|port| port.owner == owner).count() as u64 >= limit {
+            return Err(Error::TooMany);
+        }
+
+        p.last_id += 1;
+        p.ports.insert(p.last_id, Port { owner, capacity, messages: VecDeque::new() });
+        Ok(p.last_id)
+    })
+}
+
+/// Returns the number of ports the process `owner` owns.
+pub fn owned(owner: Id) -> usize {
+    with_ports(|p| p.ports.values().filter(|port| port.owner == owner).count())
+}
+
+/// Returns `true` if this core is using the port table, so that closing
+/// ports would deadlock. For the out-of-memory killer.
+pub fn is_locked_here() -> bool {
+    PORTS.is_held_by_current_core()
+}
+
+/// Closes the port `id` on behalf of the process `caller`, discarding any
+/// queued messages. Processes blocked on the port fail with `NoPort`.
+pub fn close(id: PortId, caller: Id) -> Result<(), Error> {
+    let port = with_ports(|p| {
+        match p.ports.get(&id).map(|port| port.owner) {
+            None => Err(Error::NoPort),
+            Some(owner) if owner != caller => Err(Error::NotOwner),
+            Some(_) => Ok(p.ports.remove(&id)),
+        }
+    })?;
+
+    // The messages are freed here, outside the lock.
+    drop(port);
+    wake_all();
+    Ok(())
+}
+
+/// Closes every port owned by the process `owner`. Called when it exits.
+pub fn release(owner: Id) {
+    let closed: Vec<Port> = with_ports(|p| {
+        let ids: Vec<PortId> = p.ports.iter()
+            .filter(|&(_, port)| port.owner == owner)
+            .map(|(&id, _)| id)
+            .collect();
+        ids.iter().filter_map(|id| p.ports.remove(id)).collect()
+    });
+
+    if !closed.is_empty() {
+        drop(closed);
+        wake_all();
+    }
+}
+
+/// Wakes every blocked sender and receiver.
+fn wake_all() {
+    SENDABLE.wake_all();
+    RECEIVABLE.wake_all();
+}
+
+/// Queues a copy of `msg` on the port `id` without blocking.
+pub fn try_send(id: PortId, msg: &[u8]) -> Result<(), Error> {
+    push(id, msg)?;
+    RECEIVABLE.wake_all();
+    Ok(())
+}
+
+/// Removes the oldest message from the port `id` without blocking, copies it
+/// to the start of `buf`, and returns its length. A message longer than
+/// `buf` is left queued and `TooLong` is returned.
+pub fn try_recv(id: PortId, buf: &mut [u8]) -> Result<usize, Error> {
+    let len = pop(id, buf)?;
+    SENDABLE.wake_all();
+    Ok(len)
+}
+
+/// Like `try_send()`, but sleeps while the port is full. For kernel
+/// processes; user processes send through the `send` system call.
+pub fn send(id: PortId, msg: &[u8]) -> Result<(), Error> {
+    let mut result = Err(Error::Full);
+    SENDABLE.wait_until(|| {
+        result = push(id, msg);
+        result != Err(Error::Full)
+    });
+
+    if result.is_ok() {
+        RECEIVABLE.wake_all();
+    }
+    result
+}
+
+/// Like `try_recv()`, but sleeps while the port is empty. For kernel
+/// processes; user processes receive through the `recv` system call.
+pub fn recv(id: PortId, buf: &mut [u8]) -> Result<usize, Error> {
+    let mut result = Err(Error::Empty);
+    RECEIVABLE.wait_until(|| {
+        result = pop(id, buf);
+        result != Err(Error::Empty)
+    });
+
+    if result.is_ok() {
+        SENDABLE.wake_all();
+    }
+    result
+}
+
+/// Like `try_send()`, but if the port is full, queues the running process
+/// to be woken when it may have room, marks it waiting, and asks for it to
+/// be switched out. For system calls, which retry when woken.
+pub fn park_send(id: PortId, msg: &[u8]) -> Result<(), Error> {
+    let mut result = Err(Error::Full);
+    SENDABLE.park_unless(|| {
+        result = push(id, msg);
+        result != Err(Error::Full)
+    });
+
+    match result {
+        Ok(()) => RECEIVABLE.wake_all(),
+        Err(Error::Full) => scheduler::request_resched(),
+        Err(_) => (),
+    }
+    result
+}
+
+/// Like `try_recv()`, but if the port is empty, queues the running process
+/// to be woken when it may have a message, marks it waiting, and asks for
+/// it to be switched out. For system calls, which retry when woken.
+pub fn park_recv(id: PortId, buf: &mut [u8]) -> Result<usize, Error> {
+    let mut result = Err(Error::Empty);
+    RECEIVABLE.park_unless(|| {
+        result = pop(id, buf);
+        result != Err(Error::Empty)
+    });
+
+    match result {
+        Ok(_) => SENDABLE.wake_all(),
+        Err(Error::Empty) => scheduler::request_resched(),
+        Err(_) => (),
+    }
+    result
+}
+
+/// Queues a copy of `msg` on the port `id`. The caller wakes receivers.
+fn push(id: PortId, msg: &[u8]) -> Result<(), Error> {
+    if msg.len() > MAX_MESSAGE {
+        return Err(Error::TooLong);
+    }
+
+    let msg = msg.to_vec();
+    with_ports(|p| {
+        let port = p.ports.get_mut(&id).ok_or(Error::NoPort)?;
+        if port.messages.len() >= port.capacity {
+            return Err(Error::Full);
+        }
+
+        port.messages.push_back(msg);
+        Ok(())
+    })
+}
+
+/// Moves the oldest message on the port `id` to `buf`. The caller wakes
+/// senders.
+fn pop(id: PortId, buf: &mut [u8]) -> Result<usize, Error> {
+    let msg = with_ports(|p| {
+        let port = p.ports.get_mut(&id).ok_or(Error::NoPort)?;
+        match port.messages.front().map(|msg| msg.len()) {
+            None => Err(Error::Empty),
+            Some(len) if len > buf.len() => Err(Error::TooLong),
+            Some(_) => Ok(port.messages.pop_front().unwrap()),
+        }
+    })?;
+
+    buf[..msg.len()].copy_from_slice(&msg);
+    Ok(msg.len())
+}
+
+kernel_test!(PORT_TEST, "ipc::port", test_port);
+
+/// Messages come out of a port in order, a full port refuses more, and a
+/// closed port is gone.
+fn test_port() {
+    let me = scheduler::current_id();
+    let id = create(me, 2, u64::max_value()).expect("create failed");
+    let mut buf = [0; 8];
+    assert_eq!(try_recv(id, &mut buf), Err(Error::Empty));
+
+    try_send(id, b"one").unwrap();
+    try_send(id, b"two").unwrap();
+    assert_eq!(try_send(id, b"three"), Err(Error::Full));
+    assert_eq!(try_recv(id, &mut buf), Ok(3));
+    assert_eq!(&buf[..3], b"one");
+    assert_eq!(try_recv(id, &mut buf[..2]), Err(Error::TooLong));
+    assert_eq!(try_recv(id, &mut buf), Ok(3));
+    assert_eq!(&buf[..3], b"two");
+
+    close(id, me).unwrap();
+    assert_eq!(try_send(id, b"four"), Err(Error::NoPort));
+}
@@ -0,0 +1,184 @@
+use pi::interrupt::{Controller, Interrupt};
+use pi::local::LocalController;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use aarch64;
+use mutex::IrqMutex;
+use smp::MAX_CORES;
+use traps::TrapFrame;
+
+/// A handler for an interrupt source, called with the interrupted context.
+pub type IrqHandler = fn(&mut TrapFrame);
+
+/// Error type for `register` failures.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// A handler is already registered for the interrupt.
+    Busy,
+}
+
+/// The registered handlers and per-source counts, indexed by interrupt number.
+struct Table {
+    handlers: [Option<IrqHandler>; Interrupt::MAX],
+    counts: [u64; Interrupt::MAX],
+    spurious: u64,
+    /// The handler for every core's own physical timer.
+    local_timer: Option<IrqHandler>,
+    /// The handler for every core's own performance monitor.
+    local_pmu: Option<IrqHandler>,
+}
+
+/// The global IRQ table. It is only locked with IRQs masked on the current
+/// core, so `dispatch` can never find it held by the code it interrupted.
+static TABLE: IrqMutex<Table> = IrqMutex::new(Table {
+    handlers: [None; Interrupt::MAX],
+    counts: [0; Interrupt::MAX],
+    spurious: 0,
+    local_timer: None,
+    local_pmu: None,
+});
+
+/// The number of the interrupt whose handler each core is running, plus
+/// one, or 0.
+static DISPATCHING: [AtomicUsize; MAX_CORES] = [
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+];
+
+/// Runs `f` with the table locked and IRQs masked on this core.
+fn with_table<R, F: FnOnce(&mut Table) -> R>(f: F) -> R {
+    f(&mut TABLE.lock())
+}
+
+/// Registers `handler` for `int` and enables `int` at the interrupt
+/// controller. Fails if `int` already has a handler.
+pub fn register(int: Interrupt, handler: IrqHandler) -> Result<(), Error> {
+    with_table(|table| {
+        let slot = &mut table.handlers[int as usize];
+        if slot.is_some() {
+            return Err(Error::Busy);
+        }
+
+        *slot = Some(handler);
+        Controller::new().enable(int);
+        Ok(())
+    })
+}
+
+/// Disables `int` and removes its handler, if any.
+pub fn unregister(int: Interrupt) {
+    with_table(|table| {
+        Controller::new().disable(int);
+        table.handlers[int as usize] = None;
+    })
+}
+
+/// Sets `handler` as the handler for the calling core's physical timer and
+/// routes that timer's interrupt to the core. Every core shares the one
+/// handler.
+pub fn register_local_timer(handler: IrqHandler) {
+    with_table(|table| table.local_timer = Some(handler));
+    LocalController::new().enable_timer(aarch64::affinity());
+}
+
+/// Sets `handler` as the handler for the calling core's performance monitor
+/// and routes its interrupt to the core. Every core shares the one handler.
+pub fn register_local_pmu(handler: IrqHandler) {
+    with_table(|table| table.local_pmu = Some(handler));
+    LocalController::new().enable_pmu(aarch64::affinity());
+}
+
+/// Stops routing the calling core's performance monitor interrupt to it.
+pub fn disable_local_pmu() {
+    LocalController::new().disable_pmu(aarch64::affinity());
+}
+
+/// Enables `int` at the interrupt controller without changing its handler.
+pub fn enable(int: Interrupt) {
+    Controller::new().enable(int);
+}
+
+/// Disables `int` at the interrupt controller without changing its handler.
+pub fn disable(int: Interrupt) {
+    Controller::new().disable(int);
+}
+
+/// Returns whether `int` is enabled at the interrupt controller.
+pub fn is_enabled(int: Interrupt) -> bool {
+    Controller::new().is_enabled(int)
+}
+
+/// Calls the handler of every pending interrupt source. An IRQ with no
+/// pending source that has a handler is counted as spurious.
+///
+/// The GPU's interrupt sources are only routed to core 0. Every core checks
+/// its own physical timer and performance monitor.
+///
+/// Called by the exception handler with IRQs masked.
+pub fn dispatch(tf: &mut TrapFrame) {
+    let core = aarch64::affinity();
+    let mut handled = false;
+    if LocalController::new().is_timer_pending(core) {
+        if let Some(handler) = with_table(|table| table.local_timer) {
+            handler(tf);
+            handled = true;
+        }
+    }
+    if LocalController::new().is_pmu_pending(core) {
+        if let Some(handler) = with_table(|table| table.local_pmu) {
+            handler(tf);
+            handled = true;
+        }
+    }
+
+    let controller = Controller::new();
+    let sources: &[Interrupt] = if core == 0 { Interrupt::iter() } else { &[] };
+    for &int in sources {
+        if !controller.is_pending(int) {
+            continue;
+        }
+
+        let handler = {
+            let mut table = TABLE.lock();
+            table.counts[int as usize] += 1;
+            table.handlers[int as usize]
+        };
+
+        if let Some(handler) = handler {
+            DISPATCHING[core].store(int as usize + 1, Ordering::Relaxed);
+            handler(tf);
+            DISPATCHING[core].store(0, Ordering::Relaxed);
+            handled = true;
+        }
+    }
+
+    if !handled {
+        TABLE.lock().spurious += 1;
+    }
+}
+
+/// Returns the interrupt whose handler this core is running, if it is
+/// running one of the GPU's. The handlers of the cores' own timers and
+/// performance monitors don't count.
+pub fn current() -> Option<Interrupt> {
+    match DISPATCHING[aarch64::affinity()].load(Ordering::Relaxed) {
+        0 => None,
+        n => Interrupt::iter().iter().cloned().find(|&int| int as usize == n - 1),
+    }
+}
+
+/// Calls `f` with each interrupt source that has fired at least once, the
+/// number of times it has fired, and whether it has a handler.
+pub fn for_each_count<F: FnMut(Interrupt, u64, bool)>(mut f: F) {
+    let (handlers, counts) = with_table(|table| (table.handlers, table.counts));
+    for &int in Interrupt::iter() {
+        if counts[int as usize] > 0 {
+            f(int, counts[int as usize], handlers[int as usize].is_some());
+        }
+    }
+}
+
+/// Returns the number of IRQs taken with no handled source pending.
+pub fn spurious() -> u64 {
+    with_table(|table| table.spurious)
+}
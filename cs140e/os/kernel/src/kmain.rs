@@ -7,46 +7,109 @@
 #![feature(repr_align)]
 #![feature(attr_literals)]
 #![feature(exclusive_range_pattern)]
-#![feature(alloc, allocator_api, global_allocator)]
+#![feature(alloc, alloc_error_handler)]
 #![feature(never_type)]
 #![feature(ptr_internals)]
 #![feature(pointer_methods)]
+#![feature(used)]
+#![cfg_attr(test, feature(test))]
 
 #[macro_use]
 #[allow(unused_imports)]
 extern crate alloc;
+extern crate core;
 extern crate pi;
-extern crate stack_vec;
 extern crate fat32;
+#[cfg(test)]
+extern crate test;
 
+pub mod aarch64;
 pub mod allocator;
+pub mod backtrace;
+pub mod bytes;
+pub mod crashlog;
+pub mod ktest;
+pub mod semihosting;
+pub mod selftest;
+pub mod ksyms;
+pub mod debug;
+pub mod gdbstub;
+pub mod hwdebug;
+pub mod profile;
+pub mod trace;
+pub mod boot;
+pub mod init;
+pub mod clock;
 pub mod lang_items;
 pub mod mutex;
 pub mod console;
 pub mod shell;
 pub mod fs;
+pub mod traps;
+pub mod irq;
+pub mod tick;
+pub mod scheduler;
+pub mod oom;
+pub mod oops;
+pub mod preempt;
+pub mod process;
+pub mod kthread;
+pub mod sync;
+pub mod ipc;
+pub mod smp;
+pub mod vm;
+pub mod random;
+pub mod net;
 
-#[cfg(not(test))]
 use allocator::Allocator;
+use boot::BootInfo;
 use fs::FileSystem;
+use init::{kernel_init, Init, Stage};
 
-#[cfg(not(test))]
-#[global_allocator]
+#[cfg_attr(not(test), global_allocator)]
 pub static ALLOCATOR: Allocator = Allocator::uninitialized();
 
 pub static FILE_SYSTEM: FileSystem = FileSystem::uninitialized();
 
+kernel_init!(ALLOCATOR_INIT, Stage::Allocator, "allocator", Init::Boot(init_allocator));
+kernel_init!(FILE_SYSTEM_INIT, Stage::Fs, "fs", Init::Boot(init_file_system));
+
+fn init_allocator(info: &BootInfo) {
+    ALLOCATOR.initialize(info);
+}
+
+/// Mounts the root file system, with a block cache of `bcache=` blocks if
+/// the command line says how many.
+fn init_file_system(info: &BootInfo) {
+    let blocks = info.param("bcache").and_then(|n| n.parse().ok());
+    FILE_SYSTEM.initialize(blocks.unwrap_or(fs::cache::DEFAULT_CAPACITY));
+}
+
 #[no_mangle]
 #[cfg(not(test))]
-pub extern "C" fn kmain() {
-    ALLOCATOR.initialize();
-    use console::{kprintln, CONSOLE};
+pub extern "C" fn kmain(dtb: usize) {
+    let boot_info = BootInfo::detect(dtb);
+    init::run(&boot_info);
+    if boot_info.has_flag("test") {
+        ktest::run();
+    }
+    if boot_info.has_flag("selftest") {
+        selftest::run(&selftest::Options::from_boot(&boot_info));
+    }
+    use console::{log_info, log_debug};
     pi::timer::spin_sleep_ms(5000);
 
+    log_info!("memory: {:#x}..{:#x} (from {:?})", boot_info.mem_start,
+              boot_info.mem_end(), boot_info.mem_source);
+    if let Some((start, end)) = boot_info.dtb {
+        log_info!("device tree: {:#x}..{:#x}", start, end);
+    }
+    boot_info.check_peripherals();
+
     let mut v = vec![];
     for i in 0..150 {
         v.push(i);
-        kprintln!("{:?}", v);
+        log_debug!("{:?}", v);
     }
 
     shell::shell("->");
@@ -0,0 +1,129 @@
+//! The kernel's symbol table, for naming the functions addresses are in.
+//!
+//! The table is built by the host tool in `ksyms/` from the symbols of the
+//! kernel as first linked, then linked into the `.ksyms` section of the
+//! final image. It sits after the code, so both links put every function at
+//! the same address. Its layout is described in `ksyms/src/main.rs`: sorted
+//! addresses, searched by bisection, and names front-coded in blocks of
+//! `BLOCK`, each decoded from the start of its block. Looking a symbol up
+//! allocates nothing, so it can be done from a panic.
+
+use std::fmt;
+use std::str;
+
+use bytes::le;
+
+/// The magic number at the start of a table.
+const MAGIC: u32 = 0x4D59_534B;
+
+/// The length of a table's header.
+const HEADER_LEN: usize = 24;
+
+/// The number of names in a block.
+const BLOCK: usize = 16;
+
+/// The longest name in a table.
+pub const MAX_NAME: usize = 255;
+
+#[cfg(not(test))]
+extern "C" {
+    static __ksyms_start: u8;
+    static __ksyms_end: u8;
+    static __text_end: u8;
+}
+
+/// Returns the table and the end of the kernel's code.
+#[cfg(not(test))]
+fn table() -> (&'static [u8], usize) {
+    unsafe {
+        let start = &__ksyms_start as *const u8;
+        let end = &__ksyms_end as *const u8;
+        let table = ::std::slice::from_raw_parts(start, end as usize - start as usize);
+        (table, &__text_end as *const u8 as usize)
+    }
+}
+
+#[cfg(test)]
+fn table() -> (&'static [u8], usize) {
+    (&[], 0)
+}
+
+/// The function an address is in, and how far into it the address is.
+#[derive(Copy, Clone)]
+pub struct Symbol {
+    name: [u8; MAX_NAME],
+    len: usize,
+    pub offset: usize,
+}
+
+impl Symbol {
+    /// The function's name, demangled.
+    pub fn name(&self) -> &str {
+        str::from_utf8(&self.name[..self.len]).unwrap_or("?")
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}+{:#x}", self.name(), self.offset)
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Symbol({})", self)
+    }
+}
+
+/// Returns the `u32` at `offset` in `table`, or `None` if the table is too
+/// short for it.
+fn u32_at(table: &[u8], offset: usize) -> Option<u32> {
+    table.get(offset..offset + 4).map(|bytes| le::u32_at(bytes, 0))
+}
+
+/// Returns the function `addr` is in, or `None` if it isn't in the kernel's
+/// code or the kernel was linked without a table.
+pub fn lookup(addr: usize) -> Option<Symbol> {
+    let (table, text_end) = table();
+    if u32_at(table, 0)? != MAGIC {
+        return None;
+    }
+
+    let count = u32_at(table, 4)? as usize;
+    let base = u32_at(table, 8)? as usize | (u32_at(table, 12)? as usize) << 32;
+    let markers_at = u32_at(table, 16)? as usize;
+    let names_at = u32_at(table, 20)? as usize;
+    if count == 0 || addr < base || addr >= text_end {
+        return None;
+    }
+
+    // The last symbol at or below `addr`.
+    let offset = addr - base;
+    let (mut low, mut high) = (0, count);
+    while high - low > 1 {
+        let mid = low + (high - low) / 2;
+        if u32_at(table, HEADER_LEN + 4 * mid)? as usize <= offset {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    let index = low;
+    let start = u32_at(table, HEADER_LEN + 4 * index)? as usize;
+
+    // Decode the names from the start of the block up to this one.
+    let mut symbol = Symbol { name: [0; MAX_NAME], len: 0, offset: offset - start };
+    let mut at = names_at + u32_at(table, markers_at + 4 * (index / BLOCK))? as usize;
+    for _ in 0..(index % BLOCK + 1) {
+        let shared = *table.get(at)? as usize;
+        let rest = *table.get(at + 1)? as usize;
+        let bytes = table.get(at + 2..at + 2 + rest)?;
+        if shared > symbol.len || shared + rest > MAX_NAME {
+            return None;
+        }
+        symbol.name[shared..shared + rest].copy_from_slice(bytes);
+        symbol.len = shared + rest;
+        at += 2 + rest;
+    }
+    Some(symbol)
+}
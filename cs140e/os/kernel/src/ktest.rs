@@ -0,0 +1,106 @@
+//! Kernel integration tests: functions registered with `kernel_test!` and
+//! run on the kernel itself, after boot, when the command line has `test`
+//! on it.
+//!
+//! Tests run one after another in the boot process, in no particular order,
+//! with every subsystem initialized. A test fails by panicking, as `assert!`
+//! does. The panic handler reports which test it was and, under QEMU with
+//! semihosting, ends QEMU with `FAILED` as its exit status; the tests after
+//! it don't run. Once every test has passed, `run()` ends QEMU with
+//! `PASSED`. Without semihosting there is no one to report to, and the
+//! kernel carries on to the shell.
+//!
+//! `make qemu-test` boots the kernel this way.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use pi::timer;
+
+use console::{self, ekprintln, kprint, kprintln};
+use semihosting;
+
+/// The exit statuses of a test run.
+pub const PASSED: u32 = 0;
+pub const FAILED: u32 = 1;
+
+/// A registered test. See `kernel_test!`.
+pub struct Test {
+    pub name: &'static str,
+    pub run: fn(),
+}
+
+extern "C" {
+    static __kernel_test_start: Test;
+    static __kernel_test_end: Test;
+}
+
+/// Registers `$test`, a `fn()`, as a kernel test named `$name`. `$entry`
+/// names the static holding the registration, which must be unique within
+/// the module.
+pub macro kernel_test($entry:ident, $name:expr, $test:expr) {
+    #[link_section = ".kernel_test"]
+    #[used]
+    static $entry: $crate::ktest::Test = $crate::ktest::Test {
+        name: $name,
+        run: $test,
+    };
+}
+
+/// One more than the index of the test running, or 0 if none is.
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns every registered test, in no particular order.
+#[cfg(not(test))]
+fn tests() -> &'static [Test] {
+    use std::{mem, slice};
+
+    unsafe {
+        let start = &__kernel_test_start as *const Test;
+        let end = &__kernel_test_end as *const Test;
+        let len = (end as usize - start as usize) / mem::size_of::<Test>();
+        slice::from_raw_parts(start, len)
+    }
+}
+
+#[cfg(test)]
+fn tests() -> &'static [Test] {
+    &[]
+}
+
+/// Returns the test running, if one is.
+pub fn current() -> Option<&'static Test> {
+    match CURRENT.load(Ordering::Relaxed) {
+        0 => None,
+        n => tests().get(n - 1),
+    }
+}
+
+/// Runs every registered test, then ends QEMU with `PASSED` if it can.
+/// Returns only if it can't.
+pub fn run() {
+    let tests = tests();
+    kprintln!("running {} kernel tests", tests.len());
+
+    let start = timer::current_time();
+    for (i, test) in tests.iter().enumerate() {
+        kprint!("test {} ... ", test.name);
+        CURRENT.store(i + 1, Ordering::Relaxed);
+        (test.run)();
+        CURRENT.store(0, Ordering::Relaxed);
+        kprintln!("ok");
+    }
+
+    kprintln!("test result: ok. {} passed in {} ms",
+              tests.len(), (timer::current_time() - start) / 1000);
+    console::flush();
+    semihosting::exit(PASSED);
+}
+
+/// Reports that the test running failed and ends QEMU with `FAILED`, if a
+/// test is running and QEMU can be ended. Called by the panic handler.
+pub fn fail() {
+    if let Some(test) = current() {
+        ekprintln!("\ntest {} FAILED", test.name);
+        semihosting::exit(FAILED);
+    }
+}
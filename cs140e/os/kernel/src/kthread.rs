@@ -0,0 +1,92 @@
+//! Kernel threads: long-running kernel-mode workers, such as driver polling
+//! loops, scheduled like any other process.
+
+use ktest::kernel_test;
+use preempt;
+use process::{Id, Process};
+use scheduler;
+
+/// A thread's body, boxed so that the trampoline can take it from a single
+/// pointer-sized argument.
+type Body = Box<FnMut() + Send>;
+
+/// A handle to a kernel thread, for waiting for it to finish.
+#[derive(Debug)]
+pub struct JoinHandle {
+    id: Id,
+}
+
+impl JoinHandle {
+    /// Returns the thread's process ID.
+    pub fn id(&self) -> Id {
+        self.id
+    }
+
+    /// Waits for the thread to finish and returns its exit code: 0 if its
+    /// closure returned, or `scheduler::KILLED_EXIT_CODE` if it was killed.
+    /// Only the thread that spawned it can join it; anyone else gets `None`.
+    pub fn join(self) -> Option<i32> {
+        scheduler::wait(self.id)
+    }
+}
+
+/// Starts a kernel thread named `name` running `f` on its own stack, at EL1
+/// with IRQs unmasked. The thread exits when `f` returns. Returns `None` if
+/// the thread or its stack can't be allocated.
+pub fn spawn<F: FnOnce() + Send + 'static>(name: &str, f: F) -> Option<JoinHandle> {
+    let mut f = Some(f);
+    let body: Body = Box::new(move || (f.take().unwrap())());
+    let arg = Box::into_raw(Box::new(body));
+
+    let id = Process::with_arg(trampoline, arg as usize).and_then(|mut process| {
+        process.name = name.to_string();
+        scheduler::add(process)
+    });
+    if id.is_none() {
+        drop(unsafe { Box::from_raw(arg) });
+    }
+    id.map(|id| JoinHandle { id })
+}
+
+/// The entry point of every kernel thread: runs the `Body` at `arg`, then
+/// exits.
+extern "C" fn trampoline(arg: usize) -> ! {
+    let mut body = unsafe { Box::from_raw(arg as *mut Body) };
+    body();
+    drop(body);
+    scheduler::exit(0)
+}
+
+/// Gives up the rest of the calling thread's time slice, letting any other
+/// ready process run first.
+///
+/// # Panics
+///
+/// Panics in atomic context. See `preempt::might_sleep()`.
+#[cfg(not(test))]
+pub fn yield_now() {
+    preempt::might_sleep();
+    // `svc` from EL1 goes through the same path as a user system call, which
+    // switches on the way out. 7 is `SYS_YIELD`.
+    unsafe {
+        asm!("svc #7" : : : "x0", "x7", "memory" : "volatile");
+    }
+}
+
+#[cfg(test)]
+pub fn yield_now() { }
+
+kernel_test!(SPAWN_JOIN_TEST, "kthread::spawn_join", test_spawn_join);
+
+/// A thread runs its closure, and joining it gives its exit code.
+fn test_spawn_join() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    let ran = Arc::new(AtomicBool::new(false));
+    let flag = ran.clone();
+    let thread = spawn("ktest", move || flag.store(true, Ordering::Relaxed))
+        .expect("spawn failed");
+    assert_eq!(thread.join(), Some(0));
+    assert!(ran.load(Ordering::Relaxed));
+}
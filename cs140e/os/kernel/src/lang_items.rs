@@ -3,8 +3,8 @@
 #[lang = "panic_fmt"]
 
 pub extern fn panic_fmt(fmt: ::std::fmt::Arguments, file: &'static str, line: u32, col: u32) -> ! {
-    // FIXME: Print `fmt`, `file`, and `line` to the console.
-	use console::kprintln;
+	use console::{ekprintln, Color};
+	use {crashlog, ktest, oops};
     let pi = r#"            (
        (      )     )
          )   (    (
@@ -18,8 +18,18 @@ pub extern fn panic_fmt(fmt: ::std::fmt::Arguments, file: &'static str, line: u3
 
 ---------- PANIC ----------"#;
 
-	kprintln!("{}", pi);
-	kprintln!("FILE: {}\nLINE: {}\nCOL: {}", file, line, col);
+	ekprintln!("{}", Color::Red.paint(pi));
+	ekprintln!("{}", Color::Bold.paint(fmt));
+	ekprintln!("FILE: {}\nLINE: {}\nCOL: {}", file, line, col);
+
+	oops::dump_context();
+
+	match crashlog::save(fmt, file, line, col) {
+		Ok(()) => ekprintln!("\ncrash record saved to the SD card"),
+		Err(e) => ekprintln!("\ncrash record not saved: {}", e),
+	}
+
+	ktest::fail();
 
     loop { unsafe { asm!("wfe") } }
 }
@@ -1,12 +1,15 @@
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::cell::UnsafeCell;
 use std::ops::{DerefMut, Deref, Drop};
 use std::fmt;
 
+use aarch64;
+
 #[repr(align(32))]
 pub struct Mutex<T> {
     data: UnsafeCell<T>,
     lock: AtomicBool,
+    owner: AtomicUsize,
 }
 
 unsafe impl<T: Send> Send for Mutex<T> { }
@@ -23,25 +26,40 @@ impl<T> Mutex<T> {
     pub const fn new(val: T) -> Mutex<T> {
         Mutex {
             lock: AtomicBool::new(false),
+            owner: AtomicUsize::new(0),
             data: UnsafeCell::new(val)
         }
     }
 }
 
 impl<T> Mutex<T> {
-    // Once MMU/cache is enabled, do the right thing here. For now, we don't
-    // need any real synchronization.
+    // Exclusive loads and stores fault until the MMU and data cache are on.
+    // Before then only core 0 is running, so a plain load and store suffice.
     pub fn try_lock(&self) -> Option<MutexGuard<T>> {
-        if !self.lock.load(Ordering::Relaxed) {
+        let acquired = if aarch64::caches_enabled() {
+            self.lock.compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed).is_ok()
+        } else if !self.lock.load(Ordering::Relaxed) {
             self.lock.store(true, Ordering::Relaxed);
+            true
+        } else {
+            false
+        };
+
+        if acquired {
+            self.owner.store(aarch64::affinity(), Ordering::Relaxed);
             Some(MutexGuard { lock: &self })
         } else {
             None
         }
     }
 
-    // Once MMU/cache is enabled, do the right thing here. For now, we don't
-    // need any real synchronization.
+    /// Returns `true` if the lock is currently held by the calling core.
+    /// Locking the mutex again from the same core would deadlock.
+    pub fn is_held_by_current_core(&self) -> bool {
+        self.lock.load(Ordering::Relaxed)
+            && self.owner.load(Ordering::Relaxed) == aarch64::affinity()
+    }
+
     #[inline(never)]
     pub fn lock(&self) -> MutexGuard<T> {
         // Wait until we can "aquire" the lock, then "acquire" it.
@@ -54,7 +72,102 @@ impl<T> Mutex<T> {
     }
 
     fn unlock(&self) {
-        self.lock.store(false, Ordering::Relaxed);
+        self.lock.store(false, Ordering::Release);
+    }
+
+    /// Releases the lock whether or not it is held and by whom.
+    ///
+    /// This is only for code that will never return to the holder, such as
+    /// a panic handler, and is unsafe because any outstanding guard still
+    /// believes it has exclusive access.
+    pub unsafe fn force_unlock(&self) {
+        self.unlock();
+    }
+}
+
+impl<T> Mutex<T> {
+    /// Masks IRQs and FIQs on this core, then locks the mutex. The previous
+    /// mask is restored when the guard is dropped, after the mutex is
+    /// unlocked.
+    ///
+    /// A lock that an exception handler may take must always be taken this
+    /// way: if the handler interrupted the holder on the same core, it would
+    /// spin forever.
+    pub fn lock_irqsave(&self) -> IrqMutexGuard<T> {
+        let daif = aarch64::irq_save();
+        IrqMutexGuard { guard: Some(self.lock()), daif }
+    }
+
+    /// Like `lock_irqsave()`, but returns `None`, with the mask restored, if
+    /// the mutex is locked.
+    pub fn try_lock_irqsave(&self) -> Option<IrqMutexGuard<T>> {
+        let daif = aarch64::irq_save();
+        match self.try_lock() {
+            Some(guard) => Some(IrqMutexGuard { guard: Some(guard), daif }),
+            None => {
+                aarch64::irq_restore(daif);
+                None
+            }
+        }
+    }
+}
+
+/// A locked `Mutex` with IRQs masked, from `lock_irqsave()`.
+pub struct IrqMutexGuard<'a, T: 'a> {
+    /// Always `Some` until dropped, so that the mutex can be unlocked before
+    /// the mask is restored.
+    guard: Option<MutexGuard<'a, T>>,
+    daif: u64,
+}
+
+impl<'a, T: 'a> Deref for IrqMutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.guard.as_ref().unwrap()
+    }
+}
+
+impl<'a, T: 'a> DerefMut for IrqMutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.guard.as_mut().unwrap()
+    }
+}
+
+impl<'a, T: 'a> Drop for IrqMutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.guard.take();
+        aarch64::irq_restore(self.daif);
+    }
+}
+
+/// A `Mutex` that is always locked with `lock_irqsave()`, for data shared
+/// with exception handlers.
+pub struct IrqMutex<T>(Mutex<T>);
+
+impl<T> IrqMutex<T> {
+    pub const fn new(val: T) -> IrqMutex<T> {
+        IrqMutex(Mutex::new(val))
+    }
+
+    /// See `Mutex::lock_irqsave()`.
+    pub fn lock(&self) -> IrqMutexGuard<T> {
+        self.0.lock_irqsave()
+    }
+
+    /// See `Mutex::try_lock_irqsave()`.
+    pub fn try_lock(&self) -> Option<IrqMutexGuard<T>> {
+        self.0.try_lock_irqsave()
+    }
+
+    /// See `Mutex::is_held_by_current_core()`.
+    pub fn is_held_by_current_core(&self) -> bool {
+        self.0.is_held_by_current_core()
+    }
+
+    /// See `Mutex::force_unlock()`.
+    pub unsafe fn force_unlock(&self) {
+        self.0.force_unlock();
     }
 }
 
@@ -78,6 +191,12 @@ impl<'a, T: 'a> Drop for MutexGuard<'a, T> {
     }
 }
 
+impl<T: fmt::Debug> fmt::Debug for IrqMutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
 impl<T: fmt::Debug> fmt::Debug for Mutex<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self.try_lock() {
@@ -0,0 +1,206 @@
+//! ARP: finding the Ethernet address of a neighbor from its IPv4 address.
+//!
+//! Addresses learned are cached for `ENTRY_LIFETIME_US`. A packet for a
+//! neighbor that isn't cached waits, with up to `MAX_PENDING - 1` others
+//! for it, while a request is broadcast; the request is repeated, at most
+//! once every `RETRY_INTERVAL_US`, by later packets for the neighbor.
+//! Requests for one of our addresses are answered, and, as RFC 826 says,
+//! teach us the sender's address.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::io;
+
+use bytes::be::{put_u16, u16_at};
+use clock;
+use mutex::Mutex;
+use net::{self, ethernet, Ipv4Addr, MacAddr, Route};
+
+/// The length of an ARP packet for IPv4 over Ethernet.
+const PACKET_LEN: usize = 28;
+
+/// The hardware type of Ethernet.
+const HTYPE_ETHERNET: u16 = 1;
+
+/// The operations.
+const OP_REQUEST: u16 = 1;
+const OP_REPLY: u16 = 2;
+
+/// How long a learned address is used before it is asked for again.
+const ENTRY_LIFETIME_US: u64 = 300 * 1_000_000;
+
+/// The least time between requests for the same address.
+const RETRY_INTERVAL_US: u64 = 1_000_000;
+
+/// The most packets kept waiting for one address; the oldest is dropped to
+/// make room.
+const MAX_PENDING: usize = 4;
+
+/// A learned address.
+struct Entry {
+    mac: MacAddr,
+    /// When the entry is no longer used, by `clock::monotonic_us()`.
+    expires: u64,
+}
+
+/// Packets waiting for an address.
+struct Pending {
+    packets: VecDeque<Vec<u8>>,
+    /// When the address was last asked for.
+    requested: u64,
+}
+
+/// The cache and waiting packets, by interface and address.
+struct Table {
+    entries: BTreeMap<(usize, Ipv4Addr), Entry>,
+    pending: BTreeMap<(usize, Ipv4Addr), Pending>,
+}
+
+static TABLE: Mutex<Option<Table>> = Mutex::new(None);
+
+/// Calls `f` with the table, creating it first if needed.
+fn with_table<T, F: FnOnce(&mut Table) -> T>(f: F) -> T {
+    let mut table = TABLE.lock();
+    f(table.get_or_insert_with(|| Table { entries: BTreeMap::new(), pending: BTreeMap::new() }))
+}
+
+/// What to do with a packet.
+enum Next {
+    /// Send it to the address.
+    Send(MacAddr, Vec<u8>),
+    /// It is queued; ask for the address.
+    Request,
+    /// It is queued behind a request already made.
+    Wait,
+}
+
+/// Sends `packet`, an IPv4 packet, to the next hop of `route`, or queues
+/// it until the next hop's address is known.
+pub fn send(route: &Route, packet: Vec<u8>) -> io::Result<()> {
+    let now = clock::monotonic_us();
+    let key = (route.index, route.next_hop);
+    let next = with_table(|table| {
+        match table.entries.get(&key).map(|entry| (entry.mac, entry.expires)) {
+            Some((mac, expires)) if expires > now => return Next::Send(mac, packet),
+            Some(_) => {
+                table.entries.remove(&key);
+            }
+            None => {}
+        }
+
+        let pending = table.pending.entry(key)
+            .or_insert_with(|| Pending { packets: VecDeque::new(), requested: 0 });
+        if pending.packets.len() >= MAX_PENDING {
+            pending.packets.pop_front();
+        }
+        pending.packets.push_back(packet);
+        if pending.requested != 0 && now < pending.requested + RETRY_INTERVAL_US {
+            return Next::Wait;
+        }
+        pending.requested = now;
+        Next::Request
+    });
+
+    match next {
+        Next::Send(mac, packet) => {
+            let frame = ethernet::build(mac, route.mac, ethernet::ETHERTYPE_IPV4, &packet);
+            net::send(route.index, &frame)
+        }
+        Next::Request => {
+            let request = packet_of(OP_REQUEST, route.mac, route.src, MacAddr::ZERO,
+                                    route.next_hop);
+            let frame = ethernet::build(MacAddr::BROADCAST, route.mac, ethernet::ETHERTYPE_ARP,
+                                        &request);
+            net::send(route.index, &frame)
+        }
+        Next::Wait => Ok(()),
+    }
+}
+
+/// Handles `bytes`, an ARP packet received by interface `index`.
+pub fn receive(index: usize, bytes: &[u8]) {
+    if bytes.len() < PACKET_LEN || u16_at(bytes, 0) != HTYPE_ETHERNET
+        || u16_at(bytes, 2) != ethernet::ETHERTYPE_IPV4 || bytes[4] != 6 || bytes[5] != 4
+    {
+        return;
+    }
+
+    let op = u16_at(bytes, 6);
+    let sender_mac = ethernet::mac_at(bytes, 8);
+    let sender = Ipv4Addr::at(bytes, 14);
+    let target = Ipv4Addr::at(bytes, 24);
+    let (ours, mac) = match (net::config(index), net::mac_addr(index)) {
+        (Some(config), Some(mac)) => (config.addr, mac),
+        _ => return,
+    };
+    if sender == Ipv4Addr::UNSPECIFIED {
+        // A probe by a host checking that its address is free.
+        return;
+    }
+
+    // Learn the sender's address if it is asking us, or if it is one we
+    // have or want already.
+    let now = clock::monotonic_us();
+    let key = (index, sender);
+    let waiting = with_table(|table| {
+        let wanted = table.entries.contains_key(&key) || table.pending.contains_key(&key);
+        if target == ours || wanted {
+            table.entries.insert(key, Entry { mac: sender_mac, expires: now + ENTRY_LIFETIME_US });
+        }
+        table.pending.remove(&key).map_or(VecDeque::new(), |pending| pending.packets)
+    });
+
+    for packet in waiting {
+        let frame = ethernet::build(sender_mac, mac, ethernet::ETHERTYPE_IPV4, &packet);
+        let _ = net::send(index, &frame);
+    }
+
+    if op == OP_REQUEST && target == ours {
+        let reply = packet_of(OP_REPLY, mac, ours, sender_mac, sender);
+        let frame = ethernet::build(sender_mac, mac, ethernet::ETHERTYPE_ARP, &reply);
+        let _ = net::send(index, &frame);
+    }
+}
+
+/// A cached address, for listing.
+#[derive(Debug, Copy, Clone)]
+pub struct Neighbor {
+    pub index: usize,
+    pub addr: Ipv4Addr,
+    pub mac: MacAddr,
+    /// Seconds until the entry expires.
+    pub expires_in: u64,
+}
+
+/// Returns every cached address that hasn't expired.
+pub fn neighbors() -> Vec<Neighbor> {
+    let now = clock::monotonic_us();
+    with_table(|table| {
+        table.entries.iter()
+            .filter(|&(_, entry)| entry.expires > now)
+            .map(|(&(index, addr), entry)| Neighbor {
+                index,
+                addr,
+                mac: entry.mac,
+                expires_in: (entry.expires - now) / 1_000_000,
+            })
+            .collect()
+    })
+}
+
+/// Returns an ARP packet of operation `op` from `sender_mac` at `sender` to
+/// `target_mac` at `target`.
+fn packet_of(op: u16, sender_mac: MacAddr, sender: Ipv4Addr, target_mac: MacAddr, target: Ipv4Addr)
+    -> [u8; PACKET_LEN]
+{
+    let mut packet = [0; PACKET_LEN];
+    put_u16(&mut packet, 0, HTYPE_ETHERNET);
+    put_u16(&mut packet, 2, ethernet::ETHERTYPE_IPV4);
+    packet[4] = 6;
+    packet[5] = 4;
+    put_u16(&mut packet, 6, op);
+    packet[8..14].copy_from_slice(&sender_mac.0);
+    packet[14..18].copy_from_slice(&sender.0);
+    packet[18..24].copy_from_slice(&target_mac.0);
+    packet[24..28].copy_from_slice(&target.0);
+    packet
+}
@@ -0,0 +1,557 @@
+//! A DHCP client (RFC 2131): leases an address for each Ethernet interface
+//! and configures the interface with it and with the netmask, gateway, and
+//! name server the server gives.
+//!
+//! One kernel thread, `dhcp`, runs a client for every Ethernet interface
+//! that has no address when it is found; interfaces configured by other
+//! means are left alone. Leases are renewed from the server that granted
+//! them halfway through, and from any server seven eighths through. An
+//! interface whose lease runs out loses its address and starts again.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use bytes::be::{put_u16, put_u32, u32_at};
+use clock;
+use console::{log_info, log_warn};
+use init::{kernel_init, Init, Stage};
+use kthread;
+use mutex::Mutex;
+use net::{self, udp, Config, Ipv4Addr, Link, MacAddr};
+use net::udp::SocketId;
+use random;
+use scheduler;
+
+/// The ports of servers and clients.
+const SERVER_PORT: u16 = 67;
+const CLIENT_PORT: u16 = 68;
+
+/// The operations: every message a client sends is a request, and every
+/// one a server sends a reply.
+const OP_REQUEST: u8 = 1;
+const OP_REPLY: u8 = 2;
+
+/// The hardware type of Ethernet.
+const HTYPE_ETHERNET: u8 = 1;
+
+/// The length of a message before its options, and the cookie that starts
+/// the options.
+const FIXED_LEN: usize = 236;
+const MAGIC: [u8; 4] = [99, 130, 83, 99];
+
+/// The flag asking servers to broadcast replies, since we can't take
+/// packets for an address we don't have yet.
+const FLAG_BROADCAST: u16 = 0x8000;
+
+/// The message types.
+const DISCOVER: u8 = 1;
+const OFFER: u8 = 2;
+const REQUEST: u8 = 3;
+const ACK: u8 = 5;
+const NAK: u8 = 6;
+
+/// The options used.
+const OPT_PAD: u8 = 0;
+const OPT_NETMASK: u8 = 1;
+const OPT_ROUTER: u8 = 3;
+const OPT_DNS: u8 = 6;
+const OPT_REQUESTED_ADDR: u8 = 50;
+const OPT_LEASE_TIME: u8 = 51;
+const OPT_MESSAGE_TYPE: u8 = 53;
+const OPT_SERVER_ID: u8 = 54;
+const OPT_PARAMETERS: u8 = 55;
+const OPT_RENEWAL_TIME: u8 = 58;
+const OPT_REBINDING_TIME: u8 = 59;
+const OPT_END: u8 = 255;
+
+/// The lease time that means forever.
+const INFINITE: u32 = 0xFFFF_FFFF;
+
+/// How long to wait for the first reply to a broadcast, doubled on each
+/// retry up to `MAX_TIMEOUT_US`, as RFC 2131 suggests.
+const FIRST_TIMEOUT_US: u64 = 4 * 1_000_000;
+const MAX_TIMEOUT_US: u64 = 64 * 1_000_000;
+
+/// The least time between requests to renew a lease.
+const MIN_RENEW_RETRY_US: u64 = 60 * 1_000_000;
+
+/// How many times to request an offered address before starting again.
+const REQUEST_TRIES: u32 = 4;
+
+/// How long the client waits for a message before checking its timers.
+const POLL_MS: u64 = 100;
+
+/// What a client is doing.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum State {
+    /// About to look for servers.
+    Init,
+    /// Waiting for offers of an address.
+    Selecting,
+    /// Waiting for the server to grant the address it offered.
+    Requesting,
+    /// Holding a lease.
+    Bound,
+    /// Asking the server that granted the lease to extend it.
+    Renewing,
+    /// Asking any server to extend the lease.
+    Rebinding,
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            State::Init => "init",
+            State::Selecting => "selecting",
+            State::Requesting => "requesting",
+            State::Bound => "bound",
+            State::Renewing => "renewing",
+            State::Rebinding => "rebinding",
+        })
+    }
+}
+
+/// The state of an interface's client, for `ifconfig`.
+#[derive(Debug, Copy, Clone)]
+pub struct Status {
+    pub state: State,
+    /// The server that granted the lease, if there is one.
+    pub server: Option<Ipv4Addr>,
+    /// Seconds until the lease runs out, or `None` if there is no lease or
+    /// it never does.
+    pub expires_in: Option<u64>,
+}
+
+/// The clients' states, by interface, published by the client thread.
+static STATUS: Mutex<Option<BTreeMap<usize, Status>>> = Mutex::new(None);
+
+/// Returns the state of interface `index`'s client, or `None` if DHCP
+/// doesn't configure the interface.
+pub fn status(index: usize) -> Option<Status> {
+    STATUS.lock().as_ref().and_then(|status| status.get(&index).cloned())
+}
+
+/// A reply from a server.
+pub(super) struct Reply {
+    pub(super) kind: u8,
+    pub(super) xid: u32,
+    pub(super) chaddr: MacAddr,
+    pub(super) yiaddr: Ipv4Addr,
+    pub(super) server: Option<Ipv4Addr>,
+    pub(super) netmask: Option<Ipv4Addr>,
+    pub(super) router: Option<Ipv4Addr>,
+    pub(super) dns: Option<Ipv4Addr>,
+    pub(super) lease: Option<u32>,
+    pub(super) renewal: Option<u32>,
+    pub(super) rebinding: Option<u32>,
+}
+
+impl Reply {
+    /// Parses `bytes` as a reply, returning `None` if it isn't one.
+    pub(super) fn parse(bytes: &[u8]) -> Option<Reply> {
+        if bytes.len() < FIXED_LEN + MAGIC.len() || bytes[0] != OP_REPLY
+            || bytes[1] != HTYPE_ETHERNET || bytes[2] != 6
+            || bytes[FIXED_LEN..FIXED_LEN + MAGIC.len()] != MAGIC
+        {
+            return None;
+        }
+
+        let mut chaddr = [0; 6];
+        chaddr.copy_from_slice(&bytes[28..34]);
+        let mut reply = Reply {
+            kind: 0,
+            xid: u32_at(bytes, 4),
+            chaddr: MacAddr(chaddr),
+            yiaddr: Ipv4Addr::at(bytes, 16),
+            server: None,
+            netmask: None,
+            router: None,
+            dns: None,
+            lease: None,
+            renewal: None,
+            rebinding: None,
+        };
+
+        let mut i = FIXED_LEN + MAGIC.len();
+        while i < bytes.len() {
+            let code = bytes[i];
+            if code == OPT_PAD {
+                i += 1;
+                continue;
+            }
+            if code == OPT_END || i + 1 >= bytes.len() {
+                break;
+            }
+
+            let len = bytes[i + 1] as usize;
+            let data = match bytes.get(i + 2..i + 2 + len) {
+                Some(data) => data,
+                None => break,
+            };
+            // Routers and name servers are lists; the first is used.
+            let addr = if len >= 4 { Some(Ipv4Addr::at(data, 0)) } else { None };
+            let time = if len >= 4 { Some(u32_at(data, 0)) } else { None };
+            match code {
+                OPT_MESSAGE_TYPE if len >= 1 => reply.kind = data[0],
+                OPT_SERVER_ID => reply.server = addr,
+                OPT_NETMASK => reply.netmask = addr,
+                OPT_ROUTER => reply.router = addr,
+                OPT_DNS => reply.dns = addr,
+                OPT_LEASE_TIME => reply.lease = time,
+                OPT_RENEWAL_TIME => reply.renewal = time,
+                OPT_REBINDING_TIME => reply.rebinding = time,
+                _ => {}
+            }
+            i += 2 + len;
+        }
+
+        Some(reply)
+    }
+}
+
+/// A lease held.
+struct Lease {
+    server: Ipv4Addr,
+    config: Config,
+    /// When to start renewing, rebinding, and when the lease runs out, by
+    /// `clock::monotonic_us()`; `u64::max_value()` for never.
+    renew_at: u64,
+    rebind_at: u64,
+    expires: u64,
+}
+
+/// The client of one interface.
+struct Client {
+    index: usize,
+    name: String,
+    mac: MacAddr,
+    state: State,
+    /// The transaction ID of the last message sent.
+    xid: u32,
+    /// When to send again if no reply comes.
+    deadline: u64,
+    /// How long to wait for a reply to the next broadcast.
+    timeout: u64,
+    /// How many requests have been sent for the offered address.
+    tries: u32,
+    /// The address offered, and the server that offered it.
+    offer: Option<(Ipv4Addr, Ipv4Addr)>,
+    lease: Option<Lease>,
+}
+
+impl Client {
+    fn new(index: usize, name: String, mac: MacAddr) -> Client {
+        Client {
+            index,
+            name,
+            mac,
+            state: State::Init,
+            xid: 0,
+            deadline: 0,
+            timeout: FIRST_TIMEOUT_US,
+            tries: 0,
+            offer: None,
+            lease: None,
+        }
+    }
+
+    fn status(&self, now: u64) -> Status {
+        Status {
+            state: self.state,
+            server: self.lease.as_ref().map(|lease| lease.server),
+            expires_in: self.lease.as_ref().and_then(|lease| match lease.expires {
+                expires if expires == u64::max_value() => None,
+                expires => Some(expires.saturating_sub(now) / 1_000_000),
+            }),
+        }
+    }
+
+    /// Sends what is due and handles a lease running out.
+    fn poll(&mut self, socket: SocketId, now: u64) {
+        let (renew_at, rebind_at, expires) = match self.lease {
+            Some(ref lease) => (lease.renew_at, lease.rebind_at, lease.expires),
+            None => (0, 0, 0),
+        };
+
+        match self.state {
+            State::Init => self.discover(socket, now),
+            State::Selecting if now >= self.deadline => self.discover(socket, now),
+            State::Requesting if now >= self.deadline => {
+                if self.tries < REQUEST_TRIES {
+                    self.request(socket, now);
+                } else {
+                    self.restart();
+                }
+            }
+            State::Bound if now >= renew_at => {
+                self.state = State::Renewing;
+                self.renew(socket, now);
+            }
+            State::Renewing if now >= rebind_at => {
+                self.state = State::Rebinding;
+                self.renew(socket, now);
+            }
+            State::Rebinding if now >= expires => {
+                log_warn!("dhcp: {}: lease of {} ran out", self.name,
+                          self.lease.as_ref().map_or(Ipv4Addr::UNSPECIFIED, |l| l.config.addr));
+                self.unconfigure();
+                self.restart();
+            }
+            State::Renewing | State::Rebinding if now >= self.deadline => self.renew(socket, now),
+            _ => {}
+        }
+    }
+
+    /// Handles `reply`, which is to this client.
+    fn handle(&mut self, socket: SocketId, reply: &Reply, now: u64) {
+        match (self.state, reply.kind) {
+            (State::Selecting, OFFER) => {
+                let server = match reply.server {
+                    Some(server) => server,
+                    None => return,
+                };
+                self.offer = Some((reply.yiaddr, server));
+                self.tries = 0;
+                self.request(socket, now);
+            }
+            (State::Requesting, ACK) | (State::Renewing, ACK) | (State::Rebinding, ACK) => {
+                self.bind(reply, now);
+            }
+            (State::Requesting, NAK) | (State::Renewing, NAK) | (State::Rebinding, NAK) => {
+                log_warn!("dhcp: {}: server refused the address", self.name);
+                self.unconfigure();
+                self.restart();
+            }
+            _ => {}
+        }
+    }
+
+    /// Forgets any offer and starts looking for servers again.
+    fn restart(&mut self) {
+        self.state = State::Init;
+        self.offer = None;
+        self.timeout = FIRST_TIMEOUT_US;
+    }
+
+    /// Broadcasts a discover.
+    fn discover(&mut self, socket: SocketId, now: u64) {
+        self.xid = new_xid();
+        self.state = State::Selecting;
+        let msg = message(DISCOVER, self.xid, self.mac, Ipv4Addr::UNSPECIFIED, &[]);
+        self.send(socket, None, &msg);
+        self.deadline = now + self.timeout;
+        self.timeout = (self.timeout * 2).min(MAX_TIMEOUT_US);
+    }
+
+    /// Broadcasts a request for the offered address.
+    fn request(&mut self, socket: SocketId, now: u64) {
+        let (addr, server) = match self.offer {
+            Some(offer) => offer,
+            None => {
+                self.restart();
+                return;
+            }
+        };
+
+        self.state = State::Requesting;
+        self.tries += 1;
+        let options: [(u8, &[u8]); 2] = [(OPT_REQUESTED_ADDR, &addr.0), (OPT_SERVER_ID, &server.0)];
+        let msg = message(REQUEST, self.xid, self.mac, Ipv4Addr::UNSPECIFIED, &options);
+        self.send(socket, None, &msg);
+        self.deadline = now + FIRST_TIMEOUT_US;
+    }
+
+    /// Asks to extend the lease: the server that granted it while renewing,
+    /// and any server, by broadcast, while rebinding. Asks again halfway to
+    /// the next step, but not more than once a minute.
+    fn renew(&mut self, socket: SocketId, now: u64) {
+        let (addr, server, next) = match self.lease {
+            Some(ref lease) if self.state == State::Renewing => {
+                (lease.config.addr, Some(lease.server), lease.rebind_at)
+            }
+            Some(ref lease) => (lease.config.addr, None, lease.expires),
+            None => {
+                self.restart();
+                return;
+            }
+        };
+
+        self.xid = new_xid();
+        let msg = message(REQUEST, self.xid, self.mac, addr, &[]);
+        self.send(socket, server, &msg);
+        let wait = (next.saturating_sub(now) / 2).max(MIN_RENEW_RETRY_US);
+        self.deadline = now.saturating_add(wait).min(next);
+    }
+
+    /// Takes the lease `ack` grants and configures the interface with it.
+    fn bind(&mut self, ack: &Reply, now: u64) {
+        let server = match ack.server.or(self.offer.map(|(_, server)| server)) {
+            Some(server) => server,
+            None => return,
+        };
+        let config = Config {
+            addr: ack.yiaddr,
+            // Servers should always send the mask; without it, assume the
+            // most common.
+            netmask: ack.netmask.unwrap_or(Ipv4Addr([255, 255, 255, 0])),
+            gateway: ack.router,
+            dns: ack.dns,
+        };
+
+        let lease_s = ack.lease.unwrap_or(INFINITE);
+        let (renew_at, rebind_at, expires) = lease_times(now, lease_s, ack.renewal, ack.rebinding);
+        let lease = Lease { server, config, renew_at, rebind_at, expires };
+
+        let renewed = self.lease.as_ref().map_or(false, |old| old.config == config);
+        if !renewed {
+            log_info!("dhcp: {}: leased {}/{} from {} for {}s", self.name, config.addr,
+                      config.prefix_len(), server, lease_s);
+        }
+        if let Err(e) = net::set_config(self.index, Some(config)) {
+            log_warn!("dhcp: {}: {}", self.name, e);
+        }
+
+        self.state = State::Bound;
+        self.offer = None;
+        self.timeout = FIRST_TIMEOUT_US;
+        self.lease = Some(lease);
+    }
+
+    /// Removes the lease's address from the interface.
+    fn unconfigure(&mut self) {
+        if self.lease.take().is_some() {
+            let _ = net::set_config(self.index, None);
+        }
+    }
+
+    /// Sends `msg` to `server`, or broadcasts it if `server` is `None`.
+    fn send(&self, socket: SocketId, server: Option<Ipv4Addr>, msg: &[u8]) {
+        let result = match server {
+            Some(server) => udp::send_to(socket, server, SERVER_PORT, msg),
+            None => match net::broadcast_route(self.index) {
+                Some(route) => udp::send_routed(socket, &route, Ipv4Addr::BROADCAST, SERVER_PORT,
+                                                msg),
+                None => return,
+            },
+        };
+
+        if let Err(e) = result {
+            log_warn!("dhcp: {}: {}", self.name, e);
+        }
+    }
+}
+
+/// Returns when to start renewing and rebinding a lease of `lease_s`
+/// seconds granted at `now`, and when it runs out, as `Lease` holds them.
+/// `renewal_s` and `rebinding_s` are the times the server gave, if any;
+/// they default to a half and seven eighths of the lease, and are kept in
+/// order and within it, so that a lease always runs out while rebinding.
+pub(super) fn lease_times(now: u64, lease_s: u32, renewal_s: Option<u32>,
+                          rebinding_s: Option<u32>) -> (u64, u64, u64) {
+    if lease_s == INFINITE {
+        return (u64::max_value(), u64::max_value(), u64::max_value());
+    }
+
+    let rebinding_s = rebinding_s.unwrap_or(lease_s / 8 * 7).min(lease_s);
+    let renewal_s = renewal_s.unwrap_or(lease_s / 2).min(rebinding_s);
+    let at = |secs: u32| now + secs as u64 * 1_000_000;
+    (at(renewal_s), at(rebinding_s), at(lease_s))
+}
+
+/// Returns a random transaction ID.
+fn new_xid() -> u32 {
+    random::next_u64().unwrap_or_else(|_| clock::monotonic_us()) as u32
+}
+
+/// Returns a message of type `kind` from the interface with hardware
+/// address `mac` and address `ciaddr`, with `options` after the type and
+/// the parameters asked for.
+pub(super) fn message(kind: u8, xid: u32, mac: MacAddr, ciaddr: Ipv4Addr,
+                      options: &[(u8, &[u8])]) -> Vec<u8>
+{
+    let mut msg = vec![0; FIXED_LEN];
+    msg[0] = OP_REQUEST;
+    msg[1] = HTYPE_ETHERNET;
+    msg[2] = 6;
+    put_u32(&mut msg, 4, xid);
+    if ciaddr == Ipv4Addr::UNSPECIFIED {
+        put_u16(&mut msg, 10, FLAG_BROADCAST);
+    }
+    msg[12..16].copy_from_slice(&ciaddr.0);
+    msg[28..34].copy_from_slice(&mac.0);
+    msg.extend_from_slice(&MAGIC);
+
+    msg.extend_from_slice(&[OPT_MESSAGE_TYPE, 1, kind]);
+    msg.extend_from_slice(&[OPT_PARAMETERS, 6, OPT_NETMASK, OPT_ROUTER, OPT_DNS, OPT_LEASE_TIME,
+                            OPT_RENEWAL_TIME, OPT_REBINDING_TIME]);
+    for &(code, data) in options.iter() {
+        msg.push(code);
+        msg.push(data.len() as u8);
+        msg.extend_from_slice(data);
+    }
+    msg.push(OPT_END);
+    msg
+}
+
+kernel_init!(DHCP_INIT, Stage::Scheduler, "DHCP client", Init::Plain(start));
+
+/// Starts the client thread.
+fn start() {
+    if kthread::spawn("dhcp", run).is_none() {
+        log_warn!("no memory for the DHCP client; interfaces won't be configured");
+    }
+}
+
+/// The client thread: adopts new Ethernet interfaces, handles replies, and
+/// keeps every client's timers.
+fn run() {
+    let socket = match udp::bind(scheduler::current_id(), CLIENT_PORT) {
+        Ok(socket) => socket,
+        Err(e) => {
+            log_warn!("dhcp: can't bind port {}: {}", CLIENT_PORT, e);
+            return;
+        }
+    };
+
+    let mut clients: Vec<Client> = Vec::new();
+    let mut seen = 0;
+    let mut buf = vec![0; 1500];
+    loop {
+        let count = net::interface_count();
+        for index in seen..count {
+            match net::info(index) {
+                Some(ref info) if info.link == Link::Ethernet && info.mac != MacAddr::ZERO
+                    && info.config.is_none() =>
+                {
+                    clients.push(Client::new(index, info.name.clone(), info.mac));
+                }
+                _ => {}
+            }
+        }
+        seen = count;
+
+        let now = clock::monotonic_us();
+        for client in clients.iter_mut() {
+            if net::info(client.index).map_or(false, |info| info.up) {
+                client.poll(socket, now);
+            }
+        }
+
+        match udp::recv_timeout(socket, &mut buf, POLL_MS) {
+            Ok(Some((len, _, _))) => if let Some(reply) = Reply::parse(&buf[..len]) {
+                let now = clock::monotonic_us();
+                for client in clients.iter_mut() {
+                    if client.xid == reply.xid && client.mac == reply.chaddr {
+                        client.handle(socket, &reply, now);
+                    }
+                }
+            },
+            Ok(None) => {}
+            Err(e) => log_warn!("dhcp: {}", e),
+        }
+
+        let now = clock::monotonic_us();
+        let status = clients.iter().map(|client| (client.index, client.status(now))).collect();
+        *STATUS.lock() = Some(status);
+    }
+}
@@ -0,0 +1,67 @@
+//! Ethernet II frames: the destination and source addresses, the EtherType
+//! naming the protocol of the payload, then the payload. The frame check
+//! sequence is the device's business and isn't included.
+
+use bytes::be::{push_u16, u16_at};
+use net::MacAddr;
+
+/// The length of the header.
+pub const HEADER_LEN: usize = 14;
+
+/// The shortest frame, without the frame check sequence. Shorter frames are
+/// padded with zeros.
+pub const MIN_FRAME_LEN: usize = 60;
+
+/// The EtherType of IPv4 packets.
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+
+/// The EtherType of ARP packets.
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+
+/// A received frame.
+#[derive(Debug)]
+pub struct Frame<'a> {
+    pub dst: MacAddr,
+    pub src: MacAddr,
+    pub ethertype: u16,
+    /// The payload, with any padding.
+    pub payload: &'a [u8],
+}
+
+impl<'a> Frame<'a> {
+    /// Parses `bytes` as a frame, or returns `None` if it is too short to
+    /// hold a header.
+    pub fn parse(bytes: &'a [u8]) -> Option<Frame<'a>> {
+        if bytes.len() < HEADER_LEN {
+            return None;
+        }
+
+        Some(Frame {
+            dst: mac_at(bytes, 0),
+            src: mac_at(bytes, 6),
+            ethertype: u16_at(bytes, 12),
+            payload: &bytes[HEADER_LEN..],
+        })
+    }
+}
+
+/// Returns a frame from `src` to `dst` carrying `payload` of protocol
+/// `ethertype`, padded to `MIN_FRAME_LEN`.
+pub fn build(dst: MacAddr, src: MacAddr, ethertype: u16, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    frame.extend_from_slice(&dst.0);
+    frame.extend_from_slice(&src.0);
+    push_u16(&mut frame, ethertype);
+    frame.extend_from_slice(payload);
+    if frame.len() < MIN_FRAME_LEN {
+        frame.resize(MIN_FRAME_LEN, 0);
+    }
+    frame
+}
+
+/// Returns the address in the six bytes of `bytes` from `offset`.
+pub fn mac_at(bytes: &[u8], offset: usize) -> MacAddr {
+    let mut mac = [0; 6];
+    mac.copy_from_slice(&bytes[offset..offset + 6]);
+    MacAddr(mac)
+}
@@ -0,0 +1,105 @@
+//! ICMP echo: answering pings, and sending them and collecting the replies
+//! for `ping`. Other ICMP messages are ignored.
+
+use std::collections::VecDeque;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use bytes::be::{put_u16, u16_at};
+use clock;
+use mutex::Mutex;
+use net::ipv4::{self, Header, Ipv4Addr};
+
+/// The length of an echo message's header.
+const HEADER_LEN: usize = 8;
+
+/// The message types.
+const TYPE_ECHO_REPLY: u8 = 0;
+const TYPE_ECHO_REQUEST: u8 = 8;
+
+/// The most replies kept for pingers to collect; the oldest is dropped to
+/// make room.
+const MAX_REPLIES: usize = 16;
+
+/// An echo reply received.
+#[derive(Debug, Copy, Clone)]
+pub struct EchoReply {
+    pub from: Ipv4Addr,
+    pub id: u16,
+    pub seq: u16,
+    pub ttl: u8,
+    /// The length of the message, header included.
+    pub len: usize,
+    /// When it arrived, by `clock::monotonic_us()`.
+    pub received: u64,
+}
+
+/// Replies received and not yet collected.
+static REPLIES: Mutex<Option<VecDeque<EchoReply>>> = Mutex::new(None);
+
+/// The identifier of the next pinger.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// Returns an identifier for a new pinger's echo requests, so that it can
+/// tell its replies from other pingers'.
+pub fn next_id() -> u16 {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed) as u16
+}
+
+/// Sends an echo request carrying `data` to `dst`, identified by `id` and
+/// `seq`.
+pub fn send_echo(dst: Ipv4Addr, id: u16, seq: u16, data: &[u8]) -> io::Result<()> {
+    let mut message = vec![0; HEADER_LEN];
+    message[0] = TYPE_ECHO_REQUEST;
+    put_u16(&mut message, 4, id);
+    put_u16(&mut message, 6, seq);
+    message.extend_from_slice(data);
+    let sum = ipv4::checksum(&[&message]);
+    put_u16(&mut message, 2, sum);
+    ipv4::send(dst, ipv4::PROTO_ICMP, &message)
+}
+
+/// Removes and returns the reply to the echo request identified by `id`
+/// and `seq`, if it has arrived.
+pub fn take_reply(id: u16, seq: u16) -> Option<EchoReply> {
+    let mut replies = REPLIES.lock();
+    let replies = replies.get_or_insert_with(VecDeque::new);
+    let index = replies.iter().position(|reply| reply.id == id && reply.seq == seq)?;
+    replies.remove(index)
+}
+
+/// Handles `bytes`, an ICMP message received in a packet with `header`.
+pub fn receive(header: &Header, bytes: &[u8]) {
+    if bytes.len() < HEADER_LEN || bytes[1] != 0 || ipv4::checksum(&[bytes]) != 0 {
+        return;
+    }
+
+    match bytes[0] {
+        TYPE_ECHO_REQUEST => {
+            let mut reply = bytes.to_vec();
+            reply[0] = TYPE_ECHO_REPLY;
+            put_u16(&mut reply, 2, 0);
+            let sum = ipv4::checksum(&[&reply]);
+            put_u16(&mut reply, 2, sum);
+            let _ = ipv4::send(header.src, ipv4::PROTO_ICMP, &reply);
+        }
+        TYPE_ECHO_REPLY => {
+            let reply = EchoReply {
+                from: header.src,
+                id: u16_at(bytes, 4),
+                seq: u16_at(bytes, 6),
+                ttl: header.ttl,
+                len: bytes.len(),
+                received: clock::monotonic_us(),
+            };
+
+            let mut replies = REPLIES.lock();
+            let replies = replies.get_or_insert_with(VecDeque::new);
+            if replies.len() >= MAX_REPLIES {
+                replies.pop_front();
+            }
+            replies.push_back(reply);
+        }
+        _ => {}
+    }
+}
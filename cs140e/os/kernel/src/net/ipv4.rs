@@ -0,0 +1,224 @@
+//! IPv4: addresses, packet headers and checksums, and sending and receiving
+//! packets.
+//!
+//! Fragments aren't reassembled, and packets larger than the MTU of the
+//! interface they leave by aren't fragmented: received fragments are
+//! dropped, and sending too large a packet fails. Options in received
+//! headers are skipped and never sent.
+
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use bytes::be::{put_u16, u16_at};
+use net::{self, arp, ethernet, icmp, tcp, udp, Link, MacAddr, Route};
+
+/// The length of a header without options.
+pub const HEADER_LEN: usize = 20;
+
+/// The protocol numbers of ICMP, TCP, and UDP.
+pub const PROTO_ICMP: u8 = 1;
+pub const PROTO_TCP: u8 = 6;
+pub const PROTO_UDP: u8 = 17;
+
+/// The time to live of packets sent.
+const DEFAULT_TTL: u8 = 64;
+
+/// The flag that forbids routers to fragment a packet, and the bits of the
+/// fragment field that show a packet is a fragment: more fragments follow,
+/// or it starts past the start of the original.
+const FLAG_DONT_FRAGMENT: u16 = 0x4000;
+const FRAGMENT_MASK: u16 = 0x3FFF;
+
+/// The identification of the next packet sent.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// An IPv4 address.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Ipv4Addr(pub [u8; 4]);
+
+impl Ipv4Addr {
+    /// The address of a host that doesn't know its own yet.
+    pub const UNSPECIFIED: Ipv4Addr = Ipv4Addr([0; 4]);
+
+    /// The address of every host on the local network.
+    pub const BROADCAST: Ipv4Addr = Ipv4Addr([255; 4]);
+
+    /// The address of this host on the loopback interface.
+    pub const LOCALHOST: Ipv4Addr = Ipv4Addr([127, 0, 0, 1]);
+
+    /// Returns the address whose bits are `bits`, most significant first.
+    pub fn from_u32(bits: u32) -> Ipv4Addr {
+        Ipv4Addr([(bits >> 24) as u8, (bits >> 16) as u8, (bits >> 8) as u8, bits as u8])
+    }
+
+    /// Returns the address's bits, most significant first.
+    pub fn to_u32(&self) -> u32 {
+        self.0.iter().fold(0, |bits, &b| bits << 8 | b as u32)
+    }
+
+    /// Returns whether the address is in `127.0.0.0/8`.
+    pub fn is_loopback(&self) -> bool {
+        self.0[0] == 127
+    }
+
+    /// Returns the address in the four bytes of `bytes` from `offset`.
+    pub fn at(bytes: &[u8], offset: usize) -> Ipv4Addr {
+        let mut addr = [0; 4];
+        addr.copy_from_slice(&bytes[offset..offset + 4]);
+        Ipv4Addr(addr)
+    }
+}
+
+impl fmt::Display for Ipv4Addr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}.{}", self.0[0], self.0[1], self.0[2], self.0[3])
+    }
+}
+
+/// The error for a string that isn't a dotted-quad address.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ParseAddrError;
+
+impl FromStr for Ipv4Addr {
+    type Err = ParseAddrError;
+
+    fn from_str(s: &str) -> Result<Ipv4Addr, ParseAddrError> {
+        let mut addr = [0; 4];
+        let mut parts = s.split('.');
+        for byte in addr.iter_mut() {
+            let part = parts.next().ok_or(ParseAddrError)?;
+            if part.is_empty() || part.len() > 3 || !part.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(ParseAddrError);
+            }
+            *byte = part.parse().map_err(|_| ParseAddrError)?;
+        }
+
+        match parts.next() {
+            Some(_) => Err(ParseAddrError),
+            None => Ok(Ipv4Addr(addr)),
+        }
+    }
+}
+
+/// The header of a received packet.
+#[derive(Debug, Copy, Clone)]
+pub struct Header {
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    pub protocol: u8,
+    pub ttl: u8,
+}
+
+/// Returns the Internet checksum of the concatenation of `parts`: the one's
+/// complement of the one's complement sum of its 16-bit words.
+pub fn checksum(parts: &[&[u8]]) -> u16 {
+    let mut sum: u32 = 0;
+    let mut odd = None;
+    for part in parts.iter() {
+        for &byte in part.iter() {
+            match odd.take() {
+                Some(high) => sum += (high as u32) << 8 | byte as u32,
+                None => odd = Some(byte),
+            }
+        }
+    }
+    if let Some(high) = odd {
+        sum += (high as u32) << 8;
+    }
+
+    while sum >> 16 != 0 {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Returns the pseudo-header UDP and TCP include in their checksums.
+pub fn pseudo_header(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, len: usize) -> [u8; 12] {
+    let mut header = [0; 12];
+    header[0..4].copy_from_slice(&src.0);
+    header[4..8].copy_from_slice(&dst.0);
+    header[9] = protocol;
+    put_u16(&mut header, 10, len as u16);
+    header
+}
+
+/// Returns the error for a destination no interface can reach.
+fn unreachable() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "network unreachable")
+}
+
+/// Sends `payload` of `protocol` to `dst`, by the route `net::route()`
+/// chooses.
+pub fn send(dst: Ipv4Addr, protocol: u8, payload: &[u8]) -> io::Result<()> {
+    let route = net::route(dst).ok_or_else(unreachable)?;
+    send_routed(&route, dst, protocol, payload)
+}
+
+/// Sends `payload` of `protocol` to `dst` by `route`. Fails with
+/// `InvalidInput` if the packet is larger than the interface's MTU.
+pub fn send_routed(route: &Route, dst: Ipv4Addr, protocol: u8, payload: &[u8]) -> io::Result<()> {
+    let len = HEADER_LEN + payload.len();
+    if len > route.mtu || len > 0xFFFF {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "packet too large"));
+    }
+
+    let mut packet = vec![0; HEADER_LEN];
+    packet[0] = 0x45;
+    put_u16(&mut packet, 2, len as u16);
+    put_u16(&mut packet, 4, NEXT_ID.fetch_add(1, Ordering::Relaxed) as u16);
+    put_u16(&mut packet, 6, FLAG_DONT_FRAGMENT);
+    packet[8] = DEFAULT_TTL;
+    packet[9] = protocol;
+    packet[12..16].copy_from_slice(&route.src.0);
+    packet[16..20].copy_from_slice(&dst.0);
+    let sum = checksum(&[&packet]);
+    put_u16(&mut packet, 10, sum);
+    packet.extend_from_slice(payload);
+
+    let dst_mac = match route.link {
+        Link::Ip => return net::send(route.index, &packet),
+        // The loopback device needs no addresses.
+        Link::Ethernet if route.mac == MacAddr::ZERO => MacAddr::ZERO,
+        Link::Ethernet if route.broadcast => MacAddr::BROADCAST,
+        Link::Ethernet => return arp::send(route, packet),
+    };
+    let frame = ethernet::build(dst_mac, route.mac, ethernet::ETHERTYPE_IPV4, &packet);
+    net::send(route.index, &frame)
+}
+
+/// Handles `bytes`, an IPv4 packet received by interface `index`.
+pub fn receive(index: usize, bytes: &[u8]) {
+    if bytes.len() < HEADER_LEN || bytes[0] >> 4 != 4 {
+        return;
+    }
+
+    let header_len = ((bytes[0] & 0x0F) as usize) * 4;
+    let len = u16_at(bytes, 2) as usize;
+    if header_len < HEADER_LEN || len < header_len || len > bytes.len()
+        || checksum(&[&bytes[..header_len]]) != 0
+        || u16_at(bytes, 6) & FRAGMENT_MASK != 0
+    {
+        return;
+    }
+
+    let header = Header {
+        src: Ipv4Addr::at(bytes, 12),
+        dst: Ipv4Addr::at(bytes, 16),
+        protocol: bytes[9],
+        ttl: bytes[8],
+    };
+    if !net::accepts(index, header.dst) {
+        return;
+    }
+
+    // Any padding the link added is past `len`.
+    let payload = &bytes[header_len..len];
+    match header.protocol {
+        PROTO_ICMP => icmp::receive(&header, payload),
+        PROTO_TCP => tcp::receive(&header, payload),
+        PROTO_UDP => udp::receive(&header, payload),
+        _ => {}
+    }
+}
@@ -0,0 +1,52 @@
+//! The loopback device, `lo`: every frame sent is received back, so the
+//! stack can talk to itself without any hardware.
+
+use std::collections::VecDeque;
+use std::io;
+
+use net::{MacAddr, NetDevice};
+
+/// The most frames queued; frames sent beyond it are dropped.
+const QUEUE_LEN: usize = 64;
+
+/// The loopback device's MTU, the largest an IPv4 packet can be.
+const MTU: usize = 65_535;
+
+/// The loopback device.
+pub struct Loopback {
+    queue: VecDeque<Vec<u8>>,
+}
+
+impl Loopback {
+    /// Returns a loopback device with nothing queued.
+    pub fn new() -> Loopback {
+        Loopback { queue: VecDeque::new() }
+    }
+}
+
+impl NetDevice for Loopback {
+    fn name(&self) -> &str {
+        "lo"
+    }
+
+    fn mac_addr(&self) -> MacAddr {
+        MacAddr::ZERO
+    }
+
+    fn mtu(&self) -> usize {
+        MTU
+    }
+
+    fn send_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        if self.queue.len() >= QUEUE_LEN {
+            return Err(io::Error::new(io::ErrorKind::WouldBlock, "loopback queue full"));
+        }
+
+        self.queue.push_back(frame.to_vec());
+        Ok(())
+    }
+
+    fn recv_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.queue.pop_front())
+    }
+}
@@ -0,0 +1,456 @@
+//! Networking: the devices frames are sent and received through, and the
+//! protocols above them.
+//!
+//! A driver implements `NetDevice`, which moves whole link-layer frames, and
+//! registers the device as an interface with `register()`. The protocols
+//! above don't know what kind of device they are using beyond its `Link`:
+//! whether its frames carry an Ethernet header or are bare IP packets.
+//!
+//! Devices are polled for received frames by the `netpoll` kernel thread,
+//! which hands each to the protocol it is for. An interface is given an
+//! IPv4 address with `set_config()`, and packets are sent by the interface
+//! `route()` chooses for their destination: our own addresses and the
+//! loopback network by the loopback interface, then the interface on the
+//! destination's network, then one with a gateway.
+
+pub mod arp;
+pub mod dhcp;
+pub mod ethernet;
+pub mod icmp;
+pub mod ipv4;
+pub mod loopback;
+pub mod slip;
+pub mod sntp;
+pub mod tcp;
+pub mod telnet;
+pub mod tftp;
+pub mod udp;
+
+#[cfg(test)]
+mod tests;
+
+use std::fmt;
+use std::io;
+
+use console::log_warn;
+use init::{kernel_init, Init, Stage};
+use kthread;
+use mutex::Mutex;
+use tick;
+use self::ethernet::Frame;
+use self::loopback::Loopback;
+
+pub use self::ipv4::Ipv4Addr;
+
+/// The most frames taken from one device in a round of polling, so that a
+/// busy device can't starve the others.
+const POLL_BUDGET: usize = 32;
+
+/// How long the poller sleeps after a round in which no frames arrived. It
+/// wakes on the first tick after.
+const POLL_INTERVAL_MS: u64 = 1;
+
+/// An Ethernet hardware address.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MacAddr(pub [u8; 6]);
+
+impl MacAddr {
+    /// The address of every station on the link.
+    pub const BROADCAST: MacAddr = MacAddr([0xFF; 6]);
+
+    /// The address of devices that have none, such as the loopback device.
+    pub const ZERO: MacAddr = MacAddr([0; 6]);
+
+    /// Returns whether frames sent to the address reach more than one
+    /// station: the broadcast address and multicast groups.
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 1 != 0
+    }
+}
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let b = &self.0;
+        write!(f, "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", b[0], b[1], b[2], b[3], b[4], b[5])
+    }
+}
+
+/// What a device's frames are.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Link {
+    /// Ethernet II frames, with a header of addresses and an EtherType.
+    Ethernet,
+    /// IP packets with no link-layer header, as on a serial line.
+    Ip,
+}
+
+/// A network device, which sends and receives whole frames.
+///
+/// Neither operation blocks: a driver queues what it can't send yet, and
+/// `recv_frame()` returns `None` when nothing has arrived.
+pub trait NetDevice: Send {
+    /// Returns the device's name, such as `eth0`.
+    fn name(&self) -> &str;
+
+    /// Returns the device's hardware address, or `MacAddr::ZERO` if it has
+    /// none.
+    fn mac_addr(&self) -> MacAddr;
+
+    /// Returns the largest packet a frame can carry, not counting the
+    /// link-layer header.
+    fn mtu(&self) -> usize;
+
+    /// Returns what the device's frames are.
+    fn link(&self) -> Link {
+        Link::Ethernet
+    }
+
+    /// Returns whether the device is connected to a network.
+    fn link_up(&self) -> bool {
+        true
+    }
+
+    /// Sends `frame`, including its link-layer header.
+    fn send_frame(&mut self, frame: &[u8]) -> io::Result<()>;
+
+    /// Returns the next frame received, or `None` if there is none.
+    fn recv_frame(&mut self) -> io::Result<Option<Vec<u8>>>;
+}
+
+/// Frame and byte counts of an interface.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Stats {
+    pub rx_frames: u64,
+    pub rx_bytes: u64,
+    pub tx_frames: u64,
+    pub tx_bytes: u64,
+    /// Frames the device failed to send or receive.
+    pub errors: u64,
+}
+
+/// An interface's IPv4 configuration.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub addr: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    /// The router to send packets for other networks to.
+    pub gateway: Option<Ipv4Addr>,
+    /// The name server to use.
+    pub dns: Option<Ipv4Addr>,
+}
+
+impl Config {
+    /// Returns whether `addr` is on the interface's network.
+    pub fn contains(&self, addr: Ipv4Addr) -> bool {
+        let mask = self.netmask.to_u32();
+        addr.to_u32() & mask == self.addr.to_u32() & mask
+    }
+
+    /// Returns the broadcast address of the interface's network.
+    pub fn broadcast(&self) -> Ipv4Addr {
+        Ipv4Addr::from_u32(self.addr.to_u32() | !self.netmask.to_u32())
+    }
+
+    /// Returns the number of leading ones in the netmask.
+    pub fn prefix_len(&self) -> u32 {
+        self.netmask.to_u32().count_ones()
+    }
+}
+
+/// A registered device.
+struct Interface {
+    device: Box<NetDevice>,
+    stats: Stats,
+    config: Option<Config>,
+}
+
+/// A snapshot of an interface, for listing.
+#[derive(Debug, Clone)]
+pub struct Info {
+    /// The interface's index, for `send()` and `recv()`.
+    pub index: usize,
+    pub name: String,
+    pub mac: MacAddr,
+    pub mtu: usize,
+    pub link: Link,
+    pub up: bool,
+    pub stats: Stats,
+    pub config: Option<Config>,
+}
+
+/// How to send a packet: by which interface, from which address, and to
+/// which neighbor.
+#[derive(Debug, Copy, Clone)]
+pub struct Route {
+    pub index: usize,
+    /// Our address on the interface, the source of the packet.
+    pub src: Ipv4Addr,
+    /// The destination if it is on the interface's network, or else the
+    /// gateway.
+    pub next_hop: Ipv4Addr,
+    /// Whether the destination is every host on the network.
+    pub broadcast: bool,
+    pub link: Link,
+    /// Our hardware address on the interface.
+    pub mac: MacAddr,
+    pub mtu: usize,
+}
+
+/// Every registered interface, by index.
+static INTERFACES: Mutex<Option<Vec<Interface>>> = Mutex::new(None);
+
+/// Calls `f` with the interface table, creating it first if needed.
+fn with_interfaces<T, F: FnOnce(&mut Vec<Interface>) -> T>(f: F) -> T {
+    f(INTERFACES.lock().get_or_insert_with(Vec::new))
+}
+
+/// Registers `device` and returns its interface index.
+pub fn register(device: Box<NetDevice>) -> usize {
+    with_interfaces(|interfaces| {
+        interfaces.push(Interface { device, stats: Stats::default(), config: None });
+        interfaces.len() - 1
+    })
+}
+
+/// Returns the error for an interface index that names no interface.
+fn no_interface() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "no such interface")
+}
+
+/// Sends `frame` through interface `index`.
+pub fn send(index: usize, frame: &[u8]) -> io::Result<()> {
+    with_interfaces(|interfaces| {
+        let interface = interfaces.get_mut(index).ok_or_else(no_interface)?;
+        match interface.device.send_frame(frame) {
+            Ok(()) => {
+                interface.stats.tx_frames += 1;
+                interface.stats.tx_bytes += frame.len() as u64;
+                Ok(())
+            }
+            Err(e) => {
+                interface.stats.errors += 1;
+                Err(e)
+            }
+        }
+    })
+}
+
+/// Returns the next frame received by interface `index`, or `None` if there
+/// is none.
+pub fn recv(index: usize) -> io::Result<Option<Vec<u8>>> {
+    with_interfaces(|interfaces| {
+        let interface = interfaces.get_mut(index).ok_or_else(no_interface)?;
+        match interface.device.recv_frame() {
+            Ok(Some(frame)) => {
+                interface.stats.rx_frames += 1;
+                interface.stats.rx_bytes += frame.len() as u64;
+                Ok(Some(frame))
+            }
+            Ok(None) => Ok(None),
+            Err(e) => {
+                interface.stats.errors += 1;
+                Err(e)
+            }
+        }
+    })
+}
+
+/// Returns a snapshot of interface `index`, or `None` if there is none.
+pub fn info(index: usize) -> Option<Info> {
+    with_interfaces(|interfaces| {
+        interfaces.get(index).map(|interface| {
+            let device = &interface.device;
+            Info {
+                index,
+                name: device.name().to_string(),
+                mac: device.mac_addr(),
+                mtu: device.mtu(),
+                link: device.link(),
+                up: device.link_up(),
+                stats: interface.stats,
+                config: interface.config,
+            }
+        })
+    })
+}
+
+/// Returns the number of registered interfaces. Indices run from 0 to one
+/// less than it.
+pub fn interface_count() -> usize {
+    with_interfaces(|interfaces| interfaces.len())
+}
+
+/// Sets the IPv4 configuration of interface `index`, or removes it if
+/// `config` is `None`.
+pub fn set_config(index: usize, config: Option<Config>) -> io::Result<()> {
+    with_interfaces(|interfaces| {
+        interfaces.get_mut(index).ok_or_else(no_interface)?.config = config;
+        Ok(())
+    })
+}
+
+/// Returns the IPv4 configuration of interface `index`, if it has one.
+pub fn config(index: usize) -> Option<Config> {
+    with_interfaces(|interfaces| interfaces.get(index).and_then(|interface| interface.config))
+}
+
+/// Returns the hardware address of interface `index`.
+pub fn mac_addr(index: usize) -> Option<MacAddr> {
+    with_interfaces(|interfaces| interfaces.get(index).map(|interface| interface.device.mac_addr()))
+}
+
+/// Returns the route to `dst`, or `None` if no interface that is up can
+/// reach it.
+pub fn route(dst: Ipv4Addr) -> Option<Route> {
+    with_interfaces(|interfaces| {
+        let configured: Vec<(usize, &Interface, Config)> = interfaces.iter()
+            .enumerate()
+            .filter(|&(_, interface)| interface.device.link_up())
+            .filter_map(|(index, interface)| interface.config.map(|c| (index, interface, c)))
+            .collect();
+        let route_by = |&(index, interface, config): &(usize, &Interface, Config),
+                        next_hop: Ipv4Addr,
+                        broadcast: bool| Route {
+            index,
+            src: config.addr,
+            next_hop,
+            broadcast,
+            link: interface.device.link(),
+            mac: interface.device.mac_addr(),
+            mtu: interface.device.mtu(),
+        };
+
+        // Packets to ourselves go round the loopback interface, from the
+        // address they are sent to.
+        if dst.is_loopback() || configured.iter().any(|&(_, _, config)| config.addr == dst) {
+            let lo = configured.iter().find(|&&(_, _, config)| config.addr.is_loopback())?;
+            let src = if dst.is_loopback() { lo.2.addr } else { dst };
+            return Some(Route { src, ..route_by(lo, dst, false) });
+        }
+
+        let external: Vec<_> = configured.iter()
+            .filter(|&&(_, _, config)| !config.addr.is_loopback())
+            .collect();
+        if dst == Ipv4Addr::BROADCAST {
+            return external.first().map(|&via| route_by(via, dst, true));
+        }
+        if let Some(&&via) = external.iter().find(|&&&(_, _, config)| config.contains(dst)) {
+            return Some(route_by(&via, dst, dst == via.2.broadcast()));
+        }
+        external.iter()
+            .filter_map(|&via| via.2.gateway.map(|gateway| route_by(via, gateway, false)))
+            .next()
+    })
+}
+
+/// Returns the route for broadcasts on interface `index`, from its address
+/// or, if it has none yet, from `0.0.0.0`. For protocols, such as DHCP,
+/// that find out what the address is.
+pub fn broadcast_route(index: usize) -> Option<Route> {
+    with_interfaces(|interfaces| {
+        interfaces.get(index).map(|interface| Route {
+            index,
+            src: interface.config.map_or(Ipv4Addr::UNSPECIFIED, |config| config.addr),
+            next_hop: Ipv4Addr::BROADCAST,
+            broadcast: true,
+            link: interface.device.link(),
+            mac: interface.device.mac_addr(),
+            mtu: interface.device.mtu(),
+        })
+    })
+}
+
+/// Returns whether interface `index` takes packets for `dst`: ours on any
+/// interface, broadcasts, and, before the interface has an address, all.
+pub fn accepts(index: usize, dst: Ipv4Addr) -> bool {
+    with_interfaces(|interfaces| {
+        let config = match interfaces.get(index) {
+            Some(interface) => interface.config,
+            None => return false,
+        };
+
+        config.map_or(true, |config| {
+            dst == Ipv4Addr::BROADCAST || dst == config.broadcast()
+                || (dst.is_loopback() && config.addr.is_loopback())
+                || interfaces.iter().any(|interface| interface.config.map(|c| c.addr) == Some(dst))
+        })
+    })
+}
+
+/// Hands `bytes`, a frame received by interface `index`, to the protocol
+/// it is for.
+fn input(index: usize, bytes: &[u8]) {
+    let device = with_interfaces(|interfaces| {
+        interfaces.get(index).map(|i| (i.device.link(), i.device.mac_addr()))
+    });
+    let (link, mac) = match device {
+        Some(device) => device,
+        None => return,
+    };
+
+    if link == Link::Ip {
+        ipv4::receive(index, bytes);
+        return;
+    }
+
+    let frame = match Frame::parse(bytes) {
+        Some(frame) => frame,
+        None => return,
+    };
+    if frame.dst != mac && !frame.dst.is_multicast() && mac != MacAddr::ZERO {
+        return;
+    }
+
+    match frame.ethertype {
+        ethernet::ETHERTYPE_ARP => arp::receive(index, frame.payload),
+        ethernet::ETHERTYPE_IPV4 => ipv4::receive(index, frame.payload),
+        _ => {}
+    }
+}
+
+kernel_init!(NET_INIT, Stage::Drivers, "network", Init::Plain(init));
+
+/// Registers the loopback device, as interface 0, with address `127.0.0.1`.
+fn init() {
+    let lo = register(Box::new(Loopback::new()));
+    let config = Config {
+        addr: Ipv4Addr::LOCALHOST,
+        netmask: Ipv4Addr([255, 0, 0, 0]),
+        gateway: None,
+        dns: None,
+    };
+    set_config(lo, Some(config)).unwrap();
+}
+
+kernel_init!(POLLER_INIT, Stage::Scheduler, "network poller", Init::Plain(start_poller));
+
+/// Starts the poller.
+fn start_poller() {
+    if kthread::spawn("netpoll", poll).is_none() {
+        log_warn!("no memory for the network poller; no frames will be received");
+    }
+}
+
+/// The poller: takes the frames each device has received and handles them.
+fn poll() {
+    loop {
+        let mut idle = true;
+        for index in 0..interface_count() {
+            for _ in 0..POLL_BUDGET {
+                match recv(index) {
+                    Ok(Some(frame)) => {
+                        idle = false;
+                        input(index, &frame);
+                    }
+                    Ok(None) | Err(_) => break,
+                }
+            }
+        }
+
+        if idle {
+            tick::sleep_ms(POLL_INTERVAL_MS);
+        } else {
+            kthread::yield_now();
+        }
+    }
+}
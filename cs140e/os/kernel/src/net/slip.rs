@@ -0,0 +1,278 @@
+//! SLIP (RFC 1055): IP packets over the serial line, through the PL011
+//! UART, as the interface `sl0`. A slow link, but one that needs nothing
+//! but the USB serial adapter already used for the console, for boards
+//! whose USB Ethernet doesn't work.
+//!
+//! The link is set up at boot if the kernel command line has
+//! `slip=ADDR/PREFIX`, the interface's address and network, and optionally
+//! `slipgw=ADDR`, a gateway, and `slipbaud=BAUD`, the line's speed, 115200
+//! unless given. The Pi 3 has only the one pair of serial pins, so the
+//! console stops writing to the mini UART; use `telnetd=PORT` to reach the
+//! shell over the link. On the host, `slattach -p slip -s BAUD DEVICE` and
+//! `ip addr add PEER/PREFIX dev sl0` bring up the other end.
+//!
+//! Bytes are moved by the UART's interrupt: received ones into a buffer
+//! that `recv_frame()` decodes, and queued ones from a buffer that
+//! `send_frame()` encodes into, so the poller never waits for the line.
+
+use std::collections::VecDeque;
+use std::io;
+use std::mem;
+
+use pi::interrupt::Interrupt;
+use pi::pl011::{Pl011, INT_RX, INT_RX_TIMEOUT, INT_TX};
+
+use boot::BootInfo;
+use console::{log_info, log_warn, CONSOLE, UART_SINK};
+use init::{kernel_init, Init, Stage};
+use irq;
+use mutex::IrqMutex;
+use net::{self, Config, Ipv4Addr, Link, MacAddr, NetDevice};
+use traps::TrapFrame;
+
+/// The bytes that end a frame and that start an escape, and what follows
+/// the escape in place of each.
+const END: u8 = 0xC0;
+const ESC: u8 = 0xDB;
+const ESC_END: u8 = 0xDC;
+const ESC_ESC: u8 = 0xDD;
+
+/// The largest packet sent or accepted, the customary SLIP MTU.
+const MTU: usize = 1006;
+
+/// The line speed unless `slipbaud` gives another.
+const DEFAULT_BAUD: u32 = 115_200;
+
+/// How many received bytes wait to be decoded, and how many encoded bytes
+/// wait to be sent. Bytes received beyond the first are dropped, and
+/// frames that don't fit in the second are refused.
+const RX_LIMIT: usize = 8 * 1024;
+const TX_LIMIT: usize = 16 * 1024;
+
+/// The UART and the bytes its interrupt moves.
+struct Line {
+    uart: Pl011,
+    rx: VecDeque<u8>,
+    tx: VecDeque<u8>,
+}
+
+impl Line {
+    /// Moves bytes from the receive FIFO to `rx`, and from `tx` to the
+    /// transmit FIFO, masking the transmit interrupt once `tx` is empty.
+    fn pump(&mut self) {
+        // Garbled bytes are dropped; the IP checksum catches the packet
+        // they were in.
+        while let Some(byte) = self.uart.try_read_byte() {
+            if let Ok(byte) = byte {
+                if self.rx.len() < RX_LIMIT {
+                    self.rx.push_back(byte);
+                }
+            }
+        }
+
+        while self.uart.can_write() {
+            match self.tx.pop_front() {
+                Some(byte) => {
+                    self.uart.try_write_byte(byte);
+                }
+                None => break,
+            }
+        }
+        if self.tx.is_empty() {
+            self.uart.disable_interrupts(INT_TX);
+        } else {
+            self.uart.enable_interrupts(INT_TX);
+        }
+    }
+}
+
+/// The line, once `init()` has set it up. Locked by the interrupt handler.
+static LINE: IrqMutex<Option<Line>> = IrqMutex::new(None);
+
+/// The SLIP device, which decodes frames from the line's received bytes.
+pub struct Slip {
+    /// The frame being received.
+    frame: Vec<u8>,
+    /// Whether the last byte was `ESC`.
+    escaped: bool,
+    /// Whether the frame being received is bad and is being skipped to its
+    /// end.
+    dropping: bool,
+}
+
+impl Slip {
+    fn new() -> Slip {
+        Slip { frame: Vec::with_capacity(MTU), escaped: false, dropping: false }
+    }
+
+    /// Decodes `byte`, returning the frame it completes, if any. Empty
+    /// frames, from the `END` that starts each frame, are skipped.
+    fn decode(&mut self, byte: u8) -> Option<Vec<u8>> {
+        if byte == END {
+            let frame = mem::replace(&mut self.frame, Vec::with_capacity(MTU));
+            let dropped = self.dropping || self.escaped;
+            self.escaped = false;
+            self.dropping = false;
+            return if frame.is_empty() || dropped { None } else { Some(frame) };
+        }
+
+        let byte = match (self.escaped, byte) {
+            (false, ESC) => {
+                self.escaped = true;
+                return None;
+            }
+            (false, byte) => Some(byte),
+            (true, ESC_END) => Some(END),
+            (true, ESC_ESC) => Some(ESC),
+            // Not an escape: the frame is corrupt.
+            (true, _) => None,
+        };
+        self.escaped = false;
+
+        match byte {
+            Some(byte) if !self.dropping && self.frame.len() < MTU => self.frame.push(byte),
+            _ => self.dropping = true,
+        }
+        None
+    }
+}
+
+impl NetDevice for Slip {
+    fn name(&self) -> &str {
+        "sl0"
+    }
+
+    fn mac_addr(&self) -> MacAddr {
+        MacAddr::ZERO
+    }
+
+    fn mtu(&self) -> usize {
+        MTU
+    }
+
+    fn link(&self) -> Link {
+        Link::Ip
+    }
+
+    fn send_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        if frame.len() > MTU {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "frame too long"));
+        }
+
+        // An `END` before the frame too flushes any noise on the line into
+        // an empty frame, which the other end discards.
+        let mut encoded = Vec::with_capacity(frame.len() * 2 + 2);
+        encoded.push(END);
+        for &byte in frame {
+            match byte {
+                END => encoded.extend_from_slice(&[ESC, ESC_END]),
+                ESC => encoded.extend_from_slice(&[ESC, ESC_ESC]),
+                byte => encoded.push(byte),
+            }
+        }
+        encoded.push(END);
+
+        let mut line = LINE.lock();
+        let line = match line.as_mut() {
+            Some(line) => line,
+            None => return Err(io::Error::new(io::ErrorKind::NotConnected, "no serial line")),
+        };
+        if line.tx.len() + encoded.len() > TX_LIMIT {
+            return Err(io::Error::new(io::ErrorKind::WouldBlock, "slip queue full"));
+        }
+        line.tx.extend(encoded);
+        line.pump();
+        Ok(())
+    }
+
+    fn recv_frame(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut line = LINE.lock();
+        let line = match line.as_mut() {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+
+        while let Some(byte) = line.rx.pop_front() {
+            if let Some(frame) = self.decode(byte) {
+                return Ok(Some(frame));
+            }
+        }
+        Ok(None)
+    }
+}
+
+kernel_init!(SLIP_INIT, Stage::Scheduler, "SLIP", Init::Boot(init));
+
+/// Sets up the line and registers `sl0` if the command line asks for it.
+fn init(info: &BootInfo) {
+    let param = match info.param("slip") {
+        Some(param) => param,
+        None => return,
+    };
+    let config = match parse_config(param, info.param("slipgw")) {
+        Some(config) => config,
+        None => {
+            log_warn!("slip: invalid configuration {:?}; expected ADDR/PREFIX", param);
+            return;
+        }
+    };
+    let baud = match info.param("slipbaud") {
+        None => DEFAULT_BAUD,
+        Some(baud) => match baud.parse::<u32>() {
+            Ok(baud) if baud != 0 => baud,
+            _ => {
+                log_warn!("slip: invalid baud rate {:?}", baud);
+                return;
+            }
+        },
+    };
+
+    // The PL011 takes the serial pins from the mini UART, so this is the
+    // last console output that goes down the wire.
+    log_info!("slip: sl0 at {} baud, {}/{}; console leaving the serial line", baud,
+              config.addr, config.prefix_len());
+    CONSOLE.lock().set_sink_enabled(UART_SINK, false);
+
+    let mut uart = Pl011::new(baud);
+    uart.enable_interrupts(INT_RX | INT_RX_TIMEOUT);
+    *LINE.lock() = Some(Line {
+        uart,
+        rx: VecDeque::new(),
+        tx: VecDeque::new(),
+    });
+    irq::register(Interrupt::Uart, handle_irq).expect("UART IRQ already registered");
+
+    let index = net::register(Box::new(Slip::new()));
+    net::set_config(index, Some(config)).unwrap();
+}
+
+/// Returns the configuration `slip=ADDR/PREFIX` and `slipgw=ADDR` give.
+fn parse_config(param: &str, gateway: Option<&str>) -> Option<Config> {
+    let mut parts = param.splitn(2, '/');
+    let addr = parts.next()?.parse::<Ipv4Addr>().ok()?;
+    let prefix_len = parts.next()?.parse::<u32>().ok()?;
+    if prefix_len == 0 || prefix_len > 32 {
+        return None;
+    }
+    let gateway = match gateway {
+        Some(gateway) => Some(gateway.parse::<Ipv4Addr>().ok()?),
+        None => None,
+    };
+
+    Some(Config {
+        addr,
+        netmask: Ipv4Addr::from_u32(!0u32 << (32 - prefix_len)),
+        gateway,
+        dns: None,
+    })
+}
+
+/// The UART IRQ handler: moves bytes between the FIFOs and the buffers,
+/// which deasserts the interrupt.
+fn handle_irq(_tf: &mut TrapFrame) {
+    if let Some(line) = LINE.lock().as_mut() {
+        let pending = line.uart.pending_interrupts();
+        line.uart.clear_interrupts(pending);
+        line.pump();
+    }
+}
@@ -0,0 +1,208 @@
+//! An SNTP client (RFC 4330), which sets the wall clock from a time server.
+//!
+//! The server is named on the kernel command line with `ntp=ADDR`, or
+//! later with `set_server()`. One kernel thread, `sntp`, asks it for the
+//! time once the network can reach it, then every `SYNC_INTERVAL_MS`, and
+//! again after `RETRY_MS` if it doesn't answer. Each answer steps the clock:
+//! the error of a single exchange is at most half its round trip, far less
+//! than a second on a LAN, and there is no need to slew.
+
+use std::io;
+
+use boot::BootInfo;
+use bytes::be::{put_u64, u64_at};
+use clock::{self, DateTime};
+use console::{log_info, log_warn};
+use init::{kernel_init, Init, Stage};
+use kthread;
+use mutex::Mutex;
+use net::{self, udp, Ipv4Addr};
+use net::udp::SocketId;
+use scheduler;
+use tick;
+
+/// The port servers take requests on.
+const SERVER_PORT: u16 = 123;
+
+/// The length of a message without the optional authenticator.
+const PACKET_LEN: usize = 48;
+
+/// The first byte of a request: no leap warning, version 4, client mode.
+const REQUEST: u8 = 0x23;
+
+/// The modes of a message, in its low three bits.
+const MODE_MASK: u8 = 0x07;
+const MODE_SERVER: u8 = 4;
+
+/// The leap indicator, in the top two bits, of a server that isn't
+/// synchronized.
+const LEAP_UNSYNCHRONIZED: u8 = 3;
+
+/// The seconds from the NTP epoch, 1900, to the UNIX epoch.
+const EPOCH_OFFSET: u64 = 2_208_988_800;
+
+/// How long to wait for an answer, and how many times to ask.
+const TIMEOUT_MS: u64 = 2000;
+const TRIES: u32 = 3;
+
+/// How often the clock is set once it has been, and how long to wait
+/// before asking again when the server doesn't answer or can't be reached.
+const SYNC_INTERVAL_MS: u64 = 15 * 60 * 1000;
+const RETRY_MS: u64 = 30 * 1000;
+
+/// The outcome of setting the clock.
+#[derive(Debug, Copy, Clone)]
+pub struct Sample {
+    /// How far the clock was moved, in microseconds: positive if it was
+    /// behind.
+    pub offset_us: i64,
+    /// The round trip to the server, less the time it held the request.
+    pub delay_us: u64,
+}
+
+/// What the client knows, for the shell.
+#[derive(Debug, Copy, Clone)]
+pub struct Status {
+    pub server: Option<Ipv4Addr>,
+    /// When the clock was last set, in microseconds since boot, and how.
+    pub last: Option<(u64, Sample)>,
+}
+
+static STATUS: Mutex<Status> = Mutex::new(Status { server: None, last: None });
+
+/// Returns the server and the last time the clock was set.
+pub fn status() -> Status {
+    *STATUS.lock()
+}
+
+/// Makes `server` the time server the `sntp` thread asks, or clears it.
+pub fn set_server(server: Option<Ipv4Addr>) {
+    STATUS.lock().server = server;
+}
+
+/// Asks `server` for the time and sets the clock to it, returning how far
+/// it moved and the round trip.
+pub fn sync(server: Ipv4Addr) -> io::Result<Sample> {
+    let owner = scheduler::current_id();
+    let socket = udp::bind(owner, 0)?;
+    let result = query(socket, server);
+    let _ = udp::close(socket, owner);
+
+    let sample = result?;
+    STATUS.lock().last = Some((clock::monotonic_us(), sample));
+    Ok(sample)
+}
+
+fn query(socket: SocketId, server: Ipv4Addr) -> io::Result<Sample> {
+    let mut buf = [0; PACKET_LEN];
+    for _ in 0..TRIES {
+        // The server copies our transmit time into its answer, which is how
+        // the answer is matched to this request.
+        let sent = clock::monotonic_us();
+        let transmit = to_ntp(clock::to_wall_us(sent));
+        let mut request = [0; PACKET_LEN];
+        request[0] = REQUEST;
+        put_u64(&mut request, 40, transmit);
+        udp::send_to(socket, server, SERVER_PORT, &request)?;
+
+        loop {
+            let received = udp::recv_timeout(socket, &mut buf, TIMEOUT_MS)?;
+            let arrived = clock::monotonic_us();
+            let (len, from, from_port) = match received {
+                Some(received) => received,
+                None => break,
+            };
+            if from != server || from_port != SERVER_PORT || len < PACKET_LEN
+                || u64_at(&buf, 24) != transmit
+            {
+                continue;
+            }
+
+            if buf[0] & MODE_MASK != MODE_SERVER || buf[0] >> 6 == LEAP_UNSYNCHRONIZED
+                || buf[1] == 0
+            {
+                // Stratum 0 is a "kiss-o'-death": the server wants us to
+                // stop asking, or ask less often.
+                return Err(io::Error::new(io::ErrorKind::Other, "server not synchronized"));
+            }
+
+            let server_received = from_ntp(u64_at(&buf, 32));
+            let server_sent = from_ntp(u64_at(&buf, 40));
+            let held = server_sent.saturating_sub(server_received);
+            let delay = (arrived - sent).saturating_sub(held);
+            // The answer left the server halfway through the round trip.
+            let now = server_sent + delay / 2 + (clock::monotonic_us() - arrived);
+            let before = clock::now_us();
+            if !clock::set_us(now) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "time before boot"));
+            }
+            return Ok(Sample { offset_us: now as i64 - before as i64, delay_us: delay });
+        }
+    }
+
+    Err(io::Error::new(io::ErrorKind::TimedOut, "server not responding"))
+}
+
+kernel_init!(SNTP_INIT, Stage::Scheduler, "SNTP client", Init::Boot(start));
+
+/// Starts the client thread, with the server on the command line, if any.
+fn start(info: &BootInfo) {
+    if let Some(server) = info.param("ntp") {
+        match server.parse() {
+            Ok(server) => set_server(Some(server)),
+            Err(_) => log_warn!("sntp: invalid server address {:?}", server),
+        }
+    }
+
+    if kthread::spawn("sntp", run).is_none() {
+        log_warn!("no memory for the SNTP client; the clock won't be set");
+    }
+}
+
+/// The client thread: sets the clock whenever a server is known and
+/// reachable, then waits `SYNC_INTERVAL_MS`.
+fn run() {
+    let mut synced = false;
+    loop {
+        let server = match status().server {
+            Some(server) if net::route(server).is_some() => server,
+            _ => {
+                tick::sleep_ms(RETRY_MS);
+                continue;
+            }
+        };
+
+        match sync(server) {
+            Ok(sample) => {
+                if !synced {
+                    log_info!("sntp: clock set from {} to {} UTC", server,
+                              DateTime::from_epoch(clock::now_us() / 1_000_000));
+                } else if sample.offset_us.abs() >= 1_000_000 {
+                    log_warn!("sntp: clock was off by {} ms", sample.offset_us / 1000);
+                }
+                synced = true;
+                tick::sleep_ms(SYNC_INTERVAL_MS);
+            }
+            Err(e) => {
+                log_warn!("sntp: {}: {}", server, e);
+                tick::sleep_ms(RETRY_MS);
+            }
+        }
+    }
+}
+
+/// Converts microseconds since the UNIX epoch to an NTP timestamp: seconds
+/// since 1900 in the high 32 bits, and the fraction in the low.
+fn to_ntp(us: u64) -> u64 {
+    let secs = us / 1_000_000 + EPOCH_OFFSET;
+    let frac = ((us % 1_000_000) << 32) / 1_000_000;
+    secs << 32 | frac
+}
+
+/// Converts an NTP timestamp to microseconds since the UNIX epoch, or 0 if
+/// it is before it.
+fn from_ntp(timestamp: u64) -> u64 {
+    let secs = (timestamp >> 32).saturating_sub(EPOCH_OFFSET);
+    let frac = ((timestamp & 0xFFFF_FFFF) * 1_000_000) >> 32;
+    secs * 1_000_000 + frac
+}
@@ -0,0 +1,1357 @@
+//! TCP (RFC 793): reliable byte streams between a local and a remote port.
+//!
+//! A listening socket takes connections on a port of every interface and
+//! queues them, once their handshakes finish, until they are accepted.
+//! Data sent is kept until acknowledged and sent again, from the oldest
+//! unacknowledged byte, if no acknowledgment comes within the
+//! retransmission timeout, which follows the measured round-trip time (RFC
+//! 6298) and doubles with each retry. As much is kept in flight as the
+//! peer's window allows. Segments that arrive ahead of the next byte
+//! expected aren't kept: the acknowledgment sent back tells the peer where
+//! to resume.
+//!
+//! Sockets are named by ID like UDP sockets: only the process that opened
+//! one may close it, and its sockets are closed when it exits. Closing a
+//! connection sends what is buffered and then a FIN; the connection lives
+//! on, without its owner, until the peer has closed its side too.
+//!
+//! Locks are taken in this order: `EVENTS`, then `TCP`, then the random
+//! number generator (for initial sequence numbers) and the interface table
+//! (`net::route()`, for a new connection's MSS). Segments are queued while
+//! `TCP` is held and sent once it is released, and `EVENTS` is only woken
+//! with it released, so sending and waking never run under the table. The
+//! scheduler is only taken inside `EVENTS`, to park or sleep.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::io;
+
+use bytes::be::{put_u16, put_u32, u16_at, u32_at};
+use clock;
+use console::log_warn;
+use init::{kernel_init, Init, Stage};
+use kthread;
+use mutex::IrqMutex;
+use net::{self, Ipv4Addr, Route};
+use net::ipv4::{self, Header};
+use process::Id;
+use random;
+use scheduler;
+use sync::WaitQueue;
+use tick;
+
+/// A socket identifier. IDs are never reused.
+pub type SocketId = u64;
+
+/// The length of a header without options.
+const HEADER_LEN: usize = 20;
+
+/// The flags.
+const FIN: u8 = 0x01;
+const SYN: u8 = 0x02;
+const RST: u8 = 0x04;
+const PSH: u8 = 0x08;
+const ACK: u8 = 0x10;
+
+/// The options: the end of the list, padding, and the largest segment the
+/// sender takes, which is the only one sent or used.
+const OPT_END: u8 = 0;
+const OPT_NOP: u8 = 1;
+const OPT_MSS: u8 = 2;
+
+/// The largest segment a peer takes if it doesn't say.
+const DEFAULT_MSS: usize = 536;
+
+/// The smallest segment size used, whatever a peer or route says: what
+/// fits in the 68-byte datagram every IPv4 host must take. A peer claiming
+/// less, even 0, would otherwise stall the connection.
+const MIN_MSS: usize = 28;
+
+/// The size of each connection's send and receive buffers. The receive
+/// buffer's free space is the window advertised.
+const BUFFER_LEN: usize = 16 * 1024;
+
+/// The most connections a listener queues, finished or not, before it
+/// ignores new ones.
+const MAX_BACKLOG: usize = 16;
+
+/// The most sockets open at once.
+const MAX_SOCKETS: usize = 64;
+
+/// The ports given to connections: the IANA dynamic range.
+const EPHEMERAL_FIRST: u16 = 49152;
+const EPHEMERAL_LAST: u16 = 65535;
+
+/// The retransmission timeout before any round trip is measured, and its
+/// bounds.
+const INITIAL_RTO_US: u64 = 1_000_000;
+const MIN_RTO_US: u64 = 200_000;
+const MAX_RTO_US: u64 = 60_000_000;
+
+/// How many times a segment is sent again before the connection is given
+/// up on.
+const MAX_RETRIES: u32 = 8;
+
+/// How long a connection stays in TIME-WAIT, far shorter than RFC 793's
+/// four minutes, so that closed connections don't hold on to the few
+/// sockets there are.
+const TIME_WAIT_US: u64 = 10 * 1_000_000;
+
+/// How long a connection closed by its owner waits for the peer's FIN.
+const FIN_WAIT_2_US: u64 = 60 * 1_000_000;
+
+/// How often the timer thread runs, in milliseconds.
+const TIMER_MS: u64 = 10;
+
+/// The states of a connection, as named by RFC 793. Listeners have none.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum State {
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+    Closed,
+}
+
+/// A segment to send.
+pub(super) struct Segment {
+    src: Ipv4Addr,
+    dst: Ipv4Addr,
+    pub(super) bytes: Vec<u8>,
+}
+
+/// A segment received.
+pub(super) struct Incoming<'a> {
+    pub(super) src_port: u16,
+    pub(super) dst_port: u16,
+    pub(super) seq: u32,
+    pub(super) ack: u32,
+    pub(super) flags: u8,
+    pub(super) window: u16,
+    pub(super) mss: Option<usize>,
+    pub(super) data: &'a [u8],
+}
+
+impl<'a> Incoming<'a> {
+    /// Parses `bytes`, the payload of a packet with `header`, returning
+    /// `None` if it isn't a valid segment.
+    pub(super) fn parse(header: &Header, bytes: &'a [u8]) -> Option<Incoming<'a>> {
+        if bytes.len() < HEADER_LEN {
+            return None;
+        }
+
+        let offset = (bytes[12] >> 4) as usize * 4;
+        let pseudo = ipv4::pseudo_header(header.src, header.dst, ipv4::PROTO_TCP, bytes.len());
+        if offset < HEADER_LEN || offset > bytes.len() || ipv4::checksum(&[&pseudo, bytes]) != 0 {
+            return None;
+        }
+
+        let mut mss = None;
+        let mut i = HEADER_LEN;
+        while i < offset {
+            match bytes[i] {
+                OPT_END => break,
+                OPT_NOP => i += 1,
+                kind => {
+                    let len = match bytes.get(i + 1) {
+                        Some(&len) if len >= 2 && i + len as usize <= offset => len as usize,
+                        _ => break,
+                    };
+                    if kind == OPT_MSS && len == 4 {
+                        mss = Some(u16_at(bytes, i + 2) as usize);
+                    }
+                    i += len;
+                }
+            }
+        }
+
+        Some(Incoming {
+            src_port: u16_at(bytes, 0),
+            dst_port: u16_at(bytes, 2),
+            seq: u32_at(bytes, 4),
+            ack: u32_at(bytes, 8),
+            flags: bytes[13],
+            window: u16_at(bytes, 14),
+            mss,
+            data: &bytes[offset..],
+        })
+    }
+
+    /// Returns how much sequence space the segment takes.
+    fn len(&self) -> u32 {
+        let mut len = self.data.len() as u32;
+        if self.flags & SYN != 0 {
+            len += 1;
+        }
+        if self.flags & FIN != 0 {
+            len += 1;
+        }
+        len
+    }
+}
+
+/// A socket waiting for connections.
+struct Listener {
+    owner: Id,
+    port: u16,
+    backlog: usize,
+    /// Connections that are established and not yet accepted.
+    ready: VecDeque<SocketId>,
+}
+
+/// An address and port.
+pub(super) type Endpoint = (Ipv4Addr, u16);
+
+/// A connection, and its transmission control block.
+pub(super) struct Connection {
+    owner: Id,
+    pub(super) state: State,
+    local: Endpoint,
+    remote: Endpoint,
+    /// The listener that took the connection, until it is accepted.
+    listener: Option<SocketId>,
+    /// Whether the owner has closed it.
+    user_closed: bool,
+    /// Why the connection was given up on, if it was.
+    pub(super) error: Option<io::ErrorKind>,
+
+    /// Our initial sequence number, the oldest byte not acknowledged, the
+    /// next to send, and the peer's window and largest segment.
+    iss: u32,
+    pub(super) snd_una: u32,
+    pub(super) snd_nxt: u32,
+    snd_wnd: usize,
+    pub(super) snd_mss: usize,
+    /// The largest segment we take, given the route to the peer.
+    our_mss: usize,
+    /// Data not yet acknowledged, from the first byte after our SYN that
+    /// hasn't been, followed by any not yet sent.
+    pub(super) send_buf: VecDeque<u8>,
+    /// Whether to send a FIN once `send_buf` has been sent.
+    pub(super) fin_queued: bool,
+
+    /// The next byte expected, and the data received but not yet read.
+    pub(super) rcv_nxt: u32,
+    pub(super) recv_buf: VecDeque<u8>,
+    /// Whether to send an acknowledgment even if there is no data to carry
+    /// it.
+    ack_pending: bool,
+
+    /// The retransmission timeout, the smoothed round-trip time and its
+    /// variation, and the sequence number and time of the segment being
+    /// timed, if any.
+    rto: u64,
+    srtt: Option<u64>,
+    rttvar: u64,
+    timing: Option<(u32, u64)>,
+    /// When to send the oldest unacknowledged segment again, and how many
+    /// times it has been.
+    retransmit_at: Option<u64>,
+    retries: u32,
+    /// When TIME-WAIT, or FIN-WAIT-2 after the owner closed, ends.
+    deadline: u64,
+}
+
+impl Connection {
+    /// Returns a closed connection whose initial sequence number is `iss`.
+    pub(super) fn new(owner: Id, local: Endpoint, remote: Endpoint, our_mss: usize, iss: u32)
+        -> Connection
+    {
+        Connection {
+            owner,
+            state: State::Closed,
+            local,
+            remote,
+            listener: None,
+            user_closed: false,
+            error: None,
+            iss,
+            snd_una: iss,
+            snd_nxt: iss,
+            snd_wnd: 0,
+            snd_mss: DEFAULT_MSS.min(our_mss),
+            our_mss,
+            send_buf: VecDeque::new(),
+            fin_queued: false,
+            rcv_nxt: 0,
+            recv_buf: VecDeque::new(),
+            ack_pending: false,
+            rto: INITIAL_RTO_US,
+            srtt: None,
+            rttvar: 0,
+            timing: None,
+            retransmit_at: None,
+            retries: 0,
+            deadline: 0,
+        }
+    }
+
+    /// Returns the window to advertise: the receive buffer's free space.
+    fn window(&self) -> u16 {
+        (BUFFER_LEN - self.recv_buf.len()).min(0xFFFF) as u16
+    }
+
+    /// Returns the sequence number of the first byte in `send_buf`.
+    fn data_seq(&self) -> u32 {
+        let first = self.iss.wrapping_add(1);
+        if seq_lt(self.snd_una, first) { first } else { self.snd_una }
+    }
+
+    /// Returns whether our FIN has been sent.
+    fn fin_sent(&self) -> bool {
+        match self.state {
+            State::FinWait1 | State::FinWait2 | State::Closing | State::LastAck
+            | State::TimeWait => true,
+            _ => false,
+        }
+    }
+
+    /// Returns whether the peer has closed its side.
+    fn fin_received(&self) -> bool {
+        match self.state {
+            State::CloseWait | State::Closing | State::LastAck | State::TimeWait => true,
+            State::Closed => self.error.is_none(),
+            _ => false,
+        }
+    }
+
+    /// Returns the error for an operation on the connection once it has
+    /// closed.
+    fn closed_error(&self) -> io::Error {
+        match self.error {
+            Some(io::ErrorKind::ConnectionRefused) => {
+                io::Error::new(io::ErrorKind::ConnectionRefused, "connection refused")
+            }
+            Some(io::ErrorKind::TimedOut) => {
+                io::Error::new(io::ErrorKind::TimedOut, "connection timed out")
+            }
+            Some(kind) => io::Error::new(kind, "connection reset"),
+            None => io::Error::new(io::ErrorKind::NotConnected, "not connected"),
+        }
+    }
+
+    /// Returns a segment of the connection with `flags`.
+    fn segment(&self, seq: u32, flags: u8, data: &[u8]) -> Segment {
+        let ack = if flags & ACK != 0 { self.rcv_nxt } else { 0 };
+        let mss = if flags & SYN != 0 { Some(self.our_mss as u16) } else { None };
+        segment(self.local, self.remote, seq, ack, flags, self.window(), mss, data)
+    }
+
+    /// Sends our SYN, or, if `ack`, our SYN and the acknowledgment of the
+    /// peer's.
+    pub(super) fn send_syn(&mut self, ack: bool, now: u64, out: &mut Vec<Segment>) {
+        let flags = if ack { SYN | ACK } else { SYN };
+        out.push(self.segment(self.iss, flags, &[]));
+        self.snd_nxt = self.iss.wrapping_add(1);
+        self.retransmit_at = Some(now + self.rto);
+    }
+
+    /// Gives up on the connection because of `kind`.
+    fn fail(&mut self, kind: io::ErrorKind) {
+        self.state = State::Closed;
+        self.error = Some(kind);
+        self.send_buf.clear();
+        self.retransmit_at = None;
+        self.timing = None;
+    }
+
+    /// Sends as much buffered data as the peer's window allows, then the FIN
+    /// if one is queued and all data has been sent, and otherwise any
+    /// acknowledgment owed.
+    pub(super) fn output(&mut self, now: u64, out: &mut Vec<Segment>) {
+        let mut sent = false;
+        if self.state == State::Established || self.state == State::CloseWait {
+            let data_seq = self.data_seq();
+            loop {
+                let offset = self.snd_nxt.wrapping_sub(data_seq) as usize;
+                let in_flight = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+                // With nothing in flight, a byte is sent into a closed window
+                // to find out when it opens.
+                let window = if in_flight == 0 { self.snd_wnd.max(1) } else { self.snd_wnd };
+                let len = (self.send_buf.len() - offset)
+                    .min(self.snd_mss)
+                    .min(window.saturating_sub(in_flight));
+                if len == 0 {
+                    break;
+                }
+
+                let data: Vec<u8> = self.send_buf.iter().skip(offset).take(len).cloned().collect();
+                let segment = self.segment(self.snd_nxt, ACK | PSH, &data);
+                out.push(segment);
+                self.snd_nxt = self.snd_nxt.wrapping_add(len as u32);
+                sent = true;
+            }
+
+            let offset = self.snd_nxt.wrapping_sub(data_seq) as usize;
+            if self.fin_queued && offset == self.send_buf.len() {
+                let segment = self.segment(self.snd_nxt, FIN | ACK, &[]);
+                out.push(segment);
+                self.snd_nxt = self.snd_nxt.wrapping_add(1);
+                self.state = match self.state {
+                    State::Established => State::FinWait1,
+                    _ => State::LastAck,
+                };
+                sent = true;
+            }
+        }
+
+        if sent {
+            if self.retransmit_at.is_none() {
+                self.retransmit_at = Some(now + self.rto);
+            }
+            if self.timing.is_none() {
+                self.timing = Some((self.snd_nxt, now));
+            }
+            self.ack_pending = false;
+        } else if self.ack_pending {
+            let segment = self.segment(self.snd_nxt, ACK, &[]);
+            out.push(segment);
+            self.ack_pending = false;
+        }
+    }
+
+    /// Sends the oldest unacknowledged segment again. Returns `false` if it
+    /// has been sent too many times already.
+    fn retransmit(&mut self, now: u64, out: &mut Vec<Segment>) -> bool {
+        if self.retries >= MAX_RETRIES {
+            return false;
+        }
+
+        self.retries += 1;
+        self.rto = (self.rto * 2).min(MAX_RTO_US);
+        // Karn's algorithm: a segment sent twice can't be timed.
+        self.timing = None;
+        match self.state {
+            State::SynSent => self.send_syn(false, now, out),
+            State::SynReceived => self.send_syn(true, now, out),
+            _ => {
+                let in_flight = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+                if in_flight == 0 {
+                    self.retransmit_at = None;
+                    return true;
+                }
+                let fin = self.fin_sent();
+                let data_len = (in_flight - fin as usize).min(self.snd_mss);
+                if data_len > 0 {
+                    let data: Vec<u8> = self.send_buf.iter().take(data_len).cloned().collect();
+                    out.push(self.segment(self.snd_una, ACK | PSH, &data));
+                } else if fin {
+                    out.push(self.segment(self.snd_nxt.wrapping_sub(1), FIN | ACK, &[]));
+                }
+            }
+        }
+
+        self.retransmit_at = Some(now + self.rto);
+        true
+    }
+
+    /// Takes a round-trip time measurement of `rtt` microseconds and sets
+    /// the retransmission timeout from it.
+    fn sample_rtt(&mut self, rtt: u64) {
+        let srtt = match self.srtt {
+            None => {
+                self.rttvar = rtt / 2;
+                rtt
+            }
+            Some(srtt) => {
+                let delta = if srtt > rtt { srtt - rtt } else { rtt - srtt };
+                self.rttvar = (3 * self.rttvar + delta) / 4;
+                (7 * srtt + rtt) / 8
+            }
+        };
+
+        self.srtt = Some(srtt);
+        let granularity = TIMER_MS * 1000;
+        self.rto = (srtt + (4 * self.rttvar).max(granularity)).max(MIN_RTO_US).min(MAX_RTO_US);
+    }
+
+    /// Handles an acknowledgment of everything before `ack`.
+    fn acknowledge(&mut self, ack: u32, now: u64) {
+        let data_seq = self.data_seq();
+        if seq_lt(data_seq, ack) {
+            let len = (ack.wrapping_sub(data_seq) as usize).min(self.send_buf.len());
+            self.send_buf.drain(..len);
+        }
+
+        self.snd_una = ack;
+        if let Some((seq, sent)) = self.timing {
+            if seq_le(seq, ack) {
+                self.sample_rtt(now - sent);
+                self.timing = None;
+            }
+        }
+        self.retries = 0;
+        self.retransmit_at = if ack == self.snd_nxt { None } else { Some(now + self.rto) };
+    }
+
+    /// Handles `seg` in SYN-SENT, when the peer's answer to our SYN is
+    /// expected. Returns whether the connection is now established.
+    fn arrive_syn_sent(&mut self, seg: &Incoming, now: u64, out: &mut Vec<Segment>) -> bool {
+        let ack_ok = seg.flags & ACK != 0 && seg.ack == self.snd_nxt;
+        if seg.flags & ACK != 0 && !ack_ok {
+            reset(self.local, self.remote, seg, out);
+            return false;
+        }
+        if seg.flags & RST != 0 {
+            if ack_ok {
+                self.fail(io::ErrorKind::ConnectionRefused);
+            }
+            return false;
+        }
+        if seg.flags & SYN == 0 {
+            return false;
+        }
+
+        self.rcv_nxt = seg.seq.wrapping_add(1);
+        self.snd_wnd = seg.window as usize;
+        self.snd_mss = peer_mss(seg, self.our_mss);
+        if !ack_ok {
+            // Both sides opened at once.
+            self.state = State::SynReceived;
+            self.send_syn(true, now, out);
+            return false;
+        }
+
+        self.state = State::Established;
+        self.acknowledge(seg.ack, now);
+        self.ack_pending = true;
+        self.output(now, out);
+        true
+    }
+
+    /// Handles `seg`, which is for this connection. Returns whether the
+    /// connection is now established.
+    pub(super) fn arrive(&mut self, seg: &Incoming, now: u64, out: &mut Vec<Segment>) -> bool {
+        match self.state {
+            State::SynSent => return self.arrive_syn_sent(seg, now, out),
+            State::Closed => {
+                reset(self.local, self.remote, seg, out);
+                return false;
+            }
+            _ => {}
+        }
+
+        // Only the next byte expected is taken; anything ahead of it is
+        // dropped, and anything before it trimmed off.
+        if seq_lt(self.rcv_nxt, seg.seq) {
+            if seg.flags & RST == 0 {
+                self.ack_pending = true;
+                self.output(now, out);
+            }
+            return false;
+        }
+        let dup = self.rcv_nxt.wrapping_sub(seg.seq) as usize;
+        let (data, mut fin) = if dup <= seg.data.len() {
+            (&seg.data[dup..], seg.flags & FIN != 0)
+        } else {
+            (&[][..], false)
+        };
+        if seg.len() > 0 && data.is_empty() && !fin {
+            // A segment sent again whose acknowledgment was lost.
+            self.ack_pending = true;
+        }
+
+        if seg.flags & RST != 0 {
+            if dup == 0 {
+                self.fail(io::ErrorKind::ConnectionReset);
+            }
+            return false;
+        }
+        if seg.flags & SYN != 0 {
+            // A SYN in a synchronized state is answered with an
+            // acknowledgment of where we are (RFC 5961).
+            self.ack_pending = true;
+            self.output(now, out);
+            return false;
+        }
+        if seg.flags & ACK == 0 {
+            return false;
+        }
+
+        let mut established = false;
+        if self.state == State::SynReceived {
+            if seg.ack != self.snd_nxt {
+                reset(self.local, self.remote, seg, out);
+                return false;
+            }
+            self.state = State::Established;
+            established = true;
+        }
+
+        if seq_lt(self.snd_una, seg.ack) && seq_le(seg.ack, self.snd_nxt) {
+            self.acknowledge(seg.ack, now);
+        } else if seq_lt(self.snd_nxt, seg.ack) {
+            // It acknowledges something not yet sent.
+            self.ack_pending = true;
+            self.output(now, out);
+            return established;
+        }
+        self.snd_wnd = seg.window as usize;
+        if self.snd_wnd == 0 && seg.ack == self.snd_una {
+            // The peer answered a probe of its closed window, so it is still
+            // there however long the window stays closed.
+            self.retries = 0;
+        }
+
+        if self.fin_sent() && self.snd_una == self.snd_nxt {
+            match self.state {
+                State::FinWait1 => {
+                    self.state = State::FinWait2;
+                    self.deadline = now + FIN_WAIT_2_US;
+                }
+                State::Closing => {
+                    self.state = State::TimeWait;
+                    self.deadline = now + TIME_WAIT_US;
+                }
+                State::LastAck => self.state = State::Closed,
+                _ => {}
+            }
+        }
+
+        if !data.is_empty() {
+            match self.state {
+                State::Established | State::FinWait1 | State::FinWait2 => {
+                    let len = data.len().min(BUFFER_LEN - self.recv_buf.len());
+                    self.recv_buf.extend(data[..len].iter().cloned());
+                    self.rcv_nxt = self.rcv_nxt.wrapping_add(len as u32);
+                    // A FIN after data that didn't fit comes again later.
+                    fin = fin && len == data.len();
+                }
+                _ => fin = false,
+            }
+            self.ack_pending = true;
+        }
+
+        if fin {
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+            self.ack_pending = true;
+            match self.state {
+                State::Established => self.state = State::CloseWait,
+                State::FinWait1 if self.snd_una == self.snd_nxt => {
+                    self.state = State::TimeWait;
+                    self.deadline = now + TIME_WAIT_US;
+                }
+                State::FinWait1 => self.state = State::Closing,
+                State::FinWait2 => {
+                    self.state = State::TimeWait;
+                    self.deadline = now + TIME_WAIT_US;
+                }
+                _ => {}
+            }
+        }
+
+        self.output(now, out);
+        established
+    }
+
+    /// Handles the connection's timers. Returns whether its state changed.
+    pub(super) fn timer(&mut self, now: u64, out: &mut Vec<Segment>) -> bool {
+        match self.state {
+            State::TimeWait if now >= self.deadline => {
+                self.state = State::Closed;
+                true
+            }
+            State::FinWait2 if self.user_closed && now >= self.deadline => {
+                self.state = State::Closed;
+                true
+            }
+            _ => match self.retransmit_at {
+                Some(at) if now >= at => if self.retransmit(now, out) {
+                    false
+                } else {
+                    self.fail(io::ErrorKind::TimedOut);
+                    true
+                },
+                _ => false,
+            },
+        }
+    }
+
+    /// Returns whether the connection is finished with and can be freed: it
+    /// is closed, and its owner has closed it or never had it.
+    fn finished(&self) -> bool {
+        self.state == State::Closed && (self.user_closed || self.listener.is_some())
+    }
+}
+
+enum Socket {
+    Listener(Listener),
+    Connection(Connection),
+}
+
+impl Socket {
+    fn owner(&self) -> Id {
+        match *self {
+            Socket::Listener(ref listener) => listener.owner,
+            Socket::Connection(ref connection) => connection.owner,
+        }
+    }
+
+    fn port(&self) -> u16 {
+        match *self {
+            Socket::Listener(ref listener) => listener.port,
+            Socket::Connection(ref connection) => connection.local.1,
+        }
+    }
+}
+
+/// Every open socket.
+struct Tcp {
+    sockets: BTreeMap<SocketId, Socket>,
+    last_id: SocketId,
+    /// The last ephemeral port given out.
+    last_port: u16,
+}
+
+impl Tcp {
+    fn insert(&mut self, socket: Socket) -> SocketId {
+        self.last_id += 1;
+        self.sockets.insert(self.last_id, socket);
+        self.last_id
+    }
+
+    fn connection(&mut self, id: SocketId) -> io::Result<&mut Connection> {
+        match self.sockets.get_mut(&id) {
+            Some(&mut Socket::Connection(ref mut connection)) => Ok(connection),
+            Some(_) => Err(io::Error::new(io::ErrorKind::InvalidInput, "socket is listening")),
+            None => Err(no_socket()),
+        }
+    }
+
+    /// Returns an unused port from the dynamic range.
+    fn ephemeral_port(&mut self) -> u16 {
+        let mut port = self.last_port;
+        loop {
+            port = if port == EPHEMERAL_LAST { EPHEMERAL_FIRST } else { port + 1 };
+            if !self.sockets.values().any(|socket| socket.port() == port) {
+                self.last_port = port;
+                return port;
+            }
+        }
+    }
+}
+
+/// Every socket. Locked with IRQs masked; see the module documentation for
+/// what may be locked with it.
+static TCP: IrqMutex<Option<Tcp>> = IrqMutex::new(None);
+
+/// Processes waiting for something to happen on a socket: a connection to
+/// finish its handshake or be accepted, data or buffer space, or a close.
+/// Woken whenever any of those happens on any socket, and recheck their
+/// own.
+static EVENTS: WaitQueue = WaitQueue::new();
+
+/// Calls `f` with the socket table, creating it first if needed, and a list
+/// to queue segments on, which are sent once the table is unlocked.
+fn with_tcp<T, F: FnOnce(&mut Tcp, &mut Vec<Segment>) -> T>(f: F) -> T {
+    let mut out = Vec::new();
+    let result = {
+        let mut tcp = TCP.lock();
+        f(tcp.get_or_insert_with(|| Tcp {
+            sockets: BTreeMap::new(),
+            last_id: 0,
+            last_port: EPHEMERAL_LAST,
+        }), &mut out)
+    };
+
+    for segment in out {
+        if let Some(route) = net::route(segment.dst) {
+            let route = Route { src: segment.src, ..route };
+            let _ = ipv4::send_routed(&route, segment.dst, ipv4::PROTO_TCP, &segment.bytes);
+        }
+    }
+    result
+}
+
+fn no_socket() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "no such socket")
+}
+
+fn would_block() -> io::Error {
+    io::Error::new(io::ErrorKind::WouldBlock, "operation would block")
+}
+
+fn is_would_block<T>(result: &io::Result<T>) -> bool {
+    result.as_ref().err().map_or(false, |e| e.kind() == io::ErrorKind::WouldBlock)
+}
+
+/// Returns the largest segment we take by `route`.
+fn mss_of(route: Option<Route>) -> usize {
+    route.map_or(DEFAULT_MSS, |route| {
+        route.mtu.saturating_sub(ipv4::HEADER_LEN + HEADER_LEN).max(MIN_MSS).min(0xFFFF)
+    })
+}
+
+/// Returns the largest segment to send a peer that said so in `seg`, its
+/// SYN, when we take `our_mss`.
+fn peer_mss(seg: &Incoming, our_mss: usize) -> usize {
+    seg.mss.unwrap_or(DEFAULT_MSS).max(MIN_MSS).min(our_mss)
+}
+
+/// Returns an initial sequence number for a new connection.
+fn initial_sequence() -> u32 {
+    random::next_u64().unwrap_or_else(|_| clock::monotonic_us()) as u32
+}
+
+/// Runs `op` until it stops failing with `WouldBlock`, sleeping between
+/// tries until something happens on a socket.
+fn block<T, F: FnMut() -> io::Result<T>>(mut op: F) -> io::Result<T> {
+    let mut result = Err(would_block());
+    EVENTS.wait_until(|| {
+        result = op();
+        !is_would_block(&result)
+    });
+    result
+}
+
+/// Runs `op`, and if it fails with `WouldBlock`, queues the running process
+/// to be woken when something happens on a socket, marks it waiting, and
+/// asks for it to be switched out. For system calls, which retry when
+/// woken.
+fn park<T, F: FnOnce() -> io::Result<T>>(op: F) -> io::Result<T> {
+    let mut result = Err(would_block());
+    EVENTS.park_unless(|| {
+        result = op();
+        !is_would_block(&result)
+    });
+
+    if is_would_block(&result) {
+        scheduler::request_resched();
+    }
+    result
+}
+
+/// Opens a socket owned by the process `owner` that listens on `port` and
+/// queues up to `backlog` connections. Fails with `AddrInUse` if another
+/// socket listens on the port.
+pub fn listen(owner: Id, port: u16, backlog: usize) -> io::Result<SocketId> {
+    if port == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "can't listen on port 0"));
+    }
+
+    with_tcp(|tcp, _| {
+        if tcp.sockets.len() >= MAX_SOCKETS {
+            return Err(io::Error::new(io::ErrorKind::Other, "too many sockets"));
+        }
+        let in_use = tcp.sockets.values().any(|socket| match *socket {
+            Socket::Listener(ref listener) => listener.port == port,
+            _ => false,
+        });
+        if in_use {
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, "port in use"));
+        }
+
+        let backlog = backlog.max(1).min(MAX_BACKLOG);
+        Ok(tcp.insert(Socket::Listener(Listener { owner, port, backlog, ready: VecDeque::new() })))
+    })
+}
+
+/// Takes the oldest established connection from `listener`, returning it
+/// and the address and port it is from.
+fn try_accept(listener: SocketId) -> io::Result<(SocketId, Ipv4Addr, u16)> {
+    with_tcp(|tcp, _| loop {
+        let next = match tcp.sockets.get_mut(&listener) {
+            Some(&mut Socket::Listener(ref mut listener)) => listener.ready.pop_front(),
+            Some(_) => return Err(io::Error::new(io::ErrorKind::InvalidInput, "not listening")),
+            None => return Err(no_socket()),
+        };
+        let id = next.ok_or_else(would_block)?;
+
+        // A connection reset before it was accepted is gone already.
+        if let Ok(connection) = tcp.connection(id) {
+            connection.listener = None;
+            return Ok((id, connection.remote.0, connection.remote.1));
+        }
+    })
+}
+
+/// Waits for a connection to `listener` and returns it, and the address and
+/// port it is from. It belongs to the listener's owner.
+pub fn accept(listener: SocketId) -> io::Result<(SocketId, Ipv4Addr, u16)> {
+    block(|| try_accept(listener))
+}
+
+/// Like `accept()`, but parks instead of sleeping. See `park()`.
+pub fn park_accept(listener: SocketId) -> io::Result<(SocketId, Ipv4Addr, u16)> {
+    park(|| try_accept(listener))
+}
+
+/// Opens a connection, owned by the process `owner`, to `port` at `dst`,
+/// and sends its SYN. Returns its ID at once; `established()` waits for the
+/// handshake to finish.
+pub fn start_connect(owner: Id, dst: Ipv4Addr, port: u16) -> io::Result<SocketId> {
+    let route = net::route(dst)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "network unreachable"))?;
+    let now = clock::monotonic_us();
+    with_tcp(|tcp, out| {
+        if tcp.sockets.len() >= MAX_SOCKETS {
+            return Err(io::Error::new(io::ErrorKind::Other, "too many sockets"));
+        }
+
+        let local = (route.src, tcp.ephemeral_port());
+        let mut connection = Connection::new(owner, local, (dst, port), mss_of(Some(route)),
+                                             initial_sequence());
+        connection.state = State::SynSent;
+        connection.send_syn(false, now, out);
+        Ok(tcp.insert(Socket::Connection(connection)))
+    })
+}
+
+/// Returns whether the handshake of connection `id` has finished, failing
+/// with `WouldBlock` if it hasn't yet and with the reason if it failed.
+fn try_established(id: SocketId) -> io::Result<()> {
+    with_tcp(|tcp, _| {
+        let connection = tcp.connection(id)?;
+        match connection.state {
+            State::SynSent | State::SynReceived => Err(would_block()),
+            State::Closed if connection.error.is_some() => Err(connection.closed_error()),
+            _ => Ok(()),
+        }
+    })
+}
+
+/// Waits for the handshake of connection `id` to finish.
+pub fn established(id: SocketId) -> io::Result<()> {
+    block(|| try_established(id))
+}
+
+/// Like `established()`, but parks instead of sleeping. See `park()`.
+pub fn park_established(id: SocketId) -> io::Result<()> {
+    park(|| try_established(id))
+}
+
+/// Opens a connection, owned by the process `owner`, to `port` at `dst`,
+/// and waits for it to be established.
+pub fn connect(owner: Id, dst: Ipv4Addr, port: u16) -> io::Result<SocketId> {
+    let id = start_connect(owner, dst, port)?;
+    match established(id) {
+        Ok(()) => Ok(id),
+        Err(e) => {
+            let _ = close(id, owner);
+            Err(e)
+        }
+    }
+}
+
+/// Buffers as much of `data` as fits to be sent on connection `id`, and
+/// returns how much that was.
+fn try_send(id: SocketId, data: &[u8]) -> io::Result<usize> {
+    let now = clock::monotonic_us();
+    with_tcp(|tcp, out| {
+        let connection = tcp.connection(id)?;
+        match connection.state {
+            State::SynSent | State::SynReceived => return Err(would_block()),
+            State::Established | State::CloseWait if !connection.fin_queued => {}
+            State::Closed => return Err(connection.closed_error()),
+            _ => return Err(io::Error::new(io::ErrorKind::BrokenPipe, "connection closing")),
+        }
+        if data.is_empty() {
+            return Ok(0);
+        }
+
+        let len = data.len().min(BUFFER_LEN - connection.send_buf.len());
+        if len == 0 {
+            return Err(would_block());
+        }
+        connection.send_buf.extend(data[..len].iter().cloned());
+        connection.output(now, out);
+        Ok(len)
+    })
+}
+
+/// Waits for room in the send buffer of connection `id`, fills it with as
+/// much of `data` as fits, and returns how much that was.
+pub fn send(id: SocketId, data: &[u8]) -> io::Result<usize> {
+    block(|| try_send(id, data))
+}
+
+/// Like `send()`, but parks instead of sleeping. See `park()`.
+pub fn park_send(id: SocketId, data: &[u8]) -> io::Result<usize> {
+    park(|| try_send(id, data))
+}
+
+/// Moves as much received data from connection `id` as fits to `buf`, and
+/// returns how much that was: 0 if the peer has closed its side.
+fn try_recv(id: SocketId, buf: &mut [u8]) -> io::Result<usize> {
+    let now = clock::monotonic_us();
+    with_tcp(|tcp, out| {
+        let connection = tcp.connection(id)?;
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if connection.recv_buf.is_empty() {
+            return match connection.state {
+                _ if connection.fin_received() => Ok(0),
+                State::Closed => Err(connection.closed_error()),
+                _ => Err(would_block()),
+            };
+        }
+
+        let closed_window = (connection.window() as usize) < connection.snd_mss;
+        let len = buf.len().min(connection.recv_buf.len());
+        for (byte, received) in buf.iter_mut().zip(connection.recv_buf.drain(..len)) {
+            *byte = received;
+        }
+        // Tell the peer when a window too small to send into opens up.
+        if closed_window && connection.window() as usize >= connection.snd_mss {
+            connection.ack_pending = true;
+            connection.output(now, out);
+        }
+        Ok(len)
+    })
+}
+
+/// Waits for data on connection `id`, moves as much as fits to `buf`, and
+/// returns how much that was: 0 if the peer has closed its side.
+pub fn recv(id: SocketId, buf: &mut [u8]) -> io::Result<usize> {
+    block(|| try_recv(id, buf))
+}
+
+/// Like `recv()`, but parks instead of sleeping. See `park()`.
+pub fn park_recv(id: SocketId, buf: &mut [u8]) -> io::Result<usize> {
+    park(|| try_recv(id, buf))
+}
+
+/// Closes socket `id`, which the caller has checked it may. A listener's
+/// connections not yet accepted are reset.
+fn close_socket(tcp: &mut Tcp, id: SocketId, now: u64, out: &mut Vec<Segment>) {
+    let listening = match tcp.sockets.get(&id) {
+        Some(&Socket::Listener(_)) => true,
+        _ => false,
+    };
+    if listening {
+        tcp.sockets.remove(&id);
+        let orphans: Vec<SocketId> = tcp.sockets.iter()
+            .filter(|&(_, socket)| match *socket {
+                Socket::Connection(ref connection) => connection.listener == Some(id),
+                _ => false,
+            })
+            .map(|(&id, _)| id)
+            .collect();
+        for orphan in orphans {
+            if let Some(Socket::Connection(connection)) = tcp.sockets.remove(&orphan) {
+                let segment = connection.segment(connection.snd_nxt, RST | ACK, &[]);
+                out.push(segment);
+            }
+        }
+        return;
+    }
+
+    let finished = match tcp.connection(id) {
+        Ok(connection) => {
+            connection.user_closed = true;
+            match connection.state {
+                State::SynSent | State::SynReceived => connection.state = State::Closed,
+                State::Established | State::CloseWait => {
+                    connection.fin_queued = true;
+                    connection.output(now, out);
+                }
+                _ => {}
+            }
+            connection.finished()
+        }
+        Err(_) => false,
+    };
+    if finished {
+        tcp.sockets.remove(&id);
+    }
+}
+
+/// Closes socket `id` on behalf of the process `caller`. A connection sends
+/// what is buffered and then its FIN; blocked operations on the socket fail
+/// with `NotFound`.
+pub fn close(id: SocketId, caller: Id) -> io::Result<()> {
+    let now = clock::monotonic_us();
+    with_tcp(|tcp, out| {
+        match tcp.sockets.get(&id).map(|socket| socket.owner()) {
+            None => return Err(no_socket()),
+            Some(owner) if owner != caller => {
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied,
+                                          "socket belongs to another process"));
+            }
+            Some(_) => {}
+        }
+
+        close_socket(tcp, id, now, out);
+        Ok(())
+    })?;
+
+    EVENTS.wake_all();
+    Ok(())
+}
+
+/// Closes every socket owned by the process `owner`. Called when it exits.
+pub fn release(owner: Id) {
+    let now = clock::monotonic_us();
+    let closed = with_tcp(|tcp, out| {
+        let ids: Vec<SocketId> = tcp.sockets.iter()
+            .filter(|&(_, socket)| socket.owner() == owner && match *socket {
+                Socket::Connection(ref connection) => !connection.user_closed,
+                _ => true,
+            })
+            .map(|(&id, _)| id)
+            .collect();
+        for &id in ids.iter() {
+            close_socket(tcp, id, now, out);
+        }
+        !ids.is_empty()
+    });
+
+    if closed {
+        EVENTS.wake_all();
+    }
+}
+
+/// Returns `true` if this core is using the socket table or the wait queue,
+/// so that closing sockets would deadlock. For the out-of-memory killer.
+pub fn is_locked_here() -> bool {
+    TCP.is_held_by_current_core() || EVENTS.is_locked_here()
+}
+
+/// Handles `bytes`, a TCP segment received in a packet with `header`.
+pub fn receive(header: &Header, bytes: &[u8]) {
+    if header.dst == Ipv4Addr::BROADCAST {
+        return;
+    }
+    let seg = match Incoming::parse(header, bytes) {
+        Some(seg) => seg,
+        None => return,
+    };
+
+    let now = clock::monotonic_us();
+    let local = (header.dst, seg.dst_port);
+    let remote = (header.src, seg.src_port);
+    with_tcp(|tcp, out| {
+        let found = tcp.sockets.iter()
+            .filter_map(|(&id, socket)| match *socket {
+                Socket::Connection(ref c) if c.local == local && c.remote == remote => Some(id),
+                _ => None,
+            })
+            .next();
+        if let Some(id) = found {
+            let (established, listener, finished) = match tcp.connection(id) {
+                Ok(connection) => {
+                    let established = connection.arrive(&seg, now, out);
+                    (established, connection.listener, connection.finished())
+                }
+                Err(_) => return,
+            };
+
+            if finished {
+                tcp.sockets.remove(&id);
+            } else if let (true, Some(listener_id)) = (established, listener) {
+                if let Some(&mut Socket::Listener(ref mut listener)) =
+                    tcp.sockets.get_mut(&listener_id)
+                {
+                    listener.ready.push_back(id);
+                }
+            }
+            return;
+        }
+
+        let listener = tcp.sockets.iter()
+            .filter_map(|(&id, socket)| match *socket {
+                Socket::Listener(ref listener) if listener.port == seg.dst_port => {
+                    Some((id, listener.owner, listener.backlog))
+                }
+                _ => None,
+            })
+            .next();
+        let (id, owner, backlog) = match listener {
+            Some(listener) if seg.flags & (SYN | ACK | RST) == SYN => listener,
+            Some(_) if seg.flags & RST != 0 => return,
+            _ => return reset(local, remote, &seg, out),
+        };
+
+        // New connections beyond the backlog are ignored, and their SYNs
+        // sent again later.
+        let queued = tcp.sockets.values()
+            .filter(|socket| match **socket {
+                Socket::Connection(ref connection) => connection.listener == Some(id),
+                _ => false,
+            })
+            .count();
+        if queued >= backlog || tcp.sockets.len() >= MAX_SOCKETS {
+            return;
+        }
+
+        let mut connection = Connection::new(owner, local, remote, mss_of(net::route(remote.0)),
+                                             initial_sequence());
+        connection.state = State::SynReceived;
+        connection.listener = Some(id);
+        connection.rcv_nxt = seg.seq.wrapping_add(1);
+        connection.snd_wnd = seg.window as usize;
+        connection.snd_mss = peer_mss(&seg, connection.our_mss);
+        connection.send_syn(true, now, out);
+        tcp.insert(Socket::Connection(connection));
+    });
+
+    EVENTS.wake_all();
+}
+
+/// Queues a reset answering `seg`, which has no connection, unless it is a
+/// reset itself.
+fn reset(local: Endpoint, remote: Endpoint, seg: &Incoming, out: &mut Vec<Segment>) {
+    if seg.flags & RST != 0 {
+        return;
+    }
+
+    if seg.flags & ACK != 0 {
+        out.push(segment(local, remote, seg.ack, 0, RST, 0, None, &[]));
+    } else {
+        let ack = seg.seq.wrapping_add(seg.len());
+        out.push(segment(local, remote, 0, ack, RST | ACK, 0, None, &[]));
+    }
+}
+
+/// Returns a segment from `src` to `dst`, with an MSS option if `mss` is
+/// given.
+pub(super) fn segment(src: Endpoint, dst: Endpoint, seq: u32, ack: u32, flags: u8, window: u16,
+                      mss: Option<u16>, data: &[u8]) -> Segment {
+    let header_len = if mss.is_some() { HEADER_LEN + 4 } else { HEADER_LEN };
+    let mut bytes = vec![0; header_len];
+    put_u16(&mut bytes, 0, src.1);
+    put_u16(&mut bytes, 2, dst.1);
+    put_u32(&mut bytes, 4, seq);
+    put_u32(&mut bytes, 8, ack);
+    bytes[12] = ((header_len / 4) as u8) << 4;
+    bytes[13] = flags;
+    put_u16(&mut bytes, 14, window);
+    if let Some(mss) = mss {
+        bytes[20] = OPT_MSS;
+        bytes[21] = 4;
+        put_u16(&mut bytes, 22, mss);
+    }
+    bytes.extend_from_slice(data);
+
+    let pseudo = ipv4::pseudo_header(src.0, dst.0, ipv4::PROTO_TCP, bytes.len());
+    let sum = ipv4::checksum(&[&pseudo, &bytes]);
+    put_u16(&mut bytes, 16, sum);
+    Segment { src: src.0, dst: dst.0, bytes }
+}
+
+kernel_init!(TCP_INIT, Stage::Scheduler, "TCP timers", Init::Plain(start));
+
+/// Starts the timer thread.
+fn start() {
+    if kthread::spawn("tcp", run_timers).is_none() {
+        log_warn!("no memory for the TCP timer thread; segments won't be sent again");
+    }
+}
+
+/// The timer thread: sends segments again, gives up on connections, and
+/// ends TIME-WAIT.
+fn run_timers() {
+    loop {
+        tick::sleep_ms(TIMER_MS);
+        let now = clock::monotonic_us();
+        let changed = with_tcp(|tcp, out| {
+            let mut changed = false;
+            let mut finished = Vec::new();
+            for (&id, socket) in tcp.sockets.iter_mut() {
+                if let Socket::Connection(ref mut connection) = *socket {
+                    changed |= connection.timer(now, out);
+                    if connection.finished() {
+                        finished.push(id);
+                    }
+                }
+            }
+            for id in finished {
+                tcp.sockets.remove(&id);
+            }
+            changed
+        });
+
+        if changed {
+            EVENTS.wake_all();
+        }
+    }
+}
+
+/// A connection, for kernel processes, that is closed when dropped.
+pub struct TcpStream {
+    id: SocketId,
+    owner: Id,
+}
+
+impl TcpStream {
+    /// Opens a connection to `port` at `dst`, owned by the running process.
+    pub fn connect(dst: Ipv4Addr, port: u16) -> io::Result<TcpStream> {
+        let owner = scheduler::current_id();
+        connect(owner, dst, port).map(|id| TcpStream { id, owner })
+    }
+
+    /// Returns the connection's socket ID.
+    pub fn id(&self) -> SocketId {
+        self.id
+    }
+
+    /// Like `read()`, but fails with `WouldBlock` instead of waiting for
+    /// data.
+    pub fn try_read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        try_recv(self.id, buf)
+    }
+}
+
+impl io::Read for TcpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        recv(self.id, buf)
+    }
+}
+
+impl io::Write for TcpStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        send(self.id, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for TcpStream {
+    fn drop(&mut self) {
+        let _ = close(self.id, self.owner);
+    }
+}
+
+/// A listening socket, for kernel processes, that is closed when dropped.
+pub struct TcpListener {
+    id: SocketId,
+    owner: Id,
+}
+
+impl TcpListener {
+    /// Listens on `port`, queueing up to `backlog` connections, for the
+    /// running process.
+    pub fn bind(port: u16, backlog: usize) -> io::Result<TcpListener> {
+        let owner = scheduler::current_id();
+        listen(owner, port, backlog).map(|id| TcpListener { id, owner })
+    }
+
+    /// Waits for a connection and returns it and the address and port it is
+    /// from.
+    pub fn accept(&self) -> io::Result<(TcpStream, Ipv4Addr, u16)> {
+        let (id, addr, port) = accept(self.id)?;
+        Ok((TcpStream { id, owner: self.owner }, addr, port))
+    }
+}
+
+impl Drop for TcpListener {
+    fn drop(&mut self) {
+        let _ = close(self.id, self.owner);
+    }
+}
+
+/// Returns whether sequence number `a` is before `b`, modulo 2^32.
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn seq_le(a: u32, b: u32) -> bool {
+    a == b || seq_lt(a, b)
+}
@@ -0,0 +1,265 @@
+//! A telnet server (RFC 854) giving each connection a kernel shell, so that
+//! a board without a serial cable can be administered over the network.
+//!
+//! The server starts at boot if the kernel command line has `telnetd=PORT`.
+//! Nothing is authenticated: anyone who can reach the port gets a shell, so
+//! it is only for trusted networks.
+//!
+//! Each connection gets a `console::Session` and two kernel threads: a
+//! shell on the session, and a pump that moves bytes between the session
+//! and the connection, speaking the telnet protocol. The server offers to
+//! echo and to suppress go-aheads, which puts clients in character mode so
+//! that the shell's line editing works, and refuses every other option.
+
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use boot::BootInfo;
+use console::{log_info, log_warn, Session, SessionInput};
+use init::{kernel_init, Init, Stage};
+use kthread;
+use net::tcp::{TcpListener, TcpStream};
+use scheduler;
+use shell;
+use tick;
+
+/// The commands used: "interpret as command", which starts each, and the
+/// ones negotiating options and bracketing subnegotiations.
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+
+/// The options the server agrees to.
+const OPT_ECHO: u8 = 1;
+const OPT_SGA: u8 = 3;
+
+/// The most sessions open at once; connections beyond it are turned away.
+const MAX_SESSIONS: usize = 4;
+
+/// How many connections wait to be accepted.
+const BACKLOG: usize = 4;
+
+/// How often an idle pump looks for input and output, in milliseconds.
+const POLL_MS: u64 = 10;
+
+/// The most bytes the pump moves at once.
+const CHUNK: usize = 512;
+
+/// The prefix for each line of a session's shell.
+const PROMPT: &str = "->";
+
+/// The number of sessions open.
+static SESSIONS: AtomicUsize = AtomicUsize::new(0);
+
+kernel_init!(TELNETD_INIT, Stage::Scheduler, "telnet server", Init::Boot(start));
+
+/// Starts the server if the command line asks for it.
+fn start(info: &BootInfo) {
+    let port = match info.param("telnetd") {
+        Some(port) => port,
+        None => return,
+    };
+    let port = match port.parse::<u16>() {
+        Ok(port) if port != 0 => port,
+        _ => {
+            log_warn!("telnetd: invalid port {:?}", port);
+            return;
+        }
+    };
+
+    if kthread::spawn("telnetd", move || serve(port)).is_none() {
+        log_warn!("telnetd: no memory for the server thread");
+    }
+}
+
+/// The server thread: accepts connections on `port` and starts a session
+/// for each.
+fn serve(port: u16) {
+    let listener = match TcpListener::bind(port, BACKLOG) {
+        Ok(listener) => listener,
+        Err(e) => {
+            log_warn!("telnetd: port {}: {}", port, e);
+            return;
+        }
+    };
+    log_info!("telnetd: listening on port {}", port);
+
+    loop {
+        let (mut stream, addr, from_port) = match listener.accept() {
+            Ok(connection) => connection,
+            Err(e) => {
+                log_warn!("telnetd: {}", e);
+                return;
+            }
+        };
+        if SESSIONS.load(Ordering::Relaxed) >= MAX_SESSIONS {
+            let _ = stream.write_all(b"too many sessions\r\n");
+            continue;
+        }
+
+        let name = format!("{}:{}", addr, from_port);
+        log_info!("telnetd: session from {}", name);
+        open_session(stream, Session::new(&name));
+    }
+}
+
+/// Starts the shell and the pump for a new connection, `stream`.
+fn open_session(stream: TcpStream, session: Arc<Session>) {
+    SESSIONS.fetch_add(1, Ordering::Relaxed);
+    let shell_session = session.clone();
+    let shell = kthread::spawn("telnet shell", move || {
+        scheduler::set_session(Some(shell_session.clone()));
+        shell::serve(PROMPT, SessionInput(shell_session));
+    });
+
+    let pump_session = session.clone();
+    if shell.is_none() || kthread::spawn("telnet", move || pump(stream, pump_session)).is_none() {
+        log_warn!("telnetd: {}: no memory for the session", session.name());
+        session.hang_up();
+        SESSIONS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The pump thread: moves input from `stream` to `session` and output the
+/// other way until either ends, then ends the other.
+fn pump(mut stream: TcpStream, session: Arc<Session>) {
+    match run(&mut stream, &session) {
+        Ok(()) => log_info!("telnetd: session from {} closed", session.name()),
+        Err(e) => log_info!("telnetd: session from {}: {}", session.name(), e),
+    }
+    session.hang_up();
+    SESSIONS.fetch_sub(1, Ordering::Relaxed);
+}
+
+fn run(stream: &mut TcpStream, session: &Session) -> io::Result<()> {
+    let mut decoder = Decoder::new();
+    let mut buf = [0; CHUNK];
+    let mut input = Vec::new();
+    let mut replies = Vec::new();
+    stream.write_all(&[IAC, WILL, OPT_ECHO, IAC, WILL, OPT_SGA])?;
+    loop {
+        let output = session.take_output(CHUNK);
+        if !output.is_empty() {
+            stream.write_all(&escape(&output))?;
+        }
+
+        let received = match stream.try_read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => 0,
+            Err(e) => return Err(e),
+        };
+        if received > 0 {
+            input.clear();
+            replies.clear();
+            decoder.decode(&buf[..received], &mut input, &mut replies);
+            session.push_input(&input);
+            stream.write_all(&replies)?;
+        }
+
+        if output.is_empty() && received == 0 {
+            // Once the shell has gone and its output has been sent, the
+            // connection is closed.
+            if session.is_hung_up() {
+                return Ok(());
+            }
+            tick::sleep_ms(POLL_MS);
+        }
+    }
+}
+
+/// Returns `bytes` with every `IAC` doubled, as data is sent.
+fn escape(bytes: &[u8]) -> Vec<u8> {
+    let mut escaped = Vec::with_capacity(bytes.len());
+    for &byte in bytes {
+        if byte == IAC {
+            escaped.push(IAC);
+        }
+        escaped.push(byte);
+    }
+    escaped
+}
+
+/// Where `Decoder` is in the stream from the client.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    Data,
+    /// After a carriage return, which may be followed by a NUL or line
+    /// feed that is dropped.
+    Cr,
+    /// After an `IAC`.
+    Command,
+    /// After an `IAC` and one of `WILL`, `WONT`, `DO`, and `DONT`.
+    Negotiating(u8),
+    /// In a subnegotiation, which is ignored, and after an `IAC` in one.
+    Sub,
+    SubCommand,
+}
+
+/// Separates data from commands in the stream from the client.
+struct Decoder {
+    state: State,
+}
+
+impl Decoder {
+    fn new() -> Decoder {
+        Decoder { state: State::Data }
+    }
+
+    /// Decodes `bytes`, appending the data in them to `input` and answers to
+    /// the client's option requests to `replies`. Lines end with a lone
+    /// carriage return, as the shell expects.
+    fn decode(&mut self, bytes: &[u8], input: &mut Vec<u8>, replies: &mut Vec<u8>) {
+        for &byte in bytes {
+            self.state = match (self.state, byte) {
+                (State::Command, IAC) => {
+                    input.push(IAC);
+                    State::Data
+                }
+                (State::Command, SB) => State::Sub,
+                (State::Command, verb @ WILL...DONT) => State::Negotiating(verb),
+                // Everything else, such as NOP and "are you there", is
+                // ignored.
+                (State::Command, _) => State::Data,
+                (State::Negotiating(verb), option) => {
+                    negotiate(verb, option, replies);
+                    State::Data
+                }
+                (State::Sub, IAC) => State::SubCommand,
+                (State::Sub, _) => State::Sub,
+                (State::SubCommand, SE) => State::Data,
+                (State::SubCommand, _) => State::Sub,
+                (_, IAC) => State::Command,
+                (State::Cr, 0) | (State::Cr, b'\n') => State::Data,
+                (_, b'\r') => {
+                    input.push(b'\r');
+                    State::Cr
+                }
+                (_, byte) => {
+                    input.push(byte);
+                    State::Data
+                }
+            };
+        }
+    }
+}
+
+/// Answers the client's `verb` for `option`. Requests that the server echo
+/// and suppress go-aheads were offered already and need no answer; offers
+/// from the client to suppress go-aheads are taken, and everything else is
+/// refused.
+fn negotiate(verb: u8, option: u8, replies: &mut Vec<u8>) {
+    let reply = match verb {
+        DO if option == OPT_ECHO || option == OPT_SGA => return,
+        DO => WONT,
+        WILL if option == OPT_SGA => DO,
+        WILL => DONT,
+        _ => return,
+    };
+    replies.extend_from_slice(&[IAC, reply, option]);
+}
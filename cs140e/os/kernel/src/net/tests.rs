@@ -0,0 +1,508 @@
+mod dhcp {
+    use bytes::be;
+    use net::dhcp::{lease_times, message, Reply};
+    use net::{Ipv4Addr, MacAddr};
+
+    const FIXED_LEN: usize = 236;
+    const MAGIC: [u8; 4] = [99, 130, 83, 99];
+
+    const MAC: MacAddr = MacAddr([0xb8, 0x27, 0xeb, 1, 2, 3]);
+    const XID: u32 = 0x1234_5678;
+
+    /// Returns a reply to `MAC` for transaction `XID`, offering 10.0.0.5,
+    /// with `options` after the magic cookie.
+    fn reply_bytes(options: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0; FIXED_LEN];
+        bytes[0] = 2;
+        bytes[1] = 1;
+        bytes[2] = 6;
+        be::put_u32(&mut bytes, 4, XID);
+        bytes[16..20].copy_from_slice(&[10, 0, 0, 5]);
+        bytes[28..34].copy_from_slice(&MAC.0);
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(options);
+        bytes
+    }
+
+    fn parse(bytes: &[u8]) -> Reply {
+        Reply::parse(bytes).unwrap_or_else(|| panic!("rejected a reply"))
+    }
+
+    #[test]
+    fn parses_an_ack() {
+        let ack = reply_bytes(&[
+            0, 0,                       // Pads.
+            53, 1, 5,                   // An ACK.
+            54, 4, 10, 0, 0, 1,         // From 10.0.0.1.
+            1, 4, 255, 255, 255, 0,
+            3, 8, 10, 0, 0, 254, 10, 0, 0, 253,
+            6, 4, 8, 8, 8, 8,
+            51, 4, 0, 0, 0x0e, 0x10,    // An hour.
+            58, 4, 0, 0, 0x07, 0x08,
+            59, 4, 0, 0, 0x0c, 0x4e,
+            43, 2, 1, 2,                // Vendor information, ignored.
+            255,
+            53, 1, 6,                   // Past the end.
+        ]);
+        let reply = parse(&ack);
+
+        assert_eq!((reply.kind, reply.xid, reply.chaddr), (5, XID, MAC));
+        assert_eq!(reply.yiaddr, Ipv4Addr([10, 0, 0, 5]));
+        assert_eq!(reply.server, Some(Ipv4Addr([10, 0, 0, 1])));
+        assert_eq!(reply.netmask, Some(Ipv4Addr([255, 255, 255, 0])));
+        assert_eq!(reply.router, Some(Ipv4Addr([10, 0, 0, 254])));
+        assert_eq!(reply.dns, Some(Ipv4Addr([8, 8, 8, 8])));
+        assert_eq!((reply.lease, reply.renewal, reply.rebinding),
+                   (Some(3600), Some(1800), Some(3150)));
+    }
+
+    #[test]
+    fn rejects_what_isnt_a_reply() {
+        assert!(Reply::parse(&reply_bytes(&[])[..FIXED_LEN + 3]).is_none());
+        assert!(Reply::parse(&[]).is_none());
+
+        // A request, a token ring reply, the wrong address length, and the
+        // wrong cookie.
+        for &(at, value) in [(0, 1), (1, 6), (2, 8), (FIXED_LEN, 0)].iter() {
+            let mut bytes = reply_bytes(&[53, 1, 5, 255]);
+            bytes[at] = value;
+            assert!(Reply::parse(&bytes).is_none(), "byte {} = {}", at, value);
+        }
+    }
+
+    #[test]
+    fn truncated_options() {
+        // An option whose data runs past the end: those before it count.
+        let reply = parse(&reply_bytes(&[53, 1, 2, 54, 4, 10, 0]));
+        assert_eq!((reply.kind, reply.server), (2, None));
+
+        // An option code without a length.
+        let reply = parse(&reply_bytes(&[53, 1, 2, 54]));
+        assert_eq!((reply.kind, reply.server), (2, None));
+
+        // No options at all, not even the end.
+        let reply = parse(&reply_bytes(&[]));
+        assert_eq!((reply.kind, reply.lease), (0, None));
+    }
+
+    #[test]
+    fn short_options() {
+        // Addresses and times shorter than four bytes, and an empty message
+        // type, are ignored; the options after them still count.
+        let reply = parse(&reply_bytes(&[
+            53, 0,
+            54, 2, 10, 0,
+            3, 0,
+            51, 3, 0, 0, 1,
+            1, 4, 255, 0, 0, 0,
+            255,
+        ]));
+        assert_eq!(reply.kind, 0);
+        assert_eq!((reply.server, reply.router, reply.lease), (None, None, None));
+        assert_eq!(reply.netmask, Some(Ipv4Addr([255, 0, 0, 0])));
+    }
+
+    #[test]
+    fn builds_messages() {
+        let requested = [10, 0, 0, 5];
+        let server = [10, 0, 0, 1];
+        let options: [(u8, &[u8]); 2] = [(50, &requested), (54, &server)];
+        let mut msg = message(3, XID, MAC, Ipv4Addr::UNSPECIFIED, &options);
+
+        assert_eq!(&msg[..3], &[1, 1, 6]);
+        assert_eq!(be::u32_at(&msg, 4), XID);
+        // Broadcast the reply: we have no address to take it at.
+        assert_eq!(be::u16_at(&msg, 10), 0x8000);
+        assert_eq!(&msg[28..34], &MAC.0);
+        assert_eq!(&msg[FIXED_LEN..FIXED_LEN + 4], &MAGIC);
+        assert_eq!(&msg[FIXED_LEN + 4..],
+                   &[53, 1, 3, 55, 6, 1, 3, 6, 51, 58, 59,
+                     50, 4, 10, 0, 0, 5, 54, 4, 10, 0, 0, 1, 255][..]);
+
+        // Read as a reply, it parses back to what was sent.
+        msg[0] = 2;
+        let reply = parse(&msg);
+        assert_eq!((reply.kind, reply.xid, reply.chaddr), (3, XID, MAC));
+        assert_eq!(reply.server, Some(Ipv4Addr(server)));
+
+        // Renewing from an address, the reply can come straight to it.
+        let msg = message(3, XID, MAC, Ipv4Addr([10, 0, 0, 5]), &[]);
+        assert_eq!(be::u16_at(&msg, 10), 0);
+        assert_eq!(&msg[12..16], &[10, 0, 0, 5]);
+    }
+
+    #[test]
+    fn lease_times_default() {
+        let now = 7;
+        assert_eq!(lease_times(now, 800, None, None),
+                   (now + 400_000_000, now + 700_000_000, now + 800_000_000));
+        assert_eq!(lease_times(now, 800, Some(100), Some(200)),
+                   (now + 100_000_000, now + 200_000_000, now + 800_000_000));
+        assert_eq!(lease_times(now, 0xFFFF_FFFF, Some(100), Some(200)),
+                   (u64::max_value(), u64::max_value(), u64::max_value()));
+    }
+
+    #[test]
+    fn lease_times_out_of_order() {
+        // Times past the lease, or renewing after rebinding, are pulled in
+        // so that the lease still runs out while rebinding.
+        assert_eq!(lease_times(0, 800, Some(900), Some(1000)),
+                   (800_000_000, 800_000_000, 800_000_000));
+        assert_eq!(lease_times(0, 800, Some(600), Some(300)),
+                   (300_000_000, 300_000_000, 800_000_000));
+        assert_eq!(lease_times(0, 800, Some(600), None),
+                   (600_000_000, 700_000_000, 800_000_000));
+        assert_eq!(lease_times(0, 800, None, Some(100)),
+                   (100_000_000, 100_000_000, 800_000_000));
+    }
+}
+
+mod tcp {
+    use std::io;
+
+    use bytes::be;
+    use net::ipv4::{self, Header};
+    use net::tcp::{segment, Connection, Endpoint, Incoming, Segment, State};
+    use net::Ipv4Addr;
+
+    const FIN: u8 = 0x01;
+    const SYN: u8 = 0x02;
+    const RST: u8 = 0x04;
+    const ACK: u8 = 0x10;
+
+    const LOCAL: Endpoint = (Ipv4Addr([10, 0, 0, 2]), 49152);
+    const REMOTE: Endpoint = (Ipv4Addr([10, 0, 0, 1]), 80);
+
+    /// Our initial sequence number and the peer's.
+    const ISS: u32 = 1000;
+    const PEER_ISS: u32 = 5000;
+
+    const OUR_MSS: usize = 1460;
+    const BUFFER_LEN: usize = 16 * 1024;
+
+    fn header(src: Endpoint, dst: Endpoint) -> Header {
+        Header { src: src.0, dst: dst.0, protocol: ipv4::PROTO_TCP, ttl: 64 }
+    }
+
+    /// Returns a segment from the peer to us, with a window of 64 KB.
+    fn from_peer(seq: u32, ack: u32, flags: u8, data: &[u8]) -> Vec<u8> {
+        segment(REMOTE, LOCAL, seq, ack, flags, 0xFFFF, None, data).bytes
+    }
+
+    /// Sets the checksum of `bytes`, a segment from the peer to us.
+    fn fix_checksum(bytes: &mut [u8]) {
+        be::put_u16(bytes, 16, 0);
+        let pseudo = ipv4::pseudo_header(REMOTE.0, LOCAL.0, ipv4::PROTO_TCP, bytes.len());
+        let sum = ipv4::checksum(&[&pseudo[..], &bytes[..]]);
+        be::put_u16(bytes, 16, sum);
+    }
+
+    fn parse(bytes: &[u8]) -> Option<Incoming> {
+        Incoming::parse(&header(REMOTE, LOCAL), bytes)
+    }
+
+    /// What was sent: the sequence and acknowledgment numbers, flags,
+    /// window, and data of each segment, which must have a good checksum.
+    fn sent(out: &[Segment]) -> Vec<(u32, u32, u8, u16, Vec<u8>)> {
+        out.iter().map(|segment| {
+            let seg = Incoming::parse(&header(LOCAL, REMOTE), &segment.bytes)
+                .expect("a valid segment");
+            assert_eq!((seg.src_port, seg.dst_port), (LOCAL.1, REMOTE.1));
+            (seg.seq, seg.ack, seg.flags & !0x08, seg.window, seg.data.to_vec())
+        }).collect()
+    }
+
+    /// Hands `bytes` to `connection` at time `now`. Returns whether it
+    /// became established, and what it sent.
+    fn arrive(connection: &mut Connection, bytes: &[u8], now: u64)
+        -> (bool, Vec<(u32, u32, u8, u16, Vec<u8>)>)
+    {
+        let seg = parse(bytes).expect("a valid segment");
+        let mut out = Vec::new();
+        let established = connection.arrive(&seg, now, &mut out);
+        (established, sent(&out))
+    }
+
+    /// Returns a connection that has sent its SYN, and what it sent.
+    fn connecting() -> (Connection, Vec<(u32, u32, u8, u16, Vec<u8>)>) {
+        let mut connection = Connection::new(1, LOCAL, REMOTE, OUR_MSS, ISS);
+        connection.state = State::SynSent;
+        let mut out = Vec::new();
+        connection.send_syn(false, 0, &mut out);
+        (connection, sent(&out))
+    }
+
+    /// Returns the SYN-ACK answering our SYN, with `mss` as the MSS option.
+    fn syn_ack(mss: Option<u16>) -> Vec<u8> {
+        segment(REMOTE, LOCAL, PEER_ISS, ISS + 1, SYN | ACK, 0xFFFF, mss, &[]).bytes
+    }
+
+    fn established() -> Connection {
+        let (mut connection, _) = connecting();
+        assert!(arrive(&mut connection, &syn_ack(Some(1400)), 0).0);
+        connection
+    }
+
+    fn received(connection: &Connection) -> Vec<u8> {
+        connection.recv_buf.iter().cloned().collect()
+    }
+
+    #[test]
+    fn parses_segments() {
+        let bytes = segment(REMOTE, LOCAL, 7, 9, SYN | ACK, 1234, Some(1400), b"hi").bytes;
+        let seg = parse(&bytes).expect("a valid segment");
+        assert_eq!((seg.src_port, seg.dst_port), (80, 49152));
+        assert_eq!((seg.seq, seg.ack, seg.flags, seg.window), (7, 9, SYN | ACK, 1234));
+        assert_eq!((seg.mss, seg.data), (Some(1400), &b"hi"[..]));
+    }
+
+    #[test]
+    fn rejects_malformed_segments() {
+        let bytes = from_peer(1, 2, ACK, b"data");
+        assert!(parse(&bytes[..19]).is_none());
+        assert!(parse(&[]).is_none());
+
+        // A bad checksum, and a good one for the wrong addresses.
+        let mut corrupt = bytes.clone();
+        corrupt[4] ^= 1;
+        assert!(parse(&corrupt).is_none());
+        assert!(Incoming::parse(&header(REMOTE, (Ipv4Addr([10, 0, 0, 3]), 49152)), &bytes)
+            .is_none());
+
+        // Data offsets inside the header and past the end of the segment.
+        for &offset in [0u8, 4, 7, 15].iter() {
+            let mut bad = bytes.clone();
+            bad[12] = offset << 4;
+            fix_checksum(&mut bad);
+            assert!(parse(&bad).is_none(), "data offset {}", offset);
+        }
+    }
+
+    #[test]
+    fn malformed_options() {
+        // Returns the MSS of a segment with 12 bytes of `options`.
+        let mss = |options: &[u8]| {
+            let mut bytes = from_peer(1, 2, SYN, &[0; 12]);
+            bytes[12] = 8 << 4;
+            bytes[20..20 + options.len()].copy_from_slice(options);
+            fix_checksum(&mut bytes);
+            let seg = parse(&bytes).expect("a valid segment");
+            assert!(seg.data.is_empty());
+            seg.mss
+        };
+
+        assert_eq!(mss(&[1, 1, 2, 4, 0x05, 0xb4]), Some(1460));
+        // An MSS of the wrong length is skipped, and the next one taken.
+        assert_eq!(mss(&[2, 3, 9, 2, 4, 0x05, 0xb4]), Some(1460));
+        // After the end of the list, after an option too short to skip,
+        // after one running past the header, and without its length.
+        assert_eq!(mss(&[0, 2, 4, 0x05, 0xb4]), None);
+        assert_eq!(mss(&[3, 0, 2, 4, 0x05, 0xb4]), None);
+        assert_eq!(mss(&[3, 1, 2, 4, 0x05, 0xb4]), None);
+        assert_eq!(mss(&[8, 20, 2, 4, 0x05, 0xb4]), None);
+        assert_eq!(mss(&[1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 1, 2]), None);
+        assert_eq!(mss(&[1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 4, 0x05]), None);
+    }
+
+    #[test]
+    fn connects() {
+        let (mut connection, syn) = connecting();
+        assert_eq!(syn, [(ISS, 0, SYN, BUFFER_LEN as u16, vec![])]);
+        let mut out = Vec::new();
+        Connection::new(1, LOCAL, REMOTE, OUR_MSS, ISS).send_syn(false, 0, &mut out);
+        let seg = Incoming::parse(&header(LOCAL, REMOTE), &out[0].bytes).unwrap();
+        assert_eq!(seg.mss, Some(OUR_MSS));
+
+        let (established, out) = arrive(&mut connection, &syn_ack(Some(1400)), 0);
+        assert!(established);
+        assert_eq!(connection.state, State::Established);
+        assert_eq!((connection.snd_una, connection.rcv_nxt), (ISS + 1, PEER_ISS + 1));
+        assert_eq!(connection.snd_mss, 1400);
+        assert_eq!(out, [(ISS + 1, PEER_ISS + 1, ACK, BUFFER_LEN as u16, vec![])]);
+    }
+
+    #[test]
+    fn hostile_segment_sizes() {
+        // A peer that takes no more than nothing still gets data.
+        let (mut connection, _) = connecting();
+        arrive(&mut connection, &syn_ack(Some(0)), 0);
+        assert_eq!(connection.snd_mss, 28);
+
+        connection.send_buf.extend([7u8; 100].iter().cloned());
+        let mut out = Vec::new();
+        connection.output(0, &mut out);
+        let lens: Vec<usize> = sent(&out).iter().map(|seg| seg.4.len()).collect();
+        assert_eq!(lens, [28, 28, 28, 16]);
+
+        // One that takes more than we do gets no more than we take.
+        let (mut connection, _) = connecting();
+        arrive(&mut connection, &syn_ack(Some(9000)), 0);
+        assert_eq!(connection.snd_mss, OUR_MSS);
+    }
+
+    #[test]
+    fn refused_and_bad_answers() {
+        // An acknowledgment of something we never sent is reset.
+        let (mut connection, _) = connecting();
+        let bad = segment(REMOTE, LOCAL, PEER_ISS, ISS + 7, SYN | ACK, 0xFFFF, None, &[]).bytes;
+        assert_eq!(arrive(&mut connection, &bad, 0), (false, vec![(ISS + 7, 0, RST, 0, vec![])]));
+        assert_eq!(connection.state, State::SynSent);
+
+        // A reset that doesn't acknowledge our SYN is ignored.
+        assert_eq!(arrive(&mut connection, &from_peer(PEER_ISS, 0, RST, &[]), 0), (false, vec![]));
+        assert_eq!(arrive(&mut connection, &from_peer(PEER_ISS, ISS + 7, RST | ACK, &[]), 0),
+                   (false, vec![]));
+        assert_eq!(connection.state, State::SynSent);
+
+        arrive(&mut connection, &from_peer(0, ISS + 1, RST | ACK, &[]), 0);
+        assert_eq!(connection.state, State::Closed);
+        assert_eq!(connection.error, Some(io::ErrorKind::ConnectionRefused));
+    }
+
+    #[test]
+    fn simultaneous_open() {
+        let (mut connection, _) = connecting();
+        let (established, out) = arrive(&mut connection, &from_peer(PEER_ISS, 0, SYN, &[]), 0);
+        assert!(!established);
+        assert_eq!(connection.state, State::SynReceived);
+        assert_eq!(out.len(), 1);
+        assert_eq!((out[0].0, out[0].1, out[0].2), (ISS, PEER_ISS + 1, SYN | ACK));
+    }
+
+    #[test]
+    fn receives_in_order() {
+        let mut connection = established();
+        let (_, out) = arrive(&mut connection, &from_peer(PEER_ISS + 1, ISS + 1, ACK, b"hello"), 0);
+        assert_eq!(received(&connection), b"hello");
+        assert_eq!(connection.rcv_nxt, PEER_ISS + 6);
+        assert_eq!(out.len(), 1);
+        assert_eq!((out[0].1, out[0].2), (PEER_ISS + 6, ACK));
+
+        // A retransmission overlapping what was taken is trimmed.
+        arrive(&mut connection, &from_peer(PEER_ISS + 1, ISS + 1, ACK, b"hello world"), 0);
+        assert_eq!(received(&connection), b"hello world");
+        assert_eq!(connection.rcv_nxt, PEER_ISS + 12);
+
+        // One of nothing new is only acknowledged again.
+        let (_, out) = arrive(&mut connection, &from_peer(PEER_ISS + 1, ISS + 1, ACK, b"hello"), 0);
+        assert_eq!(received(&connection), b"hello world");
+        assert_eq!(out.len(), 1);
+        assert_eq!(out[0].1, PEER_ISS + 12);
+    }
+
+    #[test]
+    fn drops_segments_ahead() {
+        let mut connection = established();
+        let (_, out) = arrive(&mut connection, &from_peer(PEER_ISS + 9, ISS + 1, ACK, b"later"), 0);
+        assert!(received(&connection).is_empty());
+        assert_eq!(connection.rcv_nxt, PEER_ISS + 1);
+        assert_eq!(out.len(), 1);
+        assert_eq!((out[0].1, out[0].2), (PEER_ISS + 1, ACK));
+
+        // Data without an acknowledgment isn't taken.
+        arrive(&mut connection, &from_peer(PEER_ISS + 1, 0, 0, b"bare"), 0);
+        assert!(received(&connection).is_empty());
+    }
+
+    #[test]
+    fn resets_only_in_sequence() {
+        let mut connection = established();
+        // Behind and ahead of the next byte: a blind reset, ignored and
+        // not answered.
+        for &seq in [PEER_ISS, PEER_ISS + 2, PEER_ISS + 100_000].iter() {
+            assert_eq!(arrive(&mut connection, &from_peer(seq, 0, RST, &[]), 0), (false, vec![]));
+            assert_eq!(connection.state, State::Established);
+        }
+
+        arrive(&mut connection, &from_peer(PEER_ISS + 1, 0, RST, &[]), 0);
+        assert_eq!(connection.state, State::Closed);
+        assert_eq!(connection.error, Some(io::ErrorKind::ConnectionReset));
+
+        // Anything more is answered with a reset.
+        let (_, out) = arrive(&mut connection, &from_peer(PEER_ISS + 1, ISS + 1, ACK, b"x"), 0);
+        assert_eq!(out, [(ISS + 1, 0, RST, 0, vec![])]);
+    }
+
+    #[test]
+    fn challenges_a_syn() {
+        let mut connection = established();
+        let syn = from_peer(PEER_ISS + 40, 0, SYN, &[]);
+        let (_, out) = arrive(&mut connection, &syn, 0);
+        assert_eq!(connection.state, State::Established);
+        assert_eq!(out.len(), 1);
+        assert_eq!((out[0].0, out[0].1, out[0].2), (ISS + 1, PEER_ISS + 1, ACK));
+
+        let syn = from_peer(PEER_ISS + 1, 0, SYN, &[]);
+        let (_, out) = arrive(&mut connection, &syn, 0);
+        assert_eq!((connection.state, connection.rcv_nxt), (State::Established, PEER_ISS + 1));
+        assert_eq!(out.len(), 1);
+    }
+
+    #[test]
+    fn acknowledgment_of_unsent_data() {
+        let mut connection = established();
+        let (_, out) = arrive(&mut connection, &from_peer(PEER_ISS + 1, ISS + 500, ACK, &[]), 0);
+        assert_eq!((connection.snd_una, connection.snd_nxt), (ISS + 1, ISS + 1));
+        assert_eq!(out.len(), 1);
+        assert_eq!((out[0].0, out[0].2), (ISS + 1, ACK));
+    }
+
+    #[test]
+    fn more_than_the_buffer_holds() {
+        let mut connection = established();
+        let data = vec![b'z'; BUFFER_LEN + 4000];
+        let fin = from_peer(PEER_ISS + 1, ISS + 1, ACK | FIN, &data);
+        let (_, out) = arrive(&mut connection, &fin, 0);
+
+        // What fits is taken, and the FIN after what didn't comes again.
+        assert_eq!(connection.recv_buf.len(), BUFFER_LEN);
+        assert_eq!(connection.rcv_nxt, PEER_ISS + 1 + BUFFER_LEN as u32);
+        assert_eq!(connection.state, State::Established);
+        assert_eq!(out.len(), 1);
+        assert_eq!((out[0].1, out[0].3), (PEER_ISS + 1 + BUFFER_LEN as u32, 0));
+    }
+
+    #[test]
+    fn peer_closes() {
+        let mut connection = established();
+        let fin = from_peer(PEER_ISS + 1, ISS + 1, ACK | FIN, &[]);
+        let (_, out) = arrive(&mut connection, &fin, 0);
+        assert_eq!(connection.state, State::CloseWait);
+        assert_eq!(out.len(), 1);
+        assert_eq!((out[0].1, out[0].2), (PEER_ISS + 2, ACK));
+
+        // We close too, and the peer acknowledges our FIN.
+        connection.fin_queued = true;
+        let mut out = Vec::new();
+        connection.output(0, &mut out);
+        assert_eq!(sent(&out)[0].2, FIN | ACK);
+        assert_eq!(connection.state, State::LastAck);
+        arrive(&mut connection, &from_peer(PEER_ISS + 2, ISS + 2, ACK, &[]), 0);
+        assert_eq!(connection.state, State::Closed);
+        assert_eq!(connection.error, None);
+    }
+
+    #[test]
+    fn gives_up_after_retries() {
+        let mut connection = established();
+        connection.send_buf.extend(b"unheard".iter().cloned());
+        let mut out = Vec::new();
+        connection.output(0, &mut out);
+        assert_eq!(sent(&out).len(), 1);
+
+        // Each retry doubles the timeout, to at most a minute.
+        for retry in 1..9 {
+            let mut out = Vec::new();
+            assert!(!connection.timer(retry * 61_000_000, &mut out));
+            let out = sent(&out);
+            assert_eq!(out.len(), 1);
+            assert_eq!((out[0].0, &out[0].4[..]), (ISS + 1, &b"unheard"[..]));
+        }
+
+        let mut out = Vec::new();
+        assert!(connection.timer(9 * 61_000_000, &mut out));
+        assert!(out.is_empty());
+        assert_eq!(connection.state, State::Closed);
+        assert_eq!(connection.error, Some(io::ErrorKind::TimedOut));
+    }
+}
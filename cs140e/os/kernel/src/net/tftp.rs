@@ -0,0 +1,215 @@
+//! A TFTP client (RFC 1350), for fetching files from a server.
+//!
+//! Only reading is supported, in octet mode. Larger blocks than the
+//! protocol's 512 bytes are asked for (RFC 2348), enough to fill an
+//! Ethernet frame, and used if the server agrees. Each packet is sent again
+//! if no answer comes within `TIMEOUT_MS`, up to `RETRIES` times.
+
+use std::io::{self, Write};
+
+use bytes::be::{push_u16, u16_at};
+use net::{udp, Ipv4Addr};
+use net::udp::SocketId;
+use scheduler;
+
+/// The port servers take requests on.
+const SERVER_PORT: u16 = 69;
+
+/// The opcodes.
+const OP_RRQ: u16 = 1;
+const OP_DATA: u16 = 3;
+const OP_ACK: u16 = 4;
+const OP_ERROR: u16 = 5;
+const OP_OACK: u16 = 6;
+
+/// The error codes used.
+const ERR_NOT_DEFINED: u16 = 0;
+const ERR_NOT_FOUND: u16 = 1;
+const ERR_ACCESS: u16 = 2;
+const ERR_UNKNOWN_TID: u16 = 5;
+
+/// The block size without options, and the one asked for: as much as fits
+/// in a 1500-byte Ethernet frame after the IPv4, UDP, and TFTP headers.
+const DEFAULT_BLOCK_SIZE: usize = 512;
+const BLOCK_SIZE: usize = 1468;
+
+/// How long to wait for each packet, and how many times to send again
+/// before giving up.
+const TIMEOUT_MS: u64 = 1000;
+const RETRIES: u32 = 5;
+
+/// Fetches `file` from the server at `server` and writes it to `out`.
+/// Returns the number of bytes fetched.
+pub fn get<W: Write>(server: Ipv4Addr, file: &str, out: &mut W) -> io::Result<u64> {
+    let owner = scheduler::current_id();
+    let socket = udp::bind(owner, 0)?;
+    let result = transfer(socket, server, file, out);
+    let _ = udp::close(socket, owner);
+    result
+}
+
+/// What to do with a packet from the server.
+enum Next {
+    /// Nothing: wait for another.
+    Wait,
+    /// Send the acknowledgment for a block.
+    Ack(u16),
+    /// Send the acknowledgment for the last block, and stop.
+    Done(u16),
+}
+
+fn transfer<W: Write>(socket: SocketId, server: Ipv4Addr, file: &str, out: &mut W)
+    -> io::Result<u64>
+{
+    if file.is_empty() || file.contains('\0') {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid file name"));
+    }
+
+    let mut request = Vec::new();
+    push_u16(&mut request, OP_RRQ);
+    for field in [file, "octet", "blksize", &BLOCK_SIZE.to_string()].iter() {
+        request.extend_from_slice(field.as_bytes());
+        request.push(0);
+    }
+
+    // The port the server answers from identifies the transfer; until the
+    // first answer, packets go to the one it listens on.
+    let mut port = None;
+    let mut last = request;
+    let mut block_size = DEFAULT_BLOCK_SIZE;
+    let mut expected: u16 = 1;
+    let mut total = 0;
+    let mut retries = 0;
+    let mut buf = vec![0; 4 + BLOCK_SIZE];
+    udp::send_to(socket, server, SERVER_PORT, &last)?;
+    loop {
+        let (len, from, from_port) = match udp::recv_timeout(socket, &mut buf, TIMEOUT_MS)? {
+            Some(received) => received,
+            None if retries < RETRIES => {
+                retries += 1;
+                udp::send_to(socket, server, port.unwrap_or(SERVER_PORT), &last)?;
+                continue;
+            }
+            None => return Err(io::Error::new(io::ErrorKind::TimedOut, "server not responding")),
+        };
+
+        if from != server || len < 4 {
+            continue;
+        }
+        match port {
+            Some(port) if port != from_port => {
+                let error = error_packet(ERR_UNKNOWN_TID, "unknown transfer ID");
+                let _ = udp::send_to(socket, from, from_port, &error);
+                continue;
+            }
+            Some(_) => {}
+            None => port = Some(from_port),
+        }
+
+        let packet = &buf[..len];
+        let next = match u16_at(packet, 0) {
+            OP_DATA => {
+                let block = u16_at(packet, 2);
+                let data = &packet[4..];
+                if block == expected {
+                    if data.len() > block_size {
+                        return Err(abort(socket, server, from_port, "block too large"));
+                    }
+                    if let Err(e) = out.write_all(data) {
+                        let _ = udp::send_to(socket, server, from_port,
+                                             &error_packet(ERR_ACCESS, "write failed"));
+                        return Err(e);
+                    }
+
+                    total += data.len() as u64;
+                    expected = expected.wrapping_add(1);
+                    if data.len() < block_size { Next::Done(block) } else { Next::Ack(block) }
+                } else if block == expected.wrapping_sub(1) {
+                    // Our acknowledgment was lost; send it again.
+                    Next::Ack(block)
+                } else {
+                    Next::Wait
+                }
+            }
+            OP_OACK if expected == 1 && total == 0 => {
+                block_size = match negotiated_block_size(&packet[2..]) {
+                    Some(size) => size,
+                    None => return Err(abort(socket, server, from_port, "bad option")),
+                };
+                Next::Ack(0)
+            }
+            OP_ERROR => {
+                let code = u16_at(packet, 2);
+                let message = packet[4..].split(|&b| b == 0).next().unwrap_or(&[]);
+                let message = String::from_utf8_lossy(message).into_owned();
+                let kind = match code {
+                    ERR_NOT_FOUND => io::ErrorKind::NotFound,
+                    ERR_ACCESS => io::ErrorKind::PermissionDenied,
+                    _ => io::ErrorKind::Other,
+                };
+                return Err(io::Error::new(kind, message));
+            }
+            _ => Next::Wait,
+        };
+
+        match next {
+            Next::Wait => {}
+            Next::Ack(block) => {
+                last = ack_packet(block);
+                retries = 0;
+                udp::send_to(socket, server, from_port, &last)?;
+            }
+            Next::Done(block) => {
+                // The server may not see this, and send the last block
+                // again; it gives up on its own.
+                udp::send_to(socket, server, from_port, &ack_packet(block))?;
+                return Ok(total);
+            }
+        }
+    }
+}
+
+/// Returns the block size in `options`, the body of an option
+/// acknowledgment, or `None` if it acknowledges anything not asked for.
+fn negotiated_block_size(options: &[u8]) -> Option<usize> {
+    let mut fields = options.split(|&b| b == 0);
+    let mut size = DEFAULT_BLOCK_SIZE;
+    while let Some(name) = fields.next() {
+        if name.is_empty() {
+            break;
+        }
+
+        let value = fields.next()?;
+        if !name.eq_ignore_ascii_case(b"blksize") {
+            return None;
+        }
+        size = ::std::str::from_utf8(value).ok()?.parse().ok()?;
+        if size < 8 || size > BLOCK_SIZE {
+            return None;
+        }
+    }
+    Some(size)
+}
+
+/// Tells the server the transfer is abandoned, and returns the error for
+/// why.
+fn abort(socket: SocketId, server: Ipv4Addr, port: u16, why: &'static str) -> io::Error {
+    let _ = udp::send_to(socket, server, port, &error_packet(ERR_NOT_DEFINED, why));
+    io::Error::new(io::ErrorKind::InvalidData, why)
+}
+
+fn ack_packet(block: u16) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(4);
+    push_u16(&mut packet, OP_ACK);
+    push_u16(&mut packet, block);
+    packet
+}
+
+fn error_packet(code: u16, message: &str) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(5 + message.len());
+    push_u16(&mut packet, OP_ERROR);
+    push_u16(&mut packet, code);
+    packet.extend_from_slice(message.as_bytes());
+    packet.push(0);
+    packet
+}
@@ -0,0 +1,311 @@
+//! UDP: datagram sockets, each bound to a local port on every interface.
+//!
+//! Sockets are named by ID like message ports: any process that knows one
+//! can send and receive on it, but only the process that bound it may
+//! close it, and its sockets are closed when it exits. Datagrams arriving
+//! for a port no socket is bound to are dropped, as are those for a socket
+//! that already holds `QUEUE_LEN`.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::io;
+
+use bytes::be::{put_u16, u16_at};
+use mutex::IrqMutex;
+use net::{self, Ipv4Addr, Route};
+use net::ipv4::{self, Header};
+use process::Id;
+use scheduler;
+use sync::WaitQueue;
+use tick;
+
+/// A socket identifier. IDs are never reused.
+pub type SocketId = u64;
+
+/// The length of a UDP header.
+pub const HEADER_LEN: usize = 8;
+
+/// The most data a datagram can carry: what fits in the largest IPv4
+/// packet.
+pub const MAX_DATAGRAM: usize = 0xFFFF - ipv4::HEADER_LEN - HEADER_LEN;
+
+/// The most datagrams a socket holds; more are dropped until some are
+/// received.
+const QUEUE_LEN: usize = 32;
+
+/// The most sockets open at once.
+const MAX_SOCKETS: usize = 64;
+
+/// The ports given to sockets bound to port 0: the IANA dynamic range.
+const EPHEMERAL_FIRST: u16 = 49152;
+const EPHEMERAL_LAST: u16 = 65535;
+
+/// How often `recv_timeout()` looks for a datagram, in milliseconds.
+const POLL_MS: u64 = 10;
+
+/// A datagram received and not yet taken.
+struct Datagram {
+    src: Ipv4Addr,
+    src_port: u16,
+    data: Vec<u8>,
+}
+
+struct Socket {
+    owner: Id,
+    port: u16,
+    queue: VecDeque<Datagram>,
+}
+
+/// Every open socket.
+struct Sockets {
+    sockets: BTreeMap<SocketId, Socket>,
+    last_id: SocketId,
+    /// The last ephemeral port given out.
+    last_port: u16,
+}
+
+static SOCKETS: IrqMutex<Option<Sockets>> = IrqMutex::new(None);
+
+/// Receivers waiting for a socket to have a datagram. Woken whenever one
+/// arrives for any socket or a socket is closed, and recheck their own.
+static RECEIVABLE: WaitQueue = WaitQueue::new();
+
+/// Calls `f` with the socket table, creating it first if needed.
+fn with_sockets<T, F: FnOnce(&mut Sockets) -> T>(f: F) -> T {
+    let mut sockets = SOCKETS.lock();
+    f(sockets.get_or_insert_with(|| Sockets {
+        sockets: BTreeMap::new(),
+        last_id: 0,
+        last_port: EPHEMERAL_LAST,
+    }))
+}
+
+fn no_socket() -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, "no such socket")
+}
+
+fn empty() -> io::Error {
+    io::Error::new(io::ErrorKind::WouldBlock, "no datagram received")
+}
+
+fn is_empty(error: &io::Error) -> bool {
+    error.kind() == io::ErrorKind::WouldBlock
+}
+
+/// Opens a socket owned by the process `owner` bound to `port`, or, if it
+/// is 0, to an unused port from the dynamic range, and returns its ID.
+/// Fails with `AddrInUse` if another socket is bound to `port`.
+pub fn bind(owner: Id, port: u16) -> io::Result<SocketId> {
+    with_sockets(|s| {
+        if s.sockets.len() >= MAX_SOCKETS {
+            return Err(io::Error::new(io::ErrorKind::Other, "too many sockets"));
+        }
+
+        let in_use = |s: &Sockets, port| s.sockets.values().any(|socket| socket.port == port);
+        let port = match port {
+            0 => {
+                let mut port = s.last_port;
+                loop {
+                    port = if port == EPHEMERAL_LAST { EPHEMERAL_FIRST } else { port + 1 };
+                    if !in_use(s, port) {
+                        break;
+                    }
+                }
+                s.last_port = port;
+                port
+            }
+            port if in_use(s, port) => {
+                return Err(io::Error::new(io::ErrorKind::AddrInUse, "port in use"));
+            }
+            port => port,
+        };
+
+        s.last_id += 1;
+        s.sockets.insert(s.last_id, Socket { owner, port, queue: VecDeque::new() });
+        Ok(s.last_id)
+    })
+}
+
+/// Returns the local port of socket `id`.
+pub fn local_port(id: SocketId) -> io::Result<u16> {
+    with_sockets(|s| s.sockets.get(&id).map(|socket| socket.port).ok_or_else(no_socket))
+}
+
+/// Closes socket `id` on behalf of the process `caller`, discarding any
+/// queued datagrams. Processes blocked on the socket fail with `NotFound`.
+pub fn close(id: SocketId, caller: Id) -> io::Result<()> {
+    let socket = with_sockets(|s| {
+        match s.sockets.get(&id).map(|socket| socket.owner) {
+            None => Err(no_socket()),
+            Some(owner) if owner != caller => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied, "socket belongs to another process")),
+            Some(_) => Ok(s.sockets.remove(&id)),
+        }
+    })?;
+
+    // The datagrams are freed here, outside the lock.
+    drop(socket);
+    RECEIVABLE.wake_all();
+    Ok(())
+}
+
+/// Closes every socket owned by the process `owner`. Called when it exits.
+pub fn release(owner: Id) {
+    let closed: Vec<Socket> = with_sockets(|s| {
+        let ids: Vec<SocketId> = s.sockets.iter()
+            .filter(|&(_, socket)| socket.owner == owner)
+            .map(|(&id, _)| id)
+            .collect();
+        ids.iter().filter_map(|id| s.sockets.remove(id)).collect()
+    });
+
+    if !closed.is_empty() {
+        drop(closed);
+        RECEIVABLE.wake_all();
+    }
+}
+
+/// Returns `true` if this core is using the socket table, so that closing
+/// sockets would deadlock. For the out-of-memory killer.
+pub fn is_locked_here() -> bool {
+    SOCKETS.is_held_by_current_core()
+}
+
+/// Sends `data` from socket `id` to port `port` at `dst`.
+pub fn send_to(id: SocketId, dst: Ipv4Addr, port: u16, data: &[u8]) -> io::Result<()> {
+    let route = net::route(dst)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "network unreachable"))?;
+    send_routed(id, &route, dst, port, data)
+}
+
+/// Like `send_to()`, but by `route`, which may be from an interface that
+/// has no address yet.
+pub fn send_routed(id: SocketId, route: &Route, dst: Ipv4Addr, port: u16, data: &[u8])
+    -> io::Result<()>
+{
+    if data.len() > MAX_DATAGRAM {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "datagram too long"));
+    }
+
+    let src_port = local_port(id)?;
+    let len = HEADER_LEN + data.len();
+    let mut datagram = vec![0; HEADER_LEN];
+    put_u16(&mut datagram, 0, src_port);
+    put_u16(&mut datagram, 2, port);
+    put_u16(&mut datagram, 4, len as u16);
+    datagram.extend_from_slice(data);
+
+    let pseudo = ipv4::pseudo_header(route.src, dst, ipv4::PROTO_UDP, len);
+    // A checksum of 0 means none was computed, so 0 is sent as its
+    // equivalent in one's complement.
+    let sum = match ipv4::checksum(&[&pseudo, &datagram]) {
+        0 => 0xFFFF,
+        sum => sum,
+    };
+    put_u16(&mut datagram, 6, sum);
+    ipv4::send_routed(route, dst, ipv4::PROTO_UDP, &datagram)
+}
+
+/// Removes the oldest datagram from socket `id` without blocking, copies
+/// as much of its data as fits to `buf`, and returns the length copied and
+/// the address and port it came from. The rest of a datagram longer than
+/// `buf` is discarded. Fails with `WouldBlock` if there is none.
+pub fn try_recv_from(id: SocketId, buf: &mut [u8]) -> io::Result<(usize, Ipv4Addr, u16)> {
+    let datagram = with_sockets(|s| {
+        let socket = s.sockets.get_mut(&id).ok_or_else(no_socket)?;
+        socket.queue.pop_front().ok_or_else(empty)
+    })?;
+
+    let len = datagram.data.len().min(buf.len());
+    buf[..len].copy_from_slice(&datagram.data[..len]);
+    Ok((len, datagram.src, datagram.src_port))
+}
+
+/// Like `try_recv_from()`, but sleeps until a datagram arrives. For kernel
+/// processes; user processes receive through the `udp_recvfrom` system
+/// call.
+pub fn recv_from(id: SocketId, buf: &mut [u8]) -> io::Result<(usize, Ipv4Addr, u16)> {
+    let mut result = Err(empty());
+    RECEIVABLE.wait_until(|| {
+        result = try_recv_from(id, buf);
+        !result.as_ref().err().map_or(false, is_empty)
+    });
+    result
+}
+
+/// Like `recv_from()`, but gives up after `timeout_ms` milliseconds,
+/// returning `None`.
+pub fn recv_timeout(id: SocketId, buf: &mut [u8], timeout_ms: u64)
+    -> io::Result<Option<(usize, Ipv4Addr, u16)>>
+{
+    let mut waited = 0;
+    loop {
+        match try_recv_from(id, buf) {
+            Ok(received) => return Ok(Some(received)),
+            Err(ref e) if is_empty(e) && waited < timeout_ms => {}
+            Err(ref e) if is_empty(e) => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        tick::sleep_ms(POLL_MS);
+        waited += POLL_MS;
+    }
+}
+
+/// Like `try_recv_from()`, but if the socket is empty, queues the running
+/// process to be woken when it may have a datagram, marks it waiting, and
+/// asks for it to be switched out. For system calls, which retry when
+/// woken.
+pub fn park_recv_from(id: SocketId, buf: &mut [u8]) -> io::Result<(usize, Ipv4Addr, u16)> {
+    let mut result = Err(empty());
+    RECEIVABLE.park_unless(|| {
+        result = try_recv_from(id, buf);
+        !result.as_ref().err().map_or(false, is_empty)
+    });
+
+    if result.as_ref().err().map_or(false, is_empty) {
+        scheduler::request_resched();
+    }
+    result
+}
+
+/// Handles `bytes`, a UDP datagram received in a packet with `header`.
+pub fn receive(header: &Header, bytes: &[u8]) {
+    if bytes.len() < HEADER_LEN {
+        return;
+    }
+
+    let len = u16_at(bytes, 4) as usize;
+    if len < HEADER_LEN || len > bytes.len() {
+        return;
+    }
+    let bytes = &bytes[..len];
+    if u16_at(bytes, 6) != 0 {
+        let pseudo = ipv4::pseudo_header(header.src, header.dst, ipv4::PROTO_UDP, len);
+        if ipv4::checksum(&[&pseudo, bytes]) != 0 {
+            return;
+        }
+    }
+
+    let datagram = Datagram {
+        src: header.src,
+        src_port: u16_at(bytes, 0),
+        data: bytes[HEADER_LEN..].to_vec(),
+    };
+    let port = u16_at(bytes, 2);
+    let queued = with_sockets(|s| {
+        let socket = match s.sockets.values_mut().find(|socket| socket.port == port) {
+            Some(socket) => socket,
+            None => return false,
+        };
+        if socket.queue.len() >= QUEUE_LEN {
+            return false;
+        }
+
+        socket.queue.push_back(datagram);
+        true
+    });
+
+    if queued {
+        RECEIVABLE.wake_all();
+    }
+}
@@ -0,0 +1,33 @@
+//! The out-of-memory killer.
+//!
+//! When the heap is exhausted, the allocator's out-of-memory handler kills a
+//! user process and retries the allocation, rather than failing it and,
+//! for allocations that can't fail, panicking the kernel. Processes over
+//! their memory limit go first, largest first; if none is over its limit,
+//! the largest user process goes. See `scheduler::oom_kill()`.
+
+use core::alloc::Layout;
+
+use allocator::OomAction;
+use console::log_error;
+use init::{kernel_init, Init, Stage};
+use scheduler;
+use ALLOCATOR;
+
+kernel_init!(OOM_INIT, Stage::Scheduler, "oom killer", Init::Plain(init));
+
+/// Registers the killer as the allocator's out-of-memory handler.
+fn init() {
+    ALLOCATOR.set_oom_handler(Some(handle));
+}
+
+/// Kills a process for an allocation of `layout` that failed, asking for
+/// a retry if one was killed.
+fn handle(layout: &Layout) -> OomAction {
+    if scheduler::oom_kill() {
+        return OomAction::Retry;
+    }
+
+    log_error!("out of memory allocating {} bytes: no process to kill", layout.size());
+    OomAction::Fail
+}
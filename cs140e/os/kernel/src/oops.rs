@@ -0,0 +1,127 @@
+//! Kernel oopses: bugs reported as fully as panics, but recovered from when
+//! what went wrong can be pinned on something the kernel can do without.
+//!
+//! `kernel_oops!` prints the message, the exception being handled if any,
+//! and a backtrace. Then, if it was called from a driver's interrupt
+//! handler, that interrupt is disabled and its handler removed; if from a
+//! system call or exception of a user process, the process is killed; and
+//! if from a kernel process that can exit, it exits. Anywhere else, such as
+//! with preemption disabled, at boot, or in the shell, nothing can safely
+//! be given up, and the oops becomes a panic.
+//!
+//! The caller must not hold the scheduler's or the IRQ table's lock, and
+//! when `kernel_oops!` returns, should give up what it was doing and return
+//! too: the driver or process it was running for is gone.
+
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use pi::interrupt::Interrupt;
+
+use backtrace;
+use console::{ekprintln, Color};
+use irq;
+use preempt;
+use process::Id;
+use scheduler;
+use traps;
+
+/// Reports a kernel bug with a message formatted like `panic!`'s, and
+/// recovers from it by killing the process or disabling the driver it
+/// happened in, or panics if it can't. See the module documentation.
+pub macro kernel_oops($($arg:tt)*) {
+    _oops(format_args!($($arg)*), file!(), line!(), column!())
+}
+
+/// The number of oopses recovered from since boot.
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the number of oopses recovered from since boot.
+pub fn count() -> usize {
+    COUNT.load(Ordering::Relaxed)
+}
+
+/// What an oops gives up to recover.
+#[derive(Debug, Copy, Clone)]
+enum Recovery {
+    /// The driver handling an interrupt.
+    Driver(Interrupt),
+    /// A user process, in a system call or exception.
+    UserProcess(Id),
+    /// A kernel process.
+    KernelProcess(Id),
+}
+
+/// Returns what the code running on this core can give up, if anything.
+fn recovery() -> Option<Recovery> {
+    if traps::in_interrupt() {
+        return irq::current().map(Recovery::Driver);
+    }
+
+    // A user process's kernel code only runs in its exceptions, and only the
+    // outermost of those, which interrupted the process itself, is its own.
+    if traps::in_exception() {
+        return match traps::current_frame() {
+            Some(tf) if tf.el() == 0 && scheduler::current_is_user() => {
+                Some(Recovery::UserProcess(scheduler::current_id()))
+            }
+            _ => None,
+        };
+    }
+
+    if preempt::disabled() || !scheduler::current_can_exit() {
+        return None;
+    }
+    Some(Recovery::KernelProcess(scheduler::current_id()))
+}
+
+/// Internal function called by `kernel_oops!`.
+#[doc(hidden)]
+pub fn _oops(args: fmt::Arguments, file: &'static str, line: u32, col: u32) {
+    let recovery = match recovery() {
+        Some(recovery) => recovery,
+        None => {
+            ekprintln!("oops at {}:{}:{} can't be recovered from", file, line, col);
+            panic!("{}", args);
+        }
+    };
+
+    let number = COUNT.fetch_add(1, Ordering::Relaxed) + 1;
+    ekprintln!("{}", Color::Yellow.paint(format_args!("---------- OOPS #{} ----------", number)));
+    ekprintln!("{}", Color::Bold.paint(args));
+    ekprintln!("FILE: {}\nLINE: {}\nCOL: {}", file, line, col);
+    dump_context();
+
+    match recovery {
+        Recovery::Driver(int) => {
+            irq::unregister(int);
+            ekprintln!("\n{:?} interrupt disabled; continuing", int);
+        }
+        Recovery::UserProcess(id) => {
+            ekprintln!("\nprocess {} killed; continuing", id);
+            scheduler::exit_current(scheduler::FAULT_EXIT_CODE);
+        }
+        Recovery::KernelProcess(id) => {
+            ekprintln!("\nkernel process {} exiting; continuing", id);
+            scheduler::exit(scheduler::FAULT_EXIT_CODE);
+        }
+    }
+}
+
+/// Prints the context the innermost exception being handled interrupted, if
+/// any, with a backtrace from it if it was kernel code, and then a backtrace
+/// from here. For reporting panics and oopses.
+pub fn dump_context() {
+    if let Some(tf) = traps::current_frame() {
+        ekprintln!("\nwhile handling an exception:");
+        tf.dump();
+        if tf.el() == 1 {
+            ekprintln!("interrupted kernel backtrace:");
+            let from_fp = backtrace::from_fp(tf.x[29] as usize);
+            backtrace::print(Some(tf.elr as usize).into_iter().chain(from_fp));
+        }
+    }
+
+    ekprintln!("\nbacktrace:");
+    backtrace::print(backtrace::here());
+}
@@ -0,0 +1,91 @@
+//! Preemption control and atomic context tracking.
+//!
+//! Code that must not be switched out, such as code working with per-core
+//! state, disables preemption with `disable()`: until the guard is dropped,
+//! the tick still asks for a switch, but the process keeps running. The
+//! switch happens as soon as preemption is enabled again.
+//!
+//! Code running with preemption disabled or in an exception handler is in
+//! atomic context and must not sleep: nothing would switch it out, or the
+//! handler would sleep on behalf of whatever it interrupted. Every blocking
+//! primitive calls `might_sleep()` first, which panics with a diagnostic in
+//! atomic context, rather than hanging only when the primitive is
+//! contended.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use aarch64;
+use kthread;
+use scheduler;
+use smp::MAX_CORES;
+use traps;
+
+/// How many times preemption has been disabled on each core, and not yet
+/// enabled. Only each core changes its own count, and an exception that
+/// changes it restores it before returning, so a load and a store suffice.
+static COUNT: [AtomicUsize; MAX_CORES] = [
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+];
+
+/// Disables preemption on this core until dropped. See `disable()`.
+pub struct PreemptGuard(());
+
+impl !Send for PreemptGuard { }
+
+/// Disables preemption on this core until the returned guard is dropped.
+/// Guards nest.
+pub fn disable() -> PreemptGuard {
+    let count = &COUNT[aarch64::affinity()];
+    count.store(count.load(Ordering::Relaxed) + 1, Ordering::Relaxed);
+    PreemptGuard(())
+}
+
+impl Drop for PreemptGuard {
+    /// Enables preemption again if this is the outermost guard, switching
+    /// out the running process if the tick asked for it in the meantime.
+    /// The switch waits for the next exception if this core is in an
+    /// exception handler or has IRQs masked, as it would with a spinlock
+    /// held.
+    fn drop(&mut self) {
+        let count = &COUNT[aarch64::affinity()];
+        let remaining = count.load(Ordering::Relaxed) - 1;
+        count.store(remaining, Ordering::Relaxed);
+
+        if remaining == 0 && scheduler::resched_pending()
+            && !traps::in_exception() && !aarch64::irqs_masked()
+        {
+            kthread::yield_now();
+        }
+    }
+}
+
+/// Returns `true` if preemption is disabled on this core.
+pub fn disabled() -> bool {
+    COUNT[aarch64::affinity()].load(Ordering::Relaxed) > 0
+}
+
+/// Returns `true` if this core is in atomic context: handling an exception
+/// or with preemption disabled.
+pub fn in_atomic() -> bool {
+    traps::in_exception() || disabled()
+}
+
+/// Called by every primitive that may sleep, before it does.
+///
+/// # Panics
+///
+/// Panics, saying why, if this core is in atomic context. The backtrace
+/// names the caller.
+pub fn might_sleep() {
+    if traps::in_interrupt() {
+        panic!("sleeping in an interrupt handler");
+    }
+    if traps::in_exception() {
+        panic!("sleeping in an exception handler");
+    }
+
+    let count = COUNT[aarch64::affinity()].load(Ordering::Relaxed);
+    if count > 0 {
+        panic!("sleeping with preemption disabled (count {})", count);
+    }
+}
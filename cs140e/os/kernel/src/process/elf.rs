@@ -0,0 +1,341 @@
+use std::cmp::{max, min};
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::mem::size_of;
+
+use allocator::PAGE_SIZE;
+use allocator::util::align_up;
+use process::Memory;
+use vm::Perm;
+
+/// The largest span of memory a program's segments may cover.
+pub const MAX_IMAGE_SIZE: usize = 64 * 1024 * 1024;
+
+const ET_DYN: u16 = 3;
+const EM_AARCH64: u16 = 183;
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PF_X: u32 = 1 << 0;
+const PF_W: u32 = 1 << 1;
+const DT_NULL: i64 = 0;
+const DT_RELA: i64 = 7;
+const DT_RELASZ: i64 = 8;
+const DT_RELAENT: i64 = 9;
+const R_AARCH64_RELATIVE: u32 = 1027;
+
+/// The ELF64 file header.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+struct Header {
+    ident: [u8; 16],
+    kind: u16,
+    machine: u16,
+    version: u32,
+    entry: u64,
+    phoff: u64,
+    shoff: u64,
+    flags: u32,
+    ehsize: u16,
+    phentsize: u16,
+    phnum: u16,
+    shentsize: u16,
+    shnum: u16,
+    shstrndx: u16,
+}
+
+/// An ELF64 program header.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+struct ProgramHeader {
+    kind: u32,
+    flags: u32,
+    offset: u64,
+    vaddr: u64,
+    paddr: u64,
+    filesz: u64,
+    memsz: u64,
+    align: u64,
+}
+
+/// An ELF64 relocation with an addend.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+struct Rela {
+    offset: u64,
+    info: u64,
+    addend: i64,
+}
+
+/// The reasons a program can't be loaded.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading the file failed.
+    Io(io::Error),
+    /// The file doesn't start with the ELF magic number.
+    NotElf,
+    /// The file is not a little-endian, 64-bit, version 1 ELF file.
+    UnsupportedFormat,
+    /// The file is not for AArch64.
+    WrongMachine(u16),
+    /// The file is not a position-independent executable. Programs are
+    /// loaded wherever the kernel chooses, so they must be linked with
+    /// `-pie`.
+    NotPositionIndependent(u16),
+    /// The file has no loadable segments.
+    NoSegments,
+    /// A header or segment is inconsistent: out of range, overlapping the
+    /// end of the file or another segment, misaligned, or larger in the file
+    /// than in memory.
+    Malformed(&'static str),
+    /// The segments span more than `MAX_IMAGE_SIZE` bytes.
+    TooLarge(u64),
+    /// A relocation of a type other than `R_AARCH64_RELATIVE`.
+    UnsupportedRelocation(u32),
+    /// The program's memory couldn't be allocated.
+    OutOfMemory,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "I/O error: {}", e),
+            Error::NotElf => write!(f, "not an ELF file"),
+            Error::UnsupportedFormat => write!(f, "not a little-endian ELF64 file"),
+            Error::WrongMachine(m) => write!(f, "built for machine {}, not AArch64", m),
+            Error::NotPositionIndependent(t) => {
+                write!(f, "ELF type {} is not a position-independent executable (link with -pie)", t)
+            }
+            Error::NoSegments => write!(f, "no loadable segments"),
+            Error::Malformed(what) => write!(f, "malformed ELF: {}", what),
+            Error::TooLarge(size) => write!(f, "segments span {} bytes, more than {}", size, MAX_IMAGE_SIZE),
+            Error::UnsupportedRelocation(t) => write!(f, "unsupported relocation type {}", t),
+            Error::OutOfMemory => write!(f, "out of memory"),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        Error::Io(error)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(error: Error) -> io::Error {
+        match error {
+            Error::Io(e) => e,
+            e => io::Error::new(io::ErrorKind::InvalidData, format!("{}", e)),
+        }
+    }
+}
+
+/// A loadable segment's place in a `Program`'s image.
+#[derive(Debug, Copy, Clone)]
+pub struct Segment {
+    /// The offset of the segment from the start of the image.
+    pub offset: usize,
+    /// The size of the segment in memory.
+    pub size: usize,
+    /// What the program may do with the segment.
+    pub perm: Perm,
+}
+
+/// A program loaded into memory, ready to be mapped and run.
+#[derive(Debug)]
+pub struct Program {
+    /// The memory holding the file contents of every segment, at its offset
+    /// from the lowest. Segments may extend past its end.
+    pub image: Memory,
+    /// Where each segment lies in `image`.
+    pub segments: Vec<Segment>,
+    /// The address of the entry point once `image` is mapped at the base
+    /// address passed to `load()`.
+    pub entry: usize,
+}
+
+/// Reads a `T` from `file`.
+///
+/// `T` must be a `repr(C)` structure of integers, for which any bit pattern
+/// is valid.
+fn read_struct<T: Default + Copy, R: Read>(file: &mut R) -> Result<T, Error> {
+    let mut value = T::default();
+    let bytes = unsafe {
+        ::std::slice::from_raw_parts_mut(&mut value as *mut T as *mut u8, size_of::<T>())
+    };
+    file.read_exact(bytes)?;
+    Ok(value)
+}
+
+/// Loads the ELF64 position-independent executable in `file` to run at
+/// `base`.
+///
+/// The file contents of every `PT_LOAD` segment are copied into one block of
+/// memory at their offset from the lowest segment address. The rest of each
+/// segment, its BSS, is zeroed where it shares a page with file contents and
+/// otherwise left for the caller to map on demand. Relative relocations are
+/// then applied for the block being mapped at `base`.
+pub fn load<R: Read + Seek>(file: &mut R, base: usize) -> Result<Program, Error> {
+    let header: Header = read_struct(file)?;
+    if &header.ident[..4] != b"\x7fELF" {
+        return Err(Error::NotElf);
+    }
+
+    // EI_CLASS = ELFCLASS64, EI_DATA = ELFDATA2LSB, EI_VERSION = EV_CURRENT.
+    if header.ident[4] != 2 || header.ident[5] != 1 || header.ident[6] != 1 {
+        return Err(Error::UnsupportedFormat);
+    }
+
+    if header.machine != EM_AARCH64 {
+        return Err(Error::WrongMachine(header.machine));
+    }
+
+    if header.kind != ET_DYN {
+        return Err(Error::NotPositionIndependent(header.kind));
+    }
+
+    if header.phentsize as usize != size_of::<ProgramHeader>() {
+        return Err(Error::Malformed("unexpected program header size"));
+    }
+
+    let len = file.seek(SeekFrom::End(0))?;
+    let headers_end = (header.phnum as u64 * header.phentsize as u64).checked_add(header.phoff);
+    if headers_end.map_or(true, |end| end > len) {
+        return Err(Error::Malformed("program headers past the end of the file"));
+    }
+
+    file.seek(SeekFrom::Start(header.phoff))?;
+    let mut segments = Vec::with_capacity(header.phnum as usize);
+    for _ in 0..header.phnum {
+        segments.push(read_struct::<ProgramHeader, R>(file)?);
+    }
+
+    let (mut low, mut high, mut file_high) = (u64::max_value(), 0, 0);
+    for ph in segments.iter().filter(|ph| ph.kind == PT_LOAD) {
+        if ph.filesz > ph.memsz {
+            return Err(Error::Malformed("segment larger in the file than in memory"));
+        }
+
+        if ph.align > PAGE_SIZE as u64 {
+            return Err(Error::Malformed("segment alignment larger than a page"));
+        }
+
+        // An alignment of 0 or 1 means none.
+        let misaligned = ph.vaddr % ph.align.max(1) != ph.offset % ph.align.max(1);
+        if ph.align > 1 && (!ph.align.is_power_of_two() || misaligned) {
+            return Err(Error::Malformed("segment address and file offset misaligned"));
+        }
+
+        if ph.offset.checked_add(ph.filesz).map_or(true, |end| end > len) {
+            return Err(Error::Malformed("segment past the end of the file"));
+        }
+
+        let end = ph.vaddr.checked_add(ph.memsz)
+            .ok_or(Error::Malformed("segment wraps the address space"))?;
+        low = min(low, ph.vaddr);
+        high = max(high, end);
+        file_high = max(file_high, ph.vaddr + ph.filesz);
+    }
+
+    if low > high {
+        return Err(Error::NoSegments);
+    }
+
+    let mut ranges: Vec<(u64, u64)> = segments.iter()
+        .filter(|ph| ph.kind == PT_LOAD)
+        .map(|ph| (ph.vaddr, ph.vaddr + ph.memsz))
+        .collect();
+    ranges.sort();
+    if ranges.windows(2).any(|pair| pair[1].0 < pair[0].1) {
+        return Err(Error::Malformed("segments overlap"));
+    }
+
+    if high - low > MAX_IMAGE_SIZE as u64 {
+        return Err(Error::TooLarge(high - low));
+    }
+
+    if header.entry < low || header.entry >= high {
+        return Err(Error::Malformed("entry point outside the loaded segments"));
+    }
+
+    // The image holds only what comes from the file; the rest of each
+    // segment is mapped on demand. It is mapped a page at a time, so it must
+    // fill its last page.
+    let size = align_up(max((file_high - low) as usize, 1), PAGE_SIZE);
+    let mut image = Memory::new(size, PAGE_SIZE).ok_or(Error::OutOfMemory)?;
+    let mut loaded = Vec::new();
+    for ph in segments.iter().filter(|ph| ph.kind == PT_LOAD) {
+        let start = (ph.vaddr - low) as usize;
+        file.seek(SeekFrom::Start(ph.offset))?;
+        file.read_exact(&mut image.as_mut_slice()[start..start + ph.filesz as usize])?;
+        loaded.push(Segment {
+            offset: start,
+            size: ph.memsz as usize,
+            perm: Perm { write: ph.flags & PF_W != 0, exec: ph.flags & PF_X != 0 },
+        });
+    }
+
+    let bias = (base as u64).wrapping_sub(low);
+    if let Some(dynamic) = segments.iter().find(|ph| ph.kind == PT_DYNAMIC) {
+        relocate(&mut image, low, bias, dynamic)?;
+    }
+
+    let entry = header.entry.wrapping_add(bias) as usize;
+    Ok(Program { image, segments: loaded, entry })
+}
+
+/// Returns the `T` at `vaddr` in `image`, which starts at virtual address
+/// `low`, or `None` if it doesn't fit.
+fn image_offset<T>(image: &mut Memory, low: u64, vaddr: u64) -> Option<*mut T> {
+    let offset = vaddr.checked_sub(low)? as usize;
+    let end = offset.checked_add(size_of::<T>())?;
+    if end > image.end() - image.start() {
+        return None;
+    }
+
+    Some((image.start() + offset) as *mut T)
+}
+
+/// Applies the relocations listed in the `PT_DYNAMIC` segment `dynamic` to
+/// `image`, which was linked at `low` and runs `bias` bytes from there.
+fn relocate(image: &mut Memory, low: u64, bias: u64, dynamic: &ProgramHeader) -> Result<(), Error> {
+    let (mut rela, mut relasz, mut relaent) = (0, 0, size_of::<Rela>() as u64);
+    let count = dynamic.memsz / 16;
+    for i in 0..count {
+        let entry = image_offset::<[u64; 2]>(image, low, dynamic.vaddr + i * 16)
+            .ok_or(Error::Malformed("dynamic section outside the loaded segments"))?;
+        let entry = unsafe { entry.read_unaligned() };
+        let (tag, value) = (entry[0] as i64, entry[1]);
+        match tag {
+            DT_NULL => break,
+            DT_RELA => rela = value,
+            DT_RELASZ => relasz = value,
+            DT_RELAENT => relaent = value,
+            _ => {}
+        }
+    }
+
+    if relasz == 0 {
+        return Ok(());
+    }
+
+    if relaent != size_of::<Rela>() as u64 {
+        return Err(Error::Malformed("unexpected relocation entry size"));
+    }
+
+    for i in 0..relasz / relaent {
+        let r: Rela = image_offset::<Rela>(image, low, rela + i * relaent)
+            .map(|p| unsafe { p.read_unaligned() })
+            .ok_or(Error::Malformed("relocations outside the loaded segments"))?;
+
+        let kind = r.info as u32;
+        if kind != R_AARCH64_RELATIVE {
+            return Err(Error::UnsupportedRelocation(kind));
+        }
+
+        let target = image_offset::<u64>(image, low, r.offset)
+            .ok_or(Error::Malformed("relocation target outside the loaded segments"))?;
+        unsafe { target.write_unaligned(bias.wrapping_add(r.addend as u64)); }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,211 @@
+//! File descriptors: each user process's table of open files.
+//!
+//! A descriptor indexes the table and names an `OpenFile`, which holds the
+//! file and its offset and is kept in the `OPEN_FILES` slab cache. Forked
+//! processes share their parent's open files, offsets included, as do
+//! descriptors of one process that name the same one. A program starts
+//! with descriptors 0, 1, and 2, its standard input, output, and error,
+//! open on the console.
+//!
+//! Everything here that touches a file may sleep, so system calls leave it
+//! to the workers in `fs::worker`.
+
+use std::cmp::min;
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use allocator::{Counted, SlabArc, SlabCache, SlabStats};
+use fs::dev;
+use fs::vfs::{self, Kind, Metadata};
+use mutex::IrqMutex;
+use sync;
+use FILE_SYSTEM;
+
+/// A file descriptor.
+pub type Fd = u64;
+
+/// The most files a process can have open at once.
+pub const MAX_FILES: usize = 64;
+
+/// Open for reading only.
+pub const O_RDONLY: u64 = 0;
+/// Open for writing only.
+pub const O_WRONLY: u64 = 1;
+/// Open for reading and writing.
+pub const O_RDWR: u64 = 2;
+/// The bits of the flags that hold one of the three modes above.
+pub const O_ACCMODE: u64 = 3;
+/// Create the file if it doesn't exist.
+pub const O_CREAT: u64 = 0o100;
+/// Empty the file if it exists and is opened for writing.
+pub const O_TRUNC: u64 = 0o1000;
+/// Write at the end of the file, wherever the offset is.
+pub const O_APPEND: u64 = 0o2000;
+
+/// Every open file.
+static OPEN_FILES: IrqMutex<SlabCache<Counted<OpenFile>>> =
+    IrqMutex::new(SlabCache::new("open_file"));
+
+/// Returns the statistics of the slab cache open files are kept in.
+pub fn slab_stats() -> SlabStats {
+    OPEN_FILES.lock().stats()
+}
+
+/// An open file: the file, its offset, and what it was opened for.
+pub struct OpenFile {
+    file: sync::Mutex<Box<vfs::File>>,
+    flags: u64,
+    /// Whether a read may wait indefinitely; see `vfs::File::may_block()`.
+    may_block: bool,
+    /// The file system the file is on, held so that it can't be unmounted
+    /// while the file is open.
+    _fs: Option<Arc<vfs::FileSystem>>,
+}
+
+impl OpenFile {
+    /// Wraps `file`, opened with `flags`.
+    pub fn new(file: Box<vfs::File>, flags: u64) -> OpenFile {
+        let may_block = file.may_block();
+        OpenFile { file: sync::Mutex::new(file), flags, may_block, _fs: None }
+    }
+
+    /// Opens the console for reading and writing.
+    pub fn console() -> OpenFile {
+        OpenFile::new(dev::open_console(), O_RDWR)
+    }
+
+    /// Opens the file at the absolute `path` with `flags`: an access mode,
+    /// and any of `O_CREAT`, `O_TRUNC`, and `O_APPEND`.
+    ///
+    /// Files can't be truncated, so a non-empty file opened with `O_TRUNC`
+    /// is removed and created again.
+    pub fn open(path: &Path, flags: u64) -> io::Result<OpenFile> {
+        let mode = flags & O_ACCMODE;
+        if mode != O_RDONLY && mode != O_WRONLY && mode != O_RDWR {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid access mode"));
+        }
+
+        let truncate = flags & O_TRUNC != 0 && mode != O_RDONLY;
+        let fs = FILE_SYSTEM.file_system(path)?;
+        let file = match FILE_SYSTEM.open(path) {
+            Ok(ref entry) if truncate && entry.metadata().size > 0 => {
+                FILE_SYSTEM.remove(path, false)?;
+                FILE_SYSTEM.create_file(path)?
+            }
+            Ok(entry) => entry.into_file()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "is a directory"))?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound && flags & O_CREAT != 0 => {
+                FILE_SYSTEM.create_file(path)?
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(OpenFile { _fs: Some(fs), ..OpenFile::new(file, flags) })
+    }
+
+    /// Moves this file into `OPEN_FILES` to be shared by descriptors.
+    /// Returns `None`, closing it, if it can't be allocated.
+    pub fn share(self) -> Option<SlabArc<OpenFile>> {
+        SlabArc::new(&OPEN_FILES, self).ok()
+    }
+
+    /// Returns whether the file was opened for reading.
+    pub fn readable(&self) -> bool {
+        self.flags & O_ACCMODE != O_WRONLY
+    }
+
+    /// Returns whether the file was opened for writing.
+    pub fn writable(&self) -> bool {
+        self.flags & O_ACCMODE != O_RDONLY
+    }
+
+    /// Returns whether a read may wait indefinitely, as one of the console
+    /// does. Unlike the other methods, this doesn't sleep.
+    pub fn may_block(&self) -> bool {
+        self.may_block
+    }
+
+    /// Reads up to `len` bytes from the offset.
+    pub fn read(&self, len: usize) -> io::Result<Vec<u8>> {
+        let mut buf = vec![0; len];
+        let n = self.file.lock().read(&mut buf)?;
+        buf.truncate(n);
+        Ok(buf)
+    }
+
+    /// Writes `buf` at the offset, or at the end of the file if it was
+    /// opened with `O_APPEND`, and returns how many bytes were written.
+    pub fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        let mut file = self.file.lock();
+        if self.flags & O_APPEND != 0 {
+            file.seek(SeekFrom::End(0))?;
+        }
+        file.write(buf)
+    }
+
+    /// Moves the offset and returns it.
+    pub fn seek(&self, pos: SeekFrom) -> io::Result<u64> {
+        self.file.lock().seek(pos)
+    }
+
+    /// Returns the file's metadata.
+    pub fn metadata(&self) -> Metadata {
+        Metadata::new(Kind::File, self.file.lock().size(), !self.writable())
+    }
+}
+
+/// A process's descriptor table. Clones share the open files.
+#[derive(Clone)]
+pub struct Files {
+    table: Vec<Option<SlabArc<OpenFile>>>,
+}
+
+impl Files {
+    /// Returns a table with nothing open.
+    pub fn new() -> Files {
+        Files { table: Vec::new() }
+    }
+
+    /// Returns a table with descriptors 0, 1, and 2 open on the console, or
+    /// `None` if the console's open file can't be allocated.
+    pub fn with_console() -> Option<Files> {
+        let console = OpenFile::console().share()?;
+        Some(Files { table: vec![Some(console.clone()), Some(console.clone()), Some(console)] })
+    }
+
+    /// Returns the file open as `fd`.
+    pub fn get(&self, fd: Fd) -> Option<SlabArc<OpenFile>> {
+        self.table.get(min(fd, usize::max_value() as u64) as usize).and_then(|file| file.clone())
+    }
+
+    /// Opens `file` as the lowest free descriptor and returns it, or returns
+    /// `None` if `MAX_FILES` are open.
+    pub fn insert(&mut self, file: SlabArc<OpenFile>) -> Option<Fd> {
+        let fd = match self.table.iter().position(|file| file.is_none()) {
+            Some(fd) => fd,
+            None if self.table.len() < MAX_FILES => {
+                self.table.push(None);
+                self.table.len() - 1
+            }
+            None => return None,
+        };
+
+        self.table[fd] = Some(file);
+        Some(fd as Fd)
+    }
+
+    /// Closes `fd` and returns the file it was open on. The file is closed
+    /// when the last descriptor naming it is.
+    pub fn remove(&mut self, fd: Fd) -> Option<SlabArc<OpenFile>> {
+        self.table.get_mut(min(fd, usize::max_value() as u64) as usize).and_then(|file| file.take())
+    }
+}
+
+impl fmt::Debug for Files {
+    /// Lists the open descriptors.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let open = self.table.iter().enumerate().filter(|&(_, file)| file.is_some());
+        f.debug_list().entries(open.map(|(fd, _)| fd)).finish()
+    }
+}
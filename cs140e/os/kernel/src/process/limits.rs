@@ -0,0 +1,61 @@
+/// The value of a limit that doesn't limit anything.
+pub const UNLIMITED: u64 = !0;
+
+/// The most ports a new process may own.
+pub const DEFAULT_PORTS: u64 = 32;
+
+/// A resource whose use by a process can be limited.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Resource {
+    /// Bytes of memory mapped into the address space. Faults that would map
+    /// more fail, and a process over its limit is the first the
+    /// out-of-memory killer considers.
+    Memory = 0,
+    /// Message ports owned at once. Creating more fails.
+    Ports = 1,
+}
+
+impl Resource {
+    /// Returns the resource with number `num`, as the `getrlimit` and
+    /// `setrlimit` system calls number them.
+    pub fn from_num(num: u64) -> Option<Resource> {
+        match num {
+            0 => Some(Resource::Memory),
+            1 => Some(Resource::Ports),
+            _ => None,
+        }
+    }
+}
+
+/// A process's resource limits. Inherited by the processes it forks or
+/// spawns.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Limits {
+    memory: u64,
+    ports: u64,
+}
+
+impl Limits {
+    /// Returns the limits of a new process: unlimited memory and
+    /// `DEFAULT_PORTS` ports.
+    pub fn new() -> Limits {
+        Limits { memory: UNLIMITED, ports: DEFAULT_PORTS }
+    }
+
+    /// Returns the limit on `resource`.
+    pub fn get(&self, resource: Resource) -> u64 {
+        match resource {
+            Resource::Memory => self.memory,
+            Resource::Ports => self.ports,
+        }
+    }
+
+    /// Sets the limit on `resource` to `limit` and returns the old one.
+    pub fn set(&mut self, resource: Resource, limit: u64) -> u64 {
+        let old = match resource {
+            Resource::Memory => &mut self.memory,
+            Resource::Ports => &mut self.ports,
+        };
+        ::std::mem::replace(old, limit)
+    }
+}
@@ -0,0 +1,62 @@
+use core::alloc::{GlobalAlloc, Layout};
+use std::slice;
+
+#[cfg(not(test))]
+use ALLOCATOR;
+#[cfg(test)]
+use std::alloc::System as ALLOCATOR;
+
+/// A zeroed block of kernel heap, such as a program image being loaded,
+/// returned to the heap when dropped.
+#[derive(Debug)]
+pub struct Memory {
+    ptr: *mut u8,
+    layout: Layout,
+}
+
+unsafe impl Send for Memory { }
+
+impl Memory {
+    /// Allocates `size` zeroed bytes aligned to `align`, which must be a power
+    /// of two. Returns `None` if `size` is zero or the heap is exhausted.
+    pub fn new(size: usize, align: usize) -> Option<Memory> {
+        let layout = Layout::from_size_align(size, align).ok()?;
+        if size == 0 {
+            return None;
+        }
+
+        let ptr = unsafe { ALLOCATOR.alloc(layout.clone()) };
+        if ptr.is_null() {
+            return None;
+        }
+
+        unsafe { ptr.write_bytes(0, size); }
+        Some(Memory { ptr, layout })
+    }
+
+    /// Returns the address of the first byte.
+    pub fn start(&self) -> usize {
+        self.ptr as usize
+    }
+
+    /// Returns the address one past the last byte.
+    pub fn end(&self) -> usize {
+        self.start() + self.layout.size()
+    }
+
+    /// Returns the memory as a slice.
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.ptr, self.layout.size()) }
+    }
+
+    /// Returns the memory as a mutable slice.
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.ptr, self.layout.size()) }
+    }
+}
+
+impl Drop for Memory {
+    fn drop(&mut self) {
+        unsafe { ALLOCATOR.dealloc(self.ptr, self.layout.clone()) }
+    }
+}
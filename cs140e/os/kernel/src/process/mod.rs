@@ -0,0 +1,468 @@
+mod stack;
+mod state;
+mod memory;
+pub mod elf;
+pub mod files;
+pub mod limits;
+pub mod signal;
+
+#[cfg(test)]
+mod tests;
+
+pub use self::stack::Stack;
+pub use self::state::State;
+pub use self::memory::Memory;
+pub use self::signal::{Signal, Signals};
+pub use self::limits::{Limits, Resource};
+pub use self::files::Files;
+
+use std::cmp::{max, min};
+use std::io;
+use std::mem::size_of;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::Arc;
+
+use pi::timer;
+
+use aarch64;
+use allocator::PAGE_SIZE;
+use allocator::util::{align_down, align_up};
+use console::Session;
+use traps::TrapFrame;
+use vm::{self, AddressSpace, Region, UserPageTable, Perm};
+use FILE_SYSTEM;
+
+/// A process identifier. IDs are never reused.
+pub type Id = u64;
+
+/// The lowest priority. A process's nice level runs from 0, the highest
+/// priority, to `MAX_NICE`.
+pub const MAX_NICE: u8 = 3;
+
+/// The nice level of new processes. The boot process, which runs the shell,
+/// starts at 0 so that it stays responsive.
+pub const DEFAULT_NICE: u8 = 1;
+
+/// The most space a user process's arguments may take at the top of its
+/// stack.
+pub const ARG_MAX: usize = 64 * 1024;
+
+/// The size a user process's stack may grow to. Below the pages holding the
+/// arguments, pages are mapped as the stack reaches them.
+pub const USER_STACK_LIMIT: usize = 1024 * 1024;
+
+/// The size of the guard below a user process's stack, which is never
+/// mapped, so that overflowing the stack faults rather than running into
+/// whatever is mapped below it.
+pub const USER_STACK_GUARD: usize = 64 * 1024;
+
+/// `SPSR_EL1` for a new kernel process: EL1 using `SP_EL1`, with every
+/// exception unmasked.
+const SPSR_EL1H: u64 = 0b0101;
+
+/// `SPSR_EL1` for a new user process: EL0, with every exception unmasked.
+const SPSR_EL0T: u64 = 0b0000;
+
+/// A snapshot of a process's scheduling and accounting state, for `ps`.
+#[derive(Debug, Clone)]
+pub struct Info {
+    pub id: Id,
+    pub name: String,
+    pub state: State,
+    pub nice: u8,
+    /// Microseconds spent running at EL0.
+    pub user_time: u64,
+    /// Microseconds spent running at EL1: in system calls and exception
+    /// handlers, or for a kernel process, all of its time.
+    pub kernel_time: u64,
+    /// The number of times the process has been switched to.
+    pub switches: u64,
+    /// Bytes of memory mapped into a user process's address space.
+    pub memory: usize,
+    /// The current directory.
+    pub cwd: PathBuf,
+}
+
+impl Info {
+    /// Returns the microseconds spent running at either level.
+    pub fn cpu_time(&self) -> u64 {
+        self.user_time + self.kernel_time
+    }
+}
+
+/// A process: its saved context, the kernel stack it runs on, and its
+/// scheduling and accounting state.
+#[derive(Debug)]
+pub struct Process {
+    /// The process's ID, assigned by the scheduler.
+    pub id: Id,
+    /// A name for `ps`: the program's path, or what a kernel process does.
+    pub name: String,
+    /// The scheduling state.
+    pub state: State,
+    /// The nice level, from 0 to `MAX_NICE`.
+    pub nice: u8,
+    /// The number of switches since this process last ran.
+    pub waited: u32,
+    /// The trap frame the process resumes from, which lives at the top of
+    /// its stack. Only meaningful while the process is not running.
+    pub frame: *mut TrapFrame,
+    /// The kernel stack, or `None` for the boot process, whose stack isn't
+    /// owned by the scheduler. Freed when the process is dropped.
+    stack: Option<Stack>,
+    /// Microseconds spent running at EL0.
+    pub user_time: u64,
+    /// Microseconds spent running at EL1.
+    pub kernel_time: u64,
+    /// The number of times the process has been switched to.
+    pub switches: u64,
+    /// The time the process last started running, in microseconds.
+    started: u64,
+    /// The process that started this one, if any.
+    pub parent: Option<Id>,
+    /// The exit code, once the process is a zombie.
+    pub exit_code: Option<i32>,
+    /// A user process's address space.
+    space: Option<AddressSpace>,
+    /// Pending and blocked signals and their actions. Only user processes
+    /// have signals delivered.
+    pub signals: Signals,
+    /// The resource limits. See `set_limit()`.
+    limits: Limits,
+    /// A user process's open files.
+    files: Files,
+    /// The current directory, which relative paths are resolved against.
+    /// Always canonical. See `vfs::canonicalize()`.
+    pub cwd: PathBuf,
+    /// The session whose terminal is the process's console, if it isn't
+    /// the UART's. See `console::Session`.
+    pub session: Option<Arc<Session>>,
+}
+
+unsafe impl Send for Process { }
+
+impl Process {
+    /// Returns a process that will run `entry` at EL1, with IRQs unmasked, on
+    /// a fresh kernel stack. Returns `None` if the stack can't be allocated.
+    pub fn new(entry: fn() -> !) -> Option<Process> {
+        let mut tf = TrapFrame::default();
+        tf.elr = entry as usize as u64;
+        tf.spsr = SPSR_EL1H;
+        Process::with_frame(tf)
+    }
+
+    /// Like `new()`, but `entry` is passed `arg` as its argument.
+    pub fn with_arg(entry: extern "C" fn(usize) -> !, arg: usize) -> Option<Process> {
+        let mut tf = TrapFrame::default();
+        tf.elr = entry as usize as u64;
+        tf.spsr = SPSR_EL1H;
+        tf.x[0] = arg as u64;
+        Process::with_frame(tf)
+    }
+
+    /// Loads the ELF program at `path` and returns a process that will run it
+    /// at EL0 with `args` as its arguments and `env`, a list of `NAME=VALUE`
+    /// strings, as its environment. See `elf::load()`.
+    ///
+    /// The program is entered with its initial stack laid out as follows,
+    /// from `sp` up, every entry 8 bytes:
+    ///
+    ///   * `argc`, the number of arguments;
+    ///   * `argv`: `argc` pointers to the arguments, then a null pointer;
+    ///   * `envp`: a pointer to each environment string, then a null pointer;
+    ///   * the strings themselves, each NUL-terminated, up to the top of the
+    ///     stack.
+    ///
+    /// `sp` is 16-byte aligned. For convenience `x0` holds `argc`, `x1`
+    /// `argv`, and `x2` `envp`.
+    pub fn load<P: AsRef<Path>>(path: P, args: &[&str], env: &[&str]) -> io::Result<Process> {
+        let mut file = FILE_SYSTEM.open_file(path.as_ref())?;
+        let program = elf::load(&mut file, vm::USER_IMG_BASE)?;
+        let mut space = AddressSpace::new();
+        load_program(&mut space, &program).ok_or_else(out_of_memory)?;
+        let image_end = program.segments.iter()
+            .map(|s| s.offset + s.size)
+            .fold(program.image.len(), max);
+        space.set_heap_base(vm::USER_IMG_BASE + align_up(image_end, PAGE_SIZE));
+        signal::map_trampoline(space.table_mut()).ok_or_else(out_of_memory)?;
+
+        let args_base = vm::USER_STACK_TOP - ARG_MAX;
+        let mut args_area = vec![0; ARG_MAX];
+        let sp = push_args(&mut args_area, args_base, args, env)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "arguments too long"))?;
+
+        let used = align_down(sp - args_base, PAGE_SIZE);
+        copy_to_user(&mut space, args_base + used, &args_area[used..], Perm::READ_WRITE)
+            .ok_or_else(out_of_memory)?;
+        let stack_bottom = vm::USER_STACK_TOP - USER_STACK_LIMIT;
+        space.add_region(Region {
+            start: stack_bottom,
+            end: vm::USER_STACK_TOP,
+            perm: Perm::READ_WRITE,
+        });
+        space.add_guard(stack_bottom - USER_STACK_GUARD, stack_bottom);
+
+        let mut tf = TrapFrame::default();
+        tf.elr = program.entry as u64;
+        tf.spsr = SPSR_EL0T;
+        let argv = sp + size_of::<u64>();
+        tf.sp_el0 = sp as u64;
+        tf.x[0] = args.len() as u64;
+        tf.x[1] = argv as u64;
+        tf.x[2] = (argv + (args.len() + 1) * size_of::<u64>()) as u64;
+
+        let mut process = Process::with_frame(tf).ok_or_else(out_of_memory)?;
+        process.name = format!("{}", path.as_ref().display());
+        process.space = Some(space);
+        process.files = Files::with_console().ok_or_else(out_of_memory)?;
+        Ok(process)
+    }
+
+    /// Returns a copy of this user process that resumes from `tf`, its
+    /// context in a `fork` system call, with a result of 0. The copy's
+    /// address space shares this one's frames copy-on-write. Returns `None`
+    /// for a kernel process or if the copy's kernel stack can't be
+    /// allocated.
+    pub fn fork(&mut self, tf: &TrapFrame) -> Option<Process> {
+        if self.space.is_none() {
+            return None;
+        }
+
+        let mut frame = *tf;
+        frame.x[0] = 0;
+        frame.x[7] = 0;
+
+        let mut child = Process::with_frame(frame)?;
+        child.name = self.name.clone();
+        child.nice = self.nice;
+        child.signals = self.signals.fork();
+        child.limits = self.limits;
+        child.files = self.files.clone();
+        child.cwd = self.cwd.clone();
+        child.session = self.session.clone();
+        child.space = self.space.as_mut().map(|space| space.fork());
+        Some(child)
+    }
+
+    /// Returns a process that resumes from `tf` on a fresh kernel stack, or
+    /// `None` if the stack can't be allocated.
+    fn with_frame(tf: TrapFrame) -> Option<Process> {
+        let stack = Stack::new()?;
+        let frame = (stack.top() - size_of::<TrapFrame>()) as *mut TrapFrame;
+        unsafe { ptr::write(frame, tf); }
+        Some(Process::with_stack(frame, Some(stack), DEFAULT_NICE))
+    }
+
+    /// Returns a process for the code running at boot, on the boot stack.
+    pub fn boot() -> Process {
+        let mut process = Process::with_stack(ptr::null_mut(), None, 0);
+        process.name = "boot".to_string();
+        process.state = State::Running;
+        process.started = timer::current_time();
+        process
+    }
+
+    /// Returns a ready process with no ID resuming from `frame`.
+    fn with_stack(frame: *mut TrapFrame, stack: Option<Stack>, nice: u8) -> Process {
+        Process {
+            id: 0,
+            name: String::new(),
+            state: State::Ready,
+            nice,
+            waited: 0,
+            frame,
+            stack,
+            user_time: 0,
+            kernel_time: 0,
+            switches: 0,
+            started: 0,
+            parent: None,
+            exit_code: None,
+            space: None,
+            signals: Signals::new(),
+            limits: Limits::new(),
+            files: Files::new(),
+            cwd: PathBuf::from("/"),
+            session: None,
+        }
+    }
+
+    /// Returns a snapshot of the process's state.
+    pub fn info(&self) -> Info {
+        Info {
+            id: self.id,
+            name: self.name.clone(),
+            state: self.state,
+            nice: self.nice,
+            user_time: self.user_time,
+            kernel_time: self.kernel_time,
+            switches: self.switches,
+            memory: self.memory(),
+            cwd: self.cwd.clone(),
+        }
+    }
+
+    /// Like `info()`, but for a process running since `started()`, of which
+    /// `user` microseconds were at EL0, counts the time up to `now`.
+    pub fn info_running(&self, now: u64, user: u64) -> Info {
+        let mut info = self.info();
+        let elapsed = now.saturating_sub(self.started);
+        let user = min(user, elapsed);
+        info.user_time += user;
+        info.kernel_time += elapsed - user;
+        info
+    }
+
+    /// Returns a user process's translation tables.
+    pub fn page_table(&self) -> Option<&UserPageTable> {
+        self.space.as_ref().map(|space| space.table())
+    }
+
+    /// Returns a user process's address space.
+    pub fn address_space_mut(&mut self) -> Option<&mut AddressSpace> {
+        self.space.as_mut()
+    }
+
+    /// Removes and returns a user process's address space, freeing its
+    /// memory once dropped. For a process that will never run again.
+    pub fn take_address_space(&mut self) -> Option<AddressSpace> {
+        self.space.take()
+    }
+
+    /// Returns the bytes of memory mapped into a user process's address
+    /// space, or 0 for a kernel process. See `AddressSpace::size()`.
+    pub fn memory(&self) -> usize {
+        self.space.as_ref().map_or(0, |space| space.size())
+    }
+
+    /// Returns the descriptor table.
+    pub fn files_mut(&mut self) -> &mut Files {
+        &mut self.files
+    }
+
+    /// Returns the resource limits.
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    /// Sets the limit on `resource` to `limit` and returns the old one. A
+    /// memory limit applies to the address space at once.
+    pub fn set_limit(&mut self, resource: Resource, limit: u64) -> u64 {
+        if let (Resource::Memory, Some(space)) = (resource, self.space.as_mut()) {
+            space.set_limit(min(limit, usize::max_value() as u64) as usize);
+        }
+        self.limits.set(resource, limit)
+    }
+
+    /// Sets every resource limit to those in `limits`.
+    pub fn set_limits(&mut self, limits: Limits) {
+        for &resource in [Resource::Memory, Resource::Ports].iter() {
+            self.set_limit(resource, limits.get(resource));
+        }
+    }
+
+    /// Returns the process's kernel stack, if the scheduler owns it.
+    pub fn stack(&self) -> Option<&Stack> {
+        self.stack.as_ref()
+    }
+
+    /// Records that the process started running at `now`, in microseconds.
+    pub fn start_running(&mut self, now: u64) {
+        self.state = State::Running;
+        self.switches += 1;
+        self.started = now;
+    }
+
+    /// Records that the process stopped running at `now`, in microseconds,
+    /// having spent `user` microseconds of its run at EL0, and saves
+    /// `frame` as its context.
+    pub fn stop_running(&mut self, now: u64, user: u64, frame: *mut TrapFrame) {
+        let elapsed = now.saturating_sub(self.started);
+        let user = min(user, elapsed);
+        self.user_time += user;
+        self.kernel_time += elapsed - user;
+        self.frame = frame;
+    }
+}
+
+/// Returns the error for a failed allocation while loading a process.
+fn out_of_memory() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "out of memory")
+}
+
+/// Copies `bytes` to freshly allocated pages at consecutive user addresses
+/// from the page-aligned `va`, with permissions `perm`. Returns `None` if
+/// memory is exhausted.
+fn copy_to_user(space: &mut AddressSpace, va: usize, bytes: &[u8], perm: Perm) -> Option<()> {
+    for (i, chunk) in bytes.chunks(PAGE_SIZE).enumerate() {
+        let page = space.table_mut().alloc(va + i * PAGE_SIZE, perm).ok()?;
+        page[..chunk.len()].copy_from_slice(chunk);
+        if perm.exec {
+            aarch64::sync_icache(page.as_ptr() as usize, PAGE_SIZE);
+        }
+    }
+
+    Some(())
+}
+
+/// Copies `program`'s image to `USER_IMG_BASE`, each page with the union of
+/// the permissions of the segments on it. Pages holding no segment are left
+/// unmapped. The parts of segments past the end of the image become regions
+/// mapped on demand. Returns `None` if memory is exhausted.
+fn load_program(space: &mut AddressSpace, program: &elf::Program) -> Option<()> {
+    let image = program.image.as_slice();
+
+    for (i, page) in image.chunks(PAGE_SIZE).enumerate() {
+        let (start, end) = (i * PAGE_SIZE, (i + 1) * PAGE_SIZE);
+        let perm = program.segments.iter()
+            .filter(|s| s.offset < end && s.offset + s.size > start)
+            .map(|s| s.perm)
+            .fold(None, |acc: Option<Perm>, perm| Some(acc.map_or(perm, |p| p.union(perm))));
+
+        if let Some(perm) = perm {
+            copy_to_user(space, vm::USER_IMG_BASE + start, page, perm)?;
+        }
+    }
+
+    for segment in program.segments.iter() {
+        let start = max(align_down(segment.offset, PAGE_SIZE), image.len());
+        let end = align_up(segment.offset + segment.size, PAGE_SIZE);
+        if start < end {
+            space.add_region(Region {
+                start: vm::USER_IMG_BASE + start,
+                end: vm::USER_IMG_BASE + end,
+                perm: segment.perm,
+            });
+        }
+    }
+
+    Some(())
+}
+
+/// Lays out the initial stack described at `Process::load()` at the top of
+/// `stack`, which will be copied to `base`. Returns the user address of its
+/// bottom, the initial `sp`, or `None` if it doesn't fit.
+fn push_args(stack: &mut [u8], base: usize, args: &[&str], env: &[&str]) -> Option<usize> {
+    let mut words = Vec::with_capacity(args.len() + env.len() + 3);
+    words.push(args.len() as u64);
+
+    let mut top = stack.len();
+    for strings in [args, env].iter() {
+        for string in strings.iter() {
+            top = top.checked_sub(string.len() + 1)?;
+            stack[top..top + string.len()].copy_from_slice(string.as_bytes());
+            stack[top + string.len()] = 0;
+            words.push((base + top) as u64);
+        }
+        words.push(0);
+    }
+
+    top = (top & !15).checked_sub(words.len() * size_of::<u64>())? & !15;
+    let sp = stack[top..].as_mut_ptr() as *mut u64;
+    for (i, &word) in words.iter().enumerate() {
+        unsafe { sp.add(i).write_unaligned(word); }
+    }
+
+    Some(base + top)
+}
@@ -0,0 +1,245 @@
+use std::mem::size_of;
+use std::ptr;
+
+use aarch64;
+use scheduler;
+use traps::TrapFrame;
+use vm::{self, Access, Perm, UserPageTable};
+
+/// The user address of the page holding the signal trampoline, just above
+/// the stack. A handler returns to it, and it makes the `sigreturn` system
+/// call.
+pub const TRAMPOLINE: usize = vm::USER_STACK_TOP;
+
+/// `svc #15`, `sigreturn`, then `brk #0` should it ever return.
+const TRAMPOLINE_CODE: [u32; 2] = [0xd400_01e1, 0xd420_0000];
+
+/// The handler value selecting a signal's default action.
+pub const SIG_DFL: usize = 0;
+
+/// The handler value selecting that a signal be ignored.
+pub const SIG_IGN: usize = 1;
+
+/// A signal that can be sent to a process.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Signal {
+    /// An interrupt from the console: Ctrl-C.
+    Int = 2,
+    /// Terminates the process. Can't be handled, ignored, or blocked.
+    Kill = 9,
+    /// A request to terminate.
+    Term = 15,
+}
+
+impl Signal {
+    /// Returns the signal numbered `num`, if there is one.
+    pub fn from_num(num: u64) -> Option<Signal> {
+        match num {
+            2 => Some(Signal::Int),
+            9 => Some(Signal::Kill),
+            15 => Some(Signal::Term),
+            _ => None,
+        }
+    }
+
+    /// Returns the signal named `name`, such as `INT` or `SIGINT`, or
+    /// numbered `name`.
+    pub fn from_name(name: &str) -> Option<Signal> {
+        match name.trim_left_matches("SIG") {
+            "INT" => Some(Signal::Int),
+            "KILL" => Some(Signal::Kill),
+            "TERM" => Some(Signal::Term),
+            num => num.parse().ok().and_then(Signal::from_num),
+        }
+    }
+
+    /// Returns the exit code of a process the signal terminates: its
+    /// negated number.
+    pub fn exit_code(self) -> i32 {
+        -(self as i32)
+    }
+
+    /// Returns the signal's bit in a signal mask.
+    fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+/// What happens when a signal is delivered.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// The process is terminated.
+    Default,
+    /// The signal is discarded.
+    Ignore,
+    /// The user function at this address is called with the signal number.
+    Handler(usize),
+}
+
+impl Action {
+    /// Returns the action for a `sigaction` handler value.
+    pub fn from_handler(handler: usize) -> Action {
+        match handler {
+            SIG_DFL => Action::Default,
+            SIG_IGN => Action::Ignore,
+            addr => Action::Handler(addr),
+        }
+    }
+
+    /// Returns the `sigaction` handler value for the action.
+    pub fn handler(self) -> usize {
+        match self {
+            Action::Default => SIG_DFL,
+            Action::Ignore => SIG_IGN,
+            Action::Handler(addr) => addr,
+        }
+    }
+}
+
+/// A process's signal state: which signals are pending, which are blocked,
+/// and what each does.
+#[derive(Debug, Copy, Clone)]
+pub struct Signals {
+    pending: u32,
+    blocked: u32,
+    actions: [Action; 32],
+}
+
+impl Signals {
+    /// Returns a state with nothing pending or blocked and every signal
+    /// taking its default action.
+    pub fn new() -> Signals {
+        Signals { pending: 0, blocked: 0, actions: [Action::Default; 32] }
+    }
+
+    /// Returns the state a forked child inherits: the same actions and
+    /// blocked signals, with nothing pending.
+    pub fn fork(&self) -> Signals {
+        Signals { pending: 0, ..*self }
+    }
+
+    /// Marks `signal` pending.
+    pub fn raise(&mut self, signal: Signal) {
+        self.pending |= signal.bit();
+    }
+
+    /// Sets the action for `signal` and returns the old one. Returns `None`
+    /// for `Kill`, whose action can't be changed.
+    pub fn set_action(&mut self, signal: Signal, action: Action) -> Option<Action> {
+        if signal == Signal::Kill {
+            return None;
+        }
+
+        let slot = &mut self.actions[signal as usize];
+        Some(::std::mem::replace(slot, action))
+    }
+
+    /// Returns the mask of blocked signals.
+    pub fn blocked(&self) -> u32 {
+        self.blocked
+    }
+
+    /// Sets the mask of blocked signals, which never includes `Kill`.
+    pub fn set_blocked(&mut self, mask: u32) {
+        self.blocked = mask & !Signal::Kill.bit();
+    }
+
+    /// Returns `true` if an unblocked signal is pending.
+    pub fn has_deliverable(&self) -> bool {
+        self.pending & !self.blocked != 0
+    }
+
+    /// Removes the lowest-numbered pending, unblocked signal that isn't
+    /// ignored, discarding ignored ones, and returns it with its action
+    /// and the blocked mask to restore once its handler returns. A handled
+    /// signal is blocked until then.
+    pub fn take(&mut self) -> Option<(Signal, Action, u32)> {
+        loop {
+            let deliverable = self.pending & !self.blocked;
+            if deliverable == 0 {
+                return None;
+            }
+
+            let num = deliverable.trailing_zeros();
+            self.pending &= !(1 << num);
+            let signal = match Signal::from_num(num as u64) {
+                Some(signal) => signal,
+                None => continue,
+            };
+
+            let blocked = self.blocked;
+            match self.actions[num as usize] {
+                Action::Ignore => continue,
+                Action::Handler(_) => self.blocked |= signal.bit(),
+                Action::Default => (),
+            }
+            return Some((signal, self.actions[num as usize], blocked));
+        }
+    }
+}
+
+/// What a handler's frame holds on the user stack: the context to resume
+/// once it returns and the blocked mask to restore.
+#[repr(C)]
+struct SignalFrame {
+    blocked: u64,
+    _pad: u64,
+    tf: TrapFrame,
+}
+
+/// Maps the signal trampoline into `table`. Returns `None` if memory is
+/// exhausted.
+pub fn map_trampoline(table: &mut UserPageTable) -> Option<()> {
+    let page = table.alloc(TRAMPOLINE, Perm::READ_EXEC).ok()?;
+    for (i, &insn) in TRAMPOLINE_CODE.iter().enumerate() {
+        unsafe { (page.as_mut_ptr() as *mut u32).add(i).write(insn); }
+    }
+
+    aarch64::sync_icache(page.as_ptr() as usize, page.len());
+    Some(())
+}
+
+/// Arranges for the context in `tf` to call `handler` with `signal` on
+/// return to EL0, by saving it and `blocked` in a frame pushed on the user
+/// stack of the running process. The handler returns to the trampoline.
+/// Returns `false` if the stack can't hold the frame.
+pub fn push_frame(tf: &mut TrapFrame, signal: Signal, handler: usize, blocked: u32) -> bool {
+    let size = size_of::<SignalFrame>();
+    let sp = match (tf.sp_el0 as usize).checked_sub(size) {
+        Some(sp) => sp & !15,
+        None => return false,
+    };
+
+    if !scheduler::populate(sp, size, Access::Write) || !vm::user_accessible(sp, size, true) {
+        return false;
+    }
+
+    let frame = SignalFrame { blocked: blocked as u64, _pad: 0, tf: *tf };
+    unsafe { ptr::write(sp as *mut SignalFrame, frame); }
+
+    tf.x[0] = signal as u64;
+    tf.x[1] = sp as u64;
+    tf.x[30] = TRAMPOLINE as u64;
+    tf.sp_el0 = sp as u64;
+    tf.elr = handler as u64;
+    true
+}
+
+/// Restores the context saved by `push_frame()` from the frame at the user
+/// stack pointer in `tf`, where it is when the handler returns, and returns
+/// the blocked mask to restore. Returns `None` if there's no readable frame.
+///
+/// Only the condition flags of the saved `SPSR` are kept, so the handler
+/// can't use the frame to raise its exception level or mask interrupts.
+pub fn pop_frame(tf: &mut TrapFrame) -> Option<u32> {
+    let sp = tf.sp_el0 as usize;
+    if !vm::user_accessible(sp, size_of::<SignalFrame>(), false) {
+        return None;
+    }
+
+    let frame = unsafe { ptr::read_unaligned(sp as *const SignalFrame) };
+    *tf = frame.tf;
+    tf.spsr &= 0xf000_0000;
+    Some(frame.blocked as u32)
+}
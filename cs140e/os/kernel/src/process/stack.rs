@@ -0,0 +1,45 @@
+use std::fmt;
+
+use vm::kstack;
+
+/// A kernel stack, mapped above an unmapped guard so that overflowing it
+/// faults, and unmapped when dropped. See `vm::kstack`.
+pub struct Stack {
+    bottom: usize,
+}
+
+impl Stack {
+    /// The size of a stack in bytes.
+    pub const SIZE: usize = kstack::STACK_SIZE;
+
+    /// Allocates a new zeroed stack. Returns `None` if memory is exhausted.
+    pub fn new() -> Option<Stack> {
+        kstack::alloc().map(|bottom| Stack { bottom })
+    }
+
+    /// Returns the lowest address of the stack.
+    pub fn bottom(&self) -> usize {
+        self.bottom
+    }
+
+    /// Returns the address one past the end of the stack, where it starts
+    /// growing down from.
+    pub fn top(&self) -> usize {
+        self.bottom() + Self::SIZE
+    }
+}
+
+impl Drop for Stack {
+    fn drop(&mut self) {
+        kstack::free(self.bottom)
+    }
+}
+
+impl fmt::Debug for Stack {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Stack")
+            .field("bottom", &format_args!("{:#x}", self.bottom()))
+            .field("top", &format_args!("{:#x}", self.top()))
+            .finish()
+    }
+}
@@ -0,0 +1,12 @@
+/// The scheduling state of a process.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum State {
+    /// Waiting in the run queue for its turn.
+    Ready,
+    /// Running on a core.
+    Running,
+    /// Blocked until it is woken and made ready again.
+    Waiting,
+    /// Killed; never runs again.
+    Zombie,
+}
@@ -0,0 +1,293 @@
+mod elf {
+    use std::io::{Cursor, ErrorKind};
+
+    use allocator::PAGE_SIZE;
+    use bytes::le;
+    use process::elf::{load, Error, Program, MAX_IMAGE_SIZE};
+
+    const PT_LOAD: u32 = 1;
+    const PT_DYNAMIC: u32 = 2;
+    const PF_X: u32 = 1;
+    const PF_W: u32 = 2;
+    const PF_R: u32 = 4;
+
+    const HEADER_SIZE: usize = 64;
+    const PH_SIZE: usize = 56;
+
+    /// Where the tests load programs.
+    const BASE: usize = 0x40_0000;
+
+    /// A program header.
+    #[derive(Copy, Clone)]
+    struct Ph {
+        kind: u32,
+        flags: u32,
+        offset: u64,
+        vaddr: u64,
+        filesz: u64,
+        memsz: u64,
+        align: u64,
+    }
+
+    /// Returns a `PT_LOAD` segment of `filesz` bytes from `offset` in the
+    /// file, loaded at `vaddr` and `memsz` bytes in memory.
+    fn segment(offset: u64, vaddr: u64, filesz: u64, memsz: u64) -> Ph {
+        Ph { kind: PT_LOAD, flags: PF_R | PF_X, offset, vaddr, filesz, memsz, align: 0x1000 }
+    }
+
+    /// Returns a `len`-byte AArch64 PIE with `phs` after its header and entry
+    /// point `entry`.
+    fn elf(entry: u64, phs: &[Ph], len: usize) -> Vec<u8> {
+        let mut file = vec![0; len.max(HEADER_SIZE + phs.len() * PH_SIZE)];
+        file[..8].copy_from_slice(b"\x7fELF\x02\x01\x01\x00");
+        le::put_u16(&mut file, 16, 3);
+        le::put_u16(&mut file, 18, 183);
+        le::put_u32(&mut file, 20, 1);
+        le::put_u64(&mut file, 24, entry);
+        le::put_u64(&mut file, 32, HEADER_SIZE as u64);
+        le::put_u16(&mut file, 52, HEADER_SIZE as u16);
+        le::put_u16(&mut file, 54, PH_SIZE as u16);
+        le::put_u16(&mut file, 56, phs.len() as u16);
+        for (i, ph) in phs.iter().enumerate() {
+            let at = HEADER_SIZE + i * PH_SIZE;
+            le::put_u32(&mut file, at, ph.kind);
+            le::put_u32(&mut file, at + 4, ph.flags);
+            le::put_u64(&mut file, at + 8, ph.offset);
+            le::put_u64(&mut file, at + 16, ph.vaddr);
+            le::put_u64(&mut file, at + 32, ph.filesz);
+            le::put_u64(&mut file, at + 40, ph.memsz);
+            le::put_u64(&mut file, at + 48, ph.align);
+        }
+        file
+    }
+
+    /// Returns a program of one 0x400-byte segment at 0 in the file and in
+    /// memory, with its entry point at 0x100.
+    fn simple() -> Vec<u8> {
+        elf(0x100, &[segment(0, 0, 0x400, 0x400)], 0x400)
+    }
+
+    fn load_bytes(file: Vec<u8>) -> Result<Program, Error> {
+        load(&mut Cursor::new(file), BASE)
+    }
+
+    fn error(file: Vec<u8>) -> Error {
+        match load_bytes(file) {
+            Ok(program) => panic!("loaded a bad program: {:?}", program),
+            Err(e) => e,
+        }
+    }
+
+    /// Returns what `load()` found malformed about `file`.
+    fn malformed(file: Vec<u8>) -> &'static str {
+        match error(file) {
+            Error::Malformed(what) => what,
+            e => panic!("expected a malformed ELF, got: {}", e),
+        }
+    }
+
+    #[test]
+    fn loads_a_segment() {
+        let mut file = elf(0x100, &[segment(0, 0, 0x200, 0x3000)], 0x200);
+        file[0x1FF] = 0xAB;
+        let program = load_bytes(file).expect("load");
+
+        assert_eq!(program.image.as_slice().len(), PAGE_SIZE);
+        assert_eq!(&program.image.as_slice()[..4], b"\x7fELF");
+        assert_eq!(program.image.as_slice()[0x1FF], 0xAB);
+        assert_eq!(program.entry, BASE + 0x100);
+        assert_eq!(program.segments.len(), 1);
+        let loaded = program.segments[0];
+        assert_eq!((loaded.offset, loaded.size), (0, 0x3000));
+        assert!(loaded.perm.exec && !loaded.perm.write);
+    }
+
+    #[test]
+    fn places_segments_relative_to_the_lowest() {
+        let mut data = segment(0x1000, 0x11000, 0x100, 0x100);
+        data.flags = PF_R | PF_W;
+        let program = load_bytes(elf(0x10000, &[segment(0, 0x10000, 0x800, 0x800), data], 0x1100))
+            .expect("load");
+
+        assert_eq!(program.entry, BASE);
+        assert_eq!(program.image.as_slice().len(), 0x2000);
+        assert_eq!(program.segments[1].offset, 0x1000);
+        assert!(program.segments[1].perm.write && !program.segments[1].perm.exec);
+    }
+
+    #[test]
+    fn applies_relative_relocations() {
+        let dynamic = Ph { kind: PT_DYNAMIC, flags: PF_R, offset: 0x200, vaddr: 0x200,
+                           filesz: 0x40, memsz: 0x40, align: 8 };
+        let mut file = elf(0x100, &[segment(0, 0, 0x400, 0x400), dynamic], 0x400);
+        // DT_RELA, DT_RELASZ, DT_RELAENT, DT_NULL.
+        for (i, &(tag, value)) in [(7, 0x300), (8, 24), (9, 24), (0, 0)].iter().enumerate() {
+            le::put_u64(&mut file, 0x200 + i * 16, tag);
+            le::put_u64(&mut file, 0x208 + i * 16, value);
+        }
+        // R_AARCH64_RELATIVE at 0x380, addend 0x10.
+        le::put_u64(&mut file, 0x300, 0x380);
+        le::put_u64(&mut file, 0x308, 1027);
+        le::put_u64(&mut file, 0x310, 0x10);
+
+        let program = load_bytes(file).expect("load");
+        assert_eq!(le::u64_at(program.image.as_slice(), 0x380), BASE as u64 + 0x10);
+    }
+
+    #[test]
+    fn rejects_other_relocations() {
+        let dynamic = Ph { kind: PT_DYNAMIC, flags: PF_R, offset: 0x200, vaddr: 0x200,
+                           filesz: 0x40, memsz: 0x40, align: 8 };
+        let mut file = elf(0x100, &[segment(0, 0, 0x400, 0x400), dynamic], 0x400);
+        for (i, &(tag, value)) in [(7, 0x300), (8, 24), (9, 24), (0, 0)].iter().enumerate() {
+            le::put_u64(&mut file, 0x200 + i * 16, tag);
+            le::put_u64(&mut file, 0x208 + i * 16, value);
+        }
+        // R_AARCH64_ABS64.
+        le::put_u64(&mut file, 0x308, 257);
+
+        match error(file) {
+            Error::UnsupportedRelocation(257) => {}
+            e => panic!("expected an unsupported relocation, got: {}", e),
+        }
+    }
+
+    #[test]
+    fn truncated_header() {
+        let mut file = simple();
+        file.truncate(HEADER_SIZE - 1);
+        match error(file) {
+            Error::Io(ref e) if e.kind() == ErrorKind::UnexpectedEof => {}
+            e => panic!("expected the end of the file, got: {}", e),
+        }
+    }
+
+    #[test]
+    fn truncated_program_headers() {
+        let mut file = elf(0x100, &[segment(0, 0, 0x40, 0x40); 2], 0);
+        file.truncate(HEADER_SIZE + PH_SIZE + 8);
+        assert_eq!(malformed(file), "program headers past the end of the file");
+    }
+
+    #[test]
+    fn not_elf() {
+        let mut file = simple();
+        file[1] = b'X';
+        match error(file) {
+            Error::NotElf => {}
+            e => panic!("expected not ELF, got: {}", e),
+        }
+    }
+
+    #[test]
+    fn wrong_class_or_byte_order() {
+        // ELFCLASS32, ELFDATA2MSB, and a version other than EV_CURRENT.
+        for &(at, value) in [(4, 1), (5, 2), (6, 0)].iter() {
+            let mut file = simple();
+            file[at] = value;
+            match error(file) {
+                Error::UnsupportedFormat => {}
+                e => panic!("expected an unsupported format, got: {}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn wrong_machine() {
+        let mut file = simple();
+        // EM_X86_64.
+        le::put_u16(&mut file, 18, 62);
+        match error(file) {
+            Error::WrongMachine(62) => {}
+            e => panic!("expected the wrong machine, got: {}", e),
+        }
+    }
+
+    #[test]
+    fn not_position_independent() {
+        let mut file = simple();
+        // ET_EXEC.
+        le::put_u16(&mut file, 16, 2);
+        match error(file) {
+            Error::NotPositionIndependent(2) => {}
+            e => panic!("expected a non-PIE, got: {}", e),
+        }
+    }
+
+    #[test]
+    fn wrong_program_header_size() {
+        let mut file = simple();
+        le::put_u16(&mut file, 54, PH_SIZE as u16 - 8);
+        assert_eq!(malformed(file), "unexpected program header size");
+    }
+
+    #[test]
+    fn segment_past_the_end_of_the_file() {
+        let file = elf(0x100, &[segment(0x100, 0x100, 0x400, 0x400)], 0x400);
+        assert_eq!(malformed(file), "segment past the end of the file");
+
+        let file = elf(0x100, &[segment(u64::max_value() - 0xFFF, 0, 0x1000, 0x1000)], 0x400);
+        assert_eq!(malformed(file), "segment past the end of the file");
+    }
+
+    #[test]
+    fn segment_larger_in_the_file() {
+        let file = elf(0x100, &[segment(0, 0, 0x400, 0x200)], 0x400);
+        assert_eq!(malformed(file), "segment larger in the file than in memory");
+    }
+
+    #[test]
+    fn overlapping_segments() {
+        let phs = [segment(0, 0x1000, 0x400, 0x2000), segment(0, 0x2000, 0x400, 0x400)];
+        assert_eq!(malformed(elf(0x1000, &phs, 0x400)), "segments overlap");
+
+        // Segments that only share a page are fine.
+        let phs = [segment(0, 0x1000, 0x400, 0x400), segment(0x400, 0x1400, 0x400, 0x400)];
+        assert!(load_bytes(elf(0x1000, &phs, 0x800)).is_ok());
+    }
+
+    #[test]
+    fn misaligned_segments() {
+        let mut ph = segment(0x10, 0x1000, 0x100, 0x100);
+        assert_eq!(malformed(elf(0x1000, &[ph], 0x400)),
+                   "segment address and file offset misaligned");
+
+        ph = segment(0, 0, 0x100, 0x100);
+        ph.align = 0x300;
+        assert_eq!(malformed(elf(0, &[ph], 0x400)), "segment address and file offset misaligned");
+
+        ph.align = 2 * PAGE_SIZE as u64;
+        assert_eq!(malformed(elf(0, &[ph], 0x400)), "segment alignment larger than a page");
+    }
+
+    #[test]
+    fn segment_wrapping_the_address_space() {
+        let file = elf(0, &[segment(0, 0xFFFF_FFFF_FFFF_F000, 0x400, 0x2000)], 0x400);
+        assert_eq!(malformed(file), "segment wraps the address space");
+    }
+
+    #[test]
+    fn no_segments() {
+        let mut ph = segment(0, 0, 0x400, 0x400);
+        ph.kind = PT_DYNAMIC;
+        match error(elf(0x100, &[ph], 0x400)) {
+            Error::NoSegments => {}
+            e => panic!("expected no segments, got: {}", e),
+        }
+    }
+
+    #[test]
+    fn too_large() {
+        let file = elf(0x100, &[segment(0, 0, 0x400, MAX_IMAGE_SIZE as u64 + 1)], 0x400);
+        match error(file) {
+            Error::TooLarge(size) => assert_eq!(size, MAX_IMAGE_SIZE as u64 + 1),
+            e => panic!("expected too large, got: {}", e),
+        }
+    }
+
+    #[test]
+    fn entry_outside_the_segments() {
+        let file = elf(0x400, &[segment(0, 0, 0x400, 0x400)], 0x400);
+        assert_eq!(malformed(file), "entry point outside the loaded segments");
+    }
+}
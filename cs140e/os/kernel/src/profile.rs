@@ -0,0 +1,262 @@
+//! A sampling profiler, driven by the cycle counter of each core's PMU.
+//!
+//! While it runs, every core's cycle counter is set to overflow after a
+//! sampling period's worth of cycles, and the overflow interrupt records
+//! where the core was: the PC the interrupt came in at, for kernel code,
+//! or a count, for user code. `report()` then resolves the PCs to the
+//! functions they are in with the kernel's symbol table and counts them.
+//!
+//! The interrupt is an IRQ, so code that runs with IRQs masked is charged
+//! to wherever it unmasks them: time spent in exception handlers and under
+//! an `IrqMutex` shows up just after, not in it. Cores other than the one
+//! that starts or stops the profiler follow at their next tick.
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use pi::timer;
+
+use aarch64;
+use irq;
+use ksyms;
+use mutex::IrqMutex;
+use smp::MAX_CORES;
+use traps::TrapFrame;
+
+/// The sampling rate unless another is asked for, per core.
+pub const DEFAULT_HZ: u32 = 1000;
+
+/// The highest sampling rate allowed, per core.
+pub const MAX_HZ: u32 = 100_000;
+
+/// The most samples kept from one run; later ones are counted as dropped.
+const MAX_SAMPLES: usize = 128 * 1024;
+
+/// How long the cycle counter is timed for to learn the clock rate.
+const CALIBRATION_US: u64 = 10_000;
+
+/// An error starting the profiler.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The profiler is already running.
+    Running,
+    /// The rate is 0 or above `MAX_HZ`.
+    InvalidRate,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Error::Running => "already running",
+            Error::InvalidRate => "invalid sampling rate",
+        })
+    }
+}
+
+/// The samples of the last run.
+struct Samples {
+    /// The kernel PCs sampled, once a run has started.
+    pcs: Option<Vec<usize>>,
+    /// Samples taken in user code.
+    user: usize,
+    /// Samples that didn't fit.
+    dropped: usize,
+    /// When the run started and stopped, in microseconds since boot.
+    started: u64,
+    stopped: Option<u64>,
+}
+
+static SAMPLES: IrqMutex<Samples> = IrqMutex::new(Samples {
+    pcs: None,
+    user: 0,
+    dropped: 0,
+    started: 0,
+    stopped: None,
+});
+
+/// Whether the profiler is running, and whether each core's PMU is set up
+/// to sample.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static SAMPLING: [AtomicBool; MAX_CORES] = [
+    AtomicBool::new(false), AtomicBool::new(false), AtomicBool::new(false), AtomicBool::new(false),
+];
+
+/// The cycles between samples.
+static PERIOD: AtomicUsize = AtomicUsize::new(0);
+
+/// The cores' clock rate, in cycles per second, once measured.
+static CYCLES_PER_SEC: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns whether the profiler is running.
+pub fn is_running() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Discards the last run's samples and starts sampling every core `hz`
+/// times a second.
+pub fn start(hz: u32) -> Result<(), Error> {
+    if hz == 0 || hz > MAX_HZ {
+        return Err(Error::InvalidRate);
+    }
+    if is_running() {
+        return Err(Error::Running);
+    }
+
+    let period = cycles_per_sec() / hz as usize;
+    PERIOD.store(period.max(1), Ordering::Relaxed);
+    // Allocated here, so that the interrupt handler never allocates.
+    let pcs = Vec::with_capacity(MAX_SAMPLES);
+    {
+        let mut samples = SAMPLES.lock();
+        samples.pcs = Some(pcs);
+        samples.user = 0;
+        samples.dropped = 0;
+        samples.started = timer::current_time();
+        samples.stopped = None;
+    }
+    ENABLED.store(true, Ordering::Relaxed);
+    sync();
+    Ok(())
+}
+
+/// Stops sampling, keeping the samples for `report()`. Returns `false` if
+/// the profiler wasn't running.
+pub fn stop() -> bool {
+    if !ENABLED.swap(false, Ordering::Relaxed) {
+        return false;
+    }
+
+    SAMPLES.lock().stopped = Some(timer::current_time());
+    sync();
+    true
+}
+
+/// Starts or stops sampling on this core to match the profiler. Called by
+/// each core's tick.
+pub fn sync() {
+    let core = aarch64::affinity();
+    let enabled = ENABLED.load(Ordering::Relaxed);
+    if SAMPLING[core].load(Ordering::Relaxed) == enabled {
+        return;
+    }
+
+    if enabled {
+        irq::register_local_pmu(handle_pmu);
+        aarch64::start_cycle_counter(true);
+        aarch64::set_cycle_counter(reload());
+    } else {
+        aarch64::stop_cycle_counter();
+        irq::disable_local_pmu();
+    }
+    SAMPLING[core].store(enabled, Ordering::Relaxed);
+}
+
+/// Returns the cycle counter value that overflows after a period.
+fn reload() -> u64 {
+    (1u64 << 32) - PERIOD.load(Ordering::Relaxed) as u64
+}
+
+/// Returns the cores' clock rate, measuring it against the system timer
+/// the first time.
+fn cycles_per_sec() -> usize {
+    let known = CYCLES_PER_SEC.load(Ordering::Relaxed);
+    if known != 0 {
+        return known;
+    }
+
+    aarch64::start_cycle_counter(false);
+    let start = aarch64::cycle_counter();
+    timer::spin_sleep_us(CALIBRATION_US);
+    let cycles = aarch64::cycle_counter().wrapping_sub(start);
+    aarch64::stop_cycle_counter();
+
+    let rate = (cycles * (1_000_000 / CALIBRATION_US)) as usize;
+    CYCLES_PER_SEC.store(rate, Ordering::Relaxed);
+    rate
+}
+
+/// The PMU interrupt handler: records where the interrupted context was,
+/// and sets the counter to overflow again after a period.
+fn handle_pmu(tf: &mut TrapFrame) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        aarch64::stop_cycle_counter();
+        return;
+    }
+    aarch64::set_cycle_counter(reload());
+
+    let mut samples = SAMPLES.lock();
+    if tf.el() == 0 {
+        samples.user += 1;
+        return;
+    }
+
+    let kept = match samples.pcs.as_mut() {
+        Some(pcs) => if pcs.len() < pcs.capacity() {
+            pcs.push(tf.elr as usize);
+            true
+        } else {
+            false
+        },
+        None => false,
+    };
+    if !kept {
+        samples.dropped += 1;
+    }
+}
+
+/// The samples of a run, counted by function.
+pub struct Report {
+    /// The samples in each function, most first, with the functions the
+    /// symbol table doesn't know counted as one.
+    pub functions: Vec<(String, usize)>,
+    /// The samples taken in kernel code, in user code, and dropped.
+    pub kernel: usize,
+    pub user: usize,
+    pub dropped: usize,
+    /// How long the run lasted, or has lasted so far, in microseconds.
+    pub duration_us: u64,
+}
+
+/// Returns the samples of the current or last run, or `None` if there
+/// hasn't been one.
+pub fn report() -> Option<Report> {
+    let (mut pcs, user, dropped, duration_us) = {
+        let samples = SAMPLES.lock();
+        let pcs = samples.pcs.as_ref()?.clone();
+        let stopped = samples.stopped.unwrap_or_else(timer::current_time);
+        (pcs, samples.user, samples.dropped, stopped - samples.started)
+    };
+
+    // A function's addresses are contiguous, so sorting the PCs brings each
+    // function's samples together.
+    pcs.sort();
+    let mut functions: Vec<(String, usize)> = Vec::new();
+    let mut unknown = 0;
+    let mut current = None;
+    for &pc in pcs.iter() {
+        let symbol = match ksyms::lookup(pc) {
+            Some(symbol) => symbol,
+            None => {
+                unknown += 1;
+                continue;
+            }
+        };
+
+        // The start of the function the PC is in identifies it.
+        let start = pc - symbol.offset;
+        if current == Some(start) {
+            if let Some(last) = functions.last_mut() {
+                last.1 += 1;
+            }
+        } else {
+            functions.push((symbol.name().to_string(), 1));
+            current = Some(start);
+        }
+    }
+    if unknown > 0 {
+        functions.push(("[unknown]".to_string(), unknown));
+    }
+    functions.sort_by(|a, b| b.1.cmp(&a.1));
+
+    Some(Report { functions, kernel: pcs.len(), user, dropped, duration_us })
+}
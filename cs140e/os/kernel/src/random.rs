@@ -0,0 +1,101 @@
+//! Random numbers from the hardware generator.
+//!
+//! Raw words from the generator pass two health tests before they are used:
+//! a word stuck at all zeroes or all ones fails, as does the same word
+//! coming out `MAX_REPEATS` times in a row, either of which means the noise
+//! source has failed. Each output word is then whitened by mixing two raw
+//! words through the SplitMix64 finalizer, so that any bias in the raw bits
+//! is spread across the output.
+//!
+//! This is an entropy source for user programs, through `getrandom`, and for
+//! the kernel's own use, such as stack canaries.
+
+use pi::rng::Rng;
+
+use console::log_error;
+use init::{kernel_init, Init, Stage};
+use mutex::IrqMutex;
+
+/// The most times in a row the generator may return the same word.
+const MAX_REPEATS: u32 = 3;
+
+/// The generator and the state of its health tests. `None` until `init()`.
+static RNG: IrqMutex<Option<State>> = IrqMutex::new(None);
+
+/// The noise source failed a health test.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct HealthError;
+
+struct State {
+    rng: Rng,
+    /// The last raw word and how many times in a row it came out.
+    last: u32,
+    repeats: u32,
+    /// Set once a health test fails. The generator isn't trusted again.
+    failed: bool,
+}
+
+impl State {
+    /// Returns the next raw word that passes the health tests.
+    fn next_raw(&mut self) -> Result<u32, HealthError> {
+        if self.failed {
+            return Err(HealthError);
+        }
+
+        let word = self.rng.next_u32();
+        self.repeats = if word == self.last { self.repeats + 1 } else { 1 };
+        self.last = word;
+
+        if word == 0 || word == !0 || self.repeats > MAX_REPEATS {
+            log_error!("hardware RNG failed a health test (word {:#010x}, {} in a row)",
+                       word, self.repeats);
+            self.failed = true;
+            return Err(HealthError);
+        }
+        Ok(word)
+    }
+
+    /// Returns the next whitened word.
+    fn next_u64(&mut self) -> Result<u64, HealthError> {
+        let raw = (self.next_raw()? as u64) << 32 | self.next_raw()? as u64;
+        Ok(mix(raw))
+    }
+}
+
+/// The SplitMix64 finalizer: a bijection that makes every output bit depend
+/// on every input bit.
+fn mix(mut x: u64) -> u64 {
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
+}
+
+kernel_init!(RANDOM_INIT, Stage::Drivers, "random", Init::Plain(init));
+
+/// Enables the hardware generator. Must be called before anything else
+/// here, which otherwise fails.
+pub fn init() {
+    let mut rng = Rng::new();
+    rng.enable();
+    *RNG.lock() = Some(State { rng, last: 0, repeats: 0, failed: false });
+}
+
+/// Returns a random word, or an error if the generator isn't enabled or has
+/// failed.
+pub fn next_u64() -> Result<u64, HealthError> {
+    RNG.lock().as_mut().ok_or(HealthError)?.next_u64()
+}
+
+/// Fills `buf` with random bytes, or returns an error if the generator
+/// isn't enabled or has failed, leaving `buf` partly filled.
+pub fn fill(buf: &mut [u8]) -> Result<(), HealthError> {
+    let mut guard = RNG.lock();
+    let state = guard.as_mut().ok_or(HealthError)?;
+    for chunk in buf.chunks_mut(8) {
+        let word = state.next_u64()?;
+        for (i, byte) in chunk.iter_mut().enumerate() {
+            *byte = (word >> (8 * i)) as u8;
+        }
+    }
+    Ok(())
+}
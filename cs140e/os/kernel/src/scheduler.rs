@@ -0,0 +1,1017 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use pi::timer;
+
+use aarch64;
+use allocator::{SlabBox, SlabCache, SlabStats};
+use console::{log_debug, log_warn, Session};
+use fs::{vfs, worker};
+use init::{kernel_init, Init, Stage};
+use ipc;
+use kthread;
+use net::{tcp, udp};
+use sync::WaitQueue;
+use mutex::IrqMutex;
+use preempt;
+use process::{self, Files, Id, Limits, Process, Resource, State, MAX_NICE};
+use process::signal::{self, Action, Signal};
+use smp::MAX_CORES;
+use trace::{trace_event, Event};
+use traps::{self, TrapFrame};
+use vm::{self, Access, FaultError, FaultStatus};
+
+/// The exit code of a process killed with `kill()` or `SIGKILL`.
+pub const KILLED_EXIT_CODE: i32 = -(Signal::Kill as i32);
+
+/// The exit code of a process killed for a page fault that couldn't be
+/// resolved.
+pub const FAULT_EXIT_CODE: i32 = -11;
+
+/// The result of `try_wait()` when the process can't be reaped yet.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WaitError {
+    /// The process is not a child of the caller, or does not exist.
+    NoChild,
+    /// The child is still running. The caller has been marked waiting and
+    /// is made ready again when a child exits.
+    Blocked,
+}
+
+/// How many switches a ready process must be passed over for to make up for
+/// one nice level. This keeps low-priority processes from starving.
+const SWITCHES_PER_LEVEL: i64 = 4;
+
+/// One core's share of the scheduler: the process it is running, processes
+/// it has switched out but is still leaving, its run queue, and its idle
+/// process. Each process is kept in the `PROCESSES` slab cache.
+struct Core {
+    current: Option<SlabBox<Process>>,
+    /// Processes switched out by the exception this core is returning from.
+    /// The core is still on their kernel stacks, so they are kept out of the
+    /// queue, where another core could pick them up, until `finish_switch()`.
+    switched_out: Vec<SlabBox<Process>>,
+    queue: VecDeque<SlabBox<Process>>,
+    /// The process the core runs when nothing else can run, while it isn't
+    /// running. Only this core runs it, so it never joins a queue.
+    idle: Option<SlabBox<Process>>,
+    /// The ID of the idle process, once it has been created.
+    idle_id: Option<Id>,
+}
+
+impl Core {
+    fn new(current: Option<SlabBox<Process>>) -> Core {
+        Core {
+            current,
+            switched_out: Vec::new(),
+            queue: VecDeque::new(),
+            idle: None,
+            idle_id: None,
+        }
+    }
+
+    /// Returns the index of the ready process in the queue with the best
+    /// priority.
+    ///
+    /// Priority is the nice level, less a credit for every switch the process
+    /// has waited through. Ties go to the process nearest the front, so
+    /// processes of equal priority run round-robin.
+    fn best(&self) -> Option<usize> {
+        let priority = |p: &Process| p.nice as i64 * SWITCHES_PER_LEVEL - p.waited as i64;
+        let ready = self.queue.iter().enumerate().filter(|&(_, p)| p.state == State::Ready);
+        ready.min_by_key(|&(i, p)| (priority(p), i)).map(|(index, _)| index)
+    }
+
+    /// Returns the number of ready processes in the queue.
+    fn ready(&self) -> usize {
+        self.queue.iter().filter(|p| p.state == State::Ready).count()
+    }
+
+    /// Returns every process the core holds, running process first.
+    fn processes<'a>(&'a self) -> Box<Iterator<Item = &'a Process> + 'a> {
+        let current = self.current.iter().chain(self.switched_out.iter());
+        Box::new(current.chain(self.queue.iter()).map(|p| &**p))
+    }
+
+    /// Like `processes()`, but mutable.
+    fn processes_mut<'a>(&'a mut self) -> Box<Iterator<Item = &'a mut Process> + 'a> {
+        let current = self.current.iter_mut().chain(self.switched_out.iter_mut());
+        Box::new(current.chain(self.queue.iter_mut()).map(|p| &mut **p))
+    }
+}
+
+/// A priority round-robin scheduler with a run queue per core. A process
+/// stays on the queue of the core it last ran on, in the order it will be
+/// considered for running, until a core with nothing else to run steals it.
+struct Scheduler {
+    cores: [Core; MAX_CORES],
+    last_id: Id,
+    /// The process that adopts orphans, once started. See `start_init()`.
+    init: Option<Id>,
+}
+
+impl Scheduler {
+    /// Returns a scheduler whose only process is the one running now on
+    /// core 0, with ID 0.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the boot process can't be allocated.
+    fn new() -> Scheduler {
+        let boot = SlabBox::new(&PROCESSES, Process::boot()).expect("boot process");
+        Scheduler {
+            cores: [Core::new(Some(boot)), Core::new(None), Core::new(None), Core::new(None)],
+            last_id: 0,
+            init: None,
+        }
+    }
+
+    /// Returns the calling core's part of the scheduler.
+    fn core(&mut self) -> &mut Core {
+        &mut self.cores[aarch64::affinity()]
+    }
+
+    /// Returns the process running on the calling core.
+    fn current(&mut self) -> Option<&mut Process> {
+        self.core().current.as_mut().map(|p| &mut **p)
+    }
+
+    /// Adds `process` to the back of the calling core's queue as ready,
+    /// assigning it an ID and making it a child of the running process, or
+    /// of init if there is none. It inherits the running process's resource
+    /// limits, current directory, and session.
+    fn add(&mut self, mut process: SlabBox<Process>) -> Id {
+        self.last_id += 1;
+        process.id = self.last_id;
+        process.state = State::Ready;
+        process.parent = self.current().map(|p| p.id).or(self.init);
+        if let Some(limits) = self.current().map(|p| p.limits()) {
+            process.set_limits(limits);
+        }
+        if let Some(cwd) = self.current().map(|p| p.cwd.clone()) {
+            process.cwd = cwd;
+        }
+        if let Some(session) = self.current().and_then(|p| p.session.clone()) {
+            process.session = Some(session);
+        }
+        self.core().queue.push_back(process);
+        self.last_id
+    }
+
+    /// Saves `tf` as the context of the process running on the calling core
+    /// and switches it out, as ready unless it is waiting or a zombie. Then
+    /// starts running the ready process in the core's queue with the best
+    /// priority, installing its address space, and returns its frame.
+    ///
+    /// If no other process is ready, returns `tf` if the running process can
+    /// continue. If it can't, runs a process switched out earlier in the
+    /// same exception, or steals a ready process from the core with the
+    /// most, or runs the core's idle process. Returns `None` only if the
+    /// idle process can't be created.
+    fn switch(&mut self, tf: *mut TrapFrame) -> Option<*mut TrapFrame> {
+        let core = aarch64::affinity();
+        let index = match self.cores[core].best() {
+            Some(index) => Some(index),
+            None => {
+                if let Some(current) = self.cores[core].current.as_mut() {
+                    if current.state == State::Running || current.state == State::Ready {
+                        current.state = State::Running;
+                        return Some(tf);
+                    }
+                }
+
+                match self.reclaim(core) {
+                    Some(index) => Some(index),
+                    None => self.steal(core),
+                }
+            }
+        };
+
+        let mut next = match index {
+            Some(index) => self.cores[core].queue.remove(index).unwrap(),
+            None => self.take_idle(core)?,
+        };
+
+        let this = &mut self.cores[core];
+        for process in this.queue.iter_mut().filter(|p| p.state == State::Ready) {
+            process.waited = process.waited.saturating_add(1);
+        }
+
+        let now = timer::current_time();
+        if let Some(mut current) = this.current.take() {
+            let user = USER_TIME[core].swap(0, Ordering::Relaxed) as u64;
+            current.stop_running(now, user, tf);
+            if current.state == State::Running {
+                current.state = State::Ready;
+            }
+
+            if Some(current.id) == this.idle_id {
+                this.idle = Some(current);
+            } else {
+                this.switched_out.push(current);
+            }
+        }
+
+        next.start_running(now);
+        next.waited = 0;
+        trace_event!(Event::Switch, next.id);
+        let frame = next.frame;
+        vm::activate(next.page_table());
+        this.current = Some(next);
+        Some(frame)
+    }
+
+    /// Returns `core`'s idle process to run, creating it the first time.
+    /// Returns `None` if it can't be allocated.
+    fn take_idle(&mut self, core: usize) -> Option<SlabBox<Process>> {
+        if self.cores[core].idle_id.is_none() {
+            let mut idle = Process::new(idle_loop)?;
+            self.last_id += 1;
+            idle.id = self.last_id;
+            idle.name = format!("idle/{}", core);
+            idle.nice = MAX_NICE;
+            let idle = SlabBox::new(&PROCESSES, idle).ok()?;
+            self.cores[core].idle_id = Some(idle.id);
+            self.cores[core].idle = Some(idle);
+        }
+
+        self.cores[core].idle.take()
+    }
+
+    /// Moves a ready process that `core` switched out earlier in the same
+    /// exception to the back of its queue and returns its index there. Only
+    /// `core` may run it, and it is about to.
+    fn reclaim(&mut self, core: usize) -> Option<usize> {
+        let this = &mut self.cores[core];
+        let index = this.switched_out.iter().position(|p| p.state == State::Ready)?;
+        let process = this.switched_out.remove(index);
+        this.queue.push_back(process);
+        Some(this.queue.len() - 1)
+    }
+
+    /// Moves the best ready process of the core with the most ready
+    /// processes to the back of `core`'s queue and returns its index there,
+    /// or returns `None` if no other core has one.
+    fn steal(&mut self, core: usize) -> Option<usize> {
+        let victim = (0..MAX_CORES)
+            .filter(|&c| c != core)
+            .max_by_key(|&c| self.cores[c].ready())
+            .filter(|&c| self.cores[c].ready() > 0)?;
+
+        let index = self.cores[victim].best()?;
+        let process = self.cores[victim].queue.remove(index).unwrap();
+        self.cores[core].queue.push_back(process);
+        Some(self.cores[core].queue.len() - 1)
+    }
+
+    /// Returns a snapshot of every process but the idle processes, each
+    /// core's running process first, with the time running processes have
+    /// run up to `now`.
+    fn infos(&self, now: u64) -> Vec<process::Info> {
+        let mut infos = Vec::new();
+        for (core, c) in self.cores.iter().enumerate() {
+            let user = running_user_time(core, now);
+            let current = c.current.iter().filter(|p| Some(p.id) != c.idle_id);
+            infos.extend(current.map(|p| p.info_running(now, user)));
+            infos.extend(c.switched_out.iter().chain(c.queue.iter()).map(|p| p.info()));
+        }
+        infos
+    }
+
+    /// Returns the process with ID `id`, wherever it is.
+    fn find(&mut self, id: Id) -> Option<&mut Process> {
+        self.cores.iter_mut().flat_map(|c| c.processes_mut()).find(|p| p.id == id)
+    }
+
+    /// Makes the process `id` a zombie with exit code `code`, unless it
+    /// already is one, and hands its children to init. Returns `false` if no
+    /// such process exists or it is an idle process, which can't exit. The
+    /// caller must wake `CHILD_EXITED` once the scheduler is unlocked, for
+    /// its parent and for init.
+    ///
+    /// The zombie keeps its stack and memory until it is reaped.
+    fn zombify(&mut self, id: Id, code: i32) -> bool {
+        if self.cores.iter().any(|c| c.idle_id == Some(id)) {
+            return false;
+        }
+
+        match self.find(id) {
+            Some(process) => {
+                if process.state != State::Zombie {
+                    process.state = State::Zombie;
+                    process.exit_code = Some(code);
+                }
+            }
+            None => return false,
+        }
+
+        if self.init != Some(id) {
+            let init = self.init;
+            for core in self.cores.iter_mut() {
+                for child in core.processes_mut().filter(|p| p.parent == Some(id)) {
+                    child.parent = init;
+                }
+            }
+        }
+        true
+    }
+
+    /// Returns the process the out-of-memory killer should kill: of the
+    /// user processes over their memory limit, or if none is, of every user
+    /// process, the one using the most memory. Only queued processes are
+    /// considered, since a core may be on the stack or in the address space
+    /// of the others.
+    fn oom_victim(&mut self) -> Option<&mut Process> {
+        let over_limit = |p: &Process| p.memory() as u64 > p.limits().get(Resource::Memory);
+        self.cores.iter_mut()
+            .flat_map(|c| c.queue.iter_mut().map(|p| &mut **p))
+            .filter(|p| p.state != State::Zombie && p.memory() > 0)
+            .max_by_key(|p| (over_limit(&**p), p.memory()))
+    }
+
+    /// Removes and returns the zombie `id` if it is a child of the running
+    /// process.
+    fn reap(&mut self, id: Id) -> Result<SlabBox<Process>, WaitError> {
+        let parent = match self.current() {
+            Some(process) => process.id,
+            None => return Err(WaitError::NoChild),
+        };
+
+        let is_child = |p: &Process| p.id == id && p.parent == Some(parent);
+        if !self.cores.iter().flat_map(|c| c.processes()).any(|p| is_child(p)) {
+            return Err(WaitError::NoChild);
+        }
+
+        self.take_zombie(is_child).ok_or(WaitError::Blocked)
+    }
+
+    /// Removes and returns any zombie child of the running process.
+    fn reap_any(&mut self) -> Option<SlabBox<Process>> {
+        let parent = self.current()?.id;
+        self.take_zombie(|p| p.parent == Some(parent))
+    }
+
+    /// Removes and returns a queued zombie for which `f` returns `true`.
+    ///
+    /// A zombie still running, or still being switched out, is not taken
+    /// yet: its core is on its stack. `finish_switch()` wakes `CHILD_EXITED`
+    /// once it is queued.
+    fn take_zombie<F>(&mut self, f: F) -> Option<SlabBox<Process>>
+        where F: Fn(&Process) -> bool
+    {
+        for core in self.cores.iter_mut() {
+            let index = core.queue.iter().position(|p| f(p) && p.state == State::Zombie);
+            if let Some(index) = index {
+                return core.queue.remove(index);
+            }
+        }
+        None
+    }
+}
+
+/// The global scheduler. Only locked with IRQs masked on the current core.
+///
+/// It is locked after any wait queue's lock, `CHILD_EXITED` included, since
+/// a process is marked waiting with its queue locked, and so a queue is
+/// only woken with the scheduler unlocked. Code run with it locked, such
+/// as `handle_fault()` and the closure passed to `with_files()`, may lock
+/// the page and heap allocators, but must not lock a wait queue, or
+/// anything held while the scheduler is locked.
+///
+/// Each process is in exactly one place on exactly one core: running,
+/// switched out, queued, or idle. A process switched out stays off the
+/// queue until `finish_switch()`, as its core is still on its stack, so
+/// `steal()` only ever takes ready processes from another core's queue.
+static SCHEDULER: IrqMutex<Option<Scheduler>> = IrqMutex::new(None);
+
+/// Every process. Like the allocators, it is locked after the scheduler and
+/// locks nothing itself, so it may be locked anywhere.
+static PROCESSES: IrqMutex<SlabCache<Process>> = IrqMutex::new(SlabCache::new("process"));
+
+/// Processes waiting for a child to exit. Locked before the scheduler, so
+/// only woken with the scheduler unlocked.
+static CHILD_EXITED: WaitQueue = WaitQueue::new();
+
+/// Whether the next return from an exception on each core should switch
+/// processes.
+static NEED_RESCHED: [AtomicBool; MAX_CORES] = [
+    AtomicBool::new(false), AtomicBool::new(false), AtomicBool::new(false), AtomicBool::new(false),
+];
+
+/// On each core, when the running process last entered or left EL0, in
+/// microseconds.
+static MODE_CHANGED: [AtomicUsize; MAX_CORES] = [
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+];
+
+/// On each core, whether the running process is at EL0 rather than in the
+/// kernel.
+static AT_EL0: [AtomicBool; MAX_CORES] = [
+    AtomicBool::new(false), AtomicBool::new(false), AtomicBool::new(false), AtomicBool::new(false),
+];
+
+/// On each core, the microseconds the running process has spent at EL0
+/// since it was switched to, up to its last entry to the kernel.
+static USER_TIME: [AtomicUsize; MAX_CORES] = [
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+];
+
+/// Runs `f` with the scheduler locked and IRQs masked on this core.
+fn with_scheduler<R, F: FnOnce(&mut Scheduler) -> R>(f: F) -> R {
+    f(SCHEDULER.lock().get_or_insert_with(Scheduler::new))
+}
+
+/// Adds `process` to the run queue and returns its new ID, or `None` if it
+/// can't be allocated.
+pub fn add(process: Process) -> Option<Id> {
+    let process = SlabBox::new(&PROCESSES, process).ok()?;
+    Some(with_scheduler(|s| s.add(process)))
+}
+
+/// Starts a new process running `entry` and returns its ID, or `None` if it
+/// or its stack can't be allocated. See `Process::new()`.
+pub fn spawn(entry: fn() -> !) -> Option<Id> {
+    Process::new(entry).and_then(add)
+}
+
+/// Returns the ID of the running process.
+pub fn current_id() -> Id {
+    with_scheduler(|s| s.current().map(|p| p.id).unwrap_or(0))
+}
+
+/// Returns `true` if the running process is a user process.
+pub fn current_is_user() -> bool {
+    with_scheduler(|s| s.current().map_or(false, |p| p.page_table().is_some()))
+}
+
+/// Returns `true` if the running process may be made to exit: it isn't the
+/// boot process, which runs the shell, or its core's idle process.
+pub fn current_can_exit() -> bool {
+    with_scheduler(|s| {
+        let core = s.core();
+        match core.current {
+            Some(ref process) => process.id != 0 && Some(process.id) != core.idle_id,
+            None => false,
+        }
+    })
+}
+
+/// Adds a copy of the running user process, resuming from `tf` with a
+/// result of 0, and returns its ID. Returns `None` if the running process
+/// is a kernel process or the copy can't be allocated. See
+/// `Process::fork()`.
+pub fn fork(tf: &TrapFrame) -> Option<Id> {
+    with_scheduler(|s| {
+        let child = s.current()?.fork(tf)?;
+        let child = SlabBox::new(&PROCESSES, child).ok()?;
+        Some(s.add(child))
+    })
+}
+
+/// Marks the process `id` as a zombie with exit code `KILLED_EXIT_CODE` so
+/// it never runs again. If it is the running process, it is switched out at
+/// the next tick. Returns `false` if no such process exists.
+pub fn kill(id: Id) -> bool {
+    let killed = with_scheduler(|s| s.zombify(id, KILLED_EXIT_CODE));
+    ipc::release(id);
+    udp::release(id);
+    tcp::release(id);
+    worker::release(id);
+    CHILD_EXITED.wake_all();
+    killed
+}
+
+/// Kills the user process chosen by `Scheduler::oom_victim()` to free memory
+/// when the heap is exhausted, logging which it was, and frees its address
+/// space, ports, and sockets at once rather than when it is reaped. Returns
+/// `false` if there is no such process, or if this core holds a lock the
+/// kill needs, which the allocation that ran out of memory may have been
+/// made under.
+pub fn oom_kill() -> bool {
+    if SCHEDULER.is_held_by_current_core() || CHILD_EXITED.is_locked_here()
+        || ipc::is_locked_here() || udp::is_locked_here() || tcp::is_locked_here()
+        || worker::is_locked_here() || vm::frames_locked_here()
+    {
+        return false;
+    }
+
+    let victim = with_scheduler(|s| {
+        let (id, space) = {
+            let process = s.oom_victim()?;
+            let (memory, limit) = (process.memory(), process.limits().get(Resource::Memory));
+            if memory as u64 > limit {
+                log_warn!("out of memory: killed process {} ({}), using {} KiB of its {} KiB limit",
+                          process.id, process.name, memory / 1024, limit / 1024);
+            } else {
+                log_warn!("out of memory: killed process {} ({}), using {} KiB",
+                          process.id, process.name, memory / 1024);
+            }
+            (process.id, process.take_address_space())
+        };
+        s.zombify(id, KILLED_EXIT_CODE);
+        Some((id, space))
+    });
+
+    match victim {
+        Some((id, space)) => {
+            // The frames are freed here, outside the lock.
+            drop(space);
+            ipc::release(id);
+            udp::release(id);
+            tcp::release(id);
+            worker::release(id);
+            CHILD_EXITED.wake_all();
+            true
+        }
+        None => false,
+    }
+}
+
+/// Returns the running process's limit on `resource`.
+pub fn limit(resource: Resource) -> u64 {
+    with_scheduler(|s| s.current().map_or(Limits::new(), |p| p.limits())).get(resource)
+}
+
+/// Sets the running process's limit on `resource` to `limit` and returns the
+/// old one. See `Process::set_limit()`.
+pub fn set_limit(resource: Resource, limit: u64) -> u64 {
+    with_scheduler(|s| match s.current() {
+        Some(process) => process.set_limit(resource, limit),
+        None => process::limits::UNLIMITED,
+    })
+}
+
+/// Returns the running process's use of `resource`: the bytes of memory
+/// mapped into its address space, or the ports it owns.
+pub fn usage(resource: Resource) -> u64 {
+    match resource {
+        Resource::Memory => with_scheduler(|s| s.current().map_or(0, |p| p.memory())) as u64,
+        Resource::Ports => ipc::owned(current_id()) as u64,
+    }
+}
+
+/// Sends `signal` to the process `id`. `Kill` kills it at once, as `kill()`
+/// does. Other signals are marked pending and delivered when the process
+/// next returns to EL0, waking it if it is waiting so that it does; a
+/// blocking system call it was making is restarted afterwards. Returns
+/// `false` if no such process exists or it is a kernel process.
+pub fn signal(id: Id, signal: Signal) -> bool {
+    if signal == Signal::Kill {
+        let is_user = with_scheduler(|s| s.find(id).map_or(false, |p| p.page_table().is_some()));
+        return is_user && kill(id);
+    }
+
+    with_scheduler(|s| {
+        let process = match s.find(id) {
+            Some(process) => process,
+            None => return false,
+        };
+
+        if process.page_table().is_none() {
+            return false;
+        }
+
+        process.signals.raise(signal);
+        if process.state == State::Waiting && process.signals.has_deliverable() {
+            process.state = State::Ready;
+        }
+        true
+    })
+}
+
+/// Sets the action for `signal` in the running process and returns the old
+/// one, or `None` if it can't be changed.
+pub fn set_signal_action(signal: Signal, action: Action) -> Option<Action> {
+    with_scheduler(|s| s.current()?.signals.set_action(signal, action))
+}
+
+/// Sets the mask of signals blocked in the running process and returns the
+/// old one.
+pub fn set_signal_mask(mask: u32) -> u32 {
+    with_scheduler(|s| match s.current() {
+        Some(process) => {
+            let old = process.signals.blocked();
+            process.signals.set_blocked(mask);
+            old
+        }
+        None => 0,
+    })
+}
+
+/// Called on the way out of every exception with the frame `tf` of the
+/// running process. If it is returning to EL0 with a signal to deliver,
+/// either sets `tf` up to run the signal's handler or, for the default
+/// action, or if the handler's frame doesn't fit on the stack, makes the
+/// process exit and returns `false`. The caller must then schedule
+/// another process.
+pub fn deliver_signal(tf: &mut TrapFrame) -> bool {
+    if tf.el() != 0 {
+        return true;
+    }
+
+    let next = with_scheduler(|s| s.current().and_then(|p| p.signals.take()));
+    match next {
+        None => true,
+        Some((signal, Action::Handler(handler), blocked)) => {
+            if signal::push_frame(tf, signal, handler, blocked) {
+                return true;
+            }
+
+            exit_current(FAULT_EXIT_CODE);
+            false
+        }
+        Some((signal, _, _)) => {
+            exit_current(signal.exit_code());
+            false
+        }
+    }
+}
+
+/// Returns the running process from a signal handler to the context it
+/// interrupted, in `tf`. Returns `false` if the handler's frame is gone, in
+/// which case the process has been made to exit.
+pub fn signal_return(tf: &mut TrapFrame) -> bool {
+    match signal::pop_frame(tf) {
+        Some(blocked) => {
+            set_signal_mask(blocked);
+            true
+        }
+        None => {
+            exit_current(FAULT_EXIT_CODE);
+            false
+        }
+    }
+}
+
+/// Makes the running process a zombie with exit code `code` and asks for it
+/// to be switched out when the current exception returns. For system calls.
+pub fn exit_current(code: i32) {
+    let id = with_scheduler(|s| {
+        let id = s.current().map(|p| p.id).unwrap_or(0);
+        s.zombify(id, code);
+        id
+    });
+    ipc::release(id);
+    udp::release(id);
+    tcp::release(id);
+    worker::release(id);
+    CHILD_EXITED.wake_all();
+    request_resched();
+}
+
+/// Exits the running kernel process with exit code `code`. Never returns.
+pub fn exit(code: i32) -> ! {
+    exit_current(code);
+    loop {
+        kthread::yield_now();
+    }
+}
+
+/// Reaps the child `id` of the running process if it has exited, returning
+/// its exit code.
+fn reap(id: Id) -> Result<i32, WaitError> {
+    let result = with_scheduler(|s| s.reap(id));
+    // The zombie's stacks and memory are freed here, outside the lock.
+    result.map(|process| process.exit_code.unwrap_or(KILLED_EXIT_CODE))
+}
+
+/// Reaps the child `id` of the running process if it has exited, returning
+/// its exit code. Otherwise queues the running process to be woken when a
+/// child exits, marks it waiting, and asks for it to be switched out. For
+/// system calls, which retry when woken.
+pub fn try_wait(id: Id) -> Result<i32, WaitError> {
+    let mut result = Err(WaitError::Blocked);
+    CHILD_EXITED.park_unless(|| {
+        result = reap(id);
+        result != Err(WaitError::Blocked)
+    });
+
+    if result == Err(WaitError::Blocked) {
+        request_resched();
+    }
+    result
+}
+
+/// Waits for the child `id` of the running process to exit, then reaps it
+/// and returns its exit code. Returns `None` if `id` is not a child of the
+/// running process. For kernel processes; user processes wait through the
+/// `wait` system call.
+pub fn wait(id: Id) -> Option<i32> {
+    let mut result = Err(WaitError::Blocked);
+    CHILD_EXITED.wait_until(|| {
+        result = reap(id);
+        result != Err(WaitError::Blocked)
+    });
+    result.ok()
+}
+
+/// Waits for any child of the running process to exit, then reaps it and
+/// returns its ID and exit code. For kernel processes.
+pub fn wait_any() -> (Id, i32) {
+    let mut reaped = None;
+    CHILD_EXITED.wait_until(|| {
+        reaped = with_scheduler(|s| s.reap_any())
+            .map(|process| (process.id, process.exit_code.unwrap_or(KILLED_EXIT_CODE)));
+        reaped.is_some()
+    });
+    reaped.unwrap()
+}
+
+kernel_init!(INIT_INIT, Stage::Scheduler, "init", Init::Plain(init));
+
+/// Starts init, warning if it can't be.
+fn init() {
+    if !start_init() {
+        log_warn!("no memory for init; orphans won't be reaped");
+    }
+}
+
+/// Starts init, the kernel thread that adopts every process whose parent
+/// exits before it, and every process started with no parent, and reaps
+/// them as they exit. Returns `false` if its stack can't be allocated.
+pub fn start_init() -> bool {
+    let handle = kthread::spawn("init", || loop {
+        let (id, code) = wait_any();
+        log_debug!("init: reaped process {}, which exited with status {}", id, code);
+    });
+
+    match handle {
+        Some(handle) => {
+            with_scheduler(|s| s.init = Some(handle.id()));
+            true
+        }
+        None => false,
+    }
+}
+
+/// Sets the nice level of process `id` to `nice`, which must be at most
+/// `MAX_NICE`. Returns `false` if no such process exists.
+pub fn set_nice(id: Id, nice: u8) -> bool {
+    assert!(nice <= MAX_NICE, "nice level {} out of range", nice);
+    with_scheduler(|s| match s.find(id) {
+        Some(process) => {
+            process.nice = nice;
+            true
+        }
+        None => false,
+    })
+}
+
+/// Marks the running process waiting and returns its ID. It keeps running
+/// until it yields, and a `wake()` before then leaves it ready, so a
+/// blocking primitive can record the ID, release its own lock, and yield
+/// without missing a wakeup.
+pub fn block_current() -> Id {
+    with_scheduler(|s| match s.current() {
+        Some(process) => {
+            process.state = State::Waiting;
+            process.id
+        }
+        None => 0,
+    })
+}
+
+/// Returns `true` if the process `id` exists and hasn't exited.
+pub fn is_alive(id: Id) -> bool {
+    with_scheduler(|s| s.find(id).map_or(false, |p| p.state != State::Zombie))
+}
+
+/// Makes the waiting process `id` ready to run. Returns `false` if it does
+/// not exist or was not waiting.
+pub fn wake(id: Id) -> bool {
+    with_scheduler(|s| match s.find(id) {
+        Some(process) => {
+            if process.state != State::Waiting {
+                return false;
+            }
+
+            process.state = State::Ready;
+            true
+        }
+        None => false,
+    })
+}
+
+/// Returns an identifier for the running process's address space, the same
+/// for every process sharing it, or `None` for a kernel process.
+pub fn current_space_id() -> Option<u64> {
+    with_scheduler(|s| s.current().and_then(|p| p.page_table()).map(|table| table.base()))
+}
+
+/// Resolves a page fault with status `status` on `access` to the user
+/// address `addr` in the running process. See
+/// `AddressSpace::handle_fault()`.
+pub fn handle_fault(addr: usize, access: Access, status: FaultStatus) -> Result<(), FaultError> {
+    with_scheduler(|s| match s.current().and_then(|p| p.address_space_mut()) {
+        Some(space) => space.handle_fault(addr, access, status),
+        None => Err(FaultError::Unmapped),
+    })
+}
+
+/// Maps any demand-paged pages of `addr..addr + len` in the running
+/// process. Returns `false` if some page couldn't be mapped. See
+/// `AddressSpace::populate()`.
+pub fn populate(addr: usize, len: usize, access: Access) -> bool {
+    with_scheduler(|s| match s.current().and_then(|p| p.address_space_mut()) {
+        Some(space) => space.populate(addr, len, access),
+        None => false,
+    })
+}
+
+/// Calls `f` with the running process's descriptor table and returns its
+/// result.
+pub fn with_files<R, F: FnOnce(&mut Files) -> R>(f: F) -> Option<R> {
+    with_scheduler(|s| s.current().map(|p| f(p.files_mut())))
+}
+
+/// Returns the running process's current directory.
+pub fn cwd() -> PathBuf {
+    with_scheduler(|s| s.current().map_or(PathBuf::from("/"), |p| p.cwd.clone()))
+}
+
+/// Sets the running process's current directory to the canonical `path`.
+/// Nothing checks that it names a directory; that is up to the caller.
+pub fn set_cwd(path: PathBuf) {
+    with_scheduler(|s| if let Some(p) = s.current() {
+        p.cwd = path;
+    });
+}
+
+/// Returns the running process's session, if it has one. Always `None` in
+/// an interrupt handler, which runs on behalf of no process, and on a core
+/// holding the scheduler lock, so that console output from either goes to
+/// the sinks.
+pub fn session() -> Option<Arc<Session>> {
+    if traps::in_interrupt() || SCHEDULER.is_held_by_current_core() {
+        return None;
+    }
+    SCHEDULER.lock().as_mut().and_then(|s| s.current().and_then(|p| p.session.clone()))
+}
+
+/// Makes `session` the running process's session, or clears it.
+pub fn set_session(session: Option<Arc<Session>>) {
+    with_scheduler(|s| if let Some(p) = s.current() {
+        p.session = session;
+    });
+}
+
+/// Returns `path` resolved against the running process's current
+/// directory. See `vfs::canonicalize()`.
+pub fn resolve<P: AsRef<Path>>(path: P) -> PathBuf {
+    vfs::canonicalize(&cwd(), path.as_ref())
+}
+
+/// Moves the running user process's program break to `addr`, or leaves it
+/// if `addr` is 0, and returns the break. Returns `None` if the running
+/// process is a kernel process or the break can't be moved there. See
+/// `AddressSpace::set_brk()`.
+pub fn set_brk(addr: usize) -> Option<usize> {
+    with_scheduler(|s| {
+        let space = s.current().and_then(|p| p.address_space_mut())?;
+        if addr != 0 && !space.set_brk(addr) {
+            return None;
+        }
+        Some(space.brk())
+    })
+}
+
+/// Calls `f` with the process whose kernel stack starts at `bottom` and
+/// returns its result, or `None` if there is no such process or the
+/// scheduler is locked. For reporting an overflow of the stack, so it
+/// doesn't wait for the lock, which the overflowing code may hold, or
+/// allocate.
+pub fn with_stack_owner<R, F: FnOnce(&Process) -> R>(bottom: usize, f: F) -> Option<R> {
+    let guard = SCHEDULER.try_lock()?;
+    let scheduler = guard.as_ref()?;
+    let owns = |p: &&Process| p.stack().map_or(false, |stack| stack.bottom() == bottom);
+    let owner = scheduler.cores.iter().filter_map(|c| {
+        let current = c.current.iter().chain(c.switched_out.iter());
+        current.chain(c.queue.iter()).map(|p| &**p).find(&owns)
+    }).next()?;
+    Some(f(owner))
+}
+
+/// Calls `f` with a snapshot of every process, running processes first.
+pub fn for_each<F: FnMut(&process::Info)>(mut f: F) {
+    let now = timer::current_time();
+    let infos = with_scheduler(|s| s.infos(now));
+
+    for info in infos.iter() {
+        f(info);
+    }
+}
+
+/// Returns a snapshot of the process `id`, or `None` if there is no such
+/// process.
+pub fn info(id: Id) -> Option<process::Info> {
+    let now = timer::current_time();
+    with_scheduler(|s| s.infos(now)).into_iter().find(|info| info.id == id)
+}
+
+/// Returns the statistics of the slab cache processes are kept in.
+pub fn slab_stats() -> SlabStats {
+    PROCESSES.lock().stats()
+}
+
+/// Records that the running process on this core trapped from EL0 into the
+/// kernel. Called on entry to every exception taken from EL0.
+pub fn enter_kernel() {
+    let core = aarch64::affinity();
+    let now = timer::current_time() as usize;
+    if AT_EL0[core].swap(false, Ordering::Relaxed) {
+        let since = now.saturating_sub(MODE_CHANGED[core].load(Ordering::Relaxed));
+        USER_TIME[core].fetch_add(since, Ordering::Relaxed);
+    }
+    MODE_CHANGED[core].store(now, Ordering::Relaxed);
+}
+
+/// Records that this core is returning to EL0. Called on the way out of
+/// every exception that returns there.
+pub fn leave_kernel() {
+    let core = aarch64::affinity();
+    MODE_CHANGED[core].store(timer::current_time() as usize, Ordering::Relaxed);
+    AT_EL0[core].store(true, Ordering::Relaxed);
+}
+
+/// Returns the microseconds the process running on `core` has spent at EL0
+/// since it was switched to, up to `now`.
+fn running_user_time(core: usize, now: u64) -> u64 {
+    let mut user = USER_TIME[core].load(Ordering::Relaxed) as u64;
+    if AT_EL0[core].load(Ordering::Relaxed) {
+        user += now.saturating_sub(MODE_CHANGED[core].load(Ordering::Relaxed) as u64);
+    }
+    user
+}
+
+/// Asks for the process running on this core to be switched out the next
+/// time an exception returns. Called from the timer tick.
+pub fn request_resched() {
+    NEED_RESCHED[aarch64::affinity()].store(true, Ordering::Relaxed);
+}
+
+/// Returns `true` if a switch was requested on this core and hasn't
+/// happened yet.
+pub fn resched_pending() -> bool {
+    NEED_RESCHED[aarch64::affinity()].load(Ordering::Relaxed)
+}
+
+/// Called on the way out of every exception with the trap frame `tf` of the
+/// interrupted process. If a switch was requested, switches to the next
+/// ready process and returns its frame; the interrupted process stays ready
+/// unless it exited or is waiting. Otherwise returns `tf`. While the
+/// interrupted process has preemption disabled, the request is left
+/// pending; see `preempt`.
+///
+/// If the running process can't continue and no other process is ready,
+/// this switches to the core's idle process, or if it can't be created,
+/// waits here for an interrupt to make a process ready.
+pub fn schedule(tf: *mut TrapFrame) -> *mut TrapFrame {
+    if preempt::disabled() {
+        return tf;
+    }
+    if !NEED_RESCHED[aarch64::affinity()].swap(false, Ordering::Relaxed) {
+        return tf;
+    }
+
+    loop {
+        match with_scheduler(|s| s.switch(tf)) {
+            Some(frame) => return frame,
+            None => {
+                // Let the interrupt that readies a process be taken. It is
+                // nested, so it returns here rather than switching.
+                aarch64::irq_enable();
+                aarch64::wfi();
+                aarch64::irq_save();
+            }
+        }
+    }
+}
+
+/// The body of each core's idle process: waits for interrupts, saving
+/// power, until the tick finds another process ready and switches to it.
+fn idle_loop() -> ! {
+    loop {
+        aarch64::wfi();
+    }
+}
+
+/// Called by `vectors.S` once it has switched to the stack of the process
+/// `handle_exception()` returned, before restoring it. Queues the processes
+/// this core switched out, which it is no longer running on, so that any
+/// core may run or reap them.
+#[no_mangle]
+pub extern fn finish_switch() {
+    let exited = with_scheduler(|s| {
+        let core = s.core();
+        let exited = core.switched_out.iter().any(|p| p.state == State::Zombie);
+        let switched_out = ::std::mem::replace(&mut core.switched_out, Vec::new());
+        core.queue.extend(switched_out);
+        exited
+    });
+
+    if exited {
+        CHILD_EXITED.wake_all();
+    }
+}
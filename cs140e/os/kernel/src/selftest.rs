@@ -0,0 +1,294 @@
+//! A self-test of the board, for checking a newly assembled one: the heap,
+//! the system timer, the SD card, and, with wiring for them, the mini UART
+//! and a pair of GPIO pins.
+//!
+//! The `selftest` builtin runs it, as does boot when the command line has
+//! `selftest` on it. Two checks need a jumper, and are skipped unless asked
+//! for: the UART loopback needs TXD (GPIO 14) wired to RXD (GPIO 15), so it
+//! can't run with the console cable attached, and the GPIO loopback needs
+//! the output pin of the pair wired to the input pin. At boot they are
+//! asked for with `selftest.uart` and `selftest.gpio=OUT,IN`.
+//!
+//! The SD card check only reads, so that a card with data on it can be
+//! tested.
+
+use std::fmt;
+
+use pi::gpio::Gpio;
+use pi::timer;
+use pi::uart::MiniUart;
+
+use aarch64;
+use boot::BootInfo;
+use console::{self, kprint, kprintln, CONSOLE};
+use fs::block::BlockDevice;
+use fs::sd::Sd;
+
+/// The sizes the allocator check allocates, each for several blocks.
+const ALLOC_SIZES: [usize; 8] = [1, 8, 24, 100, 512, 4096, 65536, 1 << 20];
+const ALLOCS_PER_SIZE: usize = 4;
+
+/// The times the timer check reads the system timer in a row.
+const TIMER_READS: usize = 100_000;
+
+/// How long the timer check sleeps, in microseconds, and how far the
+/// generic timer may disagree about it, in percent.
+const TIMER_SLEEP_US: u64 = 10_000;
+const TIMER_TOLERANCE: u64 = 5;
+
+/// The sectors the SD card check reads twice: the MBR, and some after it.
+const SD_SECTORS: [u64; 4] = [0, 1, 63, 2048];
+
+/// The bytes the UART loopback check sends, and how long it waits for each
+/// to come back, in milliseconds.
+const UART_PATTERN: [u8; 8] = [0x55, 0xAA, 0x00, 0xFF, 0x0F, 0xF0, b'\r', b'\n'];
+const UART_TIMEOUT_MS: u32 = 10;
+
+/// The times the GPIO loopback check toggles the output pin, and how long it
+/// lets the level settle before reading the input pin, in microseconds.
+const GPIO_TOGGLES: usize = 8;
+const GPIO_SETTLE_US: u64 = 10;
+
+/// What to test beyond what needs no wiring.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Options {
+    /// Whether TXD is wired to RXD.
+    pub uart: bool,
+    /// The `(output, input)` GPIO pins wired together, if any.
+    pub gpio: Option<(u8, u8)>,
+}
+
+impl Options {
+    /// Returns the options the kernel command line gives.
+    pub fn from_boot(info: &BootInfo) -> Options {
+        Options {
+            uart: info.has_flag("selftest.uart"),
+            gpio: info.param("selftest.gpio").and_then(|pins| parse_pins(pins).ok()),
+        }
+    }
+}
+
+/// Parses a GPIO pin pair written `OUT,IN`. Pins 14 and 15 are the mini
+/// UART's and are refused, as is a pin paired with itself.
+pub fn parse_pins(pins: &str) -> Result<(u8, u8), &'static str> {
+    let mut parts = pins.splitn(2, ',').map(|pin| pin.parse::<u8>());
+    let (output, input) = match (parts.next(), parts.next()) {
+        (Some(Ok(output)), Some(Ok(input))) => (output, input),
+        _ => return Err("expected OUT,IN"),
+    };
+
+    if output > 53 || input > 53 {
+        Err("pins go up to 53")
+    } else if [14, 15].contains(&output) || [14, 15].contains(&input) {
+        Err("pins 14 and 15 are the UART's")
+    } else if output == input {
+        Err("the pins must differ")
+    } else {
+        Ok((output, input))
+    }
+}
+
+/// The result of a check.
+enum Outcome {
+    Passed,
+    Failed(String),
+    /// The check wasn't asked for; says how to ask.
+    Skipped(&'static str),
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Outcome::Passed => f.write_str("ok"),
+            Outcome::Failed(ref why) => write!(f, "FAILED: {}", why),
+            Outcome::Skipped(how) => write!(f, "skipped ({})", how),
+        }
+    }
+}
+
+/// How many checks passed, failed, and were skipped.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Summary {
+    pub passed: usize,
+    pub failed: usize,
+    pub skipped: usize,
+}
+
+/// Runs every check, printing each's outcome and then a summary, and
+/// returns the summary.
+pub fn run(options: &Options) -> Summary {
+    let checks: [(&str, fn(&Options) -> Outcome); 5] = [
+        ("allocator", check_allocator),
+        ("timer", check_timer),
+        ("SD card", check_sd),
+        ("UART loopback", check_uart),
+        ("GPIO loopback", check_gpio),
+    ];
+
+    let mut summary = Summary::default();
+    for &(name, check) in checks.iter() {
+        kprint!("selftest: {} ... ", name);
+        console::flush();
+        let outcome = check(options);
+        match outcome {
+            Outcome::Passed => summary.passed += 1,
+            Outcome::Failed(_) => summary.failed += 1,
+            Outcome::Skipped(_) => summary.skipped += 1,
+        }
+        kprintln!("{}", outcome);
+    }
+
+    kprintln!("selftest: {}: {} passed, {} failed, {} skipped",
+              if summary.failed == 0 { "PASS" } else { "FAIL" },
+              summary.passed, summary.failed, summary.skipped);
+    summary
+}
+
+/// Returns the byte the allocator check fills byte `i` of the `n`th block
+/// with.
+fn pattern(n: usize, i: usize) -> u8 {
+    (n.wrapping_mul(31) ^ i.wrapping_mul(7)) as u8
+}
+
+/// Allocates blocks of every size in `ALLOC_SIZES`, fills each with its own
+/// pattern, and checks that none was overwritten once they all exist. Then
+/// frees every other one, allocates again in the gaps, and checks again.
+fn check_allocator(_: &Options) -> Outcome {
+    let mut blocks: Vec<(usize, Vec<u8>)> = Vec::new();
+    let fill = |n: usize, size: usize| (n, (0..size).map(|i| pattern(n, i)).collect());
+    let verify = |blocks: &[(usize, Vec<u8>)]| {
+        for &(n, ref block) in blocks.iter() {
+            if let Some(i) = (0..block.len()).find(|&i| block[i] != pattern(n, i)) {
+                return Err(format!("byte {} of a {}-byte block at {:#x} overwritten",
+                                   i, block.len(), block.as_ptr() as usize));
+            }
+        }
+        Ok(())
+    };
+
+    let sizes = ALLOC_SIZES.iter().flat_map(|&size| vec![size; ALLOCS_PER_SIZE]);
+    for (n, size) in sizes.enumerate() {
+        blocks.push(fill(n, size));
+    }
+    if let Err(why) = verify(&blocks) {
+        return Outcome::Failed(why);
+    }
+
+    let mut n = blocks.len();
+    for i in (0..blocks.len()).filter(|i| i % 2 == 0) {
+        let size = blocks[i].1.len();
+        // Free the block first, so that the new one can take its place.
+        blocks[i] = (0, Vec::new());
+        blocks[i] = fill(n, size);
+        n += 1;
+    }
+    match verify(&blocks) {
+        Ok(()) => Outcome::Passed,
+        Err(why) => Outcome::Failed(why),
+    }
+}
+
+/// Checks that the system timer never goes backwards, and that it agrees
+/// with the ARM generic timer about how long a sleep took.
+fn check_timer(_: &Options) -> Outcome {
+    let mut last = timer::current_time();
+    for _ in 0..TIMER_READS {
+        let now = timer::current_time();
+        if now < last {
+            return Outcome::Failed(format!("went back from {} to {} us", last, now));
+        }
+        last = now;
+    }
+
+    // With IRQs masked, so that nothing runs in the middle of the sleep.
+    let daif = aarch64::irq_save();
+    let start = aarch64::timer_count();
+    timer::spin_sleep_us(TIMER_SLEEP_US);
+    let ticks = aarch64::timer_count() - start;
+    aarch64::irq_restore(daif);
+    let slept_us = ticks * 1_000_000 / aarch64::timer_frequency().max(1);
+    let tolerance = TIMER_SLEEP_US * TIMER_TOLERANCE / 100;
+    if slept_us + tolerance < TIMER_SLEEP_US || slept_us > TIMER_SLEEP_US + tolerance {
+        return Outcome::Failed(format!("a {} us sleep took {} us by the generic timer",
+                                       TIMER_SLEEP_US, slept_us));
+    }
+    Outcome::Passed
+}
+
+/// Reads each of `SD_SECTORS` twice and checks that the reads agree, and
+/// that the MBR has its signature.
+fn check_sd(_: &Options) -> Outcome {
+    let mut first = [0; 512];
+    let mut second = [0; 512];
+    for &sector in SD_SECTORS.iter() {
+        let read = Sd.read_blocks(sector, &mut first).and_then(|_| {
+            Sd.read_blocks(sector, &mut second)
+        });
+        if let Err(e) = read {
+            return Outcome::Failed(format!("reading sector {}: {}", sector, e));
+        }
+        if first[..] != second[..] {
+            return Outcome::Failed(format!("two reads of sector {} differ", sector));
+        }
+        if sector == 0 && first[510..] != [0x55, 0xAA] {
+            return Outcome::Failed("no MBR signature in sector 0".to_string());
+        }
+    }
+    Outcome::Passed
+}
+
+/// Sends `UART_PATTERN` through the mini UART and checks that each byte
+/// comes back. The console is held meanwhile, so that nothing else writes
+/// to the UART or reads from it.
+fn check_uart(options: &Options) -> Outcome {
+    if !options.uart {
+        return Outcome::Skipped("wire TXD to RXD and use -u");
+    }
+
+    let mut console = CONSOLE.lock();
+    console.flush();
+    let mut uart = MiniUart::new();
+    uart.set_read_timeout(UART_TIMEOUT_MS);
+    while uart.has_byte() {
+        uart.read_byte();
+    }
+
+    for &byte in UART_PATTERN.iter() {
+        uart.write_byte(byte);
+        if uart.wait_for_byte().is_err() {
+            return Outcome::Failed(format!("{:#04x} didn't come back", byte));
+        }
+        let echo = uart.read_byte();
+        if echo != byte {
+            return Outcome::Failed(format!("sent {:#04x}, got {:#04x}", byte, echo));
+        }
+    }
+    Outcome::Passed
+}
+
+/// Toggles the output pin of the pair and checks that the input pin
+/// follows. Both pins are left as inputs.
+fn check_gpio(options: &Options) -> Outcome {
+    let (output, input) = match options.gpio {
+        Some(pins) => pins,
+        None => return Outcome::Skipped("wire a pin pair and use -g OUT,IN"),
+    };
+
+    let mut out = Gpio::new(output).into_output();
+    let mut inp = Gpio::new(input).into_input();
+    let mut result = Outcome::Passed;
+    for i in 0..GPIO_TOGGLES {
+        let high = i % 2 == 0;
+        if high { out.set() } else { out.clear() }
+        timer::spin_sleep_us(GPIO_SETTLE_US);
+        if inp.level() != high {
+            result = Outcome::Failed(format!("pin {} read {} with pin {} {}", input,
+                                             if high { "low" } else { "high" }, output,
+                                             if high { "set" } else { "clear" }));
+            break;
+        }
+    }
+
+    Gpio::new(output).into_input();
+    result
+}
@@ -0,0 +1,121 @@
+//! ARM semihosting: calls that a debugger or emulator hosting the kernel
+//! handles on the host's behalf. QEMU handles them when started with
+//! `-semihosting`, which lets the kernel write to QEMU's standard output and
+//! end QEMU with an exit status, as automated test runs need.
+//!
+//! Whether anything handles the calls is found out at boot by making one
+//! through the probe in `ext/probe.S`, which fails instead of faulting when
+//! nothing does, as on a real board. If something does, console output is
+//! copied to the host from then on through the `semihosting` sink; earlier
+//! output is only in `dmesg`.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use console::{log_info, Sink, CONSOLE};
+use init::{kernel_init, Init, Stage};
+
+/// The operations used: write a NUL-terminated string to the host's
+/// console, return the last call's error number, and exit.
+const SYS_WRITE0: u64 = 0x04;
+const SYS_ERRNO: u64 = 0x13;
+const SYS_EXIT: u64 = 0x18;
+
+/// The reason `SYS_EXIT` gives for a program exiting on its own, with a
+/// status.
+const ADP_STOPPED_APPLICATION_EXIT: u64 = 0x2_0026;
+
+/// The bytes written to the host in a call.
+const WRITE_CHUNK: usize = 128;
+
+/// The name of the console sink that copies output to the host.
+pub const SINK: &str = "semihosting";
+
+/// Whether anything handles semihosting calls.
+static PRESENT: AtomicBool = AtomicBool::new(false);
+
+#[cfg(not(test))]
+extern "C" {
+    fn probe_semihosting(op: u64, param: u64, out: *mut u64) -> u64;
+}
+
+/// Makes semihosting call `op` with parameter `param` and returns its
+/// result, or `None` if nothing handles the call.
+#[cfg(not(test))]
+fn call(op: u64, param: u64) -> Option<u64> {
+    let mut result = 0;
+    match unsafe { probe_semihosting(op, param, &mut result) } {
+        0 => Some(result),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+fn call(_op: u64, _param: u64) -> Option<u64> {
+    None
+}
+
+/// Returns `true` if something, such as QEMU, handles semihosting calls.
+pub fn is_present() -> bool {
+    PRESENT.load(Ordering::Relaxed)
+}
+
+/// Writes `bytes` to the host's console. NUL bytes, which would end the
+/// string, and the `\r`s the console adds are dropped.
+pub fn write_bytes(bytes: &[u8]) {
+    if !is_present() {
+        return;
+    }
+
+    let mut chunk = [0; WRITE_CHUNK + 1];
+    let mut len = 0;
+    for &byte in bytes.iter().filter(|&&byte| byte != 0 && byte != b'\r') {
+        chunk[len] = byte;
+        len += 1;
+        if len == WRITE_CHUNK {
+            call(SYS_WRITE0, chunk.as_ptr() as u64);
+            len = 0;
+        }
+    }
+    if len > 0 {
+        chunk[len] = 0;
+        call(SYS_WRITE0, chunk.as_ptr() as u64);
+    }
+}
+
+/// Ends the host's process, QEMU's, with exit status `status`. Returns if
+/// nothing handles semihosting calls.
+pub fn exit(status: u32) {
+    if !is_present() {
+        return;
+    }
+
+    let block = [ADP_STOPPED_APPLICATION_EXIT, status as u64];
+    call(SYS_EXIT, block.as_ptr() as u64);
+}
+
+/// A console sink that writes to the host's console.
+struct HostConsole;
+
+impl Sink for HostConsole {
+    fn name(&self) -> &'static str {
+        SINK
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        write_bytes(bytes);
+    }
+}
+
+/// Finds out whether anything handles semihosting calls, and if so copies
+/// console output to the host.
+fn init() {
+    if call(SYS_ERRNO, 0).is_none() {
+        return;
+    }
+
+    PRESENT.store(true, Ordering::Relaxed);
+    let _ = CONSOLE.lock().add_sink(Box::new(HostConsole));
+    log_info!("semihosting: console output is copied to the host");
+}
+
+kernel_init!(SEMIHOSTING_INIT, Stage::Fs, "semihosting", Init::Plain(init));
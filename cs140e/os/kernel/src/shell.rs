@@ -1,7 +1,59 @@
-use stack_vec::StackVec;
-use console::{kprint, kprintln, CONSOLE};
+use console::{kprint, kprintln, set_foreground, ConsoleInput, CONSOLE, LineDiscipline, LineError};
+use console::{Session, UartInput};
+use console::log::{self, Level};
+use console::style;
+use {ALLOCATOR, FILE_SYSTEM};
+use clock::{self, DateTime};
+use crashlog;
+use selftest;
+use allocator;
+use irq;
+use gdbstub;
+use debug;
+use hwdebug::{self, Access};
+use profile;
+use trace;
+use fs;
+use fs::vfs::{self, File, Kind};
+use net::{self, arp, dhcp, icmp, sntp, tftp, Ipv4Addr, Link};
+use tick;
+use scheduler;
+use process;
+use smp;
+use pi::timer;
+use std::cmp::{max, Reverse};
+use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::str;
 
+/// The maximum number of bytes accepted on a single input line.
+pub const MAX_LINE_LEN: usize = 4096;
+
+/// The maximum number of arguments accepted in a single command.
+pub const MAX_ARGS: usize = 512;
+
+/// How many times `top` refreshes unless told otherwise.
+const TOP_REFRESHES: u32 = 10;
+
+/// How long `top` waits between refreshes, in milliseconds.
+const TOP_INTERVAL_MS: u64 = 1000;
+
+/// How many echo requests `ping` sends unless told otherwise.
+const PING_COUNT: u32 = 4;
+
+/// The number of data bytes in each of `ping`'s echo requests.
+const PING_DATA_LEN: usize = 56;
+
+/// How long `ping` waits for each reply, in milliseconds, and how often it
+/// looks for it.
+const PING_TIMEOUT_MS: u64 = 1000;
+const PING_POLL_MS: u64 = 10;
+
+/// The status of a command that isn't a builtin.
+const UNKNOWN_COMMAND_STATUS: i32 = 127;
+
+/// The status of a command line that doesn't parse.
+const SYNTAX_ERROR_STATUS: i32 = 2;
+
 /// Error type for `Command` parse failures.
 #[derive(Debug)]
 enum Error {
@@ -11,21 +63,23 @@ enum Error {
 
 /// A structure representing a single shell command.
 struct Command<'a> {
-    args: StackVec<'a, &'a str>
+    args: Vec<&'a str>
 }
 
 impl<'a> Command<'a> {
-    /// Parse a command from a string `s` using `buf` as storage for the
-    /// arguments.
+    /// Parse a command from a string `s`.
     ///
     /// # Errors
     ///
     /// If `s` contains no arguments, returns `Error::Empty`. If there are more
-    /// arguments than `buf` can hold, returns `Error::TooManyArgs`.
-    fn parse(s: &'a str, buf: &'a mut [&'a str]) -> Result<Command<'a>, Error> {
-        let mut args = StackVec::new(buf);
+    /// than `MAX_ARGS` arguments, returns `Error::TooManyArgs`.
+    fn parse(s: &'a str) -> Result<Command<'a>, Error> {
+        let mut args = Vec::new();
         for arg in s.split(' ').filter(|a| !a.is_empty()) {
-            args.push(arg).map_err(|_| Error::TooManyArgs)?;
+            if args.len() >= MAX_ARGS {
+                return Err(Error::TooManyArgs);
+            }
+            args.push(arg);
         }
 
         if args.is_empty() {
@@ -40,16 +94,1599 @@ impl<'a> Command<'a> {
         self.args[0]
     }
 
-    fn execute(&self) {
+    /// Runs this command and returns its exit status: 0 for success.
+    fn execute(&self, env: &mut Env) -> i32 {
+        let mut status = 0;
         match self.path() {
-            "echo" => {
-                let len = self.args.len();
-                for s in self.args[1..len-1].iter() {
-                    kprint!("{}", s);
+            "echo" => echo(&self.args[1..], env),
+            "set" => {
+                if self.args.len() == 1 {
+                    for &(ref name, ref value) in env.vars.iter() {
+                        kprintln!("{}={}", name, value);
+                    }
+                }
+
+                for arg in self.args[1..].iter() {
+                    match arg.find('=') {
+                        Some(i) => env.set(&arg[..i], &arg[i + 1..]),
+                        None => {
+                            kprintln!("set: expected NAME=VALUE, got '{}'", arg);
+                            status = 1;
+                        }
+                    }
+                }
+            }
+            "unset" => {
+                for arg in self.args[1..].iter() {
+                    env.unset(arg);
+                }
+            }
+            "meminfo" => meminfo(),
+            "leaks" => match self.args.get(1) {
+                Some(&"on") => ALLOCATOR.set_tracking(true),
+                Some(&"off") => ALLOCATOR.set_tracking(false),
+                None => ALLOCATOR.dump_live(),
+                Some(arg) => {
+                    kprintln!("leaks: unknown argument '{}'; usage: leaks [on|off]", arg);
+                    status = 1;
+                }
+            },
+            "heapcheck" => heapcheck(),
+            "loglevel" => loglevel(&self.args[1..]),
+            "sink" => sink(&self.args[1..]),
+            "logfmt" => logfmt(&self.args[1..]),
+            "color" => match self.args.get(1) {
+                Some(&"on") => style::set_colors_enabled(true),
+                Some(&"off") => style::set_colors_enabled(false),
+                None => kprintln!("{}", if style::colors_enabled() { "on" } else { "off" }),
+                Some(arg) => {
+                    kprintln!("color: unknown argument '{}'; usage: color [on|off]", arg);
+                    status = 1;
+                }
+            },
+            "run" => match self.args.get(1) {
+                Some(path) => status = run(path, &self.args[1..], env),
+                None => {
+                    kprintln!("usage: run PATH [ARGS...]");
+                    status = 1;
+                }
+            },
+            "date" => status = date(&self.args[1..]),
+            "cd" => status = cd(self.args.get(1).cloned().unwrap_or("/")),
+            "pwd" => kprintln!("{}", scheduler::cwd().display()),
+            "ls" => status = ls(&self.args[1..]),
+            "cat" => status = cat(&self.args[1..]),
+            "mount" => status = mount(&self.args[1..]),
+            "umount" => match self.args.get(1) {
+                Some(point) => if let Err(e) = FILE_SYSTEM.unmount(scheduler::resolve(point)) {
+                    kprintln!("umount: {}: {}", point, e);
+                    status = 1;
+                },
+                None => {
+                    kprintln!("usage: umount PATH");
+                    status = 1;
+                }
+            },
+            "format" => status = format(&self.args[1..]),
+            "fsck" => status = fsck(&self.args[1..]),
+            "ping" => status = ping(&self.args[1..]),
+            "ifconfig" => status = ifconfig(&self.args[1..]),
+            "tftp" => status = tftp(&self.args[1..]),
+            "sntp" => status = sntp(&self.args[1..]),
+            "arp" => for neighbor in arp::neighbors() {
+                let name = net::info(neighbor.index).map_or(String::new(), |info| info.name);
+                kprintln!("{} at {} on {} ({}s)", neighbor.addr, neighbor.mac, name,
+                          neighbor.expires_in);
+            },
+            "sync" => if let Err(e) = FILE_SYSTEM.sync() {
+                kprintln!("sync: {}", e);
+                status = 1;
+            },
+            "ps" => {
+                kprintln!("{:>5}  {:>4}  {:<8}  {:>10}  {:>8}  {:>9}  {}",
+                          "ID", "NICE", "STATE", "TIME (ms)", "SWITCHES", "MEM (KiB)", "NAME");
+                scheduler::for_each(|p| {
+                    kprintln!("{:>5}  {:>4}  {:<8}  {:>10}  {:>8}  {:>9}  {}", p.id, p.nice,
+                              format!("{:?}", p.state), p.cpu_time() / 1000, p.switches,
+                              p.memory / 1024, p.name);
+                });
+            }
+            "top" => match self.args.get(1).map(|arg| arg.parse::<u32>()) {
+                None => top(TOP_REFRESHES),
+                Some(Ok(count)) => top(count),
+                Some(Err(_)) => {
+                    kprintln!("usage: top [COUNT]");
+                    status = 1;
+                }
+            },
+            "kill" => {
+                // `kill -SIGNAL ID...` signals user processes; plain `kill`
+                // kills any process outright.
+                let (signal, ids) = match self.args.get(1) {
+                    Some(arg) if arg.starts_with('-') => {
+                        match process::Signal::from_name(&arg[1..]) {
+                            Some(signal) => (Some(signal), &self.args[2..]),
+                            None => {
+                                kprintln!("kill: unknown signal '{}'", &arg[1..]);
+                                return 1;
+                            }
+                        }
+                    }
+                    _ => (None, &self.args[1..]),
+                };
+
+                for arg in ids.iter() {
+                    let sent = |id| match signal {
+                        Some(signal) => scheduler::signal(id, signal),
+                        None => scheduler::kill(id),
+                    };
+
+                    match arg.parse::<process::Id>() {
+                        Ok(id) if sent(id) => continue,
+                        Ok(id) if signal.is_some() => kprintln!("kill: no user process {}", id),
+                        Ok(id) => kprintln!("kill: no process {}", id),
+                        Err(_) => kprintln!("kill: invalid process ID '{}'", arg),
+                    }
+                    status = 1;
+                }
+            }
+            "nice" => match &self.args[1..] {
+                [id, nice] => match (id.parse::<process::Id>(), nice.parse::<u8>()) {
+                    (Ok(id), Ok(nice)) if nice <= process::MAX_NICE => {
+                        if !scheduler::set_nice(id, nice) {
+                            kprintln!("nice: no process {}", id);
+                            status = 1;
+                        }
+                    }
+                    _ => {
+                        kprintln!("nice: expected a process ID and a level from 0 to {}", process::MAX_NICE);
+                        status = 1;
+                    }
+                },
+                _ => {
+                    kprintln!("usage: nice ID LEVEL");
+                    status = 1;
+                }
+            },
+            "tick" => match self.args.get(1) {
+                None => kprintln!("{} Hz, {} ticks", tick::tick_hz(), tick::ticks()),
+                Some(arg) => match arg.parse::<usize>() {
+                    Ok(hz) if hz > 0 && hz <= 1_000_000 => tick::set_tick_hz(hz),
+                    _ => {
+                        kprintln!("tick: invalid rate '{}'; usage: tick [HZ]", arg);
+                        status = 1;
+                    }
+                },
+            },
+            "irqstat" => {
+                irq::for_each_count(|int, count, handled| {
+                    kprintln!("{:<8} {:>10}{}", format!("{:?}", int), count,
+                              if handled { "" } else { "  (no handler)" });
+                });
+                kprintln!("{:<8} {:>10}", "spurious", irq::spurious());
+            }
+            "lockstat" => {
+                let sd = fs::sd::stats();
+                kprintln!("{:<8} {:>12}  {:>12}", "LOCK", "ACQUIRED", "CONTENDED");
+                kprintln!("{:<8} {:>12}  {:>12}", "sd", sd.acquisitions, sd.contentions);
+            }
+            "dmesg" => match self.args.get(1) {
+                None => match scheduler::session() {
+                    Some(session) => {
+                        let log = CONSOLE.lock().dmesg();
+                        session.write_bytes(&log);
+                    }
+                    None => CONSOLE.lock().replay_dmesg(),
+                },
+                Some(&"-c") => CONSOLE.lock().clear_dmesg(),
+                Some(arg) => {
+                    kprintln!("dmesg: unknown argument '{}'; usage: dmesg [-c]", arg);
+                    status = 1;
+                }
+            },
+            "gdb" => match self.args.len() {
+                1 if gdbstub::is_attached() => kprintln!("gdb: already attached"),
+                1 => gdbstub::attach(),
+                _ => {
+                    kprintln!("usage: gdb");
+                    status = 1;
+                }
+            },
+            "peek" => status = peek(&self.args[1..]),
+            "xxd" => status = xxd(&self.args[1..]),
+            "break" => status = hw_break(&self.args[1..]),
+            "watch" => status = watch(&self.args[1..]),
+            "profile" => status = profile(&self.args[1..]),
+            "trace" => status = trace(&self.args[1..]),
+            "crashlog" => status = crashlog(&self.args[1..]),
+            "selftest" => status = selftest(&self.args[1..]),
+            cmd => {
+                kprintln!("unknown command: {}", cmd);
+                status = UNKNOWN_COMMAND_STATUS;
+            }
+        }
+        status
+    }
+
+    /// Runs this command line as a list of commands joined by `&&` and
+    /// `||`, as in a POSIX shell: the command after `&&` runs only if the
+    /// last one that ran succeeded, and the one after `||` only if it
+    /// failed. The last status is recorded in `$?`. Each command's output
+    /// can be redirected; see `split_redirect()`.
+    fn execute_list(&self, env: &mut Env) {
+        let mut status = 0;
+        let mut run_next = true;
+        let mut rest = &self.args[..];
+        loop {
+            let end = rest.iter().position(|&arg| arg == "&&" || arg == "||").unwrap_or(rest.len());
+            if end == 0 {
+                kprintln!("syntax error near '{}'", rest.first().unwrap_or(&"newline"));
+                env.set("?", &SYNTAX_ERROR_STATUS.to_string());
+                return;
+            }
+
+            if run_next {
+                let (args, redirect) = match split_redirect(&rest[..end]) {
+                    Ok(split) => split,
+                    Err(token) => {
+                        kprintln!("syntax error near '{}'", token);
+                        env.set("?", &SYNTAX_ERROR_STATUS.to_string());
+                        return;
+                    }
+                };
+
+                let command = Command { args };
+                status = match redirect {
+                    Some(redirect) => redirect.run(|| command.execute(env)),
+                    None => command.execute(env),
+                };
+                env.set("?", &status.to_string());
+            }
+
+            if end == rest.len() {
+                return;
+            }
+            run_next = (rest[end] == "&&") == (status == 0);
+            rest = &rest[end + 1..];
+        }
+    }
+}
+
+/// Splits a trailing output redirection, `> PATH` or `>> PATH`, with or
+/// without the space, off the command `args`. Returns the token a syntax
+/// error is near if the redirection has no command or path, or is followed
+/// by anything.
+fn split_redirect<'a>(args: &[&'a str]) -> Result<(Vec<&'a str>, Option<Redirect<'a>>), &'a str> {
+    let i = match args.iter().position(|arg| arg.starts_with('>')) {
+        Some(i) => i,
+        None => return Ok((args.to_vec(), None)),
+    };
+
+    let token = args[i];
+    let (append, path) = match token.starts_with(">>") {
+        true => (true, &token[2..]),
+        false => (false, &token[1..]),
+    };
+    let (path, next) = match path.is_empty() {
+        true => (args.get(i + 1).cloned(), i + 2),
+        false => (Some(path), i + 1),
+    };
+
+    match (i, path, args.get(next)) {
+        (0, _, _) => Err(token),
+        (_, None, _) => Err("newline"),
+        (_, Some(_), Some(extra)) => Err(extra),
+        (_, Some(path), None) => Ok((args[..i].to_vec(), Some(Redirect { path, append }))),
+    }
+}
+
+/// Where a command's console output goes instead of the console: the file
+/// at `path`, replacing its contents unless `append` is set.
+struct Redirect<'a> {
+    path: &'a str,
+    append: bool,
+}
+
+impl<'a> Redirect<'a> {
+    /// Runs `command` with its console output written to the file, and
+    /// returns its status, or 1 if the file can't be written.
+    ///
+    /// Output is captured by running the command in a session of its own,
+    /// so only its output, and that of the processes it runs, goes to the
+    /// file; log messages and the console's sinks are left alone.
+    fn run<F: FnOnce() -> i32>(&self, command: F) -> i32 {
+        let mut file = match self.open() {
+            Ok(file) => file,
+            Err(e) => {
+                kprintln!("{}: {}", self.path, e);
+                return 1;
+            }
+        };
+
+        let (status, output) = capture(command);
+
+        // Sessions get a `\r` before every `\n`, which files shouldn't have.
+        let mut bytes = Vec::with_capacity(output.len());
+        for (i, &byte) in output.iter().enumerate() {
+            if !(byte == b'\r' && output.get(i + 1) == Some(&b'\n')) {
+                bytes.push(byte);
+            }
+        }
+
+        match file.write_all(&bytes).and_then(|_| file.flush()).and_then(|_| file.sync()) {
+            Ok(()) => status,
+            Err(e) => {
+                kprintln!("{}: {}", self.path, e);
+                1
+            }
+        }
+    }
+
+    /// Opens the file, creating it if it doesn't exist. Files can't be
+    /// truncated, so a non-empty file being replaced is removed and created
+    /// again.
+    fn open(&self) -> io::Result<Box<File>> {
+        let path = scheduler::resolve(self.path);
+        let mut file = match FILE_SYSTEM.open(&path) {
+            Ok(ref entry) if !self.append && entry.metadata().size > 0 => {
+                FILE_SYSTEM.remove(&path, false)?;
+                FILE_SYSTEM.create_file(&path)?
+            }
+            Ok(entry) => entry.into_file()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "is a directory"))?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => FILE_SYSTEM.create_file(&path)?,
+            Err(e) => return Err(e),
+        };
+
+        if self.append {
+            file.seek(SeekFrom::End(0))?;
+        }
+        Ok(file)
+    }
+}
+
+/// Runs `f` in a redirect session on the running process's terminal, and
+/// returns `f`'s result and the output captured.
+fn capture<T, F: FnOnce() -> T>(f: F) -> (T, Vec<u8>) {
+    let terminal = scheduler::session();
+    let session = Session::redirect("redirect", terminal.clone());
+    scheduler::set_session(Some(session.clone()));
+    let result = f();
+    scheduler::set_session(terminal);
+    (result, session.take_output(usize::max_value()))
+}
+
+/// The shell's environment: a list of `NAME=VALUE` variables.
+struct Env {
+    vars: Vec<(String, String)>
+}
+
+impl Env {
+    /// Returns a new, empty environment.
+    fn new() -> Env {
+        Env { vars: Vec::new() }
+    }
+
+    /// Returns the value of the variable `name`, if it is set.
+    fn get(&self, name: &str) -> Option<&str> {
+        self.vars.iter()
+            .find(|&&(ref n, _)| n == name)
+            .map(|&(_, ref v)| v.as_str())
+    }
+
+    /// Sets the variable `name` to `value`, replacing any previous value.
+    fn set(&mut self, name: &str, value: &str) {
+        match self.vars.iter_mut().find(|&&mut (ref n, _)| n == name) {
+            Some(&mut (_, ref mut v)) => *v = value.to_string(),
+            None => self.vars.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    /// Removes the variable `name`, if it is set.
+    fn unset(&mut self, name: &str) {
+        self.vars.retain(|&(ref n, _)| n != name);
+    }
+
+    /// Returns `s` with every `$NAME` and `${NAME}` replaced by the value of
+    /// the variable `NAME`, and `$?` by the status of the last command.
+    /// Unset variables expand to the empty string.
+    fn expand(&self, s: &str) -> String {
+        let mut out = String::new();
+        let mut rest = s;
+        while let Some(i) = rest.find('$') {
+            out.push_str(&rest[..i]);
+            rest = &rest[i + 1..];
+
+            let (name, next) = if rest.starts_with('?') {
+                ("?", &rest[1..])
+            } else if rest.starts_with('{') {
+                match rest.find('}') {
+                    Some(end) => (&rest[1..end], &rest[end + 1..]),
+                    None => (&rest[1..], ""),
+                }
+            } else {
+                let end = rest.find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                (&rest[..end], &rest[end..])
+            };
+
+            if name.is_empty() {
+                out.push('$');
+            } else {
+                out.push_str(self.get(name).unwrap_or(""));
+            }
+            rest = next;
+        }
+
+        out.push_str(rest);
+        out
+    }
+}
+
+/// Writes `bytes` to the running process's session, if it has one, or else
+/// to the console, emitting a `\r` before every `\n`.
+fn write_bytes(bytes: &[u8]) {
+    if let Some(session) = scheduler::session() {
+        for (i, line) in bytes.split(|&byte| byte == b'\n').enumerate() {
+            if i > 0 {
+                session.write_bytes(b"\r\n");
+            }
+            session.write_bytes(line);
+        }
+        return;
+    }
+
+    let mut console = CONSOLE.lock();
+    for &byte in bytes {
+        if byte == b'\n' {
+            console.write_byte(b'\r');
+        }
+        console.write_byte(byte);
+    }
+}
+
+/// Interprets the backslash escapes `\n`, `\t`, `\r`, `\0`, `\\`, and
+/// `\xNN` in `s`, appending the resulting bytes to `out`. Unrecognized escapes
+/// are passed through unchanged.
+fn unescape(s: &str, out: &mut Vec<u8>) {
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' || i + 1 == bytes.len() {
+            out.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+
+        i += 2;
+        match bytes[i - 1] {
+            b'n' => out.push(b'\n'),
+            b't' => out.push(b'\t'),
+            b'r' => out.push(b'\r'),
+            b'0' => out.push(0),
+            b'\\' => out.push(b'\\'),
+            b'x' => {
+                let digits = s[i..].bytes().take(2)
+                    .take_while(|b| (*b as char).is_digit(16))
+                    .count();
+                match u8::from_str_radix(&s[i..i + digits], 16) {
+                    Ok(byte) => out.push(byte),
+                    Err(_) => out.extend_from_slice(b"\\x"),
+                }
+                i += digits;
+            }
+            other => {
+                out.push(b'\\');
+                out.push(other);
+            }
+        }
+    }
+}
+
+/// The `echo` builtin. Prints `args` separated by spaces.
+///
+/// Leading `-n` suppresses the trailing newline, `-e` enables interpretation
+/// of backslash escapes, and `-E` disables it again. Flags may be combined, as
+/// in `-ne`. Variables are expanded in every argument.
+fn echo(args: &[&str], env: &Env) {
+    let (mut newline, mut escapes) = (true, false);
+    let mut args = args;
+    while let Some(&arg) = args.first() {
+        let is_flag = arg.len() > 1 && arg.starts_with('-')
+            && arg[1..].chars().all(|c| "neE".contains(c));
+        if !is_flag {
+            break;
+        }
+
+        for flag in arg[1..].chars() {
+            match flag {
+                'n' => newline = false,
+                'e' => escapes = true,
+                _ => escapes = false,
+            }
+        }
+        args = &args[1..];
+    }
+
+    let mut out = Vec::new();
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            out.push(b' ');
+        }
+
+        let arg = env.expand(arg);
+        match escapes {
+            true => unescape(&arg, &mut out),
+            false => out.extend_from_slice(arg.as_bytes()),
+        }
+    }
+
+    if newline {
+        out.push(b'\n');
+    }
+
+    write_bytes(&out);
+}
+
+/// The `meminfo` builtin. Prints the allocator's statistics and those of
+/// each slab cache.
+fn meminfo() {
+    let stats = ALLOCATOR.stats();
+    kprintln!("in use:   {} bytes (peak {} bytes)", stats.bytes_in_use, stats.peak_bytes);
+    kprintln!("allocs:   {} ({} failed)", stats.alloc_calls, stats.failed_allocs);
+    kprintln!("deallocs: {}", stats.dealloc_calls);
+    kprintln!("pages:    {} free of {} ({} B each)",
+              stats.free_pages, stats.total_pages, allocator::PAGE_SIZE);
+    kprintln!("free blocks:");
+    for (i, &count) in stats.per_bin_frees.iter().enumerate() {
+        if count > 0 {
+            kprintln!("  {:>10} B: {}", allocator::block_size(i), count);
+        }
+    }
+    kprintln!("slab caches:");
+    for cache in [scheduler::slab_stats(), process::files::slab_stats()].iter() {
+        kprintln!("  {:>10}: {} in use (peak {}), {} free, {} slabs of {} B",
+                  cache.name, cache.objects_in_use, cache.peak_objects, cache.free_objects,
+                  cache.slabs, cache.slab_size);
+    }
+}
+
+/// The `heapcheck` builtin. Checks the red zones around every live
+/// allocation.
+#[cfg(feature = "heap-debug")]
+fn heapcheck() {
+    match ALLOCATOR.check_heap() {
+        Ok(count) => kprintln!("heap ok: {} allocations checked", count),
+        Err(c) => match c.block_size {
+            Some(size) => kprintln!("heap corrupt: {:#x} overwritten in block {:#x} \
+                                     (size class {})", c.addr, c.block, size),
+            None => kprintln!("heap corrupt: header of block {:#x} overwritten", c.block),
+        },
+    }
+}
+
+/// The `heapcheck` builtin. Red zones require the `heap-debug` feature.
+#[cfg(not(feature = "heap-debug"))]
+fn heapcheck() {
+    kprintln!("heapcheck: kernel built without the heap-debug feature");
+}
+
+/// The `loglevel` builtin.
+///
+/// With no arguments, prints the global log level and every per-module
+/// override. `loglevel LEVEL` sets the global level, `loglevel MODULE LEVEL`
+/// overrides it for modules whose path starts with `MODULE`, and
+/// `loglevel MODULE -` removes the override.
+fn loglevel(args: &[&str]) {
+    match args {
+        [] => {
+            kprintln!("global: {}", log::level());
+            log::for_each_override(|module, level| kprintln!("{}: {}", module, level));
+        }
+        [level] => match level.parse::<Level>() {
+            Ok(level) => log::set_level(level),
+            Err(_) => kprintln!("loglevel: unknown level '{}'", level),
+        },
+        [module, "-"] => log::set_module_level(module, None),
+        [module, level] => match level.parse::<Level>() {
+            Ok(level) => log::set_module_level(module, Some(level)),
+            Err(_) => kprintln!("loglevel: unknown level '{}'", level),
+        },
+        _ => kprintln!("usage: loglevel [MODULE] [error|warn|info|debug|trace|-]"),
+    }
+}
+
+/// The `sink` builtin. With no arguments, lists the console's output sinks.
+/// `sink NAME on|off` enables or disables the sink named `NAME`.
+fn sink(args: &[&str]) {
+    let enabled = match args {
+        [] => {
+            // Printing locks the console, so collect the list first.
+            let mut sinks = Vec::new();
+            CONSOLE.lock().for_each_sink(|name, enabled| sinks.push((name.to_string(), enabled)));
+            for (name, enabled) in sinks {
+                kprintln!("{:<12} {}", name, if enabled { "on" } else { "off" });
+            }
+            return;
+        }
+        [_, "on"] => true,
+        [_, "off"] => false,
+        _ => return kprintln!("usage: sink [NAME on|off]"),
+    };
+
+    if !CONSOLE.lock().set_sink_enabled(args[0], enabled) {
+        kprintln!("sink: no sink named '{}'", args[0]);
+    }
+}
+
+/// The `logfmt` builtin. With no arguments, shows which prefixes are added to
+/// log lines. `logfmt time on|off` toggles the microseconds-since-boot
+/// timestamp and `logfmt core on|off` toggles the core ID.
+fn logfmt(args: &[&str]) {
+    let on_off = |enabled| if enabled { "on" } else { "off" };
+    match args {
+        [] => {
+            kprintln!("date: {}", on_off(log::dates()));
+            kprintln!("time: {}", on_off(log::timestamps()));
+            kprintln!("core: {}", on_off(log::core_ids()));
+        }
+        ["date", "on"] => log::set_dates(true),
+        ["date", "off"] => log::set_dates(false),
+        ["time", "on"] => log::set_timestamps(true),
+        ["time", "off"] => log::set_timestamps(false),
+        ["core", "on"] => log::set_core_ids(true),
+        ["core", "off"] => log::set_core_ids(false),
+        _ => kprintln!("usage: logfmt [date|time|core on|off]"),
+    }
+}
+
+/// The `date` builtin. With no arguments, prints the date and time; with
+/// `-s DATE`, sets them from `YYYY-MM-DD HH:MM:SS` or `@SECONDS` since the
+/// UNIX epoch. Returns the command's status.
+fn date(args: &[&str]) -> i32 {
+    if args.is_empty() {
+        let now = DateTime::from_epoch(clock::now_us() / 1_000_000);
+        match clock::is_set() {
+            true => kprintln!("{} UTC", now),
+            false => kprintln!("{} UTC (clock not set; use date -s)", now),
+        }
+        return 0;
+    }
+
+    let text = args[1..].join(" ");
+    let secs = match (args[0], text.starts_with('@')) {
+        ("-s", true) => text[1..].parse::<u64>().ok(),
+        ("-s", false) => text.parse::<DateTime>().ok().and_then(|date| date.to_epoch()),
+        _ => {
+            kprintln!("usage: date [-s YYYY-MM-DD HH:MM:SS | -s @SECONDS]");
+            return 1;
+        }
+    };
+
+    match secs.and_then(|secs| secs.checked_mul(1_000_000)) {
+        Some(us) if clock::set_us(us) => 0,
+        _ => {
+            kprintln!("date: invalid date '{}'", text);
+            1
+        }
+    }
+}
+
+/// The `ls` builtin. Lists the entries of the directory at `PATH`, or the
+/// current directory, directories with a trailing `/`, or names `PATH` if
+/// it is a file. With `-l`, lists each entry's attributes, size, and
+/// modification time too.
+/// Returns the command's status.
+fn ls(args: &[&str]) -> i32 {
+    let (long, path) = match args {
+        [] => (false, "."),
+        ["-l"] => (true, "."),
+        ["-l", path] => (true, *path),
+        [path] if !path.starts_with('-') => (false, *path),
+        _ => {
+            kprintln!("usage: ls [-l] [PATH]");
+            return 1;
+        }
+    };
+
+    let entry = match FILE_SYSTEM.open(scheduler::resolve(path)) {
+        Ok(entry) => entry,
+        Err(e) => {
+            kprintln!("ls: {}: {}", path, e);
+            return 1;
+        }
+    };
+
+    if entry.metadata().kind == Kind::File {
+        print_entry(&*entry, long);
+        return 0;
+    }
+
+    match entry.into_dir().map_or(Ok(Vec::new()), |dir| dir.entries()) {
+        Ok(entries) => {
+            for entry in entries.iter() {
+                print_entry(&**entry, long);
+            }
+            0
+        }
+        Err(e) => {
+            kprintln!("ls: {}: {}", path, e);
+            1
+        }
+    }
+}
+
+/// Prints a line of `ls` for `entry`. The long form is its kind, `d` or
+/// `-`, and its read-only, hidden, and system attributes, `r`, `h`, and `s`
+/// or `-`; its size; when it was last modified; and its name.
+fn print_entry(entry: &vfs::Entry, long: bool) {
+    let metadata = entry.metadata();
+    let suffix = if metadata.kind == Kind::Dir { "/" } else { "" };
+    if !long {
+        return kprintln!("{}{}", entry.name(), suffix);
+    }
+
+    let flag = |set, c| if set { c } else { '-' };
+    let modified = metadata.modified.map_or("-".to_string(), |date| date.to_string());
+    kprintln!("{}{}{}{}  {:>10}  {:<19}  {}{}",
+              flag(metadata.kind == Kind::Dir, 'd'), flag(metadata.read_only, 'r'),
+              flag(metadata.hidden, 'h'), flag(metadata.system, 's'),
+              metadata.size, modified, entry.name(), suffix);
+}
+
+/// The `format` builtin. `format sdN fat32 [LABEL]` formats partition `N`
+/// of the SD card as an empty FAT32 file system, erasing whatever was on
+/// it. Returns the command's status.
+fn format(args: &[&str]) -> i32 {
+    let (device, label) = match args {
+        [device, "fat32"] => (*device, ""),
+        [device, "fat32", label] => (*device, *label),
+        _ => {
+            kprintln!("usage: format sdN fat32 [LABEL]");
+            return 1;
+        }
+    };
+
+    let number = match device.starts_with("sd") {
+        true => device[2..].parse::<usize>().ok(),
+        false => None,
+    };
+    let result = match number {
+        Some(number) => FILE_SYSTEM.format_fat32(number, label),
+        None => Err(io::Error::new(io::ErrorKind::NotFound, "no such device")),
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            kprintln!("format: {}: {}", device, e);
+            1
+        }
+    }
+}
+
+/// The `mount` builtin. With no arguments, lists every mount; `mount sdN
+/// PATH` mounts the file system on partition `N` of the SD card at `PATH`.
+/// Returns the command's status.
+fn mount(args: &[&str]) -> i32 {
+    let (device, point) = match args {
+        [] => {
+            FILE_SYSTEM.for_each_mount(|point, kind, source| {
+                kprintln!("{} on {} type {}", source, point.display(), kind);
+            });
+            return 0;
+        }
+        [device, point] => (*device, *point),
+        _ => {
+            kprintln!("usage: mount [sdN PATH]");
+            return 1;
+        }
+    };
+
+    let number = match device.starts_with("sd") {
+        true => device[2..].parse::<usize>().ok(),
+        false => None,
+    };
+    let result = match number {
+        Some(number) => FILE_SYSTEM.mount_partition(number, scheduler::resolve(point)),
+        None => Err(io::Error::new(io::ErrorKind::NotFound, "no such device")),
+    };
+
+    match result {
+        Ok(_) => 0,
+        Err(e) => {
+            kprintln!("mount: {}: {}", device, e);
+            1
+        }
+    }
+}
+
+/// The `fsck` builtin. `fsck [-r] sdN` checks the FAT32 file system on
+/// partition `N` of the SD card and prints what is wrong with it, repairing
+/// it with `-r`. Returns the command's status: 0 if nothing is left wrong.
+fn fsck(args: &[&str]) -> i32 {
+    let (device, repair) = match args {
+        [device] => (*device, false),
+        ["-r", device] => (*device, true),
+        _ => {
+            kprintln!("usage: fsck [-r] sdN");
+            return 1;
+        }
+    };
+
+    let number = match device.starts_with("sd") {
+        true => device[2..].parse::<usize>().ok(),
+        false => None,
+    };
+    let result = match number {
+        Some(number) => FILE_SYSTEM.check_fat32(number, repair),
+        None => Err(io::Error::new(io::ErrorKind::NotFound, "no such device")),
+    };
+
+    let report = match result {
+        Ok(report) => report,
+        Err(e) => {
+            kprintln!("fsck: {}: {}", device, e);
+            return 1;
+        }
+    };
+
+    let mut left = 0;
+    for problem in report.problems.iter() {
+        let fixed = report.repaired && problem.repairable();
+        kprintln!("{}{}", problem, if fixed { " (repaired)" } else { "" });
+        if !fixed {
+            left += 1;
+        }
+    }
+    kprintln!("{}: {} files, {} directories, {}/{} clusters", device, report.files,
+              report.dirs, report.used, report.clusters);
+    match left {
+        0 => 0,
+        _ => 1,
+    }
+}
+
+/// The `ifconfig` builtin. Shows every network interface, or only the one
+/// named by the argument: its link, addresses, DHCP lease, and counts.
+/// Returns the command's status.
+fn ifconfig(args: &[&str]) -> i32 {
+    let name = match args {
+        [] => None,
+        [name] => Some(*name),
+        _ => {
+            kprintln!("usage: ifconfig [NAME]");
+            return 1;
+        }
+    };
+
+    let mut shown = 0;
+    for info in (0..net::interface_count()).filter_map(net::info) {
+        if name.map_or(false, |name| name != info.name) {
+            continue;
+        }
+
+        shown += 1;
+        let link = match info.link {
+            Link::Ethernet => "ethernet",
+            Link::Ip => "ip",
+        };
+        kprintln!("{}: {}, {}, mtu {}", info.name, link, if info.up { "up" } else { "down" },
+                  info.mtu);
+        if info.link == Link::Ethernet {
+            kprintln!("    ether {}", info.mac);
+        }
+        match info.config {
+            Some(config) => {
+                kprint!("    inet {}/{}", config.addr, config.prefix_len());
+                if let Some(gateway) = config.gateway {
+                    kprint!(" gateway {}", gateway);
+                }
+                if let Some(dns) = config.dns {
+                    kprint!(" dns {}", dns);
                 }
-                kprintln!("{}", self.args[len-1]);
+                kprintln!();
+            }
+            None => kprintln!("    no address"),
+        }
+        if let Some(dhcp) = dhcp::status(info.index) {
+            kprint!("    dhcp {}", dhcp.state);
+            if let Some(server) = dhcp.server {
+                kprint!(" from {}", server);
+            }
+            if let Some(secs) = dhcp.expires_in {
+                kprint!(", lease {}s", secs);
+            }
+            kprintln!();
+        }
+        let stats = info.stats;
+        kprintln!("    rx {} frames, {} bytes; tx {} frames, {} bytes; {} errors",
+                  stats.rx_frames, stats.rx_bytes, stats.tx_frames, stats.tx_bytes, stats.errors);
+    }
+
+    match (name, shown) {
+        (Some(name), 0) => {
+            kprintln!("ifconfig: {}: no such interface", name);
+            1
+        }
+        _ => 0,
+    }
+}
+
+/// The `tftp` builtin. `tftp get SERVER FILE PATH` fetches `FILE` from the
+/// TFTP server at `SERVER` into the file at `PATH`, replacing it. Returns
+/// the command's status.
+fn tftp(args: &[&str]) -> i32 {
+    let (server, file, path) = match args {
+        ["get", server, file, path] => match server.parse::<Ipv4Addr>() {
+            Ok(server) => (server, *file, *path),
+            Err(_) => {
+                kprintln!("tftp: {}: not an IPv4 address", server);
+                return 1;
             }
-            cmd => { kprintln!("unknown command: {}", cmd); }
+        },
+        _ => {
+            kprintln!("usage: tftp get SERVER FILE PATH");
+            return 1;
+        }
+    };
+
+    let start = clock::monotonic_us();
+    let path_buf = scheduler::resolve(path);
+    let result = FILE_SYSTEM.open(&path_buf)
+        .and_then(|entry| match entry.metadata().kind {
+            Kind::Dir => Err(io::Error::new(io::ErrorKind::Other, "is a directory")),
+            Kind::File => FILE_SYSTEM.remove(&path_buf, false),
+        })
+        .or_else(|e| match e.kind() {
+            io::ErrorKind::NotFound => Ok(()),
+            _ => Err(e),
+        })
+        .and_then(|_| FILE_SYSTEM.create_file(&path_buf))
+        .and_then(|mut out| {
+            let len = tftp::get(server, file, &mut out)?;
+            out.flush()?;
+            out.sync()?;
+            Ok(len)
+        });
+
+    match result {
+        Ok(len) => {
+            let ms = ((clock::monotonic_us() - start) / 1000).max(1);
+            kprintln!("{} bytes in {} ms ({} KiB/s)", len, ms, len * 1000 / 1024 / ms);
+            0
+        }
+        Err(e) => {
+            kprintln!("tftp: {}: {}", file, e);
+            1
+        }
+    }
+}
+
+/// The `sntp` builtin. With no arguments, shows the time server and when
+/// the clock was last set from it; with `SERVER`, makes that the time
+/// server and sets the clock from it now. Returns the command's status.
+fn sntp(args: &[&str]) -> i32 {
+    let server = match args {
+        [] => {
+            let status = sntp::status();
+            match status.server {
+                Some(server) => kprintln!("server: {}", server),
+                None => kprintln!("server: none (use sntp SERVER or ntp= at boot)"),
+            }
+            match status.last {
+                Some((at, sample)) => kprintln!(
+                    "last set {}s ago, moved {} ms, round trip {} ms",
+                    (clock::monotonic_us() - at) / 1_000_000, sample.offset_us / 1000,
+                    sample.delay_us / 1000),
+                None => kprintln!("not set yet"),
+            }
+            return 0;
+        }
+        [server] => match server.parse::<Ipv4Addr>() {
+            Ok(server) => server,
+            Err(_) => {
+                kprintln!("sntp: {}: not an IPv4 address", server);
+                return 1;
+            }
+        },
+        _ => {
+            kprintln!("usage: sntp [SERVER]");
+            return 1;
+        }
+    };
+
+    sntp::set_server(Some(server));
+    match sntp::sync(server) {
+        Ok(sample) => {
+            kprintln!("{} UTC (moved {} ms, round trip {} ms)",
+                      DateTime::from_epoch(clock::now_us() / 1_000_000),
+                      sample.offset_us / 1000, sample.delay_us / 1000);
+            0
+        }
+        Err(e) => {
+            kprintln!("sntp: {}: {}", server, e);
+            1
+        }
+    }
+}
+
+/// The length `xxd` dumps unless given one.
+const XXD_LEN: usize = 256;
+
+/// Parses a number, in hex if it starts with `0x` and in decimal otherwise.
+fn parse_number(arg: &str) -> Option<usize> {
+    if arg.starts_with("0x") || arg.starts_with("0X") {
+        usize::from_str_radix(&arg[2..], 16).ok()
+    } else {
+        arg.parse().ok()
+    }
+}
+
+/// The `peek` builtin. `peek ADDR [SIZE]` reads the 1, 2, 4, or 8 bytes
+/// (4 unless given) at `ADDR` in a single load and prints them, or reports
+/// that the address can't be read. Returns the command's status.
+fn peek(args: &[&str]) -> i32 {
+    let (addr, size) = match args {
+        [addr] => (parse_number(addr), Some(4)),
+        [addr, size] => (parse_number(addr), parse_number(size)),
+        _ => (None, None),
+    };
+    let (addr, size) = match (addr, size) {
+        (Some(addr), Some(size)) if [1, 2, 4, 8].contains(&size) && addr % size == 0 => {
+            (addr, size)
+        }
+        _ => {
+            kprintln!("usage: peek ADDR [1|2|4|8], with ADDR aligned to the size");
+            return 1;
+        }
+    };
+
+    let value = match size {
+        1 => debug::try_read::<u8>(addr).map(|value| value as u64),
+        2 => debug::try_read::<u16>(addr).map(|value| value as u64),
+        4 => debug::try_read::<u32>(addr).map(|value| value as u64),
+        _ => debug::try_read::<u64>(addr),
+    };
+    match value {
+        Some(value) => {
+            kprintln!("{:#018x}: {:#0width$x} ({})", addr, value, value, width = size * 2 + 2);
+            0
+        }
+        None => {
+            kprintln!("peek: {:#x}: can't read", addr);
+            1
+        }
+    }
+}
+
+/// The `xxd` builtin. `xxd ADDR [LEN]` dumps the `LEN` bytes (`XXD_LEN`
+/// unless given) at `ADDR` in hex and ASCII, stopping at the first byte
+/// that can't be read. Returns the command's status.
+fn xxd(args: &[&str]) -> i32 {
+    let (addr, len) = match args {
+        [addr] => (parse_number(addr), Some(XXD_LEN)),
+        [addr, len] => (parse_number(addr), parse_number(len)),
+        _ => (None, None),
+    };
+    let (addr, len) = match (addr, len) {
+        (Some(addr), Some(len)) => (addr, len),
+        _ => {
+            kprintln!("usage: xxd ADDR [LEN]");
+            return 1;
+        }
+    };
+
+    match debug::dump_region(addr, len) {
+        Ok(()) => 0,
+        Err(at) => {
+            kprintln!("xxd: {:#x}: can't read", at);
+            1
+        }
+    }
+}
+
+/// The `break` builtin. With no arguments, lists the hardware breakpoints;
+/// `break ADDR` sets one at `ADDR`, and `break -d ADDR` clears it. Hits are
+/// reported on the console with the registers. Returns the command's status.
+fn hw_break(args: &[&str]) -> i32 {
+    let (clear, addr) = match args {
+        [] => {
+            for (n, bp) in hwdebug::breakpoints().iter().enumerate() {
+                if let Some(bp) = bp {
+                    kprintln!("{}: {:#018x}  {} hits", n, bp.addr, bp.hits);
+                }
+            }
+            return 0;
+        }
+        [addr] => (false, parse_number(addr)),
+        ["-d", addr] => (true, parse_number(addr)),
+        _ => (false, None),
+    };
+    let addr = match addr {
+        Some(addr) => addr,
+        None => {
+            kprintln!("usage: break [[-d] ADDR]");
+            return 1;
+        }
+    };
+
+    if clear {
+        if hwdebug::clear_breakpoint(addr) {
+            return 0;
+        }
+        kprintln!("break: no breakpoint at {:#x}", addr);
+        return 1;
+    }
+    match hwdebug::set_breakpoint(addr) {
+        Ok(()) => 0,
+        Err(e) => {
+            kprintln!("break: {:#x}: {}", addr, e);
+            1
+        }
+    }
+}
+
+/// The `watch` builtin. With no arguments, lists the hardware watchpoints;
+/// `watch ADDR [LEN] [r|w|rw]` watches the `LEN` bytes (8 unless given) at
+/// `ADDR` for reads, writes, or both (writes unless given), and `watch -d
+/// ADDR` stops watching them. Returns the command's status.
+fn watch(args: &[&str]) -> i32 {
+    if args.is_empty() {
+        for (n, wp) in hwdebug::watchpoints().iter().enumerate() {
+            if let Some(wp) = wp {
+                kprintln!("{}: {:#018x}  {} bytes  {:<10}  {} hits",
+                          n, wp.addr, wp.len, wp.access, wp.hits);
+            }
+        }
+        return 0;
+    }
+
+    if let ["-d", addr] = args {
+        return match parse_number(addr) {
+            Some(addr) if hwdebug::clear_watchpoint(addr) => 0,
+            Some(addr) => {
+                kprintln!("watch: no watchpoint at {:#x}", addr);
+                1
+            }
+            None => {
+                kprintln!("usage: watch -d ADDR");
+                1
+            }
+        };
+    }
+
+    let addr = parse_number(args[0]);
+    let mut len = Some(8);
+    let mut access = Access::Write;
+    for arg in &args[1..] {
+        match *arg {
+            "r" => access = Access::Read,
+            "w" => access = Access::Write,
+            "rw" => access = Access::ReadWrite,
+            arg => len = parse_number(arg),
+        }
+    }
+    let (addr, len) = match (addr, len) {
+        (Some(addr), Some(len)) if args.len() <= 3 => (addr, len),
+        _ => {
+            kprintln!("usage: watch [ADDR [LEN] [r|w|rw] | -d ADDR]");
+            return 1;
+        }
+    };
+
+    match hwdebug::set_watchpoint(addr, len, access) {
+        Ok(()) => 0,
+        Err(e) => {
+            kprintln!("watch: {:#x}: {}", addr, e);
+            1
+        }
+    }
+}
+
+/// The number of functions `profile report` lists unless given a count.
+const PROFILE_REPORT_LEN: usize = 20;
+
+/// The `profile` builtin. `profile start [HZ]` starts sampling every core
+/// `HZ` times a second (`profile::DEFAULT_HZ` unless given), `profile stop`
+/// stops, and `profile report [COUNT]` lists the `COUNT` functions the most
+/// samples were in, of the current or last run. Returns the command's
+/// status.
+fn profile(args: &[&str]) -> i32 {
+    match args {
+        ["start"] | ["start", _] => {
+            let hz = match args.get(1).map(|arg| arg.parse::<u32>()) {
+                None => profile::DEFAULT_HZ,
+                Some(Ok(hz)) => hz,
+                Some(Err(_)) => {
+                    kprintln!("profile: invalid rate '{}'", args[1]);
+                    return 1;
+                }
+            };
+            match profile::start(hz) {
+                Ok(()) => {
+                    kprintln!("profiling at {} Hz per core", hz);
+                    0
+                }
+                Err(e) => {
+                    kprintln!("profile: {}", e);
+                    1
+                }
+            }
+        }
+        ["stop"] => if profile::stop() {
+            0
+        } else {
+            kprintln!("profile: not running");
+            1
+        },
+        ["report"] | ["report", _] => {
+            let count = match args.get(1).map(|arg| arg.parse::<usize>()) {
+                None => PROFILE_REPORT_LEN,
+                Some(Ok(count)) => count,
+                Some(Err(_)) => {
+                    kprintln!("profile: invalid count '{}'", args[1]);
+                    return 1;
+                }
+            };
+            match profile::report() {
+                Some(report) => {
+                    show_profile(&report, count);
+                    0
+                }
+                None => {
+                    kprintln!("profile: no samples; use profile start");
+                    1
+                }
+            }
+        }
+        _ => {
+            kprintln!("usage: profile start [HZ] | stop | report [COUNT]");
+            1
+        }
+    }
+}
+
+/// Prints the `count` functions with the most samples in `report`.
+fn show_profile(report: &profile::Report, count: usize) {
+    let total = report.kernel + report.user;
+    kprintln!("{} samples over {} ms{}: {} kernel, {} user, {} dropped",
+              total, report.duration_us / 1000,
+              if profile::is_running() { " (running)" } else { "" },
+              report.kernel, report.user, report.dropped);
+    if total == 0 {
+        return;
+    }
+
+    kprintln!("{:>8} {:>6}  {}", "SAMPLES", "%", "FUNCTION");
+    for &(ref name, samples) in report.functions.iter().take(count) {
+        let percent = samples as f64 * 100.0 / total as f64;
+        kprintln!("{:>8} {:>5.1}%  {}", samples, percent, name);
+    }
+}
+
+/// The number of events `trace dump` prints unless given a count.
+const TRACE_DUMP_LEN: usize = 50;
+
+/// The `trace` builtin. `trace on` and `trace off` start and stop recording
+/// events, `trace clear` discards those recorded, and `trace dump [COUNT]`
+/// prints the last `COUNT` of them. With no arguments, says whether events
+/// are being recorded. Returns the command's status.
+fn trace(args: &[&str]) -> i32 {
+    match args {
+        [] => {
+            kprintln!("tracing is {}", if trace::is_enabled() { "on" } else { "off" });
+            0
+        }
+        ["on"] => {
+            trace::set_enabled(true);
+            0
+        }
+        ["off"] => {
+            trace::set_enabled(false);
+            0
+        }
+        ["clear"] => {
+            trace::clear();
+            0
+        }
+        ["dump"] | ["dump", _] => {
+            let count = match args.get(1).map(|arg| arg.parse::<usize>()) {
+                None => TRACE_DUMP_LEN,
+                Some(Ok(count)) => count,
+                Some(Err(_)) => {
+                    kprintln!("trace: invalid count '{}'", args[1]);
+                    return 1;
+                }
+            };
+            if trace::dump(count) == 0 {
+                kprintln!("trace: no events; use trace on");
+            }
+            0
+        }
+        _ => {
+            kprintln!("usage: trace [on | off | clear | dump [COUNT]]");
+            1
+        }
+    }
+}
+
+/// The `crashlog` builtin. `crashlog` shows the crash record the last panic
+/// saved to the SD card, and `crashlog -c` discards it. Returns the
+/// command's status.
+fn crashlog(args: &[&str]) -> i32 {
+    let result = match args {
+        [] => crashlog::read().map(|record| match record {
+            Some(record) => show_crash(&record),
+            None => kprintln!("no crash record"),
+        }),
+        ["-c"] => crashlog::clear(),
+        _ => {
+            kprintln!("usage: crashlog [-c]");
+            return 1;
+        }
+    };
+
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            kprintln!("crashlog: {}", e);
+            1
+        }
+    }
+}
+
+/// The `selftest` builtin. `selftest [-u] [-g OUT,IN]` checks the heap, the
+/// timer, and the SD card, and with `-u` the mini UART, whose TXD must be
+/// wired to its RXD, and with `-g` the GPIO pins `OUT` and `IN`, which must
+/// be wired together. Returns the command's status: 0 if no check failed.
+fn selftest(args: &[&str]) -> i32 {
+    let usage = || {
+        kprintln!("usage: selftest [-u] [-g OUT,IN]");
+        1
+    };
+
+    let mut options = selftest::Options::default();
+    let mut args = args.iter();
+    while let Some(&arg) = args.next() {
+        match arg {
+            "-u" => options.uart = true,
+            "-g" => match args.next().map(|pins| selftest::parse_pins(pins)) {
+                Some(Ok(pins)) => options.gpio = Some(pins),
+                Some(Err(e)) => {
+                    kprintln!("selftest: {}", e);
+                    return 1;
+                }
+                None => return usage(),
+            },
+            _ => return usage(),
+        }
+    }
+
+    if selftest::run(&options).failed == 0 { 0 } else { 1 }
+}
+
+/// Prints a crash record.
+fn show_crash(record: &crashlog::Record) {
+    kprintln!("panic on core {} at {}.{:06} s after boot:", record.core,
+              record.uptime_us / 1_000_000, record.uptime_us % 1_000_000);
+    kprint!("{}", record.message);
+    kprintln!("\nregisters:");
+    kprint!("{}", record.registers);
+    kprintln!("");
+    kprint!("{}", record.backtrace);
+    kprintln!("\nlast {} bytes of console output:", record.dmesg.len());
+    kprint!("{}", String::from_utf8_lossy(&record.dmesg));
+    kprintln!("");
+}
+
+/// The `ping` builtin. `ping [-c COUNT] IP` sends `COUNT` echo requests to
+/// `IP`, one a second, and prints each reply and a summary. Returns the
+/// command's status: 0 if any reply came.
+fn ping(args: &[&str]) -> i32 {
+    let (count, dst) = match args {
+        [dst] => (Ok(PING_COUNT), *dst),
+        ["-c", count, dst] => (count.parse::<u32>(), *dst),
+        _ => (Ok(0), ""),
+    };
+    let (count, dst) = match (count, dst.parse::<Ipv4Addr>()) {
+        (Ok(count), Ok(dst)) if count > 0 => (count, dst),
+        _ => {
+            kprintln!("usage: ping [-c COUNT] IP");
+            return 1;
+        }
+    };
+
+    let id = icmp::next_id();
+    let data: Vec<u8> = (0..PING_DATA_LEN).map(|i| i as u8).collect();
+    let mut received = 0;
+    let mut times = Vec::new();
+    kprintln!("PING {}: {} data bytes", dst, PING_DATA_LEN);
+    for seq in 0..count as u16 {
+        let sent = clock::monotonic_us();
+        if let Err(e) = icmp::send_echo(dst, id, seq, &data) {
+            kprintln!("ping: {}: {}", dst, e);
+            return 1;
+        }
+
+        let mut waited = 0;
+        let reply = loop {
+            if let Some(reply) = icmp::take_reply(id, seq) {
+                break Some(reply);
+            }
+            if waited >= PING_TIMEOUT_MS {
+                break None;
+            }
+            tick::sleep_ms(PING_POLL_MS);
+            waited += PING_POLL_MS;
+        };
+
+        match reply {
+            Some(reply) => {
+                let time = reply.received.saturating_sub(sent);
+                kprintln!("{} bytes from {}: icmp_seq={} ttl={} time={}.{:03} ms", reply.len,
+                          reply.from, seq, reply.ttl, time / 1000, time % 1000);
+                received += 1;
+                times.push(time);
+            }
+            None => kprintln!("Request timeout for icmp_seq {}", seq),
+        }
+
+        // Send one a second, counting the time spent waiting.
+        if seq + 1 < count as u16 && waited < PING_TIMEOUT_MS {
+            tick::sleep_ms(PING_TIMEOUT_MS - waited);
+        }
+    }
+
+    kprintln!("--- {} ping statistics ---", dst);
+    kprintln!("{} packets transmitted, {} packets received, {}% packet loss", count, received,
+              (count - received) * 100 / count);
+    if !times.is_empty() {
+        let min = times.iter().min().cloned().unwrap_or(0);
+        let max = times.iter().max().cloned().unwrap_or(0);
+        let avg = times.iter().sum::<u64>() / times.len() as u64;
+        kprintln!("round-trip min/avg/max = {}.{:03}/{}.{:03}/{}.{:03} ms", min / 1000,
+                  min % 1000, avg / 1000, avg % 1000, max / 1000, max % 1000);
+    }
+
+    match received {
+        0 => 1,
+        _ => 0,
+    }
+}
+
+/// The `cd` builtin. Makes the directory at `path` the shell's current
+/// directory, which the programs it runs inherit. Returns the command's
+/// status.
+fn cd(path: &str) -> i32 {
+    let dir = scheduler::resolve(path);
+    let result = FILE_SYSTEM.open(&dir).and_then(|entry| match entry.metadata().kind {
+        Kind::Dir => Ok(()),
+        Kind::File => Err(vfs::not_a_directory()),
+    });
+
+    match result {
+        Ok(()) => {
+            scheduler::set_cwd(dir);
+            0
+        }
+        Err(e) => {
+            kprintln!("cd: {}: {}", path, e);
+            1
+        }
+    }
+}
+
+/// The `cat` builtin. Prints the contents of each file in `paths`.
+fn cat(paths: &[&str]) -> i32 {
+    let mut status = 0;
+    for path in paths.iter() {
+        let result = FILE_SYSTEM.open_file(scheduler::resolve(path)).and_then(|mut file| {
+            let mut buf = [0; 512];
+            loop {
+                match file.read(&mut buf)? {
+                    0 => return Ok(()),
+                    n => write_bytes(&buf[..n]),
+                }
+            }
+        });
+
+        if let Err(e) = result {
+            kprintln!("cat: {}: {}", path, e);
+            status = 1;
+        }
+    }
+    status
+}
+
+/// The `top` builtin. Shows each process's share of the CPU time over the
+/// last interval, busiest first, refreshing `count` times or until a key is
+/// pressed. A process busy on every core would show 100%.
+fn top(count: u32) {
+    let mut last: Vec<process::Info> = Vec::new();
+    let mut last_time = timer::current_time();
+    scheduler::for_each(|p| last.push(p.clone()));
+
+    for _ in 0..count {
+        tick::sleep_ms(TOP_INTERVAL_MS);
+        let pressed = match scheduler::session() {
+            Some(session) => session.try_read_byte().is_some() || session.is_hung_up(),
+            None => UartInput.try_read_byte().is_some(),
+        };
+        if pressed {
+            break;
+        }
+
+        let mut now: Vec<process::Info> = Vec::new();
+        scheduler::for_each(|p| now.push(p.clone()));
+        let now_time = timer::current_time();
+        show_top(&last, &now, (now_time - last_time) * smp::online() as u64);
+        last = now;
+        last_time = now_time;
+    }
+}
+
+/// Prints a screen of `top`, given snapshots `last` and `now` taken
+/// `capacity` microseconds of CPU time apart, counting every core.
+fn show_top(last: &[process::Info], now: &[process::Info], capacity: u64) {
+    // (info, user and kernel time this interval, switches this interval)
+    let mut rows: Vec<(&process::Info, u64, u64, u64)> = now.iter().map(|p| {
+        match last.iter().find(|q| q.id == p.id) {
+            Some(q) => (p, p.user_time.saturating_sub(q.user_time),
+                        p.kernel_time.saturating_sub(q.kernel_time),
+                        p.switches - q.switches),
+            None => (p, p.user_time, p.kernel_time, p.switches),
+        }
+    }).collect();
+    rows.sort_by_key(|&(_, user, kernel, _)| Reverse(user + kernel));
+
+    let percent = |us: u64| us as f64 * 100.0 / max(capacity, 1) as f64;
+    let busy: u64 = rows.iter().map(|&(_, user, kernel, _)| user + kernel).sum();
+    kprint!("\x1b[2J\x1b[H");
+    kprintln!("{} processes, {} cores, {:.1}% busy; press any key to stop",
+              rows.len(), smp::online(), percent(busy));
+    kprintln!("{:>5}  {:<8}  {:>6}  {:>6}  {:>6}  {:>8}  {}",
+              "ID", "STATE", "CPU%", "USER%", "SYS%", "SWITCHES", "NAME");
+    for &(p, user, kernel, switches) in rows.iter() {
+        kprintln!("{:>5}  {:<8}  {:>6.1}  {:>6.1}  {:>6.1}  {:>8}  {}",
+                  p.id, format!("{:?}", p.state), percent(user + kernel),
+                  percent(user), percent(kernel), switches, p.name);
+    }
+}
+
+/// The `run` builtin. Loads the program at `path`, runs it with `args` and
+/// the shell's variables as its environment, waits for it to exit, and
+/// returns its exit code.
+fn run(path: &str, args: &[&str], env: &Env) -> i32 {
+    let strings: Vec<String> = env.vars.iter().map(|&(ref n, ref v)| format!("{}={}", n, v)).collect();
+    let vars: Vec<&str> = strings.iter().map(|s| s.as_str()).collect();
+    let process = match process::Process::load(scheduler::resolve(path), args, &vars) {
+        Ok(process) => process,
+        Err(e) => {
+            kprintln!("run: {}: {}", path, e);
+            return UNKNOWN_COMMAND_STATUS;
+        }
+    };
+
+    let id = match scheduler::add(process) {
+        Some(id) => id,
+        None => {
+            kprintln!("run: {}: out of memory", path);
+            return 1;
+        }
+    };
+    set_foreground(Some(id));
+    let code = scheduler::wait(id);
+    set_foreground(None);
+    match code {
+        Some(0) => 0,
+        Some(code) => {
+            kprintln!("run: {} exited with status {}", path, code);
+            code
+        }
+        None => {
+            kprintln!("run: lost track of process {}", id);
+            1
         }
     }
 }
@@ -57,55 +1694,39 @@ impl<'a> Command<'a> {
 /// Starts a shell using `prefix` as the prefix for each line. This function
 /// never returns: it is perpetually in a shell loop.
 pub fn shell(prefix: &str) -> ! {
+    serve(prefix, UartInput);
+    unreachable!("the UART never hangs up")
+}
+
+/// Runs a shell reading lines from `input`, using `prefix` as the prefix
+/// for each, until the input hangs up.
+pub fn serve<I: ConsoleInput>(prefix: &str, input: I) {
+    let mut tty = LineDiscipline::new(input);
+    let mut input: Vec<u8> = Vec::new();
+    let mut env = Env::new();
     loop {
-        let mut buf = [0u8; 128];
-        let mut input = StackVec::new(&mut buf);
         kprint!("{}", prefix);
 
-        loop {
-            let byte = CONSOLE.lock().read_byte();
-
-            if byte == b'\n' || byte == b'\r' {
-                // End of input
-                let mut command_storage: [&str; 64] = [""; 64];
-                let result = Command::parse(
-                    str::from_utf8(input.into_slice()).unwrap(),
-                    &mut command_storage);
-
-                kprint!("\n");
-
-                match result {
-                    Err(Error::TooManyArgs) => {
-                        kprintln!("error: too many arguments");
-                    },
-                    Err(Error::Empty) => {
-                        // No command, ignore.
-                    }
-                    Ok(command) => {
-                        command.execute();
-                    },
-				}
-                break;
-            } else {
-                if byte == 8 || byte == 127 {
-                    if input.pop() == None {
-                        CONSOLE.lock().write_byte(7 as u8);
-                    } else {
-                        CONSOLE.lock().write_byte(8u8);
-                        CONSOLE.lock().write_byte(b' ');
-                        CONSOLE.lock().write_byte(8u8);
-                    }
-                } else if byte < 32 || byte > 126 {
-                    CONSOLE.lock().write_byte(7 as u8);
-                } else {
-                    if input.push(byte).is_err() {
-                        kprintln!("input full!");
-                        CONSOLE.lock().write_byte(7 as u8);
-                    } else {
-                        CONSOLE.lock().write_byte(byte);
-                    }
-                }
+        // Keep the allocation around between lines; only the contents reset.
+        let line = tty.read_line(&mut input, MAX_LINE_LEN);
+        if tty.hung_up() {
+            return;
+        }
+        if let Err(LineError::TooLong) = line {
+            kprintln!("error: line exceeds {} bytes", MAX_LINE_LEN);
+            continue;
+        }
+
+        match Command::parse(str::from_utf8(&input).unwrap()) {
+            Err(Error::TooManyArgs) => {
+                kprintln!("error: too many arguments (max {})", MAX_ARGS);
+            },
+            Err(Error::Empty) => {
+                // No command, ignore.
             }
+            Ok(command) => {
+                command.execute_list(&mut env);
+            },
         }
     }
 }
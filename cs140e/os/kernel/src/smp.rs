@@ -0,0 +1,104 @@
+//! Bringing up the secondary cores.
+//!
+//! At boot only core 0 runs the kernel. The others wait either in the
+//! firmware's stub, for an address in its spin table, or in `init.S`, for
+//! one in `spin_table`. `start_secondaries()` writes `_start_secondary` to
+//! both, and each core then enables its MMU, starts its own tick, and idles
+//! until the scheduler gives it a process.
+
+use core::alloc::{GlobalAlloc, Layout};
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use pi::local::NUM_CORES;
+use pi::timer;
+
+use aarch64;
+use console::{log_info, log_warn};
+use init::{kernel_init, Init, Stage};
+use tick;
+use vm;
+use ALLOCATOR;
+
+/// The number of cores, which sizes per-core state.
+pub const MAX_CORES: usize = NUM_CORES;
+
+/// The firmware's spin table: a core it parked jumps to the address written
+/// at `FIRMWARE_SPIN_TABLE + 8 * core`.
+const FIRMWARE_SPIN_TABLE: usize = 0xd8;
+
+/// The size of the stack a secondary core starts on. It runs with its MMU
+/// off until `vm::init_secondary()`, so this stack comes from the heap,
+/// whose addresses are physical, rather than being a guarded `Stack`.
+const BOOT_STACK_SIZE: usize = 16 * 1024;
+
+/// How long to wait for the secondary cores to come online, in
+/// microseconds.
+const START_TIMEOUT: u64 = 100_000;
+
+extern "C" {
+    /// Where `init.S` parks secondary cores until their entry is nonzero.
+    static mut spin_table: [usize; MAX_CORES];
+    /// The stack each secondary core starts on.
+    static mut secondary_stacks: [usize; MAX_CORES];
+    fn _start_secondary();
+}
+
+/// The number of cores running the kernel.
+static ONLINE: AtomicUsize = AtomicUsize::new(1);
+
+/// Returns the number of cores running the kernel.
+pub fn online() -> usize {
+    ONLINE.load(Ordering::Relaxed)
+}
+
+kernel_init!(SMP_INIT, Stage::Secondaries, "smp", Init::Plain(start_secondaries));
+
+/// Releases cores 1 to 3, giving each a stack from the heap, and waits for
+/// them to come online. Called once, on core 0, in the last stage of boot.
+pub fn start_secondaries() {
+    vm::clean_tables();
+
+    let entry = _start_secondary as usize;
+    for core in 1..MAX_CORES {
+        // The core runs on the stack forever.
+        let layout = Layout::from_size_align(BOOT_STACK_SIZE, 16).unwrap();
+        let stack = unsafe { ALLOCATOR.alloc(layout) };
+        if stack.is_null() {
+            return log_warn!("smp: no memory for core {}'s stack", core);
+        }
+
+        unsafe {
+            secondary_stacks[core] = stack as usize + BOOT_STACK_SIZE;
+            spin_table[core] = entry;
+            aarch64::clean_dcache(&secondary_stacks[core] as *const usize as usize, 8);
+            aarch64::clean_dcache(&spin_table[core] as *const usize as usize, 8);
+
+            let firmware = (FIRMWARE_SPIN_TABLE + 8 * core) as *mut usize;
+            ptr::write_volatile(firmware, entry);
+            aarch64::clean_dcache(firmware as usize, 8);
+        }
+    }
+
+    aarch64::sev();
+
+    let deadline = timer::current_time() + START_TIMEOUT;
+    while online() < MAX_CORES && timer::current_time() < deadline { }
+    log_info!("smp: {} of {} cores online", online(), MAX_CORES);
+}
+
+/// Called by `_start_secondary` on each secondary core, on its own stack.
+/// Enables the MMU and the core's tick, then waits: the first tick switches
+/// to a ready process, or to the core's idle process, abandoning this
+/// context.
+#[no_mangle]
+#[cfg(not(test))]
+pub extern "C" fn kmain_secondary() -> ! {
+    vm::init_secondary();
+    ONLINE.fetch_add(1, Ordering::Relaxed);
+    tick::init_local();
+
+    loop {
+        aarch64::wfi();
+    }
+}
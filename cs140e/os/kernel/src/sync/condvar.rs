@@ -0,0 +1,53 @@
+use aarch64;
+use kthread;
+use mutex::Mutex as SpinLock;
+use sync::MutexGuard;
+use sync::waiters::{self, Waiters};
+
+/// A condition variable: lets processes sleep, with a `Mutex` unlocked,
+/// until another process notifies them of a change to the data it guards.
+pub struct Condvar {
+    waiters: SpinLock<Waiters>,
+}
+
+impl Condvar {
+    /// Returns a condition variable with no waiters.
+    pub const fn new() -> Condvar {
+        Condvar { waiters: SpinLock::new(Waiters::new()) }
+    }
+
+    /// Unlocks `guard`'s mutex and sleeps until notified, then locks it
+    /// again and returns the new guard. Wakeups may be spurious, so callers
+    /// should recheck their condition in a loop.
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex();
+        // Join the waiters before unlocking, so a notification sent in
+        // between isn't lost, and don't get preempted while waiting with
+        // the mutex still locked.
+        let daif = aarch64::irq_save();
+        self.waiters.lock().sleep();
+        drop(guard);
+        aarch64::irq_restore(daif);
+        kthread::yield_now();
+        mutex.lock()
+    }
+
+    /// Wakes the longest sleeping waiter, if any.
+    pub fn notify_one(&self) {
+        loop {
+            let id = self.waiters.lock().pop();
+            match id {
+                Some(id) if !waiters::wake(id) => continue,
+                _ => return,
+            }
+        }
+    }
+
+    /// Wakes every waiter.
+    pub fn notify_all(&self) {
+        let ids = self.waiters.lock().take_all();
+        for id in ids {
+            waiters::wake(id);
+        }
+    }
+}
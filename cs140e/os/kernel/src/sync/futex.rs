@@ -0,0 +1,97 @@
+//! Futexes: wait queues keyed by user addresses, so that user programs can
+//! build their own locks and sleep only when they are contended.
+//!
+//! A user lock keeps its state in a word of user memory and changes it with
+//! atomic instructions, entering the kernel only to wait for the word to
+//! change, with `futex_wait`, or to wake waiters after changing it, with
+//! `futex_wake`. `futex_wait` checks the word and queues the caller under
+//! one lock, which `futex_wake` also takes, so a wakeup between a user
+//! program reading the word and waiting is never lost.
+//!
+//! Futexes are private to an address space: the key is the address space
+//! and the address, so processes sharing an address space share futexes and
+//! other processes using the same address don't.
+
+use std::collections::BTreeMap;
+use std::ptr;
+
+use mutex::IrqMutex;
+use scheduler;
+use sync::waiters::{self, Waiters};
+
+/// A futex: an address space, as `scheduler::current_space_id()`, and an
+/// address in it.
+type Key = (u64, usize);
+
+/// The processes waiting on each futex. Futexes nobody waits on have no
+/// entry.
+static FUTEXES: IrqMutex<Option<BTreeMap<Key, Waiters>>> = IrqMutex::new(None);
+
+/// The reasons `park()` doesn't wait.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The word didn't hold the expected value.
+    Changed,
+    /// The address isn't an aligned word, or the running process has no
+    /// address space.
+    Invalid,
+}
+
+/// Returns the key of the futex at `addr` for the running process.
+fn key(addr: usize) -> Result<Key, Error> {
+    if addr % 4 != 0 {
+        return Err(Error::Invalid);
+    }
+    scheduler::current_space_id().map(|space| (space, addr)).ok_or(Error::Invalid)
+}
+
+/// If the word at the user address `addr` holds `expected`, queues the
+/// running process on it, marks it waiting, and asks for it to be switched
+/// out. For the `futex_wait` system call, which returns once the process is
+/// woken. The caller must have checked that `addr` may be read.
+pub fn park(addr: usize, expected: u32) -> Result<(), Error> {
+    let key = key(addr)?;
+    {
+        let mut futexes = FUTEXES.lock();
+        let value = unsafe { ptr::read_volatile(addr as *const u32) };
+        if value != expected {
+            return Err(Error::Changed);
+        }
+
+        futexes.get_or_insert_with(BTreeMap::new).entry(key).or_insert_with(Waiters::new).park();
+    }
+
+    scheduler::request_resched();
+    Ok(())
+}
+
+/// Wakes up to `count` processes waiting on the futex at the user address
+/// `addr`, longest waiting first, and returns how many were woken.
+pub fn wake(addr: usize, count: usize) -> Result<usize, Error> {
+    let key = key(addr)?;
+    let mut woken = 0;
+    while woken < count {
+        let id = {
+            let mut futexes = FUTEXES.lock();
+            let map = match futexes.as_mut() {
+                Some(map) => map,
+                None => break,
+            };
+
+            let (id, empty) = match map.get_mut(&key) {
+                Some(waiters) => (waiters.pop(), waiters.is_empty()),
+                None => (None, true),
+            };
+            if empty {
+                map.remove(&key);
+            }
+            id
+        };
+
+        match id {
+            Some(id) => if waiters::wake(id) { woken += 1 },
+            None => break,
+        }
+    }
+    Ok(woken)
+}
@@ -0,0 +1,29 @@
+//! Blocking synchronization primitives.
+//!
+//! Unlike the spinlock in `mutex`, these put a process that has to wait to
+//! sleep until it is woken, so other processes run in the meantime. They
+//! are for long critical sections, such as SD card transactions, and must
+//! not be used from exception handlers, which can't sleep. A `WaitQueue`
+//! lets a process sleep until an event, such as an interrupt, wakes it, and
+//! `futex` lets user programs do the same with their own locks.
+
+mod waiters;
+mod semaphore;
+mod mutex;
+mod condvar;
+mod wait_queue;
+pub mod futex;
+
+pub use self::semaphore::Semaphore;
+pub use self::mutex::{Mutex, MutexGuard};
+pub use self::condvar::Condvar;
+pub use self::wait_queue::WaitQueue;
+
+/// Contention counters for a blocking primitive, for diagnostics.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Stats {
+    /// The number of times the primitive was acquired.
+    pub acquisitions: usize,
+    /// The number of those times the caller had to sleep first.
+    pub contentions: usize,
+}
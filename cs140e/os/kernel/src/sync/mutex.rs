@@ -0,0 +1,84 @@
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+
+use sync::{Semaphore, Stats};
+
+/// A mutual exclusion lock whose waiters sleep rather than spin.
+pub struct Mutex<T> {
+    semaphore: Semaphore,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Send for Mutex<T> { }
+unsafe impl<T: Send> Sync for Mutex<T> { }
+
+/// A locked `Mutex`, unlocked when dropped.
+pub struct MutexGuard<'a, T: 'a> {
+    lock: &'a Mutex<T>,
+}
+
+impl<'a, T> !Send for MutexGuard<'a, T> { }
+
+impl<T> Mutex<T> {
+    /// Returns an unlocked mutex holding `value`.
+    pub const fn new(value: T) -> Mutex<T> {
+        Mutex { semaphore: Semaphore::new(1), data: UnsafeCell::new(value) }
+    }
+
+    /// Locks the mutex, sleeping until it is free.
+    pub fn lock(&self) -> MutexGuard<T> {
+        self.semaphore.acquire();
+        MutexGuard { lock: self }
+    }
+
+    /// Locks the mutex if it is free.
+    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+        if self.semaphore.try_acquire() {
+            Some(MutexGuard { lock: self })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the contention counters.
+    pub fn stats(&self) -> Stats {
+        self.semaphore.stats()
+    }
+}
+
+impl<'a, T: 'a> MutexGuard<'a, T> {
+    /// Returns the mutex this guard locks.
+    pub(super) fn mutex(&self) -> &'a Mutex<T> {
+        self.lock
+    }
+}
+
+impl<'a, T: 'a> Deref for MutexGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T: 'a> DerefMut for MutexGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<'a, T: 'a> Drop for MutexGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.semaphore.release();
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Mutex<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_lock() {
+            Some(guard) => f.debug_struct("Mutex").field("data", &&*guard).finish(),
+            None => f.debug_struct("Mutex").field("data", &"<locked>").finish(),
+        }
+    }
+}
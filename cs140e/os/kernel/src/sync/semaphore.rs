@@ -0,0 +1,101 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use kthread;
+use mutex::IrqMutex;
+use preempt;
+use sync::Stats;
+use sync::waiters::{self, Waiters};
+
+struct State {
+    count: usize,
+    waiters: Waiters,
+}
+
+/// A counting semaphore whose waiters sleep.
+pub struct Semaphore {
+    /// Locked with IRQs masked: a process preempted while waiting but
+    /// still holding the lock would never be woken, since waking it needs
+    /// the lock.
+    state: IrqMutex<State>,
+    acquisitions: AtomicUsize,
+    contentions: AtomicUsize,
+}
+
+impl Semaphore {
+    /// Returns a semaphore with `count` permits.
+    pub const fn new(count: usize) -> Semaphore {
+        Semaphore {
+            state: IrqMutex::new(State { count, waiters: Waiters::new() }),
+            acquisitions: AtomicUsize::new(0),
+            contentions: AtomicUsize::new(0),
+        }
+    }
+
+    /// Takes a permit if one is available. Returns `false` otherwise.
+    pub fn try_acquire(&self) -> bool {
+        let mut state = self.state.lock();
+        if state.count == 0 {
+            return false;
+        }
+
+        state.count -= 1;
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    /// Takes a permit, sleeping until one is available.
+    ///
+    /// # Panics
+    ///
+    /// Panics in atomic context, even if a permit is available. See
+    /// `preempt::might_sleep()`.
+    pub fn acquire(&self) {
+        preempt::might_sleep();
+        let mut contended = false;
+        loop {
+            let acquired = {
+                let mut state = self.state.lock();
+                if state.count > 0 {
+                    state.count -= 1;
+                    true
+                } else {
+                    state.waiters.sleep();
+                    false
+                }
+            };
+
+            if acquired {
+                break;
+            }
+
+            contended = true;
+            kthread::yield_now();
+        }
+
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+        if contended {
+            self.contentions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns a permit, waking the longest sleeping waiter.
+    pub fn release(&self) {
+        let mut state = self.state.lock();
+        state.count += 1;
+        // Skip waiters that exited while asleep, so the permit isn't left
+        // with nobody to take it.
+        while let Some(id) = state.waiters.pop() {
+            if waiters::wake(id) {
+                break;
+            }
+        }
+    }
+
+    /// Returns the contention counters.
+    pub fn stats(&self) -> Stats {
+        Stats {
+            acquisitions: self.acquisitions.load(Ordering::Relaxed),
+            contentions: self.contentions.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use kthread;
+use mutex::IrqMutex;
+use preempt;
+use sync::waiters::{self, Waiters};
+
+/// A queue of processes sleeping until some condition holds, such as input
+/// arriving or a child exiting.
+///
+/// The condition is checked with the queue locked, and whoever makes it
+/// true must call `wake_one()` or `wake_all()` afterwards, so a wakeup is
+/// never lost between the check and going to sleep. Waking is safe from
+/// exception handlers.
+pub struct WaitQueue {
+    waiters: IrqMutex<Waiters>,
+}
+
+impl WaitQueue {
+    /// Returns an empty queue.
+    pub const fn new() -> WaitQueue {
+        WaitQueue { waiters: IrqMutex::new(Waiters::new()) }
+    }
+
+    /// Sleeps until `cond()` returns `true`. `cond` is called with IRQs
+    /// masked, once at first and again after every wakeup.
+    ///
+    /// # Panics
+    ///
+    /// Panics in atomic context, even if `cond()` already holds. See
+    /// `preempt::might_sleep()`.
+    pub fn wait_until<F: FnMut() -> bool>(&self, mut cond: F) {
+        preempt::might_sleep();
+        loop {
+            {
+                let mut waiters = self.waiters.lock();
+                if cond() {
+                    return;
+                }
+
+                waiters.sleep();
+            }
+
+            kthread::yield_now();
+        }
+    }
+
+    /// For system call handlers, which can't sleep: if `cond()` returns
+    /// `false`, queues the running process and marks it waiting, so that it
+    /// is switched out as the exception returns. The handler should arrange
+    /// to retry once woken. Returns `cond()`.
+    pub fn park_unless<F: FnOnce() -> bool>(&self, cond: F) -> bool {
+        let mut waiters = self.waiters.lock();
+        let done = cond();
+        if !done {
+            waiters.park();
+        }
+        done
+    }
+
+    /// Returns `true` if this core has the queue locked, in which case
+    /// waking it would deadlock.
+    pub fn is_locked_here(&self) -> bool {
+        self.waiters.is_held_by_current_core()
+    }
+
+    /// Wakes the longest sleeping process. Returns `false` if none was
+    /// sleeping.
+    pub fn wake_one(&self) -> bool {
+        loop {
+            let id = self.waiters.lock().pop();
+            match id {
+                Some(id) if waiters::wake(id) => return true,
+                Some(_) => continue,
+                None => return false,
+            }
+        }
+    }
+
+    /// Wakes every sleeping process.
+    pub fn wake_all(&self) {
+        let ids = self.waiters.lock().take_all();
+        for id in ids {
+            waiters::wake(id);
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use std::collections::VecDeque;
+
+use preempt;
+use process::Id;
+use scheduler;
+
+/// The processes sleeping on a primitive, in the order they went to sleep.
+///
+/// Kept behind the primitive's spinlock: a process adds itself with
+/// `sleep()` while holding it, then releases it and yields.
+pub struct Waiters(Option<VecDeque<Id>>);
+
+impl Waiters {
+    /// Returns an empty list.
+    pub const fn new() -> Waiters {
+        Waiters(None)
+    }
+
+    /// Marks the running process waiting and adds it to the back of the
+    /// list. The caller must release its lock and yield.
+    ///
+    /// # Panics
+    ///
+    /// Panics in atomic context. See `preempt::might_sleep()`.
+    pub fn sleep(&mut self) {
+        preempt::might_sleep();
+        self.park();
+    }
+
+    /// Like `sleep()`, but for a system call handler, which can't yield. The
+    /// process is switched out as the exception returns instead.
+    pub fn park(&mut self) {
+        let id = scheduler::block_current();
+        self.0.get_or_insert_with(VecDeque::new).push_back(id);
+    }
+
+    /// Removes the process at the front of the list, which the caller must
+    /// wake with `wake()`.
+    pub fn pop(&mut self) -> Option<Id> {
+        self.0.as_mut().and_then(|waiters| waiters.pop_front())
+    }
+
+    /// Returns `true` if no process is on the list.
+    pub fn is_empty(&self) -> bool {
+        self.0.as_ref().map_or(true, |waiters| waiters.is_empty())
+    }
+
+    /// Removes every process from the list.
+    pub fn take_all(&mut self) -> VecDeque<Id> {
+        self.0.take().unwrap_or_default()
+    }
+}
+
+/// Wakes the sleeping process `id`. Returns `false` if it had exited.
+pub fn wake(id: Id) -> bool {
+    // A process that was woken before it yielded is already ready.
+    scheduler::wake(id) || scheduler::is_alive(id)
+}
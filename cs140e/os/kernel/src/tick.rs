@@ -0,0 +1,130 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use pi::interrupt::Interrupt;
+use pi::timer;
+
+use aarch64;
+use hwdebug;
+use init::{kernel_init, Init, Stage};
+use irq;
+use profile;
+use scheduler;
+use sync::WaitQueue;
+use traps::TrapFrame;
+
+/// The tick rate used until `set_tick_hz()` is called.
+pub const DEFAULT_TICK_HZ: usize = 100;
+
+/// The number of timer interrupts per second.
+static TICK_HZ: AtomicUsize = AtomicUsize::new(DEFAULT_TICK_HZ);
+
+/// The number of ticks since `init()`.
+static TICKS: AtomicUsize = AtomicUsize::new(0);
+
+/// Processes in `sleep_us()`.
+static SLEEPERS: WaitQueue = WaitQueue::new();
+
+/// The earliest time, in microseconds, a sleeper should be woken at, or
+/// `usize::MAX` if nobody is sleeping.
+static NEXT_WAKE: AtomicUsize = AtomicUsize::new(usize::max_value());
+
+/// Returns the number of timer interrupts per second.
+pub fn tick_hz() -> usize {
+    TICK_HZ.load(Ordering::Relaxed)
+}
+
+/// Sets the number of timer interrupts per second, taking effect from the
+/// next tick.
+///
+/// # Panics
+///
+/// Panics if `hz` is zero or more than 1,000,000.
+pub fn set_tick_hz(hz: usize) {
+    assert!(hz > 0 && hz <= 1_000_000, "tick rate {} Hz out of range", hz);
+    TICK_HZ.store(hz, Ordering::Relaxed);
+}
+
+/// Returns the number of ticks since `init()`.
+pub fn ticks() -> usize {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Sleeps for at least `us` microseconds, letting other processes run. The
+/// sleeper is woken by the first tick after its deadline.
+pub fn sleep_us(us: u64) {
+    let deadline = (timer::current_time() + us) as usize;
+    SLEEPERS.wait_until(|| {
+        if timer::current_time() as usize >= deadline {
+            return true;
+        }
+
+        // Checked with the queue locked, so the tick can't clear this
+        // before the sleeper is queued.
+        let mut next = NEXT_WAKE.load(Ordering::Relaxed);
+        while deadline < next {
+            match NEXT_WAKE.compare_exchange(next, deadline, Ordering::Relaxed, Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(current) => next = current,
+            }
+        }
+        false
+    });
+}
+
+/// Sleeps for at least `ms` milliseconds. See `sleep_us()`.
+pub fn sleep_ms(ms: u64) {
+    sleep_us(ms * 1000);
+}
+
+kernel_init!(TICK_INIT, Stage::Drivers, "tick", Init::Plain(init));
+
+/// Starts the periodic timer interrupt and unmasks IRQs on this core. Each
+/// tick asks the scheduler to switch tasks when the interrupt returns.
+pub fn init() {
+    irq::register(Interrupt::Timer1, handle_tick).expect("timer IRQ already registered");
+    arm();
+    aarch64::irq_enable();
+}
+
+/// Starts the periodic tick of a secondary core, from its own physical
+/// timer, and unmasks IRQs on it. Only core 0's tick counts ticks and wakes
+/// sleepers.
+pub fn init_local() {
+    irq::register_local_timer(handle_local_tick);
+    arm_local();
+    aarch64::irq_enable();
+}
+
+/// Schedules the next tick.
+fn arm() {
+    timer::tick_in((1_000_000 / tick_hz()) as u32);
+}
+
+/// Schedules this core's next local tick.
+fn arm_local() {
+    aarch64::physical_timer_in((aarch64::timer_frequency() / tick_hz() as u64) as u32);
+}
+
+/// The physical timer IRQ handler of a secondary core. Each tick also
+/// picks up changes to the hardware breakpoints and watchpoints and to the
+/// profiler.
+fn handle_local_tick(_tf: &mut TrapFrame) {
+    arm_local();
+    hwdebug::sync();
+    profile::sync();
+    scheduler::request_resched();
+}
+
+/// The timer 1 IRQ handler.
+fn handle_tick(_tf: &mut TrapFrame) {
+    arm();
+    hwdebug::sync();
+    profile::sync();
+    TICKS.fetch_add(1, Ordering::Relaxed);
+    if timer::current_time() as usize >= NEXT_WAKE.load(Ordering::Relaxed) {
+        // Sleepers whose deadline hasn't passed lower it again.
+        NEXT_WAKE.store(usize::max_value(), Ordering::Relaxed);
+        SLEEPERS.wake_all();
+    }
+    scheduler::request_resched();
+}
@@ -0,0 +1,193 @@
+//! Lightweight event tracing: a fixed-size ring of timestamped binary
+//! events per core, recorded from hot paths with `trace_event!`.
+//!
+//! Recording an event takes a slot in the running core's ring with one
+//! atomic add and writes the generic timer's count, the event, and an
+//! argument to it, overwriting the oldest event once the ring is full. No
+//! lock is taken and nothing allocates, so events can be recorded from
+//! exception handlers and the allocator itself. While tracing is off,
+//! `trace_event!` costs a load and a branch.
+//!
+//! `dump()` merges the rings by time; the generic timer's count is the
+//! same on every core, so events on different cores are ordered too.
+
+use std::cell::UnsafeCell;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use aarch64;
+use console::kprintln;
+use smp::MAX_CORES;
+
+/// The events each core's ring holds.
+pub const RING_LEN: usize = 4096;
+
+/// The events traced.
+#[repr(u16)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// A core switched to a process; the argument is its ID.
+    Switch = 1,
+    /// An IRQ was taken; the argument is the PC it interrupted.
+    IrqEnter,
+    /// An IRQ's handlers returned.
+    IrqExit,
+    /// A system call was made; the argument is its number.
+    Syscall,
+    /// Memory was allocated; the argument is the size.
+    Alloc,
+    /// Memory was freed; the argument is the size.
+    Free,
+    /// A point of interest, for ad hoc tracing; the argument is anything.
+    Mark,
+}
+
+impl Event {
+    fn from_u16(value: u16) -> Option<Event> {
+        Some(match value {
+            1 => Event::Switch,
+            2 => Event::IrqEnter,
+            3 => Event::IrqExit,
+            4 => Event::Syscall,
+            5 => Event::Alloc,
+            6 => Event::Free,
+            7 => Event::Mark,
+            _ => return None,
+        })
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            Event::Switch => "switch",
+            Event::IrqEnter => "irq",
+            Event::IrqExit => "irq-exit",
+            Event::Syscall => "syscall",
+            Event::Alloc => "alloc",
+            Event::Free => "free",
+            Event::Mark => "mark",
+        }
+    }
+}
+
+/// Records `$event` with the argument `$arg`, cast to a `u64`, if tracing is
+/// on. `$arg` isn't evaluated if it isn't.
+pub macro trace_event($event:expr, $arg:expr) {
+    if is_enabled() {
+        record($event, $arg as u64)
+    }
+}
+
+/// A recorded event. A `time` of 0 marks a slot never written.
+#[derive(Copy, Clone)]
+struct Entry {
+    time: u64,
+    arg: u64,
+    event: u16,
+}
+
+const EMPTY: Entry = Entry { time: 0, arg: 0, event: 0 };
+
+/// One core's events. Only that core writes them, but an exception can
+/// interrupt a write, so slots are claimed atomically.
+struct Ring {
+    /// The number of events ever recorded; the next goes in `head % RING_LEN`.
+    head: AtomicUsize,
+    entries: UnsafeCell<[Entry; RING_LEN]>,
+}
+
+unsafe impl Sync for Ring {}
+
+impl Ring {
+    const fn new() -> Ring {
+        Ring { head: AtomicUsize::new(0), entries: UnsafeCell::new([EMPTY; RING_LEN]) }
+    }
+}
+
+static RINGS: [Ring; MAX_CORES] = [Ring::new(), Ring::new(), Ring::new(), Ring::new()];
+
+/// Whether events are recorded.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Returns whether events are recorded.
+#[inline(always)]
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Starts or stops recording events. Events already recorded are kept.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Internal function called by `trace_event!`.
+#[doc(hidden)]
+#[inline(never)]
+pub fn record(event: Event, arg: u64) {
+    let ring = &RINGS[aarch64::affinity()];
+    let slot = ring.head.fetch_add(1, Ordering::Relaxed) % RING_LEN;
+    let entry = Entry { time: aarch64::timer_count(), arg, event: event as u16 };
+    unsafe { (*ring.entries.get())[slot] = entry; }
+}
+
+/// Discards every core's events.
+pub fn clear() {
+    let enabled = ENABLED.swap(false, Ordering::Relaxed);
+    for ring in RINGS.iter() {
+        ring.head.store(0, Ordering::Relaxed);
+        unsafe { *ring.entries.get() = [EMPTY; RING_LEN]; }
+    }
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// An event's argument, shown as suits the event.
+struct Arg(Event, u64);
+
+impl fmt::Display for Arg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Event::Switch => write!(f, "pid {}", self.1),
+            Event::IrqEnter => write!(f, "pc {:#x}", self.1),
+            Event::IrqExit => Ok(()),
+            Event::Syscall => write!(f, "#{}", self.1),
+            Event::Alloc | Event::Free => write!(f, "{} bytes", self.1),
+            Event::Mark => write!(f, "{:#x}", self.1),
+        }
+    }
+}
+
+/// Prints the last `count` events on any core, oldest first: when each
+/// happened, in seconds since boot, the core, the time since the event
+/// before it, the event, and its argument. Returns the number printed.
+///
+/// Recording is paused while the rings are copied, so the events printed
+/// are all from before the call.
+pub fn dump(count: usize) -> usize {
+    let enabled = ENABLED.swap(false, Ordering::Relaxed);
+    let mut events: Vec<(u64, usize, u16, u64)> = Vec::new();
+    for (core, ring) in RINGS.iter().enumerate() {
+        let entries = unsafe { &*ring.entries.get() };
+        events.extend(entries.iter()
+            .filter(|entry| entry.time != 0)
+            .map(|entry| (entry.time, core, entry.event, entry.arg)));
+    }
+    ENABLED.store(enabled, Ordering::Relaxed);
+
+    events.sort_by_key(|&(time, ..)| time);
+    let skip = events.len().saturating_sub(count);
+    let frequency = aarch64::timer_frequency().max(1);
+    let to_us = |ticks: u64| (ticks as u128 * 1_000_000 / frequency as u128) as u64;
+
+    let mut last = None;
+    for &(time, core, event, arg) in events[skip..].iter() {
+        let event = match Event::from_u16(event) {
+            Some(event) => event,
+            None => continue,
+        };
+        let us = to_us(time);
+        let delta = last.map(|last| to_us(time - last)).unwrap_or(0);
+        last = Some(time);
+        kprintln!("[{:5}.{:06}] cpu{} +{:>8}us {:<9}{}",
+                  us / 1_000_000, us % 1_000_000, core, delta, event.name(), Arg(event, arg));
+    }
+    events.len() - skip
+}
@@ -0,0 +1,117 @@
+//! Human-readable descriptions of exception syndromes.
+//!
+//! `describe(esr, far)` formats as a single line such as
+//!
+//!   Data abort from EL0, write of 8 bytes, translation fault level 3, address 0xffffff8000012340
+//!
+//! naming the exception class and, for the classes that carry them, the
+//! interesting parts of the instruction-specific syndrome (`ESR_EL1.ISS`).
+
+use std::fmt;
+
+use traps::Syndrome;
+use vm::{Access, FaultStatus};
+
+/// `ISS.ISV` of a data abort: the access size and register are valid.
+const ISV: u64 = 1 << 24;
+
+/// `ISS.FnV` of an abort: `FAR_EL1` doesn't hold the faulting address.
+const FNV: u64 = 1 << 10;
+
+/// `ISS.CM` of a data abort: the fault came from a cache maintenance
+/// instruction.
+const CM: u64 = 1 << 8;
+
+/// `ISS.S1PTW` of an abort: the fault happened walking the translation
+/// tables, not on the access itself.
+const S1PTW: u64 = 1 << 7;
+
+/// A syndrome and faulting address, which format as a description of the
+/// exception.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Description {
+    esr: u64,
+    far: u64,
+}
+
+/// Returns a description of the exception with syndrome `esr`. `far` is
+/// only used if the exception was an abort.
+pub fn describe(esr: u64, far: u64) -> Description {
+    Description { esr, far }
+}
+
+/// Returns the name of the exception class of `syndrome`.
+fn class(syndrome: Syndrome) -> &'static str {
+    use self::Syndrome::*;
+
+    match syndrome {
+        Unknown => "Undefined instruction or unknown exception",
+        WfiWfe => "Trapped WFI/WFE",
+        SimdFp => "Trapped SIMD/floating-point access",
+        IllegalExecutionState => "Illegal execution state",
+        Svc(_) => "Supervisor call",
+        Hvc(_) => "Hypervisor call",
+        Smc(_) => "Secure monitor call",
+        MsrMrsSystem => "Trapped MSR, MRS, or system instruction",
+        InstructionAbort { .. } => "Instruction abort",
+        PcAlignmentFault => "PC alignment fault",
+        DataAbort { .. } => "Data abort",
+        SpAlignmentFault => "SP alignment fault",
+        TrappedFp => "Floating-point exception",
+        SError => "SError interrupt",
+        Breakpoint => "Breakpoint",
+        Step => "Software step",
+        Watchpoint => "Watchpoint",
+        Brk(_) => "BRK instruction",
+        Other(_) => "Exception",
+    }
+}
+
+impl Description {
+    /// Writes the details of an abort: the access, why it faulted, and
+    /// where.
+    fn fmt_abort(&self, f: &mut fmt::Formatter, access: Access) -> fmt::Result {
+        write!(f, ", {}", access)?;
+        if access != Access::Execute && self.esr & ISV != 0 {
+            write!(f, " of {} bytes", 1 << ((self.esr >> 22) & 0b11))?;
+        }
+        if access != Access::Execute && self.esr & CM != 0 {
+            write!(f, " by cache maintenance")?;
+        }
+
+        write!(f, ", {}", FaultStatus::from(self.esr))?;
+        if self.esr & S1PTW != 0 {
+            write!(f, " on a table walk")?;
+        }
+
+        match self.esr & FNV {
+            0 => write!(f, ", address {:#x}", self.far),
+            _ => write!(f, ", address unknown"),
+        }
+    }
+}
+
+impl fmt::Display for Description {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let syndrome = Syndrome::from(self.esr);
+        f.write_str(class(syndrome))?;
+
+        match syndrome {
+            Syndrome::InstructionAbort { from_lower } | Syndrome::DataAbort { from_lower } => {
+                write!(f, " from EL{}", if from_lower { 0 } else { 1 })?;
+            }
+            _ => {}
+        }
+
+        match syndrome {
+            Syndrome::Svc(imm) | Syndrome::Hvc(imm) | Syndrome::Smc(imm) | Syndrome::Brk(imm) => {
+                write!(f, " #{}", imm)
+            }
+            Syndrome::InstructionAbort { .. } => self.fmt_abort(f, Access::Execute),
+            Syndrome::DataAbort { .. } => self.fmt_abort(f, Access::from_data_abort(self.esr)),
+            Syndrome::PcAlignmentFault => write!(f, ", address {:#x}", self.far),
+            Syndrome::Other(ec) => write!(f, " class {:#04x}, ISS {:#x}", ec, self.esr & 0x1FF_FFFF),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,58 @@
+//! The exception table: instructions that are allowed to fault, and where
+//! to resume when they do.
+//!
+//! Each entry, emitted by `EX_ENTRY` in `ext/probe.S` into the `.ex_table`
+//! section, pairs the address of an instruction with that of its fixup
+//! code. A data abort from EL1 at one of those loads, or an undefined
+//! instruction exception at the semihosting probe, isn't a kernel bug: the
+//! handler resumes at the fixup, which reports the fault to the caller.
+
+use traps::TrapFrame;
+
+/// An entry of the table, as `EX_ENTRY` lays it out.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct Entry {
+    insn: usize,
+    fixup: usize,
+}
+
+#[cfg(not(test))]
+extern "C" {
+    static __ex_table_start: Entry;
+    static __ex_table_end: Entry;
+}
+
+#[cfg(not(test))]
+fn entries() -> &'static [Entry] {
+    use std::{mem, slice};
+
+    unsafe {
+        let start = &__ex_table_start as *const Entry;
+        let end = &__ex_table_end as *const Entry;
+        let len = (end as usize - start as usize) / mem::size_of::<Entry>();
+        slice::from_raw_parts(start, len)
+    }
+}
+
+#[cfg(test)]
+fn entries() -> &'static [Entry] {
+    &[]
+}
+
+/// Returns the fixup for a fault at `pc`, if it is in the table.
+fn search(pc: usize) -> Option<usize> {
+    entries().iter().find(|entry| entry.insn == pc).map(|entry| entry.fixup)
+}
+
+/// If the exception that `tf` describes is at an instruction in the table,
+/// makes the exception return to its fixup and returns `true`.
+pub fn apply(tf: &mut TrapFrame) -> bool {
+    match search(tf.elr as usize) {
+        Some(fixup) => {
+            tf.elr = fixup as u64;
+            true
+        }
+        None => false,
+    }
+}
@@ -0,0 +1,230 @@
+mod trap_frame;
+mod syndrome;
+mod fixup;
+pub mod esr;
+pub mod syscall;
+
+#[cfg(test)]
+mod tests;
+
+pub use self::trap_frame::TrapFrame;
+pub use self::syndrome::Syndrome;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use aarch64;
+use console::{ekprintln, log_error, log_warn};
+use gdbstub;
+use hwdebug;
+use irq;
+use oops::kernel_oops;
+use scheduler;
+use smp::MAX_CORES;
+use trace::{trace_event, Event};
+use vm::{kstack, Access, FaultError, FaultStatus};
+
+/// Where an exception was taken from: which vector table quarter it used.
+#[repr(u16)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Source {
+    CurrentSpEl0 = 0,
+    CurrentSpElx = 1,
+    LowerAArch64 = 2,
+    LowerAArch32 = 3,
+}
+
+/// The kind of an exception: which entry within a quarter it used.
+#[repr(u16)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Kind {
+    Synchronous = 0,
+    Irq = 1,
+    Fiq = 2,
+    SError = 3,
+}
+
+/// The source and kind of an exception, passed to `handle_exception` in `x0`
+/// as `source | kind << 16` by `vectors.S`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Info {
+    pub source: Source,
+    pub kind: Kind,
+}
+
+/// The number of exceptions currently being handled on each core.
+static DEPTH: [AtomicUsize; MAX_CORES] = [
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+];
+
+/// The number of IRQs currently being handled on each core.
+static IRQ_DEPTH: [AtomicUsize; MAX_CORES] = [
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+];
+
+/// The trap frame of the innermost exception being handled on each core, or
+/// 0.
+static FRAMES: [AtomicUsize; MAX_CORES] = [
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+];
+
+/// Returns `true` if an exception is being handled on this core, and the
+/// caller therefore must not block.
+pub fn in_exception() -> bool {
+    DEPTH[aarch64::affinity()].load(Ordering::Relaxed) > 0
+}
+
+/// Returns `true` if an IRQ is being handled on this core.
+pub fn in_interrupt() -> bool {
+    IRQ_DEPTH[aarch64::affinity()].load(Ordering::Relaxed) > 0
+}
+
+/// Returns the context the innermost exception being handled on this core
+/// interrupted, if any. For reporting panics in handlers.
+pub fn current_frame() -> Option<&'static TrapFrame> {
+    match FRAMES[aarch64::affinity()].load(Ordering::Relaxed) {
+        0 => None,
+        frame => Some(unsafe { &*(frame as *const TrapFrame) }),
+    }
+}
+
+/// Called by `vectors.S` for every exception taken to EL1, with the
+/// interrupted context saved in `tf`. Returns the trap frame to resume from:
+/// `tf`, unless a handler asked for a different task to run.
+#[no_mangle]
+pub extern fn handle_exception(info: Info, tf: &mut TrapFrame) -> *mut TrapFrame {
+    let depth = DEPTH[aarch64::affinity()].fetch_add(1, Ordering::Relaxed);
+    let outer = FRAMES[aarch64::affinity()].swap(tf as *mut TrapFrame as usize, Ordering::Relaxed);
+    if info.source == Source::LowerAArch64 {
+        scheduler::enter_kernel();
+    }
+    match info.kind {
+        Kind::Synchronous => handle_sync(info, Syndrome::from(tf.esr), tf),
+        Kind::Irq => {
+            trace_event!(Event::IrqEnter, tf.elr);
+            IRQ_DEPTH[aarch64::affinity()].fetch_add(1, Ordering::Relaxed);
+            irq::dispatch(tf);
+            IRQ_DEPTH[aarch64::affinity()].fetch_sub(1, Ordering::Relaxed);
+            trace_event!(Event::IrqExit, 0);
+        }
+        Kind::Fiq | Kind::SError => unhandled(info, tf),
+    }
+
+    // Only the outermost exception can switch tasks: a nested one returns
+    // into the handler it interrupted, which is not a task. A process that
+    // a signal terminates is switched out in turn.
+    let frame = if depth == 0 {
+        let mut frame = scheduler::schedule(tf);
+        while !scheduler::deliver_signal(unsafe { &mut *frame }) {
+            frame = scheduler::schedule(frame);
+        }
+        frame
+    } else {
+        tf as *mut TrapFrame
+    };
+    if unsafe { (*frame).el() } == 0 {
+        scheduler::leave_kernel();
+    }
+    FRAMES[aarch64::affinity()].store(outer, Ordering::Relaxed);
+    DEPTH[aarch64::affinity()].fetch_sub(1, Ordering::Relaxed);
+    frame
+}
+
+/// Handles a synchronous exception with syndrome `syndrome`.
+fn handle_sync(info: Info, syndrome: Syndrome, tf: &mut TrapFrame) {
+    match syndrome {
+        Syndrome::Brk(imm) => if !gdbstub::handle_brk(tf) {
+            log_warn!("brk #{} at {:#x}", imm, tf.elr);
+            // Unlike `svc`, `brk` leaves ELR pointing at itself.
+            tf.elr += 4;
+        },
+        // A step over a hardware breakpoint or watchpoint may be one GDB
+        // asked for too.
+        Syndrome::Step => if !hwdebug::handle_step(tf) && !gdbstub::handle_step(tf) {
+            unhandled(info, tf);
+        },
+        Syndrome::Breakpoint if tf.el() == 1 => hwdebug::handle_breakpoint(tf),
+        Syndrome::Watchpoint if tf.el() == 1 => hwdebug::handle_watchpoint(tf),
+        Syndrome::Svc(num) => {
+            trace_event!(Event::Syscall, num);
+            syscall::handle(num, tf)
+        }
+        Syndrome::DataAbort { from_lower: true } => {
+            user_fault(tf, Access::from_data_abort(tf.esr))
+        }
+        Syndrome::InstructionAbort { from_lower: true } => user_fault(tf, Access::Execute),
+        Syndrome::DataAbort { from_lower: false } => if !fixup::apply(tf) {
+            fatal(info, tf);
+        },
+        // A semihosting call with nothing to handle it.
+        Syndrome::Unknown if tf.el() == 1 => if !fixup::apply(tf) {
+            fatal(info, tf);
+        },
+        _ => unhandled(info, tf),
+    }
+}
+
+/// Handles an instruction or data abort from EL0 by mapping the page on
+/// demand. If that isn't possible, reports the fault and kills the process.
+fn user_fault(tf: &mut TrapFrame, access: Access) {
+    let addr = tf.far as usize;
+    let status = FaultStatus::from(tf.esr);
+    if let Err(error) = scheduler::handle_fault(addr, access, status) {
+        if error == FaultError::StackOverflow {
+            log_error!("stack overflow in PID {}", scheduler::current_id());
+        }
+        log_error!("process {} killed: {}: {}",
+                   scheduler::current_id(), error, esr::describe(tf.esr, tf.far));
+        log_error!("  PC {:#018x}  SP {:#018x}  ESR {:#010x}", tf.elr, tf.sp_el0, tf.esr);
+        scheduler::exit_current(scheduler::FAULT_EXIT_CODE);
+    }
+}
+
+/// Handles an exception no handler is for. One from a user process is a
+/// kernel oops, which kills the process; any other is fatal.
+fn unhandled(info: Info, tf: &mut TrapFrame) {
+    if info.source != Source::LowerAArch64 {
+        fatal(info, tf);
+    }
+
+    match info.kind {
+        Kind::Synchronous => kernel_oops!("unhandled {:?} exception from {:?}: {}",
+                                          info.kind, info.source, esr::describe(tf.esr, tf.far)),
+        _ => kernel_oops!("unhandled {:?} exception from {:?}", info.kind, info.source),
+    }
+}
+
+/// Reports an exception the kernel can't recover from and panics. The panic
+/// handler dumps `tf`, which `handle_exception()` recorded. If GDB is
+/// attached, the kernel stops for it first.
+fn fatal(info: Info, tf: &mut TrapFrame) -> ! {
+    gdbstub::fault(tf);
+    if let Syndrome::DataAbort { from_lower: false } = Syndrome::from(tf.esr) {
+        if let Some(bottom) = kstack::guarded_stack(tf.far as usize) {
+            report_stack_overflow(bottom);
+        }
+    }
+
+    match info.kind {
+        Kind::Synchronous => ekprintln!("{:?} exception from {:?}: {}",
+                                        info.kind, info.source, esr::describe(tf.esr, tf.far)),
+        _ => ekprintln!("{:?} exception from {:?}", info.kind, info.source),
+    }
+    panic!("unhandled exception");
+}
+
+/// Reports that the kernel stack starting at `bottom` overflowed, naming the
+/// process it belongs to. `vectors.S` has switched to an overflow stack.
+fn report_stack_overflow(bottom: usize) {
+    let reported = scheduler::with_stack_owner(bottom, |process| {
+        if process.page_table().is_some() {
+            ekprintln!("kernel stack overflow in PID {} ({})", process.id, process.name);
+        } else {
+            ekprintln!("kernel stack overflow in kthread {} (PID {})", process.name, process.id);
+        }
+    });
+
+    if reported.is_none() {
+        ekprintln!("kernel stack overflow in the stack at {:#x}", bottom);
+    }
+}
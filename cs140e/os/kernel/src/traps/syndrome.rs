@@ -0,0 +1,56 @@
+/// The class of a synchronous exception, decoded from `ESR_EL1`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Syndrome {
+    Unknown,
+    WfiWfe,
+    SimdFp,
+    IllegalExecutionState,
+    Svc(u16),
+    Hvc(u16),
+    Smc(u16),
+    MsrMrsSystem,
+    InstructionAbort { from_lower: bool },
+    PcAlignmentFault,
+    DataAbort { from_lower: bool },
+    SpAlignmentFault,
+    TrappedFp,
+    SError,
+    Breakpoint,
+    Step,
+    Watchpoint,
+    Brk(u16),
+    Other(u32),
+}
+
+impl From<u64> for Syndrome {
+    /// Decodes the exception class (`ESR.EC`, bits 31:26) and, for the
+    /// exception-generating instructions, their 16-bit immediate.
+    fn from(esr: u64) -> Syndrome {
+        use self::Syndrome::*;
+
+        let imm = esr as u16;
+        match (esr >> 26) & 0b111111 {
+            0b000000 => Unknown,
+            0b000001 => WfiWfe,
+            0b000111 => SimdFp,
+            0b001110 => IllegalExecutionState,
+            0b010101 => Svc(imm),
+            0b010110 => Hvc(imm),
+            0b010111 => Smc(imm),
+            0b011000 => MsrMrsSystem,
+            0b100000 => InstructionAbort { from_lower: true },
+            0b100001 => InstructionAbort { from_lower: false },
+            0b100010 => PcAlignmentFault,
+            0b100100 => DataAbort { from_lower: true },
+            0b100101 => DataAbort { from_lower: false },
+            0b100110 => SpAlignmentFault,
+            0b101100 => TrappedFp,
+            0b101111 => SError,
+            0b110000 | 0b110001 => Breakpoint,
+            0b110010 | 0b110011 => Step,
+            0b110100 | 0b110101 => Watchpoint,
+            0b111100 => Brk(imm),
+            ec => Other(ec as u32),
+        }
+    }
+}
@@ -0,0 +1,788 @@
+use std::cmp::min;
+use std::io::{self, SeekFrom};
+use std::mem;
+use std::ptr;
+use std::slice;
+use std::str;
+
+use allocator::SlabArc;
+use clock;
+use console::CONSOLE;
+use fs::vfs::{self, Kind, Metadata};
+use fs::worker::{self, Op, Reply};
+use ipc::{self, PortId};
+use net::Ipv4Addr;
+use net::{tcp, udp};
+use net::udp::SocketId;
+use process::{Id, Process, Resource, Signal};
+use process::files::{Fd, OpenFile};
+use random;
+use process::signal::Action;
+use scheduler::{self, WaitError};
+use sync::futex;
+use traps::TrapFrame;
+use vm::{self, Access};
+
+/// `exit(code: i32) -> !`
+pub const SYS_EXIT: u16 = 1;
+/// `write(buf: *const u8, len: usize) -> usize`: writes to the console.
+pub const SYS_WRITE: u16 = 2;
+/// `spawn(path: *const u8, path_len: usize, args: *const (*const u8, usize),
+/// argc: usize, env: *const (*const u8, usize), envc: usize) -> Id`: `env`
+/// holds `NAME=VALUE` strings.
+pub const SYS_SPAWN: u16 = 3;
+/// `wait(id: Id) -> i32`
+pub const SYS_WAIT: u16 = 4;
+/// `getpid() -> Id`
+pub const SYS_GETPID: u16 = 5;
+/// `fork() -> Id`: returns the child's ID in the parent and 0 in the child,
+/// which shares the parent's memory copy-on-write.
+pub const SYS_FORK: u16 = 6;
+/// `yield()`: gives up the rest of the time slice.
+pub const SYS_YIELD: u16 = 7;
+/// `port_create(capacity: usize) -> PortId`: creates a message port owned by
+/// the caller, closed when it exits.
+pub const SYS_PORT_CREATE: u16 = 8;
+/// `port_close(port: PortId)`
+pub const SYS_PORT_CLOSE: u16 = 9;
+/// `send(port: PortId, buf: *const u8, len: usize) -> usize`: blocks while
+/// the port is full.
+pub const SYS_SEND: u16 = 10;
+/// `recv(port: PortId, buf: *mut u8, len: usize) -> usize`: blocks while the
+/// port is empty. Returns the message's length.
+pub const SYS_RECV: u16 = 11;
+/// `try_recv(port: PortId, buf: *mut u8, len: usize) -> usize`: like `recv`,
+/// but fails with `WouldBlock` if the port is empty.
+pub const SYS_TRY_RECV: u16 = 12;
+/// `kill(id: Id, signal: u64)`: sends a signal to a user process.
+pub const SYS_KILL: u16 = 13;
+/// `sigaction(signal: u64, handler: usize) -> usize`: sets what a signal
+/// does, returning the old handler. `SIG_DFL` (0) selects the default
+/// action, `SIG_IGN` (1) ignores the signal, and anything else is a
+/// function called with the signal number.
+pub const SYS_SIGACTION: u16 = 14;
+/// `sigreturn() -> !`: returns from a signal handler. Made by the
+/// trampoline that handlers return to, not called directly.
+pub const SYS_SIGRETURN: u16 = 15;
+/// `sigmask(mask: u32) -> u32`: sets the mask of blocked signals, bit `n`
+/// blocking signal `n`, returning the old one.
+pub const SYS_SIGMASK: u16 = 16;
+/// `brk(addr: usize) -> usize`: moves the end of the heap, the program
+/// break, to `addr`, unless it is 0, and returns the break. The heap starts
+/// just above the program and its pages are mapped as they are touched.
+pub const SYS_BRK: u16 = 17;
+/// `times(id: Id, times: *mut [u64; 3])`: stores the microseconds process
+/// `id` has spent at EL0 and in the kernel, and the number of times it has
+/// been switched to.
+pub const SYS_TIMES: u16 = 18;
+/// `gettime(clock: u64, time: *mut [u64; 2])`: stores the time on `clock`
+/// as seconds and nanoseconds: since the UNIX epoch for `CLOCK_REALTIME`
+/// (0), and since boot for `CLOCK_MONOTONIC` (1).
+pub const SYS_GETTIME: u16 = 19;
+/// `getrandom(buf: *mut u8, len: usize) -> usize`: fills `buf` with random
+/// bytes from the hardware generator, at most `GETRANDOM_MAX` of them, and
+/// returns how many. Fails with `Io` if the generator has failed.
+pub const SYS_GETRANDOM: u16 = 20;
+/// `futex_wait(addr: *const u32, expected: u32)`: sleeps until woken by
+/// `futex_wake` on `addr`, unless the word there doesn't hold `expected`, in
+/// which case it fails at once with `WouldBlock`. May return early, so
+/// callers must check the word again.
+pub const SYS_FUTEX_WAIT: u16 = 21;
+/// `futex_wake(addr: *const u32, count: usize) -> usize`: wakes up to
+/// `count` processes waiting on `addr` and returns how many were woken.
+pub const SYS_FUTEX_WAKE: u16 = 22;
+/// `getrlimit(resource: u64, rlimit: *mut [u64; 2])`: stores the caller's
+/// limit on `resource` and its current use of it: `RLIMIT_MEMORY` (0),
+/// bytes of memory mapped, or `RLIMIT_PORTS` (1), ports owned.
+/// `RLIM_INFINITY` (`!0`) is no limit.
+pub const SYS_GETRLIMIT: u16 = 23;
+/// `setrlimit(resource: u64, limit: u64) -> u64`: sets the caller's limit
+/// on `resource`, which processes it forks or spawns inherit, and returns
+/// the old one. Lowering a limit below the current use is allowed: a
+/// process over its memory limit maps no more pages and is the first the
+/// out-of-memory killer considers.
+pub const SYS_SETRLIMIT: u16 = 24;
+/// `open(path: *const u8, path_len: usize, flags: u64) -> Fd`: opens the
+/// file at `path` as the lowest free descriptor. `flags` is
+/// `O_RDONLY` (0), `O_WRONLY` (1), or `O_RDWR` (2), with any of `O_CREAT`
+/// (0o100), `O_TRUNC` (0o1000), and `O_APPEND` (0o2000).
+pub const SYS_OPEN: u16 = 25;
+/// `close(fd: Fd)`
+pub const SYS_CLOSE: u16 = 26;
+/// `read(fd: Fd, buf: *mut u8, len: usize) -> usize`: reads at most
+/// `IO_MAX` bytes. Returns 0 at the end of the file.
+pub const SYS_READ: u16 = 27;
+/// `write_fd(fd: Fd, buf: *const u8, len: usize) -> usize`: writes at most
+/// `IO_MAX` bytes.
+pub const SYS_WRITE_FD: u16 = 28;
+/// `lseek(fd: Fd, offset: i64, whence: u64) -> u64`: moves the offset to
+/// `offset` from the start (0), the current offset (1), or the end (2), and
+/// returns it.
+pub const SYS_LSEEK: u16 = 29;
+/// `stat(path: *const u8, path_len: usize, stat: *mut [u64; 3])`: stores
+/// what the entry at `path` is, file (0) or directory (1), its size, and
+/// whether it is read only.
+pub const SYS_STAT: u16 = 30;
+/// `fstat(fd: Fd, stat: *mut [u64; 3])`: like `stat`, for an open file.
+pub const SYS_FSTAT: u16 = 31;
+/// `chdir(path: *const u8, path_len: usize)`: makes the directory at `path`
+/// the current directory.
+pub const SYS_CHDIR: u16 = 32;
+/// `getcwd(buf: *mut u8, len: usize) -> usize`: stores the current
+/// directory in `buf` and returns its length, or fails with `InvalidArgument`
+/// if it doesn't fit.
+pub const SYS_GETCWD: u16 = 33;
+/// `udp_bind(port: u64) -> SocketId`: opens a UDP socket bound to `port`
+/// on every interface, or to an unused port if it is 0. Fails with
+/// `AddrInUse` if another socket has the port. The caller owns the socket,
+/// which is closed when it exits.
+pub const SYS_UDP_BIND: u16 = 34;
+/// `udp_close(socket: SocketId)`
+pub const SYS_UDP_CLOSE: u16 = 35;
+/// `udp_sendto(socket: SocketId, buf: *const u8, len: usize, addr: u32,
+/// port: u64) -> usize`: sends `buf` as one datagram to `port` at the IPv4
+/// address whose bits, most significant first, are `addr`.
+pub const SYS_UDP_SENDTO: u16 = 36;
+/// `udp_recvfrom(socket: SocketId, buf: *mut u8, len: usize, from: *mut
+/// [u64; 2]) -> usize`: blocks until a datagram arrives, stores as much of
+/// it as fits in `buf` and returns that length, and, unless `from` is
+/// null, stores the sender's address and port there.
+pub const SYS_UDP_RECVFROM: u16 = 37;
+/// `udp_try_recvfrom(socket: SocketId, buf: *mut u8, len: usize, from: *mut
+/// [u64; 2]) -> usize`: like `udp_recvfrom`, but fails with `WouldBlock`
+/// if no datagram has arrived.
+pub const SYS_UDP_TRY_RECVFROM: u16 = 38;
+/// `tcp_listen(port: u64, backlog: usize) -> SocketId`: opens a TCP socket
+/// listening on `port` on every interface, which queues up to `backlog`
+/// connections until they are accepted. Fails with `AddrInUse` if another
+/// socket listens on the port. The caller owns the socket, and the
+/// connections it accepts, which are closed when it exits.
+pub const SYS_TCP_LISTEN: u16 = 39;
+/// `tcp_accept(socket: SocketId, from: *mut [u64; 2]) -> SocketId`: blocks
+/// until a connection to the listening `socket` is established, returns it,
+/// and, unless `from` is null, stores the peer's address and port there.
+pub const SYS_TCP_ACCEPT: u16 = 40;
+/// `tcp_connect(addr: u32, port: u64) -> SocketId`: opens a connection to
+/// `port` at the IPv4 address whose bits, most significant first, are
+/// `addr`, and returns without waiting for it to be established.
+pub const SYS_TCP_CONNECT: u16 = 41;
+/// `tcp_wait(socket: SocketId)`: blocks until the connection `socket` is
+/// established. Fails with `ConnectionRefused` or `TimedOut` if it can't be.
+pub const SYS_TCP_WAIT: u16 = 42;
+/// `tcp_send(socket: SocketId, buf: *const u8, len: usize) -> usize`: blocks
+/// until the connection can buffer some of `buf`, and returns how much it
+/// took.
+pub const SYS_TCP_SEND: u16 = 43;
+/// `tcp_recv(socket: SocketId, buf: *mut u8, len: usize) -> usize`: blocks
+/// until data arrives, stores as much as fits in `buf`, and returns that
+/// length, or 0 once the peer has closed the connection.
+pub const SYS_TCP_RECV: u16 = 44;
+/// `tcp_close(socket: SocketId)`: closes a listening socket, or sends what
+/// a connection has buffered and then closes it.
+pub const SYS_TCP_CLOSE: u16 = 45;
+
+/// Paths given to system calls are resolved against the calling process's
+/// current directory unless they are absolute. See `vfs::canonicalize()`.
+
+/// The most bytes one `getrandom` call returns, bounding the time spent
+/// waiting on the generator.
+pub const GETRANDOM_MAX: u64 = 256;
+
+/// The most bytes one `read` or `write_fd` call moves, bounding the copy
+/// the kernel makes.
+pub const IO_MAX: u64 = 64 * 1024;
+
+/// The error codes a system call can return in `x7`. Success is 0.
+#[repr(u64)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    Unknown = 1,
+    NoSys = 2,
+    BadAddress = 3,
+    InvalidArgument = 4,
+    NotFound = 5,
+    NoMemory = 6,
+    NoChild = 7,
+    Io = 8,
+    WouldBlock = 9,
+    PermissionDenied = 10,
+    LimitExceeded = 11,
+    BadDescriptor = 12,
+    NotADirectory = 13,
+    AddrInUse = 14,
+    ConnectionRefused = 15,
+    ConnectionReset = 16,
+    TimedOut = 17,
+    NotConnected = 18,
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        if vfs::is_not_a_directory(&error) {
+            return Error::NotADirectory;
+        }
+
+        match error.kind() {
+            io::ErrorKind::NotFound => Error::NotFound,
+            io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => Error::InvalidArgument,
+            io::ErrorKind::PermissionDenied => Error::PermissionDenied,
+            io::ErrorKind::WouldBlock => Error::WouldBlock,
+            io::ErrorKind::AddrInUse => Error::AddrInUse,
+            io::ErrorKind::ConnectionRefused => Error::ConnectionRefused,
+            io::ErrorKind::ConnectionReset => Error::ConnectionReset,
+            io::ErrorKind::TimedOut => Error::TimedOut,
+            io::ErrorKind::NotConnected | io::ErrorKind::BrokenPipe => Error::NotConnected,
+            _ => Error::Io,
+        }
+    }
+}
+
+impl From<futex::Error> for Error {
+    fn from(error: futex::Error) -> Error {
+        match error {
+            futex::Error::Changed => Error::WouldBlock,
+            futex::Error::Invalid => Error::InvalidArgument,
+        }
+    }
+}
+
+impl From<ipc::Error> for Error {
+    fn from(error: ipc::Error) -> Error {
+        match error {
+            ipc::Error::NoPort => Error::NotFound,
+            ipc::Error::NotOwner => Error::PermissionDenied,
+            ipc::Error::BadCapacity | ipc::Error::TooLong => Error::InvalidArgument,
+            ipc::Error::Full | ipc::Error::Empty => Error::WouldBlock,
+            ipc::Error::TooMany => Error::LimitExceeded,
+        }
+    }
+}
+
+/// Handles the system call `num` made with `svc #num` from the context in
+/// `tf`. Arguments are in `x0` through `x5`. The result is returned in `x0`
+/// and the error code, or 0, in `x7`.
+pub fn handle(num: u16, tf: &mut TrapFrame) {
+    let result = match num {
+        SYS_EXIT => {
+            scheduler::exit_current(tf.x[0] as i32);
+            Ok(0)
+        }
+        SYS_WRITE => sys_write(tf.x[0], tf.x[1]),
+        SYS_SPAWN => sys_spawn(tf.x[0], tf.x[1], (tf.x[2], tf.x[3]), (tf.x[4], tf.x[5])),
+        SYS_WAIT => sys_wait(tf.x[0], tf),
+        SYS_GETPID => Ok(scheduler::current_id()),
+        SYS_FORK => scheduler::fork(tf).ok_or(Error::NoMemory),
+        SYS_YIELD => {
+            scheduler::request_resched();
+            Ok(0)
+        }
+        SYS_PORT_CREATE => {
+            let limit = scheduler::limit(Resource::Ports);
+            ipc::create(scheduler::current_id(), tf.x[0] as usize, limit).map_err(Error::from)
+        }
+        SYS_PORT_CLOSE => {
+            ipc::close(tf.x[0], scheduler::current_id()).map(|_| 0).map_err(Error::from)
+        }
+        SYS_SEND => sys_send(tf.x[0], tf.x[1], tf.x[2], tf),
+        SYS_RECV => sys_recv(tf.x[0], tf.x[1], tf.x[2], Some(tf)),
+        SYS_TRY_RECV => sys_recv(tf.x[0], tf.x[1], tf.x[2], None),
+        SYS_KILL => sys_kill(tf.x[0], tf.x[1]),
+        SYS_SIGACTION => sys_sigaction(tf.x[0], tf.x[1]),
+        SYS_SIGRETURN => {
+            // Every register is restored, so there's no result to set.
+            scheduler::signal_return(tf);
+            return;
+        }
+        SYS_SIGMASK => Ok(scheduler::set_signal_mask(tf.x[0] as u32) as u64),
+        SYS_BRK => scheduler::set_brk(tf.x[0] as usize).map(|brk| brk as u64).ok_or(Error::NoMemory),
+        SYS_TIMES => sys_times(tf.x[0], tf.x[1]),
+        SYS_GETTIME => sys_gettime(tf.x[0], tf.x[1]),
+        SYS_GETRANDOM => sys_getrandom(tf.x[0], tf.x[1]),
+        SYS_FUTEX_WAIT => sys_futex_wait(tf.x[0], tf.x[1] as u32),
+        SYS_FUTEX_WAKE => sys_futex_wake(tf.x[0], tf.x[1]),
+        SYS_GETRLIMIT => sys_getrlimit(tf.x[0], tf.x[1]),
+        SYS_SETRLIMIT => sys_setrlimit(tf.x[0], tf.x[1]),
+        SYS_OPEN => sys_open(tf.x[0], tf.x[1], tf.x[2], tf),
+        SYS_CLOSE => sys_close(tf.x[0]),
+        SYS_READ => sys_read(tf.x[0], tf.x[1], tf.x[2], tf),
+        SYS_WRITE_FD => sys_write_fd(tf.x[0], tf.x[1], tf.x[2], tf),
+        SYS_LSEEK => sys_lseek(tf.x[0], tf.x[1] as i64, tf.x[2], tf),
+        SYS_STAT => sys_stat(tf.x[0], tf.x[1], tf.x[2], tf),
+        SYS_FSTAT => sys_fstat(tf.x[0], tf.x[1], tf),
+        SYS_CHDIR => sys_chdir(tf.x[0], tf.x[1], tf),
+        SYS_GETCWD => sys_getcwd(tf.x[0], tf.x[1]),
+        SYS_UDP_BIND => sys_udp_bind(tf.x[0]),
+        SYS_UDP_CLOSE => {
+            udp::close(tf.x[0], scheduler::current_id()).map(|_| 0).map_err(Error::from)
+        }
+        SYS_UDP_SENDTO => sys_udp_sendto(tf.x[0], tf.x[1], tf.x[2], tf.x[3], tf.x[4]),
+        SYS_UDP_RECVFROM => sys_udp_recvfrom(tf.x[0], tf.x[1], tf.x[2], tf.x[3], Some(tf)),
+        SYS_UDP_TRY_RECVFROM => sys_udp_recvfrom(tf.x[0], tf.x[1], tf.x[2], tf.x[3], None),
+        SYS_TCP_LISTEN => sys_tcp_listen(tf.x[0], tf.x[1]),
+        SYS_TCP_ACCEPT => sys_tcp_accept(tf.x[0], tf.x[1], tf),
+        SYS_TCP_CONNECT => sys_tcp_connect(tf.x[0], tf.x[1]),
+        SYS_TCP_WAIT => match tcp::park_established(tf.x[0]) {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                tf.elr -= 4;
+                Ok(tf.x[0])
+            }
+            result => result.map(|_| 0).map_err(Error::from),
+        },
+        SYS_TCP_SEND => sys_tcp_send(tf.x[0], tf.x[1], tf.x[2], tf),
+        SYS_TCP_RECV => sys_tcp_recv(tf.x[0], tf.x[1], tf.x[2], tf),
+        SYS_TCP_CLOSE => {
+            tcp::close(tf.x[0], scheduler::current_id()).map(|_| 0).map_err(Error::from)
+        }
+        _ => Err(Error::NoSys),
+    };
+
+    match result {
+        Ok(value) => {
+            tf.x[0] = value;
+            tf.x[7] = 0;
+        }
+        Err(error) => tf.x[7] = error as u64,
+    }
+}
+
+/// Returns the user buffer at `ptr` of `len` bytes, or `BadAddress` if the
+/// calling process can't read all of it. Pages not yet mapped on demand are
+/// mapped first.
+fn user_slice<'a>(ptr: u64, len: u64) -> Result<&'a [u8], Error> {
+    let (ptr, len) = (ptr as usize, len as usize);
+    if !scheduler::populate(ptr, len, Access::Read) || !vm::user_accessible(ptr, len, false) {
+        return Err(Error::BadAddress);
+    }
+
+    Ok(unsafe { slice::from_raw_parts(ptr as *const u8, len) })
+}
+
+/// Like `user_slice()`, but for a buffer the calling process can write.
+fn user_slice_mut<'a>(ptr: u64, len: u64) -> Result<&'a mut [u8], Error> {
+    let (ptr, len) = (ptr as usize, len as usize);
+    if !scheduler::populate(ptr, len, Access::Write) || !vm::user_accessible(ptr, len, true) {
+        return Err(Error::BadAddress);
+    }
+
+    Ok(unsafe { slice::from_raw_parts_mut(ptr as *mut u8, len) })
+}
+
+/// Returns the UTF-8 user string at `ptr` of `len` bytes.
+fn user_str<'a>(ptr: u64, len: u64) -> Result<&'a str, Error> {
+    str::from_utf8(user_slice(ptr, len)?).map_err(|_| Error::InvalidArgument)
+}
+
+fn sys_write(ptr: u64, len: u64) -> Result<u64, Error> {
+    let bytes = user_slice(ptr, len)?;
+    if let Some(session) = scheduler::session() {
+        session.write_bytes(bytes);
+        return Ok(len);
+    }
+
+    let mut console = CONSOLE.lock();
+    for &byte in bytes {
+        console.write_byte(byte);
+    }
+    Ok(len)
+}
+
+/// Returns the `count` user strings described by the `(pointer, length)`
+/// pairs at `pairs`.
+fn user_strs<'a>(pairs: u64, count: u64) -> Result<Vec<&'a str>, Error> {
+    let pairs = user_slice(pairs, count.checked_mul(16).ok_or(Error::BadAddress)?)?;
+    let mut strs = Vec::with_capacity(count as usize);
+    for pair in pairs.chunks(16) {
+        let pair = pair.as_ptr() as *const u64;
+        let (ptr, len) = unsafe { (pair.read_unaligned(), pair.add(1).read_unaligned()) };
+        strs.push(user_str(ptr, len)?);
+    }
+    Ok(strs)
+}
+
+fn sys_spawn(path: u64, path_len: u64, args: (u64, u64), env: (u64, u64)) -> Result<u64, Error> {
+    let path = scheduler::resolve(user_str(path, path_len)?);
+    let argv = user_strs(args.0, args.1)?;
+    let envp = user_strs(env.0, env.1)?;
+    let process = Process::load(path, &argv, &envp)?;
+    scheduler::add(process).ok_or(Error::NoMemory)
+}
+
+fn sys_wait(id: Id, tf: &mut TrapFrame) -> Result<u64, Error> {
+    match scheduler::try_wait(id) {
+        Ok(code) => Ok(code as u64),
+        Err(WaitError::NoChild) => Err(Error::NoChild),
+        Err(WaitError::Blocked) => {
+            // Run the `svc` again, with `x0` still holding `id`, once a
+            // child has exited.
+            tf.elr -= 4;
+            Ok(id)
+        }
+    }
+}
+
+fn sys_send(port: PortId, ptr: u64, len: u64, tf: &mut TrapFrame) -> Result<u64, Error> {
+    let msg = user_slice(ptr, len)?;
+    match ipc::park_send(port, msg) {
+        Ok(()) => Ok(len),
+        Err(ipc::Error::Full) => {
+            // Run the `svc` again, with the arguments intact, once a message
+            // has been received.
+            tf.elr -= 4;
+            Ok(port)
+        }
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Receives into the user buffer at `ptr`. With `tf`, blocks by running the
+/// `svc` again once a message has been sent; without, fails with
+/// `WouldBlock`.
+fn sys_recv(port: PortId, ptr: u64, len: u64, tf: Option<&mut TrapFrame>) -> Result<u64, Error> {
+    let buf = user_slice_mut(ptr, len)?;
+    let result = match tf {
+        Some(tf) => match ipc::park_recv(port, buf) {
+            Err(ipc::Error::Empty) => {
+                tf.elr -= 4;
+                return Ok(port);
+            }
+            result => result,
+        },
+        None => ipc::try_recv(port, buf),
+    };
+
+    result.map(|len| len as u64).map_err(Error::from)
+}
+
+fn sys_kill(id: Id, signal: u64) -> Result<u64, Error> {
+    let signal = Signal::from_num(signal).ok_or(Error::InvalidArgument)?;
+    if scheduler::signal(id, signal) { Ok(0) } else { Err(Error::NotFound) }
+}
+
+fn sys_sigaction(signal: u64, handler: u64) -> Result<u64, Error> {
+    let signal = Signal::from_num(signal).ok_or(Error::InvalidArgument)?;
+    scheduler::set_signal_action(signal, Action::from_handler(handler as usize))
+        .map(|old| old.handler() as u64)
+        .ok_or(Error::InvalidArgument)
+}
+
+fn sys_times(id: Id, ptr: u64) -> Result<u64, Error> {
+    let info = scheduler::info(id).ok_or(Error::NotFound)?;
+    let times = [info.user_time, info.kernel_time, info.switches];
+    let buf = user_slice_mut(ptr, mem::size_of_val(&times) as u64)?;
+    unsafe { ptr::write_unaligned(buf.as_mut_ptr() as *mut [u64; 3], times); }
+    Ok(0)
+}
+
+fn sys_gettime(id: u64, ptr: u64) -> Result<u64, Error> {
+    let us = match id {
+        clock::CLOCK_REALTIME => clock::now_us(),
+        clock::CLOCK_MONOTONIC => clock::monotonic_us(),
+        _ => return Err(Error::InvalidArgument),
+    };
+    let time = [us / 1_000_000, us % 1_000_000 * 1000];
+    let buf = user_slice_mut(ptr, mem::size_of_val(&time) as u64)?;
+    unsafe { ptr::write_unaligned(buf.as_mut_ptr() as *mut [u64; 2], time); }
+    Ok(0)
+}
+
+fn sys_getrandom(ptr: u64, len: u64) -> Result<u64, Error> {
+    let len = min(len, GETRANDOM_MAX);
+    let buf = user_slice_mut(ptr, len)?;
+    random::fill(buf).map_err(|_| Error::Io)?;
+    Ok(len)
+}
+
+fn sys_futex_wait(addr: u64, expected: u32) -> Result<u64, Error> {
+    user_slice(addr, 4)?;
+    futex::park(addr as usize, expected)?;
+    Ok(0)
+}
+
+fn sys_futex_wake(addr: u64, count: u64) -> Result<u64, Error> {
+    Ok(futex::wake(addr as usize, count as usize)? as u64)
+}
+
+fn sys_getrlimit(resource: u64, ptr: u64) -> Result<u64, Error> {
+    let resource = Resource::from_num(resource).ok_or(Error::InvalidArgument)?;
+    let rlimit = [scheduler::limit(resource), scheduler::usage(resource)];
+    let buf = user_slice_mut(ptr, mem::size_of_val(&rlimit) as u64)?;
+    unsafe { ptr::write_unaligned(buf.as_mut_ptr() as *mut [u64; 2], rlimit); }
+    Ok(0)
+}
+
+fn sys_setrlimit(resource: u64, limit: u64) -> Result<u64, Error> {
+    let resource = Resource::from_num(resource).ok_or(Error::InvalidArgument)?;
+    Ok(scheduler::set_limit(resource, limit))
+}
+
+/// Hands `op()` to a file I/O worker for system call `num`, made from `tf`.
+/// Returns its reply once it is done, or `None` after arranging for the
+/// `svc` to run again, with the arguments intact, when it is. See
+/// `fs::worker::call()`.
+fn file_io<F: FnOnce() -> Op>(num: u16, tf: &mut TrapFrame, op: F) -> Option<Result<Reply, Error>> {
+    match worker::call(num, op) {
+        Some(result) => Some(result.map_err(Error::from)),
+        None => {
+            tf.elr -= 4;
+            None
+        }
+    }
+}
+
+/// Returns the running process's file open as `fd`.
+fn open_file(fd: Fd) -> Result<SlabArc<OpenFile>, Error> {
+    scheduler::with_files(|files| files.get(fd)).and_then(|file| file).ok_or(Error::BadDescriptor)
+}
+
+/// Returns the user path at `ptr` of `len` bytes, resolved against the
+/// calling process's current directory.
+fn user_path(ptr: u64, len: u64) -> Result<PathBuf, Error> {
+    Ok(scheduler::resolve(user_str(ptr, len)?))
+}
+
+/// Stores `metadata` as the `[u64; 3]` of `stat` at `ptr`.
+fn write_stat(ptr: u64, metadata: Metadata) -> Result<u64, Error> {
+    let kind = match metadata.kind {
+        Kind::File => 0,
+        Kind::Dir => 1,
+    };
+    let stat = [kind, metadata.size, metadata.read_only as u64];
+    let buf = user_slice_mut(ptr, mem::size_of_val(&stat) as u64)?;
+    unsafe { ptr::write_unaligned(buf.as_mut_ptr() as *mut [u64; 3], stat); }
+    Ok(0)
+}
+
+fn sys_open(ptr: u64, len: u64, flags: u64, tf: &mut TrapFrame) -> Result<u64, Error> {
+    let path = user_path(ptr, len)?;
+    let file = match file_io(SYS_OPEN, tf, || Op::Open(path, flags)) {
+        Some(Ok(Reply::File(file))) => file.share().ok_or(Error::NoMemory)?,
+        Some(Ok(_)) => return Err(Error::Unknown),
+        Some(Err(error)) => return Err(error),
+        None => return Ok(ptr),
+    };
+
+    // If the table is full, the file is closed as it is dropped.
+    scheduler::with_files(|files| files.insert(file))
+        .and_then(|fd| fd)
+        .ok_or(Error::LimitExceeded)
+}
+
+fn sys_close(fd: Fd) -> Result<u64, Error> {
+    let file = scheduler::with_files(|files| files.remove(fd)).and_then(|file| file);
+    // The file is closed here, outside the scheduler's lock, if this was its
+    // last descriptor.
+    file.map(|_| 0).ok_or(Error::BadDescriptor)
+}
+
+fn sys_read(fd: Fd, ptr: u64, len: u64, tf: &mut TrapFrame) -> Result<u64, Error> {
+    let file = open_file(fd)?;
+    if !file.readable() {
+        return Err(Error::BadDescriptor);
+    }
+
+    let len = min(len, IO_MAX);
+    user_slice_mut(ptr, len)?;
+    let data = match file_io(SYS_READ, tf, || Op::Read(file, len as usize)) {
+        Some(Ok(Reply::Data(data))) => data,
+        Some(Ok(_)) => return Err(Error::Unknown),
+        Some(Err(error)) => return Err(error),
+        None => return Ok(fd),
+    };
+
+    let buf = user_slice_mut(ptr, data.len() as u64)?;
+    buf.copy_from_slice(&data);
+    Ok(data.len() as u64)
+}
+
+fn sys_write_fd(fd: Fd, ptr: u64, len: u64, tf: &mut TrapFrame) -> Result<u64, Error> {
+    let file = open_file(fd)?;
+    if !file.writable() {
+        return Err(Error::BadDescriptor);
+    }
+
+    let buf = user_slice(ptr, min(len, IO_MAX))?;
+    match file_io(SYS_WRITE_FD, tf, || Op::Write(file, buf.to_vec())) {
+        Some(Ok(Reply::Count(n))) => Ok(n),
+        Some(Ok(_)) => Err(Error::Unknown),
+        Some(Err(error)) => Err(error),
+        None => Ok(fd),
+    }
+}
+
+fn sys_lseek(fd: Fd, offset: i64, whence: u64, tf: &mut TrapFrame) -> Result<u64, Error> {
+    let pos = match whence {
+        0 if offset >= 0 => SeekFrom::Start(offset as u64),
+        1 => SeekFrom::Current(offset),
+        2 => SeekFrom::End(offset),
+        _ => return Err(Error::InvalidArgument),
+    };
+
+    let file = open_file(fd)?;
+    match file_io(SYS_LSEEK, tf, || Op::Seek(file, pos)) {
+        Some(Ok(Reply::Count(offset))) => Ok(offset),
+        Some(Ok(_)) => Err(Error::Unknown),
+        Some(Err(error)) => Err(error),
+        None => Ok(fd),
+    }
+}
+
+fn sys_stat(ptr: u64, len: u64, stat: u64, tf: &mut TrapFrame) -> Result<u64, Error> {
+    let path = user_path(ptr, len)?;
+    match file_io(SYS_STAT, tf, || Op::Stat(path)) {
+        Some(Ok(Reply::Metadata(metadata))) => write_stat(stat, metadata),
+        Some(Ok(_)) => Err(Error::Unknown),
+        Some(Err(error)) => Err(error),
+        None => Ok(ptr),
+    }
+}
+
+fn sys_fstat(fd: Fd, stat: u64, tf: &mut TrapFrame) -> Result<u64, Error> {
+    let file = open_file(fd)?;
+    match file_io(SYS_FSTAT, tf, || Op::Fstat(file)) {
+        Some(Ok(Reply::Metadata(metadata))) => write_stat(stat, metadata),
+        Some(Ok(_)) => Err(Error::Unknown),
+        Some(Err(error)) => Err(error),
+        None => Ok(fd),
+    }
+}
+
+fn sys_chdir(ptr: u64, len: u64, tf: &mut TrapFrame) -> Result<u64, Error> {
+    let path = user_path(ptr, len)?;
+    let reply = file_io(SYS_CHDIR, tf, || Op::Stat(path.clone()));
+    match reply {
+        Some(Ok(Reply::Metadata(metadata))) => match metadata.kind {
+            Kind::Dir => {
+                scheduler::set_cwd(path);
+                Ok(0)
+            }
+            Kind::File => Err(Error::NotADirectory),
+        },
+        Some(Ok(_)) => Err(Error::Unknown),
+        Some(Err(error)) => Err(error),
+        None => Ok(ptr),
+    }
+}
+
+fn sys_getcwd(ptr: u64, len: u64) -> Result<u64, Error> {
+    let cwd = scheduler::cwd();
+    let cwd = cwd.to_str().ok_or(Error::Unknown)?.as_bytes();
+    if cwd.len() as u64 > len {
+        return Err(Error::InvalidArgument);
+    }
+
+    user_slice_mut(ptr, cwd.len() as u64)?.copy_from_slice(cwd);
+    Ok(cwd.len() as u64)
+}
+
+fn sys_udp_bind(port: u64) -> Result<u64, Error> {
+    if port > 0xFFFF {
+        return Err(Error::InvalidArgument);
+    }
+    Ok(udp::bind(scheduler::current_id(), port as u16)?)
+}
+
+fn sys_udp_sendto(socket: SocketId, ptr: u64, len: u64, addr: u64, port: u64)
+    -> Result<u64, Error>
+{
+    if addr > 0xFFFF_FFFF || port > 0xFFFF {
+        return Err(Error::InvalidArgument);
+    }
+
+    let buf = user_slice(ptr, len)?;
+    udp::send_to(socket, Ipv4Addr::from_u32(addr as u32), port as u16, buf)?;
+    Ok(len)
+}
+
+/// Receives into the user buffer at `ptr`. With `tf`, blocks by running the
+/// `svc` again once a datagram has arrived; without, fails with
+/// `WouldBlock`.
+fn sys_udp_recvfrom(socket: SocketId, ptr: u64, len: u64, from: u64, tf: Option<&mut TrapFrame>)
+    -> Result<u64, Error>
+{
+    let buf = user_slice_mut(ptr, len)?;
+    if from != 0 {
+        user_slice_mut(from, 16)?;
+    }
+
+    let result = match tf {
+        Some(tf) => match udp::park_recv_from(socket, buf) {
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                tf.elr -= 4;
+                return Ok(socket);
+            }
+            result => result,
+        },
+        None => udp::try_recv_from(socket, buf),
+    };
+
+    let (len, addr, port) = result?;
+    if from != 0 {
+        let sender = [addr.to_u32() as u64, port as u64];
+        let buf = user_slice_mut(from, mem::size_of_val(&sender) as u64)?;
+        unsafe { ptr::write_unaligned(buf.as_mut_ptr() as *mut [u64; 2], sender); }
+    }
+    Ok(len as u64)
+}
+
+fn sys_tcp_listen(port: u64, backlog: u64) -> Result<u64, Error> {
+    if port == 0 || port > 0xFFFF {
+        return Err(Error::InvalidArgument);
+    }
+    Ok(tcp::listen(scheduler::current_id(), port as u16, backlog as usize)?)
+}
+
+/// Accepts a connection, blocking by running the `svc` again once one is
+/// established.
+fn sys_tcp_accept(socket: SocketId, from: u64, tf: &mut TrapFrame) -> Result<u64, Error> {
+    if from != 0 {
+        user_slice_mut(from, 16)?;
+    }
+
+    let (id, addr, port) = match tcp::park_accept(socket) {
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+            tf.elr -= 4;
+            return Ok(socket);
+        }
+        result => result?,
+    };
+    if from != 0 {
+        let peer = [addr.to_u32() as u64, port as u64];
+        let buf = user_slice_mut(from, mem::size_of_val(&peer) as u64)?;
+        unsafe { ptr::write_unaligned(buf.as_mut_ptr() as *mut [u64; 2], peer); }
+    }
+    Ok(id)
+}
+
+fn sys_tcp_connect(addr: u64, port: u64) -> Result<u64, Error> {
+    if addr > 0xFFFF_FFFF || port == 0 || port > 0xFFFF {
+        return Err(Error::InvalidArgument);
+    }
+    let addr = Ipv4Addr::from_u32(addr as u32);
+    Ok(tcp::start_connect(scheduler::current_id(), addr, port as u16)?)
+}
+
+/// Sends from the user buffer at `ptr`, at most `IO_MAX` bytes, blocking by
+/// running the `svc` again once there is room.
+fn sys_tcp_send(socket: SocketId, ptr: u64, len: u64, tf: &mut TrapFrame) -> Result<u64, Error> {
+    let buf = user_slice(ptr, min(len, IO_MAX))?;
+    match tcp::park_send(socket, buf) {
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+            tf.elr -= 4;
+            Ok(socket)
+        }
+        result => Ok(result? as u64),
+    }
+}
+
+/// Receives into the user buffer at `ptr`, blocking by running the `svc`
+/// again once data has arrived.
+fn sys_tcp_recv(socket: SocketId, ptr: u64, len: u64, tf: &mut TrapFrame) -> Result<u64, Error> {
+    let buf = user_slice_mut(ptr, min(len, IO_MAX))?;
+    match tcp::park_recv(socket, buf) {
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+            tf.elr -= 4;
+            Ok(socket)
+        }
+        result => Ok(result? as u64),
+    }
+}
@@ -0,0 +1,88 @@
+mod esr {
+    use traps::esr::describe;
+
+    const EC_SVC: u64 = 0b010101 << 26;
+    const EC_INSTRUCTION_ABORT_EL1: u64 = 0b100001 << 26;
+    const EC_PC_ALIGNMENT: u64 = 0b100010 << 26;
+    const EC_DATA_ABORT_EL0: u64 = 0b100100 << 26;
+    const EC_DATA_ABORT_EL1: u64 = 0b100101 << 26;
+    const EC_BRK: u64 = 0b111100 << 26;
+
+    const ISV: u64 = 1 << 24;
+    const FNV: u64 = 1 << 10;
+    const CM: u64 = 1 << 8;
+    const S1PTW: u64 = 1 << 7;
+    const WNR: u64 = 1 << 6;
+
+    fn line(esr: u64, far: u64) -> String {
+        describe(esr, far).to_string()
+    }
+
+    #[test]
+    fn exception_generating_instructions() {
+        assert_eq!(line(EC_SVC | 42, 0xdead), "Supervisor call #42");
+        assert_eq!(line(EC_BRK | 0xf000, 0), "BRK instruction #61440");
+        // The immediate is only the low 16 bits of the ISS.
+        assert_eq!(line(EC_SVC | 0x1_0007, 0), "Supervisor call #7");
+    }
+
+    #[test]
+    fn data_aborts() {
+        let esr = EC_DATA_ABORT_EL0 | ISV | 0b11 << 22 | WNR | 0b000111;
+        assert_eq!(line(esr, 0xffff_ff80_0001_2340),
+                   "Data abort from EL0, write of 8 bytes, translation fault level 3, \
+                    address 0xffffff8000012340");
+
+        // Without ISV, the access size is unknown.
+        let esr = EC_DATA_ABORT_EL1 | CM | 0b100001;
+        assert_eq!(line(esr, 0x10),
+                   "Data abort from EL1, read by cache maintenance, alignment fault, address 0x10");
+
+        let esr = EC_DATA_ABORT_EL1 | FNV | S1PTW | 0b010000;
+        assert_eq!(line(esr, 0x10),
+                   "Data abort from EL1, read, synchronous external abort on a table walk, \
+                    address unknown");
+    }
+
+    #[test]
+    fn instruction_aborts() {
+        // ISV and CM mean nothing for an instruction fetch.
+        let esr = EC_INSTRUCTION_ABORT_EL1 | ISV | CM | 0b001101;
+        assert_eq!(line(esr, 0x8_0000),
+                   "Instruction abort from EL1, execute, permission fault level 1, \
+                    address 0x80000");
+    }
+
+    #[test]
+    fn reserved_fault_status() {
+        assert_eq!(line(EC_DATA_ABORT_EL1 | 0b111111, 0),
+                   "Data abort from EL1, read, fault status 0x3f, address 0x0");
+    }
+
+    #[test]
+    fn other_classes() {
+        assert_eq!(line(EC_PC_ALIGNMENT, 0x1002), "PC alignment fault, address 0x1002");
+        assert_eq!(line(0, 0), "Undefined instruction or unknown exception");
+        assert_eq!(line(0b111111 << 26 | 0x1FF_FFFF, 0), "Exception class 0x3f, ISS 0x1ffffff");
+        assert_eq!(line(0b000010 << 26 | 0x1234, 0), "Exception class 0x02, ISS 0x1234");
+    }
+
+    #[test]
+    fn reserved_bits_are_ignored() {
+        // Bits 63:32 are RES0 or ISS2, and IL (bit 25) isn't described.
+        let high = 0xFFFF_FFFF_0000_0000 | 1 << 25;
+        assert_eq!(line(high | EC_SVC | 42, 0), "Supervisor call #42");
+        assert_eq!(line(high | 0b111111 << 26, 0), "Exception class 0x3f, ISS 0x0");
+    }
+
+    #[test]
+    fn every_syndrome_is_one_line() {
+        let patterns = [0, 0x1FF_FFFF, 0x155_5555, 0x0AA_AAAA, u64::max_value() & !(0x3F << 26)];
+        for ec in 0..64u64 {
+            for &iss in patterns.iter() {
+                let line = line(ec << 26 | iss, u64::max_value());
+                assert!(!line.is_empty() && !line.contains('\n'), "{:#x}: {:?}", ec, line);
+            }
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use std::fmt;
+
+use console::ekprint;
+use traps::{esr, Syndrome};
+
+/// The state of the interrupted context, saved by `vectors.S` on entry to an
+/// exception handler and restored from on return. Handlers may modify it to
+/// change where and how execution resumes.
+///
+/// The layout is shared with `vectors.S`; the two must change together.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct TrapFrame {
+    /// `x0` through `x30`.
+    pub x: [u64; 31],
+    /// The EL0 stack pointer.
+    pub sp_el0: u64,
+    /// The address execution resumes at (`ELR_EL1`).
+    pub elr: u64,
+    /// The saved processor state (`SPSR_EL1`).
+    pub spsr: u64,
+    /// The exception syndrome (`ESR_EL1`). Not restored.
+    pub esr: u64,
+    /// The faulting address (`FAR_EL1`), for aborts. Not restored.
+    pub far: u64,
+    /// The EL0 thread ID register.
+    pub tpidr: u64,
+    _pad: u64,
+    /// `q0` through `q31`.
+    pub q: [u128; 32],
+}
+
+impl TrapFrame {
+    /// Returns the exception level the trap was taken from, from `SPSR.M`.
+    pub fn el(&self) -> u64 {
+        (self.spsr >> 2) & 0b11
+    }
+
+    /// Prints the general-purpose registers and the exception registers,
+    /// with `FAR` only if the exception was an abort or a watchpoint, which
+    /// set it, and a description of the syndrome.
+    pub fn dump(&self) {
+        ekprint!("{}", Registers(self));
+    }
+
+    /// Returns the registers as `dump()` prints them, one per line.
+    pub fn registers(&self) -> Registers {
+        Registers(self)
+    }
+}
+
+/// A trap frame's registers, displayed as `TrapFrame::dump()` prints them.
+pub struct Registers<'a>(&'a TrapFrame);
+
+impl<'a> fmt::Display for Registers<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let tf = self.0;
+        for i in (0..30).step_by(2) {
+            writeln!(f, "  x{:<2} {:#018x}  x{:<2} {:#018x}", i, tf.x[i], i + 1, tf.x[i + 1])?;
+        }
+        writeln!(f, "  x30 {:#018x}  SP_EL0 {:#018x}", tf.x[30], tf.sp_el0)?;
+        writeln!(f, "  ELR {:#018x}  SPSR {:#010x}  ESR {:#010x}", tf.elr, tf.spsr, tf.esr)?;
+        match Syndrome::from(tf.esr) {
+            Syndrome::DataAbort { .. } | Syndrome::InstructionAbort { .. }
+            | Syndrome::Watchpoint => {
+                writeln!(f, "  FAR {:#018x}", tf.far)?
+            }
+            _ => {}
+        }
+        writeln!(f, "  {}", esr::describe(tf.esr, tf.far))
+    }
+}
@@ -0,0 +1,113 @@
+use std::fmt;
+
+/// What a faulting access was trying to do.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+    Execute,
+}
+
+impl Access {
+    /// Returns the access that caused a data abort with syndrome `esr`,
+    /// from its write-not-read bit.
+    pub fn from_data_abort(esr: u64) -> Access {
+        if esr & (1 << 6) != 0 { Access::Write } else { Access::Read }
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            Access::Read => "read",
+            Access::Write => "write",
+            Access::Execute => "execute",
+        })
+    }
+}
+
+/// Why an instruction or data abort happened: the fault status code in
+/// `ESR_EL1.ISS[5:0]`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FaultStatus {
+    /// An output or table address at the given level was too wide.
+    AddressSize(u8),
+    /// No translation at the given table level.
+    Translation(u8),
+    /// The access flag was clear at the given level.
+    AccessFlag(u8),
+    /// The translation at the given level forbids the access.
+    Permission(u8),
+    /// The address was misaligned for the access.
+    Alignment,
+    /// The memory system reported an error, on the access itself or on a
+    /// table walk for it.
+    External,
+    /// More than one TLB entry matched the address.
+    TlbConflict,
+    /// Any other fault status code.
+    Other(u8),
+}
+
+impl From<u64> for FaultStatus {
+    fn from(esr: u64) -> FaultStatus {
+        let code = (esr & 0b111111) as u8;
+        let level = code & 0b11;
+        match code >> 2 {
+            0b0000 => FaultStatus::AddressSize(level),
+            0b0001 => FaultStatus::Translation(level),
+            0b0010 => FaultStatus::AccessFlag(level),
+            0b0011 => FaultStatus::Permission(level),
+            _ if code == 0b100001 => FaultStatus::Alignment,
+            0b0101 => FaultStatus::External,
+            _ if code == 0b010000 => FaultStatus::External,
+            _ if code == 0b110000 => FaultStatus::TlbConflict,
+            _ => FaultStatus::Other(code),
+        }
+    }
+}
+
+impl fmt::Display for FaultStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FaultStatus::AddressSize(level) => write!(f, "address size fault level {}", level),
+            FaultStatus::Translation(level) => write!(f, "translation fault level {}", level),
+            FaultStatus::AccessFlag(level) => write!(f, "access flag fault level {}", level),
+            FaultStatus::Permission(level) => write!(f, "permission fault level {}", level),
+            FaultStatus::Alignment => write!(f, "alignment fault"),
+            FaultStatus::External => write!(f, "synchronous external abort"),
+            FaultStatus::TlbConflict => write!(f, "TLB conflict abort"),
+            FaultStatus::Other(code) => write!(f, "fault status {:#04x}", code),
+        }
+    }
+}
+
+/// The reasons a user page fault can't be resolved.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FaultError {
+    /// The address isn't in any of the process's regions.
+    Unmapped,
+    /// The region doesn't allow the access.
+    Protection,
+    /// The address is in the guard below a stack.
+    StackOverflow,
+    /// The fault isn't one demand paging can resolve.
+    Unsupported(FaultStatus),
+    /// No frame could be allocated for the page.
+    OutOfMemory,
+    /// The address space has reached the process's memory limit.
+    LimitExceeded,
+}
+
+impl fmt::Display for FaultError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FaultError::Unmapped => write!(f, "address not mapped"),
+            FaultError::Protection => write!(f, "access not permitted"),
+            FaultError::StackOverflow => write!(f, "stack overflow"),
+            FaultError::Unsupported(status) => write!(f, "{}", status),
+            FaultError::OutOfMemory => write!(f, "out of memory"),
+            FaultError::LimitExceeded => write!(f, "memory limit exceeded"),
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use core::alloc::{GlobalAlloc, Layout};
+use std::collections::BTreeMap;
+
+use allocator::PAGE_SIZE;
+use mutex::Mutex;
+use ALLOCATOR;
+
+/// The number of extra references to every frame mapped by more than one
+/// address space. A frame with no entry has a single owner.
+static SHARED: Mutex<Option<BTreeMap<usize, usize>>> = Mutex::new(None);
+
+/// Returns the layout of a frame.
+fn layout() -> Layout {
+    unsafe { Layout::from_size_align_unchecked(PAGE_SIZE, PAGE_SIZE) }
+}
+
+/// Allocates a zeroed frame with a single owner and returns its address, or
+/// `None` if memory is exhausted.
+pub fn alloc() -> Option<usize> {
+    let frame = unsafe { ALLOCATOR.alloc(layout()) };
+    if frame.is_null() {
+        return None;
+    }
+
+    unsafe { frame.write_bytes(0, PAGE_SIZE); }
+    Some(frame as usize)
+}
+
+/// Adds an owner to the frame at `addr`.
+pub fn share(addr: usize) {
+    let mut shared = SHARED.lock();
+    *shared.get_or_insert_with(BTreeMap::new).entry(addr).or_insert(0) += 1;
+}
+
+/// Returns `true` if the frame at `addr` has more than one owner.
+pub fn is_shared(addr: usize) -> bool {
+    SHARED.lock().as_ref().map_or(false, |shared| shared.contains_key(&addr))
+}
+
+/// Returns `true` if this core is updating the frame owners, so that
+/// releasing a frame would deadlock. For the out-of-memory killer, which may
+/// run in the middle of `share()`.
+pub fn frames_locked_here() -> bool {
+    SHARED.is_held_by_current_core()
+}
+
+/// Removes an owner from the frame at `addr`, freeing it if that was the
+/// last one.
+pub fn release(addr: usize) {
+    let mut guard = SHARED.lock();
+    if let Some(shared) = guard.as_mut() {
+        if let Some(count) = shared.get(&addr).cloned() {
+            if count == 1 {
+                shared.remove(&addr);
+            } else {
+                shared.insert(addr, count - 1);
+            }
+            return;
+        }
+    }
+
+    drop(guard);
+    unsafe { ALLOCATOR.dealloc(addr as *mut u8, layout()); }
+}
@@ -0,0 +1,148 @@
+//! Kernel stacks, mapped with an unmapped guard below each so that a stack
+//! overflowing faults at once instead of silently corrupting whatever lies
+//! below it.
+//!
+//! Stacks live in the third gigabyte of the kernel's half, from
+//! `KSTACK_BASE` up, one to a `SLOT_SIZE` slot: the lower `GUARD_SIZE` bytes
+//! of a slot are never mapped and the stack takes the rest. The slot size is
+//! a power of two so that `vectors.S` can tell whether an address is in a
+//! guard with a mask, before it has a stack to spare; its constants must
+//! match these.
+
+use aarch64;
+use allocator::PAGE_SIZE;
+use mutex::IrqMutex;
+use vm::{frame, ENTRIES};
+use vm::pagetable::*;
+
+/// The lowest address of the stack area: the start of level 1 entry
+/// `KSTACK_L1_INDEX`.
+pub const KSTACK_BASE: usize = KSTACK_L1_INDEX << 30;
+
+/// The level 1 entry covering the stack area.
+pub const KSTACK_L1_INDEX: usize = 2;
+
+/// The size of a stack in bytes.
+pub const STACK_SIZE: usize = 64 * 1024;
+
+/// The size of a slot: a guard, then a stack.
+pub const SLOT_SIZE: usize = 128 * 1024;
+
+/// The size of the unmapped guard at the bottom of every slot.
+pub const GUARD_SIZE: usize = SLOT_SIZE - STACK_SIZE;
+
+/// The number of slots in the stack area.
+const SLOTS: usize = (1 << 30) / SLOT_SIZE;
+
+/// The size of the area a level 3 table maps.
+const L3_SPAN: usize = ENTRIES * PAGE_SIZE;
+
+/// The bits of a stack page's descriptor: kernel-only, never executable.
+const STACK_PAGE: u64 = VALID | TABLE | NORMAL | INNER_SHAREABLE | ACCESSED | PXN | UXN;
+
+/// The level 2 table for the stack area. Its level 3 tables are allocated
+/// as slots are first used and never freed.
+static mut KSTACK_L2: Table = Table::empty();
+
+/// Which slots hold a stack, a bit per slot.
+static USED: IrqMutex<[u64; SLOTS / 64]> = IrqMutex::new([0; SLOTS / 64]);
+
+/// Returns the address of the level 2 table for the stack area, for the
+/// kernel's level 1 table.
+pub(super) fn l2_table() -> *const Table {
+    unsafe { &KSTACK_L2 as *const Table }
+}
+
+/// Returns the level 3 descriptor for the stack page `va`, allocating its
+/// level 3 table if needed. Must be called with `USED` locked. Returns
+/// `None` if the table can't be allocated.
+unsafe fn entry(va: usize) -> Option<&'static mut u64> {
+    let offset = va - KSTACK_BASE;
+    let l2 = &mut KSTACK_L2.entries[offset / L3_SPAN];
+    if *l2 & VALID == 0 {
+        let table = frame::alloc()?;
+        aarch64::dsb();
+        *l2 = table as u64 | VALID | TABLE;
+    }
+
+    let table = (*l2 & ADDR_MASK) as *mut Table;
+    Some(&mut (*table).entries[(offset % L3_SPAN) / PAGE_SIZE])
+}
+
+/// Maps a zeroed stack in a free slot and returns its lowest address, or
+/// `None` if there is no free slot or memory is exhausted.
+pub fn alloc() -> Option<usize> {
+    let mut used = USED.lock();
+    let slot = (0..SLOTS).find(|&slot| used[slot / 64] & (1 << (slot % 64)) == 0)?;
+    let bottom = KSTACK_BASE + slot * SLOT_SIZE + GUARD_SIZE;
+
+    let mut page = bottom;
+    while page < bottom + STACK_SIZE {
+        let mapped = unsafe { entry(page) }.and_then(|entry| {
+            let frame = frame::alloc()?;
+            *entry = frame as u64 | STACK_PAGE;
+            Some(())
+        });
+
+        if mapped.is_none() {
+            unsafe { unmap(bottom, page); }
+            return None;
+        }
+        page += PAGE_SIZE;
+    }
+
+    used[slot / 64] |= 1 << (slot % 64);
+    aarch64::dsb();
+    Some(bottom)
+}
+
+/// Unmaps the stack at `bottom`, returned by `alloc()`, and frees its
+/// frames. Nothing may use the stack any more.
+pub fn free(bottom: usize) {
+    let slot = (bottom - GUARD_SIZE - KSTACK_BASE) / SLOT_SIZE;
+    let mut used = USED.lock();
+    assert!(used[slot / 64] & (1 << (slot % 64)) != 0, "freeing unused kernel stack {:#x}", bottom);
+
+    unsafe { unmap(bottom, bottom + STACK_SIZE); }
+    used[slot / 64] &= !(1 << (slot % 64));
+}
+
+/// Unmaps the pages of `start..end`, at most a stack's worth, and frees
+/// their frames. Must be called with `USED` locked.
+unsafe fn unmap(start: usize, end: usize) {
+    let mut frames = [0usize; STACK_SIZE / PAGE_SIZE];
+    for (i, frame) in frames.iter_mut().enumerate() {
+        let page = start + i * PAGE_SIZE;
+        if page >= end {
+            break;
+        }
+
+        if let Some(entry) = entry(page) {
+            if *entry & VALID != 0 {
+                *frame = (*entry & ADDR_MASK) as usize;
+                *entry = 0;
+            }
+        }
+    }
+
+    // No core may still reach a frame through its TLB once it is freed.
+    aarch64::flush_tlb_all_cores();
+    for &frame in frames.iter().filter(|&&frame| frame != 0) {
+        frame::release(frame);
+    }
+}
+
+/// If `addr` is in the guard of a slot, returns the lowest address of the
+/// stack above it, which that stack overflowing would have reached.
+pub fn guarded_stack(addr: usize) -> Option<usize> {
+    if addr < KSTACK_BASE || addr >= KSTACK_BASE + SLOTS * SLOT_SIZE {
+        return None;
+    }
+
+    let slot_start = addr & !(SLOT_SIZE - 1);
+    if addr - slot_start < GUARD_SIZE {
+        Some(slot_start + GUARD_SIZE)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,170 @@
+//! Virtual memory.
+//!
+//! Addresses are 39 bits wide, translated with a 4 KiB granule through three
+//! levels of tables. The address space is split in two:
+//!
+//!   * The low half, through `TTBR0_EL1`, identity-maps physical memory for
+//!     the kernel: RAM as normal cacheable memory and the peripherals as
+//!     device memory. It is the same for every process and never changes,
+//!     but for the third gigabyte, where kernel stacks are mapped a page at
+//!     a time with unmapped guards between them. See `kstack`.
+//!
+//!   * The high half, from `USER_BASE` up through `TTBR1_EL1`, holds the
+//!     running user process's address space and is switched with it.
+//!
+//! Only EL1 can access the low half, so user processes can't touch the
+//! kernel or each other.
+//!
+//! Parts of a user address space, such as the stack below its first pages
+//! and a program's BSS, are `Region`s mapped a page at a time as they are
+//! first touched. See `AddressSpace::handle_fault()`. So is the heap, which
+//! starts just above the program and grows with the `brk` system call. A
+//! forked address space shares its parent's frames until either writes to
+//! them.
+
+mod pagetable;
+mod space;
+mod fault;
+mod frame;
+pub mod kstack;
+
+pub use self::pagetable::{UserPageTable, Perm, MapError};
+pub use self::space::{AddressSpace, Region};
+pub use self::fault::{Access, FaultStatus, FaultError};
+pub use self::frame::frames_locked_here;
+
+use pi::common::IO_BASE;
+
+use aarch64;
+use allocator::PAGE_SIZE;
+use init::{kernel_init, Init, Stage};
+use self::pagetable::*;
+
+/// The number of descriptors in a table.
+pub const ENTRIES: usize = 512;
+
+/// The lowest user address: the start of the `TTBR1_EL1` half.
+pub const USER_BASE: usize = 0xFFFF_FF80_0000_0000;
+
+/// Where user programs are loaded.
+pub const USER_IMG_BASE: usize = USER_BASE;
+
+/// The address just past the top of a user process's stack.
+pub const USER_STACK_TOP: usize = 0xFFFF_FFFF_FFFF_0000;
+
+/// The local peripherals (ARM timers and interrupt routing), which follow
+/// the rest in the second gigabyte.
+const LOCAL_IO_BASE: usize = 0x4000_0000;
+
+/// The size of a level 2 block.
+const L2_BLOCK: usize = 1 << 21;
+
+/// `MAIR_EL1`: attribute 0 is normal write-back, read/write-allocate memory
+/// and attribute 1 is device nGnRE memory.
+const MAIR: u64 = 0xFF | (0x04 << 8);
+
+/// `TCR_EL1`: 39-bit addresses and a 4 KiB granule in both halves, with
+/// table walks through inner shareable write-back caches, and a 32-bit
+/// physical address space.
+const TCR: u64 = (64 - 39)              // T0SZ
+    | (0b01 << 8) | (0b01 << 10)        // IRGN0, ORGN0: write-back
+    | (0b11 << 12)                      // SH0: inner shareable
+    | (0b00 << 14)                      // TG0: 4 KiB
+    | ((64 - 39) << 16)                 // T1SZ
+    | (0b01 << 24) | (0b01 << 26)       // IRGN1, ORGN1: write-back
+    | (0b11 << 28)                      // SH1: inner shareable
+    | (0b10 << 30);                     // TG1: 4 KiB
+
+/// The kernel's level 1 table.
+static mut KERNEL_L1: Table = Table::empty();
+
+/// The level 2 table for the first gigabyte: RAM, then the peripherals.
+static mut KERNEL_L2: Table = Table::empty();
+
+/// The `TTBR1_EL1` table used when the running process has no user address
+/// space, so that every user address faults.
+static mut EMPTY_L1: Table = Table::empty();
+
+kernel_init!(VM_INIT, Stage::Mmu, "vm", Init::Plain(init));
+
+/// Builds the kernel's identity map and enables the MMU and caches.
+/// Secondary cores use the same tables; see `init_secondary()`.
+///
+/// Must be called once, on core 0, before anything relies on atomic
+/// read-modify-write operations or on user addresses. Anything shared with
+/// the VideoCore through memory, such as mailbox buffers, must be flushed
+/// from the data cache once this returns.
+pub fn init() {
+    unsafe {
+        let block = VALID | ACCESSED | INNER_SHAREABLE;
+        for (i, entry) in KERNEL_L2.entries.iter_mut().enumerate() {
+            let addr = i * L2_BLOCK;
+            *entry = addr as u64 | block | if addr < IO_BASE {
+                NORMAL | UXN
+            } else {
+                DEVICE | PXN | UXN
+            };
+        }
+
+        KERNEL_L1.entries[0] = &KERNEL_L2 as *const Table as u64 | VALID | TABLE;
+        KERNEL_L1.entries[1] = LOCAL_IO_BASE as u64 | block | DEVICE | PXN | UXN;
+        KERNEL_L1.entries[kstack::KSTACK_L1_INDEX] = kstack::l2_table() as u64 | VALID | TABLE;
+    }
+
+    enable();
+}
+
+/// Enables the MMU and caches on a secondary core with the tables `init()`
+/// built. Core 0 must have called `clean_tables()` since.
+pub fn init_secondary() {
+    enable();
+}
+
+/// Cleans the kernel's tables from core 0's data cache to memory, where a
+/// core starting with its caches off can see them.
+pub fn clean_tables() {
+    let size = ::std::mem::size_of::<Table>();
+    unsafe {
+        for table in [&KERNEL_L1, &KERNEL_L2, &EMPTY_L1].iter() {
+            aarch64::clean_dcache(*table as *const Table as usize, size);
+        }
+        aarch64::clean_dcache(kstack::l2_table() as usize, size);
+    }
+}
+
+/// Enables the MMU and caches on this core with the kernel's tables.
+fn enable() {
+    unsafe {
+        aarch64::enable_mmu(MAIR, TCR, &KERNEL_L1 as *const Table as u64,
+                            &EMPTY_L1 as *const Table as u64);
+    }
+}
+
+/// Installs `table` as the user address space, or an empty one if `None`.
+pub fn activate(table: Option<&UserPageTable>) {
+    let base = match table {
+        Some(table) => table.base(),
+        None => unsafe { &EMPTY_L1 as *const Table as u64 },
+    };
+
+    unsafe { aarch64::set_ttbr1(base); }
+}
+
+/// Returns `true` if every byte of `addr..addr + len` is a user address the
+/// running process may read, or write if `write` is set.
+pub fn user_accessible(addr: usize, len: usize, write: bool) -> bool {
+    let end = match addr.checked_add(len) {
+        Some(end) if addr >= USER_BASE => end,
+        _ => return false,
+    };
+
+    let mut page = addr & !(PAGE_SIZE - 1);
+    while page < end {
+        if !aarch64::user_can_access(page, write) {
+            return false;
+        }
+        page += PAGE_SIZE;
+    }
+
+    true
+}
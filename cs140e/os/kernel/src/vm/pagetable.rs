@@ -0,0 +1,314 @@
+use std::{fmt, mem, ptr, slice};
+
+use allocator::PAGE_SIZE;
+use vm::{frame, USER_BASE, ENTRIES};
+
+/// A descriptor is valid.
+pub const VALID: u64 = 1 << 0;
+/// At levels 1 and 2, the descriptor points to a table rather than a block.
+/// At level 3, it must be set for a valid page.
+pub const TABLE: u64 = 1 << 1;
+/// `AttrIndx` selecting `MAIR_EL1` attribute 0: normal, write-back memory.
+pub const NORMAL: u64 = 0 << 2;
+/// `AttrIndx` selecting `MAIR_EL1` attribute 1: device nGnRE memory.
+pub const DEVICE: u64 = 1 << 2;
+/// `AP[1]`: EL0 may access the page.
+pub const USER: u64 = 1 << 6;
+/// `AP[2]`: the page is read-only.
+pub const READ_ONLY: u64 = 1 << 7;
+/// Inner shareable.
+pub const INNER_SHAREABLE: u64 = 0b11 << 8;
+/// The access flag. Without it, the first access faults.
+pub const ACCESSED: u64 = 1 << 10;
+/// EL1 may not execute from the page.
+pub const PXN: u64 = 1 << 53;
+/// EL0 may not execute from the page.
+pub const UXN: u64 = 1 << 54;
+/// Software-defined: the frame was allocated by the table, which releases
+/// it.
+pub const OWNED: u64 = 1 << 55;
+/// Software-defined: the page is writable, but mapped read-only because its
+/// frame may be shared. It is copied on the first write.
+pub const COW: u64 = 1 << 56;
+
+/// The bits of a descriptor holding the output address.
+pub const ADDR_MASK: u64 = 0x0000_ffff_ffff_f000;
+
+/// A translation table: 512 descriptors in one page.
+#[repr(C)]
+#[repr(align(4096))]
+pub struct Table {
+    pub entries: [u64; ENTRIES],
+}
+
+impl Table {
+    /// Returns a table with every descriptor invalid.
+    pub const fn empty() -> Table {
+        Table { entries: [0; ENTRIES] }
+    }
+}
+
+/// What a user process may do with a page.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Perm {
+    pub write: bool,
+    pub exec: bool,
+}
+
+impl Perm {
+    pub const READ: Perm = Perm { write: false, exec: false };
+    pub const READ_WRITE: Perm = Perm { write: true, exec: false };
+    pub const READ_EXEC: Perm = Perm { write: false, exec: true };
+
+    /// Returns the permissions allowing everything `self` or `other` does.
+    pub fn union(self, other: Perm) -> Perm {
+        Perm { write: self.write || other.write, exec: self.exec || other.exec }
+    }
+
+    /// Returns the descriptor bits for a user page with these permissions.
+    fn bits(&self) -> u64 {
+        let mut bits = VALID | TABLE | NORMAL | USER | INNER_SHAREABLE | ACCESSED | PXN;
+        if !self.write {
+            bits |= READ_ONLY;
+        }
+        if !self.exec {
+            bits |= UXN;
+        }
+        bits
+    }
+}
+
+/// Error type for `UserPageTable::map()` failures.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MapError {
+    /// An address is not page-aligned.
+    Misaligned,
+    /// The virtual address is outside the user half of the address space.
+    OutOfRange,
+    /// The virtual address is already mapped.
+    AlreadyMapped,
+    /// A frame or table couldn't be allocated.
+    OutOfMemory,
+}
+
+
+/// The translation tables for a user address space, installed in
+/// `TTBR1_EL1` while the process owning them runs.
+///
+/// Pages are either mapped with `map()`, and owned by the caller, or
+/// allocated with `alloc()`, and released with the tables. Allocated frames
+/// are shared copy-on-write by `fork()`.
+pub struct UserPageTable {
+    l1: Box<Table>,
+    /// The number of pages mapped with `alloc()`.
+    pages: usize,
+}
+
+impl UserPageTable {
+    /// Returns an address space with nothing mapped.
+    pub fn new() -> UserPageTable {
+        UserPageTable { l1: Box::new(Table::empty()), pages: 0 }
+    }
+
+    /// Returns the value to load into `TTBR1_EL1` to use these tables.
+    pub fn base(&self) -> u64 {
+        &*self.l1 as *const Table as u64
+    }
+
+    /// Returns the number of pages mapped with `alloc()`, counting pages
+    /// shared copy-on-write with another address space in both.
+    pub fn pages(&self) -> usize {
+        self.pages
+    }
+
+    /// Returns the level 3 descriptor for the user address `va`, allocating
+    /// the level 2 and 3 tables on the way if `create` is set.
+    fn entry(&mut self, va: usize, create: bool) -> Option<&mut u64> {
+        let offset = va.wrapping_sub(USER_BASE);
+        let indices = [(offset >> 30) % ENTRIES, (offset >> 21) % ENTRIES];
+
+        let mut table: &mut Table = &mut self.l1;
+        for &index in indices.iter() {
+            let descriptor = table.entries[index];
+            let next = if descriptor & VALID != 0 {
+                (descriptor & ADDR_MASK) as *mut Table
+            } else if create {
+                let next = Box::into_raw(Box::new(Table::empty()));
+                table.entries[index] = next as u64 | VALID | TABLE;
+                next
+            } else {
+                return None;
+            };
+
+            table = unsafe { &mut *next };
+        }
+
+        Some(&mut table.entries[(offset >> 12) % ENTRIES])
+    }
+
+    /// Maps the page at user address `va` to the physical page `pa` with
+    /// permissions `perm`.
+    pub fn map(&mut self, va: usize, pa: usize, perm: Perm) -> Result<(), MapError> {
+        if va % PAGE_SIZE != 0 || pa % PAGE_SIZE != 0 {
+            return Err(MapError::Misaligned);
+        }
+
+        if va < USER_BASE {
+            return Err(MapError::OutOfRange);
+        }
+
+        let entry = self.entry(va, true).unwrap();
+        if *entry & VALID != 0 {
+            return Err(MapError::AlreadyMapped);
+        }
+
+        *entry = pa as u64 | perm.bits();
+        Ok(())
+    }
+
+    /// Allocates a zeroed frame and maps it at the page-aligned user address
+    /// `va` with permissions `perm`. Returns the frame's contents.
+    pub fn alloc(&mut self, va: usize, perm: Perm) -> Result<&mut [u8], MapError> {
+        let frame = frame::alloc().ok_or(MapError::OutOfMemory)?;
+        match self.map(va, frame, perm) {
+            Ok(()) => {
+                *self.entry(va, false).unwrap() |= OWNED;
+                self.pages += 1;
+                Ok(unsafe { slice::from_raw_parts_mut(frame as *mut u8, PAGE_SIZE) })
+            }
+            Err(e) => {
+                frame::release(frame);
+                Err(e)
+            }
+        }
+    }
+
+    /// Unmaps the page at the page-aligned user address `va`, releasing its
+    /// frame if the table allocated it. Returns `false` if it wasn't mapped.
+    ///
+    /// The caller must discard any cached translation for `va`.
+    pub fn unmap(&mut self, va: usize) -> bool {
+        if va < USER_BASE {
+            return false;
+        }
+
+        let descriptor = match self.entry(va, false) {
+            Some(entry) => mem::replace(entry, 0),
+            None => return false,
+        };
+
+        if descriptor & VALID == 0 {
+            return false;
+        }
+        if descriptor & OWNED != 0 {
+            frame::release((descriptor & ADDR_MASK) as usize);
+            self.pages -= 1;
+        }
+        true
+    }
+
+    /// Returns a copy of this address space that shares every frame with
+    /// it. Allocated frames that are writable become read-only in both and
+    /// are copied by whichever writes first; see `copy_on_write()`. Frames
+    /// mapped with `map()` are shared as they are, and their owner must keep
+    /// them alive for both.
+    pub fn fork(&mut self) -> UserPageTable {
+        let mut child = UserPageTable::new();
+        for i in 0..ENTRIES {
+            let l1 = self.l1.entries[i];
+            if l1 & VALID == 0 {
+                continue;
+            }
+
+            let l2 = unsafe { &mut *((l1 & ADDR_MASK) as *mut Table) };
+            for j in 0..ENTRIES {
+                if l2.entries[j] & VALID == 0 {
+                    continue;
+                }
+
+                let l3 = unsafe { &mut *((l2.entries[j] & ADDR_MASK) as *mut Table) };
+                for k in 0..ENTRIES {
+                    let entry = &mut l3.entries[k];
+                    if *entry & VALID == 0 {
+                        continue;
+                    }
+
+                    if *entry & OWNED != 0 {
+                        if *entry & READ_ONLY == 0 {
+                            *entry |= READ_ONLY | COW;
+                        }
+                        frame::share((*entry & ADDR_MASK) as usize);
+                        child.pages += 1;
+                    }
+
+                    let va = USER_BASE + (i << 30) + (j << 21) + (k << 12);
+                    *child.entry(va, true).unwrap() = *entry;
+                }
+            }
+        }
+
+        child
+    }
+
+    /// Returns `true` if the page at user address `va` is copy-on-write.
+    pub fn is_cow(&mut self, va: usize) -> bool {
+        va >= USER_BASE && self.entry(va, false).map_or(false, |entry| *entry & COW != 0)
+    }
+
+    /// Makes the copy-on-write page at user address `va` writable, copying
+    /// its frame first if another address space shares it. Returns
+    /// `Ok(false)` if the page isn't copy-on-write.
+    ///
+    /// The caller must discard any cached translation for `va`.
+    pub fn copy_on_write(&mut self, va: usize) -> Result<bool, MapError> {
+        if !self.is_cow(va) {
+            return Ok(false);
+        }
+
+        let entry = self.entry(va, false).unwrap();
+        let old = (*entry & ADDR_MASK) as usize;
+        if frame::is_shared(old) {
+            let new = frame::alloc().ok_or(MapError::OutOfMemory)?;
+            unsafe { ptr::copy_nonoverlapping(old as *const u8, new as *mut u8, PAGE_SIZE); }
+            *entry = (*entry & !ADDR_MASK) | new as u64;
+            frame::release(old);
+        }
+
+        *entry &= !(READ_ONLY | COW);
+        Ok(true)
+    }
+
+    /// Returns the physical address the user address `va` maps to, if any.
+    pub fn translate(&mut self, va: usize) -> Option<usize> {
+        if va < USER_BASE {
+            return None;
+        }
+
+        let entry = *self.entry(va, false)?;
+        if entry & VALID == 0 {
+            return None;
+        }
+
+        Some((entry & ADDR_MASK) as usize + va % PAGE_SIZE)
+    }
+}
+
+impl Drop for UserPageTable {
+    fn drop(&mut self) {
+        for &l1 in self.l1.entries.iter().filter(|&&d| d & VALID != 0) {
+            let l2 = unsafe { Box::from_raw((l1 & ADDR_MASK) as *mut Table) };
+            for &l2 in l2.entries.iter().filter(|&&d| d & VALID != 0) {
+                let l3 = unsafe { Box::from_raw((l2 & ADDR_MASK) as *mut Table) };
+                for &page in l3.entries.iter().filter(|&&d| d & (VALID | OWNED) == VALID | OWNED) {
+                    frame::release((page & ADDR_MASK) as usize);
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Debug for UserPageTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("UserPageTable").field("base", &(self.base() as *const u8)).finish()
+    }
+}
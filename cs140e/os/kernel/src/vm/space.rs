@@ -0,0 +1,252 @@
+use aarch64;
+use allocator::PAGE_SIZE;
+use allocator::util::align_down;
+use vm::{UserPageTable, Perm, MapError};
+use vm::fault::{Access, FaultStatus, FaultError};
+
+/// A range of user addresses a process may use, whose pages are mapped to
+/// zeroed frames when first touched.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Region {
+    /// The first address, page-aligned.
+    pub start: usize,
+    /// The address one past the end, page-aligned.
+    pub end: usize,
+    /// What the process may do with the region.
+    pub perm: Perm,
+}
+
+impl Region {
+    /// Returns `true` if `addr` is in the region.
+    pub fn contains(&self, addr: usize) -> bool {
+        self.start <= addr && addr < self.end
+    }
+
+    /// Returns `true` if the region's permissions allow `access`.
+    pub fn allows(&self, access: Access) -> bool {
+        match access {
+            Access::Read => true,
+            Access::Write => self.perm.write,
+            Access::Execute => self.perm.exec,
+        }
+    }
+}
+
+/// A user address space: its translation tables, the regions that are
+/// filled in on demand, the heap, and the guards below stacks, which are
+/// never mapped.
+#[derive(Debug)]
+pub struct AddressSpace {
+    table: UserPageTable,
+    regions: Vec<Region>,
+    guards: Vec<(usize, usize)>,
+    /// The heap, mapped on demand like the other regions. It ends at the
+    /// page boundary at or above `brk`.
+    heap: Region,
+    /// The program break, as last set by `set_brk()`.
+    brk: usize,
+    /// The most pages that may be mapped on demand. See `set_limit()`.
+    limit: usize,
+}
+
+impl AddressSpace {
+    /// Returns an address space with nothing mapped and no regions.
+    pub fn new() -> AddressSpace {
+        AddressSpace {
+            table: UserPageTable::new(),
+            regions: Vec::new(),
+            guards: Vec::new(),
+            heap: Region { start: 0, end: 0, perm: Perm::READ_WRITE },
+            brk: 0,
+            limit: usize::max_value(),
+        }
+    }
+
+    /// Returns the translation tables.
+    pub fn table(&self) -> &UserPageTable {
+        &self.table
+    }
+
+    /// Returns the bytes of memory mapped for the address space, counting
+    /// frames shared copy-on-write.
+    pub fn size(&self) -> usize {
+        self.table.pages() * PAGE_SIZE
+    }
+
+    /// Limits the memory mapped on demand to `bytes`, rounded down to a page:
+    /// once the address space is that size, faults that would map another
+    /// page fail. Pages mapped eagerly, and copies of copy-on-write pages,
+    /// don't count against the limit, so the size may exceed it.
+    pub fn set_limit(&mut self, bytes: usize) {
+        self.limit = bytes / PAGE_SIZE;
+    }
+
+    /// Returns the translation tables, for mapping pages eagerly.
+    pub fn table_mut(&mut self) -> &mut UserPageTable {
+        &mut self.table
+    }
+
+    /// Adds `region`, whose bounds must be page-aligned. Pages already
+    /// mapped within it are left as they are.
+    pub fn add_region(&mut self, region: Region) {
+        assert!(region.start % PAGE_SIZE == 0 && region.end % PAGE_SIZE == 0,
+                "unaligned region {:#x}..{:#x}", region.start, region.end);
+        self.regions.push(region);
+    }
+
+    /// Marks `start..end` as the guard below a stack: a fault there is
+    /// reported as the stack overflowing.
+    pub fn add_guard(&mut self, start: usize, end: usize) {
+        self.guards.push((start, end));
+    }
+
+    /// Returns the region containing `addr`, if any. The heap is a region.
+    pub fn region(&self, addr: usize) -> Option<&Region> {
+        self.regions.iter().find(|r| r.contains(addr))
+            .or_else(|| if self.heap.contains(addr) { Some(&self.heap) } else { None })
+    }
+
+    /// Starts the heap, empty, at the page-aligned `base`, which must be
+    /// above everything but the stack.
+    pub fn set_heap_base(&mut self, base: usize) {
+        assert!(base % PAGE_SIZE == 0, "unaligned heap base {:#x}", base);
+        self.heap.start = base;
+        self.heap.end = base;
+        self.brk = base;
+    }
+
+    /// Returns the program break: the address just past the end of the
+    /// heap.
+    pub fn brk(&self) -> usize {
+        self.brk
+    }
+
+    /// Moves the program break to `addr`, growing or shrinking the heap.
+    /// Pages the heap grows over are mapped as they are first touched;
+    /// pages it shrinks off are unmapped. Returns `false`, leaving the
+    /// break as it was, if `addr` is below the heap's base or the heap
+    /// would run into a region or guard.
+    pub fn set_brk(&mut self, addr: usize) -> bool {
+        let start = self.heap.start;
+        let end = match addr.checked_add(PAGE_SIZE - 1) {
+            Some(end) if addr >= start => align_down(end, PAGE_SIZE),
+            _ => return false,
+        };
+
+        let overlaps = |(other_start, other_end): (usize, usize)| {
+            other_start < end && start < other_end
+        };
+        if self.regions.iter().any(|r| overlaps((r.start, r.end)))
+            || self.guards.iter().any(|&guard| overlaps(guard))
+        {
+            return false;
+        }
+
+        let mut page = end;
+        while page < self.heap.end {
+            self.table.unmap(page);
+            page += PAGE_SIZE;
+        }
+        if end < self.heap.end {
+            aarch64::flush_tlb();
+        }
+
+        self.heap.end = end;
+        self.brk = addr;
+        true
+    }
+
+    /// Returns a copy of this address space sharing its frames
+    /// copy-on-write. See `UserPageTable::fork()`.
+    pub fn fork(&mut self) -> AddressSpace {
+        let table = self.table.fork();
+        // Pages that were writable are now read-only here too.
+        aarch64::flush_tlb();
+        AddressSpace {
+            table,
+            regions: self.regions.clone(),
+            guards: self.guards.clone(),
+            heap: self.heap,
+            brk: self.brk,
+            limit: self.limit,
+        }
+    }
+
+    /// Resolves a fault with status `status` on `access` to `addr`.
+    ///
+    /// A write to a copy-on-write page gets a private, writable copy of it.
+    /// Otherwise, if `addr` is in a region that allows `access` and isn't
+    /// mapped yet, a zeroed frame is mapped there.
+    pub fn handle_fault(&mut self, addr: usize, access: Access, status: FaultStatus)
+        -> Result<(), FaultError>
+    {
+        let page = align_down(addr, PAGE_SIZE);
+        if let (FaultStatus::Permission(_), Access::Write) = (status, access) {
+            return match self.table.copy_on_write(page) {
+                Ok(true) => {
+                    aarch64::flush_tlb();
+                    Ok(())
+                }
+                Ok(false) => Err(FaultError::Protection),
+                Err(_) => Err(FaultError::OutOfMemory),
+            };
+        }
+
+        let perm = match self.region(addr) {
+            Some(region) if region.allows(access) => region.perm,
+            Some(_) => return Err(FaultError::Protection),
+            None if self.guards.iter().any(|&(start, end)| start <= addr && addr < end) => {
+                return Err(FaultError::StackOverflow)
+            }
+            None => return Err(FaultError::Unmapped),
+        };
+
+        match status {
+            FaultStatus::Translation(_) => {}
+            FaultStatus::Permission(_) => return Err(FaultError::Protection),
+            status => return Err(FaultError::Unsupported(status)),
+        }
+
+        if self.table.pages() >= self.limit {
+            return Err(FaultError::LimitExceeded);
+        }
+
+        match self.table.alloc(page, perm) {
+            // Another core may have mapped the page since it faulted.
+            Ok(_) | Err(MapError::AlreadyMapped) => Ok(()),
+            Err(MapError::OutOfMemory) => Err(FaultError::OutOfMemory),
+            Err(e) => panic!("demand-mapping {:#x}: {:?}", addr, e),
+        }
+    }
+
+    /// Resolves the faults `access` to each page of `addr..addr + len` would
+    /// cause, mapping unmapped pages in regions and, for writes, copying
+    /// copy-on-write pages. Returns `false` if some page couldn't be
+    /// resolved. For system calls, which access user memory from EL1.
+    pub fn populate(&mut self, addr: usize, len: usize, access: Access) -> bool {
+        let end = match addr.checked_add(len) {
+            Some(end) => end,
+            None => return false,
+        };
+
+        let mut page = align_down(addr, PAGE_SIZE);
+        while page < end {
+            let status = if self.table.translate(page).is_none() {
+                Some(FaultStatus::Translation(3))
+            } else if access == Access::Write && self.table.is_cow(page) {
+                Some(FaultStatus::Permission(3))
+            } else {
+                None
+            };
+
+            if let Some(status) = status {
+                if self.handle_fault(page, access, status).is_err() {
+                    return false;
+                }
+            }
+            page += PAGE_SIZE;
+        }
+
+        true
+    }
+}
@@ -0,0 +1,111 @@
+use volatile::prelude::*;
+
+use common::IO_BASE;
+use mmio::{self, Volatile, ReadVolatile};
+use timer;
+
+/// The base address of the EMMC controller's registers.
+pub const EMMC_BASE: usize = IO_BASE + 0x300000;
+
+/// The bytes in a block.
+pub const BLOCK_SIZE: usize = 512;
+
+/// How long a command or transfer may take, in microseconds.
+const TIMEOUT_US: u64 = 500_000;
+
+/// `WRITE_BLOCK` (CMD24): a response and a single-block write of data.
+const CMD_WRITE_SINGLE: u32 = 0x1822_0000;
+
+/// `STATUS` bits: the command and data lines are busy.
+const SR_CMD_INHIBIT: u32 = 1 << 0;
+const SR_DAT_INHIBIT: u32 = 1 << 1;
+
+/// `INTERRUPT` bits: the command finished, the transfer finished, the
+/// controller is ready for data to write, and any error.
+const INT_CMD_DONE: u32 = 1 << 0;
+const INT_DATA_DONE: u32 = 1 << 1;
+const INT_WRITE_RDY: u32 = 1 << 4;
+const INT_ERROR_MASK: u32 = 0x017E_8000;
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    ARG2: Volatile<u32>,
+    BLKSIZECNT: Volatile<u32>,
+    ARG1: Volatile<u32>,
+    CMDTM: Volatile<u32>,
+    RESP: [ReadVolatile<u32>; 4],
+    DATA: Volatile<u32>,
+    STATUS: ReadVolatile<u32>,
+    CONTROL0: Volatile<u32>,
+    CONTROL1: Volatile<u32>,
+    INTERRUPT: Volatile<u32>,
+}
+
+/// Error type for EMMC transfers.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The controller didn't finish in time.
+    Timeout,
+    /// The controller reported an error; the `INTERRUPT` register's bits.
+    Controller(u32),
+}
+
+/// Writes `buf` to block `lba` of the SD card, polling the controller.
+///
+/// The card must already be initialized and selected, as `libsd`'s
+/// `sd_init()` leaves it, and be a high capacity (SDHC or SDXC) card, which
+/// is addressed by block. Nothing else may use the controller meanwhile.
+pub fn write_block(lba: u32, buf: &[u8; BLOCK_SIZE]) -> Result<(), Error> {
+    let registers: &mut Registers = unsafe { mmio::registers(EMMC_BASE) };
+
+    wait_status(registers, SR_CMD_INHIBIT | SR_DAT_INHIBIT)?;
+    let pending = registers.INTERRUPT.read();
+    registers.INTERRUPT.write(pending);
+
+    registers.BLKSIZECNT.write(1 << 16 | BLOCK_SIZE as u32);
+    registers.ARG1.write(lba);
+    registers.CMDTM.write(CMD_WRITE_SINGLE);
+    wait_interrupt(registers, INT_CMD_DONE)?;
+
+    wait_interrupt(registers, INT_WRITE_RDY)?;
+    for word in buf.chunks(4) {
+        let value = word.iter().rev().fold(0, |value, &byte| value << 8 | byte as u32);
+        registers.DATA.write(value);
+    }
+    wait_interrupt(registers, INT_DATA_DONE)
+}
+
+/// Waits for the `STATUS` bits `mask` to clear.
+fn wait_status(registers: &Registers, mask: u32) -> Result<(), Error> {
+    let start = timer::current_time();
+    while registers.STATUS.read() & mask != 0 {
+        let pending = registers.INTERRUPT.read();
+        if pending & INT_ERROR_MASK != 0 {
+            return Err(Error::Controller(pending));
+        }
+        if timer::current_time() - start > TIMEOUT_US {
+            return Err(Error::Timeout);
+        }
+    }
+    Ok(())
+}
+
+/// Waits for the `INTERRUPT` bits `mask` to be set, and clears them.
+fn wait_interrupt(registers: &mut Registers, mask: u32) -> Result<(), Error> {
+    let start = timer::current_time();
+    loop {
+        let pending = registers.INTERRUPT.read();
+        if pending & INT_ERROR_MASK != 0 {
+            registers.INTERRUPT.write(pending);
+            return Err(Error::Controller(pending));
+        }
+        if pending & mask == mask {
+            registers.INTERRUPT.write(mask);
+            return Ok(());
+        }
+        if timer::current_time() - start > TIMEOUT_US {
+            return Err(Error::Timeout);
+        }
+    }
+}
@@ -0,0 +1,388 @@
+//! A read-only parser for the flattened device tree (FDT) blob the firmware
+//! loads into memory and passes to the kernel in `x0`.
+//!
+//! The blob is a header, a structure block of big-endian tokens describing
+//! the tree of nodes and their properties, and a strings block holding the
+//! property names. Nodes are found by path, such as `/chosen`, or by their
+//! `compatible` property, and properties are returned as raw bytes, with
+//! helpers for the common encodings.
+
+use core::{slice, str};
+
+/// The magic number at the start of every blob.
+const MAGIC: u32 = 0xd00d_feed;
+
+/// The oldest blob version this parser understands.
+const MIN_VERSION: u32 = 16;
+
+/// The size of the header, as of version 16.
+const HEADER_SIZE: usize = 40;
+
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+const FDT_END: u32 = 9;
+
+/// Reads the big-endian word at `offset` in `bytes`, if it is in bounds.
+fn be32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let word = bytes.get(offset..offset.checked_add(4)?)?;
+    Some((word[0] as u32) << 24 | (word[1] as u32) << 16 | (word[2] as u32) << 8 | word[3] as u32)
+}
+
+/// Returns the NUL-terminated string at `offset` in `bytes`.
+fn cstr(bytes: &[u8], offset: usize) -> Option<&str> {
+    let rest = bytes.get(offset..)?;
+    let len = rest.iter().position(|&b| b == 0)?;
+    str::from_utf8(&rest[..len]).ok()
+}
+
+/// Rounds `offset` up to the next token boundary.
+fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+/// A validated device tree blob.
+#[derive(Debug, Copy, Clone)]
+pub struct Fdt<'a> {
+    blob: &'a [u8],
+    structs: &'a [u8],
+    strings: &'a [u8],
+}
+
+/// A node in the tree.
+#[derive(Debug, Copy, Clone)]
+pub struct Node<'a> {
+    fdt: Fdt<'a>,
+    name: &'a str,
+    /// The offset in the structure block of the node's first token after
+    /// its name.
+    body: usize,
+}
+
+/// A property of a node: its name and raw value.
+#[derive(Debug, Copy, Clone)]
+pub struct Property<'a> {
+    pub name: &'a str,
+    pub value: &'a [u8],
+}
+
+/// A token in the structure block.
+enum Token<'a> {
+    Begin(&'a str),
+    End,
+    Prop(Property<'a>),
+}
+
+impl Fdt<'static> {
+    /// Returns the blob at `addr` if it holds a device tree this parser
+    /// understands.
+    ///
+    /// # Safety
+    ///
+    /// `addr` must be 0 or point to readable memory at least a header long,
+    /// and if a valid header is there, as long as the size it gives.
+    pub unsafe fn from_addr(addr: usize) -> Option<Fdt<'static>> {
+        if addr == 0 || addr % 4 != 0 {
+            return None;
+        }
+
+        let header = slice::from_raw_parts(addr as *const u8, HEADER_SIZE);
+        if be32(header, 0)? != MAGIC {
+            return None;
+        }
+
+        let size = be32(header, 4)? as usize;
+        Fdt::new(slice::from_raw_parts(addr as *const u8, size))
+    }
+}
+
+impl<'a> Fdt<'a> {
+    /// Returns the device tree in `blob`, or `None` if it isn't one this
+    /// parser understands.
+    pub fn new(blob: &'a [u8]) -> Option<Fdt<'a>> {
+        if be32(blob, 0)? != MAGIC || blob.len() < HEADER_SIZE
+            || be32(blob, 20)? < MIN_VERSION
+        {
+            return None;
+        }
+
+        let section = |offset_at: usize, size_at: usize| -> Option<&'a [u8]> {
+            let offset = be32(blob, offset_at)? as usize;
+            let size = be32(blob, size_at)? as usize;
+            blob.get(offset..offset.checked_add(size)?)
+        };
+
+        Some(Fdt {
+            blob,
+            structs: section(8, 36)?,
+            strings: section(12, 32)?,
+        })
+    }
+
+    /// Returns the size of the blob in bytes.
+    pub fn size(&self) -> usize {
+        self.blob.len()
+    }
+
+    /// Returns the token at `offset` in the structure block and the offset
+    /// of the next one, skipping `NOP`s. Returns `None` at the end of the
+    /// block or if it is malformed.
+    fn token(&self, mut offset: usize) -> Option<(Token<'a>, usize)> {
+        loop {
+            let next = offset + 4;
+            match be32(self.structs, offset)? {
+                FDT_NOP => offset = next,
+                FDT_BEGIN_NODE => {
+                    let name = cstr(self.structs, next)?;
+                    return Some((Token::Begin(name), align4(next + name.len() + 1)));
+                }
+                FDT_END_NODE => return Some((Token::End, next)),
+                FDT_PROP => {
+                    let len = be32(self.structs, next)? as usize;
+                    let name = cstr(self.strings, be32(self.structs, next + 4)? as usize)?;
+                    let start = next + 8;
+                    let value = self.structs.get(start..start.checked_add(len)?)?;
+                    return Some((Token::Prop(Property { name, value }), align4(start + len)));
+                }
+                FDT_END => return None,
+                _ => return None,
+            }
+        }
+    }
+
+    /// Returns the root node.
+    pub fn root(&self) -> Option<Node<'a>> {
+        match self.token(0)? {
+            (Token::Begin(name), body) => Some(Node { fdt: *self, name, body }),
+            _ => None,
+        }
+    }
+
+    /// Returns the node at the absolute `path`, such as `/soc/gpio`. A path
+    /// component without a unit address matches a node with one, so
+    /// `/memory` finds `memory@0`.
+    pub fn find(&self, path: &str) -> Option<Node<'a>> {
+        let mut node = self.root()?;
+        for component in path.split('/').filter(|c| !c.is_empty()) {
+            node = node.children().find(|child| {
+                child.name == component || child.name.split('@').next() == Some(component)
+            })?;
+        }
+        Some(node)
+    }
+
+    /// Returns the first node, in depth-first order, whose `compatible`
+    /// property lists `compatible`.
+    pub fn find_compatible(&self, compatible: &str) -> Option<Node<'a>> {
+        self.nodes().find(|node| node.is_compatible(compatible))
+    }
+
+    /// Returns an iterator over every node, in depth-first order.
+    pub fn nodes(&self) -> Nodes<'a> {
+        Nodes { fdt: *self, offset: 0 }
+    }
+}
+
+/// An iterator over every node in a tree. See `Fdt::nodes()`.
+pub struct Nodes<'a> {
+    fdt: Fdt<'a>,
+    offset: usize,
+}
+
+impl<'a> Iterator for Nodes<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Node<'a>> {
+        loop {
+            let (token, next) = self.fdt.token(self.offset)?;
+            self.offset = next;
+            if let Token::Begin(name) = token {
+                return Some(Node { fdt: self.fdt, name, body: next });
+            }
+        }
+    }
+}
+
+impl<'a> Node<'a> {
+    /// Returns the node's name, with its unit address, if any.
+    pub fn name(&self) -> &'a str {
+        self.name
+    }
+
+    /// Returns an iterator over the node's properties.
+    pub fn properties(&self) -> Properties<'a> {
+        Properties { fdt: self.fdt, offset: self.body }
+    }
+
+    /// Returns the value of the property `name`, if the node has it.
+    pub fn property(&self, name: &str) -> Option<&'a [u8]> {
+        self.properties().find(|p| p.name == name).map(|p| p.value)
+    }
+
+    /// Returns the property `name` as a string, without its NUL.
+    pub fn property_str(&self, name: &str) -> Option<&'a str> {
+        let value = self.property(name)?;
+        let len = value.iter().position(|&b| b == 0).unwrap_or(value.len());
+        str::from_utf8(&value[..len]).ok()
+    }
+
+    /// Returns the property `name` as a single 32-bit cell.
+    pub fn property_u32(&self, name: &str) -> Option<u32> {
+        be32(self.property(name)?, 0)
+    }
+
+    /// Returns `true` if the node's `compatible` string list includes
+    /// `compatible`.
+    pub fn is_compatible(&self, compatible: &str) -> bool {
+        self.property("compatible").map_or(false, |list| {
+            list.split(|&b| b == 0).any(|entry| entry == compatible.as_bytes())
+        })
+    }
+
+    /// Returns the `#address-cells` and `#size-cells` this node gives its
+    /// children, defaulting to 2 and 1 as the specification says.
+    pub fn cells(&self) -> (usize, usize) {
+        (self.property_u32("#address-cells").unwrap_or(2) as usize,
+         self.property_u32("#size-cells").unwrap_or(1) as usize)
+    }
+
+    /// Returns an iterator over the `(address, size)` pairs of the node's
+    /// `reg` property, given its parent's `cells()`.
+    pub fn reg(&self, (address_cells, size_cells): (usize, usize)) -> Reg<'a> {
+        Reg(Cells::new(self.property("reg").unwrap_or(&[]), [address_cells, size_cells, 0]))
+    }
+
+    /// Returns an iterator over the `(child address, parent address, size)`
+    /// triples of the node's `ranges` property, given the node's own
+    /// `cells()` and its parent's `#address-cells`.
+    pub fn ranges(&self, (address_cells, size_cells): (usize, usize), parent_address_cells: usize)
+        -> Cells<'a>
+    {
+        Cells::new(self.property("ranges").unwrap_or(&[]),
+                   [address_cells, parent_address_cells, size_cells])
+    }
+
+    /// Returns an iterator over the node's children.
+    pub fn children(&self) -> Children<'a> {
+        Children { fdt: self.fdt, offset: self.body, done: false }
+    }
+}
+
+/// An iterator over a node's properties. See `Node::properties()`.
+pub struct Properties<'a> {
+    fdt: Fdt<'a>,
+    offset: usize,
+}
+
+impl<'a> Iterator for Properties<'a> {
+    type Item = Property<'a>;
+
+    fn next(&mut self) -> Option<Property<'a>> {
+        match self.fdt.token(self.offset)? {
+            (Token::Prop(property), next) => {
+                self.offset = next;
+                Some(property)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// An iterator over a node's children. See `Node::children()`.
+pub struct Children<'a> {
+    fdt: Fdt<'a>,
+    offset: usize,
+    done: bool,
+}
+
+impl<'a> Iterator for Children<'a> {
+    type Item = Node<'a>;
+
+    fn next(&mut self) -> Option<Node<'a>> {
+        if self.done {
+            return None;
+        }
+
+        // Skip properties, then take the next child and skip its subtree.
+        let mut depth = 0;
+        let mut child = None;
+        loop {
+            let (token, next) = match self.fdt.token(self.offset) {
+                Some(token) => token,
+                None => {
+                    self.done = true;
+                    return None;
+                }
+            };
+            self.offset = next;
+
+            match token {
+                Token::Begin(name) => {
+                    if depth == 0 {
+                        child = Some(Node { fdt: self.fdt, name, body: next });
+                    }
+                    depth += 1;
+                }
+                Token::End if depth == 0 => {
+                    self.done = true;
+                    return None;
+                }
+                Token::End => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return child;
+                    }
+                }
+                Token::Prop(_) => {}
+            }
+        }
+    }
+}
+
+/// An iterator over the groups of cells in a property such as `reg`, each
+/// group being up to three numbers of the given numbers of cells, with
+/// unused numbers zero.
+pub struct Cells<'a> {
+    value: &'a [u8],
+    widths: [usize; 3],
+}
+
+impl<'a> Cells<'a> {
+    fn new(value: &'a [u8], widths: [usize; 3]) -> Cells<'a> {
+        Cells { value, widths }
+    }
+}
+
+impl<'a> Iterator for Cells<'a> {
+    type Item = (u64, u64, u64);
+
+    fn next(&mut self) -> Option<(u64, u64, u64)> {
+        let mut numbers = [0u64; 3];
+        let mut offset = 0;
+        for (number, &width) in numbers.iter_mut().zip(self.widths.iter()) {
+            for _ in 0..width {
+                *number = *number << 32 | be32(self.value, offset)? as u64;
+                offset += 4;
+            }
+        }
+
+        if offset == 0 {
+            return None;
+        }
+        self.value = &self.value[offset..];
+        Some((numbers[0], numbers[1], numbers[2]))
+    }
+}
+
+/// An iterator over the `(address, size)` pairs of a `reg` property. See
+/// `Node::reg()`.
+pub struct Reg<'a>(Cells<'a>);
+
+impl<'a> Iterator for Reg<'a> {
+    type Item = (u64, u64);
+
+    fn next(&mut self) -> Option<(u64, u64)> {
+        self.0.next().map(|(address, size, _)| (address, size))
+    }
+}
@@ -2,7 +2,7 @@ use core::marker::PhantomData;
 
 use common::{IO_BASE, states};
 use volatile::prelude::*;
-use volatile::{Volatile, WriteVolatile, ReadVolatile, Reserved};
+use mmio::{self, Volatile, WriteVolatile, ReadVolatile, Reserved};
 
 /// An alternative GPIO function.
 #[repr(u8)]
@@ -65,7 +65,7 @@ pub struct Gpio<State> {
 }
 
 /// The base address of the `GPIO` registers.
-const GPIO_BASE: usize = IO_BASE + 0x200000;
+pub const GPIO_BASE: usize = IO_BASE + 0x200000;
 
 impl<T> Gpio<T> {
     /// Transitions `self` to state `S`, consuming `self` and returning a new
@@ -93,7 +93,7 @@ impl Gpio<Uninitialized> {
         }
 
         Gpio {
-            registers: unsafe { &mut *(GPIO_BASE as *mut Registers) },
+            registers: unsafe { mmio::registers(GPIO_BASE) },
             pin: pin,
             _state: PhantomData
         }
@@ -0,0 +1,94 @@
+use common::IO_BASE;
+
+use volatile::prelude::*;
+use mmio::{self, Volatile, ReadVolatile};
+
+/// The base address of the interrupt controller's registers.
+pub const INT_BASE: usize = IO_BASE + 0xB000 + 0x200;
+
+/// An interrupt source routed through the interrupt controller's two banks of
+/// 32 GPU interrupts. The value is the source's bit number across both banks.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Interrupt {
+    Timer1 = 1,
+    Timer3 = 3,
+    Usb = 9,
+    Aux = 29,
+    Gpio0 = 49,
+    Gpio1 = 50,
+    Gpio2 = 51,
+    Gpio3 = 52,
+    Uart = 57,
+    Emmc = 62,
+}
+
+impl Interrupt {
+    /// The number of interrupt numbers, one past the largest.
+    pub const MAX: usize = 64;
+
+    /// Every interrupt source, in increasing order of number.
+    pub fn iter() -> &'static [Interrupt] {
+        use self::Interrupt::*;
+        static ALL: [Interrupt; 10] = [
+            Timer1, Timer3, Usb, Aux, Gpio0, Gpio1, Gpio2, Gpio3, Uart, Emmc,
+        ];
+        &ALL
+    }
+
+    /// Returns the interrupt's bank (0 or 1) and its bit within the bank.
+    fn bank_bit(self) -> (usize, u32) {
+        let n = self as usize;
+        (n / 32, 1 << (n % 32))
+    }
+}
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    IRQ_BASIC_PENDING: ReadVolatile<u32>,
+    IRQ_PENDING: [ReadVolatile<u32>; 2],
+    FIQ_CONTROL: Volatile<u32>,
+    ENABLE_IRQS: [Volatile<u32>; 2],
+    ENABLE_BASIC_IRQS: Volatile<u32>,
+    DISABLE_IRQS: [Volatile<u32>; 2],
+    DISABLE_BASIC_IRQS: Volatile<u32>,
+}
+
+/// An interrupt controller. Used to enable and disable interrupts as well as
+/// to detect which interrupts are pending.
+pub struct Controller {
+    registers: &'static mut Registers
+}
+
+impl Controller {
+    /// Returns a new handle to the interrupt controller.
+    pub fn new() -> Controller {
+        Controller {
+            registers: unsafe { mmio::registers(INT_BASE) },
+        }
+    }
+
+    /// Enables the interrupt `int`.
+    pub fn enable(&mut self, int: Interrupt) {
+        let (bank, bit) = int.bank_bit();
+        self.registers.ENABLE_IRQS[bank].write(bit);
+    }
+
+    /// Disables the interrupt `int`.
+    pub fn disable(&mut self, int: Interrupt) {
+        let (bank, bit) = int.bank_bit();
+        self.registers.DISABLE_IRQS[bank].write(bit);
+    }
+
+    /// Returns `true` if `int` is enabled.
+    pub fn is_enabled(&self, int: Interrupt) -> bool {
+        let (bank, bit) = int.bank_bit();
+        self.registers.ENABLE_IRQS[bank].read() & bit != 0
+    }
+
+    /// Returns `true` if `int` is pending.
+    pub fn is_pending(&self, int: Interrupt) -> bool {
+        let (bank, bit) = int.bank_bit();
+        self.registers.IRQ_PENDING[bank].read() & bit != 0
+    }
+}
@@ -7,14 +7,25 @@
 #![feature(never_type)]
 #![feature(pointer_methods)]
 
-#![cfg_attr(not(feature = "std"), no_std)]
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 
-#[cfg(feature = "std")]
+#[cfg(any(feature = "std", test))]
 extern crate core;
 extern crate volatile;
 
 pub mod timer;
+pub mod rng;
 pub mod uart;
+pub mod pl011;
 pub mod gpio;
 pub mod common;
 pub mod atags;
+pub mod fdt;
+pub mod mailbox;
+pub mod interrupt;
+pub mod local;
+pub mod emmc;
+pub mod mmio;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,71 @@
+use volatile::prelude::*;
+use mmio::{self, Volatile, ReadVolatile, Reserved};
+
+/// The base address of the per-core ("local") peripherals of the BCM2836
+/// family: interrupt routing for each core's ARM generic timers,
+/// performance monitors, and mailboxes.
+pub const LOCAL_BASE: usize = 0x4000_0000;
+
+/// The number of cores.
+pub const NUM_CORES: usize = 4;
+
+/// The bit enabling, or reporting, a core's non-secure physical timer
+/// interrupt (`nCNTPNSIRQ`), the timer EL1 uses.
+const CNTPNSIRQ: u32 = 1 << 1;
+
+/// The bit reporting a core's performance monitor interrupt (`nPMUIRQ`) in
+/// its IRQ source register.
+const PMU_IRQ: u32 = 1 << 9;
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    __r0: [Reserved<u32>; 4],
+    PMU_INT_ROUTING_SET: Volatile<u32>,
+    PMU_INT_ROUTING_CLEAR: Volatile<u32>,
+    __r1: [Reserved<u32>; 10],
+    TIMER_INT_CONTROL: [Volatile<u32>; NUM_CORES],
+    MAILBOX_INT_CONTROL: [Volatile<u32>; NUM_CORES],
+    IRQ_SOURCE: [ReadVolatile<u32>; NUM_CORES],
+    FIQ_SOURCE: [ReadVolatile<u32>; NUM_CORES],
+}
+
+/// The local interrupt controller, which routes each core's own timer and
+/// performance monitor interrupts to that core.
+pub struct LocalController {
+    registers: &'static mut Registers
+}
+
+impl LocalController {
+    /// Returns a new handle to the local interrupt controller.
+    pub fn new() -> LocalController {
+        LocalController {
+            registers: unsafe { mmio::registers(LOCAL_BASE) },
+        }
+    }
+
+    /// Routes `core`'s physical timer interrupt to it as an IRQ.
+    pub fn enable_timer(&mut self, core: usize) {
+        self.registers.TIMER_INT_CONTROL[core].or_mask(CNTPNSIRQ);
+    }
+
+    /// Returns `true` if `core`'s physical timer interrupt is pending.
+    pub fn is_timer_pending(&self, core: usize) -> bool {
+        self.registers.IRQ_SOURCE[core].read() & CNTPNSIRQ != 0
+    }
+
+    /// Routes `core`'s performance monitor interrupt to it as an IRQ.
+    pub fn enable_pmu(&mut self, core: usize) {
+        self.registers.PMU_INT_ROUTING_SET.write(1 << core);
+    }
+
+    /// Stops routing `core`'s performance monitor interrupt to it.
+    pub fn disable_pmu(&mut self, core: usize) {
+        self.registers.PMU_INT_ROUTING_CLEAR.write(1 << core);
+    }
+
+    /// Returns `true` if `core`'s performance monitor interrupt is pending.
+    pub fn is_pmu_pending(&self, core: usize) -> bool {
+        self.registers.IRQ_SOURCE[core].read() & PMU_IRQ != 0
+    }
+}
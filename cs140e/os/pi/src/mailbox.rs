@@ -0,0 +1,115 @@
+use volatile::prelude::*;
+use mmio::{self, Volatile, ReadVolatile, Reserved};
+
+use common::IO_BASE;
+
+/// The base address for the VideoCore mailbox 0 registers.
+const MBOX_REG_BASE: usize = IO_BASE + 0xB880;
+
+/// The property-tags channel (ARM to VideoCore).
+const PROPERTY_CHANNEL: u32 = 8;
+
+/// The request code placed in a message header.
+const REQUEST: u32 = 0;
+
+/// The response code indicating a successful request.
+const RESPONSE_SUCCESS: u32 = 0x80000000;
+
+/// Enum representing bit fields of the mailbox `STATUS` register.
+#[repr(u32)]
+enum Status {
+    Empty = 1 << 30,
+    Full = 1 << 31,
+}
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    READ: ReadVolatile<u32>,
+    __r0: [Reserved<u32>; 3],
+    PEEK: ReadVolatile<u32>,
+    SENDER: ReadVolatile<u32>,
+    STATUS: ReadVolatile<u32>,
+    CONFIG: Volatile<u32>,
+    WRITE: Volatile<u32>,
+}
+
+/// Property tags understood by the VideoCore firmware.
+#[repr(u32)]
+#[derive(Debug, Copy, Clone)]
+pub enum Tag {
+    BoardSerial = 0x00010004,
+    MacAddress = 0x00010003,
+    ArmMemory = 0x00010005,
+    VcMemory = 0x00010006,
+}
+
+/// A 16-byte aligned property message buffer, as required by the firmware.
+#[repr(C, align(16))]
+struct Message([u32; 32]);
+
+/// Sends a single-tag property request for `tag` with a value buffer of
+/// `value_len` words and returns the response's value words on success.
+///
+/// The buffer is passed to the VideoCore by physical address: with the data
+/// cache enabled, the caller must ensure the buffer is not cached.
+fn property(tag: Tag, value_len: usize) -> Option<[u32; 8]> {
+    let registers: &mut Registers = unsafe { mmio::registers(MBOX_REG_BASE) };
+
+    let mut message = Message([0; 32]);
+    {
+        let words = &mut message.0;
+        words[0] = ((6 + value_len) * 4) as u32;
+        words[1] = REQUEST;
+        words[2] = tag as u32;
+        words[3] = (value_len * 4) as u32;
+        words[4] = 0;
+        // words[5..5 + value_len] are the (zeroed) value buffer.
+        words[5 + value_len] = 0;
+    }
+
+    let address = (&message as *const Message as usize) as u32;
+    while registers.STATUS.has_mask(Status::Full as u32) {}
+    registers.WRITE.write(address | PROPERTY_CHANNEL);
+
+    loop {
+        while registers.STATUS.has_mask(Status::Empty as u32) {}
+        if registers.READ.read() == address | PROPERTY_CHANNEL {
+            break;
+        }
+    }
+
+    let words = unsafe { ::core::ptr::read_volatile(&message.0) };
+    if words[1] != RESPONSE_SUCCESS {
+        return None;
+    }
+
+    let mut value = [0; 8];
+    value[..value_len].copy_from_slice(&words[5..5 + value_len]);
+    Some(value)
+}
+
+/// Returns the `(base, size)` of the memory assigned to the ARM cores, as
+/// reported by the firmware. This excludes memory reserved for the GPU.
+pub fn arm_memory() -> Option<(u32, u32)> {
+    property(Tag::ArmMemory, 2).map(|v| (v[0], v[1]))
+}
+
+/// Returns the `(base, size)` of the memory reserved for the VideoCore.
+pub fn vc_memory() -> Option<(u32, u32)> {
+    property(Tag::VcMemory, 2).map(|v| (v[0], v[1]))
+}
+
+/// Returns the board's MAC address, as assigned by the firmware.
+pub fn mac_address() -> Option<[u8; 6]> {
+    property(Tag::MacAddress, 2).map(|v| {
+        let (lo, hi) = (v[0], v[1]);
+        [lo as u8, (lo >> 8) as u8, (lo >> 16) as u8, (lo >> 24) as u8,
+         hi as u8, (hi >> 8) as u8]
+    })
+}
+
+/// Returns the board's 64-bit serial number.
+pub fn board_serial() -> Option<u64> {
+    property(Tag::BoardSerial, 2).map(|v| (v[1] as u64) << 32 | v[0] as u64)
+}
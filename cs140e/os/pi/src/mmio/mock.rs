@@ -0,0 +1,206 @@
+//! A mock of the Pi's peripherals, for testing drivers on the host.
+//!
+//! Each test thread gets its own device: a zeroed window of memory standing
+//! in for the peripheral address space, which `registers()` maps device
+//! addresses into, and a model of the devices whose registers do more than
+//! hold what was written to them:
+//!
+//!   * the system timer's counter, which advances a microsecond each time
+//!     its low word is read, so that drivers spinning on it finish;
+//!   * the mini UART's FIFOs: bytes pushed with `push_uart_rx()` are read
+//!     from its I/O register, and bytes written to it are collected for
+//!     `take_uart_tx()`;
+//!   * the GPIO pins' levels, which the set and clear registers change,
+//!     the level registers report, and `set_gpio_level()` drives.
+//!
+//! Every other register reads back what was last written to it.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::{mem, ptr};
+
+use common::IO_BASE;
+use gpio::GPIO_BASE;
+use local::LOCAL_BASE;
+use timer::TIMER_REG_BASE;
+use uart::MU_REG_BASE;
+
+/// The device addresses the window covers: every peripheral, through the
+/// local peripherals' registers.
+const WINDOW_BASE: usize = IO_BASE;
+const WINDOW_END: usize = LOCAL_BASE + 0x100;
+
+/// The addresses of the registers the model implements.
+const TIMER_CLO: usize = TIMER_REG_BASE + 0x4;
+const TIMER_CHI: usize = TIMER_REG_BASE + 0x8;
+const MU_IO: usize = MU_REG_BASE;
+const MU_LSR: usize = MU_REG_BASE + 0x14;
+const MU_STAT: usize = MU_REG_BASE + 0x24;
+const GPIO_SET: usize = GPIO_BASE + 0x1C;
+const GPIO_CLR: usize = GPIO_BASE + 0x28;
+const GPIO_LEV: usize = GPIO_BASE + 0x34;
+
+/// The mini UART's line status bits: data ready, transmitter empty, and
+/// transmitter idle.
+const LSR_DATA_READY: u64 = 1 << 0;
+const LSR_TX_EMPTY: u64 = 1 << 5;
+const LSR_TX_IDLE: u64 = 1 << 6;
+
+struct Device {
+    window: Box<[u64]>,
+    time: u64,
+    uart_rx: VecDeque<u8>,
+    uart_tx: Vec<u8>,
+    gpio_levels: u64,
+}
+
+impl Device {
+    fn new() -> Device {
+        Device {
+            window: vec![0; (WINDOW_END - WINDOW_BASE) / 8].into_boxed_slice(),
+            time: 0,
+            uart_rx: VecDeque::new(),
+            uart_tx: Vec::new(),
+            gpio_levels: 0,
+        }
+    }
+
+    /// Returns the value of the modeled register at `addr`, or `None` if it
+    /// is plain memory.
+    fn read(&mut self, addr: usize) -> Option<u64> {
+        Some(match addr {
+            TIMER_CLO => {
+                let low = self.time as u32 as u64;
+                self.time += 1;
+                low
+            }
+            TIMER_CHI => self.time >> 32,
+            MU_IO => self.uart_rx.pop_front().unwrap_or(0) as u64,
+            MU_LSR => {
+                let ready = if self.uart_rx.is_empty() { 0 } else { LSR_DATA_READY };
+                ready | LSR_TX_EMPTY | LSR_TX_IDLE
+            }
+            // The receive FIFO's fill level is in bits 19:16; the transmit
+            // FIFO is always empty.
+            MU_STAT => (self.uart_rx.len().min(8) as u64) << 16,
+            a if a == GPIO_LEV || a == GPIO_LEV + 4 => {
+                let bank = (a - GPIO_LEV) / 4;
+                (self.gpio_levels >> (bank * 32)) & 0xFFFF_FFFF
+            }
+            _ => return None,
+        })
+    }
+
+    /// Applies a write of `value` to the modeled register at `addr`.
+    /// Returns `false` if it is plain memory.
+    fn write(&mut self, addr: usize, value: u64) -> bool {
+        match addr {
+            MU_IO => self.uart_tx.push(value as u8),
+            a if a == GPIO_SET || a == GPIO_SET + 4 => {
+                self.gpio_levels |= value << ((a - GPIO_SET) / 4 * 32);
+            }
+            a if a == GPIO_CLR || a == GPIO_CLR + 4 => {
+                self.gpio_levels &= !(value << ((a - GPIO_CLR) / 4 * 32));
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Returns the device address the window's memory at `host` stands for.
+    fn device_addr(&self, host: usize) -> usize {
+        let start = self.window.as_ptr() as usize;
+        assert!(host >= start && host < start + self.window.len() * 8,
+                "{:#x} is not in the mock device's window", host);
+        WINDOW_BASE + (host - start)
+    }
+}
+
+thread_local! {
+    static DEVICE: RefCell<Device> = RefCell::new(Device::new());
+}
+
+fn with_device<R, F: FnOnce(&mut Device) -> R>(f: F) -> R {
+    DEVICE.with(|device| f(&mut device.borrow_mut()))
+}
+
+/// Returns the address of the window's memory standing for the `size` bytes
+/// of registers at device address `addr`.
+///
+/// # Panics
+///
+/// Panics if the registers aren't all in the window.
+pub fn map(addr: usize, size: usize) -> usize {
+    assert!(addr >= WINDOW_BASE && addr + size <= WINDOW_END,
+            "no mock device at {:#x}..{:#x}", addr, addr + size);
+    with_device(|device| device.window.as_ptr() as usize + (addr - WINDOW_BASE))
+}
+
+/// Reads the register `reg` points to, which `map()` returned memory for.
+pub fn read<T>(reg: *const T) -> T {
+    let size = mem::size_of::<T>();
+    assert!(size <= 8, "register of {} bytes", size);
+    let value = with_device(|device| {
+        let addr = device.device_addr(reg as usize);
+        device.read(addr)
+    });
+    match value {
+        Some(value) => unsafe { ptr::read(&value as *const u64 as *const T) },
+        None => unsafe { ptr::read_volatile(reg) },
+    }
+}
+
+/// Writes `val` to the register `reg` points to, which `map()` returned
+/// memory for.
+pub fn write<T>(reg: *mut T, val: T) {
+    let size = mem::size_of::<T>();
+    assert!(size <= 8, "register of {} bytes", size);
+    let mut value = 0u64;
+    unsafe {
+        ptr::copy_nonoverlapping(&val as *const T as *const u8, &mut value as *mut u64 as *mut u8,
+                                 size);
+    }
+    let handled = with_device(|device| {
+        let addr = device.device_addr(reg as usize);
+        device.write(addr, value)
+    });
+    if handled {
+        mem::forget(val);
+    } else {
+        unsafe { ptr::write_volatile(reg, val) }
+    }
+}
+
+/// Returns the system timer's counter, in microseconds.
+pub fn time() -> u64 {
+    with_device(|device| device.time)
+}
+
+/// Sets the system timer's counter to `us` microseconds.
+pub fn set_time(us: u64) {
+    with_device(|device| device.time = us)
+}
+
+/// Queues `bytes` to be received by the mini UART.
+pub fn push_uart_rx(bytes: &[u8]) {
+    with_device(|device| device.uart_rx.extend(bytes.iter().cloned()))
+}
+
+/// Returns and forgets the bytes the mini UART has transmitted.
+pub fn take_uart_tx() -> Vec<u8> {
+    with_device(|device| mem::replace(&mut device.uart_tx, Vec::new()))
+}
+
+/// Drives GPIO pin `pin` high or low.
+pub fn set_gpio_level(pin: u8, high: bool) {
+    with_device(|device| if high {
+        device.gpio_levels |= 1 << pin;
+    } else {
+        device.gpio_levels &= !(1 << pin);
+    })
+}
+
+/// Returns whether GPIO pin `pin` is high.
+pub fn gpio_level(pin: u8) -> bool {
+    with_device(|device| device.gpio_levels & (1 << pin) != 0)
+}
@@ -0,0 +1,91 @@
+//! Access to memory-mapped device registers.
+//!
+//! Drivers declare their registers as `#[repr(C)]` structs of the wrappers
+//! here and get at them with `registers()`. On the Pi, that is a plain cast
+//! of the registers' address, and the wrappers read and write with volatile
+//! loads and stores, exactly as `volatile`'s do. Under `cfg(test)`, the
+//! address is instead mapped into memory owned by a mock device model, and
+//! every read and write goes through the model, so that drivers can be unit
+//! tested on the host. See `mock`.
+
+use core::ops::{BitAnd, BitOr};
+
+use volatile::prelude::*;
+
+pub use volatile::Reserved;
+
+#[cfg(test)]
+pub mod mock;
+
+#[cfg(test)]
+mod tests;
+
+/// Returns the registers of type `T` at address `addr`.
+///
+/// # Safety
+///
+/// `addr` must be the address of a `T`'s worth of device registers, and the
+/// caller must ensure the registers aren't used in conflicting ways through
+/// other references.
+#[cfg(not(test))]
+#[inline(always)]
+pub unsafe fn registers<T>(addr: usize) -> &'static mut T {
+    &mut *(addr as *mut T)
+}
+
+#[cfg(test)]
+pub unsafe fn registers<T>(addr: usize) -> &'static mut T {
+    &mut *(mock::map(addr, ::core::mem::size_of::<T>()) as *mut T)
+}
+
+/// A **read-only** device register.
+#[repr(C)]
+pub struct ReadVolatile<T>(T);
+
+/// A readable and writeable device register.
+#[repr(C)]
+pub struct Volatile<T>(T);
+
+/// A **write-only** device register.
+#[repr(C)]
+pub struct WriteVolatile<T>(T);
+
+/// Implements `Readable` for a wrapper, reading through the mock device
+/// under `cfg(test)`.
+macro readable($type:ident) {
+    impl<T> Readable<T> for $type<T> {
+        #[inline(always)]
+        fn inner(&self) -> *const T {
+            &self.0
+        }
+
+        #[cfg(test)]
+        fn read(&self) -> T {
+            mock::read(&self.0)
+        }
+    }
+}
+
+/// Implements `Writeable` for a wrapper, writing through the mock device
+/// under `cfg(test)`.
+macro writeable($type:ident) {
+    impl<T> Writeable<T> for $type<T> {
+        #[inline(always)]
+        fn inner(&mut self) -> *mut T {
+            &mut self.0
+        }
+
+        #[cfg(test)]
+        fn write(&mut self, val: T) {
+            mock::write(&mut self.0, val)
+        }
+    }
+}
+
+readable!(ReadVolatile);
+readable!(Volatile);
+writeable!(Volatile);
+writeable!(WriteVolatile);
+
+impl<T> ReadableWriteable<T> for Volatile<T>
+    where T: BitAnd<Output = T>, T: BitOr<Output = T> { }
@@ -0,0 +1,104 @@
+mod timer {
+    use mmio::mock;
+    use timer::{self, Timer};
+
+    #[test]
+    fn test_read() {
+        mock::set_time(0x1_2345_6789);
+        assert_eq!(Timer::new().read(), 0x1_2345_6789);
+        assert_eq!(timer::current_time(), 0x1_2345_678A);
+    }
+
+    #[test]
+    fn test_spin_sleep() {
+        mock::set_time(1000);
+        timer::spin_sleep_us(250);
+        assert!(mock::time() >= 1250);
+
+        let before = mock::time();
+        timer::spin_sleep_ms(2);
+        assert!(mock::time() >= before + 2000);
+    }
+}
+
+mod gpio {
+    use gpio::Gpio;
+    use mmio::mock;
+
+    #[test]
+    fn test_output() {
+        let mut pin = Gpio::new(16).into_output();
+        pin.set();
+        assert!(mock::gpio_level(16));
+        assert!(!mock::gpio_level(17));
+        pin.clear();
+        assert!(!mock::gpio_level(16));
+
+        let mut high = Gpio::new(47).into_output();
+        high.set();
+        assert!(mock::gpio_level(47));
+        assert!(!mock::gpio_level(15));
+    }
+
+    #[test]
+    fn test_input() {
+        let mut pin = Gpio::new(5).into_input();
+        assert!(!pin.level());
+        mock::set_gpio_level(5, true);
+        assert!(pin.level());
+        mock::set_gpio_level(5, false);
+        assert!(!pin.level());
+
+        let mut high = Gpio::new(40).into_input();
+        mock::set_gpio_level(40, true);
+        assert!(high.level());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_invalid_pin() {
+        Gpio::new(54);
+    }
+}
+
+mod uart {
+    use core::fmt::Write;
+
+    use mmio::mock;
+    use uart::MiniUart;
+
+    #[test]
+    fn test_write() {
+        let mut uart = MiniUart::new();
+        uart.write_byte(b'x');
+        uart.write_bytes(b"0123456789abcdef");
+        assert_eq!(mock::take_uart_tx(), b"x0123456789abcdef");
+
+        write!(uart, "a\nb").unwrap();
+        assert_eq!(mock::take_uart_tx(), b"a\r\nb");
+    }
+
+    #[test]
+    fn test_read() {
+        let mut uart = MiniUart::new();
+        assert!(!uart.has_byte());
+        mock::push_uart_rx(b"hi");
+        assert!(uart.has_byte());
+        assert_eq!(uart.read_byte(), b'h');
+        assert_eq!(uart.read_byte(), b'i');
+        assert!(!uart.has_byte());
+    }
+
+    #[test]
+    fn test_read_timeout() {
+        let mut uart = MiniUart::new();
+        uart.set_read_timeout(5);
+        mock::set_time(0);
+        assert_eq!(uart.wait_for_byte(), Err(()));
+        assert!(mock::time() > 5000);
+
+        mock::push_uart_rx(b"!");
+        assert_eq!(uart.wait_for_byte(), Ok(()));
+        assert_eq!(uart.read_byte(), b'!');
+    }
+}
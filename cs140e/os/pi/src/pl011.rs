@@ -0,0 +1,167 @@
+use volatile::prelude::*;
+use mmio::{self, Volatile, ReadVolatile, Reserved};
+
+use common::IO_BASE;
+use gpio::{Gpio, Function};
+
+/// The base address for the PL011 UART's registers.
+pub const PL011_REG_BASE: usize = IO_BASE + 0x201000;
+
+/// The UART's reference clock, which the firmware sets to 48 MHz on the
+/// Pi 3 unless `init_uart_clock` in `config.txt` says otherwise.
+const UART_CLOCK_HZ: u32 = 48_000_000;
+
+/// Bit fields of the `FR` register.
+#[repr(u32)]
+enum Flag {
+    Busy = 1 << 3,
+    RxEmpty = 1 << 4,
+    TxFull = 1 << 5,
+}
+
+/// Bit fields of the `LCRH` register: 8-bit words, with the FIFOs enabled.
+const LCRH_FIFO_ENABLE: u32 = 1 << 4;
+const LCRH_WORD_LEN_8: u32 = 0b11 << 5;
+
+/// Bit fields of the `CR` register.
+const CR_ENABLE: u32 = 1;
+const CR_TX_ENABLE: u32 = 1 << 8;
+const CR_RX_ENABLE: u32 = 1 << 9;
+
+/// Interrupt sources, as bits of `IMSC`, `MIS`, and `ICR`: the receive FIFO
+/// reaching its trigger level, the transmit FIFO falling to its trigger
+/// level, and received bytes waiting with the line idle.
+pub const INT_RX: u32 = 1 << 4;
+pub const INT_TX: u32 = 1 << 5;
+pub const INT_RX_TIMEOUT: u32 = 1 << 6;
+
+/// Every interrupt source, for clearing them all.
+const INT_ALL: u32 = 0x7FF;
+
+/// Trigger levels: interrupt when the receive FIFO is half full, and when
+/// the transmit FIFO is down to an eighth.
+const IFLS_RX_HALF: u32 = 0b010 << 3;
+const IFLS_TX_EIGHTH: u32 = 0b000;
+
+/// The error bits of a byte read from `DR`: framing, parity, break, and
+/// overrun.
+const DR_ERRORS: u32 = 0xF << 8;
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    DR: Volatile<u32>,
+    RSRECR: Volatile<u32>,
+    __r0: [Reserved<u32>; 4],
+    FR: ReadVolatile<u32>,
+    __r1: Reserved<u32>,
+    ILPR: Volatile<u32>,
+    IBRD: Volatile<u32>,
+    FBRD: Volatile<u32>,
+    LCRH: Volatile<u32>,
+    CR: Volatile<u32>,
+    IFLS: Volatile<u32>,
+    IMSC: Volatile<u32>,
+    RIS: ReadVolatile<u32>,
+    MIS: ReadVolatile<u32>,
+    ICR: Volatile<u32>,
+}
+
+/// The Raspberry Pi's PL011 UART, the full UART beside the mini UART.
+///
+/// The Pi 3's firmware gives it to the Bluetooth controller; `new()` routes
+/// it to GPIO pins 14 and 15 instead, the header pins the mini UART
+/// otherwise uses, so only one of the two can be on the serial line at a
+/// time.
+pub struct Pl011 {
+    registers: &'static mut Registers,
+}
+
+impl Pl011 {
+    /// Initializes the UART for `baud` bits per second, 8 data bits, no
+    /// parity, and one stop bit, with the FIFOs enabled and every interrupt
+    /// masked, and routes it to GPIO pins 14 and 15 (TXD0/RXD0).
+    pub fn new(baud: u32) -> Pl011 {
+        let registers: &mut Registers = unsafe { mmio::registers(PL011_REG_BASE) };
+
+        // Disable the UART and let the last byte go before changing its
+        // configuration.
+        registers.CR.write(0);
+        while registers.FR.has_mask(Flag::Busy as u32) {}
+        registers.LCRH.write(0);
+
+        Gpio::new(14).into_alt(Function::Alt0);
+        Gpio::new(15).into_alt(Function::Alt0);
+
+        // The divisor is a fixed-point number with six fractional bits:
+        // UART_CLOCK_HZ / (16 * baud), rounded.
+        let divisor = (UART_CLOCK_HZ as u64 * 4 + baud as u64 / 2) / baud as u64;
+        registers.IBRD.write((divisor >> 6) as u32);
+        registers.FBRD.write((divisor & 0x3F) as u32);
+        registers.LCRH.write(LCRH_WORD_LEN_8 | LCRH_FIFO_ENABLE);
+        registers.IFLS.write(IFLS_RX_HALF | IFLS_TX_EIGHTH);
+        registers.IMSC.write(0);
+        registers.ICR.write(INT_ALL);
+        registers.CR.write(CR_ENABLE | CR_TX_ENABLE | CR_RX_ENABLE);
+
+        Pl011 { registers }
+    }
+
+    /// Returns `true` if there is at least one byte ready to be read.
+    pub fn has_byte(&self) -> bool {
+        !self.registers.FR.has_mask(Flag::RxEmpty as u32)
+    }
+
+    /// Reads a byte if one is ready, without blocking. Returns `Err(byte)`
+    /// for a byte received with a framing, parity, break, or overrun error.
+    pub fn try_read_byte(&mut self) -> Option<Result<u8, u8>> {
+        if !self.has_byte() {
+            return None;
+        }
+
+        let data = self.registers.DR.read();
+        match data & DR_ERRORS {
+            0 => Some(Ok(data as u8)),
+            _ => Some(Err(data as u8)),
+        }
+    }
+
+    /// Returns `true` if the transmit FIFO has room for a byte.
+    pub fn can_write(&self) -> bool {
+        !self.registers.FR.has_mask(Flag::TxFull as u32)
+    }
+
+    /// Writes `byte` if the transmit FIFO has room, returning whether it
+    /// did.
+    pub fn try_write_byte(&mut self, byte: u8) -> bool {
+        if !self.can_write() {
+            return false;
+        }
+
+        self.registers.DR.write(byte as u32);
+        true
+    }
+
+    /// Unmasks the interrupt sources in `mask`, a combination of `INT_RX`,
+    /// `INT_TX`, and `INT_RX_TIMEOUT`.
+    pub fn enable_interrupts(&mut self, mask: u32) {
+        self.registers.IMSC.or_mask(mask);
+    }
+
+    /// Masks the interrupt sources in `mask`.
+    pub fn disable_interrupts(&mut self, mask: u32) {
+        self.registers.IMSC.and_mask(!mask);
+    }
+
+    /// Returns the unmasked interrupt sources that are asserted.
+    pub fn pending_interrupts(&self) -> u32 {
+        self.registers.MIS.read()
+    }
+
+    /// Clears the interrupt sources in `mask`. The receive interrupts also
+    /// clear once the FIFO is drained, and the transmit one once it is
+    /// filled past its trigger level.
+    pub fn clear_interrupts(&mut self, mask: u32) {
+        self.registers.ICR.write(mask);
+    }
+}
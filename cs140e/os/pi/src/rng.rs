@@ -0,0 +1,59 @@
+use common::IO_BASE;
+use volatile::prelude::*;
+use mmio::{self, Volatile};
+
+/// The base address for the hardware random number generator's registers.
+const RNG_REG_BASE: usize = IO_BASE + 0x104000;
+
+/// The number of initial bits the generator discards after it is enabled,
+/// written to `STATUS` before enabling it.
+const WARMUP_COUNT: u32 = 0x40000;
+
+#[repr(C)]
+#[allow(non_snake_case)]
+struct Registers {
+    CTRL: Volatile<u32>,
+    STATUS: Volatile<u32>,
+    DATA: Volatile<u32>,
+    FF_THRESHOLD: Volatile<u32>,
+    INT_MASK: Volatile<u32>,
+}
+
+/// The Raspberry Pi's hardware random number generator, which fills a FIFO
+/// with raw words from a noise source.
+pub struct Rng {
+    registers: &'static mut Registers
+}
+
+impl Rng {
+    /// Returns a new instance of `Rng`.
+    pub fn new() -> Rng {
+        Rng {
+            registers: unsafe { mmio::registers(RNG_REG_BASE) },
+        }
+    }
+
+    /// Enables the generator, with its interrupt masked, if it isn't
+    /// already. The first words are available once it has warmed up.
+    pub fn enable(&mut self) {
+        if self.registers.CTRL.read() & 1 == 0 {
+            self.registers.STATUS.write(WARMUP_COUNT);
+            self.registers.INT_MASK.or_mask(1);
+            self.registers.CTRL.or_mask(1);
+        }
+    }
+
+    /// Returns the number of words waiting in the FIFO.
+    pub fn available(&self) -> u32 {
+        self.registers.STATUS.read() >> 24
+    }
+
+    /// Waits for a word from the generator and returns it. The generator
+    /// must be enabled.
+    pub fn next_u32(&mut self) -> u32 {
+        while self.available() == 0 {
+            // Wait for the noise source...
+        }
+        self.registers.DATA.read()
+    }
+}
@@ -0,0 +1,293 @@
+mod fdt {
+    use fdt::{Fdt, Node};
+
+    const FDT_BEGIN_NODE: u32 = 1;
+    const FDT_END_NODE: u32 = 2;
+    const FDT_PROP: u32 = 3;
+    const FDT_NOP: u32 = 4;
+    const FDT_END: u32 = 9;
+
+    const HEADER_SIZE: usize = 40;
+
+    /// Builds a blob a token at a time.
+    struct Builder {
+        structs: Vec<u8>,
+        strings: Vec<u8>,
+    }
+
+    impl Builder {
+        fn new() -> Builder {
+            Builder { structs: Vec::new(), strings: Vec::new() }
+        }
+
+        fn word(&mut self, word: u32) -> &mut Builder {
+            self.structs.extend_from_slice(&be(word));
+            self
+        }
+
+        fn pad(&mut self) {
+            while self.structs.len() % 4 != 0 {
+                self.structs.push(0);
+            }
+        }
+
+        fn begin(&mut self, name: &str) -> &mut Builder {
+            self.word(FDT_BEGIN_NODE);
+            self.structs.extend_from_slice(name.as_bytes());
+            self.structs.push(0);
+            self.pad();
+            self
+        }
+
+        fn end(&mut self) -> &mut Builder {
+            self.word(FDT_END_NODE)
+        }
+
+        fn prop(&mut self, name: &str, value: &[u8]) -> &mut Builder {
+            let name_offset = self.strings.len() as u32;
+            self.strings.extend_from_slice(name.as_bytes());
+            self.strings.push(0);
+            self.word(FDT_PROP).word(value.len() as u32).word(name_offset);
+            self.structs.extend_from_slice(value);
+            self.pad();
+            self
+        }
+
+        fn prop_cells(&mut self, name: &str, cells: &[u32]) -> &mut Builder {
+            let value: Vec<u8> = cells.iter().flat_map(|&cell| be(cell).to_vec()).collect();
+            self.prop(name, &value)
+        }
+
+        /// Returns the blob: the header, the structure block ended with
+        /// `FDT_END`, then the strings block.
+        fn finish(&mut self) -> Vec<u8> {
+            self.word(FDT_END);
+            let structs_at = HEADER_SIZE;
+            let strings_at = structs_at + self.structs.len();
+            let size = strings_at + self.strings.len();
+            let header = [0xd00d_feed, size as u32, structs_at as u32, strings_at as u32,
+                          HEADER_SIZE as u32, 17, 16, 0, self.strings.len() as u32,
+                          self.structs.len() as u32];
+
+            let mut blob: Vec<u8> = header.iter().flat_map(|&word| be(word).to_vec()).collect();
+            blob.extend_from_slice(&self.structs);
+            blob.extend_from_slice(&self.strings);
+            blob
+        }
+    }
+
+    fn be(word: u32) -> [u8; 4] {
+        [(word >> 24) as u8, (word >> 16) as u8, (word >> 8) as u8, word as u8]
+    }
+
+    fn be_at(blob: &[u8], offset: usize) -> u32 {
+        blob[offset..offset + 4].iter().fold(0, |word, &byte| word << 8 | byte as u32)
+    }
+
+    fn set_be(blob: &mut [u8], offset: usize, word: u32) {
+        blob[offset..offset + 4].copy_from_slice(&be(word));
+    }
+
+    /// Returns a tree shaped like a Pi's:
+    ///
+    /// ```text
+    /// / { #address-cells = 1; #size-cells = 1;
+    ///     chosen { bootargs = "console=serial0"; };
+    ///     memory@0 { reg = <0 0x3b000000>; };
+    ///     soc { compatible = "simple-bus"; ranges = <0x7e000000 0x3f000000 0x1000000>;
+    ///           gpio@7e200000 { compatible = "brcm,bcm2835-gpio", "brcm,gpio"; reg = ...; };
+    ///     };
+    /// };
+    /// ```
+    fn pi_tree() -> Vec<u8> {
+        Builder::new()
+            .begin("")
+                .prop_cells("#address-cells", &[1])
+                .prop_cells("#size-cells", &[1])
+                .begin("chosen")
+                    .prop("bootargs", b"console=serial0\0")
+                .end()
+                .word(FDT_NOP)
+                .begin("memory@0")
+                    .prop_cells("reg", &[0, 0x3b00_0000])
+                .end()
+                .begin("soc")
+                    .prop("compatible", b"simple-bus\0")
+                    .prop_cells("ranges", &[0x7e00_0000, 0x3f00_0000, 0x100_0000])
+                    .begin("gpio@7e200000")
+                        .word(FDT_NOP)
+                        .prop("compatible", b"brcm,bcm2835-gpio\0brcm,gpio\0")
+                        .prop_cells("reg", &[0x7e20_0000, 0xb4])
+                    .end()
+                .end()
+            .end()
+            .finish()
+    }
+
+    fn names<'a, I: Iterator<Item = Node<'a>>>(nodes: I) -> Vec<&'a str> {
+        nodes.map(|node| node.name()).collect()
+    }
+
+    #[test]
+    fn test_find() {
+        let blob = pi_tree();
+        let fdt = Fdt::new(&blob).expect("valid blob");
+        assert_eq!(fdt.size(), blob.len());
+
+        let chosen = fdt.find("/chosen").expect("/chosen");
+        assert_eq!(chosen.property_str("bootargs"), Some("console=serial0"));
+        assert_eq!(fdt.find("/memory").map(|node| node.name()), Some("memory@0"));
+        assert_eq!(fdt.find("/soc/gpio@7e200000").map(|node| node.name()), Some("gpio@7e200000"));
+        assert_eq!(fdt.find("/").map(|node| node.name()), Some(""));
+        assert!(fdt.find("/nonexistent").is_none());
+        assert!(fdt.find("/chosen/deeper").is_none());
+    }
+
+    #[test]
+    fn test_iterate() {
+        let blob = pi_tree();
+        let fdt = Fdt::new(&blob).unwrap();
+
+        assert_eq!(names(fdt.nodes()), ["", "chosen", "memory@0", "soc", "gpio@7e200000"]);
+        let root = fdt.root().unwrap();
+        assert_eq!(names(root.children()), ["chosen", "memory@0", "soc"]);
+        assert_eq!(names(fdt.find("/chosen").unwrap().children()), Vec::<&str>::new());
+
+        let properties: Vec<&str> = root.properties().map(|p| p.name).collect();
+        assert_eq!(properties, ["#address-cells", "#size-cells"]);
+    }
+
+    #[test]
+    fn test_compatible() {
+        let blob = pi_tree();
+        let fdt = Fdt::new(&blob).unwrap();
+
+        let gpio = fdt.find_compatible("brcm,gpio").expect("gpio");
+        assert_eq!(gpio.name(), "gpio@7e200000");
+        assert!(gpio.is_compatible("brcm,bcm2835-gpio"));
+        assert!(!gpio.is_compatible("brcm"));
+        assert!(fdt.find_compatible("arm,pl011").is_none());
+    }
+
+    #[test]
+    fn test_cells() {
+        let blob = pi_tree();
+        let fdt = Fdt::new(&blob).unwrap();
+        let root = fdt.root().unwrap();
+        let soc = fdt.find("/soc").unwrap();
+
+        assert_eq!(root.cells(), (1, 1));
+        assert_eq!(soc.cells(), (2, 1));
+        let memory: Vec<(u64, u64)> = fdt.find("/memory").unwrap().reg(root.cells()).collect();
+        assert_eq!(memory, [(0, 0x3b00_0000)]);
+
+        let ranges: Vec<(u64, u64, u64)> = soc.ranges(root.cells(), 1).collect();
+        assert_eq!(ranges, [(0x7e00_0000, 0x3f00_0000, 0x100_0000)]);
+
+        // Read with two address cells, the one pair runs out part way.
+        let gpio = fdt.find("/soc/gpio").unwrap();
+        assert_eq!(gpio.reg((2, 1)).count(), 0);
+        assert_eq!(gpio.reg((0, 0)).count(), 0);
+        assert_eq!(gpio.property_u32("compatible"), Some(0x6272_636d));
+        assert_eq!(fdt.find("/chosen").unwrap().property_u32("reg"), None);
+    }
+
+    #[test]
+    fn test_bad_header() {
+        let blob = pi_tree();
+        assert!(Fdt::new(&blob[..HEADER_SIZE - 1]).is_none());
+        assert!(Fdt::new(&[]).is_none());
+
+        let mut bad_magic = blob.clone();
+        bad_magic[0] = 0;
+        assert!(Fdt::new(&bad_magic).is_none());
+
+        let mut old = blob.clone();
+        set_be(&mut old, 20, 15);
+        assert!(Fdt::new(&old).is_none());
+
+        // The structure block, then the strings block, runs past the end.
+        let mut structs_past_end = blob.clone();
+        set_be(&mut structs_past_end, 36, blob.len() as u32);
+        assert!(Fdt::new(&structs_past_end).is_none());
+
+        let mut strings_past_end = blob.clone();
+        set_be(&mut strings_past_end, 12, u32::max_value() - 2);
+        assert!(Fdt::new(&strings_past_end).is_none());
+
+        assert!(Fdt::new(&blob[..blob.len() - 1]).is_none());
+    }
+
+    #[test]
+    fn test_truncated_structure() {
+        let blob = pi_tree();
+        // Cut the structure block short, by a word at a time, and read the
+        // whole tree each time: nothing may panic or loop.
+        let structs_size = be_at(&blob, 36) as usize;
+        for cut in 1..structs_size / 4 {
+            let mut short = blob.clone();
+            set_be(&mut short, 36, (structs_size - 4 * cut) as u32);
+            let fdt = match Fdt::new(&short) {
+                Some(fdt) => fdt,
+                None => continue,
+            };
+            for node in fdt.nodes() {
+                node.properties().count();
+                node.children().count();
+                node.reg((1, 1)).count();
+            }
+            fdt.find("/soc/gpio");
+        }
+    }
+
+    #[test]
+    fn test_malformed_tokens() {
+        // A node name cut off before its NUL by the end of the block.
+        let mut blob = Builder::new().begin("root").end().finish();
+        set_be(&mut blob, 36, 8);
+        let fdt = Fdt::new(&blob).unwrap();
+        assert!(fdt.root().is_none());
+        assert_eq!(fdt.nodes().count(), 0);
+
+        // A property named past the end of the strings block.
+        let mut blob = Builder::new().begin("").prop("name", b"value\0").end().finish();
+        set_be(&mut blob, HEADER_SIZE + 16, 100);
+        let fdt = Fdt::new(&blob).unwrap();
+        assert_eq!(fdt.root().unwrap().properties().count(), 0);
+
+        // A property longer than the structure block.
+        let mut blob = Builder::new().begin("").prop("name", b"value\0").end().finish();
+        set_be(&mut blob, HEADER_SIZE + 12, 0x1000);
+        let fdt = Fdt::new(&blob).unwrap();
+        assert_eq!(fdt.root().unwrap().properties().count(), 0);
+
+        // An unknown token ends the tree.
+        let blob = Builder::new().begin("").word(0x77).begin("lost").end().end().finish();
+        let fdt = Fdt::new(&blob).unwrap();
+        assert_eq!(names(fdt.nodes()), [""]);
+        assert_eq!(fdt.root().unwrap().children().count(), 0);
+
+        // A tree that never ends its nodes.
+        let blob = Builder::new().begin("").begin("child").finish();
+        let fdt = Fdt::new(&blob).unwrap();
+        assert_eq!(names(fdt.nodes()), ["", "child"]);
+        assert_eq!(fdt.root().unwrap().children().count(), 0);
+    }
+
+    #[test]
+    fn test_from_addr() {
+        let blob = pi_tree();
+        // Copy the blob into words, so that it is aligned as the firmware
+        // aligns it.
+        let mut words = vec![0u32; (blob.len() + 3) / 4];
+        let addr = words.as_mut_ptr() as usize;
+        unsafe {
+            ::std::ptr::copy_nonoverlapping(blob.as_ptr(), addr as *mut u8, blob.len());
+            assert!(Fdt::from_addr(0).is_none());
+            assert!(Fdt::from_addr(addr + 1).is_none());
+            let fdt = Fdt::from_addr(addr).expect("valid blob");
+            assert_eq!(fdt.size(), blob.len());
+            assert!(fdt.find("/chosen").is_some());
+        }
+    }
+}
@@ -1,9 +1,9 @@
 use common::IO_BASE;
 use volatile::prelude::*;
-use volatile::{Volatile, ReadVolatile};
+use mmio::{self, Volatile, ReadVolatile};
 
 /// The base address for the ARM system timer registers.
-const TIMER_REG_BASE: usize = IO_BASE + 0x3000;
+pub const TIMER_REG_BASE: usize = IO_BASE + 0x3000;
 
 #[repr(C)]
 #[allow(non_snake_case)]
@@ -23,7 +23,7 @@ impl Timer {
     /// Returns a new instance of `Timer`.
     pub fn new() -> Timer {
         Timer {
-            registers: unsafe { &mut *(TIMER_REG_BASE as *mut Registers) },
+            registers: unsafe { mmio::registers(TIMER_REG_BASE) },
         }
     }
 
@@ -33,6 +33,15 @@ impl Timer {
         return (self.registers.CHI.read() as u64) << 32
                 | (self.registers.CLO.read() as u64);
     }
+
+    /// Sets up a match in timer 1 to occur `us` microseconds from now,
+    /// clearing any earlier match. If interrupts for timer 1 are enabled and
+    /// IRQs are unmasked, a timer interrupt is issued when the match occurs.
+    pub fn tick_in(&mut self, us: u32) {
+        let target = self.registers.CLO.read().wrapping_add(us);
+        self.registers.COMPARE[1].write(target);
+        self.registers.CS.write(1 << 1);
+    }
 }
 
 /// Returns the current time in microseconds.
@@ -40,6 +49,12 @@ pub fn current_time() -> u64 {
     Timer::new().read()
 }
 
+/// Sets up a match in timer 1 to occur `us` microseconds from now. See
+/// `Timer::tick_in()`.
+pub fn tick_in(us: u32) {
+    Timer::new().tick_in(us)
+}
+
 /// Spins until `us` microseconds have passed.
 pub fn spin_sleep_us(us: u64) {
     let timer = Timer::new();
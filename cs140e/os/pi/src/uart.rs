@@ -1,17 +1,20 @@
 use core::fmt;
 
 use volatile::prelude::*;
-use volatile::{Volatile, ReadVolatile, Reserved};
+use mmio::{self, Volatile};
 
 use timer;
 use common::IO_BASE;
 use gpio::{Gpio, Function};
 
 /// The base address for the `MU` registers.
-const MU_REG_BASE: usize = IO_BASE + 0x215040;
+pub const MU_REG_BASE: usize = IO_BASE + 0x215040;
 
 /// The `AUXENB` register from page 9 of the BCM2837 documentation.
-const AUX_ENABLES: *mut Volatile<u8> = (IO_BASE + 0x215004) as *mut Volatile<u8>;
+const AUX_ENABLES: usize = IO_BASE + 0x215004;
+
+/// The number of bytes the transmit FIFO holds.
+const TX_FIFO_DEPTH: u32 = 8;
 
 /// Enum representing bit fields of the `AUX_MU_LSR_REG` register.
 #[repr(u8)]
@@ -52,10 +55,10 @@ impl MiniUart {
     /// By default, reads will never time out. To set a read timeout, use
     /// `set_read_timeout()`.
     pub fn new() -> MiniUart {
-        let registers = unsafe {
+        let registers: &'static mut Registers = unsafe {
             // Enable the mini UART as an auxiliary device.
-            (*AUX_ENABLES).or_mask(1);
-            &mut *(MU_REG_BASE as *mut Registers)
+            mmio::registers::<Volatile<u8>>(AUX_ENABLES).or_mask(1);
+            mmio::registers(MU_REG_BASE)
         };
 
         // 8-bit mode
@@ -77,6 +80,14 @@ impl MiniUart {
         }
     }
 
+    /// Enables the receive interrupt, which is asserted while the receive
+    /// FIFO holds data.
+    pub fn enable_rx_interrupt(&mut self) {
+        // The datasheet has bits 0 and 1 swapped: bit 0 enables the receive
+        // interrupt.
+        self.registers.AUX_MU_IER_REG.or_mask(0b01);
+    }
+
     /// Set the read timeout to `milliseconds` milliseconds.
     pub fn set_read_timeout(&mut self, milliseconds: u32) {
         self.timeout = Some(milliseconds);
@@ -90,6 +101,23 @@ impl MiniUart {
         self.registers.AUX_MU_IO_REG.write(byte as u32);
     }
 
+    /// Write every byte in `bytes`. Rather than waiting for space before each
+    /// byte, this fills the output FIFO as far as it will go each time the
+    /// FIFO's fill level is read.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        let mut bytes = bytes.iter();
+        loop {
+            // AUX_MU_STAT_REG[27:24] is the transmit FIFO fill level.
+            let level = (self.registers.AUX_MU_STAT_REG.read() >> 24) & 0xF;
+            for _ in level..TX_FIFO_DEPTH {
+                match bytes.next() {
+                    Some(&byte) => self.registers.AUX_MU_IO_REG.write(byte as u32),
+                    None => return,
+                }
+            }
+        }
+    }
+
     /// Returns `true` if there is at least one byte ready to be read. If this
     /// method returns `true`, a subsequent call to `read_byte` is guaranteed to
     /// return immediately. This method does not block.
@@ -0,0 +1,36 @@
+use core::fmt::Write;
+
+use env;
+use syscall;
+use Console;
+
+/// The most arguments `main` can be passed.
+pub const MAX_ARGS: usize = 256;
+
+/// The exit code when there are more than `MAX_ARGS` arguments.
+const TOO_MANY_ARGS_EXIT_CODE: i32 = 127;
+
+extern "Rust" {
+    /// The program's entry point.
+    fn main(args: &[&str]) -> i32;
+}
+
+/// The program's ELF entry point. The kernel enters with `sp` at `argc` and
+/// `x0`, `x1`, and `x2` holding `argc`, `argv`, and `envp`. Collects the
+/// arguments for `main`, then exits with its result.
+#[no_mangle]
+pub unsafe extern "C" fn _start(argc: usize, argv: *const *const u8, envp: *const *const u8) -> ! {
+    env::init(argv, envp);
+
+    if argc > MAX_ARGS {
+        let _ = write!(Console, "{} arguments; at most {} are supported\n", argc, MAX_ARGS);
+        syscall::exit(TOO_MANY_ARGS_EXIT_CODE);
+    }
+
+    let mut args = [""; MAX_ARGS];
+    for (slot, arg) in args.iter_mut().zip(env::args()) {
+        *slot = arg;
+    }
+
+    syscall::exit(main(&args[..argc]))
+}
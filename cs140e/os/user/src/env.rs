@@ -0,0 +1,63 @@
+//! The program's arguments and environment, as laid out on its initial stack
+//! by the kernel. See `Process::load()` in the kernel.
+
+use core::{slice, str};
+
+/// The null-terminated `argv` and `envp` arrays, recorded by `crt0`.
+static mut ARGV: *const *const u8 = 0 as *const *const u8;
+static mut ENVP: *const *const u8 = 0 as *const *const u8;
+
+/// Records the arrays passed to the program. Called once by `crt0`.
+pub(crate) unsafe fn init(argv: *const *const u8, envp: *const *const u8) {
+    ARGV = argv;
+    ENVP = envp;
+}
+
+/// Returns the NUL-terminated string at `ptr`. The kernel only passes
+/// strings it got as UTF-8.
+unsafe fn c_str<'a>(ptr: *const u8) -> &'a str {
+    let mut len = 0;
+    while *ptr.add(len) != 0 {
+        len += 1;
+    }
+    str::from_utf8_unchecked(slice::from_raw_parts(ptr, len))
+}
+
+/// An iterator over the strings of a null-terminated array.
+pub struct Strings {
+    next: *const *const u8,
+}
+
+impl Iterator for Strings {
+    type Item = &'static str;
+
+    fn next(&mut self) -> Option<&'static str> {
+        unsafe {
+            if self.next.is_null() || (*self.next).is_null() {
+                return None;
+            }
+
+            let string = c_str(*self.next);
+            self.next = self.next.add(1);
+            Some(string)
+        }
+    }
+}
+
+/// Returns the program's arguments, starting with the name it was run as.
+pub fn args() -> Strings {
+    Strings { next: unsafe { ARGV } }
+}
+
+/// Returns the program's environment, as `NAME=VALUE` strings.
+pub fn vars() -> Strings {
+    Strings { next: unsafe { ENVP } }
+}
+
+/// Returns the value of the environment variable `name`, if it is set.
+pub fn var(name: &str) -> Option<&'static str> {
+    vars().filter_map(|var| {
+        let eq = var.find('=')?;
+        if &var[..eq] == name { Some(&var[eq + 1..]) } else { None }
+    }).next()
+}
@@ -0,0 +1,109 @@
+//! The program's heap: a `#[global_allocator]` that grows with `brk`, so
+//! that `Box`, `Vec`, and `String` work in user programs.
+//!
+//! Allocations are rounded up to a power of two, at least `MIN_SIZE` bytes,
+//! and aligned to their size. Each size has a free list that freed blocks go
+//! on and allocations are taken from first. Otherwise a block is carved off
+//! the end of the heap, which grows by at least `GROW` bytes at a time.
+//!
+//! Programs are single-threaded, but a signal handler can interrupt the
+//! allocator, so handlers must not allocate.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::cmp::max;
+use core::fmt::Write;
+use core::ptr;
+
+use syscall;
+use {Console, PANIC_EXIT_CODE};
+
+/// The smallest block handed out, which holds a free list link.
+const MIN_SIZE: usize = 16;
+
+/// The number of block sizes: `MIN_SIZE` and each power of two above it.
+const BINS: usize = 64 - 4;
+
+/// The least the heap grows by at a time.
+const GROW: usize = 64 * 1024;
+
+#[global_allocator]
+static HEAP: Heap = Heap::new();
+
+struct State {
+    /// The first free block of each size, or 0. A free block's first word
+    /// holds the address of the next one.
+    free: [usize; BINS],
+    /// The lowest address not yet carved into blocks.
+    next: usize,
+    /// The program break, or 0 before the first allocation.
+    end: usize,
+}
+
+/// The allocator.
+pub struct Heap(UnsafeCell<State>);
+
+unsafe impl Sync for Heap { }
+
+impl Heap {
+    const fn new() -> Heap {
+        Heap(UnsafeCell::new(State { free: [0; BINS], next: 0, end: 0 }))
+    }
+}
+
+/// Returns the size of the block for `layout` and the index of its free
+/// list.
+fn block(layout: &Layout) -> (usize, usize) {
+    let size = max(max(layout.size(), layout.align()), MIN_SIZE).next_power_of_two();
+    (size, size.trailing_zeros() as usize - MIN_SIZE.trailing_zeros() as usize)
+}
+
+impl State {
+    /// Carves a block of `size` bytes, a power of two, off the end of the
+    /// heap, growing it if needed. Returns `None` if it can't grow.
+    fn carve(&mut self, size: usize) -> Option<usize> {
+        if self.end == 0 {
+            self.end = syscall::brk(0).ok()?;
+            self.next = self.end;
+        }
+
+        let start = self.next.checked_add(size - 1)? & !(size - 1);
+        let end = start.checked_add(size)?;
+        if end > self.end {
+            let new_end = self.end.checked_add(max(end - self.end, GROW))?;
+            self.end = syscall::brk(new_end).ok()?;
+        }
+
+        self.next = end;
+        Some(start)
+    }
+}
+
+unsafe impl GlobalAlloc for Heap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let state = &mut *self.0.get();
+        let (size, bin) = block(&layout);
+
+        let head = state.free[bin];
+        if head != 0 {
+            state.free[bin] = *(head as *const usize);
+            return head as *mut u8;
+        }
+
+        state.carve(size).map_or(ptr::null_mut(), |block| block as *mut u8)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let state = &mut *self.0.get();
+        let (_, bin) = block(&layout);
+        *(ptr as *mut usize) = state.free[bin];
+        state.free[bin] = ptr as usize;
+    }
+}
+
+/// Called when an allocation fails: reports it and exits.
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+    let _ = write!(Console, "out of memory: failed to allocate {:?}\n", layout);
+    syscall::exit(PANIC_EXIT_CODE)
+}
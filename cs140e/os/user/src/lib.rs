@@ -0,0 +1,54 @@
+//! The library user programs link against: system call wrappers, access to
+//! the arguments and environment, a heap, and `crt0`, the entry point that
+//! calls the program's `main`.
+//!
+//! A program is a `#![no_std]`, `#![no_main]` position-independent
+//! executable that defines
+//!
+//! ```rust,ignore
+//! #[no_mangle]
+//! pub fn main(args: &[&str]) -> i32 { ... }
+//! ```
+//!
+//! and exits with the value it returns. With `#[macro_use] extern crate
+//! alloc;`, it can use `Box`, `Vec`, `String`, and the rest of `alloc`,
+//! which allocate from the heap in `heap`.
+
+#![feature(asm)]
+#![feature(lang_items)]
+#![feature(const_fn)]
+#![feature(alloc, alloc_error_handler)]
+#![feature(integer_atomics)]
+#![no_std]
+
+extern crate alloc;
+
+pub mod syscall;
+pub mod env;
+pub mod heap;
+mod crt0;
+
+use core::fmt;
+
+/// Writes to the console through the `write` system call.
+pub struct Console;
+
+impl fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        syscall::write(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// The exit code of a program that panics.
+pub const PANIC_EXIT_CODE: i32 = 101;
+
+#[lang = "panic_fmt"]
+#[no_mangle]
+pub extern fn panic_fmt(fmt: fmt::Arguments, file: &'static str, line: u32, col: u32) -> ! {
+    use core::fmt::Write;
+    let _ = write!(Console, "panicked at '{}', {}:{}:{}\n", fmt, file, line, col);
+    syscall::exit(PANIC_EXIT_CODE)
+}
+
+#[lang = "eh_personality"] pub extern fn eh_personality() {}
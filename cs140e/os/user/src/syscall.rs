@@ -0,0 +1,464 @@
+//! Wrappers for the kernel's system calls. The numbers and calling
+//! convention match `traps::syscall` in the kernel: arguments in `x0`
+//! through `x5`, the result in `x0`, and an error code, or 0, in `x7`.
+//!
+//! Paths are relative to the current directory unless they are absolute.
+
+use core::sync::atomic::AtomicU32;
+
+/// A process identifier.
+pub type Id = u64;
+
+/// An error code returned by the kernel in `x7`.
+pub type Error = u64;
+
+/// The error code when memory is exhausted.
+pub const NO_MEMORY: Error = 6;
+
+/// The error code when a call would have to block, or, for `futex_wait()`,
+/// the word has changed.
+pub const WOULD_BLOCK: Error = 9;
+
+/// The error code when a resource limit would be exceeded.
+pub const LIMIT_EXCEEDED: Error = 11;
+
+/// The error code when a descriptor isn't open, or not for that use.
+pub const BAD_DESCRIPTOR: Error = 12;
+
+/// The error code when a file is used as a directory.
+pub const NOT_A_DIRECTORY: Error = 13;
+
+/// The error code when a UDP port is already bound, or a TCP port already
+/// listened on.
+pub const ADDR_IN_USE: Error = 14;
+
+/// The error code when nothing listens on the port connected to.
+pub const CONNECTION_REFUSED: Error = 15;
+
+/// The error code when the peer reset the connection.
+pub const CONNECTION_RESET: Error = 16;
+
+/// The error code when the peer stopped answering.
+pub const TIMED_OUT: Error = 17;
+
+/// The error code when a connection is closed, or closing, at this end.
+pub const NOT_CONNECTED: Error = 18;
+
+pub const SYS_EXIT: u16 = 1;
+pub const SYS_WRITE: u16 = 2;
+pub const SYS_SPAWN: u16 = 3;
+pub const SYS_WAIT: u16 = 4;
+pub const SYS_GETPID: u16 = 5;
+pub const SYS_FORK: u16 = 6;
+pub const SYS_YIELD: u16 = 7;
+pub const SYS_BRK: u16 = 17;
+pub const SYS_TIMES: u16 = 18;
+pub const SYS_GETTIME: u16 = 19;
+pub const SYS_GETRANDOM: u16 = 20;
+pub const SYS_FUTEX_WAIT: u16 = 21;
+pub const SYS_FUTEX_WAKE: u16 = 22;
+pub const SYS_GETRLIMIT: u16 = 23;
+pub const SYS_SETRLIMIT: u16 = 24;
+pub const SYS_OPEN: u16 = 25;
+pub const SYS_CLOSE: u16 = 26;
+pub const SYS_READ: u16 = 27;
+pub const SYS_WRITE_FD: u16 = 28;
+pub const SYS_LSEEK: u16 = 29;
+pub const SYS_STAT: u16 = 30;
+pub const SYS_FSTAT: u16 = 31;
+pub const SYS_CHDIR: u16 = 32;
+pub const SYS_GETCWD: u16 = 33;
+pub const SYS_UDP_BIND: u16 = 34;
+pub const SYS_UDP_CLOSE: u16 = 35;
+pub const SYS_UDP_SENDTO: u16 = 36;
+pub const SYS_UDP_RECVFROM: u16 = 37;
+pub const SYS_UDP_TRY_RECVFROM: u16 = 38;
+pub const SYS_TCP_LISTEN: u16 = 39;
+pub const SYS_TCP_ACCEPT: u16 = 40;
+pub const SYS_TCP_CONNECT: u16 = 41;
+pub const SYS_TCP_WAIT: u16 = 42;
+pub const SYS_TCP_SEND: u16 = 43;
+pub const SYS_TCP_RECV: u16 = 44;
+pub const SYS_TCP_CLOSE: u16 = 45;
+
+/// The clock counting from the UNIX epoch. See `gettime()`.
+pub const CLOCK_REALTIME: u64 = 0;
+
+/// The clock counting from boot. See `gettime()`.
+pub const CLOCK_MONOTONIC: u64 = 1;
+
+/// The resource of bytes of memory mapped. See `setrlimit()`.
+pub const RLIMIT_MEMORY: u64 = 0;
+
+/// The resource of message ports owned. See `setrlimit()`.
+pub const RLIMIT_PORTS: u64 = 1;
+
+/// The limit that doesn't limit anything.
+pub const RLIM_INFINITY: u64 = !0;
+
+/// A UDP or TCP socket identifier.
+pub type SocketId = u64;
+
+/// A file descriptor. 0, 1, and 2 start open on the console.
+pub type Fd = u64;
+
+/// Standard input.
+pub const STDIN: Fd = 0;
+/// Standard output.
+pub const STDOUT: Fd = 1;
+/// Standard error.
+pub const STDERR: Fd = 2;
+
+/// `open()` for reading only.
+pub const O_RDONLY: u64 = 0;
+/// `open()` for writing only.
+pub const O_WRONLY: u64 = 1;
+/// `open()` for reading and writing.
+pub const O_RDWR: u64 = 2;
+/// `open()` creates the file if it doesn't exist.
+pub const O_CREAT: u64 = 0o100;
+/// `open()` empties the file if it is opened for writing.
+pub const O_TRUNC: u64 = 0o1000;
+/// Writes go to the end of the file.
+pub const O_APPEND: u64 = 0o2000;
+
+/// `lseek()` from the start of the file.
+pub const SEEK_SET: u64 = 0;
+/// `lseek()` from the current offset.
+pub const SEEK_CUR: u64 = 1;
+/// `lseek()` from the end of the file.
+pub const SEEK_END: u64 = 2;
+
+/// Makes system call `$num`, which must be a literal since it is encoded in
+/// the `svc` instruction, with up to six arguments, and returns `Ok(x0)` or
+/// `Err(x7)`.
+macro_rules! syscall {
+    ($num:tt $(, $arg:expr)*) => {{
+        #[allow(unused_mut)]
+        let mut args = [0u64; 6];
+        #[allow(unused_mut, unused_assignments)]
+        let mut _i = 0;
+        $( args[_i] = $arg as u64; _i += 1; )*
+
+        let (result, error): (u64, u64);
+        asm!(concat!("svc #", stringify!($num))
+             : "={x0}"(result), "={x7}"(error)
+             : "{x0}"(args[0]), "{x1}"(args[1]), "{x2}"(args[2]),
+               "{x3}"(args[3]), "{x4}"(args[4]), "{x5}"(args[5])
+             : "memory"
+             : "volatile");
+
+        if error == 0 { Ok(result) } else { Err(error) }
+    }}
+}
+
+/// Exits with `code`.
+pub fn exit(code: i32) -> ! {
+    unsafe {
+        let _: Result<u64, Error> = syscall!(1, code);
+    }
+    loop { }
+}
+
+/// Writes `buf` to the console.
+pub fn write(buf: &[u8]) -> Result<usize, Error> {
+    unsafe { syscall!(2, buf.as_ptr(), buf.len()).map(|n| n as usize) }
+}
+
+/// Starts the program at `path` with `args` as its arguments and `env`, a
+/// list of `NAME=VALUE` strings, as its environment. Returns its ID.
+pub fn spawn(path: &str, args: &[&str], env: &[&str]) -> Result<Id, Error> {
+    // `&str` is laid out as the (pointer, length) pair the kernel expects.
+    unsafe {
+        syscall!(3, path.as_ptr(), path.len(), args.as_ptr(), args.len(), env.as_ptr(), env.len())
+    }
+}
+
+/// Waits for the child `id` to exit and returns its exit code.
+pub fn wait(id: Id) -> Result<i32, Error> {
+    unsafe { syscall!(4, id).map(|code| code as i32) }
+}
+
+/// Returns the calling process's ID.
+pub fn getpid() -> Id {
+    unsafe { syscall!(5).unwrap_or(0) }
+}
+
+/// Forks the calling process. Returns the child's ID in the parent and 0 in
+/// the child.
+pub fn fork() -> Result<Id, Error> {
+    unsafe { syscall!(6) }
+}
+
+/// Gives up the rest of the time slice.
+pub fn yield_now() {
+    unsafe {
+        let _: Result<u64, Error> = syscall!(7);
+    }
+}
+
+/// Moves the program break, the end of the heap, to `addr` and returns it.
+/// `brk(0)` returns the break without moving it.
+pub fn brk(addr: usize) -> Result<usize, Error> {
+    unsafe { syscall!(17, addr).map(|brk| brk as usize) }
+}
+
+/// Moves the program break up by `increment` bytes and returns the old
+/// break: the start of the new memory.
+pub fn sbrk(increment: usize) -> Result<usize, Error> {
+    let old = brk(0)?;
+    if increment != 0 {
+        brk(old.checked_add(increment).ok_or(NO_MEMORY)?)?;
+    }
+    Ok(old)
+}
+
+/// A process's CPU time and context switches. See `times()`.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Times {
+    /// Microseconds spent running the program.
+    pub user: u64,
+    /// Microseconds spent in the kernel on the process's behalf.
+    pub kernel: u64,
+    /// The number of times the process has been switched to.
+    pub switches: u64,
+}
+
+/// Returns the CPU time and context switches of process `id`.
+pub fn times(id: Id) -> Result<Times, Error> {
+    let mut times = [0u64; 3];
+    unsafe { syscall!(18, id, times.as_mut_ptr())?; }
+    Ok(Times { user: times[0], kernel: times[1], switches: times[2] })
+}
+
+/// A time on one of the clocks. See `gettime()`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Timespec {
+    pub secs: u64,
+    /// Less than a second.
+    pub nanos: u32,
+}
+
+/// Returns the time on `clock`: `CLOCK_REALTIME` or `CLOCK_MONOTONIC`.
+/// Wall-clock time counts from boot until the clock is set.
+pub fn gettime(clock: u64) -> Result<Timespec, Error> {
+    let mut time = [0u64; 2];
+    unsafe { syscall!(19, clock, time.as_mut_ptr())?; }
+    Ok(Timespec { secs: time[0], nanos: time[1] as u32 })
+}
+
+/// Fills `buf` with random bytes from the hardware generator.
+pub fn getrandom(buf: &mut [u8]) -> Result<(), Error> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let rest = &mut buf[filled..];
+        filled += unsafe { syscall!(20, rest.as_mut_ptr(), rest.len())? } as usize;
+    }
+    Ok(())
+}
+
+/// Sleeps until another process calls `futex_wake()` on `word`, unless it
+/// no longer holds `expected`, in which case this fails at once with
+/// `WOULD_BLOCK`. May return early, so callers must check `word` again.
+pub fn futex_wait(word: &AtomicU32, expected: u32) -> Result<(), Error> {
+    unsafe { syscall!(21, word as *const AtomicU32, expected).map(|_| ()) }
+}
+
+/// Wakes up to `count` processes sleeping in `futex_wait()` on `word` and
+/// returns how many were woken.
+pub fn futex_wake(word: &AtomicU32, count: usize) -> Result<usize, Error> {
+    unsafe { syscall!(22, word as *const AtomicU32, count).map(|n| n as usize) }
+}
+
+/// A resource limit and how much of the resource is in use. See
+/// `getrlimit()`.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Rlimit {
+    pub limit: u64,
+    pub usage: u64,
+}
+
+/// Returns the calling process's limit on `resource`, `RLIMIT_MEMORY` or
+/// `RLIMIT_PORTS`, and its use of it.
+pub fn getrlimit(resource: u64) -> Result<Rlimit, Error> {
+    let mut rlimit = [0u64; 2];
+    unsafe { syscall!(23, resource, rlimit.as_mut_ptr())?; }
+    Ok(Rlimit { limit: rlimit[0], usage: rlimit[1] })
+}
+
+/// Sets the calling process's limit on `resource` to `limit`, or no limit
+/// for `RLIM_INFINITY`, and returns the old one. Processes it forks or
+/// spawns afterwards inherit the limit.
+pub fn setrlimit(resource: u64, limit: u64) -> Result<u64, Error> {
+    unsafe { syscall!(24, resource, limit) }
+}
+
+/// Opens the file at `path` with `flags`, `O_RDONLY`, `O_WRONLY`, or
+/// `O_RDWR` and any of `O_CREAT`, `O_TRUNC`, and `O_APPEND`, and returns its
+/// descriptor.
+pub fn open(path: &str, flags: u64) -> Result<Fd, Error> {
+    unsafe { syscall!(25, path.as_ptr(), path.len(), flags) }
+}
+
+/// Closes `fd`.
+pub fn close(fd: Fd) -> Result<(), Error> {
+    unsafe { syscall!(26, fd).map(|_| ()) }
+}
+
+/// Reads from `fd` into `buf` and returns how many bytes were read: 0 at the
+/// end of the file.
+pub fn read(fd: Fd, buf: &mut [u8]) -> Result<usize, Error> {
+    unsafe { syscall!(27, fd, buf.as_mut_ptr(), buf.len()).map(|n| n as usize) }
+}
+
+/// Writes `buf` to `fd` and returns how many bytes were written.
+pub fn write_fd(fd: Fd, buf: &[u8]) -> Result<usize, Error> {
+    unsafe { syscall!(28, fd, buf.as_ptr(), buf.len()).map(|n| n as usize) }
+}
+
+/// Moves `fd`'s offset to `offset` from `whence`, `SEEK_SET`, `SEEK_CUR`,
+/// or `SEEK_END`, and returns it.
+pub fn lseek(fd: Fd, offset: i64, whence: u64) -> Result<u64, Error> {
+    unsafe { syscall!(29, fd, offset, whence) }
+}
+
+/// What a file or directory is. See `stat()`.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Stat {
+    pub is_dir: bool,
+    /// The size in bytes.
+    pub size: u64,
+    pub read_only: bool,
+}
+
+impl Stat {
+    fn from_raw(stat: [u64; 3]) -> Stat {
+        Stat { is_dir: stat[0] == 1, size: stat[1], read_only: stat[2] != 0 }
+    }
+}
+
+/// Returns what the entry at `path` is.
+pub fn stat(path: &str) -> Result<Stat, Error> {
+    let mut stat = [0u64; 3];
+    unsafe { syscall!(30, path.as_ptr(), path.len(), stat.as_mut_ptr())?; }
+    Ok(Stat::from_raw(stat))
+}
+
+/// Returns what the file open as `fd` is.
+pub fn fstat(fd: Fd) -> Result<Stat, Error> {
+    let mut stat = [0u64; 3];
+    unsafe { syscall!(31, fd, stat.as_mut_ptr())?; }
+    Ok(Stat::from_raw(stat))
+}
+
+/// Makes the directory at `path` the current directory.
+pub fn chdir(path: &str) -> Result<(), Error> {
+    unsafe { syscall!(32, path.as_ptr(), path.len()).map(|_| ()) }
+}
+
+/// Stores the current directory in `buf` and returns it.
+pub fn getcwd(buf: &mut [u8]) -> Result<&str, Error> {
+    let len = unsafe { syscall!(33, buf.as_mut_ptr(), buf.len())? } as usize;
+    // The kernel only keeps paths that are UTF-8.
+    Ok(unsafe { ::core::str::from_utf8_unchecked(&buf[..len]) })
+}
+
+/// An IPv4 address and UDP or TCP port. See `udp_sendto()`.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct SocketAddr {
+    pub addr: [u8; 4],
+    pub port: u16,
+}
+
+impl SocketAddr {
+    fn bits(&self) -> u32 {
+        self.addr.iter().fold(0, |bits, &b| bits << 8 | b as u32)
+    }
+
+    fn from_raw(raw: [u64; 2]) -> SocketAddr {
+        let bits = raw[0] as u32;
+        SocketAddr {
+            addr: [(bits >> 24) as u8, (bits >> 16) as u8, (bits >> 8) as u8, bits as u8],
+            port: raw[1] as u16,
+        }
+    }
+}
+
+/// Opens a UDP socket bound to `port`, or to an unused port if it is 0.
+/// Fails with `ADDR_IN_USE` if another socket has the port.
+pub fn udp_bind(port: u16) -> Result<SocketId, Error> {
+    unsafe { syscall!(34, port) }
+}
+
+/// Closes `socket`.
+pub fn udp_close(socket: SocketId) -> Result<(), Error> {
+    unsafe { syscall!(35, socket).map(|_| ()) }
+}
+
+/// Sends `buf` from `socket` as one datagram to `to`.
+pub fn udp_sendto(socket: SocketId, buf: &[u8], to: SocketAddr) -> Result<usize, Error> {
+    unsafe {
+        syscall!(36, socket, buf.as_ptr(), buf.len(), to.bits(), to.port).map(|n| n as usize)
+    }
+}
+
+/// Waits for a datagram on `socket`, stores as much of it as fits in `buf`,
+/// and returns that length and where it came from.
+pub fn udp_recvfrom(socket: SocketId, buf: &mut [u8]) -> Result<(usize, SocketAddr), Error> {
+    let mut from = [0u64; 2];
+    let len = unsafe { syscall!(37, socket, buf.as_mut_ptr(), buf.len(), from.as_mut_ptr())? };
+    Ok((len as usize, SocketAddr::from_raw(from)))
+}
+
+/// Like `udp_recvfrom()`, but fails with `WOULD_BLOCK` if no datagram has
+/// arrived.
+pub fn udp_try_recvfrom(socket: SocketId, buf: &mut [u8]) -> Result<(usize, SocketAddr), Error> {
+    let mut from = [0u64; 2];
+    let len = unsafe { syscall!(38, socket, buf.as_mut_ptr(), buf.len(), from.as_mut_ptr())? };
+    Ok((len as usize, SocketAddr::from_raw(from)))
+}
+
+/// Opens a TCP socket listening on `port`, which queues up to `backlog`
+/// connections. Fails with `ADDR_IN_USE` if another socket listens on it.
+pub fn tcp_listen(port: u16, backlog: usize) -> Result<SocketId, Error> {
+    unsafe { syscall!(39, port, backlog) }
+}
+
+/// Waits for a connection to the listening `socket`, and returns it and
+/// where it is from.
+pub fn tcp_accept(socket: SocketId) -> Result<(SocketId, SocketAddr), Error> {
+    let mut from = [0u64; 2];
+    let connection = unsafe { syscall!(40, socket, from.as_mut_ptr())? };
+    Ok((connection, SocketAddr::from_raw(from)))
+}
+
+/// Opens a TCP connection to `to` and waits for it to be established. Fails
+/// with `CONNECTION_REFUSED` if nothing listens there, or `TIMED_OUT` if
+/// nothing answers.
+pub fn tcp_connect(to: SocketAddr) -> Result<SocketId, Error> {
+    let socket = unsafe { syscall!(41, to.bits(), to.port)? };
+    match unsafe { syscall!(42, socket) } {
+        Ok(_) => Ok(socket),
+        Err(error) => {
+            let _ = tcp_close(socket);
+            Err(error)
+        }
+    }
+}
+
+/// Waits until the connection `socket` can buffer some of `buf`, and
+/// returns how much it took.
+pub fn tcp_send(socket: SocketId, buf: &[u8]) -> Result<usize, Error> {
+    unsafe { syscall!(43, socket, buf.as_ptr(), buf.len()).map(|n| n as usize) }
+}
+
+/// Waits for data on the connection `socket`, stores as much as fits in
+/// `buf`, and returns that length, or 0 once the peer has closed its side.
+pub fn tcp_recv(socket: SocketId, buf: &mut [u8]) -> Result<usize, Error> {
+    unsafe { syscall!(44, socket, buf.as_mut_ptr(), buf.len()).map(|n| n as usize) }
+}
+
+/// Closes `socket`. A connection sends what it has buffered first.
+pub fn tcp_close(socket: SocketId) -> Result<(), Error> {
+    unsafe { syscall!(45, socket).map(|_| ()) }
+}